@@ -0,0 +1,102 @@
+"""Compare `cargo bench --bench interpreters` against the baseline in benches/baseline.json.
+
+    python3 benches/baseline.py compare    # after `cargo bench`, show what changed
+    python3 benches/baseline.py save       # make the last `cargo bench` the new baseline
+
+Only save a baseline from a quiet machine, and say what it was in the commit.
+"""
+
+import json
+import os
+import sys
+
+
+ROOT = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+CRITERION_DIR = os.path.join(ROOT, "target", "criterion")
+BASELINE = os.path.join(ROOT, "benches", "baseline.json")
+
+# changes smaller than this are noise on most machines
+THRESHOLD = 0.05
+
+
+def read_results():
+    """The mean and median of each benchmark, keyed by `group/function` like `fib/v1`"""
+    results = {}
+    for dir, _, files in os.walk(CRITERION_DIR):
+        if os.path.basename(dir) != "new" or "estimates.json" not in files:
+            continue
+
+        with open(os.path.join(dir, "estimates.json")) as f:
+            estimates = json.load(f)
+        name = os.path.relpath(os.path.dirname(dir), CRITERION_DIR).replace(os.sep, "/")
+        results[name] = {
+            "mean_ns": estimates["mean"]["point_estimate"],
+            "median_ns": estimates["median"]["point_estimate"],
+        }
+
+    return results
+
+
+def format_ns(ns):
+    for unit, scale in (("s", 1e9), ("ms", 1e6), ("µs", 1e3)):
+        if ns >= scale:
+            return f"{ns / scale:.2f}{unit}"
+    return f"{ns:.0f}ns"
+
+
+def save(results):
+    with open(BASELINE, "w") as f:
+        json.dump(dict(sorted(results.items())), f, indent=4)
+        f.write("\n")
+    print(f"Saved {len(results)} benchmarks to {os.path.relpath(BASELINE, ROOT)}")
+
+
+def compare(results):
+    if not os.path.exists(BASELINE):
+        print("There is no baseline yet. Make one with `python3 benches/baseline.py save`.")
+        return 1
+    with open(BASELINE) as f:
+        baseline = json.load(f)
+
+    slower = 0
+    for name in sorted(set(results) | set(baseline)):
+        if name not in baseline:
+            print(f"{name:<24} {format_ns(results[name]['mean_ns']):>10}  new")
+            continue
+        if name not in results:
+            print(f"{name:<24} {'':>10}  not run")
+            continue
+
+        old = baseline[name]["mean_ns"]
+        new = results[name]["mean_ns"]
+        change = (new - old) / old
+        if change > THRESHOLD:
+            verdict = "slower"
+            slower += 1
+        elif change < -THRESHOLD:
+            verdict = "faster"
+        else:
+            verdict = ""
+        print(f"{name:<24} {format_ns(new):>10}  {change:+7.1%}  {verdict}".rstrip())
+
+    return 1 if slower else 0
+
+
+def main():
+    if len(sys.argv) != 2 or sys.argv[1] not in ("save", "compare"):
+        print(__doc__)
+        return 2
+
+    results = read_results()
+    if not results:
+        print("No results in target/criterion. Run `cargo bench --bench interpreters` first.")
+        return 1
+
+    if sys.argv[1] == "save":
+        save(results)
+        return 0
+    return compare(results)
+
+
+if __name__ == "__main__":
+    sys.exit(main())
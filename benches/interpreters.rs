@@ -0,0 +1,202 @@
+//! The interpreters' hot paths, each a small program run against V1 and V2 so they can be compared
+//! over time. Every iteration gets a brand new interpreter like `simple_lisp bench` does, but the
+//! program is only parsed and converted once.
+//!
+//!     cargo bench --bench interpreters
+//!     python3 benches/baseline.py compare
+//!
+//! A program V2 can't run yet is skipped for V2 with a note saying why. It can't call functions
+//! yet, so for now it only runs `straight_line`. The rest will be compared once it can.
+//!
+//! There is no baseline in the repo yet. It has to be saved with `baseline.py save` from a quiet
+//! machine, so it is left for whoever first runs the suite on one.
+
+
+use criterion::{
+    Criterion,
+    criterion_group,
+    criterion_main,
+};
+use simple_lisp::{
+    interpreter,
+    interpreter2,
+    parser,
+    source::SearchPath,
+    Captured,
+    InterpreterOptions,
+};
+use std::{
+    hint::black_box,
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
+    path::Path,
+};
+
+
+const FIB: &str = r#"
+(defn fib [n]
+    (cond
+        ((< n 2) n)
+        (else (+ (fib (- n 1)) (fib (- n 2))))))
+
+(fib 25)
+"#;
+
+/// Arithmetic on builtins with no functions or jumps, so V2 can run it too. V2 stops after 1000 instructions for
+/// now, which this stays under.
+const STRAIGHT_LINE: &str = r#"
+(def a 7)
+(def b 3)
+(def c 11)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+(+ (* a b) (- a b) (% a b)) (* (+ a 1) (- b 1)) (- (* a a) (* b b)) (% (+ a b c) 7)
+"#;
+
+/// Nothing but numbers and a tail call
+const ARITHMETIC: &str = r#"
+(defn churn [i total]
+    (cond
+        ((< i 100000) (recur (+ i 1) (% (+ (* total 31) (- i 7)) 1000003)))
+        (else total)))
+
+(churn 0 1)
+"#;
+
+/// `+` appends to the string on the left in place
+const STRINGS: &str = r#"
+(defn grow [s i]
+    (cond
+        ((< i 5000) (recur (+ s "abcdefgh") (+ i 1)))
+        (else (core/length s))))
+
+(grow "" 0)
+"#;
+
+/// A 10k item cons list, then walking it to sum the items
+const LISTS: &str = r#"
+(defn build [i l]
+    (cond
+        ((< i 10000) (recur (+ i 1) (core/cons i l)))
+        (else l)))
+
+(defn sum [l total]
+    (cond
+        ((core/isPair l) (recur (core/cdr l) (+ total (core/car l))))
+        (else total)))
+
+(sum (build 0 None) 0)
+"#;
+
+/// Lists that are garbage as soon as they are made
+const GC_PRESSURE: &str = r#"
+(defn churn [i]
+    (cond
+        ((< i 20000) (begin
+            (core/list i i i i i i i i)
+            (recur (+ i 1))))))
+
+(churn 0)
+"#;
+
+/// Recursion that can't be a tail call, most of `DEFAULT_MAX_STACK_DEPTH` deep
+const DEEP_RECURSION: &str = r#"
+(defn depth [n]
+    (cond
+        ((= n 0) 0)
+        (else (+ 1 (depth (- n 1))))))
+
+(defn repeat [i total]
+    (cond
+        ((< i 20) (recur (+ i 1) (+ total (depth 3000))))
+        (else total)))
+
+(repeat 0 0)
+"#;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("straight_line", STRAIGHT_LINE),
+    ("fib", FIB),
+    ("arithmetic", ARITHMETIC),
+    ("strings", STRINGS),
+    ("lists", LISTS),
+    ("gc_pressure", GC_PRESSURE),
+    ("deep_recursion", DEEP_RECURSION),
+];
+
+
+fn options()->InterpreterOptions {
+    InterpreterOptions {
+        prelude: false,
+        ..InterpreterOptions::default()
+    }
+}
+
+fn v1_state(name: &str, source: &str)->interpreter::ast::ConvertState {
+    let exprs = parser::new_parser(source).parse_all().unwrap();
+    return interpreter::ast::convert(exprs, Path::new(name), SearchPath::default(), false).unwrap();
+}
+
+fn run_v1(state: &mut interpreter::ast::ConvertState) {
+    let mut interpreter = options().new_interpreter_with_output(state, Box::new(Captured::new()));
+    black_box(interpreter.run(state, None).unwrap());
+}
+
+/// The program converted for V2, or why V2 can't run it. It is run once to find out, and a
+/// `todo!` it hits counts as can't.
+fn v2_state(name: &str, source: &str)->Result<interpreter2::ast::ConvertState, String> {
+    let exprs = parser::new_parser(source).parse_all().unwrap();
+    let mut state = interpreter2::ast::convert(exprs, Path::new(name), SearchPath::default(), false)
+        .map_err(|e|format!("{e:#}"))?;
+
+    // the panic is expected, so keep it from printing
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_|{}));
+    let res = panic::catch_unwind(AssertUnwindSafe(||{
+        let mut interpreter = options().new_interpreter2_with_output(&mut state, Box::new(Captured::new()));
+        interpreter.run(&mut state, None).map(|_|())
+    }));
+    panic::set_hook(hook);
+
+    match res {
+        Ok(Ok(()))=>Ok(state),
+        Ok(Err(e))=>Err(format!("{e:#}")),
+        Err(_)=>Err("it hit something that isn't implemented".into()),
+    }
+}
+
+fn run_v2(state: &mut interpreter2::ast::ConvertState) {
+    let mut interpreter = options().new_interpreter2_with_output(state, Box::new(Captured::new()));
+    black_box(interpreter.run(state, None).unwrap());
+}
+
+
+fn interpreters(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        let mut group = c.benchmark_group(*name);
+
+        let mut state = v1_state(name, source);
+        group.bench_function("v1", |b|b.iter(||run_v1(&mut state)));
+
+        match v2_state(name, source) {
+            Ok(mut state)=>{
+                group.bench_function("v2", |b|b.iter(||run_v2(&mut state)));
+            },
+            Err(why)=>println!("Skipping {name}/v2: {why}"),
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, interpreters);
+criterion_main!(benches);
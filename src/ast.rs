@@ -1,12 +1,39 @@
+use std::collections::{
+    btree_map::Entry,
+    BTreeMap,
+};
+use crate::error::LispError;
+
+
 #[derive(Debug, PartialEq)]
 pub enum Expr<'a> {
     ReplDirective(&'a str),
 
     Module(&'a str),
+    /// `(use a/b :only [x y] :as b)`. Loads the module like `module` does and binds it to the last
+    /// segment of `path`, or `alias`. With `only` it binds those fields instead.
+    Use {
+        path: Vec<&'a str>,
+        only: Option<Vec<&'a str>>,
+        alias: Option<&'a str>,
+    },
 
     Def {
         name: &'a str,
         data: Box<Self>,
+        /// `def-` and `defn-`. Private globals aren't fields of their module's value.
+        private: bool,
+        /// `defconst`. The global can't be `set` or defined again, but what it holds can still
+        /// change, like pushing to a list.
+        constant: bool,
+    },
+    /// `(def [a b & rest] data)`. `data` has to make a list with at least as many items as there
+    /// are names, and exactly as many without a remainder. The remainder gets a list of the rest.
+    DefVector {
+        names: Vector<'a>,
+        data: Box<Self>,
+        private: bool,
+        constant: bool,
     },
     Set {
         name: &'a str,
@@ -49,6 +76,14 @@ pub enum Expr<'a> {
 
     Comment(&'a str),
 
+    /// `(breakpoint)`. Pauses the program and opens a REPL in the scope it is in.
+    Breakpoint,
+    /// `(deftest name body...)`. A function without params that `simple_lisp test` calls. Only
+    /// allowed at the top level of a script.
+    Test(Fn<'a>),
+    /// `(yield v)`. Only allowed directly in the body of a `fn-gen`.
+    Yield(Box<Self>),
+
     None,
 }
 
@@ -63,6 +98,62 @@ pub enum FnSignature<'a> {
     Single(Vector<'a>, Vec<Expr<'a>>),
     Multi(Vec<(Vector<'a>, Vec<Expr<'a>>)>),
 }
+impl<'a> FnSignature<'a> {
+    /// The params and body of each arity
+    pub fn bodies(&self)->Vec<(&Vector<'a>, &[Expr<'a>])> {
+        match self {
+            Self::Single(params, body)=>vec![(params, body)],
+            Self::Multi(items)=>items.iter()
+                .map(|(params, body)|(params, body.as_slice()))
+                .collect(),
+        }
+    }
+
+    /// Check that every branch of a multi-arity fn can be called. Two branches that take the same
+    /// argument count, or the same minimum, are an error. A `&` branch that only gets counts other
+    /// branches take first is returned as a warning. See `interpreter::ast::FnSignature` for the
+    /// order branches are picked in.
+    pub fn check_arities(&self)->Result<Vec<LispError>, LispError> {
+        let Self::Multi(items) = self else {return Ok(Vec::new())};
+
+        let mut exact = BTreeMap::new();
+        let mut at_least = BTreeMap::new();
+        for (params, _) in items {
+            let variadic = params.remainder.is_some();
+            let counts = if variadic {&mut at_least} else {&mut exact};
+            match counts.entry(params.items.len()) {
+                Entry::Occupied(first)=>return Err(LispError::ConflictingArity {
+                    first: first.get().describe(),
+                    second: params.describe(),
+                    count: params.items.len(),
+                    variadic,
+                }),
+                Entry::Vacant(slot)=>{slot.insert(params);},
+            }
+        }
+
+        // a `&` branch only gets the counts from its minimum up to the next larger minimum, and
+        // only the ones without an exact branch
+        let mut warnings = Vec::new();
+        let mins = at_least.iter().collect::<Vec<_>>();
+        for pair in mins.windows(2) {
+            let [(min, params), (next_min, next)] = pair else {unreachable!()};
+            if (**min..**next_min).all(|count|exact.contains_key(&count)) {
+                let mut covered_by = (**min..**next_min)
+                    .map(|count|exact[&count].describe())
+                    .collect::<Vec<_>>();
+                covered_by.push(next.describe());
+
+                warnings.push(LispError::UnreachableBranch {
+                    params: params.describe(),
+                    covered_by,
+                });
+            }
+        }
+
+        return Ok(warnings);
+    }
+}
 
 
 #[derive(Debug, PartialEq)]
@@ -70,6 +161,18 @@ pub struct Vector<'a> {
     pub items: Vec<&'a str>,
     pub remainder: Option<&'a str>,
 }
+impl<'a> Vector<'a> {
+    /// Formats the vector like it is written in the source: `[a b & rest]`
+    pub fn describe(&self)->String {
+        let mut out = self.items.clone();
+        if let Some(rem) = self.remainder {
+            out.push("&");
+            out.push(rem);
+        }
+
+        return format!("[{}]", out.join(" "));
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Squiggle<'a> {
@@ -81,4 +184,6 @@ pub struct Fn<'a> {
     pub name: Option<&'a str>,
     pub captures: Option<Squiggle<'a>>,
     pub signature: FnSignature<'a>,
+    /// From `fn-gen`. Calling it makes a generator instead of running the body.
+    pub generator: bool,
 }
@@ -0,0 +1,136 @@
+//! `simple_lisp bench`: runs a program many times with the V1 interpreter and reports timing
+//! statistics. The program is parsed and converted once, but every iteration gets a brand new
+//! `Interpreter` so nothing carries over between runs.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use serde::{
+    Serialize,
+    Serializer,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+use crate::{
+    interpreter::ast::{
+        ConvertState,
+        Instruction,
+    },
+    InterpreterOptions,
+};
+
+
+/// All of these are written as nanoseconds in the JSON report
+#[derive(Debug, Serialize)]
+pub struct TimeStats {
+    #[serde(rename = "min_ns", serialize_with = "as_nanos")]
+    pub min: Duration,
+    #[serde(rename = "median_ns", serialize_with = "as_nanos")]
+    pub median: Duration,
+    #[serde(rename = "mean_ns", serialize_with = "as_nanos")]
+    pub mean: Duration,
+    #[serde(rename = "p95_ns", serialize_with = "as_nanos")]
+    pub p95: Duration,
+    #[serde(rename = "max_ns", serialize_with = "as_nanos")]
+    pub max: Duration,
+}
+impl TimeStats {
+    /// `samples` must not be empty
+    fn new(mut samples: Vec<Duration>)->Self {
+        samples.sort();
+
+        let total: Duration = samples.iter().sum();
+        let percentile = |p: f64|{
+            let idx = ((samples.len() - 1) as f64 * p).round() as usize;
+            samples[idx]
+        };
+
+        TimeStats {
+            min: samples[0],
+            median: percentile(0.5),
+            mean: total / samples.len() as u32,
+            p95: percentile(0.95),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub iterations: usize,
+    pub warmup: usize,
+    pub wall_time: TimeStats,
+    /// Mean per iteration
+    pub instructions_executed: u64,
+    /// Mean per iteration
+    pub allocations: u64,
+    /// The deepest any iteration went
+    pub max_call_stack_depth: usize,
+}
+
+
+fn as_nanos<S: Serializer>(d: &Duration, s: S)->Result<S::Ok, S::Error> {
+    s.serialize_u64(d.as_nanos() as u64)
+}
+
+/// Returns true if the program mentions `stdin` anywhere. Reading stdin more than once doesn't
+/// work, and waiting on input would ruin the timings anyways.
+pub fn reads_stdin(state: &ConvertState)->bool {
+    let Some(stdin) = state.interner.lookup("stdin") else {
+        return false;
+    };
+
+    let mut iter = state.instructions.iter();
+    while let Some(ins) = iter.next() {
+        match ins {
+            Instruction::Var(name)|
+                Instruction::DotIdent(name)=>if *name == stdin {return true},
+            Instruction::Path(path)|
+                Instruction::SetPath(path)=>if path.contains(&stdin) {return true},
+            _=>{},
+        }
+    }
+
+    return false;
+}
+
+/// Run the program `warmup + iterations` times and report on the last `iterations` runs
+pub fn bench(state: &mut ConvertState, iterations: usize, warmup: usize, options: InterpreterOptions)->Result<BenchReport> {
+    if iterations == 0 {
+        bail!("Need at least one iteration");
+    }
+
+    let mut times = Vec::with_capacity(iterations);
+    let mut instructions = 0;
+    let mut allocations = 0;
+    let mut max_depth = 0;
+
+    for i in 0..(warmup + iterations) {
+        // dropping the interpreter frees everything it allocated and checks for leaks
+        let mut interpreter = options.new_interpreter(state);
+
+        let start = Instant::now();
+        interpreter.run(state, None)?;
+        let elapsed = start.elapsed();
+
+        if i >= warmup {
+            times.push(elapsed);
+            instructions += interpreter.metrics.instructions_executed;
+            allocations += interpreter.metrics.allocations;
+            max_depth = max_depth.max(interpreter.metrics.max_call_stack_depth);
+        }
+    }
+
+    return Ok(BenchReport {
+        iterations,
+        warmup,
+        wall_time: TimeStats::new(times),
+        instructions_executed: instructions / iterations as u64,
+        allocations: allocations / iterations as u64,
+        max_call_stack_depth: max_depth,
+    });
+}
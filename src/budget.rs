@@ -0,0 +1,85 @@
+//! Hard limits on how long a program may run. Both interpreters check these so untrusted scripts
+//! can't run forever.
+
+
+use std::{
+    error::Error as ErrorTrait,
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+
+/// Checking the clock every instruction is slow, so we only do it this often
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// The process exit code when a script is stopped by its budget. Same as the `timeout` command.
+pub const BUDGET_EXIT_CODE: i32 = 124;
+
+
+/// Returned when a program goes over its `Budget`. `error_trace` prints this as "execution budget
+/// exceeded".
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BudgetExceeded {
+    Instructions(u64),
+    Timeout(Duration),
+}
+impl ErrorTrait for BudgetExceeded {}
+impl Display for BudgetExceeded {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        match self {
+            Self::Instructions(max)=>write!(f, "execution budget exceeded: ran more than {max} instructions"),
+            Self::Timeout(max)=>write!(f, "execution budget exceeded: ran longer than {max:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Budget {
+    /// Compared against the total instruction count, so in the REPL this is shared by every line
+    pub max_instructions: Option<u64>,
+    /// Wall-clock time allowed for each call to `run`
+    pub timeout: Option<Duration>,
+    /// When the current run started. Set by `start`.
+    started: Option<Instant>,
+}
+impl Budget {
+    pub fn new(max_instructions: Option<u64>, timeout: Option<Duration>)->Self {
+        Budget {
+            max_instructions,
+            timeout,
+            started: None,
+        }
+    }
+
+    /// Start the timeout clock for a new run
+    pub fn start(&mut self) {
+        self.started = Some(Instant::now());
+    }
+
+    /// Call once per instruction with the number of instructions executed so far
+    #[inline]
+    pub fn check(&self, instructions: u64)->Result<(), BudgetExceeded> {
+        if let Some(max) = self.max_instructions {
+            if instructions > max {
+                return Err(BudgetExceeded::Instructions(max));
+            }
+        }
+
+        if instructions % TIMEOUT_CHECK_INTERVAL == 0 {
+            if let (Some(timeout), Some(started)) = (self.timeout, self.started) {
+                if started.elapsed() > timeout {
+                    return Err(BudgetExceeded::Timeout(timeout));
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
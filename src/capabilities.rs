@@ -0,0 +1,53 @@
+//! What natives are allowed to touch outside of the interpreter. Everything is allowed unless the
+//! host or the CLI says otherwise, and a native that needs something it wasn't given fails with
+//! `LispError::CapabilityDenied` before doing anything.
+
+
+bitflags::bitflags! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct Capabilities:u8 {
+        const FS_READ       = 0b0000_0001;
+        const FS_WRITE      = 0b0000_0010;
+        const STDIO         = 0b0000_0100;
+        const ENV           = 0b0000_1000;
+        const PROCESS       = 0b0001_0000;
+        const NETWORK       = 0b0010_0000;
+    }
+}
+impl Default for Capabilities {
+    fn default()->Self {
+        Self::all()
+    }
+}
+impl Capabilities {
+    /// The name used in errors, and the shorter one `--allow` takes
+    const NAMES: &'static [(Self, &'static str, &'static str)] = &[
+        (Self::FS_READ, "filesystem-read", "fs-read"),
+        (Self::FS_WRITE, "filesystem-write", "fs-write"),
+        (Self::STDIO, "stdio", "stdio"),
+        (Self::ENV, "environment", "env"),
+        (Self::PROCESS, "process", "process"),
+        (Self::NETWORK, "network", "network"),
+    ];
+
+    /// The name of the first capability in `self`, like `filesystem-read`
+    pub fn name(&self)->&'static str {
+        Self::NAMES.iter()
+            .find(|(cap, _, _)|self.contains(*cap))
+            .map(|(_, name, _)|*name)
+            .unwrap_or("none")
+    }
+
+    /// Parse one capability by either of its names
+    pub fn parse(name: &str)->Result<Self, String> {
+        Self::NAMES.iter()
+            .find(|(_, long, short)|*long == name || *short == name)
+            .map(|(cap, _, _)|*cap)
+            .ok_or_else(||{
+                let names = Self::NAMES.iter()
+                    .map(|(_, _, short)|*short)
+                    .collect::<Vec<_>>();
+                format!("unknown capability `{name}`, expected one of {}", names.join(", "))
+            })
+    }
+}
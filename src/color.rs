@@ -0,0 +1,124 @@
+//! ANSI colors for the errors and warnings `error_trace` and `warning_trace` print. They are only
+//! used if stdout is a terminal, and never with `--no-color` or `NO_COLOR` set. `CLICOLOR_FORCE`
+//! turns them on even when stdout isn't a terminal, which the snapshot tests use.
+//!
+//! See <https://no-color.org> and <https://bixense.com/clicolors>.
+
+
+use std::{
+    fmt::Display,
+    io::{
+        IsTerminal,
+        stdout,
+    },
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+};
+use unicode_width::UnicodeWidthChar;
+use crate::diagnostic::line_column;
+
+
+/// `Error` labels and the caret under where a parse error happened
+pub const ERROR: &str = "1;31";
+/// `Warning` labels
+pub const WARNING: &str = "1;33";
+/// File names and line numbers
+pub const LOCATION: &str = "36";
+/// The `Trace:` and `Stack:` headers
+pub const TRACE_HEADER: &str = "1;35";
+/// The lines of a trace or stack, so they don't blend into the error itself
+pub const TRACE: &str = "2";
+
+/// Ends every color
+const RESET: &str = "\x1b[0m";
+
+
+/// Set by `--no-color`
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+
+/// Never color anything, even with `CLICOLOR_FORCE` set
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether diagnostics printed to stdout should be colored
+pub fn enabled()->bool {
+    if DISABLED.load(Ordering::Relaxed) || env_set("NO_COLOR") {
+        return false;
+    }
+    if std::env::var_os("CLICOLOR_FORCE").is_some_and(|v|!v.is_empty() && v != "0") {
+        return true;
+    }
+
+    return stdout().is_terminal();
+}
+
+fn env_set(name: &str)->bool {
+    std::env::var_os(name).is_some_and(|v|!v.is_empty())
+}
+
+/// `text` in the SGR `code`, or as it is if `on` is false
+pub fn paint(on: bool, code: &str, text: impl Display)->String {
+    match on {
+        true=>format!("\x1b[{code}m{text}{RESET}"),
+        false=>text.to_string(),
+    }
+}
+
+/// A parse error with the line it happened on and a caret under the spot:
+///
+/// ```text
+/// Error: Unexpected `)`
+///  --> test.slp:3:6
+///   |
+/// 3 | (+ 1))
+///   |      ^
+/// ```
+pub fn snippet(on: bool, message: impl Display, source: &str, file: impl Display, offset: usize)->String {
+    let (line, column) = line_column(source, offset);
+    return snippet_at(on, message, Some(source), file, line, column);
+}
+
+/// Like `snippet`, but at a 1-based `line` and `column`. Without `source` only the error and the
+/// `-->` line are shown.
+pub fn snippet_at(on: bool, message: impl Display, source: Option<&str>, file: impl Display, line: usize, column: usize)->String {
+    let number = line.to_string();
+    let gutter = " ".repeat(number.len());
+
+    let mut out = format!("{}: {message}\n", paint(on, ERROR, "Error"));
+    out.push_str(&format!("{gutter}{} {}\n", paint(on, LOCATION, "-->"), paint(on, LOCATION, format!("{file}:{line}:{column}"))));
+    let Some(source) = source else {
+        return out;
+    };
+
+    let text = source.lines()
+        .nth(line.saturating_sub(1))
+        .unwrap_or("");
+    let indent = caret_indent(text, column);
+    out.push_str(&format!("{gutter} {}\n", paint(on, LOCATION, "|")));
+    out.push_str(&format!("{} {} {text}\n", paint(on, LOCATION, &number), paint(on, LOCATION, "|")));
+    out.push_str(&format!("{gutter} {} {indent}{}\n", paint(on, LOCATION, "|"), paint(on, ERROR, "^")));
+
+    return out;
+}
+
+/// What goes before the caret so it lines up under `column` of `text`. The column is counted in
+/// chars, but wide chars like CJK take two cells and tabs stay tabs.
+fn caret_indent(text: &str, column: usize)->String {
+    let mut indent = String::new();
+    for c in text.chars().take(column.saturating_sub(1)) {
+        match c {
+            '\t'=>indent.push('\t'),
+            _=>indent.push_str(&" ".repeat(c.width().unwrap_or(0))),
+        }
+    }
+
+    // past the end of the line, like an error at the end of the file
+    let missing = column.saturating_sub(1).saturating_sub(text.chars().count());
+    indent.push_str(&" ".repeat(missing));
+
+    return indent;
+}
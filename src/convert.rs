@@ -0,0 +1,272 @@
+//! Converting between Rust data and `Value`s. `Engine::set_global`, `Engine::value_of` and
+//! `Ctx::value_of` take anything that is `IntoLisp`, and `Value::convert` returns anything that is
+//! `FromLisp`.
+//!
+//! | Rust                | Lisp                        |
+//! |---------------------|-----------------------------|
+//! | `i64`               | number                      |
+//! | `f64`               | float                       |
+//! | `bool`              | bool                        |
+//! | `char`              | char                        |
+//! | `String`, `&str`    | string                      |
+//! | `()`                | none                        |
+//! | `Option<T>`         | none, or whatever `T` is    |
+//! | `Vec<T>`            | list                        |
+//! | `HashMap<String, T>`| object                      |
+//! | tuples of up to 4   | list with that many items   |
+//! | `Rc<T: HostObject>` | native                      |
+//!
+//! There is no derive, but your own types only need a couple of small impls. Build the value out
+//! of other values with a `Ctx`, and take it apart with the `as_*` methods on `Value`.
+//! `Value::convert_error` makes the error for when it is the wrong shape:
+//!
+//! ```
+//! use simple_lisp::{
+//!     Ctx,
+//!     Engine,
+//!     FromLisp,
+//!     IntoLisp,
+//!     Value,
+//! };
+//!
+//! #[derive(Debug, PartialEq)]
+//! struct Point {
+//!     x: i64,
+//!     y: i64,
+//! }
+//! impl IntoLisp for Point {
+//!     fn into_lisp(self, ctx: &mut Ctx)->Value {
+//!         let x = ctx.number(self.x);
+//!         let y = ctx.number(self.y);
+//!         ctx.object([("x", x), ("y", y)])
+//!     }
+//! }
+//! impl FromLisp for Point {
+//!     fn from_lisp(value: &Value)->anyhow::Result<Self> {
+//!         let Some(fields) = value.as_object() else {
+//!             return Err(value.convert_error::<Self>());
+//!         };
+//!         let (Some(x), Some(y)) = (fields.get("x"), fields.get("y")) else {
+//!             return Err(value.convert_error::<Self>());
+//!         };
+//!
+//!         Ok(Point {x: x.convert()?, y: y.convert()?})
+//!     }
+//! }
+//!
+//! let mut engine = Engine::new();
+//! engine.set_global("p", Point {x: 1, y: 2});
+//!
+//! let moved = engine.eval_str("(p .x 5) p").unwrap();
+//! assert_eq!(moved.convert::<Point>().unwrap(), Point {x: 5, y: 2});
+//! ```
+
+
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    rc::Rc,
+};
+use crate::{
+    engine::{
+        Ctx,
+        Value,
+    },
+    host::HostObject,
+};
+
+
+/// Rust data that can become a `Value`. Allocate it with the `Ctx`.
+pub trait IntoLisp {
+    fn into_lisp(self, ctx: &mut Ctx)->Value;
+}
+
+/// Rust data that can be read out of a `Value`. Errors should come from `Value::convert_error` so
+/// they say what was expected.
+pub trait FromLisp: Sized {
+    fn from_lisp(value: &Value)->Result<Self>;
+}
+
+
+/// Panics if the value is from a different engine
+impl IntoLisp for Value {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.check_engine(&self);
+
+        return self;
+    }
+}
+impl FromLisp for Value {
+    fn from_lisp(value: &Value)->Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: HostObject> IntoLisp for Rc<T> {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        Value::from_host(ctx, self)
+    }
+}
+impl<T: HostObject> FromLisp for Rc<T> {
+    fn from_lisp(value: &Value)->Result<Self> {
+        value.downcast_host().ok_or_else(||value.convert_error::<Self>())
+    }
+}
+
+impl IntoLisp for i64 {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.number(self)
+    }
+}
+impl FromLisp for i64 {
+    fn from_lisp(value: &Value)->Result<Self> {
+        value.as_i64().ok_or_else(||value.convert_error::<Self>())
+    }
+}
+
+impl IntoLisp for f64 {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.float(self)
+    }
+}
+impl FromLisp for f64 {
+    fn from_lisp(value: &Value)->Result<Self> {
+        value.as_f64().ok_or_else(||value.convert_error::<Self>())
+    }
+}
+
+impl IntoLisp for bool {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.bool(self)
+    }
+}
+impl FromLisp for bool {
+    fn from_lisp(value: &Value)->Result<Self> {
+        value.as_bool().ok_or_else(||value.convert_error::<Self>())
+    }
+}
+
+impl IntoLisp for char {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.char(self)
+    }
+}
+impl FromLisp for char {
+    fn from_lisp(value: &Value)->Result<Self> {
+        value.as_char().ok_or_else(||value.convert_error::<Self>())
+    }
+}
+
+impl IntoLisp for String {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.string(self)
+    }
+}
+impl IntoLisp for &str {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.string(self)
+    }
+}
+impl FromLisp for String {
+    fn from_lisp(value: &Value)->Result<Self> {
+        value.as_string().ok_or_else(||value.convert_error::<Self>())
+    }
+}
+
+impl IntoLisp for () {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        ctx.none()
+    }
+}
+impl FromLisp for () {
+    fn from_lisp(value: &Value)->Result<Self> {
+        match value.is_none() {
+            true=>Ok(()),
+            false=>Err(value.convert_error::<Self>()),
+        }
+    }
+}
+
+impl<T: IntoLisp> IntoLisp for Option<T> {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        match self {
+            Some(value)=>value.into_lisp(ctx),
+            None=>ctx.none(),
+        }
+    }
+}
+impl<T: FromLisp> FromLisp for Option<T> {
+    fn from_lisp(value: &Value)->Result<Self> {
+        if value.is_none() {
+            return Ok(None);
+        }
+
+        return T::from_lisp(value).map(Some);
+    }
+}
+
+impl<T: IntoLisp> IntoLisp for Vec<T> {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        let items = self.into_iter()
+            .map(|item|item.into_lisp(ctx))
+            .collect::<Vec<_>>();
+
+        return ctx.list(&items);
+    }
+}
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn from_lisp(value: &Value)->Result<Self> {
+        let Some(items) = value.as_list() else {
+            return Err(value.convert_error::<Self>());
+        };
+
+        return items.iter().map(T::from_lisp).collect();
+    }
+}
+
+impl<T: IntoLisp> IntoLisp for HashMap<String, T> {
+    fn into_lisp(self, ctx: &mut Ctx)->Value {
+        let fields = self.into_iter()
+            .map(|(name, value)|(name, value.into_lisp(ctx)))
+            .collect::<Vec<_>>();
+
+        return ctx.object(fields.iter().map(|(name, value)|(name.as_str(), value.clone())));
+    }
+}
+impl<T: FromLisp> FromLisp for HashMap<String, T> {
+    fn from_lisp(value: &Value)->Result<Self> {
+        let Some(fields) = value.as_object() else {
+            return Err(value.convert_error::<Self>());
+        };
+
+        return fields.into_iter()
+            .map(|(name, value)|Ok((name, T::from_lisp(&value)?)))
+            .collect();
+    }
+}
+
+/// Tuples are lists with exactly as many items
+macro_rules! tuple_impls {
+    ($($len:literal=>($($t:ident $i:tt),+);)+)=>{$(
+        impl<$($t: IntoLisp),+> IntoLisp for ($($t,)+) {
+            fn into_lisp(self, ctx: &mut Ctx)->Value {
+                let items = [$(self.$i.into_lisp(ctx)),+];
+                ctx.list(&items)
+            }
+        }
+        impl<$($t: FromLisp),+> FromLisp for ($($t,)+) {
+            fn from_lisp(value: &Value)->Result<Self> {
+                match value.as_list() {
+                    Some(items) if items.len() == $len=>Ok(($($t::from_lisp(&items[$i])?,)+)),
+                    _=>Err(value.convert_error::<Self>()),
+                }
+            }
+        }
+    )+};
+}
+tuple_impls! {
+    1=>(A 0);
+    2=>(A 0, B 1);
+    3=>(A 0, B 1, C 2);
+    4=>(A 0, B 1, C 2, D 3);
+}
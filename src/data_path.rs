@@ -0,0 +1,51 @@
+//! Paths like `foo/items/3/name` go into objects by field and into lists by index. Both
+//! interpreters walk them through here so they agree on which segments are indices and what goes
+//! wrong.
+//!
+//! A segment that parses as an integer indexes a list, and a negative one counts from the end, so
+//! `-1` is the last item. On an object it is just a field name.
+
+
+use crate::error::LispError;
+
+
+/// The item `segment` picks out of a list of `len` items. `None` if it isn't an index, and the
+/// index it was if that is past either end.
+pub fn list_index(segment: &str, len: usize)->Option<Result<usize, i64>> {
+    let index = segment.parse::<i64>().ok()?;
+    let from_start = match index < 0 {
+        true=>index + len as i64,
+        false=>index,
+    };
+    if from_start < 0 || from_start >= len as i64 {
+        return Some(Err(index));
+    }
+
+    return Some(Ok(from_start as usize));
+}
+
+/// The path like it is written in the source
+pub fn text<'a>(segments: impl IntoIterator<Item = &'a str>)->String {
+    segments.into_iter().collect::<Vec<_>>().join("/")
+}
+
+/// The error for an index past the end of a list. `op` is the path, or `set` and the path.
+pub fn out_of_bounds(path: String, setting: bool, index: i64, len: usize)->LispError {
+    let op = match setting {
+        true=>format!("set {path}"),
+        false=>path,
+    };
+
+    return LispError::IndexOutOfBounds {op, index, len};
+}
+
+/// The error for a path that reached a value it can't go into, like a `none`, or a list with a
+/// segment that isn't an index. `actual` is the value's type name.
+pub fn dead_end(path: String, segment: &str, actual: &'static str, setting: bool)->LispError {
+    LispError::DeadEndPath {
+        path,
+        segment: segment.to_string(),
+        actual,
+        setting,
+    }
+}
@@ -0,0 +1,103 @@
+//! Structured errors and warnings for tooling. Normally `error_trace` prints straight to the
+//! terminal, but while `collect` is running it records `Diagnostic`s instead so they can be
+//! emitted as JSON.
+
+
+use anyhow::Error;
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    fmt::Display,
+};
+use crate::error::LispError;
+
+
+thread_local! {
+    static COLLECTED: RefCell<Option<Vec<Diagnostic>>> = RefCell::new(None);
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub file: String,
+    /// 1-based. `None` if the error has no location.
+    pub line: Option<usize>,
+    /// 1-based and counted in chars. `None` if the error has no location.
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+impl Diagnostic {
+    pub fn new(err: &Error, source: &str, file: impl Display, severity: Severity)->Self {
+        let offset = err.root_cause()
+            .downcast_ref::<LispError>()
+            .and_then(LispError::offset);
+
+        let (line, column) = match offset {
+            Some(offset)=>{
+                let (line, column) = line_column(source, offset);
+                (Some(line), Some(column))
+            },
+            None=>(None, None),
+        };
+
+        // the outermost context first, same as anyhow's alternate format
+        let message = err.chain()
+            .map(|e|e.to_string())
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        Diagnostic {
+            file: file.to_string(),
+            line,
+            column,
+            severity,
+            message,
+        }
+    }
+}
+
+
+/// Converts a byte offset into a 1-based line and column
+pub(crate) fn line_column(source: &str, offset: usize)->(usize, usize) {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n')
+        .map(|i|i + 1)
+        .unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+
+    return (line, column);
+}
+
+/// Run `f` while recording diagnostics instead of printing them
+pub fn collect<T>(f: impl FnOnce()->T)->(T, Vec<Diagnostic>) {
+    let old = COLLECTED.with(|c|c.replace(Some(Vec::new())));
+    let out = f();
+    let collected = COLLECTED.with(|c|c.replace(old)).unwrap_or_default();
+
+    return (out, collected);
+}
+
+/// Record `diag`. Does nothing if we aren't collecting.
+pub fn record(diag: Diagnostic) {
+    COLLECTED.with(|c|if let Some(collected) = &mut *c.borrow_mut() {
+        collected.push(diag);
+    });
+}
+
+pub fn is_collecting()->bool {
+    COLLECTED.with(|c|c.borrow().is_some())
+}
@@ -0,0 +1,225 @@
+//! `simple_lisp difftest`: runs every `.slp` file in a directory with both interpreters and
+//! reports the ones where they disagree. A file agrees if both print the same thing, and either
+//! both end with the same value or both fail. Error messages aren't compared since the two
+//! interpreters word them differently.
+//!
+//! A file with a `; difftest-allow: REASON` line is still run, but it may disagree. This is for
+//! features only one of the interpreters has.
+//!
+//! The fixture tests run files with `run_v1` and `run_v2` too.
+
+
+use anyhow::Result;
+use std::{
+    panic::{
+        AssertUnwindSafe,
+        catch_unwind,
+        set_hook,
+        take_hook,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use crate::{
+    interpreter::{
+        self,
+        pretty::{
+            ONE_LINE,
+            pretty_format,
+        },
+    },
+    interpreter2::{
+        self,
+        debug::value_line,
+    },
+    output::Captured,
+    source::{
+        SearchPath,
+        find_sources,
+        read_source,
+    },
+    parser,
+    InterpreterOptions,
+};
+
+
+const ALLOW_DIRECTIVE: &str = "difftest-allow:";
+
+/// What running a file with one of the interpreters did
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub stdout: String,
+    /// The value the program ended with, or the error it stopped with
+    pub result: Result<String, String>,
+}
+impl Outcome {
+    fn failed(stdout: String, error: String)->Self {
+        Outcome {
+            stdout,
+            result: Err(error),
+        }
+    }
+
+    pub fn agrees(&self, other: &Self)->bool {
+        let same_result = match (&self.result, &other.result) {
+            (Ok(l), Ok(r))=>l == r,
+            (Err(_), Err(_))=>true,
+            _=>false,
+        };
+
+        return same_result && self.stdout == other.stdout;
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub v1: Outcome,
+    pub v2: Outcome,
+    /// Why the file may disagree, if it may
+    pub allowed: Option<String>,
+}
+impl FileReport {
+    #[inline]
+    pub fn agrees(&self)->bool {
+        self.v1.agrees(&self.v2)
+    }
+
+    /// Disagrees without being allowed to
+    #[inline]
+    pub fn failed(&self)->bool {
+        self.allowed.is_none() && !self.agrees()
+    }
+}
+
+
+/// Run every `.slp` file under `dir`, in path order
+pub fn difftest_dir(dir: &Path, options: InterpreterOptions, search_path: &SearchPath)->Result<Vec<FileReport>> {
+    let mut paths = Vec::new();
+    find_sources(dir, &mut paths)?;
+    paths.sort();
+
+    // V2 panics on everything it can't do yet. We report those, so the default message is noise.
+    let hook = take_hook();
+    set_hook(Box::new(|_|{}));
+    let reports = paths.into_iter()
+        .map(|path|difftest_file(path, options, search_path))
+        .collect();
+    set_hook(hook);
+
+    return reports;
+}
+
+pub fn difftest_file(path: PathBuf, options: InterpreterOptions, search_path: &SearchPath)->Result<FileReport> {
+    let source = read_source(&path)?;
+    let allowed = source.lines()
+        .filter_map(|line|line.trim_start().strip_prefix(';'))
+        .find_map(|comment|comment.trim_start().strip_prefix(ALLOW_DIRECTIVE))
+        .map(|reason|reason.trim().to_string());
+
+    let v1 = run_v1(&source, &path, options, search_path.clone());
+    let v2 = run_v2(&source, &path, options, search_path.clone());
+
+    return Ok(FileReport {path, v1, v2, allowed});
+}
+
+fn catch_panic(f: impl FnOnce()->Outcome)->Outcome {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(outcome)=>outcome,
+        Err(payload)=>{
+            let msg = payload.downcast_ref::<&str>().map(|s|s.to_string())
+                .or_else(||payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Outcome::failed(String::new(), format!("panicked: {msg}"))
+        },
+    }
+}
+
+fn error_text(err: anyhow::Error)->String {
+    err.root_cause().to_string()
+}
+
+/// Run `source` with the V1 interpreter, capturing what it prints. Panics count as errors.
+pub fn run_v1(source: &str, path: &Path, options: InterpreterOptions, search_path: SearchPath)->Outcome {
+    catch_panic(||try_v1(source, path, options, search_path))
+}
+
+/// Run `source` with the V2 interpreter, capturing what it prints. Panics count as errors.
+pub fn run_v2(source: &str, path: &Path, options: InterpreterOptions, search_path: SearchPath)->Outcome {
+    catch_panic(||try_v2(source, path, options, search_path))
+}
+
+fn try_v1(source: &str, path: &Path, options: InterpreterOptions, search_path: SearchPath)->Outcome {
+    use interpreter::{
+        ast::convert,
+        data::Data,
+    };
+
+
+    let exprs = match parser::new_parser(source).parse_all() {
+        Ok(exprs)=>exprs,
+        Err(e)=>return Outcome::failed(String::new(), error_text(e)),
+    };
+    let mut state = match convert(exprs, path, search_path, options.prelude) {
+        Ok(state)=>state,
+        Err(e)=>return Outcome::failed(String::new(), error_text(e)),
+    };
+    state.warnings.clear();
+
+    let output = Captured::new();
+    let mut interpreter = options.new_interpreter_with_output(&mut state, Box::new(output.clone()));
+
+    let result = match interpreter.run(&mut state, None) {
+        Ok(Some(dr)) if !matches!(&*dr.get_data(), Data::None)=>{
+            Ok(pretty_format(&dr, &state.interner, &ONE_LINE))
+        },
+        Ok(_)=>Ok("None".into()),
+        Err(e)=>Err(error_text(e)),
+    };
+
+    return Outcome {
+        stdout: output.take(),
+        result,
+    };
+}
+
+fn try_v2(source: &str, path: &Path, options: InterpreterOptions, search_path: SearchPath)->Outcome {
+    use interpreter2::{
+        ast::convert,
+        optimize::optimize,
+        verify::verify,
+    };
+
+
+    let exprs = match parser::new_parser(source).parse_all() {
+        Ok(exprs)=>exprs,
+        Err(e)=>return Outcome::failed(String::new(), error_text(e)),
+    };
+    let mut state = match convert(exprs, path, search_path, options.prelude) {
+        Ok(state)=>state,
+        Err(e)=>return Outcome::failed(String::new(), error_text(e)),
+    };
+    state.warnings.clear();
+    optimize(&mut state);
+    if cfg!(debug_assertions) {
+        if let Err(e) = verify(&state) {
+            return Outcome::failed(String::new(), error_text(e));
+        }
+    }
+
+    let output = Captured::new();
+    let mut interpreter = options.new_interpreter2_with_output(&mut state, Box::new(output.clone()));
+
+    let result = match interpreter.run(&mut state, None) {
+        Ok(value)=>Ok(value_line(&value, &state)),
+        Err(e)=>Err(error_text(e)),
+    };
+
+    return Outcome {
+        stdout: output.take(),
+        result,
+    };
+}
+
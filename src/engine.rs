@@ -0,0 +1,692 @@
+//! The embedding API. An `Engine` is a V1 interpreter with its own globals, and `Value`s are the
+//! data it hands back to the host. Host functions registered with `Engine::register_fn` get a
+//! `Ctx` to make new values and call back into lisp.
+
+
+use anyhow::{
+    Error,
+    Result,
+    bail,
+};
+use std::{
+    any::Any,
+    cell::{
+        Ref,
+        RefCell,
+    },
+    collections::HashMap,
+    fmt::{
+        Debug,
+        Formatter,
+        Result as FmtResult,
+    },
+    marker::PhantomData,
+    path::{
+        Path,
+        PathBuf,
+    },
+    rc::{
+        Rc,
+        Weak,
+    },
+};
+use crate::{
+    interpreter::{
+        ast::{
+            ConvertState,
+            Interner,
+            repl_convert_with_path,
+            convert_prelude,
+        },
+        data::{
+            Data,
+            DataRef,
+            ExternalData,
+            NativeData,
+        },
+        ArgCount,
+        IdentMap,
+        Interpreter,
+        NativeFunc,
+    },
+    convert::{
+        FromLisp,
+        IntoLisp,
+    },
+    error::LispError,
+    host::HostObject,
+    capabilities::Capabilities,
+    output::{
+        Output,
+        Stdout,
+    },
+    suggest::similar_names,
+    parser::new_parser,
+    ast::Expr,
+    source::read_source,
+    InterpreterOptions,
+};
+
+
+/// Makes whatever holds it `!Send` and `!Sync`. `DataRef` is a plain pointer into the engine's
+/// data, which nothing synchronizes, so an engine and its values can't leave the thread that made
+/// them.
+type NotSend = PhantomData<*const ()>;
+
+
+/// What an engine shares with its `Value`s. They only get a weak reference, so they know when the
+/// engine is gone.
+#[derive(Default)]
+struct Shared {
+    /// A copy of the interner's names so values can read object fields without the engine
+    names: RefCell<Vec<String>>,
+}
+impl Shared {
+    /// Copy over the names interned since last time
+    fn sync_names(&self, interner: &Interner) {
+        let mut names = self.names.borrow_mut();
+        let count = names.len();
+        names.extend(interner.names_after(count).map(str::to_string));
+    }
+}
+
+
+/// A SimpleLisp interpreter with its own globals. Every `eval_*` call shares them, so a function
+/// defined by one can be called by the next.
+///
+/// Engines don't share anything, so a thread can have as many as it wants.
+pub struct Engine {
+    state: ConvertState,
+    interpreter: Interpreter,
+    shared: Rc<Shared>,
+    /// The functions from `register_fn`, so `reset` can define them again
+    host_fns: IdentMap<DataRef>,
+    _not_send: NotSend,
+}
+impl Engine {
+    /// An engine with the default limits and the prelude loaded
+    pub fn new()->Self {
+        Self::with_options(InterpreterOptions::default())
+    }
+
+    pub fn with_options(options: InterpreterOptions)->Self {
+        Self::with_output(options, Stdout)
+    }
+
+    /// Scripts print to `output` instead of stdout
+    ///
+    /// ```
+    /// use simple_lisp::{
+    ///     Captured,
+    ///     Engine,
+    ///     InterpreterOptions,
+    /// };
+    ///
+    /// let output = Captured::new();
+    /// let mut engine = Engine::with_output(InterpreterOptions::default(), output.clone());
+    /// engine.eval_str("(core/pprint 1 \"two\")").unwrap();
+    /// assert_eq!(output.take(), "1\n\"two\"\n");
+    /// ```
+    pub fn with_output(options: InterpreterOptions, output: impl Output + 'static)->Self {
+        let mut state = ConvertState::new();
+        state.reserve_module();
+        if options.prelude {
+            convert_prelude(&mut state);
+        }
+        let mut interpreter = options.new_interpreter_with_output(&mut state, Box::new(output));
+        let shared = Rc::<Shared>::default();
+
+        let engine = Rc::downgrade(&shared);
+        interpreter.set_host_field(Rc::new(move|host, name, interpreter, state|{
+            let mut ctx = Ctx {
+                interpreter,
+                state,
+                engine: &engine,
+                name,
+            };
+            let field = host.field(name, &mut ctx)?;
+            assert_same_engine(&engine, &field);
+
+            return Some(field.data_ref().clone());
+        }));
+
+        return Engine {
+            state,
+            interpreter,
+            shared,
+            host_fns: IdentMap::default(),
+            _not_send: PhantomData,
+        };
+    }
+
+    /// Forget every global that scripts or `set_global` defined, and collect everything they
+    /// used. The builtins, the prelude, and functions from `register_fn` stay, and nothing is set
+    /// up again, so this is much cheaper than a new engine. `Value`s the host still holds stay
+    /// valid.
+    ///
+    /// ```
+    /// use simple_lisp::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.eval_str("(def x 1)").unwrap();
+    ///
+    /// engine.reset();
+    /// assert!(engine.eval_str("x").is_err());
+    /// engine.eval_str("(def x 2)").unwrap();
+    /// ```
+    pub fn reset(&mut self) {
+        self.interpreter.reset_globals();
+        for (name, data) in self.host_fns.iter() {
+            self.interpreter.define_global(*name, data.clone());
+        }
+    }
+
+    /// Run `source` and return the value of its last expression
+    pub fn eval_str(&mut self, source: &str)->Result<Value> {
+        let exprs = new_parser(source).parse_all()?;
+
+        return self.eval_exprs(exprs, PathBuf::new());
+    }
+
+    /// Run the file at `path` and return the value of its last expression. Its modules are looked
+    /// for next to it.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>)->Result<Value> {
+        let path = path.as_ref();
+        let source = read_source(path)?;
+        let exprs = new_parser(&source).parse_all()?;
+        let module_path = path.parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        return self.eval_exprs(exprs, module_path);
+    }
+
+    fn eval_exprs(&mut self, exprs: Vec<Expr>, module_path: PathBuf)->Result<Value> {
+        if exprs.is_empty() {
+            let none = self.interpreter.alloc(Data::None);
+            return Ok(self.value(none));
+        }
+
+        let start = repl_convert_with_path(&mut self.state, exprs, module_path)?;
+        let data = match self.interpreter.run(&mut self.state, Some(start))? {
+            Some(data)=>data,
+            None=>self.interpreter.alloc(Data::None),
+        };
+
+        return Ok(self.value(data));
+    }
+
+    /// Define the global `name`, replacing it if it already exists. Panics if `value` is a `Value`
+    /// from a different engine.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use simple_lisp::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.set_global("config", HashMap::from([("retries".to_string(), 3)]));
+    ///
+    /// let retries: i64 = engine.eval_str("(config .retries)").unwrap().convert().unwrap();
+    /// assert_eq!(retries, 3);
+    /// ```
+    pub fn set_global(&mut self, name: &str, value: impl IntoLisp) {
+        let value = self.value_of(value);
+        let name = self.state.intern(name);
+        self.interpreter.define_global(name, value.data_ref().clone());
+    }
+
+    /// Call the global function `name` with `args` and return what it returns. Natives it calls
+    /// can call back into lisp too. Panics if any of `args` came from a different engine.
+    ///
+    /// ```
+    /// use simple_lisp::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.eval_str("(defn on-click [x y] (+ x y))").unwrap();
+    ///
+    /// let args = [engine.value_of(3), engine.value_of(4)];
+    /// assert_eq!(engine.call("on-click", &args).unwrap().as_i64(), Some(7));
+    /// ```
+    pub fn call(&mut self, name: &str, args: &[Value])->Result<Value> {
+        let Some(func) = self.get_global(name) else {
+            let globals = self.interpreter.globals()
+                .map(|(ident, _)|self.state.interner.get(ident));
+            bail!(LispError::UndefinedVar {
+                name: name.to_string(),
+                span: None,
+                suggestions: similar_names(name, globals),
+            });
+        };
+
+        let engine = Rc::downgrade(&self.shared);
+        // the values keep the args rooted while the function runs
+        let args = args.iter()
+            .map(|arg|{
+                assert_same_engine(&engine, arg);
+                arg.data_ref().clone()
+            })
+            .collect();
+        let out = self.interpreter.call(&mut self.state, func.data_ref().clone(), args)?;
+
+        return Ok(self.value(out));
+    }
+
+    /// The global `name`, if it is defined
+    pub fn get_global(&self, name: &str)->Option<Value> {
+        let data = self.interpreter.globals()
+            .find(|(ident, _)|self.state.interner.get(*ident) == name)
+            .map(|(_, data)|data.clone())?;
+
+        return Some(self.value(data));
+    }
+
+    /// Turn some Rust data into a `Value` from this engine
+    pub fn value_of(&mut self, value: impl IntoLisp)->Value {
+        let engine = Rc::downgrade(&self.shared);
+        let mut ctx = Ctx {
+            interpreter: &mut self.interpreter,
+            state: &mut self.state,
+            engine: &engine,
+            name: "host",
+        };
+
+        return value.into_lisp(&mut ctx);
+    }
+
+    /// Free everything that isn't reachable from the globals or a `Value`
+    pub fn collect_garbage(&mut self) {
+        self.interpreter.gc_collect();
+    }
+
+    /// How many objects the engine has, including the ones the next collection will free
+    pub fn live_data(&self)->usize {
+        self.interpreter.live_data()
+    }
+
+    /// Send what scripts print to `output` from now on
+    pub fn set_output(&mut self, output: impl Output + 'static) {
+        self.interpreter.set_output(Box::new(output));
+    }
+
+    /// The warnings from converting everything so far. They are only returned once.
+    pub fn take_warnings(&mut self)->Vec<Error> {
+        std::mem::take(&mut self.state.warnings)
+    }
+
+    /// Define the global function `name`, replacing it if it already exists. The engine checks the
+    /// argument count before calling `f`. Returning a `Value` from a different engine panics.
+    ///
+    /// ```
+    /// use std::{
+    ///     cell::RefCell,
+    ///     rc::Rc,
+    /// };
+    /// use simple_lisp::{
+    ///     ArgCount,
+    ///     Engine,
+    /// };
+    ///
+    /// let log = Rc::new(RefCell::new(Vec::new()));
+    /// let host_log = log.clone();
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_fn("host-log", ArgCount::Exact(1), move|ctx, args|{
+    ///     let Some(msg) = args[0].as_string() else {
+    ///         return Err(ctx.type_error("string", &args[0]));
+    ///     };
+    ///     host_log.borrow_mut().push(msg);
+    ///
+    ///     Ok(ctx.none())
+    /// });
+    ///
+    /// engine.eval_str(r#"(host-log "hello") (host-log "world")"#).unwrap();
+    /// assert_eq!(*log.borrow(), ["hello", "world"]);
+    /// assert!(engine.eval_str("(host-log 5)").is_err());
+    /// ```
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arg_count: ArgCount,
+        f: impl Fn(&mut Ctx, &[Value])->Result<Value> + 'static,
+    ) {
+        self.register_fn_needing(name, arg_count, Capabilities::empty(), f);
+    }
+
+    /// Like `register_fn`, but `f` is only called if the engine was given all of `needs`. Otherwise
+    /// the call fails with a `capability denied` error, just like a builtin would.
+    pub fn register_fn_needing(
+        &mut self,
+        name: &str,
+        arg_count: ArgCount,
+        needs: Capabilities,
+        f: impl Fn(&mut Ctx, &[Value])->Result<Value> + 'static,
+    ) {
+        let engine = Rc::downgrade(&self.shared);
+        let fn_name: Rc<str> = name.into();
+        let ctx_name = fn_name.clone();
+        let native = NativeFunc::new(move|args, interpreter, state|{
+            interpreter.require(needs)?;
+            let args = args.into_iter()
+                .map(|data|Value::new(data, &engine))
+                .collect::<Vec<_>>();
+            engine.upgrade().unwrap().sync_names(&state.interner);
+            let mut ctx = Ctx {
+                interpreter,
+                state,
+                engine: &engine,
+                name: &ctx_name,
+            };
+            let out = f(&mut ctx, &args)?;
+            assert_same_engine(&engine, &out);
+
+            return Ok(out.data_ref().clone());
+        });
+
+        let data = self.interpreter.alloc(Data::NativeFn(fn_name, native, arg_count));
+        data.set_pinned();
+        let name = self.state.intern(name);
+        self.interpreter.define_global(name, data.clone());
+        self.host_fns.insert(name, data);
+    }
+
+    fn value(&self, data: DataRef)->Value {
+        self.shared.sync_names(&self.state.interner);
+
+        return Value::new(data, &Rc::downgrade(&self.shared));
+    }
+}
+impl Default for Engine {
+    fn default()->Self {
+        Self::new()
+    }
+}
+
+
+/// What a function from `Engine::register_fn` gets besides its arguments. It can make new values,
+/// call lisp functions, and make errors that look like the ones builtins raise.
+pub struct Ctx<'a> {
+    interpreter: &'a mut Interpreter,
+    state: &'a mut ConvertState,
+    engine: &'a Weak<Shared>,
+    /// The name the function was registered as
+    name: &'a str,
+}
+impl Ctx<'_> {
+    fn alloc(&mut self, data: Data)->Value {
+        let data = self.interpreter.alloc(data);
+        Value::new(data, self.engine)
+    }
+
+    pub fn none(&mut self)->Value {
+        self.alloc(Data::None)
+    }
+
+    pub fn number(&mut self, n: i64)->Value {
+        self.alloc(Data::Number(n))
+    }
+
+    pub fn float(&mut self, f: f64)->Value {
+        self.alloc(Data::Float(f))
+    }
+
+    pub fn bool(&mut self, b: bool)->Value {
+        self.alloc(Data::Bool(b))
+    }
+
+    pub fn char(&mut self, c: char)->Value {
+        self.alloc(Data::Char(c))
+    }
+
+    pub fn string(&mut self, s: impl Into<String>)->Value {
+        self.alloc(Data::String(s.into()))
+    }
+
+    /// Panics if any of `items` came from a different engine
+    pub fn list(&mut self, items: &[Value])->Value {
+        let items = items.iter()
+            .map(|item|{
+                assert_same_engine(self.engine, item);
+                item.data_ref().clone()
+            })
+            .collect();
+
+        return self.alloc(Data::List(items));
+    }
+
+    /// Panics if any of the values came from a different engine
+    pub fn object<'n>(&mut self, fields: impl IntoIterator<Item = (&'n str, Value)>)->Value {
+        let mut object = IdentMap::default();
+        for (name, value) in fields {
+            assert_same_engine(self.engine, &value);
+            object.insert(self.state.intern(name), value.data_ref().clone());
+        }
+        self.engine.upgrade().unwrap().sync_names(&self.state.interner);
+
+        return self.alloc(Data::Object(object));
+    }
+
+    /// Turn some Rust data into a `Value`
+    pub fn value_of(&mut self, value: impl IntoLisp)->Value {
+        value.into_lisp(self)
+    }
+
+    pub(crate) fn check_engine(&self, value: &Value) {
+        assert_same_engine(self.engine, value);
+    }
+
+    /// Call a lisp or native function and return what it returns. Panics if `func` or any of
+    /// `args` came from a different engine.
+    pub fn call(&mut self, func: &Value, args: &[Value])->Result<Value> {
+        assert_same_engine(self.engine, func);
+        let args = args.iter()
+            .map(|arg|{
+                assert_same_engine(self.engine, arg);
+                arg.data_ref().clone()
+            })
+            .collect();
+        let out = self.interpreter.call(self.state, func.data_ref().clone(), args)?;
+
+        return Ok(Value::new(out, self.engine));
+    }
+
+    /// The error for getting a `got` when this function wanted an `expected`, like `"string"`
+    pub fn type_error(&self, expected: &'static str, got: &Value)->Error {
+        Error::new(LispError::Type {
+            op: self.name.to_string(),
+            expected,
+            actual: got.type_name(),
+        })
+    }
+}
+
+
+/// Some data from an `Engine`. The collector won't free it while the host holds on to it. Using it
+/// after its engine is dropped panics.
+pub struct Value {
+    /// Only `None` while it is being dropped
+    data: Option<ExternalData>,
+    engine: Weak<Shared>,
+    _not_send: NotSend,
+}
+impl Drop for Value {
+    fn drop(&mut self) {
+        // the engine already freed the data, so there is nothing to unroot
+        if self.engine.strong_count() == 0 {
+            std::mem::forget(self.data.take());
+        }
+    }
+}
+impl Clone for Value {
+    fn clone(&self)->Self {
+        Value {
+            data: Some(self.data_ref().clone().external()),
+            engine: self.engine.clone(),
+            _not_send: PhantomData,
+        }
+    }
+}
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        if self.engine.strong_count() == 0 {
+            return write!(f, "<dropped>");
+        }
+
+        self.data_ref().fmt(f)
+    }
+}
+impl Value {
+    fn new(data: DataRef, engine: &Weak<Shared>)->Self {
+        Value {
+            data: Some(data.external()),
+            engine: engine.clone(),
+            _not_send: PhantomData,
+        }
+    }
+
+    fn data_ref(&self)->&DataRef {
+        assert!(self.engine.strong_count() > 0, "Used a `Value` after its `Engine` was dropped");
+
+        return self.data.as_ref().unwrap();
+    }
+
+    fn data(&self)->Ref<'_, Data> {
+        self.data_ref().get_data()
+    }
+
+    /// Like `number` or `list`
+    pub fn type_name(&self)->&'static str {
+        self.data().type_name()
+    }
+
+    pub fn is_none(&self)->bool {
+        matches!(&*self.data(), Data::None)
+    }
+
+    pub fn as_i64(&self)->Option<i64> {
+        match &*self.data() {
+            Data::Number(n)=>Some(*n),
+            _=>None,
+        }
+    }
+
+    pub fn as_f64(&self)->Option<f64> {
+        match &*self.data() {
+            Data::Float(f)=>Some(*f),
+            _=>None,
+        }
+    }
+
+    pub fn as_bool(&self)->Option<bool> {
+        match &*self.data() {
+            Data::Bool(b)=>Some(*b),
+            _=>None,
+        }
+    }
+
+    pub fn as_char(&self)->Option<char> {
+        match &*self.data() {
+            Data::Char(c)=>Some(*c),
+            _=>None,
+        }
+    }
+
+    pub fn as_string(&self)->Option<String> {
+        match &*self.data() {
+            Data::String(s)=>Some(s.clone()),
+            _=>None,
+        }
+    }
+
+    pub fn as_list(&self)->Option<Vec<Value>> {
+        match &*self.data() {
+            Data::List(items)=>Some(items.iter()
+                .map(|item|Value::new(item.clone(), &self.engine))
+                .collect()),
+            _=>None,
+        }
+    }
+
+    pub fn as_object(&self)->Option<HashMap<String, Value>> {
+        let data = self.data();
+        let Data::Object(fields) = &*data else {return None};
+        let engine = self.engine.upgrade().unwrap();
+        let names = engine.names.borrow();
+
+        return Some(fields.iter()
+            .map(|(name, value)|(names[name.0].clone(), Value::new(value.clone(), &self.engine)))
+            .collect());
+    }
+
+    /// Hand a host object to scripts. The host can keep its own `Rc` to see what scripts do to it.
+    pub fn from_host<T: HostObject>(ctx: &mut Ctx, obj: Rc<T>)->Value {
+        ctx.alloc(Data::NativeData(NativeData::Custom(obj)))
+    }
+
+    /// The host object this holds, if it is a `T`
+    pub fn downcast_host<T: HostObject>(&self)->Option<Rc<T>> {
+        match &*self.data() {
+            Data::NativeData(NativeData::Custom(host))=>{
+                let host: Rc<dyn Any> = host.clone();
+                host.downcast().ok()
+            },
+            _=>None,
+        }
+    }
+
+    /// Convert this to some Rust data
+    ///
+    /// ```
+    /// use simple_lisp::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// let n: i64 = engine.eval_str("(+ 1 2)").unwrap().convert().unwrap();
+    /// assert_eq!(n, 3);
+    ///
+    /// let err = engine.eval_str("\"3\"").unwrap().convert::<i64>().unwrap_err();
+    /// assert_eq!(err.to_string(), "Cannot convert string to `i64`");
+    /// ```
+    pub fn convert<T: FromLisp>(&self)->Result<T> {
+        T::from_lisp(self)
+    }
+
+    /// The error for this not being convertible to a `T`. For `FromLisp` impls.
+    pub fn convert_error<T: ?Sized>(&self)->Error {
+        let actual = match &*self.data() {
+            Data::List(items) if items.len() == 1=>"list of 1 item".to_string(),
+            Data::List(items)=>format!("list of {} items", items.len()),
+            data=>data.type_name().to_string(),
+        };
+
+        return Error::new(LispError::Convert {
+            expected: short_type_name(std::any::type_name::<T>()),
+            actual,
+        });
+    }
+}
+
+fn assert_same_engine(engine: &Weak<Shared>, value: &Value) {
+    assert!(
+        value.engine.ptr_eq(engine),
+        "`Value`s can only be used with the `Engine` that made them",
+    );
+}
+
+/// `alloc::vec::Vec<alloc::string::String>` is just `Vec<String>` to most people
+fn short_type_name(name: &str)->String {
+    let mut out = String::new();
+    let mut segment = String::new();
+    for c in name.chars() {
+        match c {
+            ':'=>segment.clear(),
+            c if c.is_alphanumeric() || c == '_'=>segment.push(c),
+            c=>{
+                out.push_str(&segment);
+                segment.clear();
+                out.push(c);
+            },
+        }
+    }
+    out.push_str(&segment);
+
+    return out;
+}
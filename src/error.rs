@@ -0,0 +1,625 @@
+//! What can go wrong when parsing, converting or running a program. These still travel inside an
+//! `anyhow::Error` so callers can add context, but `error_trace` and the REPL match on the root
+//! cause instead of downcasting to a bunch of different types. Natives should return the variant
+//! that fits so scripts can find out what kind of error it was once they can catch them.
+
+
+use parser_helper::SimpleError;
+use anyhow::Error;
+use crate::{
+    numeric::FloatDisplay,
+    suggest::did_you_mean,
+};
+use std::{
+    error::Error as ErrorTrait,
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+    io::Error as IoError,
+    ops::Range,
+    path::PathBuf,
+};
+
+
+/// Byte offsets into the source
+pub type Span = Range<usize>;
+
+
+#[derive(Debug)]
+pub enum LispError {
+    Parse(SimpleError<String>),
+    /// The source ended in the middle of an expression. The REPL reads another line instead of
+    /// printing this.
+    Incomplete(SimpleError<String>),
+
+    UndefinedVar {
+        name: String,
+        span: Option<Span>,
+        /// Visible names that are close to `name`
+        suggestions: Vec<String>,
+    },
+    /// Defining a var or global that already exists
+    AlreadyDefined {
+        name: String,
+        span: Option<Span>,
+    },
+    /// Setting or defining again a `defconst` global
+    Constant {
+        name: String,
+        span: Option<Span>,
+    },
+    /// Only a warning. A top level `def` of a global that already exists, which replaces it. If
+    /// `builtin` is set it was one of the builtins, which is like `set`ting it.
+    Redefined {
+        name: String,
+        span: Option<Span>,
+        builtin: bool,
+    },
+    /// Only a warning. A local that is never read. `span` is where it was defined.
+    UnusedVar {
+        name: String,
+        span: Option<Span>,
+    },
+    /// Only a warning. A local with the same name as a local of an outer scope, or as a builtin
+    /// global if `builtin` is set.
+    ShadowedVar {
+        name: String,
+        span: Option<Span>,
+        builtin: bool,
+    },
+
+    Arity {
+        /// `None` for anonymous functions
+        name: Option<String>,
+        /// Every way the function can be called
+        expected: Vec<Signature>,
+        got: usize,
+    },
+    /// `op` is what we were doing, like `+` or `call`. The types are names from `Data::type_name`.
+    Type {
+        op: String,
+        expected: &'static str,
+        actual: &'static str,
+    },
+    /// Ordering two values that can't be ordered against each other, like a number and a string
+    Compare {
+        op: String,
+        left: &'static str,
+        right: &'static str,
+    },
+    UndefinedField {
+        name: String,
+    },
+    /// Reading a `def-` global from outside of its module
+    PrivateField {
+        name: String,
+        module: String,
+    },
+    /// A path reached a value it can't go into, like the `none` that `foo/items/3` is in
+    /// `(set foo/items/3/name 1)`. `actual` is the type name of the value, and `segment` is the
+    /// part of the path it couldn't take.
+    DeadEndPath {
+        path: String,
+        segment: String,
+        actual: &'static str,
+        setting: bool,
+    },
+    /// Reading a field of a module's value that the module doesn't define
+    UndefinedMember {
+        module: String,
+        name: String,
+        /// Everything the module does define
+        exports: Vec<String>,
+    },
+    DivisionByZero,
+    /// Indexing past either end of a list or string. Negative indices are out of bounds, except in
+    /// paths where they count from the end.
+    IndexOutOfBounds {
+        op: String,
+        index: i64,
+        len: usize,
+    },
+    /// Integer math that doesn't fit in 64 bits
+    Overflow {
+        op: String,
+    },
+    /// `int` on a float that has no integer value, like NaN, infinity or anything past `i64`
+    NotAnInteger {
+        op: String,
+        value: f64,
+    },
+    /// A native needed something the interpreter wasn't allowed to do. `capability` is a name from
+    /// `Capabilities::name`.
+    CapabilityDenied {
+        capability: &'static str,
+    },
+    /// Data was used while something else was modifying it, or modified while it was used
+    Borrowed {
+        op: String,
+        mutating: bool,
+    },
+    /// Copying data to another thread that only works in the interpreter that made it, like a
+    /// closure or a file
+    NotSendable {
+        op: String,
+        actual: &'static str,
+    },
+    /// Calling something that isn't a function, like `(42)`. `value` is how it prints, if it is
+    /// short enough to say.
+    NotCallable {
+        value: Option<String>,
+        actual: &'static str,
+    },
+    /// Hashing data that has no structural hash, like a function, or that contains itself
+    Unhashable {
+        op: String,
+        actual: &'static str,
+    },
+    /// Two branches of a multi-arity fn that take the same argument count, or the same minimum if
+    /// `variadic`
+    ConflictingArity {
+        first: String,
+        second: String,
+        count: usize,
+        variadic: bool,
+    },
+    /// `(def [a b] data)` where the list `data` made has too few items, or too many without a
+    /// remainder
+    DestructureLength {
+        pattern: String,
+        needed: usize,
+        variadic: bool,
+        actual: usize,
+    },
+    /// `core/assert` got something false. `message` is its second argument, if it had one.
+    AssertFailed {
+        message: Option<String>,
+    },
+    /// Calling a function with `max` calls already on the stack. `name` is `None` for anonymous
+    /// functions.
+    RecursionLimit {
+        max: usize,
+        name: Option<String>,
+    },
+    /// A generator that called `next` on itself
+    GeneratorRunning,
+    /// `yield` ran without a generator to suspend
+    YieldOutsideGenerator,
+    /// A native got an argument of the right type that it can't use, like a negative count.
+    /// `expected` finishes "`op` needs ...", and `actual` is how the argument prints.
+    OutOfRange {
+        op: String,
+        expected: &'static str,
+        actual: String,
+    },
+    /// A native that takes one of a set of names, like `style`, got some other name. `kind` is
+    /// what the names are.
+    UnknownName {
+        op: String,
+        kind: &'static str,
+        name: String,
+        options: Vec<String>,
+    },
+    /// Every temp name tried in `dir` was already taken
+    TempNameTaken {
+        dir: PathBuf,
+        attempts: usize,
+    },
+    /// `recv` on an empty channel that nothing can send to anymore
+    ChannelClosed,
+    /// Joining a thread that was already joined
+    ThreadJoined,
+    /// The thread being joined returned an error
+    ThreadFailed {
+        error: Error,
+    },
+    ThreadPanicked,
+    /// A fn or module id that was reserved but never converted. Always a bug in the converter.
+    Unresolved {
+        kind: &'static str,
+        id: usize,
+    },
+    /// Only a warning. A `&` branch of a multi-arity fn that every argument count goes past, to
+    /// the branches in `covered_by`.
+    UnreachableBranch {
+        params: String,
+        covered_by: Vec<String>,
+    },
+
+    /// Something went wrong while loading a module. `error_trace` prints how we got to the module,
+    /// then `error` with the module's source.
+    Module {
+        /// Starts at the root, ends with the module that failed
+        imports: Vec<Import>,
+        /// `None` if the module wasn't found
+        path: Option<PathBuf>,
+        /// Empty if it couldn't be read
+        source: String,
+        error: Error,
+    },
+    ModuleNotFound {
+        name: String,
+        /// Every path the module could have been at
+        tried: Vec<PathBuf>,
+    },
+    /// A module used itself, directly or through other modules, before it finished loading
+    ModuleCycle {
+        name: String,
+    },
+    /// `(use x :only [...])` named something `x` doesn't define
+    UndefinedImport {
+        module: String,
+        missing: Vec<String>,
+        /// Everything the module does define
+        exports: Vec<String>,
+    },
+    /// Only a warning. The module is in more than one directory of the search path.
+    AmbiguousModule {
+        name: String,
+        used: PathBuf,
+        ignored: Vec<PathBuf>,
+    },
+    /// A `Value` couldn't be converted to a Rust type
+    Convert {
+        /// The Rust type, like `Vec<i64>`
+        expected: String,
+        /// What the value was, like `string` or `list of 3 items`
+        actual: String,
+    },
+    /// A script or module couldn't be read
+    Read {
+        path: PathBuf,
+        reason: ReadFailure,
+    },
+    Io(IoError),
+}
+impl ErrorTrait for LispError {
+    fn source(&self)->Option<&(dyn ErrorTrait + 'static)> {
+        match self {
+            Self::Io(e)|Self::Read{reason: ReadFailure::Io(e),..}=>Some(e),
+            _=>None,
+        }
+    }
+}
+impl Display for LispError {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        match self {
+            Self::Parse(e)|Self::Incomplete(e)=>e.fmt(f),
+            Self::UndefinedVar{name, suggestions,..}=>{
+                write!(f, "Var `{name}` is not defined")?;
+                if let Some(hint) = did_you_mean(suggestions) {
+                    write!(f, ", {hint}")?;
+                }
+
+                Ok(())
+            },
+            Self::AlreadyDefined{name,..}=>write!(f, "Var `{name}` is already defined"),
+            Self::Constant{name,..}=>write!(f, "`{name}` is a constant, so it can't be set or defined again"),
+            Self::Redefined{name, builtin: false,..}=>write!(f, "Global `{name}` is already defined, this replaces it"),
+            Self::Redefined{name, builtin: true,..}=>write!(f, "This replaces the builtin `{name}` for everything after it"),
+            Self::UnusedVar{name,..}=>write!(f, "Var `{name}` is never used. Start its name with `_` if that is on purpose"),
+            Self::ShadowedVar{name, builtin: false,..}=>write!(f, "Var `{name}` shadows a var of the same name in an outer scope"),
+            Self::ShadowedVar{name, builtin: true,..}=>write!(f, "Var `{name}` shadows the builtin `{name}`"),
+            Self::Arity{name, expected, got}=>{
+                match name {
+                    Some(name)=>write!(f, "Function `{name}` takes ")?,
+                    None=>write!(f, "Function takes ")?,
+                }
+                write!(f, "{}", join_list(expected.iter().map(Signature::count), "or"))?;
+                match expected.as_slice() {
+                    [sig] if sig.count == 1 && !sig.variadic=>write!(f, " argument")?,
+                    _=>write!(f, " arguments")?,
+                }
+
+                let params = expected.iter()
+                    .filter_map(|sig|sig.params.as_deref())
+                    .collect::<Vec<_>>();
+                if params.len() > 0 {
+                    write!(f, " ({})", params.join(", "))?;
+                }
+
+                write!(f, ", but got {got}")
+            },
+            Self::Type{op, expected, actual}=>write!(f, "Type error: `{op}` expected {expected}, but got {actual}"),
+            Self::Compare{op, left, right}=>write!(f, "Type error: `{op}` can't compare {left} with {right}"),
+            Self::UndefinedField{name}=>write!(f, "Object does not have a field named `{name}`"),
+            Self::PrivateField{name, module}=>write!(f, "No such field, `{name}` is private to module `{module}`"),
+            Self::DeadEndPath{path, segment, actual, setting}=>{
+                let what = match segment.parse::<i64>() {
+                    Ok(_)=>"item",
+                    Err(_)=>"field",
+                };
+                let doing = if *setting {"setting"} else {"reading"};
+                write!(f, "Cannot access {what} `{segment}` of {actual} while {doing} `{path}`")
+            },
+            Self::UndefinedMember{module, name, exports}=>{
+                write!(f, "Module `{module}` has no member `{name}`")?;
+                if exports.is_empty() {
+                    return write!(f, ", it doesn't export anything");
+                }
+
+                let exports = exports.iter().map(|n|format!("`{n}`"));
+                write!(f, ", it exports {}", join_list(exports, "and"))
+            },
+            Self::DivisionByZero=>write!(f, "Division by zero"),
+            Self::IndexOutOfBounds{op, index, len}=>write!(f, "`{op}`: index {index} is out of bounds for length {len}"),
+            Self::Overflow{op}=>write!(f, "Integer overflow in `{op}`"),
+            Self::NotAnInteger{op, value}=>write!(f, "`{op}` can't convert {} to an integer", FloatDisplay(*value)),
+            Self::CapabilityDenied{capability}=>write!(f, "capability denied: {capability}"),
+            Self::Borrowed{op, mutating: false}=>write!(f, "`{op}` can't read data while it is being modified"),
+            Self::Borrowed{op, mutating: true}=>write!(f, "`{op}` can't modify data while it is being used"),
+            Self::NotSendable{op, actual}=>write!(f, "`{op}` can't copy a {actual} to another thread, only plain data"),
+            Self::NotCallable{value: Some(value), actual}=>write!(f, "{value} is not callable, it is a {actual}"),
+            Self::NotCallable{value: None, actual}=>write!(f, "A {actual} is not callable"),
+            Self::Unhashable{op, actual}=>write!(f, "`{op}` can't hash a {actual}, only plain data"),
+            Self::ConflictingArity{first, second, count, variadic: true}=>write!(f, "Fn branches `{first}` and `{second}` both take {count} or more arguments"),
+            Self::ConflictingArity{first, second, count: 1, variadic: false}=>write!(f, "Fn branches `{first}` and `{second}` both take exactly 1 argument"),
+            Self::ConflictingArity{first, second, count, variadic: false}=>write!(f, "Fn branches `{first}` and `{second}` both take exactly {count} arguments"),
+            Self::DestructureLength{pattern, needed, variadic, actual}=>{
+                let exactly = if *variadic {"at least"} else {"exactly"};
+                let items = if *needed == 1 {"item"} else {"items"};
+                write!(f, "`{pattern}` needs {exactly} {needed} {items}, but the list has {actual}")
+            },
+            Self::AssertFailed{message: Some(message)}=>write!(f, "Assertion failed: {message}"),
+            Self::AssertFailed{message: None}=>write!(f, "Assertion failed"),
+            Self::RecursionLimit{max, name: Some(name)}=>write!(f, "maximum recursion depth {max} exceeded while calling `{name}`"),
+            Self::RecursionLimit{max, name: None}=>write!(f, "maximum recursion depth {max} exceeded while calling an anonymous function"),
+            Self::GeneratorRunning=>write!(f, "A generator can't resume itself while it is running"),
+            Self::YieldOutsideGenerator=>write!(f, "`yield` can only be used while a generator is running"),
+            Self::OutOfRange{op, expected, actual}=>write!(f, "`{op}` needs {expected}, but got {actual}"),
+            Self::UnknownName{op, kind, name, options}=>{
+                let options = options.iter()
+                    .map(|o|format!("`{o}`"))
+                    .collect::<Vec<_>>();
+                write!(f, "`{op}` has no {kind} named `{name}`, expected one of {}", options.join(", "))
+            },
+            Self::TempNameTaken{dir, attempts}=>write!(f, "Could not make a unique name in `{}` after {attempts} tries", dir.display()),
+            Self::ChannelClosed=>write!(f, "`recv` would wait forever, nothing else has this channel"),
+            Self::ThreadJoined=>write!(f, "This thread was already joined"),
+            Self::ThreadFailed{error}=>write!(f, "The thread failed: {error}"),
+            Self::ThreadPanicked=>write!(f, "The thread panicked"),
+            Self::Unresolved{kind, id}=>write!(f, "Internal error: unresolved {kind} #{id}"),
+            Self::UnreachableBranch{params, covered_by}=>{
+                let covered_by = covered_by.iter().map(|p|format!("`{p}`"));
+                write!(f, "Fn branch `{params}` can never be called, {} take every argument count it would", join_list(covered_by, "and"))
+            },
+            Self::Module{imports, error,..}=>match imports.last() {
+                Some(import)=>write!(f, "Could not load {import}: {error}"),
+                None=>write!(f, "Could not load module: {error}"),
+            },
+            Self::ModuleNotFound{name, tried}=>{
+                let tried = tried.iter().map(|p|format!("`{}`", p.display()));
+                write!(f, "Module `{name}` was not found, tried {}", join_list(tried, "and"))
+            },
+            Self::ModuleCycle{name}=>write!(f, "Module `{name}` was used while it was still loading"),
+            Self::UndefinedImport{module, missing, exports}=>{
+                let missing = missing.iter().map(|n|format!("`{n}`"));
+                write!(f, "Module `{module}` does not define {}", join_list(missing, "or"))?;
+                if exports.is_empty() {
+                    return write!(f, ", it doesn't define anything");
+                }
+
+                let exports = exports.iter().map(|n|format!("`{n}`"));
+                write!(f, ", it only defines {}", join_list(exports, "and"))
+            },
+            Self::AmbiguousModule{name, used, ignored}=>{
+                let ignored = ignored.iter().map(|p|format!("`{}`", p.display()));
+                write!(
+                    f,
+                    "Module `{name}` is in more than one search directory. Using `{}` and ignoring {}",
+                    used.display(),
+                    join_list(ignored, "and"),
+                )
+            },
+            Self::Convert{expected, actual}=>write!(f, "Cannot convert {actual} to `{expected}`"),
+            Self::Read{path, reason}=>write!(f, "Cannot read `{}`: {reason}", path.display()),
+            Self::Io(e)=>e.fmt(f),
+        }
+    }
+}
+impl From<IoError> for LispError {
+    fn from(e: IoError)->Self {
+        LispError::Io(e)
+    }
+}
+impl LispError {
+    /// Where in the source this happened, if we know
+    pub fn offset(&self)->Option<usize> {
+        match self {
+            Self::Parse(e)|Self::Incomplete(e)=>Some(e.span.start),
+            Self::UndefinedVar{span,..}|
+                Self::AlreadyDefined{span,..}|
+                Self::Constant{span,..}|
+                Self::Redefined{span,..}|
+                Self::UnusedVar{span,..}|
+                Self::ShadowedVar{span,..}=>span.as_ref().map(|s|s.start),
+            _=>None,
+        }
+    }
+}
+
+
+/// One step on the way from the root to a module
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub name: String,
+    /// The file that declared the module. `None` in the REPL.
+    pub importer: Option<PathBuf>,
+}
+impl Display for Import {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        write!(f, "module `{}`", self.name)?;
+        if let Some(importer) = &self.importer {
+            write!(f, " from `{}`", importer.display())?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Why a file couldn't be read
+#[derive(Debug)]
+pub enum ReadFailure {
+    Io(IoError),
+    Directory,
+    /// `offset` is the first byte that isn't
+    NotUtf8 {
+        offset: usize,
+    },
+}
+impl Display for ReadFailure {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        match self {
+            // the OS error code doesn't help anyone
+            Self::Io(e)=>match e.raw_os_error() {
+                Some(code)=>write!(f, "{}", e.to_string().trim_end_matches(&format!(" (os error {code})"))),
+                None=>e.fmt(f),
+            },
+            Self::Directory=>write!(f, "it is a directory"),
+            Self::NotUtf8{offset}=>write!(f, "it is not valid UTF-8 (bad byte at offset {offset})"),
+        }
+    }
+}
+
+/// One way to call a function, for arity errors
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    /// How many arguments it takes, or the minimum if it is variadic
+    pub count: usize,
+    pub variadic: bool,
+    /// The parameters like they are written in the source: `[a b & rest]`. Natives don't have
+    /// these.
+    pub params: Option<String>,
+}
+impl Signature {
+    pub fn exact(count: usize)->Self {
+        Signature {
+            count,
+            variadic: false,
+            params: None,
+        }
+    }
+
+    pub fn at_least(count: usize)->Self {
+        Signature {
+            count,
+            variadic: true,
+            params: None,
+        }
+    }
+
+    /// Like `2` or `2+`
+    pub fn count(&self)->String {
+        if self.variadic {
+            return format!("{}+", self.count);
+        }
+
+        return self.count.to_string();
+    }
+}
+
+/// Joins `items` like `a, b or c`, where `conjunction` is the `or`
+fn join_list(items: impl Iterator<Item = String>, conjunction: &str)->String {
+    let items = items.collect::<Vec<_>>();
+
+    return match items.split_last() {
+        Some((last, rest)) if !rest.is_empty()=>format!("{} {conjunction} {last}", rest.join(", ")),
+        _=>items.join(""),
+    };
+}
+
+
+/// A runtime error and the calls that were running when it happened. `error_trace` prints the
+/// error like normal and the frames under it.
+#[derive(Debug)]
+pub struct StackTrace {
+    pub error: Error,
+    /// The innermost call first
+    pub frames: Vec<TraceFrame>,
+}
+impl ErrorTrait for StackTrace {
+    fn source(&self)->Option<&(dyn ErrorTrait + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+impl Display for StackTrace {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        self.error.fmt(f)
+    }
+}
+impl StackTrace {
+    /// Attach `frames` to `error`. Errors outside of any call are left alone.
+    pub fn wrap(error: Error, frames: Vec<TraceFrame>)->Error {
+        if frames.is_empty() {
+            return error;
+        }
+
+        return Error::new(StackTrace {error, frames});
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    /// Like `` `name` ``, `<anonymous>` or `` module `name` ``
+    pub name: String,
+    /// The id of the instruction that made the call
+    pub call_site: Option<usize>,
+    /// How many tail calls replaced this frame. The functions they came from aren't in the trace.
+    pub tail_calls: usize,
+}
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        write!(f, "in {}", self.name)?;
+        if let Some(id) = self.call_site {
+            write!(f, ", called at instruction {id}")?;
+        }
+        match self.tail_calls {
+            0=>{},
+            1=>write!(f, " (1 tail call elided)")?,
+            n=>write!(f, " ({n} tail calls elided)")?,
+        }
+
+        return Ok(());
+    }
+}
+
+
+/// A runtime error and where in the source it happened, from a source map. `error_trace` prints
+/// the error with a snippet of that line.
+#[derive(Debug)]
+pub struct Located {
+    pub error: Error,
+    pub location: SourceLocation,
+}
+impl ErrorTrait for Located {
+    fn source(&self)->Option<&(dyn ErrorTrait + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+impl Display for Located {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        self.error.fmt(f)
+    }
+}
+impl Located {
+    /// Attach `location` to `error`, if there is one
+    pub fn wrap(error: Error, location: Option<SourceLocation>)->Error {
+        match location {
+            Some(location)=>Error::new(Located {error, location}),
+            None=>error,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceLocation {
+    pub path: PathBuf,
+    /// 1-based
+    pub line: usize,
+    /// 1-based and counted in chars
+    pub column: usize,
+    /// The file's contents, if we have them
+    pub source: Option<String>,
+}
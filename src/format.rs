@@ -0,0 +1,398 @@
+//! The source formatter used by `simple_lisp fmt`. This works on the tokens instead of the parsed
+//! AST because the parser desugars things like `defn` and `chain`, and it has no idea where the
+//! comments were. Atoms are written back exactly as they appear in the source.
+//!
+//! The rules are simple so formatting formatted code never changes it:
+//! - Top level forms are separated by a blank line. Comments directly above a form stay attached.
+//! - A form that fits in the line width stays on one line, unless it has a comment or it is (or
+//!   contains) a `defn`, `cond`, `begin`, or `chain`.
+//! - Otherwise the head and a few special form arguments (like the name in `def`) stay on the
+//!   first line, and the rest go on their own lines, indented two spaces past the opening bracket.
+//! - Single blank lines inside of broken forms are kept.
+//! - Comments after code on the same line stay there.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use logos::Logos;
+use crate::lexer::*;
+
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+
+enum Node<'a> {
+    Atom(&'a str),
+    Comment(&'a str),
+    /// A quote or splat and the thing it applies to
+    Prefixed(&'a str, Box<Self>),
+    Group {
+        open: &'static str,
+        close: &'static str,
+        items: Vec<Item<'a>>,
+    },
+}
+impl<'a> Node<'a> {
+    fn head(&self)->Option<&'a str> {
+        match self {
+            Self::Group{open: "(", items, ..}=>match items.first().map(|i|&i.node) {
+                Some(Self::Atom(a))=>Some(a),
+                _=>None,
+            },
+            _=>None,
+        }
+    }
+
+    fn is_comment(&self)->bool {
+        match self {
+            Self::Comment(_)=>true,
+            _=>false,
+        }
+    }
+}
+
+struct Item<'a> {
+    node: Node<'a>,
+    /// A comment on the same line, after this node
+    comment: Option<&'a str>,
+    /// There was a blank line before this item
+    blank_before: bool,
+}
+
+
+struct Reader<'a> {
+    source: &'a str,
+    tokens: Vec<(Token<'a>, &'a str, usize, usize)>,
+    index: usize,
+}
+impl<'a> Reader<'a> {
+    fn new(source: &'a str)->Result<Self> {
+        let mut lexer = Token::lexer(source);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next() {
+            let span = lexer.span();
+            match token {
+                Ok(Token::Invalid(_))|Err(_)=>bail!("Invalid token `{}` at byte {}", lexer.slice(), span.start),
+                Ok(token)=>tokens.push((token, lexer.slice(), span.start, span.end)),
+            }
+        }
+
+        return Ok(Reader {
+            source,
+            tokens,
+            index: 0,
+        });
+    }
+
+    /// Counts the newlines between the end of the previous token and the start of the next one
+    fn newlines_before(&self)->usize {
+        let start = self.tokens[self.index].2;
+        let prev_end = match self.index {
+            0=>0,
+            i=>self.tokens[i - 1].3,
+        };
+
+        self.source[prev_end..start].matches('\n').count()
+    }
+
+    fn is_end(&self)->bool {
+        self.index >= self.tokens.len()
+    }
+
+    fn is_close(&self)->bool {
+        match self.tokens.get(self.index) {
+            Some((Token::List(End)|Token::Vector(End)|Token::Squiggle(End), ..))=>true,
+            _=>false,
+        }
+    }
+
+    /// Read items until a closing bracket or the end of the file. `top` is true if this is the
+    /// root of the file.
+    fn items(&mut self, top: bool)->Result<Vec<Item<'a>>> {
+        let mut items: Vec<Item<'a>> = Vec::new();
+
+        loop {
+            if self.is_end() {
+                if top {break}
+                bail!("Unexpected end of file. Missing a closing bracket");
+            }
+            if self.is_close() {
+                if top {
+                    bail!("Unexpected `{}` at byte {}", self.tokens[self.index].1, self.tokens[self.index].2);
+                }
+                break;
+            }
+
+            let newlines = self.newlines_before();
+            if let Token::Comment(_) = self.tokens[self.index].0 {
+                let text = self.tokens[self.index].1.trim_end();
+
+                // a comment on the same line as the previous item belongs to it
+                if newlines == 0 {
+                    if let Some(last) = items.last_mut() {
+                        if last.comment.is_none() && !last.node.is_comment() {
+                            last.comment = Some(text);
+                            self.index += 1;
+                            continue;
+                        }
+                    }
+                }
+
+                self.index += 1;
+                items.push(Item {
+                    node: Node::Comment(text),
+                    comment: None,
+                    blank_before: newlines > 1 && items.len() > 0,
+                });
+                continue;
+            }
+
+            let node = self.node()?;
+            items.push(Item {
+                node,
+                comment: None,
+                blank_before: newlines > 1 && items.len() > 0,
+            });
+        }
+
+        return Ok(items);
+    }
+
+    fn node(&mut self)->Result<Node<'a>> {
+        let (token, text, start, _) = &self.tokens[self.index];
+        let start = *start;
+        let text = *text;
+        let (open, close) = match token {
+            Token::List(Start)=>("(", ")"),
+            Token::Vector(Start)=>("[", "]"),
+            Token::Squiggle(Start)=>("{", "}"),
+            Token::Quote|Token::Splat=>{
+                self.index += 1;
+                if self.is_end() || self.is_close() {
+                    bail!("`{text}` at byte {start} has nothing after it");
+                }
+                if let Token::Comment(_) = self.tokens[self.index].0 {
+                    bail!("Comments are not allowed after `{text}` (byte {start})");
+                }
+
+                return Ok(Node::Prefixed(text, Box::new(self.node()?)));
+            },
+            _=>{
+                self.index += 1;
+                return Ok(Node::Atom(text));
+            },
+        };
+        self.index += 1;
+
+        let items = self.items(false)?;
+
+        let close_text = self.tokens[self.index].1;
+        if close_text != close {
+            bail!("Expected `{close}` to match the `{open}` at byte {start}, but found `{close_text}`");
+        }
+        self.index += 1;
+
+        return Ok(Node::Group {open, close, items});
+    }
+}
+
+
+struct Printer {
+    width: usize,
+    out: String,
+    /// Where the indent the last `newline` wrote ends
+    indent_end: usize,
+}
+impl Printer {
+    fn column(&self)->usize {
+        let line_start = self.out.rfind('\n')
+            .map(|i|i + 1)
+            .unwrap_or(0);
+
+        self.out[line_start..].chars().count()
+    }
+
+    fn newline(&mut self, indent: usize) {
+        // never leave an indent with nothing after it behind. Other trailing spaces, like the one in
+        // a `\ ` char, are part of the code.
+        if self.out.len() == self.indent_end {
+            let line_start = self.out.rfind('\n')
+                .map(|i|i + 1)
+                .unwrap_or(0);
+            self.out.truncate(line_start);
+        }
+
+        self.out.push('\n');
+        for _ in 0..indent {
+            self.out.push(' ');
+        }
+        self.indent_end = self.out.len();
+    }
+
+    fn blank_line(&mut self, indent: usize) {
+        self.newline(0);
+        self.newline(indent);
+    }
+
+    /// The node on a single line, or `None` if it has comments, multiline strings, or forms that
+    /// are always broken.
+    fn flat(node: &Node)->Option<String> {
+        if Self::always_break(node) {
+            return None;
+        }
+
+        match node {
+            Node::Atom(a)=>if a.contains('\n') {
+                None
+            } else {
+                Some(a.to_string())
+            },
+            Node::Comment(_)=>None,
+            Node::Prefixed(prefix, node)=>Some(format!("{prefix}{}", Self::flat(node)?)),
+            Node::Group{open, close, items}=>{
+                let mut out = open.to_string();
+                for (i, item) in items.iter().enumerate() {
+                    if item.comment.is_some() {return None}
+                    if i > 0 {out.push(' ')}
+                    out.push_str(&Self::flat(&item.node)?);
+                }
+                out.push_str(close);
+
+                Some(out)
+            },
+        }
+    }
+
+    /// How many items after the head stay on the first line when a list is broken
+    fn header_count(node: &Node)->usize {
+        let Node::Group{items, ..} = node else {return 0};
+        let is_vector = |i: usize|match items.get(i).map(|i|&i.node) {
+            Some(Node::Group{open: "[", ..})=>true,
+            _=>false,
+        };
+        let has_captures = |i: usize|match items.get(i).map(|i|&i.node) {
+            Some(Node::Group{open: "{", ..})=>true,
+            _=>false,
+        };
+
+        match node.head() {
+            Some("def"|"def-"|"defconst"|"set"|"module"|"use"|"chain")=>1,
+            Some("defn"|"defn-"|"fn-gen")=>{
+                let mut count = 1;
+                if has_captures(count + 1) {count += 1}
+                if is_vector(count + 1) {count += 1}
+                count
+            },
+            Some("fn")=>{
+                let mut count = 0;
+                if has_captures(count + 1) {count += 1}
+                if is_vector(count + 1) {count += 1}
+                count
+            },
+            _=>0,
+        }
+    }
+
+    /// Some forms read better broken up even when they would fit
+    fn always_break(node: &Node)->bool {
+        match node.head() {
+            Some("defn"|"defn-"|"fn-gen"|"cond"|"begin"|"chain")=>true,
+            _=>false,
+        }
+    }
+
+    fn print(&mut self, node: &Node, indent: usize) {
+        if let Some(flat) = Self::flat(node) {
+            if self.column() + flat.chars().count() <= self.width {
+                self.out.push_str(&flat);
+                return;
+            }
+        }
+
+        match node {
+            Node::Atom(a)=>self.out.push_str(a),
+            Node::Comment(c)=>self.out.push_str(c),
+            Node::Prefixed(prefix, inner)=>{
+                self.out.push_str(prefix);
+                let indent = indent + prefix.len();
+                self.print(inner, indent);
+            },
+            Node::Group{open, close, items}=>{
+                let header = Self::header_count(node);
+                let inner_indent = indent + 2;
+
+                self.out.push_str(open);
+                // set after comments so the next item starts on a new line
+                let mut need_newline = false;
+                for (i, item) in items.iter().enumerate() {
+                    if i == 0 && !need_newline {
+                        // the head goes right after the bracket
+                    } else if i <= header && !need_newline && !item.node.is_comment() {
+                        self.out.push(' ');
+                    } else if item.blank_before {
+                        self.blank_line(inner_indent);
+                    } else {
+                        self.newline(inner_indent);
+                    }
+
+                    let item_indent = self.column();
+                    self.print(&item.node, item_indent.max(inner_indent));
+                    need_newline = item.node.is_comment();
+
+                    if let Some(comment) = item.comment {
+                        self.out.push(' ');
+                        self.out.push_str(comment);
+                        need_newline = true;
+                    }
+                }
+
+                if need_newline {
+                    self.newline(indent);
+                }
+                self.out.push_str(close);
+            },
+        }
+    }
+}
+
+
+/// Format a whole source file. The output always ends with exactly one newline.
+pub fn format_source(source: &str, width: usize)->Result<String> {
+    let mut reader = Reader::new(source)?;
+    let items = reader.items(true)?;
+
+    let mut printer = Printer {
+        width,
+        out: String::new(),
+        indent_end: 0,
+    };
+
+    let mut prev_comment = false;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            // comments directly above a form stay attached to it
+            if item.blank_before || !prev_comment {
+                printer.blank_line(0);
+            } else {
+                printer.newline(0);
+            }
+        }
+
+        printer.print(&item.node, 0);
+        prev_comment = item.node.is_comment();
+
+        if let Some(comment) = item.comment {
+            printer.out.push(' ');
+            printer.out.push_str(comment);
+        }
+    }
+
+    let mut out = printer.out;
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    return Ok(out);
+}
@@ -0,0 +1,239 @@
+//! Random input for the fuzz tests in `tests/fuzz.rs`. The lexer and parser get bytes and bits of
+//! the grammar glued together, and the converters get random trees shaped like what the parser
+//! makes. New syntax should be added to `FRAGMENTS` and `random_expr` so it gets fuzzed too.
+//!
+//! Everything comes from a seeded `Rng`, so a failure can be replayed from the seed it printed.
+
+
+use crate::ast::*;
+
+
+/// Bits of the grammar `random_source` glues together. Mostly delimiters and keywords, with the
+/// odd literal that has broken the lexer before.
+const FRAGMENTS: &[&str] = &[
+    "(", "(", "(", ")", ")", ")", "[", "]", "{", "}", "'", "...", "\"", "\\", "\\space", "#",
+    "#t", "#f", "#inf", "#-inf", "#nan", "#xff", "#b101", "#nope", ":", ":only", ":as", ";", "\n",
+    " ", " ", " ", ".", "..", ".field", "/", "//", "&", "-", "-1", "0", "1_000", "1.5e3", "1e",
+    "9223372036854775807", "9223372036854775808", "-9223372036854775809", "fn", "defn", "defn-",
+    "def", "def-", "defconst", "cond", "else", "set", "object", "module", "use", "chain", "begin",
+    "quote", "yield", "fn-gen", "deftest", "breakpoint", "None", "x", "y", "a/b", "a//b", "a/",
+    "a/0/-1", "core/list", "+", "é", "naïve-π", "名前", "😀", "\u{0}",
+];
+
+/// The names random exprs use. Few enough that defining and using the same one is common.
+const NAMES: &[&str] = &["x", "y", "f", "lst", "+", "-", "<", "else"];
+
+/// The field names random objects and paths use
+const FIELDS: &[&str] = &["a", "b", "0", "-1", "len"];
+
+
+/// A small xorshift generator. Not good for anything but making test input.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+impl Rng {
+    pub fn new(seed: u64)->Self {
+        // all zeros would stay zero forever
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    pub fn next_u64(&mut self)->u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+
+    /// A number in `0..n`
+    pub fn below(&mut self, n: usize)->usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// True one time in `n`
+    pub fn one_in(&mut self, n: usize)->bool {
+        self.below(n) == 0
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T])->&'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+
+/// `len` random bytes. Invalid UTF-8 is replaced, since the parser only takes `&str`.
+pub fn random_bytes(rng: &mut Rng, len: usize)->String {
+    let bytes = (0..len)
+        .map(|_|rng.next_u64() as u8)
+        .collect::<Vec<_>>();
+
+    return String::from_utf8_lossy(&bytes).into_owned();
+}
+
+/// `count` pieces of the grammar in a random order. Almost never valid, but close enough to real
+/// code that the parser gets deep into every form before it fails.
+pub fn random_source(rng: &mut Rng, count: usize)->String {
+    let mut out = String::new();
+    for _ in 0..count {
+        out.push_str(rng.pick(FRAGMENTS));
+        if rng.one_in(3) {
+            out.push(' ');
+        }
+    }
+
+    return out;
+}
+
+/// `count` top level exprs no deeper than `depth`. They are shaped like what the parser makes, but
+/// they don't have to make sense, so most fail to convert.
+pub fn random_exprs(rng: &mut Rng, count: usize, depth: usize)->Vec<Expr<'static>> {
+    (0..count)
+        .map(|_|random_expr(rng, depth))
+        .collect()
+}
+
+/// A random expr no deeper than `depth`
+pub fn random_expr(rng: &mut Rng, depth: usize)->Expr<'static> {
+    if depth == 0 {
+        return random_leaf(rng);
+    }
+    let depth = depth - 1;
+
+    match rng.below(20) {
+        0|1|2|3=>random_leaf(rng),
+        4=>Expr::Def {
+            name: *rng.pick(NAMES),
+            data: Box::new(random_expr(rng, depth)),
+            private: rng.one_in(4),
+            constant: rng.one_in(4),
+        },
+        5=>Expr::DefVector {
+            names: random_vector(rng),
+            data: Box::new(random_expr(rng, depth)),
+            private: rng.one_in(4),
+            constant: rng.one_in(4),
+        },
+        6=>Expr::Set {
+            name: *rng.pick(NAMES),
+            data: Box::new(random_expr(rng, depth)),
+        },
+        7=>Expr::SetPath {
+            path: random_path(rng),
+            data: Box::new(random_expr(rng, depth)),
+        },
+        8=>{
+            let name = rng.one_in(2).then(||*rng.pick(NAMES));
+            let f = random_fn(rng, name, depth);
+            match name {
+                // like `defn`
+                Some(name) if rng.one_in(2)=>Expr::Def {
+                    name,
+                    data: Box::new(Expr::Fn(f)),
+                    private: false,
+                    constant: false,
+                },
+                _=>Expr::Fn(f),
+            }
+        },
+        9=>Expr::Cond {
+            conditions: (0..rng.below(4))
+                .map(|_|(random_expr(rng, depth), random_expr(rng, depth)))
+                .collect(),
+            default: rng.one_in(2).then(||Box::new(random_expr(rng, depth))),
+        },
+        10=>Expr::Object((0..rng.below(4))
+            .map(|_|match rng.one_in(2) {
+                true=>Field::Shorthand(*rng.pick(FIELDS)),
+                false=>Field::Full(*rng.pick(FIELDS), random_expr(rng, depth)),
+            })
+            .collect()
+        ),
+        11=>Expr::Quote(Box::new(random_expr(rng, depth))),
+        12=>Expr::Splat(Box::new(random_expr(rng, depth))),
+        13=>{
+            let count = rng.below(4);
+            Expr::Begin(random_exprs(rng, count, depth))
+        },
+        14=>{
+            let name = *rng.pick(NAMES);
+            Expr::Test(random_fn(rng, Some(name), depth))
+        },
+        15=>Expr::Yield(Box::new(random_expr(rng, depth))),
+        // calls are the most common thing in real code
+        _=>{
+            let count = rng.below(5);
+            Expr::List(random_exprs(rng, count, depth))
+        },
+    }
+}
+
+fn random_leaf(rng: &mut Rng)->Expr<'static> {
+    match rng.below(14) {
+        0=>Expr::Number(match rng.below(3) {
+            0=>i64::MAX,
+            1=>i64::MIN,
+            _=>rng.below(10) as i64 - 5,
+        }),
+        1=>Expr::Float(*rng.pick(&[0.5, -0.0, f64::INFINITY, f64::NAN])),
+        2=>Expr::String(rng.pick(&["", "a", "é\n"]).to_string()),
+        3=>Expr::Char(*rng.pick(&['a', '\n', '\0'])),
+        4=>Expr::True,
+        5=>Expr::False,
+        6=>Expr::None,
+        7=>Expr::Comment("comment"),
+        8=>Expr::DotIdent(*rng.pick(FIELDS)),
+        9=>Expr::Path(random_path(rng)),
+        10=>Expr::Breakpoint,
+        _=>Expr::Ident(*rng.pick(NAMES)),
+    }
+}
+
+fn random_fn(rng: &mut Rng, name: Option<&'static str>, depth: usize)->Fn<'static> {
+    let captures = rng.one_in(4).then(||Squiggle {
+        items: (0..rng.below(3))
+            .map(|_|*rng.pick(NAMES))
+            .collect(),
+    });
+    let signature = match rng.one_in(3) {
+        true=>FnSignature::Multi((0..rng.below(4))
+            .map(|_|random_arity(rng, depth))
+            .collect()
+        ),
+        false=>{
+            let (params, body) = random_arity(rng, depth);
+            FnSignature::Single(params, body)
+        },
+    };
+
+    return Fn {
+        name,
+        captures,
+        signature,
+        generator: rng.one_in(4),
+    };
+}
+
+/// The params and body of one arity
+fn random_arity(rng: &mut Rng, depth: usize)->(Vector<'static>, Vec<Expr<'static>>) {
+    let params = random_vector(rng);
+    let count = rng.below(3);
+
+    return (params, random_exprs(rng, count, depth));
+}
+
+fn random_vector(rng: &mut Rng)->Vector<'static> {
+    Vector {
+        items: (0..rng.below(4))
+            .map(|_|*rng.pick(NAMES))
+            .collect(),
+        remainder: rng.one_in(3).then(||*rng.pick(NAMES)),
+    }
+}
+
+/// A var and at least one field, like the lexer makes
+fn random_path(rng: &mut Rng)->Vec<&'static str> {
+    let mut path = vec![*rng.pick(NAMES)];
+    for _ in 0..=rng.below(3) {
+        path.push(*rng.pick(FIELDS));
+    }
+
+    return path;
+}
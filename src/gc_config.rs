@@ -0,0 +1,91 @@
+//! When the interpreters collect garbage, and what the collectors did. Shared by both interpreters
+//! so the CLI flags and stats work the same for `run` and `run2`.
+
+
+use std::time::Duration;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GcConfig {
+    /// Do a major collection once this many allocations happened since the last one, not counting
+    /// what minor collections freed. Zero turns this trigger off. Only used by V1; V2's collector
+    /// does a little work on every allocation.
+    pub trigger_allocations: usize,
+    /// Collect once roughly this many bytes were allocated since the last collection. Zero turns
+    /// this trigger off. Only used by V1.
+    pub trigger_bytes: usize,
+    /// Mark at most this many objects between two instructions, so a collection is spread out
+    /// instead of pausing for the whole heap. Zero collects everything at once. Only used by V1.
+    pub slice_size: usize,
+    /// Collect just the nursery after this many allocations. Zero turns off minor collections, so
+    /// only the triggers above collect. Only used by V1.
+    pub nursery_size: usize,
+    /// How many minor collections data has to survive before it is moved out of the nursery
+    pub promote_after: u8,
+    /// Never collect while running. Everything is still freed when the interpreter is dropped.
+    pub disabled: bool,
+    /// Do a major collection before every allocation, and poison freed data instead of giving it
+    /// back to the allocator. Data that isn't rooted is freed right away, so using it panics
+    /// instead of crashing at random. Very slow. Only used by V1.
+    pub stress: bool,
+}
+impl Default for GcConfig {
+    fn default()->Self {
+        GcConfig {
+            trigger_allocations: 100_000,
+            trigger_bytes: 64 * 1024 * 1024,
+            slice_size: 1000,
+            nursery_size: 10_000,
+            promote_after: 2,
+            disabled: false,
+            stress: false,
+        }
+    }
+}
+
+/// What a collector did. The collectors count these as they go, and the interpreters merge them
+/// into their metrics at the end of each run.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct GcStats {
+    /// How many objects were put on the heap. Values that aren't boxed don't count.
+    pub allocations: u64,
+    /// How many times only the nursery was collected
+    pub minor_collections: u64,
+    /// How many full collections finished, not counting the cleanup when dropping
+    pub major_collections: u64,
+    /// Total time spent in minor collections
+    pub minor_time: Duration,
+    /// Total time spent in major collections, including every slice of incremental ones
+    pub major_time: Duration,
+    /// The longest the program was stopped for a collection, or one slice of an incremental one
+    pub max_pause: Duration,
+    pub freed_objects: u64,
+    /// Roughly how many bytes the freed objects took up
+    pub freed_bytes: u64,
+    /// The most objects the heap held at once, including garbage that wasn't collected yet
+    pub peak_live: usize,
+}
+impl GcStats {
+    /// Add the counts from `other` to ours and keep the highest maximums
+    pub fn merge(&mut self, other: &GcStats) {
+        self.allocations += other.allocations;
+        self.minor_collections += other.minor_collections;
+        self.major_collections += other.major_collections;
+        self.minor_time += other.minor_time;
+        self.major_time += other.major_time;
+        self.max_pause = self.max_pause.max(other.max_pause);
+        self.freed_objects += other.freed_objects;
+        self.freed_bytes += other.freed_bytes;
+        self.peak_live = self.peak_live.max(other.peak_live);
+    }
+
+    /// Count one pause of a minor or major collection
+    pub fn record_pause(&mut self, major: bool, pause: Duration) {
+        if major {
+            self.major_time += pause;
+        } else {
+            self.minor_time += pause;
+        }
+        self.max_pause = self.max_pause.max(pause);
+    }
+}
@@ -0,0 +1,26 @@
+//! Host objects are Rust data handed to scripts as an opaque value, like a database connection or
+//! a game entity. Scripts can pass them around, read whatever fields the object exposes, and give
+//! them to the native functions the host registered. `core/typeOf` says they are `native`.
+
+
+use std::{
+    any::Any,
+    fmt::Debug,
+};
+use crate::engine::{
+    Ctx,
+    Value,
+};
+
+
+/// Something the host can hand to scripts with `Value::from_host`. Two are only equal if they are
+/// the same object.
+pub trait HostObject: Any + Debug {
+    /// What it is called when it is printed, like `counter`
+    fn type_name(&self)->&'static str;
+
+    /// The field `name`, for paths like `counter/count`. There are no fields by default.
+    fn field(&self, _name: &str, _ctx: &mut Ctx)->Option<Value> {
+        None
+    }
+}
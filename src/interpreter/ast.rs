@@ -17,16 +17,12 @@ use std::{
         Hasher,
         Hash,
     },
-    fmt::{
-        Display,
-        Formatter,
-        Result as FmtResult,
-    },
-    error::Error as ErrorTrait,
     result::Result as StdResult,
     collections::VecDeque,
-    fs::read_to_string,
-    path::PathBuf,
+    path::{
+        Path,
+        PathBuf,
+    },
     rc::Rc,
 };
 use crate::{
@@ -37,7 +33,16 @@ use crate::{
         Vector as RefVector,
         Fn as RefFn,
     },
-    error_trace,
+    error::{
+        Import,
+        LispError,
+        Signature,
+    },
+    source::{
+        SearchPath,
+        find_module,
+        read_source,
+    },
 };
 
 
@@ -56,6 +61,11 @@ pub enum Instruction {
 
     /// Reads the previous result
     Define(Ident),
+    /// Same as `Define`, but the var can't be set or defined again in the same env
+    DefineConst(Ident),
+    /// Reads the previous result, which has to be a list. Defines each name to an item of it, and
+    /// the remainder to a new list of the rest. The `bool` makes them constants.
+    DefineVector(Vector, bool),
     /// Reads the previous result
     Set(Ident),
 
@@ -67,6 +77,10 @@ pub enum Instruction {
     Object(Vec<Ident>),
 
     Path(Vec<Ident>),
+    /// Reads the previous result
+    Field(Ident),
+    /// Reads the previous result and puts it at the end of the path, which starts with a var
+    SetPath(Vec<Ident>),
 
     Number(i64),
     Float(f64),
@@ -93,10 +107,96 @@ pub enum Instruction {
     JumpIfFalse(InstructionId),
     Jump(InstructionId),
 
+    /// Pauses and opens a REPL in the current scope. Pushes `None`.
+    Breakpoint,
+
     None,
+
+    /// The first instruction of a generator's body. Moves the frame into a new generator and
+    /// returns that instead of running the body.
+    MakeGenerator,
+    /// Reads the previous result. Suspends the running generator and hands the result to whoever
+    /// resumed it.
+    Yield,
 }
 
-#[derive(Debug, PartialEq)]
+impl Instruction {
+    /// The name of each opcode, indexed by `opcode`
+    pub const OPCODE_NAMES: &'static [&'static str] = &[
+        "Nop", "Exit", "ReturnModule", "Module", "Define", "Set", "FnOrClosure", "Var", "DotIdent",
+        "Object", "Path", "Field", "Number", "Float", "String", "Char", "True", "False", "Splat",
+        "Call", "TailCall", "Return", "StartReturnScope", "StartScope", "EndScope", "JumpIfTrue",
+        "JumpIfFalse", "Jump", "Breakpoint", "None", "DefineConst", "MakeGenerator", "Yield",
+        "SetPath", "DefineVector",
+    ];
+
+    /// A number for each kind of instruction, for counting them
+    pub fn opcode(&self)->usize {
+        match self {
+            Self::Nop=>0,
+            Self::Exit=>1,
+            Self::ReturnModule=>2,
+            Self::Module(..)=>3,
+            Self::Define(..)=>4,
+            Self::Set(..)=>5,
+            Self::FnOrClosure(..)=>6,
+            Self::Var(..)=>7,
+            Self::DotIdent(..)=>8,
+            Self::Object(..)=>9,
+            Self::Path(..)=>10,
+            Self::Field(..)=>11,
+            Self::Number(..)=>12,
+            Self::Float(..)=>13,
+            Self::String(..)=>14,
+            Self::Char(..)=>15,
+            Self::True=>16,
+            Self::False=>17,
+            Self::Splat=>18,
+            Self::Call=>19,
+            Self::TailCall=>20,
+            Self::Return=>21,
+            Self::StartReturnScope=>22,
+            Self::StartScope=>23,
+            Self::EndScope=>24,
+            Self::JumpIfTrue(..)=>25,
+            Self::JumpIfFalse(..)=>26,
+            Self::Jump(..)=>27,
+            Self::Breakpoint=>28,
+            Self::None=>29,
+            Self::DefineConst(..)=>30,
+            Self::MakeGenerator=>31,
+            Self::Yield=>32,
+            Self::SetPath(..)=>33,
+            Self::DefineVector(..)=>34,
+        }
+    }
+
+    /// A human readable version of the instruction with the idents resolved to their names.
+    pub fn describe(&self, interner: &Interner)->String {
+        let join = |idents: &[Ident], sep: &str|idents.iter()
+            .map(|i|interner.get(*i))
+            .collect::<Vec<_>>()
+            .join(sep);
+
+        match self {
+            Self::Define(name)=>format!("Define({})", interner.get(*name)),
+            Self::DefineConst(name)=>format!("DefineConst({})", interner.get(*name)),
+            Self::DefineVector(names, false)=>format!("DefineVector({})", names.describe(interner)),
+            Self::DefineVector(names, true)=>format!("DefineVector(const {})", names.describe(interner)),
+            Self::Set(name)=>format!("Set({})", interner.get(*name)),
+            Self::Var(name)=>format!("Var({})", interner.get(*name)),
+            Self::DotIdent(name)=>format!("DotIdent(.{})", interner.get(*name)),
+            Self::Object(fields)=>format!("Object({})", join(fields, " ")),
+            Self::Path(path)=>format!("Path({})", join(path, "/")),
+            Self::SetPath(path)=>format!("SetPath({})", join(path, "/")),
+            Self::Field(name)=>format!("Field(.{})", interner.get(*name)),
+            Self::String(s)=>format!("String({s:?})"),
+            ins=>format!("{ins:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FnSignature {
     Single {
         params: Vector,
@@ -110,6 +210,9 @@ pub enum FnSignature {
     },
 }
 impl FnSignature {
+    /// The body to run for `count` args. A multi-arity fn picks the branch that takes exactly
+    /// `count`, then the `&` branch with the largest minimum that `count` reaches, then `[& rest]`.
+    /// The order the branches are written in doesn't matter.
     pub fn match_arg_count(&self, count: usize)->Option<(&Vector, InstructionId)> {
         match self {
             Self::Single{params, body_ptr}=>{
@@ -131,10 +234,11 @@ impl FnSignature {
                     }
                 }
 
-                for (min_param_count, (params, body_ptr)) in at_least.iter() {
-                    if count >= *min_param_count {
-                        return Some((params, *body_ptr));
-                    }
+                let closest = at_least.iter()
+                    .filter(|(min_param_count, _)|count >= **min_param_count)
+                    .max_by_key(|(min_param_count, _)|**min_param_count);
+                if let Some((_, (params, body_ptr))) = closest {
+                    return Some((params, *body_ptr));
                 }
 
                 if let Some((params, body_ptr)) = any {
@@ -145,18 +249,21 @@ impl FnSignature {
             },
         }
     }
-}
-
 
-#[derive(Debug)]
-pub struct ModuleError;
-impl ErrorTrait for ModuleError {}
-impl Display for ModuleError {
-    fn fmt(&self, f: &mut Formatter)->FmtResult {
-        write!(f, "Module error")
+    /// Every way this can be called, for arity errors
+    pub fn signatures(&self, interner: &Interner)->Vec<Signature> {
+        match self {
+            Self::Single{params,..}=>vec![params.signature(interner)],
+            Self::Multi{exact, at_least, any,..}=>exact.values()
+                .chain(at_least.values())
+                .chain(any)
+                .map(|(params, _)|params.signature(interner))
+                .collect(),
+        }
     }
 }
 
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct FnId(usize);
 impl Hash for FnId {
@@ -190,18 +297,45 @@ impl InstructionId {
     pub const fn inner(&self)->usize {self.0}
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Vector {
     pub items: Vec<Ident>,
     pub remainder: Option<Ident>,
 }
+impl Vector {
+    /// Formats the vector like it is written in the source: `[a b & rest]`
+    pub fn describe(&self, interner: &Interner)->String {
+        let mut out = self.items.iter()
+            .map(|i|interner.get(*i))
+            .collect::<Vec<_>>();
+        if let Some(rem) = self.remainder {
+            out.push("&");
+            out.push(interner.get(rem));
+        }
+
+        return format!("[{}]", out.join(" "));
+    }
+
+    pub fn signature(&self, interner: &Interner)->Signature {
+        Signature {
+            count: self.items.len(),
+            variadic: self.remainder.is_some(),
+            params: Some(self.describe(interner)),
+        }
+    }
+}
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Fn {
     pub id: FnId,
     pub name: Option<Ident>,
     pub captures: Vec<Ident>,
+    /// Captures that are `set` in this function or the ones around it. They are moved into a cell
+    /// when the closure is made, so the closures and the frame that made them share one var.
+    pub boxed: Vec<Ident>,
     pub sig: FnSignature,
+    /// From `fn-gen`. Each body starts with a `MakeGenerator`.
+    pub generator: bool,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -212,7 +346,7 @@ impl Hash for Ident {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Interner(IndexSet<String>);
 impl Interner {
     pub fn new()->Self {
@@ -227,8 +361,48 @@ impl Interner {
         self.0.get_index(i.0)
             .expect("Invalid interned ident passed")
     }
+
+    /// Like `get`, but `None` for an ident that didn't come from this interner
+    pub fn get_opt(&self, i: Ident)->Option<&str> {
+        self.0.get_index(i.0).map(String::as_str)
+    }
+
+    /// The ident for `s` if it was already interned. Doesn't intern it if it wasn't.
+    pub fn lookup(&self, s: &str)->Option<Ident> {
+        self.0.get_index_of(s).map(Ident)
+    }
+
+    #[inline]
+    pub fn len(&self)->usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self)->bool {
+        self.0.is_empty()
+    }
+
+    /// Every interned string with its ident, in the order they were interned
+    pub fn iter(&self)->impl Iterator<Item = (Ident, &str)> {
+        self.0.iter()
+            .enumerate()
+            .map(|(i, s)|(Ident(i), s.as_str()))
+    }
+
+    /// The names interned after the first `count`, in order
+    pub fn names_after(&self, count: usize)->impl Iterator<Item = &str> {
+        self.0.iter()
+            .skip(count)
+            .map(String::as_str)
+    }
+
+    /// Forget everything interned after the first `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
 }
 
+#[derive(Clone)]
 pub struct InstructionStore {
     /// Immutable list of instructions. Nothing gets deleted from here.
     instructions: Vec<Instruction>,
@@ -306,6 +480,12 @@ impl InstructionStore {
             index: 0,
         }
     }
+
+    /// Remove every instruction from `len` on, from the store and from the execution order
+    pub fn truncate(&mut self, len: usize) {
+        self.instructions.truncate(len);
+        self.ins_order.retain(|id|id.0 < len);
+    }
 }
 
 pub struct InstructionIter<'a> {
@@ -368,6 +548,19 @@ pub struct ConvertState {
     pub warnings: Vec<Error>,
     pub instructions: InstructionStore,
     pub modules: ModuleTree,
+    pub search_path: SearchPath,
+    /// The canonical path of every module file that was loaded
+    pub module_cache: IndexMap<PathBuf, ModuleId, FxBuildHasher>,
+    /// `use ... :only` forms to check once their modules are converted
+    pub pending_imports: Vec<PendingImport>,
+    /// Where the prelude starts. `Interpreter::new` runs it before anything else.
+    pub prelude: Option<InstructionId>,
+    /// Where functions called by `Interpreter::call` return to
+    call_exit: Option<InstructionId>,
+    /// The fns and modules reserved since the last `checkpoint`
+    reserved: Option<Reserved>,
+    /// Every `deftest` in the order they were defined
+    pub tests: Vec<Test>,
 }
 #[allow(dead_code)]
 impl ConvertState {
@@ -378,6 +571,13 @@ impl ConvertState {
             warnings: Vec::new(),
             instructions: InstructionStore::new(),
             modules: ModuleTree::new(),
+            search_path: SearchPath::default(),
+            module_cache: IndexMap::default(),
+            pending_imports: Vec::new(),
+            prelude: None,
+            call_exit: None,
+            reserved: None,
+            tests: Vec::new(),
         }
     }
     #[inline]
@@ -385,6 +585,44 @@ impl ConvertState {
         self.interner.intern(s)
     }
 
+    /// Copy everything needed to run this program's code on another thread. Only the functions
+    /// and modules the instructions can make are copied.
+    pub fn copy_program(&self)->ProgramCopy {
+        let mut fn_ids = Vec::new();
+        let mut module_ids = Vec::new();
+        for ins in self.instructions.instructions.iter() {
+            match ins {
+                Instruction::FnOrClosure(id)=>fn_ids.push(*id),
+                Instruction::Module(id)=>{
+                    module_ids.push(self.modules.aliases.get(id).copied().unwrap_or(*id));
+                },
+                _=>{},
+            }
+        }
+        fn_ids.sort_by_key(FnId::id);
+        fn_ids.dedup();
+        module_ids.sort_by_key(ModuleId::id);
+        module_ids.dedup();
+
+        let fns = fn_ids.into_iter()
+            .filter_map(|id|self.fns.get(id))
+            .map(|func|(**func).clone())
+            .collect();
+        let modules = module_ids.into_iter()
+            .filter_map(|id|Some((id, self.modules.tree.get(id)?.clone())))
+            .collect();
+
+        return ProgramCopy {
+            interner: self.interner.clone(),
+            fns,
+            instructions: self.instructions.clone(),
+            modules,
+            module_aliases: self.modules.aliases.clone(),
+            search_path: self.search_path.clone(),
+            prelude: self.prelude,
+        };
+    }
+
     #[inline]
     pub fn warning(&mut self, err: Error) {
         self.warnings.push(err);
@@ -416,6 +654,18 @@ impl ConvertState {
         self.instructions.push(Instruction::Define(ident));
     }
 
+    pub fn define_const(&mut self, i: &str) {
+        let ident = self.intern(i);
+
+        self.instructions.push(Instruction::DefineConst(ident));
+    }
+
+    pub fn define_vector(&mut self, names: RefVector, constant: bool) {
+        let names = convert_vector(self, names);
+
+        self.instructions.push(Instruction::DefineVector(names, constant));
+    }
+
     pub fn set_var(&mut self, i: &str) {
         let ident = self.intern(i);
 
@@ -434,6 +684,12 @@ impl ConvertState {
         self.instructions.push(Instruction::DotIdent(ident));
     }
 
+    pub fn field(&mut self, i: &str) {
+        let ident = self.intern(i);
+
+        self.instructions.push(Instruction::Field(ident));
+    }
+
     #[inline]
     pub fn var(&mut self, i: Ident) {
         self.instructions.push(Instruction::Var(i));
@@ -504,11 +760,38 @@ impl ConvertState {
         self.instructions.push(Instruction::Exit);
     }
 
+    /// An `Exit` that nothing runs into, made the first time `Interpreter::call` needs it
+    pub fn call_exit(&mut self)->InstructionId {
+        if let Some(id) = self.call_exit {
+            return id;
+        }
+
+        let id = self.instructions.push(Instruction::Exit);
+        self.call_exit = Some(id);
+
+        return id;
+    }
+
     #[inline]
     pub fn push_none(&mut self) {
         self.instructions.push(Instruction::None);
     }
 
+    #[inline]
+    pub fn breakpoint(&mut self) {
+        self.instructions.push(Instruction::Breakpoint);
+    }
+
+    #[inline]
+    pub fn make_generator(&mut self) {
+        self.instructions.push(Instruction::MakeGenerator);
+    }
+
+    #[inline]
+    pub fn push_yield(&mut self) {
+        self.instructions.push(Instruction::Yield);
+    }
+
     #[inline]
     pub fn char(&mut self, c: char) {
         self.instructions.push(Instruction::Char(c));
@@ -531,17 +814,102 @@ impl ConvertState {
         self.instructions.push(Instruction::Path(path));
     }
 
+    pub fn set_path(&mut self, path: Vec<&str>) {
+        let path = path.into_iter()
+            .map(|s|self.intern(s))
+            .collect();
+        self.instructions.push(Instruction::SetPath(path));
+    }
+
     #[inline]
     pub fn reserve_func(&mut self)->FnId {
-        self.fns.reserve_slot()
+        let f = self.fns.reserve_slot();
+        if let Some(reserved) = &mut self.reserved {
+            reserved.fns.push(f);
+        }
+
+        return f;
     }
 
     pub fn reserve_module(&mut self)->ModuleId {
         let m = self.modules.reserve_slot();
+        if let Some(reserved) = &mut self.reserved {
+            reserved.modules.push(m);
+        }
 
         return m;
     }
 
+    /// Remember how much has been converted, so `rollback` can undo a conversion that fails
+    /// partway through
+    pub fn checkpoint(&mut self)->Checkpoint {
+        self.reserved = Some(Reserved::default());
+
+        Checkpoint {
+            instructions: self.instructions.instructions.len(),
+            interner: self.interner.len(),
+            module_cache: self.module_cache.len(),
+            pending_imports: self.pending_imports.len(),
+            call_exit: self.call_exit,
+            tests: self.tests.len(),
+        }
+    }
+
+    /// The function `id`. Errors instead of panicking if it was reserved and never converted.
+    pub fn get_fn(&self, id: FnId)->Result<&Rc<Fn>> {
+        match self.fns.get(id) {
+            Some(f)=>Ok(f),
+            None=>bail!(LispError::Unresolved{kind: "function", id: id.id()}),
+        }
+    }
+
+    /// The module `id`. Errors instead of panicking if it was reserved and never converted.
+    pub fn get_module(&self, id: ModuleId)->Result<&ModuleNode> {
+        match self.modules.try_get(id) {
+            Some(module)=>Ok(module),
+            None=>bail!(LispError::Unresolved{kind: "module", id: id.id()}),
+        }
+    }
+
+    /// Errors if a fn or module reserved since `checkpoint` was never filled in. The code
+    /// converted so far could run into it.
+    pub fn check_reserved(&self)->Result<()> {
+        let Some(reserved) = &self.reserved else {return Ok(())};
+        for id in reserved.fns.iter() {
+            self.get_fn(*id)?;
+        }
+        for id in reserved.modules.iter() {
+            self.get_module(*id)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Keep everything converted since `checkpoint`
+    pub fn commit(&mut self, _checkpoint: Checkpoint) {
+        self.reserved = None;
+    }
+
+    /// Undo everything converted since `checkpoint`: the instructions, the names interned, and
+    /// the fns and modules reserved. Nothing can run into half-converted code after this.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.instructions.truncate(checkpoint.instructions);
+        self.interner.truncate(checkpoint.interner);
+        self.module_cache.truncate(checkpoint.module_cache);
+        self.pending_imports.truncate(checkpoint.pending_imports);
+        self.call_exit = checkpoint.call_exit;
+        self.tests.truncate(checkpoint.tests);
+
+        if let Some(reserved) = self.reserved.take() {
+            for id in reserved.fns {
+                self.fns.remove(id);
+            }
+            for id in reserved.modules {
+                self.modules.remove(id);
+            }
+        }
+    }
+
     #[inline]
     pub fn module(&mut self, id: ModuleId) {
         self.instructions.push(Instruction::Module(id));
@@ -558,7 +926,7 @@ impl ConvertState {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct ModuleNode {
     pub name: Ident,
@@ -567,18 +935,30 @@ pub struct ModuleNode {
     pub parent: Option<ModuleId>,
 
     pub start_ins: InstructionId,
+    /// The globals it defines, which are the fields of its value
+    pub exports: Vec<Ident>,
+    /// The `def-` globals. Only the module itself can use them.
+    pub privates: Vec<Ident>,
 }
 
 pub struct ModuleTree {
     tree: SlotMap<ModuleId, ModuleNode>,
+    /// Modules that were declared again somewhere else. They share the first one's node.
+    aliases: IndexMap<ModuleId, ModuleId, FxBuildHasher>,
 }
 impl ModuleTree {
     pub fn new()->Self {
         ModuleTree {
             tree: SlotMap::new(),
+            aliases: Default::default(),
         }
     }
 
+    /// Make `id` share `to`'s node
+    pub fn alias(&mut self, id: ModuleId, to: ModuleId) {
+        self.aliases.insert(id, to);
+    }
+
     #[inline]
     pub fn reserve_slot(&mut self)->ModuleId {
         self.tree.reserve_slot()
@@ -590,19 +970,127 @@ impl ModuleTree {
     }
 
     pub fn get(&self, id: ModuleId)->&ModuleNode {
-        self.tree.get(id).unwrap()
+        self.try_get(id).unwrap()
+    }
+
+    /// `None` if `id` was reserved and never filled in
+    pub fn try_get(&self, id: ModuleId)->Option<&ModuleNode> {
+        let id = self.aliases.get(&id).copied().unwrap_or(id);
+        self.tree.get(id)
+    }
+
+    /// Free `id` and forget any alias to or from it
+    pub fn remove(&mut self, id: ModuleId) {
+        self.tree.remove(id);
+        self.aliases.retain(|from, to|*from != id && *to != id);
     }
 }
 
+/// A copy of a program that can be sent to another thread. `ConvertState` keeps its functions in
+/// `Rc`s, so it can't be sent itself. Only what running the code needs is copied.
+pub struct ProgramCopy {
+    interner: Interner,
+    fns: Vec<Fn>,
+    instructions: InstructionStore,
+    modules: Vec<(ModuleId, ModuleNode)>,
+    module_aliases: IndexMap<ModuleId, ModuleId, FxBuildHasher>,
+    search_path: SearchPath,
+    prelude: Option<InstructionId>,
+}
+impl ProgramCopy {
+    /// A `ConvertState` that runs the copied code. Functions and modules keep their ids, so data
+    /// that refers to them means the same thing in both.
+    pub fn into_state(self)->ConvertState {
+        let mut state = ConvertState::new();
+        state.interner = self.interner;
+        state.instructions = self.instructions;
+        state.search_path = self.search_path;
+        state.prelude = self.prelude;
+
+        for func in self.fns {
+            let mut slot: FnId = state.fns.reserve_slot();
+            while slot.id() < func.id.id() {
+                slot = state.fns.reserve_slot();
+            }
+            state.fns.insert_reserved(slot, Rc::new(func)).unwrap();
+        }
+
+        for (id, node) in self.modules {
+            let mut slot = state.modules.reserve_slot();
+            while slot.id() < id.id() {
+                slot = state.modules.reserve_slot();
+            }
+            state.modules.insert_reserved(slot, node).unwrap();
+        }
+        state.modules.aliases = self.module_aliases;
+
+        return state;
+    }
+}
+
+/// How far conversion had gotten when `ConvertState::checkpoint` was called
+pub struct Checkpoint {
+    instructions: usize,
+    interner: usize,
+    module_cache: usize,
+    pending_imports: usize,
+    call_exit: Option<InstructionId>,
+    tests: usize,
+}
+
+/// The fns and modules reserved since a checkpoint, to free if it is rolled back
+#[derive(Default)]
+struct Reserved {
+    fns: Vec<FnId>,
+    modules: Vec<ModuleId>,
+}
+
+/// A `deftest`. Its function doesn't take any arguments.
+#[derive(Debug, Copy, Clone)]
+pub struct Test {
+    pub name: Ident,
+    pub id: FnId,
+}
+
+/// The names a `use` form takes from a module
+pub struct PendingImport {
+    pub module: ModuleId,
+    pub names: Vec<Ident>,
+    /// How the root got to the module with the `use`
+    pub imports: Vec<Import>,
+    pub file: Option<PathBuf>,
+}
+
 struct TodoModule {
     name: String,
     id: ModuleId,
     parent: ModuleId,
     path: PathBuf,
+    /// How the root got to this module, ending with this one
+    imports: Vec<Import>,
+}
+impl TodoModule {
+    /// Wrap an error from loading this module so `error_trace` can show it with the module's
+    /// source
+    fn error(&self, path: Option<PathBuf>, source: String, error: Error)->LispError {
+        LispError::Module {
+            imports: self.imports.clone(),
+            path,
+            source,
+            error,
+        }
+    }
 }
 
 struct Todos<'a, 'b> {
-    pub fns: VecDeque<(FnId, RefFn<'a>)>,
+    /// Each function with the names `set` in the functions around it
+    pub fns: VecDeque<(FnId, RefFn<'a>, Rc<[&'a str]>)>,
+    /// The names `set` anywhere in the function being converted, including the ones around it
+    pub assigned: Rc<[&'a str]>,
+    /// Whether the function being converted is a generator, so `yield` is allowed
+    pub in_generator: bool,
+    /// Whether a function is being converted. `deftest` is only allowed outside of them.
+    pub in_fn: bool,
     pub modules: &'b mut VecDeque<TodoModule>,
 
     /// Helper to temporarily store the children of the current module
@@ -611,20 +1099,29 @@ struct Todos<'a, 'b> {
     pub current_module: ModuleId,
 
     pub module_path: PathBuf,
+    /// The file being converted. `None` in the REPL.
+    pub file: Option<PathBuf>,
+    /// How the root got to the current module
+    pub imports: Vec<Import>,
 }
 impl<'a, 'b> Todos<'a, 'b> {
     fn new(modules: &'b mut VecDeque<TodoModule>)->Self {
         Todos {
             fns: VecDeque::new(),
+            assigned: Rc::new([]),
+            in_generator: false,
+            in_fn: false,
             modules,
             new_modules: Vec::new(),
             current_module: ModuleId::root(),
             module_path: PathBuf::new(),
+            file: None,
+            imports: Vec::new(),
         }
     }
 
     fn queue_fn(&mut self, id: FnId, f: RefFn<'a>) {
-        self.fns.push_back((id, f));
+        self.fns.push_back((id, f, self.assigned.clone()));
     }
 
     fn queue_module(&mut self, id: ModuleId, name: &str) {
@@ -635,25 +1132,42 @@ impl<'a, 'b> Todos<'a, 'b> {
             id,
             parent: self.current_module,
             path: self.module_path.clone(),
+            imports: self.imports.iter()
+                .cloned()
+                .chain([Import {
+                    name: name.to_string(),
+                    importer: self.file.clone(),
+                }])
+                .collect(),
         });
     }
 }
 
 
-pub fn convert<'a>(exprs: Vec<RefExpr<'a>>)->Result<ConvertState> {
+/// Convert the root module. `file` is where `exprs` came from, and its modules are looked for next
+/// to it and then in `search_path`. With `prelude` the prelude is converted too.
+pub fn convert<'a>(exprs: Vec<RefExpr<'a>>, file: &Path, search_path: SearchPath, prelude: bool)->Result<ConvertState> {
     let mut state = ConvertState::new();
+    state.search_path = search_path;
+    let checkpoint = state.checkpoint();
     let mut module_todos = VecDeque::new();
     let mut todos = Todos::new(&mut module_todos);
     let root_module = state.reserve_module();
     todos.current_module = root_module;
+    todos.file = Some(file.to_path_buf());
+    todos.module_path = file.parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
 
+    let globals = module_globals(&exprs);
     let start_ins = state.next_ins_id();
     convert_exprs(&mut state, &mut todos, exprs, false)?;
+    let (exports, privates) = globals.intern(&mut state);
 
     state.push_exit();
     
-    while let Some((id, f)) = todos.fns.pop_back() {
-        convert_fn(&mut state, &mut todos, f, id)?;
+    while let Some((id, f, assigned)) = todos.fns.pop_back() {
+        convert_fn(&mut state, &mut todos, f, id, assigned)?;
     }
 
     let root_children = todos.new_modules;
@@ -663,30 +1177,73 @@ pub fn convert<'a>(exprs: Vec<RefExpr<'a>>)->Result<ConvertState> {
         children: root_children,
         parent: None,
         start_ins,
+        exports,
+        privates,
     }).unwrap();
 
     while let Some(todo) = module_todos.pop_back() {
         convert_module(&mut state, &mut module_todos, todo)?;
     }
+    check_imports(&mut state)?;
+    state.check_reserved()?;
+    state.commit(checkpoint);
+
+    // last, so the program's idents are interned in the same order either way
+    if prelude {
+        convert_prelude(&mut state);
+    }
 
     return Ok(state);
 }
 
+/// Convert the prelude into `state` so the interpreter runs it first. It ships with the binary, so
+/// any error is our bug.
+pub fn convert_prelude(state: &mut ConvertState) {
+    match repl_convert(state, crate::prelude::exprs()) {
+        Ok(start)=>state.prelude = Some(start),
+        Err(e)=>panic!("The prelude failed to convert: {e}"),
+    }
+}
+
 pub fn repl_convert<'a>(state: &mut ConvertState, exprs: Vec<RefExpr<'a>>)->Result<InstructionId> {
+    repl_convert_with_path(state, exprs, PathBuf::new())
+}
+
+/// Same as `repl_convert`, but any modules are resolved relative to `module_path` instead of the
+/// current directory. `:load` uses this so a file can find its modules next to itself.
+pub fn repl_convert_with_path<'a>(state: &mut ConvertState, exprs: Vec<RefExpr<'a>>, module_path: PathBuf)->Result<InstructionId> {
+    // a failed entry leaves nothing behind for the next one to run into
+    let checkpoint = state.checkpoint();
+    match repl_convert_inner(state, exprs, module_path) {
+        Ok(start_id)=>{
+            state.commit(checkpoint);
+            return Ok(start_id);
+        },
+        Err(e)=>{
+            state.rollback(checkpoint);
+            return Err(e);
+        },
+    }
+}
+
+fn repl_convert_inner<'a>(state: &mut ConvertState, exprs: Vec<RefExpr<'a>>, module_path: PathBuf)->Result<InstructionId> {
     let start_id = state.next_ins_id();
     let mut module_todos = VecDeque::new();
     let mut todos = Todos::new(&mut module_todos);
+    todos.module_path = module_path;
     convert_exprs(state, &mut todos, exprs, false)?;
 
     state.push_exit();
     
-    while let Some((id, f)) = todos.fns.pop_front() {
-        convert_fn(state, &mut todos, f, id)?;
+    while let Some((id, f, assigned)) = todos.fns.pop_front() {
+        convert_fn(state, &mut todos, f, id, assigned)?;
     }
 
     while let Some(todo) = module_todos.pop_back() {
         convert_module(state, &mut module_todos, todo)?;
     }
+    check_imports(state)?;
+    state.check_reserved()?;
 
     return Ok(start_id);
 }
@@ -696,43 +1253,58 @@ fn convert_module<'a>(state: &mut ConvertState, module_todos: &'a mut VecDeque<T
 
     let name = state.intern(&module_todo.name);
 
-    let mut path = module_todo.path;
-    path.push(&module_todo.name);
+    let found = match find_module(&module_todo.path, &module_todo.name, &state.search_path) {
+        Ok(f)=>f,
+        Err(e)=>bail!(module_todo.error(None, String::new(), e.into())),
+    };
 
-    todos.module_path = path.clone();
-    todos.current_module = module_todo.id;
+    // reuse the module if something else already loaded the same file. If the file can't be
+    // canonicalized then reading it fails below anyway.
+    let canonical = found.path.canonicalize().unwrap_or_else(|_|found.path.clone());
+    if let Some(existing) = state.module_cache.get(&canonical) {
+        state.modules.alias(module_todo.id, *existing);
+        return Ok(());
+    }
+    state.module_cache.insert(canonical, module_todo.id);
+    if found.shadowed.len() > 0 {
+        state.warning(LispError::AmbiguousModule {
+            name: module_todo.name.clone(),
+            used: found.path.clone(),
+            ignored: found.shadowed,
+        }.into());
+    }
+    let path = found.path;
+    let source = match read_source(&path) {
+        Ok(s)=>s,
+        Err(e)=>bail!(module_todo.error(Some(path), String::new(), e.into())),
+    };
 
-    let source;
-    if path.is_dir() {
-        path.push("mod.slp");
-        source = read_to_string(&path)?;
-    } else {
-        path.set_extension("slp");
-        source = read_to_string(&path)?;
-    }
+    todos.module_path = found.dir;
+    todos.current_module = module_todo.id;
+    todos.file = Some(path.clone());
+    todos.imports = module_todo.imports.clone();
 
     let mut parser = crate::parser::new_parser(&source);
     let exprs = match parser.parse_all() {
         Ok(e)=>e,
         Err(e)=>{
-            error_trace(e, &source, path.display());
-            bail!(ModuleError);
+            bail!(module_todo.error(Some(path), source.clone(), e));
         },
     };
     drop(parser);
 
+    let globals = module_globals(&exprs);
     let start_ins = state.next_ins_id();
     if let Err(e) = convert_exprs(state, &mut todos, exprs, NOT_TAIL) {
-        error_trace(e, &source, path.display());
-        bail!(ModuleError);
+        bail!(module_todo.error(Some(path), source.clone(), e));
     }
+    let (exports, privates) = globals.intern(state);
 
     state.push_module_return();
 
-    while let Some((id, f)) = todos.fns.pop_back() {
-        if let Err(e) = convert_fn(state, &mut todos, f, id) {
-            error_trace(e, &source, path.display());
-            bail!(ModuleError);
+    while let Some((id, f, assigned)) = todos.fns.pop_back() {
+        if let Err(e) = convert_fn(state, &mut todos, f, id, assigned) {
+            bail!(module_todo.error(Some(path), source.clone(), e));
         }
     }
 
@@ -743,12 +1315,112 @@ fn convert_module<'a>(state: &mut ConvertState, module_todos: &'a mut VecDeque<T
         parent: Some(module_todo.parent),
         start_ins,
         children,
+        exports,
+        privates,
     }).expect("Module already exists!");
 
     return Ok(());
 }
 
+/// The names defined at the top level of a module. Anything defined in a nested scope goes away
+/// with that scope, so it isn't a field of the module.
+#[derive(Default)]
+struct ModuleGlobals<'a> {
+    exports: IndexSet<&'a str, FxBuildHasher>,
+    privates: IndexSet<&'a str, FxBuildHasher>,
+}
+impl<'a> ModuleGlobals<'a> {
+    /// Intern the names once the module is converted, so the ident order is the same as before
+    fn intern(self, state: &mut ConvertState)->(Vec<Ident>, Vec<Ident>) {
+        let exports = self.exports.into_iter().map(|n|state.intern(n)).collect();
+        let privates = self.privates.into_iter().map(|n|state.intern(n)).collect();
+
+        return (exports, privates);
+    }
+}
+
+fn module_globals<'a>(exprs: &[RefExpr<'a>])->ModuleGlobals<'a> {
+    let mut globals = ModuleGlobals::default();
+    for expr in exprs {
+        match expr {
+            RefExpr::Def{name, private: false,..}=>{globals.exports.insert(*name);},
+            RefExpr::Def{name, private: true,..}=>{globals.privates.insert(*name);},
+            RefExpr::DefVector{names, private,..}=>{
+                let names = names.items.iter().chain(&names.remainder).copied();
+                match private {
+                    false=>globals.exports.extend(names),
+                    true=>globals.privates.extend(names),
+                }
+            },
+            RefExpr::Module(name)=>{globals.exports.insert(*name);},
+            RefExpr::Use{path, only, alias}=>{
+                let alias = use_alias(path, only, *alias);
+                globals.exports.extend(only.iter().flatten().chain(alias.as_ref()));
+            },
+            _=>{},
+        }
+    }
+
+    return globals;
+}
+
+/// The name a `use` form binds the module itself to. Without `:as` or `:only` it is the module's
+/// own name.
+fn use_alias<'a>(path: &[&'a str], only: &Option<Vec<&'a str>>, alias: Option<&'a str>)->Option<&'a str> {
+    match alias {
+        Some(alias)=>Some(alias),
+        None if only.is_none()=>path.last().copied(),
+        None=>None,
+    }
+}
+
+/// Make sure every `use ... :only` names something its module defines
+fn check_imports(state: &mut ConvertState)->Result<()> {
+    for import in std::mem::take(&mut state.pending_imports) {
+        let module = state.modules.get(import.module);
+        let module_name = state.interner.get(module.name).to_string();
+        let missing = import.names.iter()
+            .copied()
+            .filter(|n|!module.exports.contains(n))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            continue;
+        }
+
+        let error = match missing.iter().find(|n|module.privates.contains(n)) {
+            Some(name)=>LispError::PrivateField {
+                name: state.interner.get(*name).to_string(),
+                module: module_name,
+            },
+            None=>LispError::UndefinedImport {
+                module: module_name,
+                missing: missing.iter()
+                    .map(|n|state.interner.get(*n).to_string())
+                    .collect(),
+                exports: module.exports.iter()
+                    .map(|n|state.interner.get(*n).to_string())
+                    .collect(),
+            },
+        };
+        if import.imports.is_empty() {
+            bail!(error);
+        }
+
+        // the `use` is in a module, so show how we got there
+        bail!(LispError::Module {
+            imports: import.imports,
+            path: import.file,
+            source: String::new(),
+            error: error.into(),
+        });
+    }
+
+    return Ok(());
+}
+
 fn convert_exprs<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, exprs: Vec<RefExpr<'a>>, is_tail: bool)->Result<()> {
+    if exprs.len() == 0 {return Ok(())}
+
     let last = exprs.len() - 1;
     for (i, expr) in exprs.into_iter().enumerate() {
         let expr_is_tail = (i == last) && is_tail;
@@ -771,22 +1443,59 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
         RefExpr::Comment(_)=>{},
         RefExpr::Module(name)=>{
             let id = state.reserve_module();
-            state.module(id);
             todos.queue_module(id, name);
-        },
-        RefExpr::Def{name, data}=>{
-            convert_single_expr(state, todos, *data, is_tail)?;
 
+            // the module's value is an object of its globals, and it is bound to its name like
+            // `use` does. It only runs the first time, after that `Module` just pushes its value.
+            state.module(id);
             state.define(name);
         },
+        RefExpr::Use{path, only, alias}=>{
+            let id = state.reserve_module();
+            todos.queue_module(id, &path.join("/"));
+
+            // the module only runs the first time, after that `Module` just pushes its value
+            if let Some(names) = &only {
+                for name in names {
+                    state.module(id);
+                    state.field(name);
+                    state.define(name);
+                }
+                let names = names.iter().map(|n|state.intern(n)).collect();
+                state.pending_imports.push(PendingImport {
+                    module: id,
+                    names,
+                    imports: todos.imports.clone(),
+                    file: todos.file.clone(),
+                });
+            }
+            if let Some(name) = use_alias(&path, &only, alias) {
+                state.module(id);
+                state.define(name);
+            }
+        },
+        RefExpr::Def{name, data, constant,..}=>{
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
+
+            if constant {
+                state.define_const(name);
+            } else {
+                state.define(name);
+            }
+        },
+        RefExpr::DefVector{names, data, constant,..}=>{
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
+
+            state.define_vector(names, constant);
+        },
         RefExpr::Set{name, data}=>{
-            convert_single_expr(state, todos, *data, is_tail)?;
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
 
             state.set_var(name);
         },
-        // RefExpr::SetPath{path, data}=>{
-        RefExpr::SetPath{..}=>{
-            todo!();
+        RefExpr::SetPath{path, data}=>{
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
+            state.set_path(path);
         },
         RefExpr::Object(fields)=>{
             state.start_scope();
@@ -855,11 +1564,13 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
                 state.instructions.set(id, Instruction::JumpIfFalse(this_id));
             }
 
-            if let Some(default) = default {
-                convert_single_expr(state, todos, *default, is_tail)?;
-                if is_tail {
-                    state.push_return();
-                }
+            // when nothing matches, the cond is `none`
+            match default {
+                Some(default)=>convert_single_expr(state, todos, *default, is_tail)?,
+                None=>state.push_none(),
+            }
+            if is_tail {
+                state.push_return();
             }
 
             if !is_tail {
@@ -873,6 +1584,10 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
             } else {
                 assert!(jump_ends.is_empty());
             }
+
+            // every branch returns in tail position, so this only ends the scope for the
+            // converter there
+            state.end_scope();
         },
         RefExpr::Splat(expr)=>{
             convert_single_expr(state, todos, *expr, NOT_TAIL)?;
@@ -885,10 +1600,13 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
             
             state.end_scope();
         },
+        // there is nothing to call, so it is `none` like in other lisps
+        RefExpr::List(exprs) if exprs.is_empty()=>state.push_none(),
         RefExpr::List(exprs)=>{
             state.start_scope();
 
-            convert_exprs(state, todos, exprs, is_tail)?;
+            // only the call itself is in tail position, not its last argument
+            convert_exprs(state, todos, exprs, NOT_TAIL)?;
 
             if is_tail {
                 state.tail_call_or_list();
@@ -897,39 +1615,126 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
             }
         },
         RefExpr::None=>state.push_none(),
-        RefExpr::Quote(_)=>todo!("Quote conversion"),
-        RefExpr::Vector(_)=>todo!("Vector conversion"),
-        RefExpr::Squiggle(_)=>todo!("Squiggle conversion"),
+        RefExpr::Breakpoint=>state.breakpoint(),
+        RefExpr::Test(f)=>{
+            if todos.in_fn || todos.current_module != ModuleId::root() {
+                bail!("`deftest` is only allowed at the top level of a script");
+            }
+            let name = state.intern(f.name.unwrap());
+            if state.tests.iter().any(|test|test.name == name) {
+                bail!("Test `{}` is already defined", state.interner.get(name));
+            }
+
+            let id = state.reserve_func();
+            todos.queue_fn(id, f);
+            state.tests.push(Test {name, id});
+            state.push_none();
+        },
+        RefExpr::Yield(value)=>{
+            if !todos.in_generator {
+                bail!("`yield` can only be used in the body of a `fn-gen`");
+            }
+
+            convert_single_expr(state, todos, *value, NOT_TAIL)?;
+            state.push_yield();
+        },
+        RefExpr::Quote(_)=>bail!("Quoting isn't supported yet"),
+        // these only come from quoted lists
+        RefExpr::Vector(_)|RefExpr::Squiggle(_)=>bail!("Quoting isn't supported yet"),
         RefExpr::ReplDirective(_)=>bail!("Repl directives are not allowed here!"),
     })
 }
 
-fn convert_fn<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, func: RefFn<'a>, id: FnId)->Result<()> {
+fn convert_fn<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, func: RefFn<'a>, id: FnId, outer_assigned: Rc<[&'a str]>)->Result<()> {
     let name = func.name.map(|n|state.intern(n));
-    let sig = convert_signature(state, todos, func.signature)?;
+
+    let mut assigned = outer_assigned.to_vec();
+    for (_, body) in func.signature.bodies() {
+        assigned_names(body, &mut assigned);
+    }
     let captures = func.captures
-        .map(|c|c.items
-            .into_iter()
-            .map(|s|state.intern(s))
-            .collect::<Vec<_>>()
-        )
+        .map(|c|c.items)
         .unwrap_or_default();
+    let boxed = captures.iter()
+        .filter(|c|assigned.contains(c))
+        .map(|c|state.intern(c))
+        .collect();
+    let captures = captures.into_iter()
+        .map(|s|state.intern(s))
+        .collect();
+
+    todos.assigned = assigned.into();
+    todos.in_generator = func.generator;
+    todos.in_fn = true;
+    let sig = convert_signature(state, todos, func.signature);
+    todos.in_generator = false;
+    todos.in_fn = false;
+    let sig = sig?;
 
     state.fns.insert_reserved(id, Rc::new(Fn {
         id,
         name,
         captures,
+        boxed,
         sig,
+        generator: func.generator,
     })).unwrap();
     return Ok(());
 }
 
+/// Every name `set` in `exprs`, including in the functions they make
+fn assigned_names<'a>(exprs: &[RefExpr<'a>], out: &mut Vec<&'a str>) {
+    for expr in exprs {
+        match expr {
+            RefExpr::Set{name, data}=>{
+                if !out.contains(name) {
+                    out.push(name);
+                }
+                assigned_names(std::slice::from_ref(&**data), out);
+            },
+            RefExpr::Def{data,..}|
+                RefExpr::DefVector{data,..}|
+                RefExpr::SetPath{data,..}|
+                RefExpr::Splat(data)|
+                RefExpr::Yield(data)=>assigned_names(std::slice::from_ref(&**data), out),
+            RefExpr::Fn(f)|
+                RefExpr::Test(f)=>for (_, body) in f.signature.bodies() {
+                assigned_names(body, out);
+            },
+            RefExpr::Cond{conditions, default}=>{
+                for (condition, body) in conditions {
+                    assigned_names(std::slice::from_ref(condition), out);
+                    assigned_names(std::slice::from_ref(body), out);
+                }
+                if let Some(default) = default {
+                    assigned_names(std::slice::from_ref(&**default), out);
+                }
+            },
+            RefExpr::Object(fields)=>for field in fields {
+                if let RefField::Full(_, expr) = field {
+                    assigned_names(std::slice::from_ref(expr), out);
+                }
+            },
+            RefExpr::Begin(exprs)|
+                RefExpr::List(exprs)=>assigned_names(exprs, out),
+            _=>{},
+        }
+    }
+}
+
 fn convert_signature<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, sig: RefFnSignature<'a>)->Result<FnSignature> {
+    for warning in sig.check_arities()? {
+        state.warning(warning.into());
+    }
+
     match sig {
         RefFnSignature::Single(params, body)=>{
             let params = convert_vector(state, params);
 
             let body_ptr = state.next_ins_id();
+            if todos.in_generator {
+                state.make_generator();
+            }
             convert_exprs(state, todos, body, IS_TAIL)?;
             state.push_return();
 
@@ -945,6 +1750,9 @@ fn convert_signature<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>
                 let params = convert_vector(state, params);
 
                 let body_ptr = state.next_ins_id();
+                if todos.in_generator {
+                    state.make_generator();
+                }
                 convert_exprs(state, todos, body, IS_TAIL)?;
                 state.push_return();
 
@@ -2,7 +2,14 @@ use anyhow::{
     Result,
     bail,
 };
+use std::cmp::Ordering;
+use crate::numeric::{
+    self,
+    Num,
+    Op,
+};
 use super::{
+    LispError,
     Interpreter,
     Interner,
     Data,
@@ -13,36 +20,15 @@ use super::{
 
 
 macro_rules! define_arithmetic_func {
-    ($name: ident, $sym: tt)=>{
+    ($name: ident, $op: literal, $kind: ident)=>{
         pub fn $name(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
             if args.is_empty() {return Ok(i.alloc(Data::Number(0)))}
 
             let mut iter = args.into_iter();
-
-            fn do_the_thing(d1: &mut Data, d2: &Data)->Result<()> {
-                match d1 {
-                    Data::Number(n1)=>{
-                        let Data::Number(n2) = d2 else {
-                            bail!("Type error: Expected number");
-                        };
-                        *n1 $sym *n2;
-                    },
-                    Data::Float(f1)=>{
-                        let Data::Float(f2) = d2 else {
-                            bail!("Type error: Expected float");
-                        };
-
-                        *f1 $sym *f2;
-                    },
-                    _=>bail!(concat!("Type error: ", stringify!($name), " can only accept number or float")),
-                }
-                return Ok(());
-            }
-
             let mut first = i.clone_data(&iter.next().unwrap());
 
             for arg in iter {
-                do_the_thing(&mut first.get_data_mut(), &arg.get_data())?;
+                apply_num(&mut *first.try_get_data_mut($op)?, &*arg.try_get_data($op)?, $op, Op::$kind)?;
             }
 
             return Ok(first);
@@ -51,36 +37,15 @@ macro_rules! define_arithmetic_func {
 }
 
 macro_rules! define_arithmetic_assign_func {
-    ($name: ident, $sym: tt)=>{
+    ($name: ident, $op: literal, $kind: ident)=>{
         pub fn $name(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
             if args.is_empty() {return Ok(i.alloc(Data::Number(0)))}
 
             let mut iter = args.into_iter();
-
-            fn do_the_thing(d1: &mut Data, d2: &Data)->Result<()> {
-                match d1 {
-                    Data::Number(n1)=>{
-                        let Data::Number(n2) = d2 else {
-                            bail!("Type error: Expected number");
-                        };
-                        *n1 $sym *n2;
-                    },
-                    Data::Float(f1)=>{
-                        let Data::Float(f2) = d2 else {
-                            bail!("Type error: Expected float");
-                        };
-
-                        *f1 $sym *f2;
-                    },
-                    _=>bail!(concat!("Type error: ", stringify!($name), " can only accept number or float")),
-                }
-                return Ok(());
-            }
-
             let mut first = iter.next().unwrap();
 
             for arg in iter {
-                do_the_thing(&mut first.get_data_mut(), &arg.get_data())?;
+                apply_num(&mut *first.try_get_data_mut($op)?, &*arg.try_get_data($op)?, $op, Op::$kind)?;
             }
 
             return Ok(first);
@@ -104,22 +69,51 @@ pub const BUILTINS: &[(&str, NativeFn, ArgCount)] = &[
 
     builtin!(equal, =, Any),
     builtin!(not_equal, !=, Any),
+    ("not=", not_equal, ArgCount::Any),
+    ("eq?", identical, ArgCount::Any),
     builtin!(greater, >, Any),
     builtin!(less, <, Any),
     builtin!(greater_equal, >=, Any),
     builtin!(less_equal, <=, Any),
+
+    builtin!(to_float, float, 1),
+    builtin!(to_int, int, 1),
+
+    ("nan?", is_nan, ArgCount::Exact(1)),
+    ("infinite?", is_infinite, ArgCount::Exact(1)),
+    ("finite?", is_finite, ArgCount::Exact(1)),
 ];
 
 
-fn do_the_thing_add(d1: &mut Data, d2: &Data)->Result<()> {
-    match d1 {
-        Data::Number(n1)=>{
-            let Data::Number(n2) = d2 else {
-                bail!("Type error: Expected number");
-            };
+fn as_num(data: &Data)->Option<Num> {
+    match data {
+        Data::Number(n)=>Some(Num::Int(*n)),
+        Data::Float(f)=>Some(Num::Float(*f)),
+        _=>None,
+    }
+}
 
-            *n1 += n2;
-        },
+/// `d1 = d1 op d2`, following the rules in `numeric`
+fn apply_num(d1: &mut Data, d2: &Data, name: &str, op: Op)->Result<()> {
+    let Some(l) = as_num(d1) else {
+        bail!(LispError::Type{op: name.into(), expected: "number or float", actual: d1.type_name()});
+    };
+    let Some(r) = as_num(d2) else {
+        bail!(LispError::Type{op: name.into(), expected: "number or float", actual: d2.type_name()});
+    };
+
+    *d1 = match numeric::apply(name, op, l, r)? {
+        Num::Int(n)=>Data::Number(n),
+        Num::Float(f)=>Data::Float(f),
+    };
+
+    return Ok(());
+}
+
+
+fn do_the_thing_add(d1: &mut Data, d2: &Data, op: &str)->Result<()> {
+    match d1 {
+        Data::Number(_)|Data::Float(_)=>apply_num(d1, d2, op, Op::Add)?,
         Data::String(out)=>{
             match d2 {
                 Data::String(s)=>{
@@ -128,7 +122,7 @@ fn do_the_thing_add(d1: &mut Data, d2: &Data)->Result<()> {
                 Data::Char(c)=>{
                     out.push(*c);
                 },
-                _=>bail!("Type error: Expected string or char"),
+                _=>bail!(LispError::Type{op: op.into(), expected: "string or char", actual: d2.type_name()}),
             }
         },
         Data::Char(c)=>{
@@ -143,24 +137,17 @@ fn do_the_thing_add(d1: &mut Data, d2: &Data)->Result<()> {
                     s1.push(*c2);
                     *d1 = Data::String(s1);
                 },
-                _=>bail!("Type error: Expected string or char"),
+                _=>bail!(LispError::Type{op: op.into(), expected: "string or char", actual: d2.type_name()}),
             }
         },
-        Data::Float(f1)=>{
-            let Data::Float(f2) = d2 else {
-                bail!("Type error: Expected float");
-            };
-
-            *f1 += f2;
-        },
         Data::Object(fields1)=>{
             let Data::Object(fields2) = d2 else {
-                bail!("Type error: Expected object");
+                bail!(LispError::Type{op: op.into(), expected: "object", actual: d2.type_name()});
             };
 
             fields1.extend(fields2.iter().map(|(i,dr)|(*i, dr.clone())));
         },
-        _=>bail!("Type error: AddAssign can only accept number, float, string"),
+        _=>bail!(LispError::Type{op: op.into(), expected: "number, float, string, char, list or object", actual: d1.type_name()}),
     }
     return Ok(());
 }
@@ -172,7 +159,7 @@ pub fn add(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<Da
 
 
     let mut first = i.clone_data(&iter.next().unwrap());
-    let mut first_mut = first.get_data_mut();
+    let mut first_mut = first.try_get_data_mut("+")?;
 
     match &mut *first_mut {
         Data::List(items)=>{
@@ -184,7 +171,7 @@ pub fn add(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<Da
     }
 
     for arg in iter {
-        do_the_thing_add(&mut first_mut, &arg.get_data())?;
+        do_the_thing_add(&mut first_mut, &*arg.try_get_data("+")?, "+")?;
     }
 
     drop(first_mut);
@@ -198,7 +185,7 @@ pub fn add_assign(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Re
 
 
     let mut first = iter.next().unwrap();
-    let mut first_mut = first.get_data_mut();
+    let mut first_mut = first.try_get_data_mut("+=")?;
 
     match &mut *first_mut {
         Data::List(items)=>{
@@ -210,7 +197,7 @@ pub fn add_assign(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Re
     }
 
     for arg in iter {
-        do_the_thing_add(&mut first_mut, &arg.get_data())?;
+        do_the_thing_add(&mut first_mut, &*arg.try_get_data("+=")?, "+=")?;
     }
 
     drop(first_mut);
@@ -225,7 +212,7 @@ pub fn equal(mut args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Res
     let first = args.pop().unwrap();
 
     for arg in args {
-        if &*arg.get_data() != &*first.get_data() {
+        if &*arg.try_get_data("=")? != &*first.try_get_data("=")? {
             return Ok(i.alloc(Data::Bool(false)));
         }
     }
@@ -241,7 +228,7 @@ pub fn not_equal(mut args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)-
     let first = args.pop().unwrap();
 
     for arg in args {
-        if &*arg.get_data() == &*first.get_data() {
+        if &*arg.try_get_data("!=")? == &*first.try_get_data("!=")? {
             return Ok(i.alloc(Data::Bool(false)));
         }
     }
@@ -249,84 +236,129 @@ pub fn not_equal(mut args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)-
     return Ok(i.alloc(Data::Bool(true)));
 }
 
-pub fn less_equal(mut args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    if args.len() == 0 {
-        return Ok(i.alloc(Data::Bool(true)));
-    }
+/// How `op` orders `l` and `r`. Numbers and floats compare exactly with each other, strings compare by
+/// code point, and chars with chars. Anything else is an error naming both types. `None` if either
+/// is NaN.
+fn compare(op: &str, l: &Data, r: &Data)->Result<Option<Ordering>> {
+    let ord = match (l, r) {
+        (Data::Number(_)|Data::Float(_), Data::Number(_)|Data::Float(_))=>{
+            numeric::compare(as_num(l).unwrap(), as_num(r).unwrap())
+        },
+        // UTF-8 byte order is the same as code point order
+        (Data::String(l), Data::String(r))=>Some(l.cmp(r)),
+        (Data::Char(l), Data::Char(r))=>Some(l.cmp(r)),
+        _=>bail!(LispError::Compare{op: op.into(), left: l.type_name(), right: r.type_name()}),
+    };
 
-    let first = args.pop().unwrap();
+    return Ok(ord);
+}
 
-    for arg in args {
-        match (&*arg.get_data(), &*first.get_data()) {
-            (Data::Number(l), Data::Number(r))=>if l > r {return Ok(i.alloc(Data::Bool(false)))},
-            (Data::Float(l), Data::Float(r))=>if l > r {return Ok(i.alloc(Data::Bool(false)))},
-            _=>return Ok(i.alloc(Data::Bool(false))),
-        }
+/// True if `check` holds for every pair of neighboring args, so `(< a b c)` is `a < b < c`
+fn compare_chain(args: Vec<DataRef>, op: &str, check: fn(Ordering)->bool, i: &mut Interpreter)->Result<DataRef> {
+    let mut holds = true;
+    for pair in args.windows(2) {
+        let ord = compare(op, &*pair[0].try_get_data(op)?, &*pair[1].try_get_data(op)?)?;
+        // keep going so a later pair of the wrong types is still an error
+        holds &= ord.is_some_and(check);
     }
 
-    return Ok(i.alloc(Data::Bool(true)));
+    return Ok(i.alloc(Data::Bool(holds)));
 }
 
-pub fn greater_equal(mut args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    if args.len() == 0 {
-        return Ok(i.alloc(Data::Bool(true)));
-    }
+pub fn less_equal(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    compare_chain(args, "<=", Ordering::is_le, i)
+}
 
-    let first = args.pop().unwrap();
+pub fn greater_equal(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    compare_chain(args, ">=", Ordering::is_ge, i)
+}
 
-    for arg in args {
-        match (&*arg.get_data(), &*first.get_data()) {
-            (Data::Number(l), Data::Number(r))=>if l < r {return Ok(i.alloc(Data::Bool(false)))},
-            (Data::Float(l), Data::Float(r))=>if l < r {return Ok(i.alloc(Data::Bool(false)))},
-            _=>return Ok(i.alloc(Data::Bool(false))),
-        }
-    }
+pub fn less(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    compare_chain(args, "<", Ordering::is_lt, i)
+}
 
-    return Ok(i.alloc(Data::Bool(true)));
+pub fn greater(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    compare_chain(args, ">", Ordering::is_gt, i)
 }
 
-pub fn less(mut args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    if args.len() == 0 {
+/// `eq?`: every arg is the same data as the first. Numbers, floats, chars, bools, idents and
+/// `None` are values rather than places, so equal ones count as the same. Floats compare by their
+/// bits like V2's, so a NaN is the same as itself and `0.0` isn't the same as `-0.0`.
+pub fn identical(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let Some((first, rest)) = args.split_first() else {
         return Ok(i.alloc(Data::Bool(true)));
-    }
+    };
 
-    let first = args.pop().unwrap();
+    for arg in rest {
+        if arg.is_same(first) {continue}
 
-    for arg in args {
-        match (&*arg.get_data(), &*first.get_data()) {
-            (Data::Number(l), Data::Number(r))=>if l >= r {return Ok(i.alloc(Data::Bool(false)))},
-            (Data::Float(l), Data::Float(r))=>if l >= r {return Ok(i.alloc(Data::Bool(false)))},
-            _=>return Ok(i.alloc(Data::Bool(false))),
+        let same = match (&*first.try_get_data("eq?")?, &*arg.try_get_data("eq?")?) {
+            (Data::Float(l), Data::Float(r))=>l.to_bits() == r.to_bits(),
+            (l @ (Data::Number(_)|Data::Char(_)|Data::Bool(_)|Data::Ident(_)|Data::None), r)=>l == r,
+            _=>false,
+        };
+        if !same {
+            return Ok(i.alloc(Data::Bool(false)));
         }
     }
 
     return Ok(i.alloc(Data::Bool(true)));
 }
 
-pub fn greater(mut args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    if args.len() == 0 {
-        return Ok(i.alloc(Data::Bool(true)));
-    }
+/// `(float x)`: a number as the nearest float. Floats come back as they are.
+pub fn to_float(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let f = match &*args[0].try_get_data("float")? {
+        Data::Number(n)=>*n as f64,
+        Data::Float(f)=>*f,
+        data=>bail!(LispError::Type{op: "float".into(), expected: "number or float", actual: data.type_name()}),
+    };
 
-    let first = args.pop().unwrap();
+    return Ok(i.alloc(Data::Float(f)));
+}
 
-    for arg in args {
-        match (&*arg.get_data(), &*first.get_data()) {
-            (Data::Number(l), Data::Number(r))=>if l <= r {return Ok(i.alloc(Data::Bool(false)))},
-            (Data::Float(l), Data::Float(r))=>if l <= r {return Ok(i.alloc(Data::Bool(false)))},
-            _=>return Ok(i.alloc(Data::Bool(false))),
-        }
-    }
+/// `(int x)`: a float truncated toward zero, so `(int -2.7)` is `-2`. Numbers come back as they are.
+pub fn to_int(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let n = match &*args[0].try_get_data("int")? {
+        Data::Number(n)=>*n,
+        Data::Float(f)=>numeric::to_int("int", *f)?,
+        data=>bail!(LispError::Type{op: "int".into(), expected: "number or float", actual: data.type_name()}),
+    };
 
-    return Ok(i.alloc(Data::Bool(true)));
+    return Ok(i.alloc(Data::Number(n)));
+}
+
+/// Check a property of a float. Numbers are always finite, so they get `int_answer`.
+fn float_predicate(args: Vec<DataRef>, op: &str, check: fn(f64)->bool, int_answer: bool, i: &mut Interpreter)->Result<DataRef> {
+    let holds = match &*args[0].try_get_data(op)? {
+        Data::Number(_)=>int_answer,
+        Data::Float(f)=>check(*f),
+        data=>bail!(LispError::Type{op: op.into(), expected: "number or float", actual: data.type_name()}),
+    };
+
+    return Ok(i.alloc(Data::Bool(holds)));
+}
+
+/// `(nan? x)`. The only way to tell, since `(= x x)` is false for a NaN.
+pub fn is_nan(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    float_predicate(args, "nan?", f64::is_nan, false, i)
+}
+
+/// `(infinite? x)`: `x` is `#inf` or `#-inf`
+pub fn is_infinite(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    float_predicate(args, "infinite?", f64::is_infinite, false, i)
+}
+
+/// `(finite? x)`: `x` is neither infinite nor NaN
+pub fn is_finite(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    float_predicate(args, "finite?", f64::is_finite, true, i)
 }
 
-define_arithmetic_func!(sub, -=);
-define_arithmetic_func!(mul, *=);
-define_arithmetic_func!(div, /=);
-define_arithmetic_func!(modulo, %=);
+define_arithmetic_func!(sub, "-", Sub);
+define_arithmetic_func!(mul, "*", Mul);
+define_arithmetic_func!(div, "/", Div);
+define_arithmetic_func!(modulo, "%", Rem);
 
-define_arithmetic_assign_func!(sub_assign, -=);
-define_arithmetic_assign_func!(mul_assign, *=);
-define_arithmetic_assign_func!(div_assign, /=);
-define_arithmetic_assign_func!(modulo_assign, %=);
+define_arithmetic_assign_func!(sub_assign, "-=", Sub);
+define_arithmetic_assign_func!(mul_assign, "*=", Mul);
+define_arithmetic_assign_func!(div_assign, "/=", Div);
+define_arithmetic_assign_func!(modulo_assign, "%=", Rem);
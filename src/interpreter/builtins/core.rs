@@ -2,6 +2,7 @@ use anyhow::{
     Result,
     bail,
 };
+use rustc_hash::FxHashMap;
 use super::{
     Interpreter,
     Interner,
@@ -9,6 +10,17 @@ use super::{
     DataRef,
     NativeFn,
     ArgCount,
+    LispError,
+    Signature,
+    Capabilities,
+};
+use crate::interpreter::{
+    data::pair_chain,
+    pretty::{
+        PrettyConfig,
+        pretty_format,
+        preview,
+    },
 };
 
 
@@ -17,27 +29,51 @@ pub const BUILTINS: &[(&str, NativeFn, ArgCount)] = &[
     builtin!(gc_collect, gcCollect, 0),
     builtin!(and, Any),
     builtin!(or, Any),
+    builtin!(not, 1),
     builtin!(index, 2),
     builtin!(list, Any),
+    builtin!(cons, 2),
+    builtin!(car, 1),
+    builtin!(cdr, 1),
+    builtin!(is_pair, isPair, 1),
     builtin!(length, 1),
     builtin!(list_pop, listPop, 1),
     builtin!(clone, 1),
+    builtin!(shallow_copy, shallowCopy, 1),
+    builtin!(deep_copy, deepCopy, 1),
     builtin!(debug, Any),
+    builtin!(pprint, Any),
+    builtin!(assert, Any),
     builtin!(intern, 1),
     builtin!(fields, 1),
     builtin!(is_ident, isIdent, 1),
+    builtin!(type_of, typeOf, 1),
+    builtin!(hash, 1),
 ];
 
 
 pub fn is_ident(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    match &*args[0].get_data() {
+    match &*args[0].try_get_data("isIdent")? {
         Data::Ident(_)=>Ok(i.alloc(Data::Bool(true))),
         _=>Ok(i.alloc(Data::Bool(false))),
     }
 }
 
+/// The type of the argument as an ident, like `.number`
+pub fn type_of(args: Vec<DataRef>, i: &mut Interpreter, interner: &mut Interner)->Result<DataRef> {
+    let name = args[0].try_get_data("typeOf")?.type_name();
+
+    return Ok(i.alloc(Data::Ident(interner.intern(name))));
+}
+
+/// A number that is the same for any two values that are `=`. See `Data::structural_hash`.
+pub fn hash(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let hash = args[0].structural_hash("hash")?;
+    Ok(i.alloc(Data::Number(hash as i64)))
+}
+
 pub fn fields(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    match &*args[0].get_data() {
+    match &*args[0].try_get_data("fields")? {
         Data::Object(fields)=>{
             let mut list = Vec::new();
             for (name, value) in fields.iter() {
@@ -45,12 +81,14 @@ pub fn fields(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result
                     i.alloc(Data::Ident(*name)),
                     value.clone(),
                 ];
-                list.push(i.alloc(Data::List(list2)));
+                let pair = i.alloc(Data::List(list2));
+                i.root(&pair);
+                list.push(pair);
             }
 
             return Ok(i.alloc(Data::List(list)));
         },
-        _=>bail!("Value passed to `fields` is not an object!"),
+        data=>bail!(LispError::Type{op: "fields".into(), expected: "object", actual: data.type_name()}),
     }
 }
 
@@ -61,9 +99,8 @@ pub fn gc_collect(_args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->R
 
 pub fn and(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     for arg in args {
-        match &*arg.get_data() {
-            Data::Bool(true)=>{},
-            _=>return Ok(i.alloc(Data::Bool(false))),
+        if !i.is_truthy("and", &arg)? {
+            return Ok(i.alloc(Data::Bool(false)));
         }
     }
 
@@ -72,34 +109,39 @@ pub fn and(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<Da
 
 pub fn or(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     for arg in args {
-        match &*arg.get_data() {
-            Data::Bool(true)=>return Ok(i.alloc(Data::Bool(true))),
-            _=>{},
+        if i.is_truthy("or", &arg)? {
+            return Ok(i.alloc(Data::Bool(true)));
         }
     }
 
     return Ok(i.alloc(Data::Bool(false)));
 }
 
+pub fn not(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let truthy = i.is_truthy("not", &args[0])?;
+    return Ok(i.alloc(Data::Bool(!truthy)));
+}
+
 pub fn index(mut args: Vec<DataRef>, _: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     if args.len() != 2 {
-        bail!("`index` only takes 2 arguments!");
+        bail!(LispError::Arity{name: Some("index".into()), expected: vec![Signature::exact(2)], got: args.len()});
     }
 
     let first = args.remove(0);
     let second = args.remove(0);
-    let first_ref = first.get_data();
-    let second_ref = second.get_data();
+    let first_ref = first.try_get_data("index")?;
+    let second_ref = second.try_get_data("index")?;
 
     match (&*first_ref, &*second_ref) {
         (Data::List(items), Data::Number(i))=>{
             if *i < 0 || *i >= items.len() as i64 {
-                bail!("Index out of bounds");
+                bail!(LispError::IndexOutOfBounds{op: "index".into(), index: *i, len: items.len()});
             }
 
             return Ok(items[*i as usize].clone());
         },
-        (l, r)=>bail!("`index` can only index a list with a number. index: `{l:?}`, to_index: `{r:?}`"),
+        (Data::List(_), r)=>bail!(LispError::Type{op: "index".into(), expected: "number", actual: r.type_name()}),
+        (l, _)=>bail!(LispError::Type{op: "index".into(), expected: "list", actual: l.type_name()}),
     }
 }
 
@@ -107,38 +149,203 @@ pub fn list(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<D
     Ok(i.alloc(Data::List(args.into())))
 }
 
+/// A new pair. This is O(1) for any cdr, including a list, since nothing is copied.
+pub fn cons(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    Ok(i.alloc(Data::Pair(args[0].clone(), args[1].clone())))
+}
+
+/// The car of a pair, or the first item of a list
+pub fn car(args: Vec<DataRef>, _: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    match &*args[0].try_get_data("car")? {
+        Data::Pair(car, _)=>Ok(car.clone()),
+        Data::List(items)=>items.first()
+            .cloned()
+            .ok_or_else(||LispError::IndexOutOfBounds{op: "car".into(), index: 0, len: 0}.into()),
+        data=>bail!(LispError::Type{op: "car".into(), expected: "pair or list", actual: data.type_name()}),
+    }
+}
+
+/// The cdr of a pair, or a new list of everything after the first item of a list. Lists are
+/// copied, so that is O(n).
+pub fn cdr(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let rest = match &*args[0].try_get_data("cdr")? {
+        Data::Pair(_, cdr)=>return Ok(cdr.clone()),
+        Data::List(items) if items.is_empty()=>bail!(LispError::IndexOutOfBounds{op: "cdr".into(), index: 0, len: 0}),
+        Data::List(items)=>items[1..].to_vec(),
+        data=>bail!(LispError::Type{op: "cdr".into(), expected: "pair or list", actual: data.type_name()}),
+    };
+
+    // the items are still in the rooted list, so they are safe while we allocate
+    return Ok(i.alloc(Data::List(rest)));
+}
+
+pub fn is_pair(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let is_pair = matches!(&*args[0].try_get_data("isPair")?, Data::Pair(..));
+    return Ok(i.alloc(Data::Bool(is_pair)));
+}
+
 pub fn clone(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     Ok(i.clone_data(&args[0]))
 }
 
+/// Same as `clone`: a new list or object that holds the same items
+pub fn shallow_copy(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    Ok(i.clone_data(&args[0]))
+}
+
+/// Copy lists and objects all the way down, so mutating the copy never changes the original
+pub fn deep_copy(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    deep_copy_inner(&args[0], i, &mut FxHashMap::default())
+}
+
+/// `copies` maps what was already copied to its copy. Data that shows up more than once is only
+/// copied once, so sharing and cycles look the same in the copy.
+fn deep_copy_inner(dr: &DataRef, i: &mut Interpreter, copies: &mut FxHashMap<usize, DataRef>)->Result<DataRef> {
+    if let Some(copy) = copies.get(&dr.addr()) {
+        return Ok(copy.clone());
+    }
+
+    // the copy goes in `copies` before we copy what's inside, so anything that points back at
+    // `dr` finds it. Copies are rooted since `copies` isn't.
+    let data = dr.try_get_data("deepCopy")?.clone();
+    match data {
+        Data::List(items)=>{
+            let mut copy = i.alloc(Data::List(Vec::new()));
+            i.root(&copy);
+            copies.insert(dr.addr(), copy.clone());
+
+            let mut new_items = Vec::with_capacity(items.len());
+            for item in items.iter() {
+                new_items.push(deep_copy_inner(item, i, copies)?);
+            }
+            *copy.try_get_data_mut("deepCopy")? = Data::List(new_items);
+
+            return Ok(copy);
+        },
+        Data::Object(fields)=>{
+            let mut copy = i.alloc(Data::Object(Default::default()));
+            i.root(&copy);
+            copies.insert(dr.addr(), copy.clone());
+
+            let mut new_fields = fields.clone();
+            for value in new_fields.values_mut() {
+                *value = deep_copy_inner(value, i, copies)?;
+            }
+            *copy.try_get_data_mut("deepCopy")? = Data::Object(new_fields);
+
+            return Ok(copy);
+        },
+        Data::Pair(car, cdr)=>{
+            // pairs can't be changed, but what they hold can, so the copy gets filled in like a list
+            let mut copy = i.alloc(Data::None);
+            i.root(&copy);
+            copies.insert(dr.addr(), copy.clone());
+
+            let car = deep_copy_inner(&car, i, copies)?;
+            let cdr = deep_copy_inner(&cdr, i, copies)?;
+            *copy.try_get_data_mut("deepCopy")? = Data::Pair(car, cdr);
+
+            return Ok(copy);
+        },
+        // functions and lazy seqs can't be changed, so they are shared. So are generators, since a
+        // copy would run the rest of the body a second time.
+        Data::Fn(_)|
+            Data::NativeFn(..)|
+            Data::Closure{..}|
+            Data::Lazy(_)|
+            Data::Generator(_)|
+            Data::NativeData(_)=>return Ok(dr.clone()),
+        // everything else can be changed in place with `+=` and friends, so it is copied too
+        data=>{
+            let copy = i.alloc(data);
+            i.root(&copy);
+            copies.insert(dr.addr(), copy.clone());
+
+            return Ok(copy);
+        },
+    }
+}
+
 pub fn length(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    if args.len() != 1 {bail!("Index only accepts one argument")}
+    if args.len() != 1 {bail!(LispError::Arity{name: Some("length".into()), expected: vec![Signature::exact(1)], got: args.len()})}
 
-    let data = args[0].get_data();
+    let data = args[0].try_get_data("length")?;
 
     match &*data {
         Data::List(items)=>Ok(i.alloc(Data::Number(items.len() as i64))),
         Data::String(s)=>Ok(i.alloc(Data::Number(s.len() as i64))),
+        // the number of cars in the chain, whatever it ends in
+        Data::Pair(..)=>Ok(i.alloc(Data::Number(pair_chain(&args[0]).0.len() as i64))),
         _=>Ok(i.alloc(Data::Number(0))),
     }
 }
 
 pub fn list_pop(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     let mut data = args[0].clone();
-    let mut data_ref = data.get_data_mut();
-    match &mut *data_ref {
-        Data::List(items)=>return Ok(items.pop().unwrap_or_else(||i.alloc(Data::None))),
-        _=>bail!("Type error: `listPop` only accepts Lists"),
-    }
+    let popped = match &mut *data.try_get_data_mut("listPop")? {
+        Data::List(items)=>items.pop(),
+        data=>bail!(LispError::Type{op: "listPop".into(), expected: "list", actual: data.type_name()}),
+    };
+
+    // the list can't be borrowed when we allocate, since a collection may need to look at it
+    return Ok(popped.unwrap_or_else(||i.alloc(Data::None)));
 }
 
 pub fn debug(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    // keep it in order with what the program printed before
+    i.output().flush().map_err(LispError::from)?;
     eprintln!("{args:#?}");
     return Ok(i.alloc(Data::None));
 }
 
+/// The arguments passed to the script as a list of strings. The script path is not included; use
+/// `*script*` for that.
+pub fn args(_args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let args = i.script_args().to_vec();
+    let list = args.into_iter()
+        .map(|a|{
+            let dr = i.alloc(Data::String(a));
+            i.root(&dr);
+            dr
+        })
+        .collect();
+
+    return Ok(i.alloc(Data::List(list)));
+}
+
+/// Pretty print each argument on its own line.
+pub fn pprint(args: Vec<DataRef>, i: &mut Interpreter, interner: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    let config = PrettyConfig::for_terminal();
+    for arg in args.iter() {
+        let mut text = pretty_format(arg, interner, &config);
+        text.push('\n');
+        i.output().write_str(&text).map_err(LispError::from)?;
+    }
+
+    return Ok(i.alloc(Data::None));
+}
+
+/// `(core/assert cond)` or `(core/assert cond message)`. Errors with `message` if `cond` is false
+/// or `None`. A string message is used as it is.
+pub fn assert(args: Vec<DataRef>, i: &mut Interpreter, interner: &mut Interner)->Result<DataRef> {
+    if args.len() == 0 || args.len() > 2 {
+        bail!(LispError::Arity{name: Some("assert".into()), expected: vec![Signature::exact(1), Signature::exact(2)], got: args.len()});
+    }
+    if i.is_truthy("assert", &args[0])? {
+        return Ok(i.alloc(Data::None));
+    }
+
+    let message = args.get(1).map(|message|match &*message.get_data() {
+        Data::String(s)=>s.clone(),
+        _=>preview(message, interner, 60),
+    });
+    bail!(LispError::AssertFailed{message});
+}
+
 pub fn intern(args: Vec<DataRef>, i: &mut Interpreter, interner: &mut Interner)->Result<DataRef> {
-    let dr_ref = args[0].get_data();
+    let dr_ref = args[0].try_get_data("intern")?;
     match &*dr_ref {
         Data::String(s)=>{
             let ident = interner.intern(s.as_str());
@@ -148,6 +355,6 @@ pub fn intern(args: Vec<DataRef>, i: &mut Interpreter, interner: &mut Interner)-
             let s = interner.get(*ident).to_string();
             return Ok(i.alloc(Data::String(s)));
         },
-        _=>bail!("Type error: `intern` can only accept String or Ident"),
+        data=>bail!(LispError::Type{op: "intern".into(), expected: "string or ident", actual: data.type_name()}),
     }
 }
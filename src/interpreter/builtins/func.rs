@@ -0,0 +1,193 @@
+//! Natives that make functions out of other functions. What the made functions need is kept in
+//! `NativeFunc`'s captures, so the GC can see it, and comes first in their arguments.
+//!
+//! These call back into lisp, so the arguments are rooted first like in `seq`.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use std::rc::Rc;
+use super::{
+    Interpreter,
+    Data,
+    DataRef,
+    NativeFunc,
+    CallingNativeFn,
+    ArgCount,
+    ConvertState,
+    LispError,
+    Signature,
+};
+
+
+pub const BUILTINS: &[(&str, CallingNativeFn, ArgCount)] = &[
+    builtin!(memoize, Any),
+    builtin!(compose, Any),
+    builtin!(partial, Any),
+    builtin!(identity, 1),
+    builtin!(constantly, 1),
+];
+
+
+fn root_args(args: &[DataRef], i: &mut Interpreter) {
+    for arg in args {
+        i.root(arg);
+    }
+}
+
+fn check_callable(dr: &DataRef, op: &str)->Result<()> {
+    match &*dr.try_get_data(op)? {
+        Data::Fn(_)|Data::Closure{..}|Data::NativeFn(..)=>Ok(()),
+        data=>bail!(LispError::Type{op: op.into(), expected: "fn", actual: data.type_name()}),
+    }
+}
+
+/// A function called `name` that calls `f` with `captures` and then its arguments
+fn make_fn(i: &mut Interpreter, name: &str, f: CallingNativeFn, captures: Vec<DataRef>)->DataRef {
+    let func = NativeFunc::with_captures(f, captures);
+    i.alloc(Data::NativeFn(Rc::from(name), func, ArgCount::Any))
+}
+
+
+/// `(memoize f)` or `(memoize f max)`. A function that calls `f` the first time it gets some
+/// arguments, then returns the same result every time it gets arguments `=` to those. With `max`,
+/// only the latest `max` results are kept.
+pub fn memoize(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    if !(1..=2).contains(&args.len()) {
+        bail!(LispError::Arity{
+            name: Some("memoize".into()),
+            expected: vec![Signature::exact(1), Signature::exact(2)],
+            got: args.len(),
+        });
+    }
+    check_callable(&args[0], "memoize")?;
+    let max = match args.get(1) {
+        Some(max)=>match &*max.try_get_data("memoize")? {
+            Data::Number(n) if *n > 0=>i.alloc(Data::Number(*n)),
+            Data::Number(n)=>bail!(LispError::OutOfRange{op: "memoize".into(), expected: "a max size of at least 1", actual: n.to_string()}),
+            data=>bail!(LispError::Type{op: "memoize".into(), expected: "number", actual: data.type_name()}),
+        },
+        None=>i.alloc(Data::None),
+    };
+    i.root(&max);
+
+    // `(hash args result)` lists, oldest first. `hash` is `none` if the args can't be hashed.
+    let cache = i.alloc(Data::List(Vec::new()));
+    i.root(&cache);
+
+    return Ok(make_fn(i, "memoized", call_memoized, vec![args[0].clone(), cache, max]));
+}
+
+fn call_memoized(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let [f, cache, max, call_args @ ..] = args.as_slice() else {
+        unreachable!("Memoized functions always have their captures");
+    };
+
+    // arguments that can't be hashed, like functions, are still compared with `=`
+    let key = Data::List(call_args.to_vec());
+    let hash = key.structural_hash("memoized").ok();
+    if let Data::List(entries) = &*cache.try_get_data("memoized")? {
+        for entry in entries {
+            if let Data::List(entry) = &*entry.try_get_data("memoized")? {
+                let same_hash = match (hash, &*entry[0].try_get_data("memoized")?) {
+                    (Some(hash), Data::Number(entry_hash))=>hash as i64 == *entry_hash,
+                    _=>true,
+                };
+                if same_hash && *entry[1].try_get_data("memoized")? == key {
+                    return Ok(entry[2].clone());
+                }
+            }
+        }
+    }
+
+    let result = i.call(state, f.clone(), call_args.to_vec())?;
+    i.root(&result);
+
+    let key = i.alloc(key);
+    i.root(&key);
+    let hash = match hash {
+        Some(hash)=>i.alloc(Data::Number(hash as i64)),
+        None=>i.alloc(Data::None),
+    };
+    let entry = i.alloc(Data::List(vec![hash, key, result.clone()]));
+    let max = match &*max.try_get_data("memoized")? {
+        Data::Number(n)=>Some(*n as usize),
+        _=>None,
+    };
+    let mut cache = cache.clone();
+    if let Data::List(entries) = &mut *cache.try_get_data_mut("memoized")? {
+        if max.is_some_and(|max|entries.len() >= max) {
+            entries.remove(0);
+        }
+        entries.push(entry);
+    }
+
+    return Ok(result);
+}
+
+/// `(compose f g h)` is a function that calls `h` with its arguments, `g` with what that returned,
+/// then `f` with what `g` returned
+pub fn compose(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    if args.is_empty() {
+        bail!(LispError::Arity{name: Some("compose".into()), expected: vec![Signature::at_least(1)], got: 0});
+    }
+    for arg in args.iter() {
+        check_callable(arg, "compose")?;
+    }
+
+    let fns = i.alloc(Data::List(args));
+    i.root(&fns);
+
+    return Ok(make_fn(i, "composed", call_composed, vec![fns]));
+}
+
+fn call_composed(mut args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let fns = args.remove(0);
+    let fns = match &*fns.try_get_data("composed")? {
+        Data::List(fns)=>fns.clone(),
+        _=>unreachable!("Composed functions always capture a list"),
+    };
+
+    let mut fns = fns.into_iter().rev();
+    let mut out = i.call(state, fns.next().unwrap(), args)?;
+    for f in fns {
+        i.root(&out);
+        out = i.call(state, f, vec![out])?;
+    }
+
+    return Ok(out);
+}
+
+/// `(partial f a b)` is a function that calls `f` with `a`, `b`, then its own arguments. Which of
+/// `f`'s signatures is used depends on how many there are in total.
+pub fn partial(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    let Some(f) = args.first() else {
+        bail!(LispError::Arity{name: Some("partial".into()), expected: vec![Signature::at_least(1)], got: 0});
+    };
+    check_callable(f, "partial")?;
+
+    return Ok(make_fn(i, "partial", call_partial, args));
+}
+
+fn call_partial(mut args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let f = args.remove(0);
+    return i.call(state, f, args);
+}
+
+pub fn identity(args: Vec<DataRef>, _: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    Ok(args[0].clone())
+}
+
+/// `(constantly x)` is a function that takes any arguments and always returns `x`
+pub fn constantly(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    Ok(make_fn(i, "constantly", call_constantly, args))
+}
+
+fn call_constantly(args: Vec<DataRef>, _: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    Ok(args[0].clone())
+}
@@ -8,7 +8,6 @@ use std::{
         Write,
         BufReader,
         BufRead,
-        stdout,
     },
     rc::Rc,
     cell::RefCell,
@@ -22,7 +21,8 @@ use super::{
     NativeData,
     NativeFn,
     ArgCount,
-    // DEBUG,
+    LispError,
+    Capabilities,
 };
 
 
@@ -35,10 +35,11 @@ pub const BUILTINS: &[(&str, NativeFn, ArgCount)] = &[
 
 
 pub fn open(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    let data_ref = args[0].get_data();
+    i.require(Capabilities::FS_READ)?;
+    let data_ref = args[0].try_get_data("open")?;
     match &*data_ref {
         Data::String(s)=>{
-            let file = File::open(s)?;
+            let file = File::open(s).map_err(LispError::from)?;
             println!("Open `{s}`");
 
             // hehehehe... triangle of `new`
@@ -56,18 +57,19 @@ pub fn open(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<D
                 )
             );
         },
-        _=>bail!("Open can only take Strings"),
+        data=>bail!(LispError::Type{op: "open".into(), expected: "string", actual: data.type_name()}),
     }
 }
 
 pub fn read_line(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    let data_ref = args[0].get_data();
+    let data_ref = args[0].try_get_data("readLine")?;
     match &*data_ref {
         Data::NativeData(d)=>match d {
             NativeData::File(f)=>{
+                i.require(Capabilities::FS_READ)?;
                 let mut file = f.borrow_mut();
                 let mut buf = String::new();
-                file.read_line(&mut buf)?;
+                file.read_line(&mut buf).map_err(LispError::from)?;
                 while buf.ends_with(|c:char|c=='\r'||c=='\n') {
                     buf.pop();
                 }
@@ -75,9 +77,10 @@ pub fn read_line(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Res
                 return Ok(i.alloc(Data::String(buf)));
             },
             NativeData::Stdin(f)=>{
+                i.require(Capabilities::STDIO)?;
                 let mut file = f.borrow_mut();
                 let mut buf = String::new();
-                file.read_line(&mut buf)?;
+                file.read_line(&mut buf).map_err(LispError::from)?;
                 while buf.ends_with(|c:char|c=='\r'||c=='\n') {
                     buf.pop();
                 }
@@ -85,65 +88,81 @@ pub fn read_line(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Res
                 return Ok(i.alloc(Data::String(buf)));
             },
             NativeData::Stdout=>bail!("Cannot read from stdout"),
+            NativeData::Custom(host)=>bail!(LispError::Type{op: "readLine".into(), expected: "file", actual: host.type_name()}),
+            NativeData::StringBuilder(_)=>bail!(LispError::Type{op: "readLine".into(), expected: "file", actual: "stringBuilder"}),
+            NativeData::Channel(_)=>bail!(LispError::Type{op: "readLine".into(), expected: "file", actual: "channel"}),
+            NativeData::Thread(_)=>bail!(LispError::Type{op: "readLine".into(), expected: "file", actual: "thread"}),
         },
-        _=>bail!("Invalid type for `read`"),
+        data=>bail!(LispError::Type{op: "readLine".into(), expected: "file", actual: data.type_name()}),
     }
 }
 
 pub fn read(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     let data = &args[0];
-    let data_ref = data.get_data();
+    let data_ref = data.try_get_data("read")?;
     match &*data_ref {
         Data::NativeData(d)=>match d {
             NativeData::File(f)=>{
+                i.require(Capabilities::FS_READ)?;
                 println!("Read a file");
                 let mut file = f.borrow_mut();
                 let mut buf = String::new();
 
-                file.read_to_string(&mut buf)?;
+                file.read_to_string(&mut buf).map_err(LispError::from)?;
 
                 return Ok(i.alloc(Data::String(buf)));
             },
             NativeData::Stdin(file_lock)=>{
+                i.require(Capabilities::STDIO)?;
                 println!("Read stdin");
                 let mut file = file_lock.borrow_mut();
                 let mut buf = String::new();
 
-                file.read_to_string(&mut buf)?;
+                file.read_to_string(&mut buf).map_err(LispError::from)?;
 
                 return Ok(i.alloc(Data::String(buf)));
             },
             NativeData::Stdout=>bail!("Cannot read from stdout"),
+            NativeData::Custom(host)=>bail!(LispError::Type{op: "read".into(), expected: "file", actual: host.type_name()}),
+            NativeData::StringBuilder(_)=>bail!(LispError::Type{op: "read".into(), expected: "file", actual: "stringBuilder"}),
+            NativeData::Channel(_)=>bail!(LispError::Type{op: "read".into(), expected: "file", actual: "channel"}),
+            NativeData::Thread(_)=>bail!(LispError::Type{op: "read".into(), expected: "file", actual: "thread"}),
         },
-        _=>bail!("Invalid type for `read`"),
+        data=>bail!(LispError::Type{op: "read".into(), expected: "file", actual: data.type_name()}),
     }
 }
 
 pub fn write(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
-    let file_ref = args[0].get_data();
-    let data_ref = args[1].get_data();
+    let file_ref = args[0].try_get_data("write")?;
+    let data_ref = args[1].try_get_data("write")?;
     let data = match &*data_ref {
         Data::String(s)=>s.as_str(),
-        _=>bail!("Expected string"),
+        data=>bail!(LispError::Type{op: "write".into(), expected: "string", actual: data.type_name()}),
     };
     match &*file_ref {
         Data::NativeData(d)=>match d {
             NativeData::File(f)=>{
+                i.require(Capabilities::FS_WRITE)?;
                 let mut file = f.borrow_mut();
-                let len = file.get_mut().write(data.as_bytes())?;
-                file.get_mut().flush()?;
+                let len = file.get_mut().write(data.as_bytes()).map_err(LispError::from)?;
+                file.get_mut().flush().map_err(LispError::from)?;
 
                 return Ok(i.alloc(Data::Number(len as i64)));
             },
             NativeData::Stdout=>{
-                let mut file = stdout();
-                let len = file.write(data.as_bytes())?;
-                file.flush()?;
+                i.require(Capabilities::STDIO)?;
+                let out = i.output();
+                out.write_str(data).map_err(LispError::from)?;
+                out.flush().map_err(LispError::from)?;
 
-                return Ok(i.alloc(Data::Number(len as i64)));
+                return Ok(i.alloc(Data::Number(data.len() as i64)));
             },
             NativeData::Stdin(_)=>bail!("Cannot write to stdin"),
+            NativeData::Custom(host)=>bail!(LispError::Type{op: "write".into(), expected: "file", actual: host.type_name()}),
+            NativeData::StringBuilder(_)=>bail!(LispError::Type{op: "write".into(), expected: "file", actual: "stringBuilder"}),
+            NativeData::Channel(_)=>bail!(LispError::Type{op: "write".into(), expected: "file", actual: "channel"}),
+            NativeData::Thread(_)=>bail!(LispError::Type{op: "write".into(), expected: "file", actual: "thread"}),
         },
-        _=>bail!("Invalid type for `read`"),
+        data=>bail!(LispError::Type{op: "write".into(), expected: "file", actual: data.type_name()}),
     }
 }
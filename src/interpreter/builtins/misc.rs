@@ -9,7 +9,8 @@ use super::{
     DataRef,
     NativeFn,
     ArgCount,
-    // DEBUG,
+    LispError,
+    Signature,
 };
 
 
@@ -20,12 +21,13 @@ pub const BUILTINS: &[(&str, NativeFn, ArgCount)] = &[
 
 pub fn split_list(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     if args.len() != 2 {
-        bail!("`split` can only take two arguments");
+        bail!(LispError::Arity{name: Some("splitList".into()), expected: vec![Signature::exact(2)], got: args.len()});
     }
     let mut data = i.clone_data(&args[0]);
-    let mut data_ref = data.get_data_mut();
+    i.root(&data);
+    let mut data_ref = data.try_get_data_mut("splitList")?;
     let split_thing = &args[1];
-    let split_thing_ref = split_thing.get_data();
+    let split_thing_ref = split_thing.try_get_data("splitList")?;
     match &mut *data_ref {
         Data::List(items)=>{
             match &*split_thing_ref {
@@ -34,15 +36,16 @@ pub fn split_list(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Re
                         bail!("Split index is out of range for list!");
                     }
                     let idx = *n as usize;
-                    let second = i.alloc(Data::List(items.split_off(idx)));
+                    let second = items.split_off(idx);
                     drop(data_ref);
+                    let second = i.alloc(Data::List(second));
                     let out = i.alloc(Data::List(vec![data, second]));
 
                     return Ok(out);
                 },
-                _=>bail!("`splitList` split index can only be a Number!"),
+                data=>bail!(LispError::Type{op: "splitList".into(), expected: "number", actual: data.type_name()}),
             }
         },
-        _=>bail!("`splitList` can only accept Lists"),
+        data=>bail!(LispError::Type{op: "splitList".into(), expected: "list", actual: data.type_name()}),
     }
 }
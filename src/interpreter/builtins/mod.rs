@@ -1,11 +1,41 @@
+//! The native functions. They can allocate, and with `GcConfig::stress` every allocation runs a
+//! collection first, so natives have to follow a few rooting rules:
+//!
+//! - The arguments are rooted for the whole call, and so is anything reachable from them.
+//! - Data given to `Interpreter::alloc` is rooted while it is allocated, so building a list from
+//!   data you already have is fine.
+//! - Anything else you allocated is only safe until your next allocation. Pass it to
+//!   `Interpreter::root` if you need it after that, like when building a list one item at a time.
+//! - Don't hold a `get_data_mut` borrow across an allocation. The collector has to read everything
+//!   reachable, and that panics if something is borrowed mutably.
+//! - Data you remove from rooted data, like popping an item off of a list, isn't rooted anymore.
+//!
+//! Run scripts with `--gc-stress` to check a native. Breaking these rules panics there instead of
+//! working until a collection happens at the wrong time.
+//!
+//! Return a `LispError` when one of its variants fits what went wrong, so the kind of error isn't
+//! lost in the message.
+
+
 use super::{
     Interpreter,
     Interner,
     NativeFn,
+    NativeFunc,
+    CallingNativeFn,
     NativeData,
     Data,
     DataRef,
+    LazySeq,
     ArgCount,
+    ConvertState,
+};
+use crate::{
+    capabilities::Capabilities,
+    error::{
+        LispError,
+        Signature,
+    },
 };
 
 
@@ -31,3 +61,9 @@ pub mod core;
 pub mod string;
 pub mod misc;
 pub mod io;
+pub mod seq;
+pub mod path;
+pub mod temp;
+pub mod term;
+pub mod func;
+pub mod thread;
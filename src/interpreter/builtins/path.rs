@@ -0,0 +1,165 @@
+//! Paths, using `std::path` so the platform's separators work. Everything but `absolute` only
+//! looks at the string, never at the filesystem. Where `std::path` has no answer, like the
+//! extension of a file without a dot, these return `none`.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use std::path::{
+    Component,
+    Path,
+    PathBuf,
+    absolute as std_absolute,
+};
+use super::{
+    Interpreter,
+    Interner,
+    Data,
+    DataRef,
+    NativeFn,
+    ArgCount,
+    LispError,
+    Signature,
+    Capabilities,
+};
+
+
+pub const BUILTINS: &[(&str, NativeFn, ArgCount)] = &[
+    builtin!(join, Any),
+    builtin!(parent, 1),
+    builtin!(filename, 1),
+    builtin!(stem, 1),
+    builtin!(extension, 1),
+    builtin!(absolute, 1),
+    builtin!(normalize, 1),
+    builtin!(relative, 2),
+];
+
+
+fn get_path(dr: &DataRef, op: &str)->Result<PathBuf> {
+    match &*dr.try_get_data(op)? {
+        Data::String(s)=>Ok(PathBuf::from(s)),
+        data=>bail!(LispError::Type{op: op.into(), expected: "string", actual: data.type_name()}),
+    }
+}
+
+fn alloc_path(i: &mut Interpreter, path: Option<&Path>)->DataRef {
+    match path {
+        Some(path)=>i.alloc(Data::String(path.to_string_lossy().into_owned())),
+        None=>i.alloc(Data::None),
+    }
+}
+
+/// Collapse `.` and `..` without looking at the filesystem. `..` past the root stays at the root,
+/// and leading `..`s of a relative path are kept.
+fn normalize_path(path: &Path)->PathBuf {
+    let mut out = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir=>{},
+            Component::ParentDir=>match out.last() {
+                Some(Component::Normal(_))=>{out.pop();},
+                Some(Component::RootDir|Component::Prefix(_))=>{},
+                Some(Component::ParentDir)|None=>out.push(component),
+                Some(Component::CurDir)=>unreachable!("`.` is never kept"),
+            },
+            _=>out.push(component),
+        }
+    }
+
+    if out.is_empty() {
+        return PathBuf::from(".");
+    }
+
+    return out.into_iter().collect();
+}
+
+
+/// `(join a b ...)`. A later absolute path replaces everything before it, like `Path::join`.
+pub fn join(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    if args.is_empty() {
+        bail!(LispError::Arity{name: Some("join".into()), expected: vec![Signature::at_least(1)], got: 0});
+    }
+
+    let mut out = PathBuf::new();
+    for arg in args.iter() {
+        out.push(get_path(arg, "join")?);
+    }
+
+    return Ok(alloc_path(i, Some(&out)));
+}
+
+/// The path without its last component, or `none` for a root or an empty path
+pub fn parent(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let path = get_path(&args[0], "parent")?;
+    Ok(alloc_path(i, path.parent()))
+}
+
+/// The last component, or `none` if it is `..` or there isn't one
+pub fn filename(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let path = get_path(&args[0], "filename")?;
+    Ok(alloc_path(i, path.file_name().map(Path::new)))
+}
+
+/// The file name without its extension
+pub fn stem(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let path = get_path(&args[0], "stem")?;
+    Ok(alloc_path(i, path.file_stem().map(Path::new)))
+}
+
+/// What comes after the last `.` of the file name. Dotfiles like `.bashrc` have none.
+pub fn extension(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let path = get_path(&args[0], "extension")?;
+    Ok(alloc_path(i, path.extension().map(Path::new)))
+}
+
+/// The path joined onto the current directory if it is relative. It doesn't have to exist, and
+/// `..` isn't collapsed.
+pub fn absolute(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::FS_READ)?;
+    let path = get_path(&args[0], "absolute")?;
+    let path = std_absolute(path).map_err(LispError::from)?;
+
+    return Ok(alloc_path(i, Some(&path)));
+}
+
+/// Collapse `.` and `..`. An empty path is `.`.
+pub fn normalize(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let path = get_path(&args[0], "normalize")?;
+    Ok(alloc_path(i, Some(&normalize_path(&path))))
+}
+
+/// `(relative base path)`: how to get to `path` from `base`, after normalizing both. `none` if
+/// there is no way without the filesystem, like from a relative base to an absolute path or from
+/// a base that starts with more `..`s than `path` does.
+pub fn relative(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let base = normalize_path(&get_path(&args[0], "relative")?);
+    let path = normalize_path(&get_path(&args[1], "relative")?);
+    if base.has_root() != path.has_root() {
+        return Ok(alloc_path(i, None));
+    }
+
+    let mut base = base.components().filter(|c|*c != Component::CurDir).peekable();
+    let mut path = path.components().filter(|c|*c != Component::CurDir).peekable();
+    while base.peek().is_some() && base.peek() == path.peek() {
+        base.next();
+        path.next();
+    }
+
+    let mut out = PathBuf::new();
+    for component in base {
+        match component {
+            Component::Normal(_)=>out.push(".."),
+            _=>return Ok(alloc_path(i, None)),
+        }
+    }
+    out.extend(path);
+
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+
+    return Ok(alloc_path(i, Some(&out)));
+}
@@ -0,0 +1,577 @@
+//! Sequences. Lists, strings, objects, chains of pairs, lazy seqs and generators are all walked
+//! the same way through `SeqIter`, so every native in here takes any of them. Walking a generator
+//! resumes it, so unlike the others it can only be walked once.
+//!
+//! These call back into lisp, so anything they hold across a call has to be rooted like it would
+//! be across an allocation. The arguments aren't rooted here unless the GC is stressed, so the
+//! natives root them first.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use rustc_hash::FxHashMap;
+use std::vec::IntoIter;
+use super::{
+    Interpreter,
+    Data,
+    DataRef,
+    LazySeq,
+    CallingNativeFn,
+    ArgCount,
+    ConvertState,
+    LispError,
+    Signature,
+};
+use crate::interpreter::ast::Ident;
+
+
+pub const BUILTINS: &[(&str, CallingNativeFn, ArgCount)] = &[
+    builtin!(range, Any),
+    builtin!(repeat, 1),
+    builtin!(iterate, 2),
+    builtin!(map, 2),
+    builtin!(filter, 2),
+    builtin!(take, 2),
+    builtin!(realize, 1),
+    builtin!(for_each, for, 2),
+    builtin!(next, 1),
+    builtin!(zip, Any),
+    builtin!(unzip, 1),
+    builtin!(interleave, Any),
+    builtin!(partition, 2),
+    builtin!(group_by, groupBy, 2),
+    builtin!(frequencies, 1),
+    builtin!(distinct, 1),
+];
+
+
+/// Keeps one value alive between calls into lisp. It is rooted for the rest of the native, but it
+/// only ever holds the latest value, so walking a long seq doesn't pile up roots.
+struct Slot(DataRef);
+impl Slot {
+    fn new(i: &mut Interpreter, value: DataRef)->Self {
+        let dr = i.alloc(Data::List(vec![value]));
+        i.root(&dr);
+        Slot(dr)
+    }
+
+    fn get(&self)->DataRef {
+        match &*self.0.get_data() {
+            Data::List(items)=>items[0].clone(),
+            _=>unreachable!("Slots always hold a list"),
+        }
+    }
+
+    fn set(&mut self, value: DataRef) {
+        *self.0.get_data_mut() = Data::List(vec![value]);
+    }
+}
+
+
+/// The different keys seen so far, in the order they were first seen. Keys are told apart by
+/// `structural_hash` and then `=`, so they can be lists and objects too, but not functions.
+struct Keys {
+    list: DataRef,
+    by_hash: FxHashMap<u64, Vec<usize>>,
+}
+impl Keys {
+    fn new(i: &mut Interpreter)->Self {
+        let list = i.alloc(Data::List(Vec::new()));
+        i.root(&list);
+        Keys {list, by_hash: FxHashMap::default()}
+    }
+
+    /// Where `key` is in the list, adding it to the end if it isn't there yet. True if it was
+    /// added. This doesn't allocate, so `key` doesn't have to be rooted.
+    fn find_or_add(&mut self, key: DataRef, op: &str)->Result<(usize, bool)> {
+        let hash = key.structural_hash(op)?;
+        let positions = self.by_hash.entry(hash).or_default();
+        let mut list = self.list.clone();
+        let Data::List(keys) = &mut *list.try_get_data_mut(op)? else {
+            unreachable!("Keys are always a list");
+        };
+
+        for &position in positions.iter() {
+            if keys[position] == key {
+                return Ok((position, false));
+            }
+        }
+        positions.push(keys.len());
+        keys.push(key);
+
+        return Ok((keys.len() - 1, true));
+    }
+}
+
+
+/// Walks any sequence one item at a time
+enum SeqIter {
+    Empty,
+    /// Reads the list as it goes, so pushing onto it while walking it is seen
+    List {
+        list: DataRef,
+        index: usize,
+    },
+    Chars(IntoIter<char>),
+    /// `(name value)` lists. Fields removed while walking are skipped.
+    Entries {
+        object: DataRef,
+        names: IntoIter<Ident>,
+    },
+    /// Whatever the chain ends in is walked after the cars, so a pair consed onto a list walks the
+    /// list too
+    Pairs(DataRef),
+    Range {
+        next: Option<i64>,
+        end: Option<i64>,
+        step: i64,
+    },
+    Repeat(DataRef),
+    Iterate {
+        f: DataRef,
+        current: Slot,
+        started: bool,
+    },
+    Map {
+        f: DataRef,
+        source: Box<Self>,
+    },
+    Filter {
+        pred: DataRef,
+        source: Box<Self>,
+    },
+    Generator(DataRef),
+}
+impl SeqIter {
+    /// `dr` has to stay rooted while this is used
+    fn new(dr: &DataRef, i: &mut Interpreter, op: &str)->Result<Self> {
+        let lazy = match &*dr.try_get_data(op)? {
+            Data::List(_)=>return Ok(Self::List{list: dr.clone(), index: 0}),
+            Data::String(s)=>return Ok(Self::Chars(s.chars().collect::<Vec<_>>().into_iter())),
+            Data::Object(fields)=>return Ok(Self::Entries {
+                object: dr.clone(),
+                names: fields.keys().copied().collect::<Vec<_>>().into_iter(),
+            }),
+            Data::Pair(..)=>return Ok(Self::Pairs(dr.clone())),
+            Data::Generator(_)=>return Ok(Self::Generator(dr.clone())),
+            Data::None=>return Ok(Self::Empty),
+            Data::Lazy(seq)=>seq.clone(),
+            data=>bail!(LispError::Type{op: op.into(), expected: "sequence", actual: data.type_name()}),
+        };
+
+        return Ok(match lazy {
+            LazySeq::Range{start, end, step}=>Self::Range{next: Some(start), end, step},
+            LazySeq::Repeat(item)=>Self::Repeat(item),
+            LazySeq::Iterate{f, seed}=>Self::Iterate{f, current: Slot::new(i, seed), started: false},
+            LazySeq::Map{f, source}=>Self::Map{f, source: Box::new(Self::new(&source, i, op)?)},
+            LazySeq::Filter{pred, source}=>Self::Filter{pred, source: Box::new(Self::new(&source, i, op)?)},
+        });
+    }
+
+    /// The next item, or `None` at the end. The item isn't rooted, so root it or hand it to lisp
+    /// before allocating again. Only lazy seqs and generators call into lisp.
+    fn next(&mut self, i: &mut Interpreter, state: &mut ConvertState, op: &str)->Result<Option<DataRef>> {
+        match self {
+            Self::Empty=>Ok(None),
+            Self::List{list, index}=>{
+                let item = match &*list.try_get_data(op)? {
+                    Data::List(items)=>items.get(*index).cloned(),
+                    data=>bail!(LispError::Type{op: op.into(), expected: "list", actual: data.type_name()}),
+                };
+                *index += 1;
+
+                Ok(item)
+            },
+            Self::Chars(chars)=>Ok(chars.next().map(|c|i.alloc(Data::Char(c)))),
+            Self::Entries{object, names}=>{
+                for name in names.by_ref() {
+                    let value = match &*object.try_get_data(op)? {
+                        Data::Object(fields)=>fields.get(&name).cloned(),
+                        _=>None,
+                    };
+                    if let Some(value) = value {
+                        let entry = vec![i.alloc(Data::Ident(name)), value];
+                        return Ok(Some(i.alloc(Data::List(entry))));
+                    }
+                }
+
+                Ok(None)
+            },
+            Self::Pairs(current)=>{
+                let next = match &*current.try_get_data(op)? {
+                    Data::Pair(car, cdr)=>Some((car.clone(), cdr.clone())),
+                    _=>None,
+                };
+                match next {
+                    Some((car, cdr))=>{
+                        *current = cdr;
+                        Ok(Some(car))
+                    },
+                    None=>{
+                        // pairs can't be changed, so the tail is still reachable from the head
+                        let tail = current.clone();
+                        *self = Self::new(&tail, i, op)?;
+                        self.next(i, state, op)
+                    },
+                }
+            },
+            Self::Range{next, end, step}=>{
+                let Some(n) = *next else {return Ok(None)};
+                let done = match *end {
+                    Some(end)=>(*step > 0 && n >= end) || (*step < 0 && n <= end),
+                    None=>false,
+                };
+                if done {return Ok(None)}
+                *next = n.checked_add(*step);
+
+                Ok(Some(i.alloc(Data::Number(n))))
+            },
+            Self::Repeat(item)=>Ok(Some(item.clone())),
+            Self::Iterate{f, current, started}=>{
+                if *started {
+                    let value = i.call(state, f.clone(), vec![current.get()])?;
+                    current.set(value);
+                }
+                *started = true;
+
+                Ok(Some(current.get()))
+            },
+            Self::Map{f, source}=>match source.next(i, state, op)? {
+                Some(item)=>Ok(Some(i.call(state, f.clone(), vec![item])?)),
+                None=>Ok(None),
+            },
+            Self::Filter{pred, source}=>{
+                while let Some(item) = source.next(i, state, op)? {
+                    let keep = i.call(state, pred.clone(), vec![item.clone()])?;
+                    if i.is_truthy(op, &keep)? {
+                        return Ok(Some(item));
+                    }
+                }
+
+                Ok(None)
+            },
+            Self::Generator(gen)=>i.resume_generator(state, gen),
+        }
+    }
+}
+
+
+/// The arguments aren't rooted while a native calls into lisp, so natives that call back into it
+/// root them first
+fn root_args(args: &[DataRef], i: &mut Interpreter) {
+    for arg in args {
+        i.root(arg);
+    }
+}
+
+/// Lazy seqs check what they are given when they are made, so a bad one fails where it was made
+/// instead of where it was walked
+fn check_seq(dr: &DataRef, op: &str)->Result<()> {
+    match &*dr.try_get_data(op)? {
+        Data::List(_)|Data::String(_)|Data::Object(_)|Data::Pair(..)|Data::None|Data::Lazy(_)|Data::Generator(_)=>Ok(()),
+        data=>bail!(LispError::Type{op: op.into(), expected: "sequence", actual: data.type_name()}),
+    }
+}
+
+fn get_number(dr: &DataRef, op: &str)->Result<i64> {
+    match &*dr.try_get_data(op)? {
+        Data::Number(n)=>Ok(*n),
+        data=>bail!(LispError::Type{op: op.into(), expected: "number", actual: data.type_name()}),
+    }
+}
+
+/// Walk `seq`, pushing up to `limit` items onto a new list
+fn collect(seq: &DataRef, limit: Option<usize>, i: &mut Interpreter, state: &mut ConvertState, op: &str)->Result<DataRef> {
+    let mut iter = SeqIter::new(seq, i, op)?;
+    let mut out = i.alloc(Data::List(Vec::new()));
+    i.root(&out);
+
+    let mut count = 0;
+    while limit.is_none_or(|limit|count < limit) {
+        let Some(item) = iter.next(i, state, op)? else {break};
+        match &mut *out.get_data_mut() {
+            Data::List(items)=>items.push(item),
+            _=>unreachable!(),
+        }
+        count += 1;
+    }
+
+    return Ok(out);
+}
+
+fn push(list: &DataRef, item: DataRef) {
+    match &mut *list.clone().get_data_mut() {
+        Data::List(items)=>items.push(item),
+        _=>unreachable!(),
+    }
+}
+
+fn list_items(list: &DataRef)->Vec<DataRef> {
+    match &*list.get_data() {
+        Data::List(items)=>items.clone(),
+        _=>unreachable!(),
+    }
+}
+
+/// A new rooted list
+fn rooted_list(i: &mut Interpreter)->DataRef {
+    let list = i.alloc(Data::List(Vec::new()));
+    i.root(&list);
+    list
+}
+
+/// Walk `seqs` together, calling `round` with a list of one item from each. Stops when any of
+/// them runs out, so a round is only ever full.
+fn walk_together(
+    seqs: &[DataRef],
+    i: &mut Interpreter,
+    state: &mut ConvertState,
+    op: &str,
+    mut round: impl FnMut(DataRef),
+)->Result<()> {
+    if seqs.is_empty() {
+        return Ok(());
+    }
+    let mut iters = seqs.iter()
+        .map(|seq|SeqIter::new(seq, i, op))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut row = Slot::new(i, seqs[0].clone());
+    loop {
+        row.set(i.alloc(Data::List(Vec::new())));
+        for iter in iters.iter_mut() {
+            let Some(item) = iter.next(i, state, op)? else {
+                return Ok(());
+            };
+            push(&row.get(), item);
+        }
+        round(row.get());
+    }
+}
+
+/// `(range)` counts up from 0 forever, `(range end)` from 0 to `end`, `(range start end)` and
+/// `(range start end step)`. `end` is never included.
+pub fn range(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    let numbers = args.iter()
+        .map(|arg|get_number(arg, "range"))
+        .collect::<Result<Vec<_>>>()?;
+    let (start, end, step) = match numbers.as_slice() {
+        []=>(0, None, 1),
+        [end]=>(0, Some(*end), 1),
+        [start, end]=>(*start, Some(*end), 1),
+        [_, _, 0]=>bail!(LispError::OutOfRange{op: "range".into(), expected: "a step other than 0", actual: "0".into()}),
+        [start, end, step]=>(*start, Some(*end), *step),
+        _=>bail!(LispError::Arity{
+            name: Some("range".into()),
+            expected: (0..=3).map(Signature::exact).collect(),
+            got: args.len(),
+        }),
+    };
+
+    return Ok(i.alloc(Data::Lazy(LazySeq::Range{start, end, step})));
+}
+
+/// The same item forever
+pub fn repeat(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    Ok(i.alloc(Data::Lazy(LazySeq::Repeat(args[0].clone()))))
+}
+
+/// `(iterate f x)` is `x`, `(f x)`, `(f (f x))` and so on forever
+pub fn iterate(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    Ok(i.alloc(Data::Lazy(LazySeq::Iterate{f: args[0].clone(), seed: args[1].clone()})))
+}
+
+/// `(map f seq)`. `f` isn't called until the result is walked.
+pub fn map(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    check_seq(&args[1], "map")?;
+    Ok(i.alloc(Data::Lazy(LazySeq::Map{f: args[0].clone(), source: args[1].clone()})))
+}
+
+/// `(filter pred seq)`. `pred` isn't called until the result is walked.
+pub fn filter(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    check_seq(&args[1], "filter")?;
+    Ok(i.alloc(Data::Lazy(LazySeq::Filter{pred: args[0].clone(), source: args[1].clone()})))
+}
+
+/// `(take n seq)`. A list of the first `n` items, only making as many as it needs.
+pub fn take(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let n = match get_number(&args[0], "take")? {
+        n if n < 0=>bail!(LispError::OutOfRange{op: "take".into(), expected: "a count of at least 0", actual: n.to_string()}),
+        n=>n as usize,
+    };
+
+    return collect(&args[1], Some(n), i, state, "take");
+}
+
+/// A list of every item. Never returns for an infinite seq.
+pub fn realize(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    return collect(&args[0], None, i, state, "realize");
+}
+
+/// `(for seq f)` calls `f` with each item
+pub fn for_each(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let mut iter = SeqIter::new(&args[0], i, "for")?;
+    while let Some(item) = iter.next(i, state, "for")? {
+        i.call(state, args[1].clone(), vec![item])?;
+    }
+
+    return Ok(i.alloc(Data::None));
+}
+
+/// `(next gen)` runs `gen` until it yields and returns what it yielded, or `none` once it is done
+pub fn next(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    match i.resume_generator(state, &args[0])? {
+        Some(value)=>Ok(value),
+        None=>Ok(i.alloc(Data::None)),
+    }
+}
+
+/// `(zip a b ...)` is a list of lists, the first holding the first item of each seq and so on. It
+/// is as long as the shortest seq.
+pub fn zip(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let out = rooted_list(i);
+    walk_together(&args, i, state, "zip", |row|push(&out, row))?;
+
+    return Ok(out);
+}
+
+/// `(unzip rows)` turns a seq of lists back into the lists `zip` was given. Rows longer than the
+/// shortest one have their extra items left out.
+pub fn unzip(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let rows = list_items(&collect(&args[0], None, i, state, "unzip")?);
+
+    let mut columns = Vec::new();
+    for row in rows.iter() {
+        match &*row.try_get_data("unzip")? {
+            Data::List(items)=>columns.push(items.len()),
+            data=>bail!(LispError::Type{op: "unzip".into(), expected: "list", actual: data.type_name()}),
+        }
+    }
+    let width = columns.into_iter().min().unwrap_or(0);
+
+    let out = rooted_list(i);
+    for column in 0..width {
+        let items = rows.iter()
+            .map(|row|match &*row.get_data() {
+                Data::List(items)=>items[column].clone(),
+                _=>unreachable!(),
+            })
+            .collect();
+        let column = i.alloc(Data::List(items));
+        push(&out, column);
+    }
+
+    return Ok(out);
+}
+
+/// `(interleave a b ...)` is the first item of each seq, then the second of each, and so on until
+/// the shortest runs out
+pub fn interleave(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let out = rooted_list(i);
+    walk_together(&args, i, state, "interleave", |row|{
+        for item in list_items(&row) {
+            push(&out, item);
+        }
+    })?;
+
+    return Ok(out);
+}
+
+/// `(partition pred seq)` is a list of two lists: the items `pred` is true for, and the rest
+pub fn partition(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let matches = rooted_list(i);
+    let rest = rooted_list(i);
+
+    let mut iter = SeqIter::new(&args[1], i, "partition")?;
+    let mut current = Slot::new(i, args[1].clone());
+    while let Some(item) = iter.next(i, state, "partition")? {
+        current.set(item.clone());
+        let keep = i.call(state, args[0].clone(), vec![item.clone()])?;
+        if i.is_truthy("partition", &keep)? {
+            push(&matches, item);
+        } else {
+            push(&rest, item);
+        }
+    }
+
+    return Ok(i.alloc(Data::List(vec![matches, rest])));
+}
+
+/// `(groupBy f seq)` is a list of `(key items)` lists, where `items` are the items `f` returned
+/// `key` for. Keys are in the order they were first returned, and can be anything `core/hash` can
+/// hash.
+pub fn group_by(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let mut keys = Keys::new(i);
+    let groups = rooted_list(i);
+
+    let mut iter = SeqIter::new(&args[1], i, "groupBy")?;
+    let mut current = Slot::new(i, args[1].clone());
+    while let Some(item) = iter.next(i, state, "groupBy")? {
+        current.set(item.clone());
+        let key = i.call(state, args[0].clone(), vec![item.clone()])?;
+        match keys.find_or_add(key, "groupBy")? {
+            (_, true)=>{
+                let group = i.alloc(Data::List(vec![item]));
+                push(&groups, group);
+            },
+            (position, false)=>push(&list_items(&groups)[position], item),
+        }
+    }
+
+    let out = rooted_list(i);
+    for (key, group) in list_items(&keys.list).into_iter().zip(list_items(&groups)) {
+        let entry = i.alloc(Data::List(vec![key, group]));
+        push(&out, entry);
+    }
+
+    return Ok(out);
+}
+
+/// `(frequencies seq)` is a list of `(item count)` lists, with the items in the order they first
+/// appear
+pub fn frequencies(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let mut keys = Keys::new(i);
+    let mut counts = Vec::new();
+
+    let mut iter = SeqIter::new(&args[0], i, "frequencies")?;
+    while let Some(item) = iter.next(i, state, "frequencies")? {
+        match keys.find_or_add(item, "frequencies")? {
+            (_, true)=>counts.push(1),
+            (position, false)=>counts[position] += 1,
+        }
+    }
+
+    let out = rooted_list(i);
+    for (item, count) in list_items(&keys.list).into_iter().zip(counts) {
+        let count = i.alloc(Data::Number(count));
+        let entry = i.alloc(Data::List(vec![item, count]));
+        push(&out, entry);
+    }
+
+    return Ok(out);
+}
+
+/// `(distinct seq)` is a list of the items without the ones `=` to an earlier item
+pub fn distinct(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    root_args(&args, i);
+    let mut keys = Keys::new(i);
+
+    let mut iter = SeqIter::new(&args[0], i, "distinct")?;
+    while let Some(item) = iter.next(i, state, "distinct")? {
+        keys.find_or_add(item, "distinct")?;
+    }
+
+    return Ok(keys.list);
+}
@@ -3,71 +3,150 @@ use anyhow::{
     bail,
 };
 use std::fmt::Write;
+use serde_json::{
+    Map,
+    Number,
+    Value,
+};
 use super::{
     Interpreter,
     Interner,
     Data,
     DataRef,
     NativeFn,
+    NativeData,
     ArgCount,
-    // DEBUG,
+    LispError,
+    Signature,
+};
+use crate::{
+    interpreter::data::pair_chain,
+    numeric::FloatDisplay,
 };
 
 
+/// Keeps a typo like `(formatFloat x 1000000)` from making a huge string
+const MAX_FLOAT_DIGITS: i64 = 64;
+
+
 pub const BUILTINS: &[(&str, NativeFn, ArgCount)] = &[
     builtin!(format, Any),
     builtin!(debug_format, debugFormat, Any),
     builtin!(split, 2),
     builtin!(chars, 1),
+    builtin!(from_chars, fromChars, 1),
+    builtin!(length, 1),
+    builtin!(byte_length, byteLength, 1),
+    builtin!(char_at, charAt, 2),
+    builtin!(substring, 3),
+    builtin!(join, 2),
+    builtin!(builder, 0),
+    builtin!(push, Any),
+    builtin!(build, 1),
+    builtin!(format_float, formatFloat, 2),
+    ("json-write", json_write, ArgCount::Any),
 ];
 
 
 pub fn format(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     let mut fmt = String::new();
     for arg in args {
-        format_data(&mut fmt, &arg.get_data());
+        format_data(&mut fmt, &arg, &mut Vec::new())?;
     }
 
     return Ok(i.alloc(Data::String(fmt.into())));
 }
 
-pub fn format_data(fmt: &mut String, data: &Data) {
-    match data {
+/// Returns true if `dr` is one of the lists we are printing the inside of. Print a marker instead,
+/// since it would print forever.
+fn is_cycle(fmt: &mut String, dr: &DataRef, parents: &[DataRef])->bool {
+    if parents.iter().any(|p|p.is_same(dr)) {
+        write!(fmt, "#cycle->list").unwrap();
+        return true;
+    }
+
+    return false;
+}
+
+/// `parents` are the lists `dr` is inside of
+pub fn format_data(fmt: &mut String, dr: &DataRef, parents: &mut Vec<DataRef>)->Result<()> {
+    if is_cycle(fmt, dr, parents) {return Ok(())}
+
+    match &*dr.try_get_data("format")? {
         Data::Char(c)=>write!(fmt, "\\{c}").unwrap(),
         Data::List(items)=>{
+            parents.push(dr.clone());
             write!(fmt, "(").unwrap();
-            for (i, data) in items.into_iter().enumerate() {
+            for (i, item) in items.into_iter().enumerate() {
                 if i > 0 {write!(fmt, " ").unwrap()}
-                format_data(fmt, &data.get_data());
+                format_data(fmt, item, parents)?;
             }
             write!(fmt, ")").unwrap();
+            parents.pop();
         },
+        Data::Pair(..)=>format_pair(fmt, dr, parents, format_data)?,
 
         Data::String(s)=>write!(fmt, "{s}").unwrap(),
         Data::Number(n)=>write!(fmt, "{n}").unwrap(),
-        Data::Float(f)=>write!(fmt, "{f}").unwrap(),
+        Data::Float(f)=>write!(fmt, "{}", FloatDisplay(*f)).unwrap(),
         Data::Bool(b)=>write!(fmt, "{b}").unwrap(),
 
         Data::Fn(_)|Data::Closure{..}=>write!(fmt, "<fn>").unwrap(),
+        Data::Lazy(_)=>write!(fmt, "<lazySeq>").unwrap(),
+        Data::Generator(_)=>write!(fmt, "<generator>").unwrap(),
+        Data::Cell(inner)=>format_data(fmt, inner, parents)?,
         Data::NativeFn(name, _, _)=>write!(fmt, "<nativeFn: {name}>").unwrap(),
         Data::None=>write!(fmt, "None").unwrap(),
+        Data::NativeData(NativeData::Custom(host))=>write!(fmt, "<native: {}>", host.type_name()).unwrap(),
+        Data::NativeData(NativeData::Channel(_))=>write!(fmt, "<channel>").unwrap(),
+        Data::NativeData(NativeData::Thread(_))=>write!(fmt, "<thread>").unwrap(),
         Data::NativeData(_)=>write!(fmt, "<nativeData>").unwrap(),
         Data::Object(_)=>write!(fmt, "<object>").unwrap(),
         Data::Ident(_)=>write!(fmt, "<ident>").unwrap(),
     }
+
+    return Ok(());
+}
+
+/// A chain of pairs ending in `None` prints like a list, anything else at the end goes after a `.`
+fn format_pair(
+    fmt: &mut String,
+    dr: &DataRef,
+    parents: &mut Vec<DataRef>,
+    format_item: fn(&mut String, &DataRef, &mut Vec<DataRef>)->Result<()>,
+)->Result<()> {
+    let (items, tail) = pair_chain(dr);
+
+    parents.push(dr.clone());
+    write!(fmt, "(").unwrap();
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {write!(fmt, " ").unwrap()}
+        format_item(fmt, item, parents)?;
+    }
+    if !matches!(*tail.try_get_data("format")?, Data::None) {
+        write!(fmt, " . ").unwrap();
+        format_item(fmt, &tail, parents)?;
+    }
+    write!(fmt, ")").unwrap();
+    parents.pop();
+
+    return Ok(());
 }
 
 pub fn debug_format(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     let mut fmt = String::new();
     for arg in args {
-        debug_format_data(&mut fmt, &arg.get_data());
+        debug_format_data(&mut fmt, &arg, &mut Vec::new())?;
     }
 
     return Ok(i.alloc(Data::String(fmt.into())));
 }
 
-pub fn debug_format_data(fmt: &mut String, data: &Data) {
-    match data {
+/// `parents` are the lists `dr` is inside of
+pub fn debug_format_data(fmt: &mut String, dr: &DataRef, parents: &mut Vec<DataRef>)->Result<()> {
+    if is_cycle(fmt, dr, parents) {return Ok(())}
+
+    match &*dr.try_get_data("debugFormat")? {
         Data::Char(c)=>match c {
             ' '=>write!(fmt, "\\space").unwrap(),
             '\n'=>write!(fmt, "\\newline").unwrap(),
@@ -75,69 +154,358 @@ pub fn debug_format_data(fmt: &mut String, data: &Data) {
             c=>write!(fmt, "\\{c}").unwrap(),
         },
         Data::List(items)=>{
+            parents.push(dr.clone());
             write!(fmt, "(").unwrap();
-            for (i, data) in items.into_iter().enumerate() {
+            for (i, item) in items.into_iter().enumerate() {
                 if i > 0 {write!(fmt, " ").unwrap()}
-                format_data(fmt, &data.get_data());
+                format_data(fmt, item, parents)?;
             }
             write!(fmt, ")").unwrap();
+            parents.pop();
         },
+        Data::Pair(..)=>format_pair(fmt, dr, parents, debug_format_data)?,
 
         Data::String(s)=>write!(fmt, "{s}").unwrap(),
         Data::Number(n)=>write!(fmt, "{n}").unwrap(),
-        Data::Float(f)=>write!(fmt, "{f}").unwrap(),
+        Data::Float(f)=>write!(fmt, "{}", FloatDisplay(*f)).unwrap(),
         Data::Bool(b)=>write!(fmt, "{b}").unwrap(),
 
         Data::Fn(_)|Data::Closure{..}=>write!(fmt, "<fn>").unwrap(),
+        Data::Lazy(_)=>write!(fmt, "<lazySeq>").unwrap(),
+        Data::Generator(_)=>write!(fmt, "<generator>").unwrap(),
+        Data::Cell(inner)=>debug_format_data(fmt, inner, parents)?,
         Data::NativeFn(name, _, _)=>write!(fmt, "<nativeFn: {name}>").unwrap(),
         Data::None=>write!(fmt, "None").unwrap(),
+        Data::NativeData(NativeData::Custom(host))=>write!(fmt, "<native: {}>", host.type_name()).unwrap(),
+        Data::NativeData(NativeData::Channel(_))=>write!(fmt, "<channel>").unwrap(),
+        Data::NativeData(NativeData::Thread(_))=>write!(fmt, "<thread>").unwrap(),
         Data::NativeData(_)=>write!(fmt, "<nativeData>").unwrap(),
         Data::Object(_)=>write!(fmt, "<object>").unwrap(),
         Data::Ident(_)=>write!(fmt, "<ident>").unwrap(),
     }
+
+    return Ok(());
 }
 
 pub fn chars(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     if args.len() != 1 {
-        bail!("`chars` can only take one argument");
+        bail!(LispError::Arity{name: Some("chars".into()), expected: vec![Signature::exact(1)], got: args.len()});
     }
     let data = &args[0];
-    let data_ref = data.get_data();
+    let data_ref = data.try_get_data("chars")?;
     match &*data_ref {
         Data::String(s)=>{
             let chars = s.chars()
-                .map(|c|i.alloc(Data::Char(c)))
+                .map(|c|{
+                    let dr = i.alloc(Data::Char(c));
+                    i.root(&dr);
+                    dr
+                })
                 .collect::<Vec<_>>();
 
             return Ok(i.alloc(Data::List(chars)));
         },
-        _=>bail!("`chars` can only accept Strings"),
+        data=>bail!(LispError::Type{op: "chars".into(), expected: "string", actual: data.type_name()}),
     }
 }
 
 pub fn split(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
     if args.len() != 2 {
-        bail!("`split` can only take two arguments");
+        bail!(LispError::Arity{name: Some("split".into()), expected: vec![Signature::exact(2)], got: args.len()});
     }
     let data = &args[0];
-    let data_ref = data.get_data();
+    let data_ref = data.try_get_data("split")?;
     let split_thing = &args[1];
-    let split_thing_ref = split_thing.get_data();
+    let split_thing_ref = split_thing.try_get_data("split")?;
     match &*data_ref {
         Data::String(s)=>{
-            let chars;
-            match &*split_thing_ref {
-                Data::String(s2)=>chars = s.split(s2.as_str())
-                    .map(|s|i.alloc(Data::String(s.to_string())))
-                    .collect::<Vec<_>>(),
-                Data::Char(c)=>chars = s.split(*c)
-                    .map(|s|i.alloc(Data::String(s.to_string())))
-                    .collect::<Vec<_>>(),
-                _=>bail!("`split` can only accept String or Char as the second argument"),
-            }
+            let parts = match &*split_thing_ref {
+                Data::String(s2)=>s.split(s2.as_str()).collect::<Vec<_>>(),
+                Data::Char(c)=>s.split(*c).collect::<Vec<_>>(),
+                data=>bail!(LispError::Type{op: "split".into(), expected: "string or char", actual: data.type_name()}),
+            };
+            let chars = parts.into_iter()
+                .map(|s|{
+                    let dr = i.alloc(Data::String(s.to_string()));
+                    i.root(&dr);
+                    dr
+                })
+                .collect::<Vec<_>>();
 
             return Ok(i.alloc(Data::List(chars)));
         },
-        _=>bail!("`split` can only accept Strings"),
+        data=>bail!(LispError::Type{op: "split".into(), expected: "string", actual: data.type_name()}),
+    }
+}
+
+/// Indices into strings count chars (code points), not bytes or graphemes, so they never land in
+/// the middle of a char. Finding one walks the string from the start, so each call is O(index).
+/// Taking every char of a long string with `charAt` or `substring` in a loop is quadratic, use
+/// `chars` for that.
+///
+/// `None` if `index` is past the end. The end itself is a valid offset.
+fn byte_offset(s: &str, op: &str, index: i64)->Result<Option<usize>> {
+    if index < 0 {
+        bail!(LispError::IndexOutOfBounds{op: op.into(), index, len: s.chars().count()});
+    }
+
+    return Ok(s.char_indices()
+        .map(|(offset, _)|offset)
+        .chain([s.len()])
+        .nth(index as usize));
+}
+
+fn string_arg<'a>(data: &'a Data, op: &str)->Result<&'a str> {
+    match data {
+        Data::String(s)=>Ok(s),
+        data=>bail!(LispError::Type{op: op.into(), expected: "string", actual: data.type_name()}),
+    }
+}
+
+fn index_arg(dr: &DataRef, op: &str)->Result<i64> {
+    match &*dr.try_get_data(op)? {
+        Data::Number(n)=>Ok(*n),
+        data=>bail!(LispError::Type{op: op.into(), expected: "number", actual: data.type_name()}),
+    }
+}
+
+/// How many chars are in the string
+pub fn length(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let count = string_arg(&*args[0].try_get_data("length")?, "length")?.chars().count();
+    return Ok(i.alloc(Data::Number(count as i64)));
+}
+
+/// How many bytes the string takes up as UTF-8
+pub fn byte_length(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let len = string_arg(&*args[0].try_get_data("byteLength")?, "byteLength")?.len();
+    return Ok(i.alloc(Data::Number(len as i64)));
+}
+
+/// The char at a char index, or `None` past the end
+pub fn char_at(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let index = index_arg(&args[1], "charAt")?;
+    let data = args[0].try_get_data("charAt")?;
+    let s = string_arg(&data, "charAt")?;
+    let c = byte_offset(s, "charAt", index)?
+        .and_then(|offset|s[offset..].chars().next());
+    drop(data);
+
+    return Ok(i.alloc(c.map(Data::Char).unwrap_or(Data::None)));
+}
+
+/// `(substring s start end)`: the chars from `start` up to but not including `end`
+pub fn substring(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let start = index_arg(&args[1], "substring")?;
+    let end = index_arg(&args[2], "substring")?;
+    let data = args[0].try_get_data("substring")?;
+    let s = string_arg(&data, "substring")?;
+
+    let out_of_bounds = |index|LispError::IndexOutOfBounds{op: "substring".into(), index, len: s.chars().count()};
+    let Some(start_offset) = byte_offset(s, "substring", start)? else {
+        bail!(out_of_bounds(start));
+    };
+    let Some(end_offset) = byte_offset(s, "substring", end)? else {
+        bail!(out_of_bounds(end));
+    };
+    if end < start {
+        bail!(out_of_bounds(end));
+    }
+    let sub = s[start_offset..end_offset].to_string();
+    drop(data);
+
+    return Ok(i.alloc(Data::String(sub)));
+}
+/// The opposite of `chars`: a string from a list of chars
+pub fn from_chars(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let mut out = String::new();
+    match &*args[0].try_get_data("fromChars")? {
+        Data::List(items)=>for item in items {
+            match &*item.try_get_data("fromChars")? {
+                Data::Char(c)=>out.push(*c),
+                data=>bail!(LispError::Type{op: "fromChars".into(), expected: "char", actual: data.type_name()}),
+            }
+        },
+        data=>bail!(LispError::Type{op: "fromChars".into(), expected: "list", actual: data.type_name()}),
+    }
+
+    return Ok(i.alloc(Data::String(out)));
+}
+
+/// Append `dr` like `+=` would for strings and chars, and like `format` for anything else
+fn push_data(out: &mut String, dr: &DataRef)->Result<()> {
+    match &*dr.try_get_data("push")? {
+        Data::String(s)=>out.push_str(s),
+        Data::Char(c)=>out.push(*c),
+        _=>format_data(out, dr, &mut Vec::new())?,
+    }
+
+    return Ok(());
+}
+
+/// `(join sep list)`: every item appended like `push` does, with `sep` between them
+pub fn join(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let sep = match &*args[0].try_get_data("join")? {
+        Data::String(s)=>s.clone(),
+        Data::Char(c)=>c.to_string(),
+        data=>bail!(LispError::Type{op: "join".into(), expected: "string or char", actual: data.type_name()}),
+    };
+    let list = args[1].try_get_data("join")?;
+    let Data::List(items) = &*list else {
+        bail!(LispError::Type{op: "join".into(), expected: "list", actual: list.type_name()});
+    };
+
+    // most joins are of strings, so this is usually the exact size
+    let mut size = sep.len() * items.len().saturating_sub(1);
+    for item in items {
+        if let Data::String(s) = &*item.try_get_data("join")? {
+            size += s.len();
+        }
+    }
+
+    let mut out = String::with_capacity(size);
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {out.push_str(&sep)}
+        push_data(&mut out, item)?;
+    }
+    drop(list);
+
+    return Ok(i.alloc(Data::String(out)));
+}
+
+/// An empty string builder. `push` appends to it in place, and `build` gets the string out.
+pub fn builder(_args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    Ok(i.alloc(Data::NativeData(NativeData::StringBuilder(String::new()))))
+}
+
+/// `(push builder items...)`: format each item onto the end of the builder. Returns the builder.
+pub fn push(args: Vec<DataRef>, _: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let Some((builder, items)) = args.split_first() else {
+        bail!(LispError::Arity{name: Some("push".into()), expected: vec![Signature::at_least(1)], got: 0});
+    };
+
+    let mut builder = builder.clone();
+    let mut data = builder.try_get_data_mut("push")?;
+    let Data::NativeData(NativeData::StringBuilder(out)) = &mut *data else {
+        bail!(LispError::Type{op: "push".into(), expected: "stringBuilder", actual: data.type_name()});
+    };
+    for item in items {
+        push_data(out, item)?;
+    }
+    drop(data);
+
+    return Ok(builder);
+}
+
+/// What the builder holds so far, as a new string. The builder can keep going after this.
+pub fn build(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let s = match &*args[0].try_get_data("build")? {
+        Data::NativeData(NativeData::StringBuilder(s))=>s.clone(),
+        data=>bail!(LispError::Type{op: "build".into(), expected: "stringBuilder", actual: data.type_name()}),
+    };
+
+    return Ok(i.alloc(Data::String(s)));
+}
+
+/// `(formatFloat x digits)`: `x` with exactly `digits` digits after the `.`, rounded. Numbers are
+/// formatted as floats, and `#inf`, `#-inf` and `#nan` print like they always do.
+pub fn format_float(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let f = match &*args[0].try_get_data("formatFloat")? {
+        Data::Float(f)=>*f,
+        Data::Number(n)=>*n as f64,
+        data=>bail!(LispError::Type{op: "formatFloat".into(), expected: "float or number", actual: data.type_name()}),
+    };
+    let digits = match &*args[1].try_get_data("formatFloat")? {
+        Data::Number(n @ 0..=MAX_FLOAT_DIGITS)=>*n as usize,
+        Data::Number(n)=>bail!("`formatFloat` takes 0 to {MAX_FLOAT_DIGITS} digits, but got {n}"),
+        data=>bail!(LispError::Type{op: "formatFloat".into(), expected: "number", actual: data.type_name()}),
+    };
+
+    let out = match f.is_finite() {
+        true=>format!("{f:.digits$}"),
+        false=>FloatDisplay(f).to_string(),
+    };
+
+    return Ok(i.alloc(Data::String(out)));
+}
+
+/// `(json-write x)`: `x` as a JSON string. Lists and pair chains are arrays, objects are objects,
+/// and `None` is `null`. JSON has no NaN or infinity, so writing one is an error unless the
+/// second argument is `.null`, which writes them as `null` instead. `.error` is the default.
+pub fn json_write(args: Vec<DataRef>, i: &mut Interpreter, interner: &mut Interner)->Result<DataRef> {
+    let null_floats = match args.as_slice() {
+        [_]=>false,
+        [_, option]=>match &*option.try_get_data("json-write")? {
+            Data::Ident(name) if interner.get(*name) == "null"=>true,
+            Data::Ident(name) if interner.get(*name) == "error"=>false,
+            Data::Ident(name)=>bail!("`json-write` takes .null or .error, but got .{}", interner.get(*name)),
+            data=>bail!(LispError::Type{op: "json-write".into(), expected: "ident", actual: data.type_name()}),
+        },
+        _=>bail!(LispError::Arity{
+            name: Some("json-write".into()),
+            expected: vec![Signature::exact(1), Signature::exact(2)],
+            got: args.len(),
+        }),
+    };
+
+    let json = json_value(&args[0], null_floats, interner, &mut Vec::new())?;
+
+    return Ok(i.alloc(Data::String(json.to_string())));
+}
+
+/// `parents` are the lists and objects `dr` is inside of
+fn json_value(dr: &DataRef, null_floats: bool, interner: &Interner, parents: &mut Vec<DataRef>)->Result<Value> {
+    if parents.iter().any(|p|p.is_same(dr)) {
+        bail!("`json-write` can't write data that contains itself");
     }
+
+    let value = match &*dr.try_get_data("json-write")? {
+        Data::None=>Value::Null,
+        Data::Bool(b)=>Value::Bool(*b),
+        Data::Number(n)=>Value::from(*n),
+        Data::Float(f)=>match Number::from_f64(*f) {
+            Some(n)=>Value::Number(n),
+            None if null_floats=>Value::Null,
+            None=>bail!("JSON has no `{}`. Pass .null to `json-write` to write it as null", FloatDisplay(*f)),
+        },
+        Data::String(s)=>Value::String(s.clone()),
+        Data::Char(c)=>Value::String(c.to_string()),
+        Data::Ident(name)=>Value::String(interner.get(*name).to_string()),
+        Data::Cell(inner)=>json_value(inner, null_floats, interner, parents)?,
+        Data::List(items)=>{
+            parents.push(dr.clone());
+            let items = items.iter()
+                .map(|item|json_value(item, null_floats, interner, parents))
+                .collect::<Result<_>>()?;
+            parents.pop();
+
+            Value::Array(items)
+        },
+        Data::Pair(..)=>{
+            let (items, tail) = pair_chain(dr);
+            if !matches!(*tail.try_get_data("json-write")?, Data::None) {
+                bail!("`json-write` can only write pairs that end in `None`");
+            }
+
+            parents.push(dr.clone());
+            let items = items.iter()
+                .map(|item|json_value(item, null_floats, interner, parents))
+                .collect::<Result<_>>()?;
+            parents.pop();
+
+            Value::Array(items)
+        },
+        Data::Object(fields)=>{
+            parents.push(dr.clone());
+            let mut map = Map::new();
+            for (name, field) in fields {
+                map.insert(interner.get(*name).to_string(), json_value(field, null_floats, interner, parents)?);
+            }
+            parents.pop();
+
+            Value::Object(map)
+        },
+        data=>bail!(LispError::Type{op: "json-write".into(), expected: "data JSON can hold", actual: data.type_name()}),
+    };
+
+    return Ok(value);
 }
@@ -0,0 +1,142 @@
+//! Scratch files and directories in the system's temp directory. Names are made unique by
+//! creating them with create-new semantics and trying another name if one is taken, so two
+//! programs can't end up with the same one.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use std::{
+    fs::{
+        OpenOptions,
+        create_dir,
+        remove_dir_all,
+    },
+    io::{
+        ErrorKind,
+        Result as IoResult,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+    env::temp_dir,
+    process,
+};
+use super::{
+    Interpreter,
+    Data,
+    DataRef,
+    CallingNativeFn,
+    ArgCount,
+    ConvertState,
+    LispError,
+    Capabilities,
+};
+
+
+/// How many names to try before giving up. Only reached if something else is making the same
+/// names as fast as we are.
+const MAX_ATTEMPTS: u32 = 100;
+
+/// Makes each name we try different, even within the same nanosecond
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+
+pub const BUILTINS: &[(&str, CallingNativeFn, ArgCount)] = &[
+    builtin!(dir, 0),
+    builtin!(make_file, makeFile, 1),
+    builtin!(make_dir, makeDir, 1),
+    builtin!(with_dir, withDir, 1),
+];
+
+
+fn get_prefix(dr: &DataRef, op: &str)->Result<String> {
+    let prefix = match &*dr.try_get_data(op)? {
+        Data::String(s)=>s.clone(),
+        data=>bail!(LispError::Type{op: op.into(), expected: "string", actual: data.type_name()}),
+    };
+    if prefix.chars().any(std::path::is_separator) {
+        bail!(LispError::OutOfRange{op: op.into(), expected: "a prefix without path separators", actual: format!("{prefix:?}")});
+    }
+
+    return Ok(prefix);
+}
+
+/// Call `create` with new names in the temp directory until one doesn't exist yet
+fn create_unique(prefix: &str, create: impl Fn(&Path)->IoResult<()>)->Result<PathBuf> {
+    let dir = temp_dir();
+    for _ in 0..MAX_ATTEMPTS {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d|d.subsec_nanos())
+            .unwrap_or(0);
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{prefix}{}-{nanos:x}{count:x}", process::id()));
+
+        match create(&path) {
+            Ok(())=>return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists=>continue,
+            Err(e)=>bail!(LispError::from(e)),
+        }
+    }
+
+    bail!(LispError::TempNameTaken{dir, attempts: MAX_ATTEMPTS as usize});
+}
+
+fn alloc_path(i: &mut Interpreter, path: &Path)->DataRef {
+    i.alloc(Data::String(path.to_string_lossy().into_owned()))
+}
+
+
+/// The system's temp directory
+pub fn dir(_: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    i.require(Capabilities::ENV)?;
+    Ok(alloc_path(i, &temp_dir()))
+}
+
+/// `(makeFile prefix)` makes an empty file and returns its path
+pub fn make_file(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    i.require(Capabilities::FS_WRITE)?;
+    let prefix = get_prefix(&args[0], "makeFile")?;
+    let path = create_unique(&prefix, |path|OpenOptions::new().write(true).create_new(true).open(path).map(drop))?;
+
+    return Ok(alloc_path(i, &path));
+}
+
+/// `(makeDir prefix)` makes an empty directory and returns its path
+pub fn make_dir(args: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    i.require(Capabilities::FS_WRITE)?;
+    let prefix = get_prefix(&args[0], "makeDir")?;
+    let path = create_unique(&prefix, |path|create_dir(path))?;
+
+    return Ok(alloc_path(i, &path));
+}
+
+/// `(withDir f)` makes a directory, calls `f` with its path, then removes it and everything in it.
+/// It is removed even if `f` fails. If removing it fails that is only a warning, so it doesn't
+/// hide what `f` returned or how it failed.
+pub fn with_dir(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    i.require(Capabilities::FS_WRITE)?;
+    // `f` is called, so it has to stay rooted
+    i.root(&args[0]);
+
+    let path = create_unique("simple_lisp-", |path|create_dir(path))?;
+    let path_dr = alloc_path(i, &path);
+    let out = i.call(state, args[0].clone(), vec![path_dr]);
+
+    if let Err(e) = remove_dir_all(&path) {
+        eprintln!("Warning: could not remove temporary directory `{}`: {e}", path.display());
+    }
+
+    return out;
+}
@@ -0,0 +1,218 @@
+//! Styling text with ANSI escape codes, and asking about the terminal. Styles are named by strings
+//! or idents, like `(style "done" "green" "bold")`. Text is only styled if the program's output is
+//! a terminal and `NO_COLOR` isn't set, unless `forceColor` says otherwise.
+//!
+//! The key natives return a char for keys that type one, and a name like `"up"`, `"enter"` or
+//! `"ctrl-c"` for the rest. See `crate::terminal`.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use crossterm::terminal::size as terminal_size;
+use std::time::Duration;
+use super::{
+    Interpreter,
+    Interner,
+    Data,
+    DataRef,
+    NativeFn,
+    ArgCount,
+    LispError,
+    Signature,
+    Capabilities,
+};
+use crate::terminal::{
+    Key,
+    read_key as read_terminal_key,
+};
+
+
+/// Ends every style
+const RESET: &str = "\x1b[0m";
+
+/// Each style's name and its SGR code
+const STYLES: &[(&str, u8)] = &[
+    ("bold", 1),
+    ("dim", 2),
+    ("italic", 3),
+    ("underline", 4),
+    ("inverse", 7),
+    ("black", 30),
+    ("red", 31),
+    ("green", 32),
+    ("yellow", 33),
+    ("blue", 34),
+    ("magenta", 35),
+    ("cyan", 36),
+    ("white", 37),
+    ("onBlack", 40),
+    ("onRed", 41),
+    ("onGreen", 42),
+    ("onYellow", 43),
+    ("onBlue", 44),
+    ("onMagenta", 45),
+    ("onCyan", 46),
+    ("onWhite", 47),
+];
+
+
+pub const BUILTINS: &[(&str, NativeFn, ArgCount)] = &[
+    builtin!(style, Any),
+    builtin!(width, 0),
+    builtin!(is_tty, isTty, 0),
+    builtin!(force_color, forceColor, 1),
+    builtin!(read_key, readKey, 0),
+    builtin!(read_key_timeout, readKeyTimeout, 1),
+    builtin!(raw_mode, rawMode, 1),
+    builtin!(cursor_to, cursorTo, 2),
+    builtin!(clear_screen, clearScreen, 0),
+];
+
+
+/// Wrap `text` in `codes`. Styled text inside it ends with a reset, so every reset in `text` is
+/// followed by `codes` again to put the outer style back.
+fn wrap(text: &str, codes: &str)->String {
+    let restored = format!("{RESET}{codes}");
+    format!("{codes}{}{RESET}", text.replace(RESET, &restored))
+}
+
+
+/// `(style text styles...)`. Returns `text` as it is when styling is off.
+pub fn style(args: Vec<DataRef>, i: &mut Interpreter, interner: &mut Interner)->Result<DataRef> {
+    let Some((text, styles)) = args.split_first() else {
+        bail!(LispError::Arity{name: Some("style".into()), expected: vec![Signature::at_least(1)], got: 0});
+    };
+    let text = match &*text.try_get_data("style")? {
+        Data::String(s)=>s.clone(),
+        data=>bail!(LispError::Type{op: "style".into(), expected: "string", actual: data.type_name()}),
+    };
+
+    let mut codes = String::new();
+    for style in styles {
+        let name = match &*style.try_get_data("style")? {
+            Data::String(name)=>name.clone(),
+            Data::Ident(name)=>interner.get(*name).to_string(),
+            data=>bail!(LispError::Type{op: "style".into(), expected: "string or ident", actual: data.type_name()}),
+        };
+        let Some((_, code)) = STYLES.iter().find(|(n, _)|*n == name) else {
+            bail!(LispError::UnknownName{
+                op: "style".into(),
+                kind: "style",
+                name,
+                options: STYLES.iter().map(|(n, _)|n.to_string()).collect(),
+            });
+        };
+        codes.push_str(&format!("\x1b[{code}m"));
+    }
+
+    if codes.is_empty() || !i.color_enabled() {
+        return Ok(i.alloc(Data::String(text)));
+    }
+
+    return Ok(i.alloc(Data::String(wrap(&text, &codes))));
+}
+
+/// How many columns the terminal has, or `none` if the output isn't a terminal
+pub fn width(_: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    if !i.output().is_terminal() {
+        return Ok(i.alloc(Data::None));
+    }
+
+    match terminal_size() {
+        Ok((width, _))=>Ok(i.alloc(Data::Number(width as i64))),
+        Err(_)=>Ok(i.alloc(Data::None)),
+    }
+}
+
+/// Whether the program's output goes to a terminal
+pub fn is_tty(_: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let tty = i.output().is_terminal();
+    Ok(i.alloc(Data::Bool(tty)))
+}
+
+/// `(forceColor #t)` always styles text and `(forceColor #f)` never does. `(forceColor none)` goes
+/// back to checking the output and `NO_COLOR`.
+pub fn force_color(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    let force = match &*args[0].try_get_data("forceColor")? {
+        Data::Bool(b)=>Some(*b),
+        Data::None=>None,
+        data=>bail!(LispError::Type{op: "forceColor".into(), expected: "bool or none", actual: data.type_name()}),
+    };
+    i.set_force_color(force);
+
+    return Ok(i.alloc(Data::None));
+}
+
+fn alloc_key(i: &mut Interpreter, key: Option<Key>)->DataRef {
+    match key {
+        Some(Key::Char(c))=>i.alloc(Data::Char(c)),
+        Some(key)=>i.alloc(Data::String(key.name().unwrap())),
+        None=>i.alloc(Data::None),
+    }
+}
+
+fn get_number(dr: &DataRef, op: &str)->Result<i64> {
+    match &*dr.try_get_data(op)? {
+        Data::Number(n)=>Ok(*n),
+        data=>bail!(LispError::Type{op: op.into(), expected: "number", actual: data.type_name()}),
+    }
+}
+
+/// Wait for a key. `none` once stdin has ended.
+pub fn read_key(_: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    i.output().flush().map_err(LispError::from)?;
+    let key = read_terminal_key(None);
+
+    return Ok(alloc_key(i, key));
+}
+
+/// `(readKeyTimeout ms)` is `readKey`, but it gives `none` if no key came in `ms` milliseconds
+pub fn read_key_timeout(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    let ms = match get_number(&args[0], "readKeyTimeout")? {
+        ms if ms < 0=>bail!(LispError::OutOfRange{op: "readKeyTimeout".into(), expected: "a time of at least 0", actual: ms.to_string()}),
+        ms=>ms as u64,
+    };
+    i.output().flush().map_err(LispError::from)?;
+    let key = read_terminal_key(Some(Duration::from_millis(ms)));
+
+    return Ok(alloc_key(i, key));
+}
+
+/// `(rawMode #t)` passes each key to the program as it is pressed, without echoing it. It is
+/// turned off again when the program ends, however it ends.
+pub fn raw_mode(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    let enabled = match &*args[0].try_get_data("rawMode")? {
+        Data::Bool(b)=>*b,
+        data=>bail!(LispError::Type{op: "rawMode".into(), expected: "bool", actual: data.type_name()}),
+    };
+    i.set_raw_mode(enabled)?;
+
+    return Ok(i.alloc(Data::None));
+}
+
+/// `(cursorTo x y)` moves the cursor to column `x` and row `y`, counting from 0
+pub fn cursor_to(args: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    let x = get_number(&args[0], "cursorTo")?;
+    let y = get_number(&args[1], "cursorTo")?;
+    if x < 0 || y < 0 {
+        bail!(LispError::OutOfRange{op: "cursorTo".into(), expected: "a position of at least 0", actual: format!("{x} {y}")});
+    }
+    i.output().write_str(&format!("\x1b[{};{}H", y + 1, x + 1)).map_err(LispError::from)?;
+
+    return Ok(i.alloc(Data::None));
+}
+
+/// Clear the screen and move the cursor to the top left
+pub fn clear_screen(_: Vec<DataRef>, i: &mut Interpreter, _: &mut Interner)->Result<DataRef> {
+    i.require(Capabilities::STDIO)?;
+    i.output().write_str("\x1b[2J\x1b[H").map_err(LispError::from)?;
+
+    return Ok(i.alloc(Data::None));
+}
@@ -0,0 +1,79 @@
+//! Threads and the channels they talk over. Each thread runs its own interpreter, so what is sent
+//! between them is copied. See `interpreter::threads`.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use super::{
+    Interpreter,
+    NativeData,
+    Data,
+    DataRef,
+    CallingNativeFn,
+    ArgCount,
+    ConvertState,
+    LispError,
+};
+use crate::interpreter::threads::{
+    Channel,
+    Sendable,
+};
+
+
+pub const BUILTINS: &[(&str, CallingNativeFn, ArgCount)] = &[
+    builtin!(spawn, 1),
+    builtin!(chan, 0),
+    builtin!(send, 2),
+    builtin!(recv, 1),
+    builtin!(join, 1),
+];
+
+
+/// `(spawn f)` calls `f` on a new thread and returns a handle to `join`
+pub fn spawn(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    let handle = i.spawn(state, &args[0])?;
+    Ok(i.alloc(Data::NativeData(NativeData::Thread(handle))))
+}
+
+pub fn chan(_: Vec<DataRef>, i: &mut Interpreter, _: &mut ConvertState)->Result<DataRef> {
+    Ok(i.alloc(Data::NativeData(NativeData::Channel(Channel::new()))))
+}
+
+/// `(send ch value)` copies `value` onto the channel. It never waits.
+pub fn send(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    let value = Sendable::copy(&args[1], &state.interner, "send", false)?;
+    match &*args[0].try_get_data("send")? {
+        Data::NativeData(NativeData::Channel(channel))=>channel.send(value),
+        data=>bail!(LispError::Type{op: "send".into(), expected: "channel", actual: data.type_name()}),
+    }
+
+    return Ok(i.alloc(Data::None));
+}
+
+/// `(recv ch)` waits for the next value sent on the channel
+pub fn recv(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    // the channel isn't cloned, so `recv` can tell when nothing else has it
+    let value = match &*args[0].try_get_data("recv")? {
+        Data::NativeData(NativeData::Channel(channel))=>channel.recv()?,
+        data=>bail!(LispError::Type{op: "recv".into(), expected: "channel", actual: data.type_name()}),
+    };
+    // whatever sent it may have printed first
+    i.write_thread_prints()?;
+
+    return Ok(value.into_data(i, &mut state.interner));
+}
+
+/// `(join handle)` waits for the thread to finish and returns what its function returned
+pub fn join(args: Vec<DataRef>, i: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+    let handle = match &*args[0].try_get_data("join")? {
+        Data::NativeData(NativeData::Thread(handle))=>handle.clone(),
+        data=>bail!(LispError::Type{op: "join".into(), expected: "thread", actual: data.type_name()}),
+    };
+
+    let value = handle.join();
+    i.write_thread_prints()?;
+
+    return Ok(value?.into_data(i, &mut state.interner));
+}
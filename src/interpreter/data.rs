@@ -1,7 +1,15 @@
 #![allow(unsafe_code)]
 
-use rustc_hash::FxBuildHasher;
+use rustc_hash::{
+    FxBuildHasher,
+    FxHashSet,
+    FxHasher,
+};
 use indexmap::IndexSet;
+use anyhow::{
+    Result,
+    bail,
+};
 use std::{
     cell::{
         RefCell,
@@ -27,26 +35,48 @@ use std::{
     rc::Rc,
     fs::File,
     ptr::NonNull,
+    time::Instant,
     mem,
 };
 use super::{
     ArgCount,
     CallStack,
     Scopes,
+    ScopeItem,
+    SuspendedEnv,
     // Metrics,
-    NativeFn,
+    NativeFunc,
     IdentMap,
-    DEBUG,
     ast::*,
+    threads::{
+        Channel,
+        ThreadHandle,
+    },
+};
+use crate::{
+    gc_config::{
+        GcConfig,
+        GcStats,
+    },
+    error::LispError,
+    host::HostObject,
+    truthy::Truth,
 };
 
 
 type DataRefSet = IndexSet<HashableDataRef, FxBuildHasher>;
+/// The pairs of data being compared by `PartialEq`, so cycles stop instead of recursing forever
+type EqSeen = FxHashSet<(NonNull<DataBox>, NonNull<DataBox>)>;
+
+
+/// The `age` of data in the old generation. Anything lower is in the nursery.
+const OLD: u8 = u8::MAX;
 
 
 thread_local!(
-    pub static ALLOCATIONS: RefCell<usize> = const {RefCell::new(0)};
-    pub static DEALLOCATIONS: RefCell<usize> = const {RefCell::new(0)};
+    /// The data `Debug` is printing the inside of right now. Printing one of these again means the
+    /// data contains itself.
+    static DEBUG_PARENTS: RefCell<FxHashSet<NonNull<DataBox>>> = RefCell::new(FxHashSet::default());
 );
 
 
@@ -55,6 +85,15 @@ pub enum NativeData {
     File(Rc<RefCell<BufReader<File>>>),
     Stdout,
     Stdin(Rc<RefCell<BufReader<Stdin>>>),
+    /// Something the host handed to scripts
+    Custom(Rc<dyn HostObject>),
+    /// From `std/string/builder`. Pushing onto it appends in place, so building a string out of
+    /// many pieces is linear.
+    StringBuilder(String),
+    /// From `std/thread/chan`
+    Channel(Channel),
+    /// From `std/thread/spawn`
+    Thread(ThreadHandle),
 }
 impl PartialEq for NativeData {
     fn eq(&self, other: &Self)->bool {
@@ -64,15 +103,95 @@ impl PartialEq for NativeData {
             },
             (Self::Stdout, Self::Stdout)=>true,
             (Self::Stdin(_), Self::Stdin(_))=>true,
+            (Self::Custom(l), Self::Custom(r))=>Rc::ptr_eq(l, r),
+            (Self::StringBuilder(l), Self::StringBuilder(r))=>l == r,
+            (Self::Channel(l), Self::Channel(r))=>l == r,
+            (Self::Thread(l), Self::Thread(r))=>l == r,
             _=>false,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// From the `std/seq` natives. A lazy seq is a recipe: nothing runs until something walks it, and
+/// walking it again runs the functions again. They can't be changed once they are made.
+#[derive(Debug, Clone)]
+pub enum LazySeq {
+    /// Counts by `step` until it reaches `end`, or forever without one
+    Range {
+        start: i64,
+        end: Option<i64>,
+        step: i64,
+    },
+    Repeat(DataRef),
+    /// `seed`, `(f seed)`, `(f (f seed))` and so on
+    Iterate {
+        f: DataRef,
+        seed: DataRef,
+    },
+    Map {
+        f: DataRef,
+        source: DataRef,
+    },
+    Filter {
+        pred: DataRef,
+        source: DataRef,
+    },
+}
+impl LazySeq {
+    /// The data this references directly
+    fn refs(&self)->Vec<&DataRef> {
+        match self {
+            Self::Range{..}=>Vec::new(),
+            Self::Repeat(item)=>vec![item],
+            Self::Iterate{f, seed}=>vec![f, seed],
+            Self::Map{f, source}=>vec![f, source],
+            Self::Filter{pred, source}=>vec![pred, source],
+        }
+    }
+}
+
+/// From calling a `fn-gen`. `std/seq/next` runs it until its next `yield`.
+#[derive(Debug, Clone)]
+pub enum GeneratorState {
+    Suspended(Box<SuspendedFrame>),
+    /// Its frame is on the interpreter's stacks
+    Running,
+    /// It returned or failed, so it only gives `none` now
+    Done,
+}
+impl GeneratorState {
+    /// The data this references directly
+    fn refs(&self)->Vec<&DataRef> {
+        match self {
+            Self::Suspended(frame)=>frame.env.refs()
+                .chain(frame.scopes.iter().flat_map(ScopeItem::iter))
+                .collect(),
+            Self::Running|Self::Done=>Vec::new(),
+        }
+    }
+}
+
+/// What a generator needs to pick up where it stopped. It is moved off of the interpreter's stacks
+/// into here, so the GC finds it through the generator like any other data.
+#[derive(Debug, Clone)]
+pub struct SuspendedFrame {
+    pub id: FnId,
+    /// Where to continue from
+    pub resume: InstructionId,
+    pub env: SuspendedEnv,
+    /// The innermost last
+    pub scopes: Vec<ScopeItem>,
+}
+
+#[derive(Debug, Clone)]
 pub enum Data {
     List(Vec<DataRef>),
     Object(IdentMap<DataRef>),
+    /// From `cons`: a car and a cdr. Pairs can't be changed once they are made. A chain of them
+    /// ending in `None` prints like a list, but it is never `=` to one.
+    Pair(DataRef, DataRef),
+    Lazy(LazySeq),
+    Generator(GeneratorState),
 
     Ident(Ident),
     Number(i64),
@@ -82,17 +201,58 @@ pub enum Data {
     Bool(bool),
 
     Fn(FnId),
-    NativeFn(&'static str, NativeFn, ArgCount),
+    NativeFn(Rc<str>, NativeFunc, ArgCount),
     Closure {
         id: FnId,
         captures: ClosureCaptures,
     },
+    /// A var that closures share with the frame that made them. Reading or setting the var goes
+    /// through the cell.
+    Cell(DataRef),
 
     NativeData(NativeData),
 
     None,
 }
+impl PartialEq for Data {
+    fn eq(&self, other: &Self)->bool {
+        self.eq_inner(other, &mut EqSeen::default())
+    }
+}
 impl Data {
+    fn eq_inner(&self, other: &Self, seen: &mut EqSeen)->bool {
+        match (self, other) {
+            (Self::List(l), Self::List(r))=>l.len() == r.len() && l.iter()
+                .zip(r)
+                .all(|(l, r)|l.eq_inner(r, seen)),
+            (Self::Object(l), Self::Object(r))=>l.len() == r.len() && l.iter()
+                .all(|(name, l)|r.get(name).is_some_and(|r|l.eq_inner(r, seen))),
+            (Self::Closure{id: l_id, captures: l}, Self::Closure{id: r_id, captures: r})=>{
+                l_id == r_id && l.0.len() == r.0.len() && l.0.iter()
+                    .zip(&r.0)
+                    .all(|((l_name, l), (r_name, r))|l_name == r_name && l.eq_inner(r, seen))
+            },
+            (Self::Cell(l), Self::Cell(r))=>l.eq_inner(r, seen),
+            (Self::Pair(l_car, l_cdr), Self::Pair(r_car, r_cdr))=>{
+                l_car.eq_inner(r_car, seen) && pair_chain_eq(l_cdr.clone(), r_cdr.clone(), seen)
+            },
+
+            (Self::Ident(l), Self::Ident(r))=>l == r,
+            (Self::Number(l), Self::Number(r))=>l == r,
+            (Self::Float(l), Self::Float(r))=>l == r,
+            (Self::String(l), Self::String(r))=>l == r,
+            (Self::Char(l), Self::Char(r))=>l == r,
+            (Self::Bool(l), Self::Bool(r))=>l == r,
+            (Self::Fn(l), Self::Fn(r))=>l == r,
+            (Self::NativeFn(l_name, l, l_count), Self::NativeFn(r_name, r, r_count))=>{
+                l_name == r_name && l == r && l_count == r_count
+            },
+            (Self::NativeData(l), Self::NativeData(r))=>l == r,
+            (Self::None, Self::None)=>true,
+            _=>false,
+        }
+    }
+
     pub fn add_data_refs(&self, refs: &mut DataRefSet) {
         match self {
             Self::List(items)=>refs.extend(items.iter()
@@ -108,27 +268,116 @@ impl Data {
                 .map(|(_,c)|c)
                 .map(HashableDataRef)
             ),
+            Self::Cell(data)=>{refs.insert(HashableDataRef(data.clone()));},
+            Self::Pair(car, cdr)=>{
+                refs.insert(HashableDataRef(car.clone()));
+                refs.insert(HashableDataRef(cdr.clone()));
+            },
+            Self::Lazy(seq)=>refs.extend(seq.refs()
+                .into_iter()
+                .cloned()
+                .map(HashableDataRef)
+            ),
+            Self::Generator(state)=>refs.extend(state.refs()
+                .into_iter()
+                .cloned()
+                .map(HashableDataRef)
+            ),
+            Self::NativeFn(_, f, _)=>refs.extend(f.captures()
+                .iter()
+                .cloned()
+                .map(HashableDataRef)
+            ),
             _=>{},
         }
     }
 
+    /// The data this references directly
+    pub fn data_refs(&self)->Vec<DataRef> {
+        let mut refs = DataRefSet::default();
+        self.add_data_refs(&mut refs);
+
+        return refs.into_iter()
+            .map(|d|d.0)
+            .collect();
+    }
+
+    /// Returns true if this references anything in the nursery
+    fn has_young_refs(&self)->bool {
+        match self {
+            Self::List(items)=>items.iter().any(|d|!d.is_old()),
+            Self::Object(fields)=>fields.values().any(|d|!d.is_old()),
+            Self::Closure{captures,..}=>captures.0.iter().any(|(_, d)|!d.is_old()),
+            Self::Cell(data)=>!data.is_old(),
+            Self::Pair(car, cdr)=>!car.is_old() || !cdr.is_old(),
+            Self::Lazy(seq)=>seq.refs().into_iter().any(|d|!d.is_old()),
+            Self::Generator(state)=>state.refs().into_iter().any(|d|!d.is_old()),
+            Self::NativeFn(_, f, _)=>f.captures().iter().any(|d|!d.is_old()),
+            _=>false,
+        }
+    }
+
+    pub fn type_name(&self)->&'static str {
+        match self {
+            Self::List(_)=>"list",
+            Self::Object(_)=>"object",
+            Self::Pair(..)=>"pair",
+            Self::Lazy(_)=>"lazySeq",
+            Self::Generator(_)=>"generator",
+            Self::Ident(_)=>"ident",
+            Self::Number(_)=>"number",
+            Self::Float(_)=>"float",
+            Self::String(_)=>"string",
+            Self::Char(_)=>"char",
+            Self::Bool(_)=>"bool",
+            Self::Fn(_)=>"fn",
+            Self::NativeFn(..)=>"nativeFn",
+            Self::Closure{..}=>"closure",
+            Self::Cell(_)=>"cell",
+            Self::NativeData(NativeData::Custom(_))=>"native",
+            Self::NativeData(NativeData::StringBuilder(_))=>"stringBuilder",
+            Self::NativeData(NativeData::Channel(_))=>"channel",
+            Self::NativeData(NativeData::Thread(_))=>"thread",
+            Self::NativeData(_)=>"nativeData",
+            Self::None=>"none",
+        }
+    }
+
+    /// Whether this counts as true when branching. See [`crate::truthy`].
+    pub fn truth(&self)->Truth {
+        match self {
+            Self::Bool(b)=>Truth::Bool(*b),
+            Self::None=>Truth::None,
+            data=>Truth::Other(data.type_name()),
+        }
+    }
+
     /// This is not exact, but it works for a general idea and will look cool when I say "collected
     /// N bytes with my garbage collector"
     pub fn allocation_size(&self)->usize {
         let mut alloc_size = mem::size_of::<Self>();
         match self {
+            Self::NativeData(NativeData::StringBuilder(s))=>alloc_size += s.capacity(),
+            Self::Generator(GeneratorState::Suspended(frame))=>{
+                alloc_size += mem::size_of::<SuspendedFrame>() + frame.scopes.capacity() * mem::size_of::<ScopeItem>();
+            },
+
             Self::Ident(_)|
                 Self::Number(_)|
                 Self::Float(_)|
                 Self::Char(_)|
                 Self::Bool(_)|
                 Self::Fn(_)|
-                Self::NativeFn(..)|
+                Self::Cell(_)|
+                Self::Pair(..)|
+                Self::Lazy(_)|
+                Self::Generator(_)|
                 Self::NativeData(_)|    // technically wrong, but I don't care, and they are Rc'd
                                         // so it doesn't matter much anyways
                 Self::None=>{},
 
             Self::Closure{captures,..}=>alloc_size += captures.0.capacity() * mem::size_of::<(Ident, DataRef)>(),
+            Self::NativeFn(_, f, _)=>alloc_size += f.captures().len() * mem::size_of::<DataRef>(),
 
             Self::String(s)=>alloc_size += s.capacity(),
             Self::List(items)=>alloc_size += items.capacity() * mem::size_of::<DataRef>(),
@@ -140,6 +389,126 @@ impl Data {
 }
 
 
+/// Compares the rest of two chains of pairs in a loop, since recursing on every cdr would run out of
+/// stack on long ones
+fn pair_chain_eq(mut l: DataRef, mut r: DataRef, seen: &mut EqSeen)->bool {
+    loop {
+        if l.inner == r.inner || !seen.insert((l.inner, r.inner)) {return true}
+
+        let (l_next, r_next) = {
+            let l_data = l.get_data_box().inner.borrow();
+            let r_data = r.get_data_box().inner.borrow();
+            match (&*l_data, &*r_data) {
+                (Data::Pair(l_car, l_cdr), Data::Pair(r_car, r_cdr))=>{
+                    if !l_car.eq_inner(r_car, seen) {return false}
+                    (l_cdr.clone(), r_cdr.clone())
+                },
+                (l_data, r_data)=>return l_data.eq_inner(r_data, seen),
+            }
+        };
+        l = l_next;
+        r = r_next;
+    }
+}
+
+/// The cars of a chain of pairs starting at `dr`, and the last cdr, which isn't a pair. Pairs
+/// can't be changed, so a chain can only loop back on itself through something that isn't a pair.
+pub fn pair_chain(dr: &DataRef)->(Vec<DataRef>, DataRef) {
+    let mut items = Vec::new();
+    let mut current = dr.clone();
+    loop {
+        let next = match &*current.get_data() {
+            Data::Pair(car, cdr)=>{
+                items.push(car.clone());
+                cdr.clone()
+            },
+            _=>break,
+        };
+        current = next;
+    }
+
+    return (items, current);
+}
+
+
+impl Data {
+    /// A hash of what this holds, so data that is `=` hashes the same. It is the same for the whole
+    /// run, but not between runs. Functions, native data and lazy things have no structural hash,
+    /// and neither does data that contains itself, so `op` is blamed for those.
+    pub fn structural_hash(&self, op: &str)->Result<u64> {
+        let mut hasher = FxHasher::default();
+        self.hash_into(&mut hasher, op, &mut Vec::new())?;
+
+        return Ok(hasher.finish());
+    }
+
+    /// `parents` are the addresses of the data we are inside of, to find cycles
+    fn hash_into(&self, hasher: &mut FxHasher, op: &str, parents: &mut Vec<usize>)->Result<()> {
+        mem::discriminant(self).hash(hasher);
+        match self {
+            Self::List(items)=>{
+                hasher.write_usize(items.len());
+                for item in items {
+                    item.hash_into(hasher, op, parents)?;
+                }
+            },
+            // fields are in no particular order, so each one is hashed alone and the hashes are
+            // combined in a way that doesn't care about order
+            Self::Object(fields)=>{
+                hasher.write_usize(fields.len());
+                let mut combined = 0u64;
+                for (name, value) in fields.iter() {
+                    let mut field_hasher = FxHasher::default();
+                    name.hash(&mut field_hasher);
+                    value.hash_into(&mut field_hasher, op, parents)?;
+                    combined = combined.wrapping_add(field_hasher.finish());
+                }
+                hasher.write_u64(combined);
+            },
+            // in a loop like `pair_chain_eq`, so long chains don't run out of stack
+            Self::Pair(car, cdr)=>{
+                car.hash_into(hasher, op, parents)?;
+                let mut current = cdr.clone();
+                loop {
+                    let next = match &*current.try_get_data(op)? {
+                        Self::Pair(car, cdr)=>{
+                            car.hash_into(hasher, op, parents)?;
+                            cdr.clone()
+                        },
+                        _=>break,
+                    };
+                    current = next;
+                }
+                current.hash_into(hasher, op, parents)?;
+            },
+            Self::Cell(inner)=>inner.hash_into(hasher, op, parents)?,
+
+            Self::Ident(name)=>name.hash(hasher),
+            Self::Number(n)=>n.hash(hasher),
+            // `-0.0 = 0.0`, so they have to hash the same. NaN is never `=` to anything, so its
+            // hash doesn't matter.
+            Self::Float(f)=>{
+                let f = if *f == 0.0 {0.0} else {*f};
+                hasher.write_u64(f.to_bits());
+            },
+            Self::String(s)=>s.hash(hasher),
+            Self::Char(c)=>c.hash(hasher),
+            Self::Bool(b)=>b.hash(hasher),
+            Self::None=>{},
+
+            Self::Lazy(_)|
+                Self::Generator(_)|
+                Self::Fn(_)|
+                Self::NativeFn(..)|
+                Self::Closure{..}|
+                Self::NativeData(_)=>bail!(LispError::Unhashable{op: op.into(), actual: self.type_name()}),
+        }
+
+        return Ok(());
+    }
+}
+
+
 #[derive(Clone, PartialEq)]
 pub struct ClosureCaptures(pub Vec<(Ident, DataRef)>);
 impl Debug for ClosureCaptures {
@@ -182,6 +551,22 @@ impl Deref for ExternalData {
     fn deref(&self)->&DataRef {&self.0}
 }
 
+/// What the write barriers in `DataRef::get_data_mut` tell the collector. Each `DataStore` has its
+/// own and every data it allocates points at it, so mutating one store's data never touches
+/// another store.
+#[derive(Default)]
+struct Barriers {
+    /// The generation being marked by an incremental collection, or 0 if there isn't one
+    marking_generation: Cell<u64>,
+    /// Data that was already marked and then mutated or allocated during an incremental
+    /// collection. It has to be traced again before the collection can finish.
+    regrayed: RefCell<Vec<DataRef>>,
+    /// Old data that was mutated since the last minor collection, so it may point into the
+    /// nursery now
+    remembered: RefCell<Vec<DataRef>>,
+}
+
+
 /// A shared reference to some `Data`. The data can be mutably borrowed, but it panics if the data
 /// is already borrowed either mutably or shared (does not include other copies of `DataRef`, but
 /// the internal `Data`).
@@ -198,24 +583,61 @@ impl Clone for DataRef {
 }
 impl Debug for DataRef {
     fn fmt(&self, f: &mut Formatter)->FmtResult {
-        self.get_data_box().inner.borrow().fmt(f)
+        let data = self.get_data_box().inner.borrow();
+
+        // data that contains itself would print forever, so we print a marker where it loops back
+        if !DEBUG_PARENTS.with_borrow_mut(|p|p.insert(self.inner)) {
+            return write!(f, "#cycle->{}", data.type_name());
+        }
+        let res = data.fmt(f);
+        DEBUG_PARENTS.with_borrow_mut(|p|p.remove(&self.inner));
+
+        return res;
     }
 }
 impl PartialEq for DataRef {
     fn eq(&self, other: &Self)->bool {
+        self.eq_inner(other, &mut EqSeen::default())
+    }
+}
+#[allow(dead_code)]
+impl DataRef {
+    fn eq_inner(&self, other: &Self, seen: &mut EqSeen)->bool {
         // short-circuit if the pointers are the same.
         // Why is this? Well, the pointers point to the same data, so obviously self == self
         if self.inner == other.inner {return true}
 
+        // we already compared these two or are still comparing them further up, so this is a
+        // cycle. Anything that differs is found by the first comparison, so we can say they are
+        // equal here.
+        if !seen.insert((self.inner, other.inner)) {return true}
+
         let l = self.get_data_box().inner.borrow();
         let r = other.get_data_box().inner.borrow();
 
-        l.eq(&r)
+        l.eq_inner(&r, seen)
     }
-}
-#[allow(dead_code)]
-impl DataRef {
-    fn new(data: Data)->Self {
+
+    /// See `Data::structural_hash`
+    pub fn structural_hash(&self, op: &str)->Result<u64> {
+        let mut hasher = FxHasher::default();
+        self.hash_into(&mut hasher, op, &mut Vec::new())?;
+
+        return Ok(hasher.finish());
+    }
+
+    fn hash_into(&self, hasher: &mut FxHasher, op: &str, parents: &mut Vec<usize>)->Result<()> {
+        if parents.contains(&self.addr()) {
+            bail!(LispError::Unhashable{op: op.into(), actual: "value that contains itself"});
+        }
+        parents.push(self.addr());
+        self.try_get_data(op)?.hash_into(hasher, op, parents)?;
+        parents.pop();
+
+        return Ok(());
+    }
+
+    fn new(data: Data, barriers: &Rc<Barriers>)->Self {
         use std::alloc::{Layout, alloc};
 
         // println!("Create layout");
@@ -233,11 +655,13 @@ impl DataRef {
                 pinned: Cell::new(false),
                 external: RefCell::new(0),
                 generation: Cell::new(0),
+                age: Cell::new(0),
+                remembered: Cell::new(false),
+                dead: Cell::new(false),
+                barriers: barriers.clone(),
             });
         }
 
-        ALLOCATIONS.with_borrow_mut(|a|*a += 1);
-
         // println!("Return");
         return DataRef {
             inner: ptr,
@@ -262,12 +686,52 @@ impl DataRef {
 
     #[inline]
     pub fn get_data<'a>(&'a self)->Ref<'a, Data> {
+        self.check_alive();
         self.get_data_box().inner.borrow()
     }
 
+    /// Mutating marked data during an incremental collection could hide unmarked data from the
+    /// collector, so it gets queued to be traced again. Mutated old data may point into the
+    /// nursery afterwards, so it is remembered for the next minor collection.
     #[inline]
     pub fn get_data_mut<'a>(&'a mut self)->RefMut<'a, Data> {
-        self.get_data_box().inner.borrow_mut()
+        self.check_alive();
+
+        let data_box = self.get_data_box();
+        let marking = data_box.barriers.marking_generation.get();
+        if marking != 0 && data_box.generation.get() == marking {
+            data_box.barriers.regrayed.borrow_mut().push(self.clone());
+        }
+
+        if data_box.age.get() == OLD && !data_box.remembered.get() {
+            data_box.remembered.set(true);
+            data_box.barriers.remembered.borrow_mut().push(self.clone());
+        }
+
+        data_box.inner.borrow_mut()
+    }
+
+    /// Same as `get_data`, but returns an error if the data is being modified instead of
+    /// panicking. `op` names what we were doing for the error. Natives and the interpreter should
+    /// use this, since a script can make a native read the data it is modifying.
+    #[inline]
+    pub fn try_get_data<'a>(&'a self, op: &str)->Result<Ref<'a, Data>> {
+        self.check_alive();
+        match self.get_data_box().inner.try_borrow() {
+            Ok(data)=>Ok(data),
+            Err(_)=>bail!(LispError::Borrowed{op: op.into(), mutating: false}),
+        }
+    }
+
+    /// Same as `get_data_mut`, but returns an error if the data is already borrowed instead of
+    /// panicking. `op` names what we were doing for the error.
+    #[inline]
+    pub fn try_get_data_mut<'a>(&'a mut self, op: &str)->Result<RefMut<'a, Data>> {
+        if self.get_data_box().inner.try_borrow_mut().is_err() {
+            bail!(LispError::Borrowed{op: op.into(), mutating: true});
+        }
+
+        return Ok(self.get_data_mut());
     }
 
     #[inline]
@@ -280,6 +744,12 @@ impl DataRef {
         self.get_data_box().generation.set(gen);
     }
 
+    /// Returns true if this survived enough minor collections to leave the nursery
+    #[inline]
+    pub fn is_old(&self)->bool {
+        self.get_data_box().age.get() == OLD
+    }
+
     /// Set `pinned` on the underlying data. This means it will never be collected.
     /// NOTE: if this data references any data, then the referenced data
     /// **WILL NOT BE COLLECTED** until the reference is removed
@@ -316,11 +786,28 @@ impl DataRef {
         self.inner == other.inner
     }
 
+    /// Where the data lives. Refs to the same data have the same address, so this can key a map
+    /// where `PartialEq` would compare the contents.
+    #[inline]
+    pub fn addr(&self)->usize {
+        self.inner.as_ptr() as usize
+    }
+
     #[inline]
     pub fn allocation_size(&self)->usize {
         self.get_data_box().allocation_size()
     }
 
+    /// Freed data is only poisoned instead of deallocated with `GcConfig::stress`, so this catches
+    /// a `DataRef` that was used after being freed. Only checked in debug builds.
+    #[inline]
+    fn check_alive(&self) {
+        debug_assert!(
+            !self.get_data_box().dead.get(),
+            "Used data after the collector freed it. Something held a `DataRef` without rooting it!",
+        );
+    }
+
     // /// SAFETY: The caller ensures that the data pointed to by this ref is inaccessible and **WILL BE
     // /// DEALLOCATED** immediately
     unsafe fn dealloc(self) {
@@ -348,7 +835,16 @@ struct DataBox {
     inner: RefCell<Data>,
     pinned: Cell<bool>,
     external: RefCell<usize>,
+    /// The last collection that marked this
     generation: Cell<u64>,
+    /// How many minor collections this survived, or `OLD`
+    age: Cell<u8>,
+    /// Set while this is in the `DataStore`'s remembered set
+    remembered: Cell<bool>,
+    /// Set when this was freed and poisoned. See `DataStore::poison_freed`.
+    dead: Cell<bool>,
+    /// The barriers of the store that allocated this
+    barriers: Rc<Barriers>,
 }
 impl Clone for DataBox {
     fn clone(&self)->Self {
@@ -357,6 +853,10 @@ impl Clone for DataBox {
             pinned: Cell::new(false),
             external: RefCell::new(0),
             generation: Cell::new(0),
+            age: Cell::new(0),
+            remembered: Cell::new(false),
+            dead: Cell::new(false),
+            barriers: self.barriers.clone(),
         }
     }
 }
@@ -368,117 +868,434 @@ impl DataBox {
     }
 }
 
-/// A safe way to store data
+/// A safe way to store data. New data goes in the nursery, which `minor_collect` frees without
+/// looking at the old generation. Major collections look at everything, and can either run all at
+/// once with `collect`, or be spread out with `start_collection` and `collect_slice`.
 pub struct DataStore {
+    /// The old generation
     datas: DataRefSet,
+    nursery: DataRefSet,
+    /// Old data that may point into the nursery
+    remembered: DataRefSet,
     generation: u64,
+    /// Data that is reachable but hasn't been traced yet, if an incremental collection is running
+    marking: Option<DataRefSet>,
+    /// Allocations since the last minor collection
+    nursery_allocations: usize,
+    /// Allocations since the last major collection, minus what minor collections freed
+    new_allocations: usize,
+    /// Approximate bytes allocated since the last major collection, minus what minor collections
+    /// freed. Data that grows after it is allocated isn't counted.
+    new_bytes: usize,
+    /// Freed data that was poisoned instead of deallocated, if `poison_freed` was called
+    graveyard: Option<DataRefSet>,
+    /// What we did since the last `take_stats`
+    stats: GcStats,
+    barriers: Rc<Barriers>,
+    /// Every allocation and deallocation this store made. `drop` checks that they balance.
+    allocations: usize,
+    deallocations: usize,
 }
 impl DataStore {
     pub fn new()->Self {
         DataStore {
             datas: DataRefSet::default(),
+            nursery: DataRefSet::default(),
+            remembered: DataRefSet::default(),
             generation: 0,
+            marking: None,
+            nursery_allocations: 0,
+            new_allocations: 0,
+            new_bytes: 0,
+            graveyard: None,
+            stats: GcStats::default(),
+            barriers: Rc::default(),
+            allocations: 0,
+            deallocations: 0,
         }
     }
 
+    /// Keep freed data around until we are dropped instead of deallocating it. Its contents are
+    /// dropped and it is marked as dead, so using it afterwards panics in debug builds instead of
+    /// reading freed memory.
+    pub fn poison_freed(&mut self) {
+        self.graveyard.get_or_insert_with(DataRefSet::default);
+    }
+
     pub fn insert(&mut self, data: Data)->DataRef {
         // println!("Create ref");
-        let dr = DataRef::new(data);
+        let dr = DataRef::new(data, &self.barriers);
+
+        self.allocations += 1;
+        self.nursery_allocations += 1;
+        self.new_allocations += 1;
+        self.new_bytes += dr.allocation_size();
+
+        // new data is never collected by the running collection, but what it holds still has to
+        // be traced
+        if self.marking.is_some() {
+            dr.set_generation(self.generation);
+            self.barriers.regrayed.borrow_mut().push(dr.clone());
+        }
 
         // println!("Before push");
-        self.datas.insert(dr.clone().hashable());
+        self.nursery.insert(dr.clone().hashable());
         // println!("After push");
 
+        self.stats.allocations += 1;
+        self.stats.peak_live = self.stats.peak_live.max(self.live_count());
+
         return dr;
     }
 
+    /// Returns true if enough was allocated since the last minor collection to trigger another one.
+    /// Minor collections can't run during a major one.
+    pub fn should_collect_minor(&self, config: &GcConfig)->bool {
+        if config.disabled || config.nursery_size == 0 || self.is_collecting() {return false}
+
+        return self.nursery_allocations >= config.nursery_size;
+    }
+
+    /// Returns true if enough was allocated since the last major collection to trigger another one
+    pub fn should_collect(&self, config: &GcConfig)->bool {
+        if config.disabled {return false}
+
+        let allocations = config.trigger_allocations > 0 && self.new_allocations >= config.trigger_allocations;
+        let bytes = config.trigger_bytes > 0 && self.new_bytes >= config.trigger_bytes;
+
+        return allocations || bytes;
+    }
+
+    /// How many objects we have, including the ones the next collection will free
+    #[inline]
+    pub fn live_count(&self)->usize {
+        self.datas.len() + self.nursery.len()
+    }
+
+    /// Get what we did since the last call, so the interpreter can add it to its metrics
+    pub fn take_stats(&mut self)->GcStats {
+        let live = self.live_count();
+        return mem::replace(&mut self.stats, GcStats {
+            peak_live: live,
+            ..GcStats::default()
+        });
+    }
+
     /// Active allocations
     pub fn get_alloc_rem(&self)->usize {
-        let a = ALLOCATIONS.with(|a|*a.borrow());
-        let d = DEALLOCATIONS.with(|d|*d.borrow());
+        self.allocations - self.deallocations
+    }
 
-        a - d
+    /// Returns false if nothing was allocated since the last major collection. Another one would
+    /// only free what was unrooted since then, and the next collection frees that anyway.
+    #[inline]
+    pub fn has_new_allocations(&self)->bool {
+        self.new_allocations > 0 || self.is_collecting()
     }
 
-    // This takes a while, so be sure you want to run it.
+    /// Returns true if an incremental collection was started and hasn't finished yet
+    #[inline]
+    pub fn is_collecting(&self)->bool {
+        self.marking.is_some()
+    }
+
+    /// Run a whole major collection at once. This takes a while, so be sure you want to run it. If
+    /// an incremental collection was running, then it is thrown away and started over, since data
+    /// it already marked may be garbage by now.
     pub fn collect(&mut self, call_stack: &CallStack, scopes: &Scopes)->usize {
+        let start = Instant::now();
+
+        self.begin_collection(call_stack, scopes);
+        let freed = self.mark_slice(usize::MAX, call_stack, scopes)
+            .expect("An unlimited slice always finishes the collection");
+
+        self.stats.record_pause(true, start.elapsed());
+
+        return freed;
+    }
+
+    /// Start an incremental collection by marking the roots. Call `collect_slice` between
+    /// instructions until it returns `Some`.
+    pub fn start_collection(&mut self, call_stack: &CallStack, scopes: &Scopes) {
+        let start = Instant::now();
+        self.begin_collection(call_stack, scopes);
+        self.stats.record_pause(true, start.elapsed());
+    }
+
+    /// Trace at most `max_items` data. Once everything reachable is marked, the unmarked data is
+    /// freed and this returns how many items were freed.
+    pub fn collect_slice(&mut self, max_items: usize, call_stack: &CallStack, scopes: &Scopes)->Option<usize> {
+        let start = Instant::now();
+        let freed = self.mark_slice(max_items, call_stack, scopes);
+        self.stats.record_pause(true, start.elapsed());
+
+        return freed;
+    }
+
+    /// `start_collection` without the timing, so `collect` can count everything as one pause
+    fn begin_collection(&mut self, call_stack: &CallStack, scopes: &Scopes) {
         self.generation += 1;
-        let generation = self.generation;
+        self.new_allocations = 0;
+        self.new_bytes = 0;
+
+        self.barriers.marking_generation.set(self.generation);
+        self.barriers.regrayed.borrow_mut().clear();
 
         let mut todo_list = DataRefSet::default();
+        self.mark_roots(call_stack, scopes, &mut todo_list);
+        self.marking = Some(todo_list);
+    }
 
-        let mut free_count = 0;
+    /// `collect_slice` without the timing
+    fn mark_slice(&mut self, max_items: usize, call_stack: &CallStack, scopes: &Scopes)->Option<usize> {
+        let mut todo_list = self.marking.take().expect("No collection is running");
+        let generation = self.generation;
+
+        let mut iter = 0;
+        loop {
+            self.take_regrayed(&mut todo_list);
+
+            // iterate through anything left, adding all children until there are none left or we
+            // are out of time
+            while iter < max_items {
+                let Some(item) = todo_list.pop() else {break};
+                if item.0.get_generation() == generation {continue}
+
+                item.0.set_generation(generation);
+                item.0.get_data().add_data_refs(&mut todo_list);
+                iter += 1;
+            }
+
+            if !todo_list.is_empty() {
+                self.marking = Some(todo_list);
+                return None;
+            }
+
+            // we ran out of things to mark, but new variables may have been defined since we
+            // started, so check the roots again. If that doesn't find anything new then we are
+            // done.
+            self.mark_roots(call_stack, scopes, &mut todo_list);
+            self.take_regrayed(&mut todo_list);
+            todo_list.retain(|i|i.0.get_generation() != generation);
+
+            if todo_list.is_empty() {
+                break;
+            }
+            if iter >= max_items {
+                self.marking = Some(todo_list);
+                return None;
+            }
+        }
+
+        self.barriers.marking_generation.set(0);
+
+        if cfg!(debug_assertions) {
+            self.check_marked(call_stack, scopes, false);
+        }
 
-        // set generation of external items in the previous call stacks
-        let call_stack_iter = call_stack.iter();
-        let call_scopes_iter = call_stack_iter
+        log::trace!("Took {iter} iterations in the last slice to set all reachable datas to the current generation");
+
+        return Some(self.sweep());
+    }
+
+    /// Everything the interpreter can reach directly: the scopes of every call frame, and pinned
+    /// and external data
+    fn roots<'a>(&'a self, call_stack: &'a CallStack, scopes: &'a Scopes)->impl Iterator<Item = &'a DataRef> + 'a {
+        let pinned_external = self.datas.iter()
+            .chain(self.nursery.iter())
+            .map(|d|&d.0)
+            .filter(|d|d.is_pinned() || d.is_external());
+
+        return Self::scope_roots(call_stack, scopes)
+            .chain(pinned_external);
+    }
+
+    /// The roots in the scopes of every call frame
+    fn scope_roots<'a>(call_stack: &'a CallStack, scopes: &'a Scopes)->impl Iterator<Item = &'a DataRef> + 'a {
+        let call_stack_items = call_stack.iter()
             .map(|(_, scopes)|scopes.iter())
+            .flatten()
+            .map(|items|items.iter())
             .flatten();
-        let call_item_iter = call_scopes_iter
+        let scope_items = scopes.iter()
             .map(|items|items.iter())
             .flatten();
-        call_item_iter.for_each(|d|{
+
+        return call_stack_items.chain(scope_items);
+    }
+
+    /// Set the generation of the roots and add what they reference to `todo_list`. Some of these
+    /// will probably be garbage immediately after this call, but that isn't current me's problem!
+    /// We will catch them next collection!
+    fn mark_roots(&self, call_stack: &CallStack, scopes: &Scopes, todo_list: &mut DataRefSet) {
+        let generation = self.generation;
+
+        for d in self.roots(call_stack, scopes) {
             d.set_generation(generation);
-            let dr_ref = d.get_data();
-            dr_ref.add_data_refs(&mut todo_list);
+            d.get_data().add_data_refs(todo_list);
+        }
+    }
+
+    /// Make sure everything reachable was marked before we sweep, or just the nursery if
+    /// `young_only` is set. If this fails, then a write barrier missed a mutation and we would
+    /// free data that is still in use. Slow, so it only runs in debug builds.
+    fn check_marked(&self, call_stack: &CallStack, scopes: &Scopes, young_only: bool) {
+        let generation = self.generation;
+        let mut seen = DataRefSet::default();
+        let mut todo_list = self.roots(call_stack, scopes)
+            .cloned()
+            .map(HashableDataRef)
+            .collect::<DataRefSet>();
+
+        while let Some(item) = todo_list.pop() {
+            if !seen.insert(item.clone()) {continue}
+
+            let data = item.0.get_data();
+            assert!(
+                item.0.get_generation() == generation || (young_only && item.0.is_old()),
+                "The collector missed reachable data ({}). This is a GC bug!",
+                data.type_name(),
+            );
+            data.add_data_refs(&mut todo_list);
+        }
+    }
+
+    /// Queue what the data mutated or allocated since the last slice references
+    fn take_regrayed(&self, todo_list: &mut DataRefSet) {
+        for dr in self.barriers.regrayed.borrow_mut().drain(..) {
+            dr.get_data().add_data_refs(todo_list);
+        }
+    }
+
+    /// Move the old data the barriers saw mutated into our remembered set
+    fn take_remembered(&mut self) {
+        let mutated = self.barriers.remembered.take();
+        self.remembered.extend(mutated.into_iter().map(HashableDataRef));
+    }
+
+    /// Collect only the nursery. Old data is assumed to be alive, so this only traces from the
+    /// roots and the remembered old data that may point into the nursery. Data that survives
+    /// `promote_after` minor collections is moved to the old generation. Returns how many items
+    /// were freed.
+    pub fn minor_collect(&mut self, call_stack: &CallStack, scopes: &Scopes, promote_after: u8)->usize {
+        assert!(!self.is_collecting(), "Minor collections can't run during a major collection");
+
+        let start = Instant::now();
+        self.generation += 1;
+        self.nursery_allocations = 0;
+        let generation = self.generation;
+
+        self.take_remembered();
+
+        // pinned and external old data doesn't need to be traced, so we can skip looking through
+        // the whole old generation for it
+        let young_pinned_external = self.nursery.iter()
+            .map(|d|&d.0)
+            .filter(|d|d.is_pinned() || d.is_external());
+        let mut todo_list = Self::scope_roots(call_stack, scopes)
+            .chain(young_pinned_external)
+            .cloned()
+            .map(HashableDataRef)
+            .collect::<DataRefSet>();
+        for d in self.remembered.iter() {
+            d.0.get_data().add_data_refs(&mut todo_list);
+        }
+
+        // old data is never traced. Anything in the nursery it points to is either remembered or
+        // reachable some other way.
+        while let Some(item) = todo_list.pop() {
+            if item.0.is_old() || item.0.get_generation() == generation {continue}
+
+            item.0.set_generation(generation);
+            item.0.get_data().add_data_refs(&mut todo_list);
+        }
+
+        if cfg!(debug_assertions) {
+            self.check_marked(call_stack, scopes, true);
+        }
+
+        let (free_count, dealloc_size) = Self::sweep_set(&mut self.nursery, generation, self.graveyard.as_mut());
+        self.deallocations += free_count;
+        self.stats.freed_objects += free_count as u64;
+        self.stats.freed_bytes += dealloc_size as u64;
+        self.new_allocations = self.new_allocations.saturating_sub(free_count);
+        self.new_bytes = self.new_bytes.saturating_sub(dealloc_size);
+
+        // age everything that is left and promote the survivors that are old enough
+        let mut promoted = Vec::new();
+        self.nursery.retain(|data|{
+            let age = data.0.get_data_box().age.get() + 1;
+            if age >= promote_after {
+                promoted.push(data.clone());
+                return false;
+            }
+
+            data.0.get_data_box().age.set(age);
+            return true;
         });
+        for data in promoted.iter() {
+            data.0.get_data_box().age.set(OLD);
+        }
+        for data in promoted {
+            // promoted data can point at younger data that is still in the nursery
+            data.0.get_data_box().remembered.set(true);
+            self.remembered.insert(data.clone());
+            self.datas.insert(data);
+        }
 
-        // set generation of external items in current call frame. Some of these will probably be
-        // garbage immediately after this call, but that isn't current me's problem! We will catch
-        // them next collection!
-        let scopes_iter = scopes.iter();
-        let item_iter = scopes_iter
-            .map(|items|items.iter())
-            .flatten();
-        item_iter.for_each(|d|{
-            d.set_generation(generation);
-            let dr_ref = d.get_data();
-            dr_ref.add_data_refs(&mut todo_list);
+        self.remembered.retain(|data|{
+            let keep = data.0.get_data().has_young_refs();
+            data.0.get_data_box().remembered.set(keep);
+            return keep;
         });
 
-        // set generation of pinned and external data
-        let mut pinned_count = 0;
-        let mut external_count = 0;
-        let mut both_count = 0;
-        self.datas.iter()
-            .filter(|d|d.0.is_pinned() || d.0.is_external())
-            .for_each(|d|{
-                if d.0.is_pinned() && d.0.is_external() {
-                    both_count += 1;
-                } else if d.0.is_pinned() {
-                    pinned_count += 1;
-                } else if d.0.is_external() {
-                    external_count += 1;
-                }
+        log::debug!("Minor collection freed {free_count} data entries for a total of ~{dealloc_size} bytes. {} in the nursery, {} old", self.nursery.len(), self.datas.len());
 
-                d.0.set_generation(generation);
-                let dr_ref = d.0.get_data();
-                dr_ref.add_data_refs(&mut todo_list);
-            });
-        // eprintln!("{} total allocations, {pinned_count} pinned, {external_count} external, and {both_count} are both", self.datas.len());
+        self.stats.minor_collections += 1;
+        self.stats.record_pause(false, start.elapsed());
 
-        // remove any data that already has the generation set
-        todo_list.retain(|i|i.0.get_generation() != generation);
+        return free_count;
+    }
 
-        // iterate through anything left, adding all children and removing completed children until
-        // there are none left
-        let mut iter = 0;
-        while !todo_list.is_empty() {
-            let item = todo_list.pop().unwrap().0;
-            item.set_generation(generation);
-            item.get_data().add_data_refs(&mut todo_list);
+    /// Free everything that wasn't marked with the current generation. Everything that survives
+    /// is promoted, so the nursery is empty afterwards.
+    fn sweep(&mut self)->usize {
+        let generation = self.generation;
 
-            todo_list.retain(|i|i.0.get_generation() != generation);
-            iter += 1;
+        // nothing can point into an empty nursery. This has to happen first since some of the
+        // remembered data may be freed.
+        self.take_remembered();
+        for data in self.remembered.drain(..) {
+            data.0.get_data_box().remembered.set(false);
         }
 
-        if DEBUG {
-            eprintln!("DEBUG: Took {iter} iterations to set all reachable datas to the current generation");
+        let (old_count, old_size) = Self::sweep_set(&mut self.datas, generation, self.graveyard.as_mut());
+        let (young_count, young_size) = Self::sweep_set(&mut self.nursery, generation, self.graveyard.as_mut());
+        let free_count = old_count + young_count;
+        let dealloc_size = old_size + young_size;
+        self.deallocations += free_count;
+        self.stats.major_collections += 1;
+        self.stats.freed_objects += free_count as u64;
+        self.stats.freed_bytes += dealloc_size as u64;
+
+        for data in self.nursery.drain(..) {
+            data.0.get_data_box().age.set(OLD);
+            self.datas.insert(data);
         }
+        self.nursery_allocations = 0;
+
+        log::debug!("Freed {free_count} data entries for a total of ~{dealloc_size} bytes. {} remaining allocations", self.datas.len());
 
+        return free_count;
+    }
+
+    /// Free everything in `set` that wasn't marked with `generation`, or poison it and move it to
+    /// `graveyard` if given. Returns how many items and roughly how many bytes were freed.
+    fn sweep_set(set: &mut DataRefSet, generation: u64, mut graveyard: Option<&mut DataRefSet>)->(usize, usize) {
+        let mut free_count = 0;
         let mut dealloc_size = 0;
 
-        self.datas.retain(|data|{
+        set.retain(|data|{
             if data.0.get_generation() == generation {
                 return true;
             }
@@ -490,6 +1307,14 @@ impl DataStore {
 
             free_count += 1;
 
+            if let Some(graveyard) = graveyard.as_mut() {
+                *data.0.get_data_box().inner.try_borrow_mut()
+                    .expect("Freed data that is still borrowed. Something held a `DataRef` without rooting it!") = Data::None;
+                data.0.get_data_box().dead.set(true);
+                graveyard.insert(data.clone());
+                return false;
+            }
+
             // SAFETY: We have already shaken the tree, set all reachable datas, and otherwise made
             // sure this won't (maybe? pleeeease?) cause any UB or memory errors.
             // We are also going to remove this pointer after this function, so cloning and
@@ -501,17 +1326,19 @@ impl DataStore {
             return false;
         });
 
-        DEALLOCATIONS.with_borrow_mut(|d| *d += free_count);
-
-        if DEBUG {
-            eprintln!("Freed {free_count} data entries for a total of ~{dealloc_size} bytes. {} remaining allocations", self.datas.len());
-        }
-
-        return free_count;
+        return (free_count, dealloc_size);
     }
 }
 impl Drop for DataStore {
     fn drop(&mut self) {
+        // the barriers can't hold on to our data after it is freed
+        self.marking = None;
+        self.barriers.marking_generation.set(0);
+        self.barriers.regrayed.borrow_mut().clear();
+        self.barriers.remembered.borrow_mut().clear();
+        let nursery = mem::take(&mut self.nursery);
+        self.datas.extend(nursery);
+
         let mut diff = self.get_alloc_rem();
         let mut pinned = 0;
         let mut external = 0;
@@ -539,12 +1366,17 @@ impl Drop for DataStore {
             unsafe {
                 dr.dealloc();
             }
-            DEALLOCATIONS.with_borrow_mut(|d|*d += 1);
+            self.deallocations += 1;
+        }
+
+        // poisoned data was already counted as freed when it was swept
+        for dr in self.graveyard.take().into_iter().flatten().map(|h|h.0) {
+            // SAFETY: Same as above. Nothing should have touched this since it was poisoned.
+            unsafe {
+                dr.dealloc();
+            }
         }
 
-        let dealloc_count = DEALLOCATIONS.with(|d|*d.borrow());
-        let alloc_count = ALLOCATIONS.with(|a|*a.borrow());
-        let diff = alloc_count - dealloc_count;
-        assert!(diff == 0);
+        assert!(self.allocations == self.deallocations);
     }
 }
@@ -16,19 +16,51 @@ use std::{
     },
     io::{
         BufReader,
+        IsTerminal,
         stdin,
     },
+    fmt::{
+        Debug,
+        Formatter,
+        Result as FmtResult,
+    },
     rc::Rc,
     cell::RefCell,
     mem::replace,
 };
 use ast::*;
 use data::*;
+use threads::{
+    ThreadHandle,
+    ThreadPrints,
+};
+use crate::{
+    budget::Budget,
+    capabilities::Capabilities,
+    opcode_stats::OpcodeCounts,
+    output::Output,
+    gc_config::{
+        GcConfig,
+        GcStats,
+    },
+    error::{
+        LispError,
+        Signature,
+        StackTrace,
+        TraceFrame,
+    },
+    suggest::similar_names,
+    host::HostObject,
+    terminal::RawMode,
+    data_path,
+};
 
 
 pub mod ast;
 mod builtins;
 pub mod data;
+pub mod pretty;
+pub mod threads;
 // mod new_data;
 // mod perfect_hasher;
 
@@ -36,7 +68,12 @@ pub mod data;
 pub type CallStack = Stack<(InstructionId, Scopes)>;
 pub type Scopes = Stack<ScopeItem>;
 
+/// The builtins. Anything else, like the functions a host registers, is wrapped up in a
+/// `NativeFunc`.
 pub type NativeFn = fn(Vec<DataRef>, &mut Interpreter, &mut Interner)->Result<DataRef>;
+/// Builtins that call back into lisp with `Interpreter::call`, so they need all of the
+/// `ConvertState`
+pub type CallingNativeFn = fn(Vec<DataRef>, &mut Interpreter, &mut ConvertState)->Result<DataRef>;
 
 pub type IdentMap<T> = HashMap<Ident, T, FxBuildHasher>;
 pub type IdentSet = HashSet<Ident, FxBuildHasher>;
@@ -48,15 +85,85 @@ pub type IdentSet = HashSet<Ident, FxBuildHasher>;
 // pub type InsIdSet = HashSet<InstructionId, FxBuildHasher<Ident>>;
 
 
-const DEBUG: bool = false;
-
-
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ArgCount {
     Exact(usize),
     Any,
 }
+impl ArgCount {
+    pub fn signature(&self)->Signature {
+        match self {
+            Self::Exact(count)=>Signature::exact(*count),
+            Self::Any=>Signature::at_least(0),
+        }
+    }
+
+    pub fn matches(&self, count: usize)->bool {
+        match self {
+            Self::Exact(expected)=>*expected == count,
+            Self::Any=>true,
+        }
+    }
+}
+
+/// `ast::Fn` is already taken in here
+type NativeClosure = dyn std::ops::Fn(Vec<DataRef>, &mut Interpreter, &mut ConvertState)->Result<DataRef>;
+
+/// Reads a field of a host object. Fields are made with the engine's `Ctx`, so the engine sets
+/// this when it makes the interpreter.
+pub type HostFieldFn = Rc<dyn std::ops::Fn(&dyn HostObject, &str, &mut Interpreter, &mut ConvertState)->Option<DataRef>>;
+
+/// A native function. These get all of the `ConvertState` so they can call back into lisp with
+/// `Interpreter::call`. Two are only equal if they are the same closure.
+///
+/// Rust closures can't hold data, since the GC can't see what they hold. Natives that make
+/// functions, like `memoize`, put the data in `captures` instead. They are traced like a
+/// closure's captures and passed before the arguments.
+#[derive(Clone)]
+pub struct NativeFunc(Rc<NativeClosure>, Rc<[DataRef]>);
+impl NativeFunc {
+    pub fn new(f: impl std::ops::Fn(Vec<DataRef>, &mut Interpreter, &mut ConvertState)->Result<DataRef> + 'static)->Self {
+        NativeFunc(Rc::new(f), Rc::new([]))
+    }
+
+    /// `f` is called with `captures`, then the arguments
+    pub fn with_captures(f: CallingNativeFn, captures: Vec<DataRef>)->Self {
+        NativeFunc(Rc::new(f), captures.into())
+    }
+
+    #[inline]
+    pub fn captures(&self)->&[DataRef] {
+        &self.1
+    }
+
+    #[inline]
+    pub fn call(&self, args: Vec<DataRef>, interpreter: &mut Interpreter, state: &mut ConvertState)->Result<DataRef> {
+        if self.1.is_empty() {
+            return (self.0)(args, interpreter, state);
+        }
+
+        let mut all = self.1.to_vec();
+        all.extend(args);
+        return (self.0)(all, interpreter, state);
+    }
+}
+impl From<NativeFn> for NativeFunc {
+    fn from(f: NativeFn)->Self {
+        NativeFunc::new(move|args, interpreter, state|f(args, interpreter, &mut state.interner))
+    }
+}
+impl PartialEq for NativeFunc {
+    fn eq(&self, other: &Self)->bool {
+        std::ptr::addr_eq(Rc::as_ptr(&self.0), Rc::as_ptr(&other.0))
+    }
+}
+impl Debug for NativeFunc {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        write!(f, "NativeFunc({:p})", Rc::as_ptr(&self.0))
+    }
+}
 
+#[derive(Debug, Clone)]
 pub enum ScopeItem {
     List(Vec<DataRef>),
     Return(Option<DataRef>),
@@ -110,11 +217,31 @@ impl<'a> Iterator for ScopeItemIter<'a> {
 }
 
 
+/// An `Env` a suspended generator is holding. The vars aren't `external` in here, so the GC
+/// reaches them through the generator instead, and a generator that holds itself can be freed.
+#[derive(Debug, Clone)]
+pub struct SuspendedEnv {
+    /// The values of each var, the innermost last
+    vars: Vec<(Ident, Vec<DataRef>)>,
+    /// The innermost last
+    scopes: Vec<IdentSet>,
+    constants: IdentSet,
+}
+impl SuspendedEnv {
+    pub fn refs(&self)->impl Iterator<Item = &DataRef> {
+        self.vars.iter()
+            .flat_map(|(_, values)|values.iter())
+    }
+}
+
+
 /// We now have `ExternalData` to track `external` data for us. We can't forget to set/unset
 /// external because it is encoded into the type.
 pub struct Env {
     vars: IdentMap<Stack<ExternalData>>,
     scopes: Stack<IdentSet>,
+    /// The `defconst` vars. Only the var is constant, so a list in one can still be changed.
+    constants: IdentSet,
 }
 impl Default for Env {
     fn default()->Self {
@@ -130,6 +257,7 @@ impl Env {
         Env {
             vars,
             scopes: Stack::new(),
+            constants: IdentSet::default(),
         }
     }
 
@@ -154,6 +282,7 @@ impl Env {
     pub fn clear(&mut self)->usize {
         let mut count = 0;
         self.scopes.clear();
+        self.constants.clear();
 
         // retain the storage, but not the data. Try to avoid unnecessary allocations
         for (_, scope) in self.vars.iter_mut() {
@@ -164,6 +293,12 @@ impl Env {
         return count;
     }
 
+    /// Iterate the currently visible value of each variable in this env.
+    pub fn iter_vars(&self)->impl Iterator<Item = (Ident, &DataRef)> {
+        self.vars.iter()
+            .filter_map(|(name, values)|Some((*name, &**values.get(0)?)))
+    }
+
     pub fn var_count(&self)->usize {
         let mut total = 0;
         for scope in self.vars.values() {
@@ -188,6 +323,7 @@ impl Env {
 
                 if entry.len() == 0 {
                     self.vars.remove(&var);
+                    self.constants.remove(&var);
                 }
             }
 
@@ -217,6 +353,16 @@ impl Env {
         return None;
     }
 
+    #[inline]
+    pub fn make_constant(&mut self, name: Ident) {
+        self.constants.insert(name);
+    }
+
+    #[inline]
+    pub fn is_constant(&self, name: Ident)->bool {
+        self.constants.contains(&name)
+    }
+
     pub fn set(&mut self, name: Ident, data: DataRef)->Result<DataRef, DataRef> {
         if let Some(stack) = self.vars.get_mut(&name) {
             let old = replace(&mut stack[0], data.external());
@@ -235,6 +381,57 @@ impl Env {
 
         Some((*values[0]).clone())
     }
+
+    /// Move everything out for a generator to hold while it is suspended, leaving this empty.
+    /// Returns how many vars were moved.
+    pub fn suspend(&mut self)->(SuspendedEnv, usize) {
+        let mut count = 0;
+        let vars = self.vars.drain()
+            .filter(|(_, values)|values.len() > 0)
+            .map(|(name, mut values)|{
+                let mut out = Vec::with_capacity(values.len());
+                while let Some(value) = values.pop() {
+                    out.push(value.inner());
+                }
+                out.reverse();
+                count += out.len();
+
+                (name, out)
+            })
+            .collect();
+
+        let mut scopes = Vec::with_capacity(self.scopes.len());
+        while let Some(scope) = self.scopes.pop() {
+            scopes.push(scope);
+        }
+        scopes.reverse();
+
+        let env = SuspendedEnv {
+            vars,
+            scopes,
+            constants: std::mem::take(&mut self.constants),
+        };
+
+        return (env, count);
+    }
+
+    /// Put back what `suspend` moved out. This has to be empty. Returns how many vars it put back.
+    pub fn resume(&mut self, env: SuspendedEnv)->usize {
+        let mut count = 0;
+        for (name, values) in env.vars {
+            count += values.len();
+            let stack = self.vars.entry(name).or_insert_with(Stack::new);
+            for value in values {
+                stack.push(value.external());
+            }
+        }
+        for scope in env.scopes {
+            self.scopes.push(scope);
+        }
+        self.constants = env.constants;
+
+        return count;
+    }
 }
 impl Drop for Env {
     fn drop(&mut self) {
@@ -251,11 +448,63 @@ impl Drop for Env {
 #[derive(Debug, Copy, Clone, Default)]
 pub struct Metrics {
     pub instructions_executed: u64,
-    pub max_call_stack_depth: u16,
+    /// Never more than the interpreter's `max_stack_depth`
+    pub max_call_stack_depth: usize,
     pub total_run_time: Duration,
     pub last_run_time: Duration,
     pub allocations: u64,
     pub max_allocation_bytes: u64,
+    /// Updated when each run ends
+    pub gc: GcStats,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum FrameKind {
+    Fn(FnId),
+    Module(ModuleId),
+}
+
+/// A call that hasn't returned yet. `call_stack` has what we need to go back to the caller, this
+/// is for stack traces.
+#[derive(Debug, Copy, Clone)]
+struct Frame {
+    kind: FrameKind,
+    call_site: Option<InstructionId>,
+    /// How many tail calls replaced the frame
+    tail_calls: usize,
+}
+
+/// A call that was running when a run failed, kept for looking around afterwards. See
+/// `set_keep_post_mortem`.
+pub struct PostMortemFrame {
+    pub trace: TraceFrame,
+    /// Its variables as they were when the error happened
+    pub locals: Vec<(Ident, ExternalData)>,
+}
+
+/// The error for calling `dr` when it isn't a function
+fn not_callable(dr: &DataRef, interner: &Interner)->LispError {
+    LispError::NotCallable {
+        value: Some(pretty::preview(dr, interner, 40)),
+        actual: dr.get_data().type_name(),
+    }
+}
+
+/// The item `segment` picks out of a list of `len` items while walking `path`
+fn path_index(len: usize, segment: Ident, path: &[Ident], setting: bool, interner: &Interner)->Result<usize> {
+    match data_path::list_index(interner.get(segment), len) {
+        Some(Ok(index))=>Ok(index),
+        Some(Err(index))=>Err(data_path::out_of_bounds(path_text(path, interner), setting, index, len).into()),
+        None=>Err(dead_end_path(path, segment, "list", setting, interner).into()),
+    }
+}
+
+fn dead_end_path(path: &[Ident], segment: Ident, actual: &'static str, setting: bool, interner: &Interner)->LispError {
+    data_path::dead_end(path_text(path, interner), interner.get(segment), actual, setting)
+}
+
+fn path_text(path: &[Ident], interner: &Interner)->String {
+    data_path::text(path.iter().map(|i|interner.get(*i)))
 }
 
 pub struct Interpreter {
@@ -265,9 +514,60 @@ pub struct Interpreter {
     recur_ident: Ident,
     vtable_ident: Ident,
     call_stack: CallStack,
+    /// One for each item in `call_stack`
+    frames: Vec<Frame>,
+    /// The generators that are running, innermost last, with how deep `call_stack` was and which
+    /// function it was running when each one resumed
+    generators: Vec<(usize, FnId, DataRef)>,
+    /// What each module returned, by its first instruction. A module declared in several places
+    /// shares its instructions, so it only runs the first time.
+    module_values: HashMap<InstructionId, (ModuleId, ExternalData), FxBuildHasher>,
     scopes: Scopes,
     var_count: usize,
     data: DataStore,
+    /// The names `insert_builtins` put in the root env. Used to tell user globals apart from ours.
+    builtin_globals: IdentSet,
+    /// The globals the prelude defined. The program can redefine each of these once.
+    prelude_globals: IdentSet,
+    /// The globals and `prelude_globals` right after the builtins and prelude were set up, so
+    /// `reset_globals` can put them back
+    startup_globals: Vec<(Ident, ExternalData)>,
+    startup_prelude_globals: IdentSet,
+    /// Allows `def` to replace an existing global instead of erroring. The REPL needs this so
+    /// `:load`ing a file twice works.
+    allow_global_redefinition: bool,
+    /// The path of the script being run, if there is one
+    script: Option<String>,
+    /// The arguments passed to the script after `--`
+    script_args: Vec<String>,
+    gc_config: GcConfig,
+    /// Calling a function when the call stack is this deep is an error
+    max_stack_depth: usize,
+    budget: Budget,
+    host_field: Option<HostFieldFn>,
+    /// What natives may do outside of the interpreter
+    capabilities: Capabilities,
+    /// Whether `(breakpoint)` pauses. It never does if stdin isn't a terminal.
+    breakpoints: bool,
+    /// Save the calls and their variables when a run fails, before they are thrown away
+    keep_post_mortem: bool,
+    /// The calls of the last failed run, innermost first, if `keep_post_mortem` is on
+    post_mortem: Option<Vec<PostMortemFrame>>,
+    /// Only counted if `set_opcode_stats` turned it on
+    opcodes: Option<Box<OpcodeCounts>>,
+    /// Branching on anything but a bool or `none` is an error
+    strict_bool: bool,
+    /// Where natives write what the program prints
+    output: Box<dyn Output>,
+    /// Set by `std/term/forceColor`. Otherwise styles are only used if `output` is a terminal and
+    /// `NO_COLOR` isn't set.
+    force_color: Option<bool>,
+    /// Set by `std/term/rawMode`. Dropping it turns raw mode back off.
+    raw_mode: Option<RawMode>,
+    /// Every thread this spawned. Dropping the interpreter joins the ones nothing else joined.
+    threads: Vec<ThreadHandle>,
+    /// What those threads printed, until it is written to `output`
+    thread_prints: ThreadPrints,
     pub metrics: Metrics,
 }
 impl Drop for Interpreter {
@@ -281,20 +581,31 @@ impl Drop for Interpreter {
         // eprintln!("Remaining vars: {}, root var count: {root_var_count}", self.var_count);
 
         self.root_env.clear();
+        self.startup_globals.clear();
 
         // disown the callstack and scopes
         self.scopes.clear();
         self.call_stack.clear();
+        self.frames.clear();
+        self.generators.clear();
+        self.module_values.clear();
+        self.post_mortem = None;
 
         // finally, collect all of the data before we exit
         self.data.collect(&self.call_stack, &self.scopes);
+
+        // threads don't share data with us, so they can finish after it is gone
+        self.join_threads();
     }
 }
 impl Interpreter {
-    pub fn new<'a>(state: &mut ConvertState)->Self {
+    pub fn new<'a>(state: &mut ConvertState, gc_config: GcConfig, max_stack_depth: usize, max_instructions: Option<u64>, output: Box<dyn Output>)->Self {
         let mut root_env = Env::new();
         root_env.push_scope();
-        let data = DataStore::new();
+        let mut data = DataStore::new();
+        if gc_config.stress {
+            data.poison_freed();
+        }
 
         // println!("Line: {}", line!());
 
@@ -311,24 +622,427 @@ impl Interpreter {
             recur_ident: state.interner.intern("recur"),
             vtable_ident: state.interner.intern("$"),
             call_stack: Stack::new(),
+            frames: Vec::new(),
+            generators: Vec::new(),
+            module_values: HashMap::default(),
             scopes: Stack::new(),
+            builtin_globals: IdentSet::default(),
+            prelude_globals: IdentSet::default(),
+            startup_globals: Vec::new(),
+            startup_prelude_globals: IdentSet::default(),
+            allow_global_redefinition: false,
+            script: None,
+            script_args: Vec::new(),
+            gc_config,
+            max_stack_depth,
+            budget: Budget::new(max_instructions, None),
+            host_field: None,
+            capabilities: Capabilities::default(),
+            breakpoints: true,
+            keep_post_mortem: false,
+            post_mortem: None,
+            opcodes: None,
+            strict_bool: false,
+            output,
+            force_color: None,
+            raw_mode: None,
+            threads: Vec::new(),
+            thread_prints: ThreadPrints::new(),
             metrics: Metrics::default(),
         };
 
         out.insert_builtins(state);
+        out.run_prelude(state);
+        out.save_startup_globals();
 
         return out;
     }
 
+    /// Drop every variable and all of the data, then start over with just the builtins. `state`
+    /// should be a fresh `ConvertState` since the old functions and idents are meaningless now.
+    pub fn reset(&mut self, state: &mut ConvertState) {
+        while self.env_stack.len() > 0 {
+            self.pop_env();
+        }
+        self.old_envs.clear();
+        self.root_env.clear();
+        self.startup_globals.clear();
+        self.scopes.clear();
+        self.call_stack.clear();
+        self.frames.clear();
+        self.generators.clear();
+        self.module_values.clear();
+        self.post_mortem = None;
+
+        // collect what we can first so the leak check in `DataStore::drop` only sees the pinned
+        // builtins
+        self.data.collect(&self.call_stack, &self.scopes);
+        self.data = DataStore::new();
+        if self.gc_config.stress {
+            self.data.poison_freed();
+        }
+
+        self.root_env.push_scope();
+        self.var_count = 0;
+        self.recur_ident = state.interner.intern("recur");
+        self.vtable_ident = state.interner.intern("$");
+        self.reset_metrics();
+
+        self.insert_builtins(state);
+        self.run_prelude(state);
+        self.save_startup_globals();
+    }
+
+    /// Drop every global the program defined and put the builtins and prelude back the way they
+    /// started, then collect whatever that freed. Unlike `reset` this keeps the data store and
+    /// the `ConvertState`, so nothing has to be set up again. Modules run again the next time
+    /// they are used.
+    pub fn reset_globals(&mut self) {
+        while self.env_stack.len() > 0 {
+            self.pop_env();
+        }
+        self.old_envs.clear();
+        self.scopes.clear();
+        self.call_stack.clear();
+        self.frames.clear();
+        self.generators.clear();
+        self.module_values.clear();
+        self.post_mortem = None;
+
+        // `clear` keeps the names around, and they would look defined without a value
+        self.root_env = Env::new();
+        self.root_env.push_scope();
+        for (name, data) in self.startup_globals.iter() {
+            self.root_env.insert(*name, (**data).clone());
+        }
+        self.var_count = self.root_env.var_count();
+        self.builtin_globals = self.startup_globals.iter()
+            .map(|(name, _)|*name)
+            .collect();
+        self.prelude_globals = self.startup_prelude_globals.clone();
+        self.reset_metrics();
+
+        self.data.collect(&self.call_stack, &self.scopes);
+    }
+
+    fn save_startup_globals(&mut self) {
+        self.startup_globals = self.root_env.iter_vars()
+            .map(|(name, data)|(name, data.clone().external()))
+            .collect();
+        self.startup_prelude_globals = self.prelude_globals.clone();
+    }
+
+    /// Run the prelude if `state` has one. Its globals count as builtins until the program
+    /// redefines them.
+    fn run_prelude(&mut self, state: &mut ConvertState) {
+        let Some(start) = state.prelude else {return};
+        if let Err(e) = self.run(state, Some(start)) {
+            panic!("The prelude failed to run: {e}");
+        }
+
+        let builtins = &self.builtin_globals;
+        self.prelude_globals = self.root_env.iter_vars()
+            .map(|(name, _)|name)
+            .filter(|name|!builtins.contains(name))
+            .collect();
+        self.builtin_globals.extend(self.prelude_globals.iter().copied());
+        // the stats are for the program
+        self.reset_metrics();
+    }
+
     pub fn get_data_store(&self)->&DataStore {
         &self.data
     }
 
+    /// Iterate all of the variables in the global scope. Use the interner to get the names back.
+    pub fn globals(&self)->impl Iterator<Item = (Ident, &DataRef)> {
+        self.root_env.iter_vars()
+    }
+
+    /// Define or replace a global variable. This ignores `allow_global_redefinition` since it is
+    /// only used by the host, not user code.
+    pub fn define_global(&mut self, name: Ident, data: DataRef) {
+        if self.root_env.insert(name, data).is_none() {
+            self.var_count += 1;
+        }
+    }
+
+    pub fn get_global(&self, name: Ident, interner: &Interner)->Option<DataRef> {
+        self.root_env.get(name, interner)
+    }
+
+    /// Set the script path and its arguments. These are exposed as `*script*`, `*args*`, and
+    /// `(args)`.
+    pub fn set_script_args(&mut self, script: Option<String>, args: Vec<String>, state: &mut ConvertState) {
+        self.script = script;
+        self.script_args = args;
+        self.define_script_args(state);
+    }
+
+    #[inline]
+    pub fn script_args(&self)->&[String] {
+        &self.script_args
+    }
+
+    fn define_script_args(&mut self, state: &mut ConvertState) {
+        let args = self.script_args.iter()
+            .map(|a|self.data.insert(Data::String(a.clone())))
+            .collect();
+        let args_dr = self.data.insert(Data::List(args));
+        self.define_global(state.intern("*args*"), args_dr);
+
+        let script_dr = match &self.script {
+            Some(s)=>self.data.insert(Data::String(s.clone())),
+            None=>self.data.insert(Data::None),
+        };
+        self.define_global(state.intern("*script*"), script_dr);
+    }
+
+    /// Errors if calling `func` would go past the max stack depth
+    fn check_stack_depth(&self, func: &ast::Fn, state: &ConvertState)->Result<()> {
+        if self.call_stack.len() < self.max_stack_depth {
+            return Ok(());
+        }
+
+        bail!(LispError::RecursionLimit{
+            max: self.max_stack_depth,
+            name: func.name.map(|name|state.interner.get(name).to_string()),
+        });
+    }
+
+    /// The calls made after the call stack was `call_depth` deep, innermost first
+    fn stack_trace(&self, call_depth: usize, state: &ConvertState)->Vec<TraceFrame> {
+        self.frames[call_depth.min(self.frames.len())..]
+            .iter()
+            .rev()
+            .map(|frame|{
+                let name = match frame.kind {
+                    FrameKind::Fn(id)=>match state.fns.get(id).and_then(|f|f.name) {
+                        Some(name)=>format!("`{}`", state.interner.get(name)),
+                        None=>"<anonymous>".into(),
+                    },
+                    FrameKind::Module(id)=>format!("module `{}`", state.interner.get(state.modules.get(id).name)),
+                };
+
+                TraceFrame {
+                    name,
+                    call_site: frame.call_site.map(|id|id.inner()),
+                    tail_calls: frame.tail_calls,
+                }
+            })
+            .collect()
+    }
+
+    /// Save every call that is running and its variables if `keep_post_mortem` is on. A native
+    /// that calls back into us unwinds its part of the stack before the run it is in does, so only
+    /// the first save after an error is kept.
+    fn save_post_mortem(&mut self, state: &ConvertState) {
+        if !self.keep_post_mortem || self.post_mortem.is_some() {
+            return;
+        }
+
+        // every frame has its own env, pushed right after it
+        let recur_ident = self.recur_ident;
+        let frames = self.stack_trace(0, state)
+            .into_iter()
+            .zip(self.env_stack.iter())
+            .map(|(trace, env)|PostMortemFrame {
+                trace,
+                locals: env.iter_vars()
+                    .filter(|(name, _)|*name != recur_ident)
+                    .map(|(name, dr)|(name, dr.clone().external()))
+                    .collect(),
+            })
+            .collect();
+        self.post_mortem = Some(frames);
+    }
+
+    /// Throw away everything a failed run left on the stacks so the next run starts clean
+    fn unwind(&mut self, call_depth: usize, env_depth: usize, scope_depth: usize) {
+        while self.call_stack.len() > call_depth {
+            let (_, scopes) = self.call_stack.pop().unwrap();
+            self.scopes = scopes;
+        }
+        self.frames.truncate(call_depth);
+        while self.env_stack.len() > env_depth {
+            self.pop_env();
+        }
+        while self.scopes.len() > scope_depth {
+            self.scopes.pop();
+        }
+    }
+
+    #[inline]
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Errors unless every one of `needed` is allowed. Natives call this before they do anything.
+    pub fn require(&self, needed: Capabilities)->Result<()> {
+        let denied = needed.difference(self.capabilities);
+        if !denied.is_empty() {
+            bail!(LispError::CapabilityDenied{capability: denied.name()});
+        }
+
+        return Ok(());
+    }
+
+    /// Limit how long each call to `run` may take
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.budget.timeout = timeout;
+    }
+
+    #[inline]
+    pub fn set_host_field(&mut self, f: HostFieldFn) {
+        self.host_field = Some(f);
+    }
+
+    #[inline]
+    pub fn set_allow_global_redefinition(&mut self, allow: bool) {
+        self.allow_global_redefinition = allow;
+    }
+
+    #[inline]
+    pub fn set_breakpoints(&mut self, enabled: bool) {
+        self.breakpoints = enabled;
+    }
+
+    /// Keep the calls that were running and their variables when a run fails. They would be
+    /// thrown away otherwise. Get them with `take_post_mortem`.
+    #[inline]
+    pub fn set_keep_post_mortem(&mut self, keep: bool) {
+        self.keep_post_mortem = keep;
+    }
+
+    /// The calls that were running when the last run failed, innermost first. Their variables stay
+    /// alive until these are dropped.
+    pub fn take_post_mortem(&mut self)->Option<Vec<PostMortemFrame>> {
+        self.post_mortem.take()
+    }
+
+    /// Make branching on anything but a bool or `none` an error
+    pub fn set_strict_bool(&mut self, strict: bool) {
+        self.strict_bool = strict;
+    }
+
+    /// Whether `data` counts as true. `op` is what to blame in strict mode.
+    pub fn is_truthy(&self, op: &str, data: &DataRef)->Result<bool> {
+        return data.try_get_data(op)?.truth().is_truthy(op, self.strict_bool);
+    }
+
+    /// Start or stop counting how many times each kind of instruction runs
+    pub fn set_opcode_stats(&mut self, enabled: bool) {
+        self.opcodes = enabled.then(||Box::new(OpcodeCounts::new(Instruction::OPCODE_NAMES)));
+    }
+
+    /// Send what the program prints to `output` instead
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = output;
+    }
+
+    #[inline]
+    pub fn output(&mut self)->&mut dyn Output {
+        &mut *self.output
+    }
+
+    /// Always or never style text, or go back to deciding from the output with `None`
+    pub fn set_force_color(&mut self, force: Option<bool>) {
+        self.force_color = force;
+    }
+
+    /// Turn the terminal's raw mode on or off. It is turned off when the interpreter is dropped.
+    pub fn set_raw_mode(&mut self, enabled: bool)->Result<()> {
+        match (enabled, self.raw_mode.is_some()) {
+            (true, false)=>self.raw_mode = Some(RawMode::enable().map_err(LispError::from)?),
+            (false, true)=>self.raw_mode = None,
+            _=>{},
+        }
+
+        return Ok(());
+    }
+
+    /// Whether `std/term/style` should add escape codes. See <https://no-color.org>.
+    pub fn color_enabled(&self)->bool {
+        if let Some(force) = self.force_color {
+            return force;
+        }
+
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v|!v.is_empty());
+        return !no_color && self.output.is_terminal();
+    }
+
+    fn reset_metrics(&mut self) {
+        self.metrics = Metrics::default();
+        if let Some(opcodes) = &mut self.opcodes {
+            opcodes.reset();
+        }
+    }
+
+    /// `None` unless `set_opcode_stats` turned them on
+    pub fn opcode_counts(&self)->Option<&OpcodeCounts> {
+        self.opcodes.as_deref()
+    }
+
+    /// The function that is running, or `None` at the top level and in modules
+    pub fn current_fn(&self)->Option<FnId> {
+        match self.frames.last()?.kind {
+            FrameKind::Fn(id)=>Some(id),
+            FrameKind::Module(_)=>None,
+        }
+    }
+
+    /// Iterate the variables of the function or module that is running. Empty at the top level.
+    pub fn locals(&self)->impl Iterator<Item = (Ident, &DataRef)> {
+        let recur_ident = self.recur_ident;
+        self.env_stack.get(0)
+            .into_iter()
+            .flat_map(|env|env.iter_vars())
+            .filter(move|(name, _)|*name != recur_ident)
+    }
+
+    /// Run code converted while the program is paused. It sees the paused call's variables and can
+    /// change them, and whatever it leaves in the scopes is thrown away afterwards.
+    pub fn run_in_frame(&mut self, state: &mut ConvertState, start_id: InstructionId)->Result<Option<DataRef>> {
+        let scope_depth = self.scopes.len();
+        let res = self.run(state, Some(start_id));
+        while self.scopes.len() > scope_depth {
+            self.scopes.pop();
+        }
+
+        return res;
+    }
+
+    /// Pause at a `(breakpoint)` until the user continues. Errors if they abort.
+    fn breakpoint(&mut self, state: &mut ConvertState)->Result<()> {
+        if !self.breakpoints {
+            eprintln!("Warning: skipping `(breakpoint)` because breakpoints are disabled");
+            return Ok(());
+        }
+        if !stdin().is_terminal() {
+            eprintln!("Warning: skipping `(breakpoint)` because stdin is not a terminal");
+            return Ok(());
+        }
+
+        crate::repl::breakpoint_repl(self, state)?;
+
+        // the time spent paused doesn't count towards the timeout
+        self.budget.start();
+
+        return Ok(());
+    }
+
+    /// Returns true if `name` was defined by the interpreter instead of user code.
+    #[inline]
+    pub fn is_builtin_global(&self, name: Ident)->bool {
+        self.builtin_globals.contains(&name)
+    }
+
     fn insert_builtins(&mut self, state: &mut ConvertState) {
         let mut core_object = IdentMap::default();
         for (name, func, arg_count) in builtins::core::BUILTINS.into_iter() {
             let ident = state.interner.intern(*name);
-            let data = self.data.insert(Data::NativeFn(name, *func, *arg_count));
+            let data = self.data.insert(Data::NativeFn((*name).into(), (*func).into(), *arg_count));
             data.set_pinned();
             core_object.insert(ident, data);
         }
@@ -337,7 +1051,7 @@ impl Interpreter {
         // Math operations are imported at the root level by default
         for (name, func, arg_count) in builtins::arithmetic::BUILTINS.into_iter() {
             let ident = state.interner.intern(*name);
-            let data = self.data.insert(Data::NativeFn(name, *func, *arg_count));
+            let data = self.data.insert(Data::NativeFn((*name).into(), (*func).into(), *arg_count));
             data.set_pinned();
             self.root_env.insert(ident, data);
         }
@@ -345,7 +1059,7 @@ impl Interpreter {
         let mut string_object = IdentMap::default();
         for (name, func, arg_count) in builtins::string::BUILTINS.into_iter() {
             let ident = state.interner.intern(*name);
-            let data = self.data.insert(Data::NativeFn(name, *func, *arg_count));
+            let data = self.data.insert(Data::NativeFn((*name).into(), (*func).into(), *arg_count));
             data.set_pinned();
             string_object.insert(ident, data);
         }
@@ -353,7 +1067,7 @@ impl Interpreter {
         let mut misc_object = IdentMap::default();
         for (name, func, arg_count) in builtins::misc::BUILTINS.into_iter() {
             let ident = state.interner.intern(*name);
-            let data = self.data.insert(Data::NativeFn(name, *func, *arg_count));
+            let data = self.data.insert(Data::NativeFn((*name).into(), (*func).into(), *arg_count));
             data.set_pinned();
             misc_object.insert(ident, data);
         }
@@ -361,11 +1075,59 @@ impl Interpreter {
         let mut io_object = IdentMap::default();
         for (name, func, arg_count) in builtins::io::BUILTINS.into_iter() {
             let ident = state.interner.intern(*name);
-            let data = self.data.insert(Data::NativeFn(name, *func, *arg_count));
+            let data = self.data.insert(Data::NativeFn((*name).into(), (*func).into(), *arg_count));
             data.set_pinned();
             io_object.insert(ident, data);
         }
 
+        let mut path_object = IdentMap::default();
+        for (name, func, arg_count) in builtins::path::BUILTINS.into_iter() {
+            let ident = state.interner.intern(*name);
+            let data = self.data.insert(Data::NativeFn((*name).into(), (*func).into(), *arg_count));
+            data.set_pinned();
+            path_object.insert(ident, data);
+        }
+
+        let mut temp_object = IdentMap::default();
+        for (name, func, arg_count) in builtins::temp::BUILTINS.into_iter() {
+            let ident = state.interner.intern(*name);
+            let data = self.data.insert(Data::NativeFn((*name).into(), NativeFunc::new(*func), *arg_count));
+            data.set_pinned();
+            temp_object.insert(ident, data);
+        }
+
+        let mut term_object = IdentMap::default();
+        for (name, func, arg_count) in builtins::term::BUILTINS.into_iter() {
+            let ident = state.interner.intern(*name);
+            let data = self.data.insert(Data::NativeFn((*name).into(), (*func).into(), *arg_count));
+            data.set_pinned();
+            term_object.insert(ident, data);
+        }
+
+        let mut func_object = IdentMap::default();
+        for (name, func, arg_count) in builtins::func::BUILTINS.into_iter() {
+            let ident = state.interner.intern(*name);
+            let data = self.data.insert(Data::NativeFn((*name).into(), NativeFunc::new(*func), *arg_count));
+            data.set_pinned();
+            func_object.insert(ident, data);
+        }
+
+        let mut seq_object = IdentMap::default();
+        for (name, func, arg_count) in builtins::seq::BUILTINS.into_iter() {
+            let ident = state.interner.intern(*name);
+            let data = self.data.insert(Data::NativeFn((*name).into(), NativeFunc::new(*func), *arg_count));
+            data.set_pinned();
+            seq_object.insert(ident, data);
+        }
+
+        let mut thread_object = IdentMap::default();
+        for (name, func, arg_count) in builtins::thread::BUILTINS.into_iter() {
+            let ident = state.interner.intern(*name);
+            let data = self.data.insert(Data::NativeFn((*name).into(), NativeFunc::new(*func), *arg_count));
+            data.set_pinned();
+            thread_object.insert(ident, data);
+        }
+
         let stdout_dr = self.data.insert(Data::NativeData(NativeData::Stdout));
         let stdin = Rc::new(RefCell::new(BufReader::new(stdin())));
         let stdin_dr = self.data.insert(Data::NativeData(NativeData::Stdin(stdin)));
@@ -378,20 +1140,78 @@ impl Interpreter {
         let string_data = self.data.insert(Data::Object(string_object));
         let misc_data = self.data.insert(Data::Object(misc_object));
         let io_data = self.data.insert(Data::Object(io_object));
+        let path_data = self.data.insert(Data::Object(path_object));
+        let temp_data = self.data.insert(Data::Object(temp_object));
+        let term_data = self.data.insert(Data::Object(term_object));
+        let func_data = self.data.insert(Data::Object(func_object));
+        let seq_data = self.data.insert(Data::Object(seq_object));
+        let thread_data = self.data.insert(Data::Object(thread_object));
 
         let mut std_object = IdentMap::default();
         std_object.insert(state.intern("string"), string_data);
         std_object.insert(state.intern("misc"), misc_data);
         std_object.insert(state.intern("io"), io_data);
+        std_object.insert(state.intern("path"), path_data);
+        std_object.insert(state.intern("temp"), temp_data);
+        std_object.insert(state.intern("term"), term_data);
+        std_object.insert(state.intern("func"), func_data);
+        std_object.insert(state.intern("seq"), seq_data);
+        std_object.insert(state.intern("thread"), thread_data);
 
         self.root_env.insert(state.intern("std"), self.data.insert(Data::Object(std_object)));
+
+        // `(args)` lives at the root level so scripts don't have to go digging for it
+        let args_data = self.data.insert(Data::NativeFn("args".into(), (builtins::core::args as NativeFn).into(), ArgCount::Exact(0)));
+        args_data.set_pinned();
+        self.root_env.insert(state.intern("args"), args_data);
+        self.define_script_args(state);
+
+        self.builtin_globals = self.root_env.iter_vars()
+            .map(|(name, _)|name)
+            .collect();
+    }
+
+    /// Run a collection unless the GC is disabled. Returns how many items were freed.
+    /// How many objects there are, including the ones the next collection will free
+    #[inline]
+    pub fn live_data(&self)->usize {
+        self.data.live_count()
     }
 
     pub fn gc_collect(&mut self)->usize {
+        if self.gc_config.disabled {return 0}
+
         let allocation_bytes = self.data.get_alloc_rem() as u64;
         self.metrics.max_allocation_bytes = self.metrics.max_allocation_bytes.max(allocation_bytes);
 
-        self.data.collect(&self.call_stack, &self.scopes)
+        return self.data.collect(&self.call_stack, &self.scopes);
+    }
+
+    /// Called between instructions. Continues the running incremental collection, or starts one
+    /// if enough was allocated. Does a full collection if `slice_size` is 0. Otherwise collects
+    /// the nursery if it is full.
+    fn gc_step(&mut self) {
+        if !self.data.is_collecting() {
+            if !self.data.should_collect(&self.gc_config) {
+                if self.data.should_collect_minor(&self.gc_config) {
+                    self.data.minor_collect(&self.call_stack, &self.scopes, self.gc_config.promote_after);
+                }
+
+                return;
+            }
+
+            if self.gc_config.slice_size == 0 {
+                self.gc_collect();
+                return;
+            }
+
+            let allocation_bytes = self.data.get_alloc_rem() as u64;
+            self.metrics.max_allocation_bytes = self.metrics.max_allocation_bytes.max(allocation_bytes);
+
+            self.data.start_collection(&self.call_stack, &self.scopes);
+        }
+
+        self.data.collect_slice(self.gc_config.slice_size, &self.call_stack, &self.scopes);
     }
 
     pub fn push_env(&mut self) {
@@ -429,19 +1249,27 @@ impl Interpreter {
     pub fn define_var(&mut self, var: Ident, data: DataRef, interner: &Interner)->Result<()> {
         // println!("Define var {} with data {data:?}", interner.get(var));
 
+        self.check_constant(var, interner)?;
+
         self.var_count += 1;
 
         if self.env_stack.len() > 0 {
             match self.env_stack[0].insert(var, data) {
                 Some(_)=>{
-                    bail!("Var `{}` is already defined", interner.get(var));
+                    bail!(LispError::AlreadyDefined{name: interner.get(var).to_string(), span: None});
                 },
                 _=>{},
             }
         } else {
             match self.root_env.insert(var, data) {
+                // the old value was replaced, so the var count stays the same
+                Some(_) if self.prelude_globals.remove(&var)=>{
+                    self.builtin_globals.remove(&var);
+                    self.var_count -= 1;
+                },
+                Some(_) if self.allow_global_redefinition=>self.var_count -= 1,
                 Some(_)=>{
-                    bail!("Var `{}` is already defined", interner.get(var));
+                    bail!(LispError::AlreadyDefined{name: interner.get(var).to_string(), span: None});
                 },
                 _=>{},
             }
@@ -453,11 +1281,50 @@ impl Interpreter {
     pub fn set_var(&mut self, var: Ident, data: DataRef, interner: &Interner)->Result<()> {
         // println!("Set var {} with data {data:?}", interner.get(var));
 
+        self.check_constant(var, interner)?;
+
+        let binding = match self.env_stack.len() > 0 {
+            true=>self.env_stack[0].get(var, interner),
+            false=>self.root_env.get(var, interner),
+        };
+        if let Some(mut cell) = binding.filter(|dr|matches!(dr.try_get_data("set").as_deref(), Ok(Data::Cell(_)))) {
+            *cell.try_get_data_mut("set")? = Data::Cell(data);
+            return Ok(());
+        }
+
+        return self.rebind_var(var, data, interner);
+    }
+
+    /// The env `define_var` and `set_var` use
+    #[inline]
+    fn current_env(&mut self)->&mut Env {
+        match self.env_stack.len() > 0 {
+            true=>&mut self.env_stack[0],
+            false=>&mut self.root_env,
+        }
+    }
+
+    /// Error if `var` is a `defconst` of the current env
+    fn check_constant(&mut self, var: Ident, interner: &Interner)->Result<()> {
+        if self.current_env().is_constant(var) {
+            bail!(LispError::Constant{name: interner.get(var).to_string(), span: None});
+        }
+
+        return Ok(());
+    }
+
+    /// Returns true if the global `var` is a `defconst`
+    pub fn is_constant(&self, var: Ident)->bool {
+        self.root_env.is_constant(var)
+    }
+
+    /// Point `var` at `data`, even if it is in a cell
+    fn rebind_var(&mut self, var: Ident, data: DataRef, interner: &Interner)->Result<()> {
         if self.env_stack.len() > 0 {
             match self.env_stack[0].set(var, data) {
                 Ok(_)=>{},
                 Err(_)=>{
-                    bail!("Attempt to set an undefined variable: `{}`", interner.get(var));
+                    bail!(self.undefined_var(var, interner));
                 },
             }
         } else {
@@ -465,7 +1332,7 @@ impl Interpreter {
                 Ok(dr)=>dr.unset_external(),
                 Err(dr)=>{
                     dr.unset_external();
-                    bail!("Attempt to set an undefined variable: `{}`", interner.get(var));
+                    bail!(self.undefined_var(var, interner));
                 },
             }
         }
@@ -473,7 +1340,34 @@ impl Interpreter {
         return Ok(());
     }
 
+    /// The error for using `var` when it isn't defined, with the visible vars it might be a typo
+    /// of.
+    fn undefined_var(&self, var: Ident, interner: &Interner)->LispError {
+        let name = interner.get(var);
+        let visible = self.env_stack.get(0)
+            .into_iter()
+            .flat_map(|env|env.iter_vars())
+            .chain(self.root_env.iter_vars())
+            .map(|(i, _)|interner.get(i));
+
+        return LispError::UndefinedVar {
+            name: name.to_string(),
+            span: None,
+            suggestions: similar_names(name, visible),
+        };
+    }
+
     pub fn get_var(&self, var: Ident, interner: &Interner)->Result<DataRef> {
+        let dr = self.lookup_var(var, interner)?;
+        if let Data::Cell(inner) = &*dr.try_get_data("get")? {
+            return Ok(inner.clone());
+        }
+
+        return Ok(dr);
+    }
+
+    /// Same as `get_var`, but a var in a cell gives the cell
+    fn lookup_var(&self, var: Ident, interner: &Interner)->Result<DataRef> {
         // println!("Get var {}", interner.get(var));
 
         if self.env_stack.len() > 0 {
@@ -486,15 +1380,69 @@ impl Interpreter {
             return Ok(dr);
         }
 
-        bail!("Attempt to access undefined variable: `{}`", interner.get(var));
+        bail!(self.undefined_var(var, interner));
+    }
+
+    /// Move `var` into a cell and return the cell, so the closures that capture it share it with
+    /// this frame. Vars that are already in a cell keep it.
+    fn box_var(&mut self, var: Ident, interner: &Interner)->Result<DataRef> {
+        let dr = self.lookup_var(var, interner)?;
+        if matches!(&*dr.try_get_data("capture")?, Data::Cell(_)) {
+            return Ok(dr);
+        }
+
+        let cell = self.alloc(Data::Cell(dr));
+        self.rebind_var(var, cell.clone(), interner)?;
+
+        return Ok(cell);
     }
 
     #[inline]
     pub fn alloc(&mut self, data: Data)->DataRef {
+        if self.gc_config.stress {
+            self.stress_collect(&data);
+        }
+
         self.metrics.allocations += 1;
         self.data.insert(data)
     }
 
+    /// With `GcConfig::stress` we collect before every allocation, so anything that isn't rooted
+    /// is freed right away. Nothing else references what `data` holds yet, so it is rooted until
+    /// the collection is done.
+    #[cold]
+    fn stress_collect(&mut self, data: &Data) {
+        if !self.data.has_new_allocations() {return}
+
+        self.scopes.push(ScopeItem::List(data.data_refs()));
+        self.gc_collect();
+        self.scopes.pop();
+    }
+
+    /// Keep `dr` alive until the native function that is running returns. See the rooting rules in
+    /// `builtins`.
+    #[inline]
+    pub fn root(&mut self, dr: &DataRef) {
+        self.push_dr_to_scope(dr.clone());
+    }
+
+    /// Native functions get their own scope so `root` has somewhere to put things. With
+    /// `GcConfig::stress` the arguments and the function itself are put there too, since they
+    /// were already popped from the caller's scope.
+    fn call_native(&mut self, f: NativeFunc, func: &DataRef, args: Vec<DataRef>, state: &mut ConvertState)->Result<DataRef> {
+        let mut roots = Vec::new();
+        if self.gc_config.stress {
+            roots.extend(args.iter().cloned());
+            roots.push(func.clone());
+        }
+
+        self.scopes.push(ScopeItem::List(roots));
+        let out = f.call(args, self, state);
+        self.scopes.pop();
+
+        return out;
+    }
+
     #[inline]
     pub fn clone_data(&mut self, dr: &DataRef)->DataRef {
         self.alloc(dr.get_data().clone())
@@ -525,6 +1473,219 @@ impl Interpreter {
     // TODO: Make `DataStore` aware of the data in `scopes` and `call_stack` before we do a GC and
     // cause a use-after-free bug
     pub fn run(&mut self, state: &mut ConvertState, start_id: Option<InstructionId>)->Result<Option<DataRef>> {
+        let call_depth = self.call_stack.len();
+        let env_depth = self.env_stack.len();
+        let scope_depth = self.scopes.len();
+        if call_depth == 0 {
+            self.post_mortem = None;
+        }
+
+        let res = self.run_inner(state, start_id)
+            .map_err(|e|StackTrace::wrap(e, self.stack_trace(call_depth, state)));
+        if res.is_err() {
+            self.save_post_mortem(state);
+            self.unwind(call_depth, env_depth, scope_depth);
+        }
+
+        let gc_stats = self.data.take_stats();
+        self.metrics.gc.merge(&gc_stats);
+
+        return res;
+    }
+
+    /// Call `func` with `args` and return what it returns. Unlike `run` this can be used while
+    /// something else is running, like from inside a native function.
+    pub fn call(&mut self, state: &mut ConvertState, func: DataRef, args: Vec<DataRef>)->Result<DataRef> {
+        let call_depth = self.call_stack.len();
+        let env_depth = self.env_stack.len();
+        let scope_depth = self.scopes.len();
+
+        let res = self.call_inner(state, func, args)
+            .map_err(|e|StackTrace::wrap(e, self.stack_trace(call_depth, state)));
+        if res.is_err() {
+            self.save_post_mortem(state);
+            self.unwind(call_depth, env_depth, scope_depth);
+        }
+
+        return res;
+    }
+
+    fn call_inner(&mut self, state: &mut ConvertState, func: DataRef, args: Vec<DataRef>)->Result<DataRef> {
+        let data = func.try_get_data("call")?;
+        let (id, captures) = match &*data {
+            Data::NativeFn(name, f, arg_count)=>{
+                if !arg_count.matches(args.len()) {
+                    bail!(LispError::Arity{name: Some(name.to_string()), expected: vec![arg_count.signature()], got: args.len()});
+                }
+                let f = f.clone();
+                drop(data);
+
+                return self.call_native(f, &func, args, state);
+            },
+            Data::Fn(id)=>(*id, None),
+            Data::Closure{id, captures}=>(*id, Some(captures.0.clone())),
+            data=>bail!(LispError::Type{op: "call".into(), expected: "fn", actual: data.type_name()}),
+        };
+        drop(data);
+
+        self.debug_call(id, state);
+        let func_def = state.get_fn(id)?.clone();
+        self.check_stack_depth(&func_def, state)?;
+        let Some((params, body_ptr)) = func_def.sig.match_arg_count(args.len()) else {
+            bail!(LispError::Arity{
+                name: func_def.name.map(|name|state.interner.get(name).to_string()),
+                expected: func_def.sig.signatures(&state.interner),
+                got: args.len(),
+            });
+        };
+
+        // the function returns into a scope of its own on top of the caller's, then runs into an
+        // `Exit` so `run_inner` stops and hands back what it returned
+        let exit_id = state.call_exit();
+        self.scopes.push(ScopeItem::Return(None));
+        let old_scopes = replace(&mut self.scopes, Stack::new());
+        self.call_stack.push((exit_id, old_scopes));
+        self.frames.push(Frame {
+            kind: FrameKind::Fn(id),
+            call_site: None,
+            tail_calls: 0,
+        });
+        self.scopes.push(ScopeItem::Return(None));
+        self.push_env();
+        self.push_env_scope();
+
+        if let Some(captures) = captures {
+            for (name, data) in captures {
+                self.define_var(name, data, &state.interner)?;
+            }
+            self.push_env_scope();
+        }
+
+        self.set_func_args(func, params, args, &state.interner)?;
+        self.metrics.max_call_stack_depth = self.metrics.max_call_stack_depth
+            .max(self.call_stack.len());
+
+        let out = self.run_inner(state, Some(body_ptr))?;
+        self.scopes.pop();
+
+        return Ok(out.unwrap());
+    }
+
+    /// Run `gen` until it yields or returns. Returns what it yielded, or `None` once it is done.
+    /// Like `call` this can be used while something else is running. `gen` has to stay rooted.
+    pub fn resume_generator(&mut self, state: &mut ConvertState, gen: &DataRef)->Result<Option<DataRef>> {
+        let frame = {
+            let mut gen = gen.clone();
+            let mut data = gen.try_get_data_mut("next")?;
+            match &mut *data {
+                Data::Generator(gen_state)=>match replace(gen_state, GeneratorState::Running) {
+                    GeneratorState::Suspended(frame)=>frame,
+                    GeneratorState::Running=>bail!(LispError::GeneratorRunning),
+                    GeneratorState::Done=>{
+                        *gen_state = GeneratorState::Done;
+                        return Ok(None);
+                    },
+                },
+                data=>bail!(LispError::Type{op: "next".into(), expected: "generator", actual: data.type_name()}),
+            }
+        };
+
+        let call_depth = self.call_stack.len();
+        let env_depth = self.env_stack.len();
+        let scope_depth = self.scopes.len();
+
+        self.generators.push((call_depth + 1, frame.id, gen.clone()));
+        let res = self.resume_inner(state, *frame)
+            .map_err(|e|StackTrace::wrap(e, self.stack_trace(call_depth, state)));
+        self.generators.pop();
+        if res.is_err() {
+            self.save_post_mortem(state);
+            self.unwind(call_depth, env_depth, scope_depth);
+        }
+
+        // a `yield` suspends it again, anything else means the body is done
+        let mut gen = gen.clone();
+        let mut data = gen.get_data_mut();
+        let Data::Generator(gen_state) = &mut *data else {unreachable!("Generators stay generators")};
+        let yielded = matches!(gen_state, GeneratorState::Suspended(_));
+        if !yielded {
+            *gen_state = GeneratorState::Done;
+        }
+
+        return res.map(|value|yielded.then_some(value));
+    }
+
+    fn resume_inner(&mut self, state: &mut ConvertState, frame: SuspendedFrame)->Result<DataRef> {
+        let func_def = state.get_fn(frame.id)?.clone();
+        self.check_stack_depth(&func_def, state)?;
+
+        // like `call_inner`, it returns or yields into a scope of its own on top of ours, then runs
+        // into an `Exit`
+        let exit_id = state.call_exit();
+        self.scopes.push(ScopeItem::Return(None));
+        let mut scopes = Stack::new();
+        for item in frame.scopes {
+            scopes.push(item);
+        }
+        let old_scopes = replace(&mut self.scopes, scopes);
+        self.call_stack.push((exit_id, old_scopes));
+        self.frames.push(Frame {
+            kind: FrameKind::Fn(frame.id),
+            call_site: None,
+            tail_calls: 0,
+        });
+        self.push_env();
+        self.var_count += self.env_stack[0].resume(frame.env);
+        self.metrics.max_call_stack_depth = self.metrics.max_call_stack_depth
+            .max(self.call_stack.len());
+
+        // what the `yield` it stopped at evaluates to
+        self.push_to_scope(Data::None);
+
+        let out = self.run_loop(state, Some(frame.resume))?;
+        self.scopes.pop();
+
+        return Ok(out.unwrap());
+    }
+
+    /// Move the running generator's frame off of the stacks and return to whoever resumed it, or
+    /// to whoever called the generator function if it was just made. Returns the frame and where
+    /// to continue from.
+    fn suspend_frame(&mut self, resume: InstructionId)->(SuspendedFrame, InstructionId) {
+        let mut env = self.env_stack.pop().unwrap();
+        let (env_data, count) = env.suspend();
+        self.var_count -= count;
+        self.old_envs.push(env);
+
+        let frame = self.frames.pop().unwrap();
+        let FrameKind::Fn(id) = frame.kind else {unreachable!("Generators are always functions")};
+
+        let (ret_id, ret_scopes) = self.call_stack.pop().unwrap();
+        let mut scopes = replace(&mut self.scopes, ret_scopes);
+        let mut saved = Vec::with_capacity(scopes.len());
+        while let Some(item) = scopes.pop() {
+            saved.push(item);
+        }
+        saved.reverse();
+
+        let frame = SuspendedFrame {
+            id,
+            resume,
+            env: env_data,
+            scopes: saved,
+        };
+
+        return (frame, ret_id);
+    }
+
+    fn run_inner(&mut self, state: &mut ConvertState, start_id: Option<InstructionId>)->Result<Option<DataRef>> {
+        self.scopes.push(ScopeItem::Return(None));
+        return self.run_loop(state, start_id);
+    }
+
+    /// Run until an `Exit`. Unlike `run_inner` this doesn't start a scope first, so a generator can
+    /// pick up in the middle of an expression.
+    fn run_loop(&mut self, state: &mut ConvertState, start_id: Option<InstructionId>)->Result<Option<DataRef>> {
         use Instruction as I;
         // dbg!(&state.interner);
         // for (i, ins) in state.instructions.iter().enumerate() {
@@ -549,6 +1710,7 @@ impl Interpreter {
         const MAX_ITERS: u64 = 1_000_000_000;
 
         let start = Instant::now();
+        self.budget.start();
 
         let mut iter = state.instructions.iter();
 
@@ -556,8 +1718,6 @@ impl Interpreter {
             iter.jump(start_id);
         }
 
-        self.scopes.push(ScopeItem::Return(None));
-
         let mut ins_count = 0;
 
         while let Some(ins) = iter.next() {
@@ -570,27 +1730,64 @@ impl Interpreter {
                 panic!();
             }
             self.metrics.instructions_executed += 1;
+            if let Some(opcodes) = &mut self.opcodes {
+                opcodes.record(ins.opcode());
+            }
             ins_count += 1;
+            self.budget.check(self.metrics.instructions_executed)?;
+
+            // everything live is reachable from the scopes between instructions, so this is a
+            // safe point to collect
+            self.gc_step();
 
             match ins {
                 I::Nop=>{},
                 I::Exit=>break,
 
                 I::ReturnModule=>{
-                    let module = self.env_to_object();
+                    let mut module = self.env_to_object();
+                    let frame = self.frames.pop();
+                    if let (Some(Frame{kind: FrameKind::Module(id),..}), Data::Object(fields)) = (&frame, &mut module) {
+                        for name in state.modules.get(*id).privates.iter() {
+                            fields.remove(name);
+                        }
+                    }
+                    let module = self.alloc(module);
                     let (ret_id, ret_scopes) = self.call_stack.pop().unwrap();
+                    if let Some(Frame{kind: FrameKind::Module(id),..}) = frame {
+                        let start_ins = state.modules.get(id).start_ins;
+                        self.module_values.insert(start_ins, (id, module.clone().external()));
+                    }
 
                     iter.jump(ret_id);
                     self.scopes = ret_scopes;
-                    self.push_to_scope(module);
+                    self.push_dr_to_scope(module);
                 },
                 I::Module(id)=>{
-                    let module = state.modules.get(*id);
+                    let module = state.get_module(*id)?;
+
+                    if let Some((_, value)) = self.module_values.get(&module.start_ins) {
+                        self.push_dr_to_scope((**value).clone());
+                        continue;
+                    }
+
+                    let loading = self.frames.iter().any(|f|match f.kind {
+                        FrameKind::Module(other)=>state.modules.get(other).start_ins == module.start_ins,
+                        FrameKind::Fn(_)=>false,
+                    });
+                    if loading {
+                        bail!(LispError::ModuleCycle{name: state.interner.get(module.name).to_string()});
+                    }
 
                     // standard "save the current call frame"
                     let next_ins_id = iter.next_ins_id().unwrap();
                     let old_scopes = replace(&mut self.scopes, Stack::new());
                     self.call_stack.push((next_ins_id, old_scopes));
+                    self.frames.push(Frame {
+                        kind: FrameKind::Module(*id),
+                        call_site: iter.cur_ins_id(),
+                        tail_calls: 0,
+                    });
                     self.scopes.push(ScopeItem::Return(None));
                     self.push_env();
                     self.push_env_scope();
@@ -603,19 +1800,39 @@ impl Interpreter {
 
                     self.define_var(*i, data, &state.interner)?;
                 },
+                I::DefineConst(i)=>{
+                    let data = self.scopes[0].last().unwrap();
+
+                    self.define_var(*i, data, &state.interner)?;
+                    self.current_env().make_constant(*i);
+                },
+                I::DefineVector(names, constant)=>{
+                    let data = self.scopes[0].last().unwrap();
+
+                    self.define_vector(names, data, *constant, &state.interner)?;
+                },
                 I::Set(i)=>{
                     let data = self.scopes[0].last().unwrap();
 
                     self.set_var(*i, data, &state.interner)?;
                 },
+                I::SetPath(path)=>{
+                    let data = self.scopes[0].last().unwrap();
+
+                    self.set_path(path, data, state)?;
+                },
 
                 I::FnOrClosure(id)=>{
-                    let func = state.fns.get(*id).unwrap();
+                    let func = state.get_fn(*id)?;
 
                     if func.captures.len() > 0 {
                         let mut captures = Vec::new();
                         for cap in func.captures.iter() {
-                            captures.push((*cap, self.get_var(*cap, &state.interner)?));
+                            let data = match func.boxed.contains(cap) {
+                                true=>self.box_var(*cap, &state.interner)?,
+                                false=>self.lookup_var(*cap, &state.interner)?,
+                            };
+                            captures.push((*cap, data));
                         }
 
                         let captures = ClosureCaptures(captures);
@@ -642,8 +1859,9 @@ impl Interpreter {
                 I::Path(path)=>{
                     let mut path_iter = path.iter().copied();
                     let mut obj = self.get_var(path_iter.next().unwrap(), &state.interner)?;
-                    for name in path_iter {
-                        let data = obj.get_data();
+                    let mut host_name = None;
+                    for name in path_iter.by_ref() {
+                        let data = obj.try_get_data("path")?;
                         match &*data {
                             Data::Object(fields)=>{
                                 if let Some(dr) = fields.get(&name) {
@@ -652,15 +1870,72 @@ impl Interpreter {
 
                                     obj = dr;
                                 } else {
-                                    bail!("Object does not have a field named {}", state.interner.get(name));
+                                    bail!(self.undefined_field(&obj, name, state));
                                 }
                             },
-                            _=>bail!("Paths can only be used on `Object`s"),
+                            Data::List(items)=>{
+                                let index = path_index(items.len(), name, path, false, &state.interner)?;
+                                let dr = items[index].clone();
+                                drop(data);
+
+                                obj = dr;
+                            },
+                            Data::NativeData(NativeData::Custom(_))=>{
+                                host_name = Some(name);
+                                break;
+                            },
+                            data=>bail!(dead_end_path(path, name, data.type_name(), false, &state.interner)),
+                        }
+                    }
+
+                    // host objects need all of `state` to make their fields, so the rest of the
+                    // path is walked without the instructions
+                    if let Some(name) = host_name {
+                        let rest = path_iter.collect::<Vec<_>>();
+                        let next_ins_id = iter.next_ins_id().unwrap();
+                        let mut held = obj.external();
+                        for name in [name].into_iter().chain(rest) {
+                            held = self.field_of(&held, name, "path", state)?.external();
                         }
+                        obj = held.inner();
+                        iter = state.instructions.iter();
+                        iter.jump(next_ins_id);
                     }
 
                     self.push_dr_to_scope(obj);
                 },
+                I::Field(name)=>{
+                    let obj = self.pop_from_scope().unwrap();
+                    let data = obj.try_get_data("field")?;
+                    let field = match &*data {
+                        Data::Object(fields)=>match fields.get(name) {
+                            Some(dr)=>dr.clone(),
+                            None=>bail!(self.undefined_field(&obj, *name, state)),
+                        },
+                        Data::List(items)=>{
+                            let segment = state.interner.get(*name);
+                            match data_path::list_index(segment, items.len()) {
+                                Some(Ok(index))=>items[index].clone(),
+                                Some(Err(index))=>bail!(LispError::IndexOutOfBounds{op: "field".into(), index, len: items.len()}),
+                                None=>bail!(LispError::Type{op: "field".into(), expected: "object", actual: "list"}),
+                            }
+                        },
+                        Data::NativeData(NativeData::Custom(_))=>{
+                            drop(data);
+                            let name = *name;
+                            let next_ins_id = iter.next_ins_id().unwrap();
+                            let held = obj.external();
+                            let field = self.field_of(&held, name, "field", state)?;
+                            iter = state.instructions.iter();
+                            iter.jump(next_ins_id);
+
+                            field
+                        },
+                        data=>bail!(LispError::Type{op: "field".into(), expected: "object", actual: data.type_name()}),
+                    };
+
+                    self.push_dr_to_scope(field);
+                },
 
                 I::DotIdent(i)=>self.push_to_scope(Data::Ident(*i)),
                 I::Number(n)=>self.push_to_scope(Data::Number(*n)),
@@ -673,13 +1948,13 @@ impl Interpreter {
                 I::Splat=>{
                     match self.pop_from_scope() {
                         // Some(Data::List(items))=>,
-                        Some(d)=>match &*d.get_data() {
+                        Some(d)=>match &*d.try_get_data("splat")? {
                             Data::List(items)=>{
                                 items.iter()
                                     .cloned()
                                     .for_each(|dr|self.push_dr_to_scope(dr));
                             },
-                            _=>bail!("Splat only accepts lists"),
+                            data=>bail!(LispError::Type{op: "splat".into(), expected: "list", actual: data.type_name()}),
                         },
                         None=>bail!("There is no data in the scope! This is probably a bug"),
                     }
@@ -689,13 +1964,13 @@ impl Interpreter {
                     let mut args = self.scopes.pop().unwrap().list();
                     let mut arg0 = args[0].clone();
                     let arg0_func = arg0.clone();
-                    let data = arg0.get_data();
+                    let data = arg0.try_get_data("call")?;
                     let mut has_func = true;
 
                     match &*data {
                         Data::Object(o)=>{
                             let mut name = None;
-                            match &*args[1].get_data() {
+                            match &*args[1].try_get_data("call")? {
                                 Data::Ident(i)=>name = Some(*i),
                                 _=>{},
                             }
@@ -715,14 +1990,14 @@ impl Interpreter {
                                         2=>if let Some(field_data) = o.get(&name) {
                                             self.push_dr_to_scope(field_data.clone());
                                         } else {
-                                            bail!("Method/Field `{}` does not exist on object", state.interner.get(name));
+                                            bail!(LispError::UndefinedField{name: state.interner.get(name).to_string()});
                                         },
                                         // (Object .field DATA)
                                         3=>{
                                             drop(data);
 
                                             let data = args[2].clone();
-                                            let mut dr_ref = args[0].get_data_mut();
+                                            let mut dr_ref = args[0].try_get_data_mut("set field")?;
                                             let Data::Object(fields) = &mut *dr_ref else {unreachable!()};
 
                                             fields.insert(name, data.clone());
@@ -741,28 +2016,39 @@ impl Interpreter {
                     }
 
                     if has_func {
-                        let data = arg0.get_data();
+                        let data = arg0.try_get_data("call")?;
 
                         match &*data {
                             Data::NativeFn(name, f, arg_count)=>{
-                                let dr = match arg_count {
-                                    ArgCount::Exact(count)=>if args.len() == *count {
-                                        f(args, self, &mut state.interner)?
-                                    } else {
-                                        bail!("Function `{name}` cannot take {} arguments", args.len());
-                                    },
-                                    ArgCount::Any=>f(args, self, &mut state.interner)?,
-                                };
+                                if !arg_count.matches(args.len()) {
+                                    bail!(LispError::Arity{name: Some(name.to_string()), expected: vec![arg_count.signature()], got: args.len()});
+                                }
+                                let f = f.clone();
+                                drop(data);
+
+                                // the native gets all of `state`, so let go of the instructions
+                                // until it returns
+                                let next_ins_id = iter.next_ins_id().unwrap();
+                                let dr = self.call_native(f, &arg0, args, state)?;
+                                iter = state.instructions.iter();
+                                iter.jump(next_ins_id);
+
                                 self.push_dr_to_scope(dr);
                             },
                             Data::Fn(id)=>{
                                 self.debug_call(*id, state);
 
-                                let func = state.fns.get(*id).unwrap();
+                                let func = state.get_fn(*id)?;
+                                self.check_stack_depth(func, state)?;
 
                                 let next_ins_id = iter.next_ins_id().unwrap();
                                 let old_scopes = replace(&mut self.scopes, Stack::new());
                                 self.call_stack.push((next_ins_id, old_scopes));
+                                self.frames.push(Frame {
+                                    kind: FrameKind::Fn(*id),
+                                    call_site: iter.cur_ins_id(),
+                                    tail_calls: 0,
+                                });
                                 self.scopes.push(ScopeItem::Return(None));
                                 self.push_env();
                                 self.push_env_scope();
@@ -772,24 +2058,30 @@ impl Interpreter {
 
                                     iter.jump(body_ptr);
                                 } else {
-                                    if let Some(name) = func.name {
-                                        bail!("Function `{}` cannot take {} arguments", state.interner.get(name), args.len());
-                                    } else {
-                                        bail!("Function with ID `{:?}` cannot take {} arguments", id, args.len());
-                                    }
+                                    bail!(LispError::Arity{
+                                        name: func.name.map(|name|state.interner.get(name).to_string()),
+                                        expected: func.sig.signatures(&state.interner),
+                                        got: args.len(),
+                                    });
                                 }
 
                                 self.metrics.max_call_stack_depth = self.metrics.max_call_stack_depth
-                                    .max(self.call_stack.len() as u16);
+                                    .max(self.call_stack.len());
                             },
                             Data::Closure{id, captures}=>{
                                 self.debug_call(*id, state);
 
-                                let func = state.fns.get(*id).unwrap();
+                                let func = state.get_fn(*id)?;
+                                self.check_stack_depth(func, state)?;
 
                                 let next_ins_id = iter.next_ins_id().unwrap();
                                 let old_scopes = replace(&mut self.scopes, Stack::new());
                                 self.call_stack.push((next_ins_id, old_scopes));
+                                self.frames.push(Frame {
+                                    kind: FrameKind::Fn(*id),
+                                    call_site: iter.cur_ins_id(),
+                                    tail_calls: 0,
+                                });
                                 self.scopes.push(ScopeItem::Return(None));
                                 self.push_env();
                                 self.push_env_scope();
@@ -805,17 +2097,17 @@ impl Interpreter {
 
                                     iter.jump(body_ptr);
                                 } else {
-                                    if let Some(name) = func.name {
-                                        bail!("Function `{}` cannot take {} arguments", state.interner.get(name), args.len());
-                                    } else {
-                                        bail!("Function with ID `{:?}` cannot take {} arguments", id, args.len());
-                                    }
+                                    bail!(LispError::Arity{
+                                        name: func.name.map(|name|state.interner.get(name).to_string()),
+                                        expected: func.sig.signatures(&state.interner),
+                                        got: args.len(),
+                                    });
                                 }
 
                                 self.metrics.max_call_stack_depth = self.metrics.max_call_stack_depth
-                                    .max(self.call_stack.len() as u16);
+                                    .max(self.call_stack.len());
                             },
-                            arg=>bail!("Arg0 is not callable! {:?}", arg),
+                            _=>bail!(not_callable(&arg0, &state.interner)),
                         }
                     }
                 },
@@ -823,13 +2115,13 @@ impl Interpreter {
                     let mut args = self.scopes.pop().unwrap().list();
                     let mut arg0 = args[0].clone();
                     let arg0_func = arg0.clone();
-                    let data = arg0.get_data();
+                    let data = arg0.try_get_data("call")?;
                     let mut has_func = true;
 
                     match &*data {
                         Data::Object(o)=>{
                             let mut name = None;
-                            match &*args[1].get_data() {
+                            match &*args[1].try_get_data("call")? {
                                 Data::Ident(i)=>name = Some(*i),
                                 _=>{},
                             }
@@ -845,7 +2137,7 @@ impl Interpreter {
                                     if let Some(data) = o.get(&name) {
                                         self.push_dr_to_scope(data.clone());
                                     } else {
-                                        bail!("Method/Field `{}` does not exist on object", state.interner.get(name));
+                                        bail!(LispError::UndefinedField{name: state.interner.get(name).to_string()});
                                     }
                                 },
                             }
@@ -856,24 +2148,30 @@ impl Interpreter {
                     }
 
                     if has_func {
-                        let data = arg0.get_data();
+                        let data = arg0.try_get_data("call")?;
 
                         match &*data {
                             Data::NativeFn(name, f, arg_count)=>{
-                                let dr = match arg_count {
-                                    ArgCount::Exact(count)=>if args.len() == *count {
-                                        f(args, self, &mut state.interner)?
-                                    } else {
-                                        bail!("Function `{name}` cannot take {} arguments", args.len());
-                                    },
-                                    ArgCount::Any=>f(args, self, &mut state.interner)?,
-                                };
+                                if !arg_count.matches(args.len()) {
+                                    bail!(LispError::Arity{name: Some(name.to_string()), expected: vec![arg_count.signature()], got: args.len()});
+                                }
+                                let f = f.clone();
+                                drop(data);
+
+                                // the native gets all of `state`, so let go of the instructions
+                                // until it returns
+                                let next_ins_id = iter.next_ins_id().unwrap();
+                                let dr = self.call_native(f, &arg0, args, state)?;
+                                iter = state.instructions.iter();
+                                iter.jump(next_ins_id);
+
                                 self.push_dr_to_scope(dr);
                             },
                             Data::Fn(id)=>{
                                 self.debug_tail_call(*id, state);
+                                self.replace_frame(*id);
 
-                                let func = state.fns.get(*id).unwrap();
+                                let func = state.get_fn(*id)?;
 
                                 self.scopes = Stack::new();
                                 self.scopes.push(ScopeItem::Return(None));
@@ -887,17 +2185,18 @@ impl Interpreter {
                                     iter.jump(body_ptr);
                                     // dbg!(iter.peek());
                                 } else {
-                                    if let Some(name) = func.name {
-                                        bail!("Function `{}` cannot take {} arguments", state.interner.get(name), args.len());
-                                    } else {
-                                        bail!("Function with ID `{:?}` cannot take {} arguments", id, args.len());
-                                    }
+                                    bail!(LispError::Arity{
+                                        name: func.name.map(|name|state.interner.get(name).to_string()),
+                                        expected: func.sig.signatures(&state.interner),
+                                        got: args.len(),
+                                    });
                                 }
                             },
                             Data::Closure{id, captures}=>{
                                 self.debug_tail_call(*id, state);
+                                self.replace_frame(*id);
 
-                                let func = state.fns.get(*id).unwrap();
+                                let func = state.get_fn(*id)?;
 
                                 self.scopes = Stack::new();
                                 self.scopes.push(ScopeItem::Return(None));
@@ -915,14 +2214,14 @@ impl Interpreter {
 
                                     iter.jump(body_ptr);
                                 } else {
-                                    if let Some(name) = func.name {
-                                        bail!("Function `{}` cannot take {} arguments", state.interner.get(name), args.len());
-                                    } else {
-                                        bail!("Function with ID `{:?}` cannot take {} arguments", id, args.len());
-                                    }
+                                    bail!(LispError::Arity{
+                                        name: func.name.map(|name|state.interner.get(name).to_string()),
+                                        expected: func.sig.signatures(&state.interner),
+                                        got: args.len(),
+                                    });
                                 }
                             },
-                            arg=>bail!("Arg0 is not callable! {:?}", arg),
+                            _=>bail!(not_callable(&arg0, &state.interner)),
                         }
                     }
                 },
@@ -931,6 +2230,7 @@ impl Interpreter {
                     let last = self.pop_from_scope();
                     let last = last.unwrap_or_else(||self.alloc(Data::List(Vec::new())));
                     let (ret_id, ret_scopes) = self.call_stack.pop().unwrap();
+                    self.frames.pop();
 
                     self.pop_env();
 
@@ -975,22 +2275,58 @@ impl Interpreter {
 
                 I::JumpIfTrue(id)=>{
                     let data = self.pop_from_scope().unwrap();
-                    // println!("JumpIfTrue condition: {data:?}");
-                    match *data.get_data() {
-                        Data::Bool(true)=>iter.jump(*id),
-                        _=>{},
-                    };
+                    if self.is_truthy("cond", &data)? {
+                        iter.jump(*id);
+                    }
                 },
                 I::JumpIfFalse(id)=>{
                     let data = self.pop_from_scope().unwrap();
-                    // println!("JumpIfFalse condition: {data:?}");
-                    match *data.get_data() {
-                        Data::Bool(false)=>iter.jump(*id),
-                        _=>{},
-                    };
+                    if !self.is_truthy("cond", &data)? {
+                        iter.jump(*id);
+                    }
                 },
                 I::Jump(id)=>iter.jump(*id),
+                I::Breakpoint=>{
+                    // the REPL converts more code into `state`, so let go of the instructions
+                    // until it is done
+                    let next_ins_id = iter.next_ins_id().unwrap();
+                    self.breakpoint(state)?;
+                    iter = state.instructions.iter();
+                    iter.jump(next_ins_id);
+
+                    self.push_to_scope(Data::None);
+                },
                 I::None=>self.push_to_scope(Data::None),
+                I::MakeGenerator=>{
+                    // a generator that tail calls itself, like with `recur`, keeps going as the
+                    // same generator instead of returning a new one
+                    let depth = self.call_stack.len();
+                    let current = self.current_fn();
+                    let continues = self.generators.last()
+                        .is_some_and(|(gen_depth, id, _)|*gen_depth == depth && Some(*id) == current);
+                    if continues {continue}
+
+                    // made before the frame is moved out, while everything it will hold is still
+                    // rooted
+                    let mut gen = self.alloc(Data::Generator(GeneratorState::Running));
+                    let (frame, ret_id) = self.suspend_frame(iter.next_ins_id().unwrap());
+                    *gen.get_data_mut() = Data::Generator(GeneratorState::Suspended(Box::new(frame)));
+
+                    iter.jump(ret_id);
+                    self.push_dr_to_scope(gen);
+                },
+                I::Yield=>{
+                    let value = self.pop_from_scope().unwrap();
+                    let Some((_, _, gen)) = self.generators.last() else {
+                        bail!(LispError::YieldOutsideGenerator);
+                    };
+                    let mut gen = gen.clone();
+                    let (frame, ret_id) = self.suspend_frame(iter.next_ins_id().unwrap());
+                    *gen.get_data_mut() = Data::Generator(GeneratorState::Suspended(Box::new(frame)));
+
+                    iter.jump(ret_id);
+                    self.push_dr_to_scope(value);
+                },
             }
         }
 
@@ -999,14 +2335,93 @@ impl Interpreter {
         self.metrics.last_run_time = duration;
         self.metrics.total_run_time += duration;
 
-        let allocation_bytes = self.data.get_alloc_rem() as u64;
-        self.metrics.max_allocation_bytes = self.metrics.max_allocation_bytes.max(allocation_bytes);
-
-        self.data.collect(&self.call_stack, &self.scopes);
+        self.gc_collect();
 
         return Ok(self.pop_from_scope());
     }
 
+    /// The error for `obj` not having the field `name`. Modules leave their privates out of their
+    /// value, so this says when that is why.
+    fn undefined_field(&self, obj: &DataRef, name: Ident, state: &ConvertState)->LispError {
+        let module = self.module_values.values()
+            .find(|(_, value)|value.is_same(obj))
+            .map(|(id, _)|state.modules.get(*id));
+        if let Some(module) = module {
+            if module.privates.contains(&name) {
+                return LispError::PrivateField {
+                    name: state.interner.get(name).to_string(),
+                    module: state.interner.get(module.name).to_string(),
+                };
+            }
+
+            return LispError::UndefinedMember {
+                module: state.interner.get(module.name).to_string(),
+                name: state.interner.get(name).to_string(),
+                exports: module.exports.iter()
+                    .map(|n|state.interner.get(*n).to_string())
+                    .collect(),
+            };
+        }
+
+        return LispError::UndefinedField{name: state.interner.get(name).to_string()};
+    }
+
+    /// `(set a/b/c data)`. Everything but the last segment is read like `Path` does, then the last
+    /// one is set in the object or list that was reached. Setting a field an object doesn't have
+    /// adds it.
+    fn set_path(&mut self, path: &[Ident], data: DataRef, state: &ConvertState)->Result<()> {
+        let (last, middle) = path[1..].split_last().unwrap();
+        let mut obj = self.get_var(path[0], &state.interner)?;
+        for name in middle.iter().copied() {
+            let next = match &*obj.try_get_data("set")? {
+                Data::Object(fields)=>match fields.get(&name) {
+                    Some(dr)=>dr.clone(),
+                    None=>bail!(self.undefined_field(&obj, name, state)),
+                },
+                Data::List(items)=>items[path_index(items.len(), name, path, true, &state.interner)?].clone(),
+                data=>bail!(dead_end_path(path, name, data.type_name(), true, &state.interner)),
+            };
+            obj = next;
+        }
+
+        match &mut *obj.try_get_data_mut("set")? {
+            Data::Object(fields)=>{
+                fields.insert(*last, data);
+            },
+            Data::List(items)=>{
+                let index = path_index(items.len(), *last, path, true, &state.interner)?;
+                items[index] = data;
+            },
+            data=>bail!(dead_end_path(path, *last, data.type_name(), true, &state.interner)),
+        }
+
+        return Ok(());
+    }
+
+    /// The field `name` of an object or host object
+    fn field_of(&mut self, obj: &DataRef, name: Ident, op: &str, state: &mut ConvertState)->Result<DataRef> {
+        let data = obj.try_get_data(op)?;
+        match &*data {
+            Data::Object(fields)=>match fields.get(&name) {
+                Some(dr)=>return Ok(dr.clone()),
+                None=>bail!(self.undefined_field(obj, name, state)),
+            },
+            Data::NativeData(NativeData::Custom(host))=>{
+                let host = host.clone();
+                drop(data);
+
+                let field_name = state.interner.get(name).to_string();
+                let field = self.host_field.clone()
+                    .and_then(|f|f(&*host, &field_name, self, state));
+                match field {
+                    Some(field)=>return Ok(field),
+                    None=>bail!(LispError::UndefinedField{name: field_name}),
+                }
+            },
+            data=>bail!(LispError::Type{op: op.into(), expected: "object", actual: data.type_name()}),
+        }
+    }
+
     fn env_to_object(&mut self)->Data {
         self.env_stack
             .pop()
@@ -1015,6 +2430,46 @@ impl Interpreter {
             .unwrap()
     }
 
+    /// `(def [a b & rest] data)`. Checks the length before defining anything, so a list that
+    /// doesn't fit leaves every name undefined.
+    fn define_vector(&mut self, names: &Vector, data: DataRef, constant: bool, interner: &Interner)->Result<()> {
+        let items = match &*data.try_get_data("def")? {
+            Data::List(items)=>items.clone(),
+            data=>bail!(LispError::Type{op: "def".into(), expected: "list", actual: data.type_name()}),
+        };
+
+        let needed = names.items.len();
+        let fits = match names.remainder {
+            Some(_)=>items.len() >= needed,
+            None=>items.len() == needed,
+        };
+        if !fits {
+            bail!(LispError::DestructureLength {
+                pattern: names.describe(interner),
+                needed,
+                variadic: names.remainder.is_some(),
+                actual: items.len(),
+            });
+        }
+
+        let mut items = items.into_iter();
+        for (name, item) in names.items.iter().zip(&mut items) {
+            self.define_var(*name, item, interner)?;
+        }
+        if let Some(rem) = names.remainder {
+            let rest = self.alloc(Data::List(items.collect()));
+            self.define_var(rem, rest, interner)?;
+        }
+
+        if constant {
+            for name in names.items.iter().chain(&names.remainder) {
+                self.current_env().make_constant(*name);
+            }
+        }
+
+        return Ok(());
+    }
+
     fn set_func_args(&mut self, func: DataRef, params: &Vector, args: Vec<DataRef>, interner: &Interner)->Result<()> {
         let mut args_iter = args.into_iter();
 
@@ -1042,7 +2497,7 @@ impl Interpreter {
         match object {
             Data::Object(fields)=>{
                 let Some(vtable) = fields.get(&self.vtable_ident) else {return Ok(None)};
-                let vtable_ref = vtable.get_data();
+                let vtable_ref = vtable.try_get_data("method call")?;
                 match &*vtable_ref {
                     Data::Object(entries)=>{
                         if let Some(name) = name {
@@ -1059,36 +2514,44 @@ impl Interpreter {
     }
 
     fn debug_call(&self, id: FnId, state: &ConvertState) {
-        if !DEBUG {return}
+        if !log::log_enabled!(log::Level::Trace) {return}
 
-        let func = state.fns.get(id).unwrap();
+        let Some(func) = state.fns.get(id) else {return};
         if let Some(name) = func.name {
-            println!("Call function {}", state.interner.get(name));
+            log::trace!("Call function {}", state.interner.get(name));
         } else {
-            println!("Call function `{id:?}`");
+            log::trace!("Call function `{id:?}`");
+        }
+    }
+
+    /// A tail call to `id` reuses the current frame
+    fn replace_frame(&mut self, id: FnId) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.kind = FrameKind::Fn(id);
+            frame.tail_calls += 1;
         }
     }
 
     fn debug_tail_call(&self, id: FnId, state: &ConvertState) {
-        if !DEBUG {return}
+        if !log::log_enabled!(log::Level::Trace) {return}
 
-        let func = state.fns.get(id).unwrap();
+        let Some(func) = state.fns.get(id) else {return};
         if let Some(name) = func.name {
-            println!("Tail call function {}", state.interner.get(name));
+            log::trace!("Tail call function {}", state.interner.get(name));
         } else {
-            println!("Tail call function `{id:?}`");
+            log::trace!("Tail call function `{id:?}`");
         }
     }
 
     #[allow(dead_code)]
     fn debug_return(&self, id: FnId, state: &ConvertState) {
-        if !DEBUG {return}
+        if !log::log_enabled!(log::Level::Trace) {return}
 
-        let func = state.fns.get(id).unwrap();
+        let Some(func) = state.fns.get(id) else {return};
         if let Some(name) = func.name {
-            println!("Return from function {}", state.interner.get(name));
+            log::trace!("Return from function {}", state.interner.get(name));
         } else {
-            println!("Return from function `{id:?}`");
+            log::trace!("Return from function `{id:?}`");
         }
     }
 }
@@ -0,0 +1,322 @@
+//! A width-aware pretty printer for `Data`. Lists and objects that fit on one line stay on one
+//! line, otherwise they are broken up with one item per line and two spaces of indentation per
+//! level. Self-referencing data prints `#cycle->list` (or `#cycle->object`) instead of looping forever.
+
+
+use crossterm::terminal::size as terminal_size;
+use std::fmt::Write;
+use super::{
+    ast::{
+        Interner,
+        Ident,
+    },
+    data::{
+        Data,
+        DataRef,
+        NativeData,
+        pair_chain,
+    },
+};
+use crate::numeric::FloatDisplay;
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct PrettyConfig {
+    /// The width we try to stay under
+    pub width: usize,
+    /// Lists and objects nested deeper than this are printed as `...`
+    pub max_depth: usize,
+    /// Lists and objects with more items than this have their middle replaced with a count
+    pub max_items: usize,
+}
+impl Default for PrettyConfig {
+    fn default()->Self {
+        PrettyConfig {
+            width: 80,
+            max_depth: 16,
+            max_items: 100,
+        }
+    }
+}
+impl PrettyConfig {
+    /// The default config, but using the terminal's width if we can get it.
+    pub fn for_terminal()->Self {
+        let mut config = Self::default();
+        if let Ok((width, _)) = terminal_size() {
+            config.width = width as usize;
+        }
+
+        return config;
+    }
+}
+
+/// Never breaks lines or leaves items out, for printing a result that is read back or compared.
+/// Lists nested deeper than 16 are still `...`.
+pub const ONE_LINE: PrettyConfig = PrettyConfig {
+    width: usize::MAX,
+    max_depth: 16,
+    max_items: usize::MAX,
+};
+
+
+struct Printer<'a> {
+    interner: &'a Interner,
+    config: &'a PrettyConfig,
+    parents: Vec<DataRef>,
+    out: String,
+}
+impl<'a> Printer<'a> {
+    fn column(&self)->usize {
+        let line_start = self.out.rfind('\n')
+            .map(|i|i + 1)
+            .unwrap_or(0);
+
+        self.out[line_start..].chars().count()
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        for _ in 0..indent {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn is_cycle(&self, dr: &DataRef)->bool {
+        self.parents.iter().any(|p|p.is_same(dr))
+    }
+
+    /// Returns the indices of the items we actually print. `None` is where the elided items go.
+    fn visible_items(&self, len: usize)->Vec<Option<usize>> {
+        if len <= self.config.max_items {
+            return (0..len).map(Some).collect();
+        }
+
+        let head = self.config.max_items / 2;
+        let tail = self.config.max_items - head;
+
+        let mut out = (0..head).map(Some).collect::<Vec<_>>();
+        out.push(None);
+        out.extend((len - tail..len).map(Some));
+
+        return out;
+    }
+
+    fn elided_count(&self, len: usize)->usize {
+        len.saturating_sub(self.config.max_items)
+    }
+
+    fn sorted_fields<'b>(&self, fields: impl Iterator<Item = (&'b Ident, &'b DataRef)>)->Vec<(&'a str, DataRef)> {
+        let mut fields = fields
+            .map(|(name, field)|(self.interner.get(*name), field.clone()))
+            .collect::<Vec<_>>();
+        fields.sort_by(|l, r|l.0.cmp(r.0));
+
+        return fields;
+    }
+
+    /// Print `dr`, breaking it over multiple lines if it doesn't fit.
+    fn print(&mut self, dr: &DataRef, depth: usize, indent: usize) {
+        let limit = self.config.width.saturating_sub(self.column());
+        let mut flat = String::new();
+        if self.flat(&mut flat, dr, depth, limit) || self.is_cycle(dr) || depth >= self.config.max_depth {
+            self.out.push_str(&flat);
+            return;
+        }
+
+        match &*dr.get_data() {
+            Data::List(items)=>{
+                self.parents.push(dr.clone());
+                self.out.push('(');
+                for entry in self.visible_items(items.len()) {
+                    self.newline(indent + 1);
+                    match entry {
+                        Some(idx)=>self.print(&items[idx], depth + 1, indent + 1),
+                        None=>write!(self.out, "...{} more", self.elided_count(items.len())).unwrap(),
+                    }
+                }
+                self.newline(indent);
+                self.out.push(')');
+                self.parents.pop();
+            },
+            Data::Object(fields)=>{
+                let fields = self.sorted_fields(fields.iter());
+
+                self.parents.push(dr.clone());
+                self.out.push('{');
+                for entry in self.visible_items(fields.len()) {
+                    self.newline(indent + 1);
+                    match entry {
+                        Some(idx)=>{
+                            let (name, field) = &fields[idx];
+                            write!(self.out, "{name}: ").unwrap();
+                            self.print(field, depth + 1, indent + 1);
+                        },
+                        None=>write!(self.out, "...{} more", self.elided_count(fields.len())).unwrap(),
+                    }
+                }
+                self.newline(indent);
+                self.out.push('}');
+                self.parents.pop();
+            },
+            // pairs and atoms are always printed flat, even if they are too long
+            Data::Pair(..)=>{
+                let mut full = String::new();
+                self.flat(&mut full, dr, depth, usize::MAX);
+                self.out.push_str(&full);
+            },
+            _=>self.out.push_str(&flat),
+        }
+    }
+
+    /// Writes `dr` to `out` on a single line. Returns `false` and stops early once `out` is
+    /// longer than `limit`.
+    fn flat(&mut self, out: &mut String, dr: &DataRef, depth: usize, limit: usize)->bool {
+        if out.len() > limit {return false}
+
+        if self.is_cycle(dr) {
+            out.push_str("#cycle->");
+            out.push_str(dr.get_data().type_name());
+            return out.len() <= limit;
+        }
+
+        match &*dr.get_data() {
+            Data::List(_)|Data::Object(_)|Data::Pair(..) if depth >= self.config.max_depth=>out.push_str("..."),
+            Data::List(items)=>{
+                self.parents.push(dr.clone());
+                out.push('(');
+                for (i, entry) in self.visible_items(items.len()).into_iter().enumerate() {
+                    if i > 0 {out.push(' ')}
+                    match entry {
+                        Some(idx)=>if !self.flat(out, &items[idx], depth + 1, limit) {
+                            self.parents.pop();
+                            return false;
+                        },
+                        None=>write!(out, "...{} more", self.elided_count(items.len())).unwrap(),
+                    }
+                }
+                out.push(')');
+                self.parents.pop();
+            },
+            Data::Object(fields)=>{
+                let fields = self.sorted_fields(fields.iter());
+
+                self.parents.push(dr.clone());
+                out.push('{');
+                for (i, entry) in self.visible_items(fields.len()).into_iter().enumerate() {
+                    if i > 0 {out.push_str(", ")}
+                    match entry {
+                        Some(idx)=>{
+                            let (name, field) = &fields[idx];
+                            write!(out, "{name}: ").unwrap();
+                            if !self.flat(out, field, depth + 1, limit) {
+                                self.parents.pop();
+                                return false;
+                            }
+                        },
+                        None=>write!(out, "...{} more", self.elided_count(fields.len())).unwrap(),
+                    }
+                }
+                out.push('}');
+                self.parents.pop();
+            },
+            Data::Pair(..)=>{
+                let (items, tail) = pair_chain(dr);
+                let dotted = !matches!(*tail.get_data(), Data::None);
+
+                self.parents.push(dr.clone());
+                out.push('(');
+                for (i, entry) in self.visible_items(items.len()).into_iter().enumerate() {
+                    if i > 0 {out.push(' ')}
+                    match entry {
+                        Some(idx)=>if !self.flat(out, &items[idx], depth + 1, limit) {
+                            self.parents.pop();
+                            return false;
+                        },
+                        None=>write!(out, "...{} more", self.elided_count(items.len())).unwrap(),
+                    }
+                }
+                if dotted {
+                    out.push_str(" . ");
+                    if !self.flat(out, &tail, depth + 1, limit) {
+                        self.parents.pop();
+                        return false;
+                    }
+                }
+                out.push(')');
+                self.parents.pop();
+            },
+            data=>write_atom(out, data, self.interner),
+        }
+
+        return out.len() <= limit;
+    }
+}
+
+
+fn write_atom(out: &mut String, data: &Data, interner: &Interner) {
+    match data {
+        Data::Ident(i)=>write!(out, "'{}", interner.get(*i)).unwrap(),
+        Data::String(s)=>write!(out, "{s:?}").unwrap(),
+        Data::Char(c)=>match c {
+            ' '=>out.push_str("\\space"),
+            '\n'=>out.push_str("\\newline"),
+            '\t'=>out.push_str("\\tab"),
+            c=>write!(out, "\\{c}").unwrap(),
+        },
+        Data::Number(n)=>write!(out, "{n}").unwrap(),
+        Data::Float(f)=>write!(out, "{}", FloatDisplay(*f)).unwrap(),
+        Data::Bool(b)=>write!(out, "{b}").unwrap(),
+
+        Data::Fn(_)|Data::Closure{..}=>out.push_str("<fn>"),
+        Data::Lazy(_)=>out.push_str("<lazySeq>"),
+        Data::Generator(_)=>out.push_str("<generator>"),
+        Data::Cell(_)=>out.push_str("<cell>"),
+        Data::NativeFn(name, _, _)=>write!(out, "<nativeFn: {name}>").unwrap(),
+        Data::NativeData(NativeData::Custom(host))=>write!(out, "<native: {}>", host.type_name()).unwrap(),
+        Data::NativeData(NativeData::Channel(_))=>out.push_str("<channel>"),
+        Data::NativeData(NativeData::Thread(_))=>out.push_str("<thread>"),
+        Data::NativeData(_)=>out.push_str("<nativeData>"),
+        Data::None=>out.push_str("None"),
+
+        Data::List(_)|Data::Object(_)|Data::Pair(..)=>unreachable!("Lists, objects and pairs are not atoms"),
+    }
+}
+
+/// Pretty print `dr` using `config`. The output may span multiple lines, but never ends with a
+/// newline.
+pub fn pretty_format(dr: &DataRef, interner: &Interner, config: &PrettyConfig)->String {
+    let mut printer = Printer {
+        interner,
+        config,
+        parents: Vec::new(),
+        out: String::new(),
+    };
+    printer.print(dr, 0, 0);
+
+    return printer.out;
+}
+
+/// A single line preview of `dr` that is at most `width` chars long. Anything longer is cut off
+/// and ends with `...`.
+pub fn preview(dr: &DataRef, interner: &Interner, width: usize)->String {
+    let config = PrettyConfig {
+        width,
+        ..PrettyConfig::default()
+    };
+    let mut printer = Printer {
+        interner,
+        config: &config,
+        parents: Vec::new(),
+        out: String::new(),
+    };
+
+    let mut out = String::new();
+    printer.flat(&mut out, dr, 0, width);
+
+    if out.chars().count() > width {
+        out = out.chars().take(width.saturating_sub(3)).collect();
+        out.push_str("...");
+    }
+
+    return out;
+}
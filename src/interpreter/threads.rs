@@ -0,0 +1,432 @@
+//! Threads for `std/thread`. GC'd data can't leave the interpreter that made it, so each thread runs
+//! its own `Interpreter` on a copy of the program, and everything that goes between them is copied
+//! into a `Sendable` first. Only plain data and channels can be copied. Files, closures and the
+//! like stay where they are.
+//!
+//! What a thread prints is sent back to the interpreter that spawned it, which writes it to its own
+//! output the next time it joins a thread or receives from a channel, and before it is dropped.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use std::{
+    collections::VecDeque,
+    io::Result as IoResult,
+    sync::{
+        Arc,
+        Mutex,
+        Condvar,
+        PoisonError,
+        mpsc::{
+            Sender,
+            Receiver,
+            channel,
+        },
+    },
+    thread::{
+        Builder,
+        JoinHandle,
+    },
+    fmt::{
+        Debug,
+        Formatter,
+        Result as FmtResult,
+    },
+    time::Duration,
+    rc::Rc,
+    cell::RefCell,
+};
+use super::{
+    Interpreter,
+    Data,
+    DataRef,
+    NativeData,
+    ClosureCaptures,
+    ScopeItem,
+    ast::{
+        ConvertState,
+        Interner,
+        FnId,
+        ProgramCopy,
+    },
+};
+use crate::{
+    capabilities::Capabilities,
+    gc_config::GcConfig,
+    error::LispError,
+    output::Output,
+};
+
+
+/// Plain data on its way to another interpreter. Idents go by name, since each thread has its own
+/// interner.
+pub enum Sendable {
+    List(Vec<Self>),
+    Object(Vec<(String, Self)>),
+    Pair(Box<Self>, Box<Self>),
+    /// A var a closure shares with the frame that made it. The copy is only shared in the thread.
+    Cell(Box<Self>),
+    Ident(String),
+    Number(i64),
+    Float(f64),
+    String(String),
+    Char(char),
+    Bool(bool),
+    Channel(Channel),
+    /// Only `spawn` copies functions, since the thread gets a copy of their code too
+    Fn(FnId),
+    None,
+}
+impl Sendable {
+    /// Copy `dr` and everything in it. `op` is blamed for anything that can't be copied. Functions
+    /// without captures are only copied if `fns` is set.
+    pub fn copy(dr: &DataRef, interner: &Interner, op: &str, fns: bool)->Result<Self> {
+        Self::copy_inner(dr, interner, op, fns, &mut Vec::new())
+    }
+
+    fn copy_inner(dr: &DataRef, interner: &Interner, op: &str, fns: bool, parents: &mut Vec<usize>)->Result<Self> {
+        if parents.contains(&dr.addr()) {
+            bail!("`{op}` can't copy data that contains itself to another thread");
+        }
+        parents.push(dr.addr());
+
+        let mut copy = |dr: &DataRef|Self::copy_inner(dr, interner, op, fns, parents);
+        let out = match &*dr.try_get_data(op)? {
+            Data::List(items)=>Self::List(items.iter()
+                .map(&mut copy)
+                .collect::<Result<_>>()?
+            ),
+            Data::Object(fields)=>Self::Object(fields.iter()
+                .map(|(name, value)|Ok((interner.get(*name).to_string(), copy(value)?)))
+                .collect::<Result<_>>()?
+            ),
+            Data::Pair(car, cdr)=>Self::Pair(Box::new(copy(car)?), Box::new(copy(cdr)?)),
+            Data::Cell(inner)=>Self::Cell(Box::new(copy(inner)?)),
+            Data::Ident(name)=>Self::Ident(interner.get(*name).to_string()),
+            Data::Number(n)=>Self::Number(*n),
+            Data::Float(f)=>Self::Float(*f),
+            Data::String(s)=>Self::String(s.clone()),
+            Data::Char(c)=>Self::Char(*c),
+            Data::Bool(b)=>Self::Bool(*b),
+            Data::NativeData(NativeData::Channel(channel))=>Self::Channel(channel.clone()),
+            Data::Fn(id) if fns=>Self::Fn(*id),
+            Data::None=>Self::None,
+            data=>bail!(LispError::NotSendable{op: op.into(), actual: data.type_name()}),
+        };
+
+        parents.pop();
+        return Ok(out);
+    }
+
+    /// Make the data in `i`. Everything made is rooted, so this has to run in a native's scope or
+    /// one like it.
+    pub fn into_data(self, i: &mut Interpreter, interner: &mut Interner)->DataRef {
+        let data = match self {
+            Self::List(items)=>Data::List(items.into_iter()
+                .map(|item|item.into_data(i, interner))
+                .collect()
+            ),
+            Self::Object(fields)=>Data::Object(fields.into_iter()
+                .map(|(name, value)|(interner.intern(name), value.into_data(i, interner)))
+                .collect()
+            ),
+            Self::Pair(car, cdr)=>Data::Pair(car.into_data(i, interner), cdr.into_data(i, interner)),
+            Self::Cell(inner)=>Data::Cell(inner.into_data(i, interner)),
+            Self::Ident(name)=>Data::Ident(interner.intern(name)),
+            Self::Number(n)=>Data::Number(n),
+            Self::Float(f)=>Data::Float(f),
+            Self::String(s)=>Data::String(s),
+            Self::Char(c)=>Data::Char(c),
+            Self::Bool(b)=>Data::Bool(b),
+            Self::Channel(channel)=>Data::NativeData(NativeData::Channel(channel)),
+            Self::Fn(id)=>Data::Fn(id),
+            Self::None=>Data::None,
+        };
+
+        let dr = i.alloc(data);
+        i.root(&dr);
+
+        return dr;
+    }
+}
+
+
+/// From `std/thread/chan`. Every thread with a copy of it shares the same queue.
+pub struct Channel(Arc<(Mutex<ChannelQueue>, Condvar)>);
+impl Clone for Channel {
+    fn clone(&self)->Self {
+        self.0.0.lock().unwrap().handles += 1;
+        Channel(self.0.clone())
+    }
+}
+impl Drop for Channel {
+    /// Wakes anything waiting in `recv`, since this might have been the last copy that could send
+    fn drop(&mut self) {
+        let (queue, ready) = &*self.0;
+        queue.lock().unwrap_or_else(PoisonError::into_inner).handles -= 1;
+        ready.notify_all();
+    }
+}
+impl Debug for Channel {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        write!(f, "Channel({:p})", Arc::as_ptr(&self.0))
+    }
+}
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self)->bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Channel {
+    pub fn new()->Self {
+        let queue = ChannelQueue {
+            items: VecDeque::new(),
+            handles: 1,
+        };
+        Channel(Arc::new((Mutex::new(queue), Condvar::new())))
+    }
+
+    pub fn send(&self, value: Sendable) {
+        let (queue, ready) = &*self.0;
+        queue.lock().unwrap().items.push_back(value);
+        ready.notify_one();
+    }
+
+    /// Wait for the next value. Once nothing else has a copy of the channel nothing can send on it,
+    /// so that is an error instead of waiting forever.
+    pub fn recv(&self)->Result<Sendable> {
+        let (queue, ready) = &*self.0;
+        let mut queue = queue.lock().unwrap();
+        loop {
+            if let Some(value) = queue.items.pop_front() {
+                return Ok(value);
+            }
+            if queue.handles == 1 {
+                bail!(LispError::ChannelClosed);
+            }
+
+            queue = ready.wait(queue).unwrap();
+        }
+    }
+}
+
+/// The values sent on a channel and how many copies of it there are. The count is kept with the
+/// values so `recv` can't miss the last other copy being dropped while it checks.
+struct ChannelQueue {
+    items: VecDeque<Sendable>,
+    handles: usize,
+}
+
+
+/// Where a thread's interpreter writes what it prints. The text goes to the `ThreadPrints` of the
+/// interpreter that spawned it.
+struct ThreadOutput {
+    sender: Sender<String>,
+    terminal: bool,
+}
+impl Output for ThreadOutput {
+    fn write_str(&mut self, s: &str)->IoResult<()> {
+        // the spawning interpreter joins its threads before it lets go of the other end
+        let _ = self.sender.send(s.to_string());
+        return Ok(());
+    }
+
+    fn flush(&mut self)->IoResult<()> {
+        Ok(())
+    }
+
+    fn is_terminal(&self)->bool {
+        self.terminal
+    }
+}
+
+/// What the threads an interpreter spawned printed, until it writes it to its own output
+pub(super) struct ThreadPrints {
+    sender: Sender<String>,
+    receiver: Receiver<String>,
+}
+impl ThreadPrints {
+    pub fn new()->Self {
+        let (sender, receiver) = channel();
+        ThreadPrints {sender, receiver}
+    }
+}
+
+
+/// From `std/thread/spawn`. The interpreter that spawned the thread keeps a copy, so it can join
+/// the thread when it is dropped if nothing else did.
+#[derive(Clone)]
+pub struct ThreadHandle(Rc<RefCell<Option<JoinHandle<Result<Sendable>>>>>);
+impl Debug for ThreadHandle {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        write!(f, "ThreadHandle({:p})", Rc::as_ptr(&self.0))
+    }
+}
+impl PartialEq for ThreadHandle {
+    fn eq(&self, other: &Self)->bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl ThreadHandle {
+    /// Wait for the thread to finish and return what it returned
+    pub fn join(&self)->Result<Sendable> {
+        let Some(handle) = self.0.borrow_mut().take() else {
+            bail!(LispError::ThreadJoined);
+        };
+
+        match handle.join() {
+            Ok(Ok(value))=>Ok(value),
+            Ok(Err(error))=>bail!(LispError::ThreadFailed{error}),
+            Err(_)=>bail!(LispError::ThreadPanicked),
+        }
+    }
+}
+
+
+/// What a thread's interpreter copies from the one that spawned it
+struct ThreadSetup {
+    program: ProgramCopy,
+    gc_config: GcConfig,
+    max_stack_depth: usize,
+    max_instructions: Option<u64>,
+    timeout: Option<Duration>,
+    capabilities: Capabilities,
+    strict_bool: bool,
+    script: Option<String>,
+    script_args: Vec<String>,
+    globals: Vec<(String, Sendable)>,
+    func: FnId,
+    /// `None` if it isn't a closure
+    captures: Option<Vec<(String, Sendable)>>,
+    output: ThreadOutput,
+}
+
+impl Interpreter {
+    /// Call `func` with no arguments on a new thread with its own interpreter. The thread gets a
+    /// copy of the program, of what `func` captured, and of every global that can be copied.
+    /// Globals that can't, like files, aren't defined there.
+    pub fn spawn(&mut self, state: &ConvertState, func: &DataRef)->Result<ThreadHandle> {
+        let (id, captures) = match &*func.try_get_data("spawn")? {
+            Data::Fn(id)=>(*id, None),
+            Data::Closure{id, captures}=>(*id, Some(captures.0.clone())),
+            data=>bail!(LispError::Type{op: "spawn".into(), expected: "fn", actual: data.type_name()}),
+        };
+
+        let func_def = state.get_fn(id)?;
+        if func_def.sig.match_arg_count(0).is_none() {
+            bail!(LispError::Arity{
+                name: func_def.name.map(|name|state.interner.get(name).to_string()),
+                expected: func_def.sig.signatures(&state.interner),
+                got: 0,
+            });
+        }
+
+        let captures = match captures {
+            Some(captures)=>Some(captures.iter()
+                .map(|(name, data)|Ok((
+                    state.interner.get(*name).to_string(),
+                    Sendable::copy(data, &state.interner, "spawn", true)?,
+                )))
+                .collect::<Result<Vec<_>>>()?
+            ),
+            None=>None,
+        };
+        let globals = self.root_env.iter_vars()
+            .filter(|(name, _)|!self.is_builtin_global(*name))
+            .filter_map(|(name, data)|Some((
+                state.interner.get(name).to_string(),
+                Sendable::copy(data, &state.interner, "spawn", true).ok()?,
+            )))
+            .collect();
+
+        let setup = ThreadSetup {
+            program: state.copy_program(),
+            gc_config: self.gc_config,
+            max_stack_depth: self.max_stack_depth,
+            max_instructions: self.budget.max_instructions,
+            timeout: self.budget.timeout,
+            capabilities: self.capabilities,
+            strict_bool: self.strict_bool,
+            script: self.script.clone(),
+            script_args: self.script_args.clone(),
+            globals,
+            func: id,
+            captures,
+            output: ThreadOutput {
+                sender: self.thread_prints.sender.clone(),
+                terminal: self.output.is_terminal(),
+            },
+        };
+        let handle = Builder::new()
+            .spawn(move||run_thread(setup))
+            .map_err(LispError::from)?;
+
+        let handle = ThreadHandle(Rc::new(RefCell::new(Some(handle))));
+        self.threads.push(handle.clone());
+
+        return Ok(handle);
+    }
+
+    /// Wait for every thread that was never joined, so none of them outlive the interpreter. What
+    /// they returned is thrown away, but failures are still reported.
+    pub(super) fn join_threads(&mut self) {
+        for handle in self.threads.drain(..) {
+            let Some(handle) = handle.0.borrow_mut().take() else {continue};
+            match handle.join() {
+                Ok(Ok(_))=>{},
+                Ok(Err(e))=>eprintln!("Warning: a thread that was never joined failed: {e}"),
+                Err(_)=>eprintln!("Warning: a thread that was never joined panicked"),
+            }
+        }
+
+        let _ = self.write_thread_prints();
+    }
+
+    /// Write what the spawned threads printed so far to this interpreter's output
+    pub fn write_thread_prints(&mut self)->Result<()> {
+        while let Ok(s) = self.thread_prints.receiver.try_recv() {
+            self.output.write_str(&s).map_err(LispError::from)?;
+        }
+
+        return Ok(());
+    }
+}
+
+fn run_thread(setup: ThreadSetup)->Result<Sendable> {
+    let mut state = setup.program.into_state();
+    let mut i = Interpreter::new(&mut state, setup.gc_config, setup.max_stack_depth, setup.max_instructions, Box::new(setup.output));
+    i.set_timeout(setup.timeout);
+    i.set_capabilities(setup.capabilities);
+    i.set_strict_bool(setup.strict_bool);
+    // only the main thread can pause for the REPL
+    i.set_breakpoints(false);
+    i.set_script_args(setup.script, setup.script_args, &mut state);
+
+    // like a native's scope, so what is made here stays rooted until the call returns
+    i.scopes.push(ScopeItem::List(Vec::new()));
+
+    for (name, value) in setup.globals {
+        let name = state.intern(&name);
+        let value = value.into_data(&mut i, &mut state.interner);
+        i.define_global(name, value);
+    }
+
+    let func = match setup.captures {
+        Some(captures)=>{
+            let captures = captures.into_iter()
+                .map(|(name, value)|(state.intern(&name), value.into_data(&mut i, &mut state.interner)))
+                .collect();
+            i.alloc(Data::Closure{id: setup.func, captures: ClosureCaptures(captures)})
+        },
+        None=>i.alloc(Data::Fn(setup.func)),
+    };
+    i.root(&func);
+
+    let out = i.call(&mut state, func, Vec::new())
+        .and_then(|out|Sendable::copy(&out, &state.interner, "join", false));
+    i.scopes.pop();
+
+    return out;
+}
@@ -0,0 +1,410 @@
+//! The assembly-like text form of V2 instructions, like `getg +`, `call 2` and `jf @127`. The
+//! disassembler, `--trace`, the debugger and `-ddd` all show instructions this way, and `parse`
+//! reads it back so compiler tests can be written by hand.
+//!
+//! Operands:
+//! - `%N` is local slot `N`. Globals are written by name, or `$N` if the slot has no name.
+//! - `@N` is the instruction with id `N`.
+//! - Functions are `NAME/ARITY`, like `fib/1`, `list/0+` or `f/1,2`. Anonymous ones are `#N`.
+//! - Modules are written by name. `parse` also takes `#N`.
+//! - Strings and chars are quoted like Rust does. Long strings are cut off unless the alternate
+//!   form (`{:#}`) is used, which `parse` needs to get the same string back.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use misc_utils::Key;
+use std::{
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+    rc::Rc,
+};
+use super::ast::*;
+
+
+/// Strings longer than this many chars are cut off in the short form
+pub const STRING_WIDTH: usize = 24;
+
+
+/// Shows an instruction in the assembly form. Made by `Instruction::asm`.
+pub struct Asm<'a> {
+    ins: &'a Instruction,
+    state: &'a ConvertState,
+}
+impl<'a> Display for Asm<'a> {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        let (mnemonic, operands) = parts(self.ins, self.state, f.alternate());
+        write!(f, "{mnemonic}")?;
+        for op in operands.iter() {
+            write!(f, " {op}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Instruction {
+    pub fn asm<'a>(&'a self, state: &'a ConvertState)->Asm<'a> {
+        Asm {ins: self, state}
+    }
+}
+
+
+/// The mnemonic and operands of `ins`. Strings are only cut off if `full` is false.
+pub fn parts(ins: &Instruction, state: &ConvertState, full: bool)->(&'static str, Vec<String>) {
+    use Instruction as I;
+
+    let var = |slot: &VarSlot, local: &'static str, global: &'static str|match slot.global {
+        true=>(global, global_name(slot.id, state)),
+        false=>(local, format!("%{}", slot.id)),
+    };
+
+    match ins {
+        I::Nop=>("nop", Vec::new()),
+        I::Exit=>("exit", Vec::new()),
+        I::ReturnModule=>("retmod", Vec::new()),
+        I::Module(id)=>("module", vec![module_name(*id, state)]),
+        I::Func(id)=>("func", vec![fn_ref(*id, state)]),
+        I::SetVar(slot)=>{
+            let (mnemonic, op) = var(slot, "set", "setg");
+            (mnemonic, vec![op])
+        },
+        I::SetPath(slot, path)=>{
+            let (mnemonic, op) = var(slot, "setp", "setpg");
+            let path = path.iter()
+                .map(|i|state.interner.get(*i))
+                .collect::<Vec<_>>()
+                .join(".");
+            (mnemonic, vec![op, path])
+        },
+        I::GetVar(slot)=>{
+            let (mnemonic, op) = var(slot, "get", "getg");
+            (mnemonic, vec![op])
+        },
+        I::Field(i)=>("field", vec![state.interner.get(*i).to_string()]),
+        I::Number(n)=>("int", vec![n.to_string()]),
+        I::Float(n)=>("float", vec![format!("{n:?}")]),
+        I::String(s)=>match full || s.chars().count() <= STRING_WIDTH {
+            true=>("str", vec![format!("{s:?}")]),
+            false=>{
+                let cut = s.chars().take(STRING_WIDTH).collect::<String>();
+                ("str", vec![format!("{cut:?}...")])
+            },
+        },
+        I::Char(c)=>("char", vec![format!("{c:?}")]),
+        I::Bool(true)=>("bool", vec!["#t".into()]),
+        I::Bool(false)=>("bool", vec!["#f".into()]),
+        I::Byte(b)=>("byte", vec![b.to_string()]),
+        I::Ident(i)=>("ident", vec![state.interner.get(*i).to_string()]),
+        I::None=>("none", Vec::new()),
+        I::Splat=>("splat", Vec::new()),
+        I::Unpack(names)=>{
+            let mut ops = names.items.iter()
+                .map(|i|state.interner.get(*i).to_string())
+                .collect::<Vec<_>>();
+            if let Some(rem) = names.remainder {
+                ops.push("&".into());
+                ops.push(state.interner.get(rem).to_string());
+            }
+            ("unpack", ops)
+        },
+        I::Call(count)=>("call", vec![count.to_string()]),
+        I::TailCall(count)=>("tcall", vec![count.to_string()]),
+        I::CallBuiltin(id, count)=>("callb", vec![id.name().to_string(), count.to_string()]),
+        I::Return=>("ret", Vec::new()),
+        I::Scope(count)=>("scope", vec![count.to_string()]),
+        I::EndScope(count)=>("endscope", vec![count.to_string()]),
+        I::JumpIfTrue(id)=>("jt", vec![format!("@{}", id.inner())]),
+        I::JumpIfFalse(id)=>("jf", vec![format!("@{}", id.inner())]),
+        I::Jump(id)=>("jmp", vec![format!("@{}", id.inner())]),
+        I::GetVarCall(slot, count)=>{
+            let (mnemonic, op) = var(slot, "getcall", "getgcall");
+            (mnemonic, vec![op, count.to_string()])
+        },
+        I::NumberSetVar(n, slot)=>{
+            let (mnemonic, op) = var(slot, "intset", "intsetg");
+            (mnemonic, vec![n.to_string(), op])
+        },
+    }
+}
+
+fn global_name(id: usize, state: &ConvertState)->String {
+    match state.vars.globals().nth(id) {
+        Some(name)=>state.interner.get(name).to_string(),
+        None=>format!("${id}"),
+    }
+}
+
+fn module_name(id: ModuleId, state: &ConvertState)->String {
+    state.interner.get(state.modules.get(id).name).to_string()
+}
+
+/// `NAME/ARITY`, or `#ID` for anonymous functions
+fn fn_ref(id: FnId, state: &ConvertState)->String {
+    match state.fns.get(id).and_then(|f|f.name.map(|name|(name, f))) {
+        Some((name, f))=>format!("{}/{}", state.interner.get(name), arity(&f.sig)),
+        None=>format!("#{}", id.id()),
+    }
+}
+
+/// Each argument count the function takes, like `1,2` or `0+`
+fn arity(sig: &FnSignature)->String {
+    let count = |v: &Vector|match v.remainder {
+        Some(_)=>format!("{}+", v.items.len()),
+        None=>v.items.len().to_string(),
+    };
+
+    return sig.bodies()
+        .into_iter()
+        .map(|(params, _)|count(params))
+        .collect::<Vec<_>>()
+        .join(",");
+}
+
+
+/// Read one instruction in the assembly form. Anything after a `;` is a comment. Names are
+/// interned, but globals, functions and modules have to exist already.
+pub fn parse(text: &str, state: &mut ConvertState)->Result<Instruction> {
+    use Instruction as I;
+
+    let tokens = tokenize(text)?;
+    let Some((mnemonic, operands)) = tokens.split_first() else {
+        bail!("Expected an instruction");
+    };
+    let operands = operands.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let expect = |count: usize|->Result<()> {
+        if operands.len() != count {
+            bail!("`{mnemonic}` takes {count} operands, but got {}", operands.len());
+        }
+        return Ok(());
+    };
+    let local = |s: &str|->Result<VarSlot> {
+        match s.strip_prefix('%').and_then(|id|id.parse().ok()) {
+            Some(id)=>Ok(VarSlot {id, global: false}),
+            None=>bail!("Expected a local slot like `%3`, but got `{s}`"),
+        }
+    };
+    let target = |s: &str|->Result<InstructionId> {
+        match s.strip_prefix('@').and_then(|id|id.parse().ok()) {
+            Some(id)=>Ok(InstructionId::from_inner(id)),
+            None=>bail!("Expected an instruction id like `@12`, but got `{s}`"),
+        }
+    };
+
+    let ins = match mnemonic.as_str() {
+        "nop"=>{expect(0)?; I::Nop},
+        "exit"=>{expect(0)?; I::Exit},
+        "retmod"=>{expect(0)?; I::ReturnModule},
+        "none"=>{expect(0)?; I::None},
+        "splat"=>{expect(0)?; I::Splat},
+        "ret"=>{expect(0)?; I::Return},
+        "module"=>{expect(1)?; I::Module(parse_module(operands[0], state)?)},
+        "func"=>{expect(1)?; I::Func(parse_fn(operands[0], state)?)},
+        "set"=>{expect(1)?; I::SetVar(local(operands[0])?)},
+        "setg"=>{expect(1)?; I::SetVar(parse_global(operands[0], state)?)},
+        "get"=>{expect(1)?; I::GetVar(local(operands[0])?)},
+        "getg"=>{expect(1)?; I::GetVar(parse_global(operands[0], state)?)},
+        "setp"|"setpg"=>{
+            expect(2)?;
+            let slot = match mnemonic.as_str() {
+                "setp"=>local(operands[0])?,
+                _=>parse_global(operands[0], state)?,
+            };
+            let path = operands[1].split('.')
+                .map(|name|state.interner.intern(name))
+                .collect();
+            I::SetPath(slot, Rc::new(path))
+        },
+        "field"=>{expect(1)?; I::Field(state.interner.intern(operands[0]))},
+        "unpack"=>{
+            let (items, remainder) = match operands.iter().position(|op|*op == "&") {
+                Some(i) if i + 2 == operands.len()=>(&operands[..i], Some(state.interner.intern(operands[i + 1]))),
+                Some(_)=>bail!("`unpack` takes one name after `&`"),
+                None=>(&operands[..], None),
+            };
+            let items = items.iter()
+                .map(|name|state.interner.intern(name))
+                .collect();
+            I::Unpack(Rc::new(Vector {items, remainder}))
+        },
+        "ident"=>{expect(1)?; I::Ident(state.interner.intern(operands[0]))},
+        "int"=>{expect(1)?; I::Number(parse_num(operands[0])?)},
+        "float"=>{expect(1)?; I::Float(parse_num(operands[0])?)},
+        "byte"=>{expect(1)?; I::Byte(parse_num(operands[0])?)},
+        "bool"=>{
+            expect(1)?;
+            match operands[0] {
+                "#t"=>I::Bool(true),
+                "#f"=>I::Bool(false),
+                other=>bail!("Expected `#t` or `#f`, but got `{other}`"),
+            }
+        },
+        "str"=>{
+            expect(1)?;
+            let s = unquote(operands[0], '"')?;
+            I::String(state.constants.string(s))
+        },
+        "char"=>{
+            expect(1)?;
+            let s = unquote(operands[0], '\'')?;
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None)=>I::Char(c),
+                _=>bail!("Expected one char, but got `{}`", operands[0]),
+            }
+        },
+        "call"=>{expect(1)?; I::Call(parse_num(operands[0])?)},
+        "tcall"=>{expect(1)?; I::TailCall(parse_num(operands[0])?)},
+        "callb"=>{
+            expect(2)?;
+            let slot = parse_global(operands[0], state)?;
+            let Some(id) = BuiltinId::from_inner(slot.id) else {
+                bail!("`{}` is not a builtin", operands[0]);
+            };
+            I::CallBuiltin(id, parse_num(operands[1])?)
+        },
+        "scope"=>{expect(1)?; I::Scope(parse_num(operands[0])?)},
+        "endscope"=>{expect(1)?; I::EndScope(parse_num(operands[0])?)},
+        "jt"=>{expect(1)?; I::JumpIfTrue(target(operands[0])?)},
+        "jf"=>{expect(1)?; I::JumpIfFalse(target(operands[0])?)},
+        "jmp"=>{expect(1)?; I::Jump(target(operands[0])?)},
+        "getcall"=>{expect(2)?; I::GetVarCall(local(operands[0])?, parse_num(operands[1])?)},
+        "getgcall"=>{expect(2)?; I::GetVarCall(parse_global(operands[0], state)?, parse_num(operands[1])?)},
+        "intset"=>{expect(2)?; I::NumberSetVar(parse_num(operands[0])?, local(operands[1])?)},
+        "intsetg"=>{expect(2)?; I::NumberSetVar(parse_num(operands[0])?, parse_global(operands[1], state)?)},
+        other=>bail!("Unknown instruction `{other}`"),
+    };
+
+    return Ok(ins);
+}
+
+fn parse_num<T: std::str::FromStr>(s: &str)->Result<T> {
+    match s.parse() {
+        Ok(n)=>Ok(n),
+        Err(_)=>bail!("Expected a number, but got `{s}`"),
+    }
+}
+
+fn parse_global(s: &str, state: &ConvertState)->Result<VarSlot> {
+    if let Some(id) = s.strip_prefix('$').and_then(|id|id.parse().ok()) {
+        return Ok(VarSlot {id, global: true});
+    }
+
+    let id = state.vars.globals().position(|name|state.interner.get(name) == s);
+    match id {
+        Some(id)=>Ok(VarSlot {id, global: true}),
+        None=>bail!("There is no global named `{s}`"),
+    }
+}
+
+fn parse_module(s: &str, state: &ConvertState)->Result<ModuleId> {
+    if let Some(id) = s.strip_prefix('#').and_then(|id|id.parse().ok()) {
+        return Ok(ModuleId::from_id(id));
+    }
+
+    let mut found = state.module_ids()
+        .into_iter()
+        .filter(|id|state.interner.get(state.modules.get(*id).name) == s);
+    match (found.next(), found.next()) {
+        (Some(id), None)=>Ok(id),
+        (Some(_), Some(_))=>bail!("There is more than one module named `{s}`. Use its id, like `#3`."),
+        (None, _)=>bail!("There is no module named `{s}`"),
+    }
+}
+
+fn parse_fn(s: &str, state: &ConvertState)->Result<FnId> {
+    if let Some(id) = s.strip_prefix('#').and_then(|id|id.parse().ok()) {
+        return Ok(FnId::from_id(id));
+    }
+
+    let mut found = state.fn_ids()
+        .into_iter()
+        .filter(|id|fn_ref(*id, state) == s);
+    match (found.next(), found.next()) {
+        (Some(id), None)=>Ok(id),
+        (Some(_), Some(_))=>bail!("There is more than one function `{s}`. Use its id, like `#3`."),
+        (None, _)=>bail!("There is no function `{s}`"),
+    }
+}
+
+/// Split on whitespace, keeping quoted strings and chars together. Stops at a `;` outside of quotes.
+fn tokenize(text: &str)->Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';'=>break,
+            c if c.is_whitespace()=>{chars.next();},
+            '"'|'\''=>{
+                let mut token = String::from(chars.next().unwrap());
+                let mut escaped = false;
+                loop {
+                    let Some(next) = chars.next() else {
+                        bail!("Unclosed {c} in `{text}`");
+                    };
+                    token.push(next);
+                    match next {
+                        '\\' if !escaped=>escaped = true,
+                        next if next == c && !escaped=>break,
+                        _=>escaped = false,
+                    }
+                }
+                tokens.push(token);
+            },
+            _=>{
+                let mut token = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == ';' {break}
+                    token.push(next);
+                    chars.next();
+                }
+                tokens.push(token);
+            },
+        }
+    }
+
+    return Ok(tokens);
+}
+
+/// Undo the `{:?}` quoting of a string or char
+fn unquote(s: &str, quote: char)->Result<String> {
+    let Some(inner) = s.strip_prefix(quote).and_then(|s|s.strip_suffix(quote)) else {
+        bail!("Expected {quote}quotes{quote} around `{s}`");
+    };
+
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n')=>out.push('\n'),
+            Some('t')=>out.push('\t'),
+            Some('r')=>out.push('\r'),
+            Some('0')=>out.push('\0'),
+            Some('u')=>{
+                let code = chars.by_ref()
+                    .skip_while(|c|*c == '{')
+                    .take_while(|c|*c != '}')
+                    .collect::<String>();
+                match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    Some(c)=>out.push(c),
+                    None=>bail!("Bad unicode escape in `{s}`"),
+                }
+            },
+            Some(c @ ('\\'|'"'|'\''))=>out.push(c),
+            _=>bail!("Bad escape in `{s}`"),
+        }
+    }
+
+    return Ok(out);
+}
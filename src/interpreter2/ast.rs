@@ -16,16 +16,12 @@ use std::{
         Hasher,
         Hash,
     },
-    fmt::{
-        Display,
-        Formatter,
-        Result as FmtResult,
-    },
-    error::Error as ErrorTrait,
     result::Result as StdResult,
     collections::VecDeque,
-    fs::read_to_string,
-    path::PathBuf,
+    path::{
+        Path,
+        PathBuf,
+    },
     rc::Rc,
 };
 use crate::{
@@ -35,9 +31,26 @@ use crate::{
         Vector as RefVector,
         Fn as RefFn,
     },
-    error_trace,
+    error::{
+        Import,
+        LispError,
+    },
+    suggest::similar_names,
+    source::{
+        SearchPath,
+        find_module,
+        read_source,
+        top_level_spans,
+    },
+    diagnostic::line_column,
 };
 use super::{
+    builtins,
+    source_map::{
+        LazySourceMap,
+        Location,
+        SourceMap,
+    },
     FxIndexMap,
     FxIndexSet,
     DEFAULT_GLOBALS,
@@ -76,11 +89,18 @@ pub enum Instruction {
     None,
 
     Splat,
+    /// Pops a list and pushes the list of items past the names if there is a remainder, then the
+    /// items from last to first, so `SetVar`s for the names in order take them. Errors if the list
+    /// doesn't have the right length.
+    Unpack(Rc<Vector>),
 
     /// Checks if the first data in the scope is callable. If so, then it calls it with the
     /// arguments. If not, then it throws an error.
     Call(usize),
     TailCall(usize),
+    /// Call a builtin that was never reassigned without looking it up. Used for calls in tail
+    /// position too, since builtins don't need a new frame.
+    CallBuiltin(BuiltinId, usize),
     Return,
 
     /// Start a scope with the given var slots
@@ -92,8 +112,62 @@ pub enum Instruction {
     JumpIfTrue(InstructionId),
     JumpIfFalse(InstructionId),
     Jump(InstructionId),
+
+    // Superinstructions. These are only made by `optimize::fuse_instructions`, and each one does
+    // the same thing as the pair it replaces.
+
+    /// `GetVar` then `Call`
+    GetVarCall(VarSlot, usize),
+    /// `Number` then `SetVar`
+    NumberSetVar(i64, VarSlot),
 }
-#[derive(Debug, PartialEq)]
+impl Instruction {
+    /// The name of each opcode, indexed by `opcode`
+    pub const OPCODE_NAMES: &'static [&'static str] = &[
+        "Nop", "Exit", "ReturnModule", "Module", "Func", "SetVar", "SetPath", "GetVar", "Field",
+        "Number", "Float", "String", "Char", "Bool", "Byte", "Ident", "None", "Splat", "Call",
+        "TailCall", "CallBuiltin", "Return", "Scope", "EndScope", "JumpIfTrue", "JumpIfFalse",
+        "Jump", "GetVarCall", "NumberSetVar", "Unpack",
+    ];
+
+    /// A number for each kind of instruction, for counting them
+    pub fn opcode(&self)->usize {
+        match self {
+            Self::Nop=>0,
+            Self::Exit=>1,
+            Self::ReturnModule=>2,
+            Self::Module(..)=>3,
+            Self::Func(..)=>4,
+            Self::SetVar(..)=>5,
+            Self::SetPath(..)=>6,
+            Self::GetVar(..)=>7,
+            Self::Field(..)=>8,
+            Self::Number(..)=>9,
+            Self::Float(..)=>10,
+            Self::String(..)=>11,
+            Self::Char(..)=>12,
+            Self::Bool(..)=>13,
+            Self::Byte(..)=>14,
+            Self::Ident(..)=>15,
+            Self::None=>16,
+            Self::Splat=>17,
+            Self::Call(..)=>18,
+            Self::TailCall(..)=>19,
+            Self::CallBuiltin(..)=>20,
+            Self::Return=>21,
+            Self::Scope(..)=>22,
+            Self::EndScope(..)=>23,
+            Self::JumpIfTrue(..)=>24,
+            Self::JumpIfFalse(..)=>25,
+            Self::Jump(..)=>26,
+            Self::GetVarCall(..)=>27,
+            Self::NumberSetVar(..)=>28,
+            Self::Unpack(..)=>29,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FnSignature {
     Single {
         params: Vector,
@@ -107,6 +181,9 @@ pub enum FnSignature {
     },
 }
 impl FnSignature {
+    /// The body to run for `count` args. A multi-arity fn picks the branch that takes exactly
+    /// `count`, then the `&` branch with the largest minimum that `count` reaches, then `[& rest]`.
+    /// The order the branches are written in doesn't matter.
     pub fn match_arg_count(&self, count: usize)->Option<(&Vector, InstructionId)> {
         match self {
             Self::Single{params, body_ptr}=>{
@@ -128,10 +205,11 @@ impl FnSignature {
                     }
                 }
 
-                for (min_param_count, (params, body_ptr)) in at_least.iter() {
-                    if count >= *min_param_count {
-                        return Some((params, *body_ptr));
-                    }
+                let closest = at_least.iter()
+                    .filter(|(min_param_count, _)|count >= **min_param_count)
+                    .max_by_key(|(min_param_count, _)|**min_param_count);
+                if let Some((_, (params, body_ptr))) = closest {
+                    return Some((params, *body_ptr));
                 }
 
                 if let Some((params, body_ptr)) = any {
@@ -142,6 +220,37 @@ impl FnSignature {
             },
         }
     }
+
+    /// The parameters and body pointer of every body in this signature
+    pub fn bodies(&self)->Vec<(&Vector, InstructionId)> {
+        match self {
+            Self::Single{params, body_ptr}=>vec![(params, *body_ptr)],
+            Self::Multi{exact, at_least, any, ..}=>{
+                let mut out = Vec::new();
+                out.extend(exact.values().map(|(params, body_ptr)|(params, *body_ptr)));
+                out.extend(at_least.values().map(|(params, body_ptr)|(params, *body_ptr)));
+                out.extend(any.iter().map(|(params, body_ptr)|(params, *body_ptr)));
+
+                out
+            },
+        }
+    }
+
+    /// A copy with `f` applied to every body pointer
+    pub fn map_bodies(&self, mut f: impl FnMut(InstructionId)->InstructionId)->Self {
+        let mut sig = self.clone();
+        match &mut sig {
+            Self::Single{body_ptr, ..}=>*body_ptr = f(*body_ptr),
+            Self::Multi{exact, at_least, any, ..}=>{
+                exact.values_mut()
+                    .chain(at_least.values_mut())
+                    .chain(any.iter_mut())
+                    .for_each(|(_, body_ptr)|*body_ptr = f(*body_ptr));
+            },
+        }
+
+        return sig;
+    }
 }
 
 
@@ -157,12 +266,23 @@ impl Hash for VarSlot {
     }
 }
 
-#[derive(Debug)]
-pub struct ModuleError;
-impl ErrorTrait for ModuleError {}
-impl Display for ModuleError {
-    fn fmt(&self, f: &mut Formatter)->FmtResult {
-        write!(f, "Module error")
+/// An index into `builtins::ROOT`. The builtins are also the first global slots, so this is the
+/// same as their `VarSlot` id.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BuiltinId(usize);
+impl BuiltinId {
+    pub fn from_inner(inner: usize)->Option<Self> {
+        if inner < builtins::ROOT.len() {
+            Some(BuiltinId(inner))
+        } else {
+            None
+        }
+    }
+
+    pub const fn inner(&self)->usize {self.0}
+
+    pub fn name(&self)->&'static str {
+        builtins::ROOT[self.0].0
     }
 }
 
@@ -197,13 +317,31 @@ impl InstructionId {
     }
 
     pub const fn inner(&self)->usize {self.0}
+
+    pub const fn from_inner(inner: usize)->Self {
+        InstructionId(inner)
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Vector {
     pub items: Vec<Ident>,
     pub remainder: Option<Ident>,
 }
+impl Vector {
+    /// Formats the vector like it is written in the source: `[a b & rest]`
+    pub fn describe(&self, interner: &Interner)->String {
+        let mut out = self.items.iter()
+            .map(|i|interner.get(*i))
+            .collect::<Vec<_>>();
+        if let Some(rem) = self.remainder {
+            out.push("&");
+            out.push(interner.get(rem));
+        }
+
+        return format!("[{}]", out.join(" "));
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Fn {
@@ -236,10 +374,95 @@ impl Interner {
         self.0.get_index(i.0)
             .expect("Invalid interned ident passed")
     }
+
+    /// Like `get`, but `None` for an ident that didn't come from this interner
+    pub fn get_opt(&self, i: Ident)->Option<&str> {
+        self.0.get_index(i.0).map(String::as_str)
+    }
+
+    /// The ident for `s` if it was already interned. Doesn't intern it if it wasn't.
+    pub fn lookup(&self, s: &str)->Option<Ident> {
+        self.0.get_index_of(s).map(Ident)
+    }
+
+    #[inline]
+    pub fn len(&self)->usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self)->bool {
+        self.0.is_empty()
+    }
+
+    /// Every interned string with its ident, in the order of their `Ident`s
+    pub fn iter(&self)->impl Iterator<Item = (Ident, &str)> {
+        self.0.iter()
+            .enumerate()
+            .map(|(i, s)|(Ident(i), s.as_str()))
+    }
+
+    /// Forget everything interned after the first `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
 }
 
+/// Shares one `Rc` between every identical string literal. Chars and floats are stored inline in
+/// their instructions, so there is nothing to share for those. Strings are immutable in the
+/// language, so nothing can tell two literals apart by their identity.
+#[derive(Debug, Default)]
+pub struct ConstPool {
+    strings: FxIndexSet<Rc<String>>,
+    /// Every string literal we were asked for, including the duplicates
+    string_literals: usize,
+    /// Bytes of string data we didn't have to allocate again
+    saved_bytes: usize,
+}
+impl ConstPool {
+    pub fn new()->Self {
+        Self::default()
+    }
+
+    /// The shared copy of `s`
+    pub fn string(&mut self, s: String)->Rc<String> {
+        self.string_literals += 1;
+
+        if let Some(existing) = self.strings.get(&s) {
+            self.saved_bytes += s.len();
+            return existing.clone();
+        }
+
+        let rc = Rc::new(s);
+        self.strings.insert(rc.clone());
+
+        return rc;
+    }
+
+    /// The index of `s` in `Self::strings`
+    pub fn string_index(&self, s: &String)->Option<usize> {
+        self.strings.get_index_of(s)
+    }
+
+    /// All of the unique strings
+    pub fn strings(&self)->impl Iterator<Item = &Rc<String>> {
+        self.strings.iter()
+    }
+
+    /// How many string literals were converted, and how many of those were unique
+    pub fn string_counts(&self)->(usize, usize) {
+        (self.string_literals, self.strings.len())
+    }
+
+    #[inline]
+    pub fn saved_bytes(&self)->usize {
+        self.saved_bytes
+    }
+}
+
+#[derive(Clone)]
 pub struct InstructionStore {
-    /// Immutable list of instructions. Nothing gets deleted from here.
+    /// Every instruction, indexed by id. Nothing gets deleted from here except by `compact`.
     instructions: Vec<Instruction>,
 
     /// A list of instruction indices describing the order that they execute. Things CAN be removed
@@ -255,6 +478,86 @@ impl InstructionStore {
         }
     }
 
+    /// Rebuild a store from the raw instruction list and execution order. Used when loading
+    /// bytecode files.
+    pub fn from_parts(instructions: Vec<Instruction>, ins_order: FxIndexSet<InstructionId>)->Self {
+        InstructionStore {
+            instructions,
+            ins_order,
+        }
+    }
+
+    /// All instructions in the order they were created, including ones that aren't executed
+    pub fn raw_instructions(&self)->&[Instruction] {
+        &self.instructions
+    }
+
+    pub fn ins_order(&self)->&FxIndexSet<InstructionId> {
+        &self.ins_order
+    }
+
+    /// Remove instructions from the execution order. They stay in the instruction list so none of
+    /// the ids change.
+    pub fn retain_order(&mut self, mut f: impl FnMut(InstructionId)->bool) {
+        self.ins_order.retain(|id|f(*id));
+    }
+
+    /// Take `id` out of the execution order. Returns false if it wasn't in it. Like
+    /// `retain_order`, the instruction stays until `compact`.
+    pub fn remove(&mut self, id: InstructionId)->bool {
+        self.ins_order.shift_remove(&id)
+    }
+
+    /// Keep only the instructions `f` returns true for in the execution order
+    pub fn retain(&mut self, mut f: impl FnMut(InstructionId, &Instruction)->bool) {
+        let instructions = &self.instructions;
+        self.ins_order.retain(|id|f(*id, &instructions[id.0]));
+    }
+
+    /// Drop every instruction that isn't in the execution order and renumber the rest in the order
+    /// they execute. Jumps are pointed at the new ids; anything else that holds an
+    /// `InstructionId` has to be rewritten with the returned table.
+    pub fn compact(&mut self)->InstructionRemap {
+        let mut old = std::mem::take(&mut self.instructions)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let mut remap = vec![None; old.len()];
+
+        for (new, id) in self.ins_order.iter().enumerate() {
+            remap[id.0] = Some(InstructionId(new));
+            self.instructions.push(old[id.0].take().unwrap());
+        }
+        self.ins_order = (0..self.instructions.len()).map(InstructionId).collect();
+
+        let remap = InstructionRemap(remap);
+        for ins in self.instructions.iter_mut() {
+            match ins {
+                Instruction::JumpIfTrue(target)|
+                    Instruction::JumpIfFalse(target)|
+                    Instruction::Jump(target)=>*target = remap.expect(*target),
+                _=>{},
+            }
+        }
+
+        return remap;
+    }
+
+    /// Every function referenced by a `Func` instruction, sorted by id. This includes the ones
+    /// that aren't executed.
+    pub fn fn_ids(&self)->Vec<FnId> {
+        let mut ids = self.instructions.iter()
+            .filter_map(|ins|match ins {
+                Instruction::Func(id)=>Some(*id),
+                _=>None,
+            })
+            .collect::<Vec<_>>();
+        ids.sort_by_key(|id|id.id());
+        ids.dedup();
+
+        return ids;
+    }
+
     pub fn get_mut(&mut self, id: InstructionId)->&mut Instruction {
         assert!(id.is_valid() && id.0 < self.instructions.len());
 
@@ -309,12 +612,200 @@ impl InstructionStore {
         return id;
     }
 
+    /// A cursor over the execution order for passes that need to look around. The interpreter uses
+    /// `iter` instead.
+    pub fn cursor(&self)->InstructionCursor<'_> {
+        InstructionCursor {
+            inner: self,
+            front: 0,
+            back: self.ins_order.len(),
+        }
+    }
+
+    /// A cursor that starts at `id`. `prev` can still go back before it.
+    pub fn iter_from(&self, id: InstructionId)->InstructionCursor<'_> {
+        let front = self.ins_order
+            .get_index_of(&id)
+            .expect("Invalid ID");
+
+        InstructionCursor {
+            inner: self,
+            front,
+            back: self.ins_order.len(),
+        }
+    }
+
     pub fn iter(&self)->InstructionIter {
         InstructionIter {
             inner: self,
             index: 0,
         }
     }
+
+    /// Remove every instruction from `len` on, from the store and from the execution order
+    pub fn truncate(&mut self, len: usize) {
+        self.instructions.truncate(len);
+        self.ins_order.retain(|id|id.0 < len);
+    }
+}
+
+/// Walks the execution order in both directions and can look ahead as far as it wants. This is for
+/// the optimizer; the dispatch loop keeps using the simpler `InstructionIter`.
+#[derive(Clone)]
+pub struct InstructionCursor<'a> {
+    inner: &'a InstructionStore,
+    /// Order index of what `next` returns
+    front: usize,
+    /// One past the order index of what `next_back` returns
+    back: usize,
+}
+impl<'a> InstructionCursor<'a> {
+    fn get(&self, index: usize)->&'a Instruction {
+        let inner: &'a InstructionStore = self.inner;
+        &inner.instructions[inner.ins_order[index].0]
+    }
+
+    /// The instruction `k` places after the next one without moving. `peek_n(0)` is what `next`
+    /// would return.
+    pub fn peek_n(&self, k: usize)->Option<&'a Instruction> {
+        let index = self.front + k;
+        if index >= self.back {
+            return None;
+        }
+
+        return Some(self.get(index));
+    }
+
+    #[inline]
+    pub fn peek(&self)->Option<&'a Instruction> {
+        self.peek_n(0)
+    }
+
+    /// Step back one. This returns the instruction `next` just returned, and `next` will return it
+    /// again.
+    pub fn prev(&mut self)->Option<&'a Instruction> {
+        if self.front == 0 {
+            return None;
+        }
+        self.front -= 1;
+
+        return Some(self.get(self.front));
+    }
+
+    /// The id of what `peek_n(k)` returns
+    pub fn peek_id_n(&self, k: usize)->Option<InstructionId> {
+        let index = self.front + k;
+        if index >= self.back {
+            return None;
+        }
+
+        return self.inner.ins_order.get_index(index).copied();
+    }
+
+    /// The id of what `next` would return
+    #[inline]
+    pub fn next_ins_id(&self)->Option<InstructionId> {
+        self.peek_id_n(0)
+    }
+
+    /// The id of what `next` returned last
+    pub fn cur_ins_id(&self)->Option<InstructionId> {
+        let index = self.front.checked_sub(1)?;
+        self.inner.ins_order.get_index(index).copied()
+    }
+
+    /// Every run of `n` instructions in a row from here on, moving one at a time
+    pub fn windows(self, n: usize)->Windows<'a> {
+        assert!(n > 0, "Windows can't be empty");
+
+        Windows {
+            cursor: self,
+            size: n,
+            advance: 0,
+            instructions: Vec::with_capacity(n),
+            ids: Vec::with_capacity(n),
+        }
+    }
+}
+impl<'a> Iterator for InstructionCursor<'a> {
+    type Item = &'a Instruction;
+    fn next(&mut self)->Option<Self::Item> {
+        let ins = self.peek()?;
+        self.front += 1;
+        return Some(ins);
+    }
+
+    fn size_hint(&self)->(usize, Option<usize>) {
+        let len = self.back.saturating_sub(self.front);
+        (len, Some(len))
+    }
+}
+impl<'a> DoubleEndedIterator for InstructionCursor<'a> {
+    fn next_back(&mut self)->Option<Self::Item> {
+        if self.back <= self.front {
+            return None;
+        }
+        self.back -= 1;
+
+        return Some(self.get(self.back));
+    }
+}
+impl<'a> ExactSizeIterator for InstructionCursor<'a> {}
+
+/// Made by `InstructionCursor::windows`. The windows borrow from this, so it isn't an `Iterator`.
+pub struct Windows<'a> {
+    cursor: InstructionCursor<'a>,
+    size: usize,
+    /// How far to move before the next window
+    advance: usize,
+    instructions: Vec<&'a Instruction>,
+    ids: Vec<InstructionId>,
+}
+impl<'a> Windows<'a> {
+    pub fn next_window(&mut self)->Option<&[&'a Instruction]> {
+        let buffered = self.advance.min(self.instructions.len());
+        self.instructions.drain(..buffered);
+        self.ids.drain(..buffered);
+        for _ in buffered..self.advance {
+            self.cursor.next()?;
+        }
+        self.advance = 1;
+
+        while self.instructions.len() < self.size {
+            let ins = self.cursor.next()?;
+            self.instructions.push(ins);
+            self.ids.push(self.cursor.cur_ins_id().unwrap());
+        }
+
+        return Some(&self.instructions);
+    }
+
+    /// The ids of the window `next_window` returned last
+    pub fn ids(&self)->&[InstructionId] {
+        &self.ids
+    }
+
+    /// Move `count` more instructions along before the next window, like after a pass used up
+    /// the start of this one
+    pub fn skip(&mut self, count: usize) {
+        self.advance += count;
+    }
+}
+
+/// Where each instruction went after `InstructionStore::compact`
+#[derive(Debug, Clone)]
+pub struct InstructionRemap(Vec<Option<InstructionId>>);
+impl InstructionRemap {
+    /// The new id of `old`, or `None` if it was dropped
+    pub fn get(&self, old: InstructionId)->Option<InstructionId> {
+        self.0.get(old.0).copied().flatten()
+    }
+
+    /// The new id of `old`. Panics if it was dropped, since whatever pointed at it is broken.
+    pub fn expect(&self, old: InstructionId)->InstructionId {
+        self.get(old)
+            .unwrap_or_else(||panic!("Instruction {} was dropped, but something still points at it", old.0))
+    }
 }
 
 pub struct InstructionIter<'a> {
@@ -343,6 +834,12 @@ impl<'a> InstructionIter<'a> {
         self.inner.ins_order.get_index(self.index.saturating_sub(1)).copied()
     }
 
+    /// Where the next instruction is in the execution order
+    #[inline]
+    pub fn position(&self)->usize {
+        self.index
+    }
+
     pub fn peek(&self)->&Instruction {
         let id = self.inner.ins_order.get_index(self.index).unwrap();
 
@@ -378,8 +875,19 @@ impl Key for ModuleId {
 /// Tracking for a global context
 pub struct VarState {
     globals: FxIndexSet<Ident>,
+    /// The `def-` globals of the current module
+    privates: FxIndexSet<Ident>,
+    /// The `defconst` globals of the current module and the prelude
+    constants: FxIndexSet<Ident>,
+    /// How many globals every module starts with: the defaults, then the prelude's
+    base_globals: usize,
+    /// The prelude globals the current module defined again. Only the first time is silent.
+    redefined: FxIndexSet<Ident>,
     scopes: Vec<VarScope>,
     scope_var_count: usize,
+    /// Set for each builtin that is reassigned somewhere. Never reset since every module shares
+    /// the builtin globals.
+    shadowed_builtins: Vec<bool>,
 }
 impl VarState {
     pub fn new(interner: &mut Interner)->Self {
@@ -391,47 +899,140 @@ impl VarState {
 
         return VarState {
             globals,
+            privates: FxIndexSet::default(),
+            constants: FxIndexSet::default(),
+            base_globals: DEFAULT_GLOBALS.len(),
+            redefined: FxIndexSet::default(),
             scopes: Vec::new(),
             scope_var_count: 0,
+            shadowed_builtins: vec![false; builtins::ROOT.len()],
         };
     }
 
     pub fn reset(&mut self) {
-        self.globals.drain(DEFAULT_GLOBALS.len()..);
+        self.globals.drain(self.base_globals..);
+        self.privates.clear();
+        let globals = &self.globals;
+        self.constants.retain(|name|globals.contains(name));
+        self.redefined.clear();
         self.scopes.clear();
+        self.scope_var_count = 0;
+    }
+
+    /// The current module's globals, to put back with `restore_globals` after `reset`
+    pub fn save_globals(&self)->SavedGlobals {
+        SavedGlobals {
+            globals: self.globals.clone(),
+            privates: self.privates.clone(),
+            constants: self.constants.clone(),
+            redefined: self.redefined.clone(),
+        }
+    }
+
+    pub fn restore_globals(&mut self, saved: SavedGlobals) {
+        self.globals = saved.globals;
+        self.privates = saved.privates;
+        self.constants = saved.constants;
+        self.redefined = saved.redefined;
     }
 
     pub fn reset_local(&mut self) {
         self.scopes.clear();
+        self.scope_var_count = 0;
+    }
+
+    /// Iterate the globals in slot order, including the defaults
+    pub fn globals(&self)->impl Iterator<Item = Ident> + '_ {
+        self.globals.iter().copied()
+    }
+
+    /// Every global defined so far is kept by `reset`, and the ones after the defaults can be
+    /// redefined once. Called after converting the prelude.
+    pub fn seal_prelude(&mut self) {
+        self.base_globals = self.globals.len();
+    }
+
+    /// The globals the current module defined, in slot order
+    pub fn module_globals(&self)->impl Iterator<Item = Ident> + '_ {
+        self.globals.iter()
+            .enumerate()
+            .filter(|(id, name)|*id >= self.base_globals || self.redefined.contains(*name))
+            .map(|(_, name)|*name)
+    }
+
+    /// Keep the global `name` out of the module's value
+    pub fn make_private(&mut self, name: Ident) {
+        self.privates.insert(name);
+    }
+
+    pub fn is_private(&self, name: Ident)->bool {
+        self.privates.contains(&name)
+    }
+
+    /// Make the global `name` a `defconst`, so it can't be set or defined again
+    pub fn make_constant(&mut self, name: Ident) {
+        self.constants.insert(name);
+    }
+
+    /// Returns true if `slot` is a `defconst` global
+    pub fn is_constant(&self, slot: VarSlot)->bool {
+        slot.global && self.globals.get_index(slot.id).is_some_and(|name|self.constants.contains(name))
+    }
+
+    /// What a top level `def` of `name` would replace, if anything
+    pub fn redefinition(&self, name: Ident)->Option<Redefinition> {
+        if self.scopes.len() > 0 {
+            return None;
+        }
+
+        let id = self.globals.get_index_of(&name)?;
+        let prelude = DEFAULT_GLOBALS.len()..self.base_globals;
+        if id < DEFAULT_GLOBALS.len() {
+            return Some(Redefinition::Builtin);
+        } else if prelude.contains(&id) && !self.redefined.contains(&name) {
+            return Some(Redefinition::Prelude);
+        }
+
+        return Some(Redefinition::Global);
     }
 
-    pub fn insert(&mut self, name: Ident, interner: &Interner)->Result<VarSlot> {
+    /// Define `name` in the innermost scope, or as a global at the top level. Defining a global
+    /// that exists reuses its slot, so everything that refers to it sees the new value.
+    pub fn insert(&mut self, name: Ident)->VarSlot {
         if self.scopes.len() == 0 {
-            if self.globals.contains(&name) {
-                bail!("Global {} already exists", interner.get(name));
+            if let Some(id) = self.globals.get_index_of(&name) {
+                if id >= DEFAULT_GLOBALS.len() && id < self.base_globals {
+                    self.redefined.insert(name);
+                }
+
+                return VarSlot {
+                    id,
+                    global: true,
+                };
             }
 
             let id = self.globals.insert_full(name).0;
 
-            return Ok(VarSlot {
+            return VarSlot {
                 id,
                 global: true,
-            });
+            };
         } else {
             let scope = self.scopes.last_mut().unwrap();
             if let Some(offset) = scope.vars.get_index_of(&name) {
-                return Ok(VarSlot {
+                return VarSlot {
                     id: offset + scope.start_slot,
                     global: false,
-                });
+                };
             }
 
             self.scope_var_count += 1;
             let offset = scope.vars.insert_full(name).0;
-            return Ok(VarSlot {
+            scope.read.push(false);
+            return VarSlot {
                 id: offset + scope.start_slot,
                 global: false,
-            });
+            };
         }
     }
 
@@ -440,14 +1041,16 @@ impl VarState {
             ins_id,
             start_slot: self.scope_var_count,
             vars: FxIndexSet::default(),
+            read: Vec::new(),
         });
     }
 
-    pub fn pop_scope(&mut self)->(InstructionId, usize) {
+    /// Returns where the scope started and the names of its vars in slot order
+    pub fn pop_scope(&mut self)->(InstructionId, Vec<Ident>) {
         let scope = self.scopes.pop().unwrap();
         self.scope_var_count -= scope.vars.len();
 
-        return (scope.ins_id, scope.vars.len());
+        return (scope.ins_id, scope.vars.into_iter().collect());
     }
 
     pub fn get(&self, name: Ident)->Option<VarSlot> {
@@ -469,12 +1072,113 @@ impl VarState {
 
         return None;
     }
+
+    /// Like `get`, but a local is marked as read
+    pub fn read(&mut self, name: Ident)->Option<VarSlot> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(offset) = scope.vars.get_index_of(&name) {
+                scope.read[offset] = true;
+                return Some(VarSlot {
+                    id: offset + scope.start_slot,
+                    global: false,
+                });
+            }
+        }
+
+        return self.get(name);
+    }
+
+    /// The vars of the innermost scope that were never read, in slot order
+    pub fn unread(&self)->Vec<Ident> {
+        let Some(scope) = self.scopes.last() else {return Vec::new()};
+
+        return scope.vars.iter()
+            .zip(scope.read.iter())
+            .filter(|(_, read)|!**read)
+            .map(|(name, _)|*name)
+            .collect();
+    }
+
+    /// Would a new local `name` hide a local of an outer scope? A new var in the innermost scope
+    /// doesn't count, since that is the same var.
+    pub fn shadows_local(&self, name: Ident)->bool {
+        let Some((inner, outer)) = self.scopes.split_last() else {return false};
+        if inner.vars.contains(&name) {
+            return false;
+        }
+
+        return outer.iter().any(|scope|scope.vars.contains(&name));
+    }
+
+    /// Is `name` one of the `DEFAULT_GLOBALS`?
+    pub fn is_default_global(&self, name: Ident)->bool {
+        self.globals.get_index_of(&name)
+            .is_some_and(|id|id < DEFAULT_GLOBALS.len())
+    }
+
+    #[inline]
+    pub fn in_scope(&self)->bool {
+        self.scopes.len() > 0
+    }
+
+    /// Every var that can be referred to right now, including the shadowed ones
+    pub fn visible(&self)->impl Iterator<Item = Ident> + '_ {
+        self.scopes.iter()
+            .flat_map(|scope|scope.vars.iter().copied())
+            .chain(self.globals())
+    }
+
+    /// The builtin `name` refers to, if it does and it hasn't been reassigned
+    pub fn builtin(&self, name: Ident)->Option<BuiltinId> {
+        let slot = self.get(name)?;
+        if !slot.global || self.shadowed_builtins.get(slot.id) != Some(&false) {
+            return None;
+        }
+
+        return BuiltinId::from_inner(slot.id);
+    }
+
+    /// Mark the builtin in `slot` as reassigned. Returns the builtin if this is the first time.
+    pub fn shadow_builtin(&mut self, slot: VarSlot)->Option<BuiltinId> {
+        if !slot.global {
+            return None;
+        }
+
+        match self.shadowed_builtins.get_mut(slot.id) {
+            Some(shadowed) if !*shadowed=>{
+                *shadowed = true;
+                BuiltinId::from_inner(slot.id)
+            },
+            _=>None,
+        }
+    }
+}
+
+/// Made by `VarState::save_globals`
+pub struct SavedGlobals {
+    globals: FxIndexSet<Ident>,
+    privates: FxIndexSet<Ident>,
+    constants: FxIndexSet<Ident>,
+    redefined: FxIndexSet<Ident>,
+}
+
+/// What a top level `def` of an existing global replaces
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Redefinition {
+    /// A prelude global the module hasn't defined yet. This is allowed without a warning.
+    Prelude,
+    /// One of the `DEFAULT_GLOBALS`
+    Builtin,
+    /// A global the program defined
+    Global,
 }
 
 pub struct VarScope {
     ins_id: InstructionId,
     start_slot: usize,
     vars: FxIndexSet<Ident>,
+    /// Whether each var was read, for the unused var warnings
+    read: Vec<bool>,
 }
 
 pub struct ConvertState {
@@ -484,6 +1188,20 @@ pub struct ConvertState {
     pub instructions: InstructionStore,
     pub modules: ModuleTree,
     pub vars: VarState,
+    pub constants: ConstPool,
+    pub search_path: SearchPath,
+    /// The canonical path of every module file that was loaded
+    pub module_cache: FxIndexMap<PathBuf, ModuleId>,
+    /// `use ... :only` forms to check once their modules are converted
+    pub pending_imports: Vec<PendingImport>,
+    /// The names of the vars each `Scope` instruction makes, in slot order. Only the debugger
+    /// needs these, so they aren't saved in bytecode.
+    pub scope_names: FxIndexMap<InstructionId, Vec<Ident>>,
+    /// Where each instruction came from. Empty unless this came from `convert_mapped` or a
+    /// bytecode file with one.
+    pub source_map: LazySourceMap,
+    /// The fns and modules reserved since the last `checkpoint`
+    pub(crate) reserved: Option<Reserved>,
 }
 #[allow(dead_code)]
 impl ConvertState {
@@ -498,23 +1216,129 @@ impl ConvertState {
             instructions: InstructionStore::new(),
             modules: ModuleTree::new(),
             vars,
+            constants: ConstPool::new(),
+            search_path: SearchPath::default(),
+            module_cache: FxIndexMap::default(),
+            pending_imports: Vec::new(),
+            scope_names: FxIndexMap::default(),
+            source_map: LazySourceMap::default(),
+            reserved: None,
         }
     }
 
     pub fn def_var(&mut self, name: &str)->Result<(Ident, VarSlot)> {
         let name = self.intern(name);
-        return Ok((name, self.vars.insert(name, &self.interner)?));
+        return Ok((name, self.def_var_ident(name)?));
     }
 
     pub fn def_var_ident(&mut self, name: Ident)->Result<VarSlot> {
-        return Ok(self.vars.insert(name, &self.interner)?);
+        self.check_shadowing(name);
+        let redefinition = self.vars.redefinition(name);
+        if redefinition.is_some() {
+            self.check_constant(self.vars.get(name).unwrap(), name)?;
+        }
+        let slot = self.vars.insert(name);
+
+        let builtin = match redefinition {
+            None|Some(Redefinition::Prelude)=>return Ok(slot),
+            Some(Redefinition::Global)=>false,
+            Some(Redefinition::Builtin)=>{
+                self.shadow_builtin(slot);
+                true
+            },
+        };
+        self.warning(LispError::Redefined {
+            name: self.interner.get(name).to_string(),
+            span: None,
+            builtin,
+        }.into());
+
+        return Ok(slot);
     }
 
-    pub fn lookup_var(&mut self, name: &str)->Option<VarSlot> {
-        let name = self.intern(name);
+    /// Error if `slot` is a `defconst` global
+    pub fn check_constant(&self, slot: VarSlot, name: Ident)->Result<()> {
+        if self.vars.is_constant(slot) {
+            bail!(LispError::Constant {
+                name: self.interner.get(name).to_string(),
+                span: None,
+            });
+        }
+
+        return Ok(());
+    }
+
+    /// Warn if a new local `name` hides another local or a builtin
+    fn check_shadowing(&mut self, name: Ident) {
+        if !self.vars.in_scope() {
+            return;
+        }
+
+        let builtin = match (self.vars.shadows_local(name), self.vars.is_default_global(name)) {
+            (true, _)=>false,
+            (false, true)=>true,
+            (false, false)=>return,
+        };
+        self.warning(LispError::ShadowedVar {
+            name: self.interner.get(name).to_string(),
+            span: None,
+            builtin,
+        }.into());
+    }
+
+    /// Warn about the vars of the innermost scope that were never read. Names starting with `_`
+    /// are left alone.
+    pub fn warn_unread(&mut self) {
+        for name in self.vars.unread() {
+            let name = self.interner.get(name);
+            if name.starts_with('_') {continue}
+
+            let err = LispError::UnusedVar {
+                name: name.to_string(),
+                span: None,
+            };
+            self.warning(err.into());
+        }
+    }
+
+    /// The globals the current module defined, which become the fields of its value
+    pub fn module_exports(&self)->Vec<Ident> {
+        self.vars.module_globals()
+            .filter(|name|!self.vars.is_private(*name))
+            .collect()
+    }
+
+    /// The `def-` globals the current module defined
+    pub fn module_privates(&self)->Vec<Ident> {
+        self.vars.module_globals()
+            .filter(|name|self.vars.is_private(*name))
+            .collect()
+    }
+
+    pub fn lookup_var(&self, name: &str)->Option<VarSlot> {
+        let name = self.interner.lookup(name)?;
         self.vars.get(name)
     }
 
+    /// Like `lookup_var`, but it counts as reading the var
+    pub fn read_var(&mut self, name: &str)->Option<VarSlot> {
+        let name = self.interner.lookup(name)?;
+        self.vars.read(name)
+    }
+
+    /// The error for using `name` when it isn't defined, with the visible vars it might be a typo
+    /// of.
+    pub fn undefined_var(&self, name: &str)->LispError {
+        let visible = self.vars.visible()
+            .map(|i|self.interner.get(i));
+
+        return LispError::UndefinedVar {
+            name: name.to_string(),
+            span: None,
+            suggestions: similar_names(name, visible),
+        };
+    }
+
     #[inline]
     pub fn intern(&mut self, s: &str)->Ident {
         self.interner.intern(s)
@@ -535,6 +1359,26 @@ impl ConvertState {
         self.instructions.push(Instruction::TailCall(arg_count));
     }
 
+    #[inline]
+    pub fn call_builtin(&mut self, id: BuiltinId, arg_count: usize) {
+        self.instructions.push(Instruction::CallBuiltin(id, arg_count));
+    }
+
+    /// Called when `set` reassigns a variable. If it is a builtin, then the calls to it that were
+    /// already converted have to look it up like any other function.
+    pub fn shadow_builtin(&mut self, slot: VarSlot) {
+        let Some(id) = self.vars.shadow_builtin(slot) else {return};
+
+        for i in 0..self.instructions.raw_instructions().len() {
+            let ins = self.instructions.get_mut(InstructionId::from_inner(i));
+            if let Instruction::CallBuiltin(call_id, arg_count) = ins {
+                if *call_id == id {
+                    *ins = Instruction::GetVarCall(slot, *arg_count);
+                }
+            }
+        }
+    }
+
     #[inline]
     pub fn push_return(&mut self) {
         self.instructions.push(Instruction::Return);
@@ -573,7 +1417,8 @@ impl ConvertState {
 
     #[inline]
     pub fn string(&mut self, s: String) {
-        self.instructions.push(Instruction::String(Rc::new(s)));
+        let s = self.constants.string(s);
+        self.instructions.push(Instruction::String(s));
     }
 
     #[inline]
@@ -596,6 +1441,11 @@ impl ConvertState {
         self.instructions.push(Instruction::Splat);
     }
 
+    #[inline]
+    pub fn unpack(&mut self, names: Vector) {
+        self.instructions.push(Instruction::Unpack(Rc::new(names)));
+    }
+
     #[inline]
     pub fn jump(&mut self, i: InstructionId) {
         self.instructions.push(Instruction::Jump(i));
@@ -633,7 +1483,12 @@ impl ConvertState {
 
     #[inline]
     pub fn reserve_func(&mut self)->FnId {
-        self.fns.reserve_slot()
+        let f = self.fns.reserve_slot();
+        if let Some(reserved) = &mut self.reserved {
+            reserved.fns.push(f);
+        }
+
+        return f;
     }
 
     /// Start a scope and insert a placeholder
@@ -644,17 +1499,95 @@ impl ConvertState {
 
     /// End a scope, update the start with the var count, and push the ending.
     pub fn end_scope(&mut self) {
-        let (id, count) = self.vars.pop_scope();
+        self.warn_unread();
+        let (id, names) = self.vars.pop_scope();
+        let count = names.len();
         *self.instructions.get_mut(id) = Instruction::Scope(count);
         self.instructions.push(Instruction::EndScope(count));
+        self.scope_names.insert(id, names);
     }
 
     pub fn reserve_module(&mut self)->ModuleId {
         let m = self.modules.reserve_slot();
+        if let Some(reserved) = &mut self.reserved {
+            reserved.modules.push(m);
+        }
 
         return m;
     }
 
+    /// Remember how much has been converted, so `rollback` can undo a conversion that fails
+    /// partway through
+    pub fn checkpoint(&mut self)->Checkpoint {
+        self.reserved = Some(Reserved::default());
+
+        Checkpoint {
+            instructions: self.instructions.instructions.len(),
+            interner: self.interner.len(),
+            module_cache: self.module_cache.len(),
+            pending_imports: self.pending_imports.len(),
+            globals: self.vars.save_globals(),
+        }
+    }
+
+    /// The function `id`. Errors instead of panicking if it was reserved and never converted.
+    pub fn get_fn(&self, id: FnId)->Result<&Rc<Fn>> {
+        match self.fns.get(id) {
+            Some(f)=>Ok(f),
+            None=>bail!(LispError::Unresolved{kind: "function", id: id.id()}),
+        }
+    }
+
+    /// The module `id`. Errors instead of panicking if it was reserved and never converted.
+    pub fn get_module(&self, id: ModuleId)->Result<&ModuleNode> {
+        match self.modules.try_get(id) {
+            Some(module)=>Ok(module),
+            None=>bail!(LispError::Unresolved{kind: "module", id: id.id()}),
+        }
+    }
+
+    /// Errors if a fn or module reserved since `checkpoint` was never filled in. The code
+    /// converted so far could run into it.
+    pub fn check_reserved(&self)->Result<()> {
+        let Some(reserved) = &self.reserved else {return Ok(())};
+        for id in reserved.fns.iter() {
+            self.get_fn(*id)?;
+        }
+        for id in reserved.modules.iter() {
+            self.get_module(*id)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Keep everything converted since `checkpoint`
+    pub fn commit(&mut self, _checkpoint: Checkpoint) {
+        self.reserved = None;
+    }
+
+    /// Undo everything converted since `checkpoint`: the instructions, the names interned, the
+    /// globals defined, and the fns and modules reserved. Nothing can run into half-converted
+    /// code after this.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        let len = checkpoint.instructions;
+        self.instructions.truncate(len);
+        self.scope_names.retain(|id, _|id.0 < len);
+        self.interner.truncate(checkpoint.interner);
+        self.module_cache.truncate(checkpoint.module_cache);
+        self.pending_imports.truncate(checkpoint.pending_imports);
+        self.vars.restore_globals(checkpoint.globals);
+        self.vars.reset_local();
+
+        if let Some(reserved) = self.reserved.take() {
+            for id in reserved.fns {
+                self.fns.remove(id);
+            }
+            for id in reserved.modules {
+                self.modules.remove(id);
+            }
+        }
+    }
+
     #[inline]
     pub fn module(&mut self, id: ModuleId) {
         self.instructions.push(Instruction::Module(id));
@@ -669,6 +1602,41 @@ impl ConvertState {
     pub fn cur_ins_id(&self)->InstructionId {
         self.instructions.current_id()
     }
+
+    /// Record every instruction pushed since there were `start` of them as coming from `location`,
+    /// if there is a source map
+    fn record_location(&mut self, start: usize, location: Option<Location>) {
+        let (Some(map), Some(location)) = (self.source_map.get_mut(), location) else {return};
+        let end = self.instructions.raw_instructions().len();
+        for raw in start..end {
+            map.locations.insert(InstructionId::from_inner(raw), location);
+        }
+    }
+
+    /// All the functions referenced by a `Func` instruction, sorted by id. The slot map can't list
+    /// its items, so this is how we find every converted function.
+    #[inline]
+    pub fn fn_ids(&self)->Vec<FnId> {
+        self.instructions.fn_ids()
+    }
+
+    /// The root module and all of its descendants, sorted by id
+    pub fn module_ids(&self)->Vec<ModuleId> {
+        // a module declared in more than one place shares its node, so it can be reached twice
+        let mut ids = FxIndexSet::default();
+        ids.insert(ModuleId::root());
+        let mut i = 0;
+        while i < ids.len() {
+            let id = ids[i];
+            ids.extend(self.modules.get(id).children.iter().copied());
+            i += 1;
+        }
+
+        let mut ids = ids.into_iter().collect::<Vec<_>>();
+        ids.sort_by_key(|id|id.id());
+
+        return ids;
+    }
 }
 
 #[derive(Debug)]
@@ -679,18 +1647,30 @@ pub struct ModuleNode {
     pub parent: Option<ModuleId>,
 
     pub start_ins: InstructionId,
+    /// The globals it defines, which are the fields of its value
+    pub exports: Vec<Ident>,
+    /// The `def-` globals. Only the module itself can use them.
+    pub privates: Vec<Ident>,
 }
 
 pub struct ModuleTree {
     tree: SlotMap<ModuleId, ModuleNode>,
+    /// Modules that were declared again somewhere else. They share the first one's node.
+    aliases: FxIndexMap<ModuleId, ModuleId>,
 }
 impl ModuleTree {
     pub fn new()->Self {
         ModuleTree {
             tree: SlotMap::new(),
+            aliases: Default::default(),
         }
     }
 
+    /// Make `id` share `to`'s node
+    pub fn alias(&mut self, id: ModuleId, to: ModuleId) {
+        self.aliases.insert(id, to);
+    }
+
     #[inline]
     pub fn reserve_slot(&mut self)->ModuleId {
         self.tree.reserve_slot()
@@ -702,19 +1682,75 @@ impl ModuleTree {
     }
 
     pub fn get(&self, id: ModuleId)->&ModuleNode {
-        self.tree.get(id).unwrap()
+        self.try_get(id).unwrap()
+    }
+
+    /// `None` if `id` was reserved and never filled in
+    pub fn try_get(&self, id: ModuleId)->Option<&ModuleNode> {
+        let id = self.aliases.get(&id).copied().unwrap_or(id);
+        self.tree.get(id)
+    }
+
+    /// Free `id` and forget any alias to or from it
+    pub fn remove(&mut self, id: ModuleId) {
+        self.tree.remove(id);
+        self.aliases.retain(|from, to|*from != id && *to != id);
     }
 }
 
+/// How far conversion had gotten when `ConvertState::checkpoint` was called
+pub struct Checkpoint {
+    instructions: usize,
+    interner: usize,
+    module_cache: usize,
+    pending_imports: usize,
+    /// The globals of the code being converted
+    globals: SavedGlobals,
+}
+
+/// The fns and modules reserved since a checkpoint, to free if it is rolled back
+#[derive(Default)]
+pub(crate) struct Reserved {
+    fns: Vec<FnId>,
+    modules: Vec<ModuleId>,
+}
+
+/// The names a `use` form takes from a module
+pub struct PendingImport {
+    pub module: ModuleId,
+    pub names: Vec<Ident>,
+    /// How the root got to the module with the `use`
+    pub imports: Vec<Import>,
+    pub file: Option<PathBuf>,
+    /// From a path like `m/x` instead of `:only`. These only have to not be private, since `set`
+    /// can add fields to a module's value.
+    pub path: bool,
+}
+
 struct TodoModule {
     name: String,
     id: ModuleId,
     parent: ModuleId,
     path: PathBuf,
+    /// How the root got to this module, ending with this one
+    imports: Vec<Import>,
+}
+impl TodoModule {
+    /// Wrap an error from loading this module so `error_trace` can show it with the module's
+    /// source
+    fn error(&self, path: Option<PathBuf>, source: String, error: Error)->LispError {
+        LispError::Module {
+            imports: self.imports.clone(),
+            path,
+            source,
+            error,
+        }
+    }
 }
 
 struct Todos<'a, 'b> {
-    pub fns: VecDeque<(FnId, RefFn<'a>)>,
+    /// Each with the location of the form that made it
+    pub fns: VecDeque<(FnId, RefFn<'a>, Option<Location>)>,
     pub modules: &'b mut VecDeque<TodoModule>,
 
     /// Helper to temporarily store the children of the current module
@@ -723,6 +1759,14 @@ struct Todos<'a, 'b> {
     pub current_module: ModuleId,
 
     pub module_path: PathBuf,
+    /// The file being converted. `None` in the REPL.
+    pub file: Option<PathBuf>,
+    /// How the root got to the current module
+    pub imports: Vec<Import>,
+    /// Globals bound to a module by `use`, so paths through them can be checked
+    pub module_vars: FxIndexMap<Ident, ModuleId>,
+    /// The top level form being converted, if there is a source map
+    pub location: Option<Location>,
 }
 impl<'a, 'b> Todos<'a, 'b> {
     fn new(modules: &'b mut VecDeque<TodoModule>)->Self {
@@ -732,11 +1776,15 @@ impl<'a, 'b> Todos<'a, 'b> {
             new_modules: Vec::new(),
             current_module: ModuleId::root(),
             module_path: PathBuf::new(),
+            file: None,
+            imports: Vec::new(),
+            module_vars: FxIndexMap::default(),
+            location: None,
         }
     }
 
     fn queue_fn(&mut self, id: FnId, f: RefFn<'a>) {
-        self.fns.push_back((id, f));
+        self.fns.push_back((id, f, self.location));
     }
 
     fn queue_module(&mut self, id: ModuleId, name: &str) {
@@ -747,27 +1795,60 @@ impl<'a, 'b> Todos<'a, 'b> {
             id,
             parent: self.current_module,
             path: self.module_path.clone(),
+            imports: self.imports.iter()
+                .cloned()
+                .chain([Import {
+                    name: name.to_string(),
+                    importer: self.file.clone(),
+                }])
+                .collect(),
         });
     }
 }
 
 
-pub fn convert<'a>(exprs: Vec<RefExpr<'a>>)->Result<ConvertState> {
+/// Convert the root module. `file` is where `exprs` came from, and its modules are looked for next
+/// to it and then in `search_path`. With `prelude` the root starts with the prelude, so it runs
+/// first.
+pub fn convert<'a>(exprs: Vec<RefExpr<'a>>, file: &Path, search_path: SearchPath, prelude: bool)->Result<ConvertState> {
+    convert_root(ConvertState::new(), exprs, None, file, search_path, prelude)
+}
+
+/// Like `convert`, but also make a source map. `source` is what `exprs` were parsed from.
+pub fn convert_mapped<'a>(exprs: Vec<RefExpr<'a>>, source: &str, file: &Path, search_path: SearchPath, prelude: bool)->Result<ConvertState> {
     let mut state = ConvertState::new();
+    state.source_map = LazySourceMap::new(SourceMap::default());
+
+    return convert_root(state, exprs, Some(source), file, search_path, prelude);
+}
+
+fn convert_root<'a>(mut state: ConvertState, exprs: Vec<RefExpr<'a>>, source: Option<&str>, file: &Path, search_path: SearchPath, prelude: bool)->Result<ConvertState> {
+    state.search_path = search_path;
+    let checkpoint = state.checkpoint();
     let mut module_todos = VecDeque::new();
     let mut todos = Todos::new(&mut module_todos);
     let root_module = state.reserve_module();
     todos.current_module = root_module;
+    todos.file = Some(file.to_path_buf());
+    todos.module_path = file.parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
 
     let start_ins = state.next_ins_id();
-    convert_exprs(&mut state, &mut todos, exprs.into_iter(), false)?;
+    if prelude {
+        // it ships with the binary, so any error is our bug
+        if let Err(e) = convert_exprs(&mut state, &mut todos, crate::prelude::exprs().into_iter(), false) {
+            panic!("The prelude failed to convert: {e}");
+        }
+        state.vars.seal_prelude();
+    }
+    convert_top_level(&mut state, &mut todos, exprs, source)?;
+    let exports = state.module_exports();
+    let privates = state.module_privates();
 
     state.push_exit();
 
-    while let Some((id, f)) = todos.fns.pop_back() {
-        state.vars.reset_local();
-        convert_fn(&mut state, &mut todos, f, id)?;
-    }
+    convert_queued_fns(&mut state, &mut todos)?;
 
     let root_children = todos.new_modules;
     let name = state.intern("root");
@@ -776,81 +1857,121 @@ pub fn convert<'a>(exprs: Vec<RefExpr<'a>>)->Result<ConvertState> {
         children: root_children,
         parent: None,
         start_ins,
+        exports,
+        privates,
     }).unwrap();
 
     while let Some(todo) = module_todos.pop_back() {
         state.vars.reset();
         convert_module(&mut state, &mut module_todos, todo)?;
     }
+    check_imports(&mut state)?;
+    state.check_reserved()?;
+    state.commit(checkpoint);
 
     return Ok(state);
 }
 
-// pub fn repl_convert<'a>(state: &mut ConvertState, exprs: Vec<RefExpr<'a>>)->Result<InstructionId> {
-//     let start_id = state.next_ins_id();
-//     let mut module_todos = VecDeque::new();
-//     let mut todos = Todos::new(&mut module_todos);
-//     convert_exprs(state, &mut todos, exprs, false)?;
+/// Convert more code into an existing state, like a REPL does with each line. Globals from
+/// earlier calls are still defined, and defining one again replaces it with a warning. Returns
+/// where to start running the new code. If it fails, everything it converted is undone.
+pub fn repl_convert<'a>(state: &mut ConvertState, exprs: Vec<RefExpr<'a>>)->Result<InstructionId> {
+    // the last function converted can leave scopes behind
+    state.vars.reset_local();
+
+    let checkpoint = state.checkpoint();
+    match repl_convert_inner(state, exprs) {
+        Ok(start_id)=>{
+            state.commit(checkpoint);
+            return Ok(start_id);
+        },
+        Err(e)=>{
+            state.rollback(checkpoint);
+            return Err(e);
+        },
+    }
+}
 
-//     state.push_exit();
+fn repl_convert_inner<'a>(state: &mut ConvertState, exprs: Vec<RefExpr<'a>>)->Result<InstructionId> {
 
-//     while let Some((id, f)) = todos.fns.pop_front() {
-//         state.vars.reset_local();
-//         convert_fn(state, &mut todos, f, id)?;
-//     }
+    let start_id = state.next_ins_id();
+    let mut module_todos = VecDeque::new();
+    let mut todos = Todos::new(&mut module_todos);
+    convert_exprs(state, &mut todos, exprs.into_iter(), false)?;
 
-//     while let Some(todo) = module_todos.pop_back() {
-//         state.vars.reset();
-//         convert_module(state, &mut module_todos, todo)?;
-//     }
+    state.push_exit();
 
-//     return Ok(start_id);
-// }
+    convert_queued_fns(state, &mut todos)?;
+
+    // modules get their own globals, so put ours back after
+    let globals = state.vars.save_globals();
+    while let Some(todo) = module_todos.pop_back() {
+        state.vars.reset();
+        convert_module(state, &mut module_todos, todo)?;
+    }
+    state.vars.restore_globals(globals);
+    check_imports(state)?;
+    state.check_reserved()?;
+
+    return Ok(start_id);
+}
 
 fn convert_module<'a>(state: &mut ConvertState, module_todos: &'a mut VecDeque<TodoModule>, module_todo: TodoModule)->Result<()> {
     let mut todos = Todos::new(module_todos);
 
     let name = state.intern(&module_todo.name);
 
-    let mut path = module_todo.path;
-    path.push(&module_todo.name);
+    let found = match find_module(&module_todo.path, &module_todo.name, &state.search_path) {
+        Ok(f)=>f,
+        Err(e)=>bail!(module_todo.error(None, String::new(), e.into())),
+    };
 
-    todos.module_path = path.clone();
-    todos.current_module = module_todo.id;
+    // reuse the module if something else already loaded the same file. If the file can't be
+    // canonicalized then reading it fails below anyway.
+    let canonical = found.path.canonicalize().unwrap_or_else(|_|found.path.clone());
+    if let Some(existing) = state.module_cache.get(&canonical) {
+        state.modules.alias(module_todo.id, *existing);
+        return Ok(());
+    }
+    state.module_cache.insert(canonical, module_todo.id);
+    if found.shadowed.len() > 0 {
+        state.warning(LispError::AmbiguousModule {
+            name: module_todo.name.clone(),
+            used: found.path.clone(),
+            ignored: found.shadowed,
+        }.into());
+    }
+    let path = found.path;
+    let source = match read_source(&path) {
+        Ok(s)=>s,
+        Err(e)=>bail!(module_todo.error(Some(path), String::new(), e.into())),
+    };
 
-    let source;
-    if path.is_dir() {
-        path.push("mod.slp");
-        source = read_to_string(&path)?;
-    } else {
-        path.set_extension("slp");
-        source = read_to_string(&path)?;
-    }
+    todos.module_path = found.dir;
+    todos.current_module = module_todo.id;
+    todos.file = Some(path.clone());
+    todos.imports = module_todo.imports.clone();
 
     let mut parser = crate::parser::new_parser(&source);
     let exprs = match parser.parse_all() {
         Ok(e)=>e,
         Err(e)=>{
-            error_trace(e, &source, path.display());
-            bail!(ModuleError);
+            bail!(module_todo.error(Some(path), source.clone(), e));
         },
     };
     drop(parser);
 
     let start_ins = state.next_ins_id();
-    if let Err(e) = convert_exprs(state, &mut todos, exprs.into_iter(), NOT_TAIL) {
-        error_trace(e, &source, path.display());
-        bail!(ModuleError);
+    if let Err(e) = convert_top_level(state, &mut todos, exprs, Some(&source)) {
+        bail!(module_todo.error(Some(path), source.clone(), e));
     }
+    let exports = state.module_exports();
+    let privates = state.module_privates();
 
     state.push_module_return();
 
-    while let Some((id, f)) = todos.fns.pop_back() {
-        state.vars.reset_local();
-        if let Err(e) = convert_fn(state, &mut todos, f, id) {
-            error_trace(e, &source, path.display());
-            bail!(ModuleError);
-        }
+    if let Err(e) = convert_queued_fns(state, &mut todos) {
+        bail!(module_todo.error(Some(path), source.clone(), e));
     }
 
     let children = todos.new_modules;
@@ -860,11 +1981,110 @@ fn convert_module<'a>(state: &mut ConvertState, module_todos: &'a mut VecDeque<T
         parent: Some(module_todo.parent),
         start_ins,
         children,
+        exports,
+        privates,
     }).expect("Module already exists!");
 
     return Ok(());
 }
 
+/// The name a `use` form binds the module itself to. Without `:as` or `:only` it is the module's
+/// own name.
+fn use_alias<'a>(path: &[&'a str], only: &Option<Vec<&'a str>>, alias: Option<&'a str>)->Option<&'a str> {
+    match alias {
+        Some(alias)=>Some(alias),
+        None if only.is_none()=>path.last().copied(),
+        None=>None,
+    }
+}
+
+/// Make sure every `use ... :only` names something its module defines
+fn check_imports(state: &mut ConvertState)->Result<()> {
+    for import in std::mem::take(&mut state.pending_imports) {
+        let module = state.modules.get(import.module);
+        let module_name = state.interner.get(module.name).to_string();
+        let missing = import.names.iter()
+            .copied()
+            .filter(|n|!module.exports.contains(n))
+            .collect::<Vec<_>>();
+
+        let error = match missing.iter().find(|n|module.privates.contains(n)) {
+            Some(name)=>LispError::PrivateField {
+                name: state.interner.get(*name).to_string(),
+                module: module_name,
+            },
+            None if missing.is_empty() || import.path=>continue,
+            None=>LispError::UndefinedImport {
+                module: module_name,
+                missing: missing.iter()
+                    .map(|n|state.interner.get(*n).to_string())
+                    .collect(),
+                exports: module.exports.iter()
+                    .map(|n|state.interner.get(*n).to_string())
+                    .collect(),
+            },
+        };
+        if import.imports.is_empty() {
+            bail!(error);
+        }
+
+        // the `use` is in a module, so show how we got there
+        bail!(LispError::Module {
+            imports: import.imports,
+            path: import.file,
+            source: String::new(),
+            error: error.into(),
+        });
+    }
+
+    return Ok(());
+}
+
+/// Convert the top level of `todos.file`. If there is a source map, each expr's instructions are
+/// recorded as coming from its form in `source`.
+fn convert_top_level<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, exprs: Vec<RefExpr<'a>>, source: Option<&str>)->Result<()> {
+    let mapped = match (source, &todos.file, state.source_map.get_mut()) {
+        (Some(source), Some(file), Some(map))=>{
+            let spans = top_level_spans(source);
+            // the spans come from the lexer, so they could only disagree with the parser if one
+            // of them had a bug. Guessing would point errors at the wrong form.
+            (spans.len() == exprs.len()).then(||(source, map.add_file(file, source), spans))
+        },
+        _=>None,
+    };
+    let Some((source, file, spans)) = mapped else {
+        return convert_exprs(state, todos, exprs.into_iter(), NOT_TAIL);
+    };
+
+    for (expr, span) in exprs.into_iter().zip(spans) {
+        let (line, column) = line_column(source, span.start);
+        todos.location = Some(Location {file, line, column});
+
+        let start = state.instructions.raw_instructions().len();
+        convert_single_expr(state, todos, expr, NOT_TAIL)?;
+        state.record_location(start, todos.location);
+    }
+    todos.location = None;
+
+    return Ok(());
+}
+
+/// Convert the fns queued so far and any they queue. Each one's instructions come from the form
+/// that made it.
+fn convert_queued_fns<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>)->Result<()> {
+    while let Some((id, f, location)) = todos.fns.pop_back() {
+        state.vars.reset_local();
+        todos.location = location;
+
+        let start = state.instructions.raw_instructions().len();
+        convert_fn(state, todos, f, id)?;
+        state.record_location(start, location);
+    }
+    todos.location = None;
+
+    return Ok(());
+}
+
 fn convert_exprs<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, exprs: impl ExactSizeIterator<Item = RefExpr<'a>>, is_tail: bool)->Result<()> {
     if exprs.len() == 0 {return Ok(())}
 
@@ -886,55 +2106,148 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
         RefExpr::String(s)=>state.string(s),
         RefExpr::Char(c)=>state.char(c),
         RefExpr::Ident(i)=>{
-            let slot = state.lookup_var(i)
-                .ok_or(anyhow!("Var {} does not exist", i))?;
+            let slot = state.read_var(i)
+                .ok_or_else(||state.undefined_var(i))?;
             state.get_var(slot)
         },
         RefExpr::DotIdent(i)=>state.dot_ident(i),
         RefExpr::Comment(_)=>{},
         RefExpr::Module(name)=>{
             let id = state.reserve_module();
-            state.module(id);
             todos.queue_module(id, name);
+
+            // bound to its name like `use` does, and the expression is still the module
+            state.module(id);
+            let (ident, slot) = state.def_var(name)?;
+            state.set_var(slot);
+            if slot.global {
+                todos.module_vars.insert(ident, id);
+            }
+            state.read_var(name);
+            state.get_var(slot);
         },
-        RefExpr::Def{name, data}=>{
-            convert_single_expr(state, todos, *data, is_tail)?;
+        RefExpr::Use{path, only, alias}=>{
+            let id = state.reserve_module();
+            todos.queue_module(id, &path.join("/"));
+
+            // each binding reads the module again, but it only runs the first time
+            if let Some(names) = &only {
+                let mut idents = Vec::with_capacity(names.len());
+                for name in names {
+                    state.module(id);
+                    let (ident, slot) = state.def_var(name)?;
+                    state.field(ident);
+                    state.set_var(slot);
+                    idents.push(ident);
+                }
+                state.pending_imports.push(PendingImport {
+                    module: id,
+                    names: idents,
+                    imports: todos.imports.clone(),
+                    file: todos.file.clone(),
+                    path: false,
+                });
+            }
+            if let Some(name) = use_alias(&path, &only, alias) {
+                state.module(id);
+                let (ident, slot) = state.def_var(name)?;
+                state.set_var(slot);
+                if slot.global {
+                    todos.module_vars.insert(ident, id);
+                }
+            }
+        },
+        RefExpr::Def{name, data, private, constant}=>{
+            if constant && state.vars.in_scope() {
+                bail!("`defconst` can only be used at the top level");
+            }
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
 
-            let (_, slot) = state.def_var(name)?;
+            let (ident, slot) = state.def_var(name)?;
             state.set_var(slot);
+            if private && slot.global {
+                state.vars.make_private(ident);
+            }
+            if constant {
+                state.vars.make_constant(ident);
+            }
+        },
+        RefExpr::DefVector{names, data, private, constant}=>{
+            if constant && state.vars.in_scope() {
+                bail!("`defconst` can only be used at the top level");
+            }
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
+
+            let names = convert_vector(state, names);
+            state.unpack(names.clone());
+            for name in names.items.into_iter().chain(names.remainder) {
+                let slot = state.def_var_ident(name)?;
+                state.set_var(slot);
+                if private && slot.global {
+                    state.vars.make_private(name);
+                }
+                if constant {
+                    state.vars.make_constant(name);
+                }
+            }
         },
         RefExpr::Set{name, data}=>{
-            convert_single_expr(state, todos, *data, is_tail)?;
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
 
             let slot = state.lookup_var(name)
-                .ok_or(anyhow!("Var {} does not exist", name))?;
+                .ok_or_else(||state.undefined_var(name))?;
+            state.check_constant(slot, state.interner.lookup(name).unwrap())?;
+            // it might not be the module anymore
+            if let Some(ident) = state.interner.lookup(name) {
+                todos.module_vars.shift_remove(&ident);
+            }
+            state.shadow_builtin(slot);
             state.set_var(slot);
         },
         RefExpr::SetPath{path, data}=>{
-            convert_single_expr(state, todos, *data, is_tail)?;
+            convert_single_expr(state, todos, *data, NOT_TAIL)?;
 
             let mut path_iter = path.into_iter();
             let name = path_iter.next().unwrap();
-            let slot = state.lookup_var(name)
-                .ok_or(anyhow!("Var {} does not exist", name))?;
+            let slot = state.read_var(name)
+                .ok_or_else(||state.undefined_var(name))?;
+            state.check_constant(slot, state.interner.lookup(name).unwrap())?;
 
             let path = path_iter.map(|n|state.intern(n)).collect::<Vec<_>>();
             state.set_path(slot, path);
         },
-        RefExpr::Object(_)=>panic!("Not supported in the new interpreter!"),
+        RefExpr::Object(_)=>bail!("`object` isn't supported by the V2 interpreter yet"),
         RefExpr::Path(path)=>{
             let mut path_iter = path.into_iter();
             let var = path_iter.next().unwrap();
-            let slot = state.lookup_var(var)
-                .ok_or(anyhow!("Var {} does not exist", var))?;
+            let slot = state.read_var(var)
+                .ok_or_else(||state.undefined_var(var))?;
             state.get_var(slot);
 
+            let module = state.interner.lookup(var)
+                .filter(|_|slot.global)
+                .and_then(|ident|todos.module_vars.get(&ident).copied());
+            if let (Some(module), Some(field)) = (module, path_iter.clone().next()) {
+                let field = state.intern(field);
+                state.pending_imports.push(PendingImport {
+                    module,
+                    names: vec![field],
+                    imports: todos.imports.clone(),
+                    file: todos.file.clone(),
+                    path: true,
+                });
+            }
+
             for name in path_iter {
                 let i = state.intern(name);
                 state.field(i);
             }
         },
         RefExpr::Fn(f)=>{
+            if f.generator {
+                bail!("`fn-gen` isn't supported by the V2 interpreter yet");
+            }
+
             let id = state.reserve_func();
             todos.queue_fn(id, f);
 
@@ -976,11 +2289,13 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
                 state.instructions.set(id, Instruction::JumpIfFalse(this_id));
             }
 
-            if let Some(default) = default {
-                convert_single_expr(state, todos, *default, is_tail)?;
-                if is_tail {
-                    state.push_return();
-                }
+            // when nothing matches, the cond is `none`
+            match default {
+                Some(default)=>convert_single_expr(state, todos, *default, is_tail)?,
+                None=>state.push_none(),
+            }
+            if is_tail {
+                state.push_return();
             }
 
             if !is_tail {
@@ -994,6 +2309,10 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
             } else {
                 assert!(jump_ends.is_empty());
             }
+
+            // every branch returns in tail position, so this only ends the scope for the
+            // converter there
+            state.end_scope();
         },
         RefExpr::Splat(expr)=>{
             convert_single_expr(state, todos, *expr, NOT_TAIL)?;
@@ -1006,6 +2325,8 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
 
             state.end_scope();
         },
+        // there is nothing to call, so it is `none` like in other lisps
+        RefExpr::List(exprs) if exprs.is_empty()=>state.push_none(),
         RefExpr::List(exprs)=>{
             let arg_count = exprs.len() - 1;
             state.start_scope();
@@ -1013,7 +2334,17 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
 
             let first = exprs_iter.next().unwrap();
 
-            convert_exprs(state, todos, exprs_iter.rev(), is_tail)?;
+            // only the call itself is in tail position, not its first argument
+            convert_exprs(state, todos, exprs_iter.rev(), NOT_TAIL)?;
+
+            if let RefExpr::Ident(name) = &first {
+                let name = state.intern(name);
+                if let Some(id) = state.vars.builtin(name) {
+                    state.end_scope();
+                    state.call_builtin(id, arg_count);
+                    return Ok(());
+                }
+            }
 
             convert_single_expr(state, todos, first, NOT_TAIL)?;
 
@@ -1026,9 +2357,15 @@ fn convert_single_expr<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, '
             }
         },
         RefExpr::None=>state.push_none(),
-        RefExpr::Quote(_)=>todo!("Quote conversion"),
-        RefExpr::Vector(_)=>todo!("Vector conversion"),
-        RefExpr::Squiggle(_)=>todo!("Squiggle conversion"),
+        RefExpr::Breakpoint=>{
+            state.warning(anyhow!("`(breakpoint)` does nothing in the V2 interpreter. Use `--debugger` instead."));
+            state.push_none();
+        },
+        RefExpr::Yield(_)=>bail!("`yield` isn't supported by the V2 interpreter yet"),
+        RefExpr::Test(_)=>bail!("`deftest` isn't supported by the V2 interpreter yet"),
+        RefExpr::Quote(_)=>bail!("Quoting isn't supported yet"),
+        // these only come from quoted lists
+        RefExpr::Vector(_)|RefExpr::Squiggle(_)=>bail!("Quoting isn't supported yet"),
         RefExpr::ReplDirective(_)=>bail!("Repl directives are not allowed here!"),
     })
 }
@@ -1045,7 +2382,7 @@ fn convert_fn<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, func:
 
     // TODO: Actually implement this thing
     if captures.len() > 0 {
-        todo!("Function captures");
+        bail!("Function captures aren't supported by the V2 interpreter yet");
     }
 
     let sig = convert_signature(state, todos, func.signature, &captures)?;
@@ -1060,6 +2397,11 @@ fn convert_fn<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, func:
 }
 
 fn def_func_cap_params(state: &mut ConvertState, caps: &[Ident], params: &Vector)->Result<()> {
+    // they are locals of the body, not globals. `reset_local` drops the scope before the next
+    // function.
+    let body_ptr = state.next_ins_id();
+    state.vars.push_scope(body_ptr);
+
     for cap in caps {
         state.def_var_ident(*cap)?;
     }
@@ -1074,6 +2416,10 @@ fn def_func_cap_params(state: &mut ConvertState, caps: &[Ident], params: &Vector
 }
 
 fn convert_signature<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>, sig: RefFnSignature<'a>, captures: &[Ident])->Result<FnSignature> {
+    for warning in sig.check_arities()? {
+        state.warning(warning.into());
+    }
+
     match sig {
         RefFnSignature::Single(params, body)=>{
             let params = convert_vector(state, params);
@@ -1083,6 +2429,7 @@ fn convert_signature<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>
             let body_ptr = state.next_ins_id();
             convert_exprs(state, todos, body.into_iter(), IS_TAIL)?;
             state.push_return();
+            state.warn_unread();
 
             return Ok(FnSignature::Single{params, body_ptr});
         },
@@ -1102,6 +2449,8 @@ fn convert_signature<'a, 'b>(state: &mut ConvertState, todos: &mut Todos<'a, 'b>
                 let body_ptr = state.next_ins_id();
                 convert_exprs(state, todos, body.into_iter(), IS_TAIL)?;
                 state.push_return();
+                // each body has its own scope, so a param is only unused if its body doesn't read it
+                state.warn_unread();
 
                 if params.remainder.is_some() {
                     if params.items.len() == 0 {
@@ -0,0 +1,972 @@
+//! Reading and writing compiled bytecode files (`.slpc`). A file is a header followed by a payload
+//! with the sections of a `ConvertState`: the interned strings, the string constants, the instructions and
+//! their execution order, the globals and whether each is a `defconst`, the functions, the
+//! module tree, and the source map if there is one. String instructions refer to the constants by
+//! index, so each literal is only written once.
+//!
+//! The source map is last and prefixed with its length, so loading a file only copies it. See
+//! `source_map` for when it gets decoded.
+//!
+//! The instructions are compacted before they are written, so anything the optimizer took out of
+//! the execution order is left out of the file and the ids are renumbered in execution order.
+//! Functions and modules are renumbered from 0 too, so each id is below its section's count.
+//!
+//! The header is the magic, `FORMAT_VERSION`, a byte order mark, the version of `simple_lisp` that
+//! wrote the file, and the payload's length and checksum. A file from another format or
+//! interpreter version is rejected with a note to recompile it, and a truncated or corrupted one
+//! is rejected before any of it is read.
+//!
+//! The checksum only catches accidents, so a loaded file is also checked to only refer to strings,
+//! globals, functions, modules and instructions it has, and to pass the verifier. A crafted file
+//! is rejected instead of indexing out of bounds while it runs.
+//!
+//! Everything is written little-endian, and all `usize` values are written as `u64`. Bump
+//! `FORMAT_VERSION` any time the layout changes; old files are rejected instead of misread.
+
+
+use anyhow::{
+    Result,
+    anyhow,
+    bail,
+};
+use misc_utils::{
+    SlotMap,
+    Key,
+};
+use fnv::FnvHasher;
+use std::{
+    hash::Hasher,
+    rc::Rc,
+};
+use super::{
+    ast::*,
+    verify::verify,
+    source_map::{
+        LazySourceMap,
+        Location,
+        SourceFile,
+        SourceMap,
+    },
+    FxIndexMap,
+    FxIndexSet,
+};
+
+
+pub const MAGIC: &[u8; 4] = b"SLPC";
+pub const FORMAT_VERSION: u16 = 9;
+/// The version of `simple_lisp` that writes and reads files. Instructions can mean something else
+/// in another version even when the layout didn't change.
+pub const INTERPRETER_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Written after the version so we can tell a byte-swapped file from a corrupt one
+const BYTE_ORDER_MARK: u32 = 0x0A0B0C0D;
+
+
+/// What a bytecode file says about itself before the payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub format_version: u16,
+    /// The `simple_lisp` version that wrote the file
+    pub interpreter_version: String,
+    /// How many bytes come after the header
+    pub payload_len: u64,
+    /// `checksum` of the payload
+    pub checksum: u64,
+}
+
+
+/// Returns true if `bytes` starts with the bytecode magic
+pub fn is_bytecode(bytes: &[u8])->bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// FNV-1a of `payload`. It only catches accidents like a flipped bit or a partial copy.
+pub fn checksum(payload: &[u8])->u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(payload);
+    return hasher.finish();
+}
+
+pub fn serialize(state: &ConvertState)->Vec<u8> {
+    let payload = serialize_payload(state);
+
+    let mut w = Writer(Vec::new());
+    w.bytes(MAGIC);
+    w.u16(FORMAT_VERSION);
+    w.u32(BYTE_ORDER_MARK);
+    w.str(INTERPRETER_VERSION);
+    w.usize(payload.len());
+    w.u64(checksum(&payload));
+    w.bytes(&payload);
+
+    return w.0;
+}
+
+fn serialize_payload(state: &ConvertState)->Vec<u8> {
+    let mut w = Writer(Vec::new());
+
+    let strings = state.interner.iter().map(|(_, s)|s).collect::<Vec<_>>();
+    w.usize(strings.len());
+    for s in strings {
+        w.str(s);
+    }
+
+    let constants = state.constants.strings().collect::<Vec<_>>();
+    w.usize(constants.len());
+    for s in constants {
+        w.str(s);
+    }
+
+    let mut store = state.instructions.clone();
+    let remap = store.compact();
+    // functions that are only made by dead code went with it
+    let fn_ids = store.fn_ids();
+    let module_ids = state.module_ids();
+    renumber(&mut store, &fn_ids, &module_ids);
+
+    let instructions = store.raw_instructions();
+    w.usize(instructions.len());
+    for ins in instructions {
+        w.instruction(ins, &state.constants);
+    }
+
+    let order = store.ins_order();
+    w.usize(order.len());
+    for id in order {
+        w.ins_id(*id);
+    }
+
+    let globals = state.vars.globals().collect::<Vec<_>>();
+    w.usize(globals.len());
+    for (id, global) in globals.into_iter().enumerate() {
+        w.ident(global);
+        w.u8(state.vars.is_constant(VarSlot {id, global: true}) as u8);
+    }
+
+    w.usize(fn_ids.len());
+    for (index, id) in fn_ids.into_iter().enumerate() {
+        let f = state.fns.get(id).expect("Function was never converted");
+        w.usize(index);
+        w.opt_ident(f.name);
+        w.usize(f.captures.len());
+        for capture in f.captures.iter() {
+            w.ident(*capture);
+        }
+        w.signature(&f.sig.map_bodies(|id|remap.expect(id)));
+    }
+
+    w.usize(module_ids.len());
+    for (index, id) in module_ids.iter().enumerate() {
+        let module = state.modules.get(*id);
+        w.usize(index);
+        w.ident(module.name);
+        w.usize(module.children.len());
+        for child in module.children.iter() {
+            w.usize(module_index(&module_ids, *child));
+        }
+        match module.parent {
+            Some(parent)=>{
+                w.u8(1);
+                w.usize(module_index(&module_ids, parent));
+            },
+            None=>w.u8(0),
+        }
+        w.ins_id(remap.expect(module.start_ins));
+    }
+
+    match state.source_map.get() {
+        Some(map)=>{
+            let section = serialize_source_map(map, &remap);
+            w.u8(1);
+            w.usize(section.len());
+            w.bytes(&section);
+        },
+        None=>w.u8(0),
+    }
+
+    return w.0;
+}
+
+/// Number the functions and modules that are written from 0 up, so a loaded file can't name one
+/// past its count. Dead code and rolled back REPL input leave gaps in the original ids.
+fn renumber(store: &mut InstructionStore, fn_ids: &[FnId], module_ids: &[ModuleId]) {
+    for i in 0..store.raw_instructions().len() {
+        match store.get_mut(InstructionId::from_inner(i)) {
+            Instruction::Func(id)=>{
+                let index = fn_ids.binary_search_by_key(&id.id(), |f|f.id())
+                    .expect("Function is missing from the function list");
+                *id = FnId::from_id(index);
+            },
+            Instruction::Module(id)=>*id = ModuleId::from_id(module_index(module_ids, *id)),
+            _=>{},
+        }
+    }
+}
+
+/// Where `id` is in `module_ids`, which is sorted by id
+fn module_index(module_ids: &[ModuleId], id: ModuleId)->usize {
+    module_ids.binary_search_by_key(&id.id(), |m|m.id())
+        .expect("Module is not reachable from the root")
+}
+
+/// Instructions that were compacted away are left out
+fn serialize_source_map(map: &SourceMap, remap: &InstructionRemap)->Vec<u8> {
+    let mut w = Writer(Vec::new());
+
+    w.usize(map.files.len());
+    for file in map.files.iter() {
+        w.str(&file.path.to_string_lossy());
+        match &file.contents {
+            Some(contents)=>{
+                w.u8(1);
+                w.str(contents);
+            },
+            None=>w.u8(0),
+        }
+    }
+
+    let locations = map.locations.iter()
+        .filter_map(|(id, location)|Some((remap.get(*id)?, location)))
+        .collect::<Vec<_>>();
+    w.usize(locations.len());
+    for (id, location) in locations {
+        w.ins_id(id);
+        w.usize(location.file);
+        w.usize(location.line);
+        w.usize(location.column);
+    }
+
+    return w.0;
+}
+
+/// Decode the source map section of a bytecode file. `LazySourceMap` calls this the first time
+/// the map is needed.
+pub fn read_source_map(bytes: &[u8])->Result<SourceMap> {
+    let mut r = Reader {
+        bytes,
+        pos: 0,
+    };
+
+    let mut map = SourceMap::default();
+    let file_count = r.usize()?;
+    for _ in 0..file_count {
+        let path = r.string()?.into();
+        let contents = match r.u8()? {
+            0=>None,
+            1=>Some(r.string()?),
+            _=>bail!("Bytecode file has an invalid embedded source flag"),
+        };
+        map.files.push(SourceFile {path, contents});
+    }
+
+    let location_count = r.usize()?;
+    for _ in 0..location_count {
+        let id = r.ins_id()?;
+        let file = r.usize()?;
+        let line = r.usize()?;
+        let column = r.usize()?;
+        if file >= file_count {
+            bail!("Bytecode file has a source location in a file that doesn't exist");
+        } else if line == 0 || column == 0 {
+            bail!("Bytecode file has a source location before the start of its file");
+        }
+        map.locations.insert(id, Location {file, line, column});
+    }
+
+    if r.pos != bytes.len() {
+        bail!("Bytecode file has trailing data in its source map");
+    }
+
+    return Ok(map);
+}
+
+/// Read the header and split off the payload. The payload has to be exactly as long as the header
+/// says, but its checksum isn't checked.
+pub fn read_header(bytes: &[u8])->Result<(Header, &[u8])> {
+    let mut r = Reader {
+        bytes,
+        pos: 0,
+    };
+
+    if r.take(4)? != MAGIC {
+        bail!("Not a bytecode file");
+    }
+    let version = r.u16()?;
+    if version != FORMAT_VERSION {
+        bail!("Bytecode format version {version} is not supported. This build reads version {FORMAT_VERSION}; recompile the script with `simple_lisp compile`");
+    }
+    let mark = r.u32()?;
+    if mark == BYTE_ORDER_MARK.swap_bytes() {
+        bail!("Bytecode file has the wrong byte order. It was not written by this implementation");
+    } else if mark != BYTE_ORDER_MARK {
+        bail!("Bytecode file has an invalid header");
+    }
+    let interpreter_version = r.string()?;
+    let payload_len = r.u64()?;
+    let checksum = r.u64()?;
+
+    let payload = &bytes[r.pos..];
+    if (payload.len() as u64) < payload_len {
+        bail!("Bytecode file is truncated. It should have {payload_len} bytes after the header, but it only has {}", payload.len());
+    } else if payload.len() as u64 > payload_len {
+        bail!("Bytecode file has trailing data");
+    }
+
+    let header = Header {
+        format_version: version,
+        interpreter_version,
+        payload_len,
+        checksum,
+    };
+    return Ok((header, payload));
+}
+
+pub fn deserialize(bytes: &[u8])->Result<ConvertState> {
+    let (header, payload) = read_header(bytes)?;
+    if header.interpreter_version != INTERPRETER_VERSION {
+        bail!(
+            "Bytecode file was compiled by simple_lisp {}, but this is {INTERPRETER_VERSION}; recompile the script with `simple_lisp compile`",
+            header.interpreter_version.escape_debug(),
+        );
+    }
+    let actual = checksum(payload);
+    if actual != header.checksum {
+        bail!("Bytecode file is corrupted. Its checksum is {:016x}, but its contents hash to {actual:016x}", header.checksum);
+    }
+
+    let mut r = Reader {
+        bytes: payload,
+        pos: 0,
+    };
+
+    let mut interner = Interner::new();
+    let string_count = r.usize()?;
+    for i in 0..string_count {
+        let s = r.string()?;
+        if interner.intern(s).0 != i {
+            bail!("Bytecode file has a duplicate interned string");
+        }
+    }
+
+    let mut constants = ConstPool::new();
+    let mut constant_list = Vec::new();
+    let constant_count = r.usize()?;
+    for _ in 0..constant_count {
+        constant_list.push(constants.string(r.string()?));
+    }
+
+    let ins_count = r.usize()?;
+    let mut instructions = Vec::new();
+    for _ in 0..ins_count {
+        instructions.push(r.instruction(&constant_list)?);
+    }
+
+    let order_count = r.usize()?;
+    let mut ins_order = FxIndexSet::default();
+    for _ in 0..order_count {
+        let id = r.ins_id()?;
+        if id.inner() >= ins_count {
+            bail!("Bytecode file has an out of bounds instruction in the execution order");
+        }
+        ins_order.insert(id);
+    }
+
+    // `VarState::new` interns the default globals, but they are already in the interner, so no
+    // new strings are added.
+    let mut vars = VarState::new(&mut interner);
+    let default_count = vars.globals().count();
+    let global_count = r.usize()?;
+    for i in 0..global_count {
+        let name = r.ident()?;
+        if i < default_count {
+            if vars.globals().nth(i) != Some(name) {
+                bail!("Bytecode file was compiled with different default globals");
+            }
+        } else {
+            vars.insert(name);
+        }
+        match r.u8()? {
+            0=>{},
+            1=>vars.make_constant(name),
+            _=>bail!("Bytecode file has an invalid constant flag"),
+        }
+    }
+
+    let mut fns = SlotMap::new();
+    let fn_count = r.usize()?;
+    let mut next_fn_slot = 0;
+    for _ in 0..fn_count {
+        let id = r.usize()?;
+        let name = r.opt_ident()?;
+        let capture_count = r.usize()?;
+        let mut captures = Vec::new();
+        for _ in 0..capture_count {
+            captures.push(r.ident()?);
+        }
+        let sig = r.signature()?;
+
+        if id < next_fn_slot {
+            bail!("Bytecode file has out of order functions");
+        }
+        if id >= fn_count {
+            bail!("Bytecode file has function {id}, but only {fn_count} functions");
+        }
+        let mut slot: FnId = fns.reserve_slot();
+        while slot.id() < id {
+            slot = fns.reserve_slot();
+        }
+        next_fn_slot = id + 1;
+
+        if fns.insert_reserved(slot, Rc::new(Fn {id: slot, name, captures, sig})).is_err() {
+            bail!("Could not insert function {id}");
+        }
+    }
+
+    let mut modules = ModuleTree::new();
+    let mut module_slots = Vec::new();
+    let module_count = r.usize()?;
+    let mut next_module_slot = 0;
+    for _ in 0..module_count {
+        let id = r.usize()?;
+        let name = r.ident()?;
+        let child_count = r.usize()?;
+        let mut children = Vec::new();
+        for _ in 0..child_count {
+            children.push(ModuleId::from_id(r.usize()?));
+        }
+        let parent = match r.u8()? {
+            0=>None,
+            1=>Some(ModuleId::from_id(r.usize()?)),
+            _=>bail!("Bytecode file has an invalid module parent"),
+        };
+        let start_ins = r.ins_id()?;
+
+        if id < next_module_slot {
+            bail!("Bytecode file has out of order modules");
+        }
+        if id >= module_count {
+            bail!("Bytecode file has module {id}, but only {module_count} modules");
+        }
+        let mut slot = modules.reserve_slot();
+        while slot.id() < id {
+            slot = modules.reserve_slot();
+        }
+        next_module_slot = id + 1;
+
+        // exports are only needed to check `use` forms, which happened when this was compiled
+        let node = ModuleNode {name, children, parent, start_ins, exports: Vec::new(), privates: Vec::new()};
+        if modules.insert_reserved(slot, node).is_err() {
+            bail!("Could not insert module {id}");
+        }
+        module_slots.push(slot);
+    }
+
+    // left packed until an error or the debugger needs it
+    let source_map = match r.u8()? {
+        0=>LazySourceMap::default(),
+        1=>{
+            let len = r.usize()?;
+            LazySourceMap::packed(r.take(len)?.to_vec())
+        },
+        _=>bail!("Bytecode file has an invalid source map flag"),
+    };
+
+    if r.pos != payload.len() {
+        bail!("Bytecode file has trailing data");
+    }
+
+    let state = ConvertState {
+        interner,
+        fns,
+        warnings: Vec::new(),
+        instructions: InstructionStore::from_parts(instructions, ins_order),
+        modules,
+        vars,
+        constants,
+        // the modules are already in the bytecode
+        search_path: Default::default(),
+        module_cache: Default::default(),
+        pending_imports: Vec::new(),
+        scope_names: Default::default(),
+        source_map,
+        reserved: None,
+    };
+    validate(&state, &module_slots)?;
+
+    return Ok(state);
+}
+
+/// Check that everything in `state` refers to things that exist, then verify it. `modules` are the
+/// module slots that were filled in.
+fn validate(state: &ConvertState, modules: &[ModuleId])->Result<()> {
+    let raw = state.instructions.raw_instructions();
+    let ident_count = state.interner.len();
+    let global_count = state.vars.globals().count();
+
+    let ident = |i: Ident|match i.0 < ident_count {
+        true=>Ok(()),
+        false=>Err(anyhow!("Bytecode file refers to interned string {}, but it only has {ident_count}", i.0)),
+    };
+    let vector = |v: &Vector|->Result<()> {
+        v.items.iter().try_for_each(|i|ident(*i))?;
+        v.remainder.map_or(Ok(()), ident)
+    };
+    let slot = |slot: VarSlot|match !slot.global || slot.id < global_count {
+        true=>Ok(()),
+        false=>Err(anyhow!("Bytecode file refers to global {}, but it only has {global_count}", slot.id)),
+    };
+    let ins_id = |id: InstructionId|match id.inner() < raw.len() {
+        true=>Ok(()),
+        false=>Err(anyhow!("Bytecode file refers to instruction {}, but it only has {}", id.inner(), raw.len())),
+    };
+
+    for name in state.vars.globals() {
+        ident(name)?;
+    }
+
+    for ins in raw {
+        use Instruction as I;
+        match ins {
+            I::Field(i)|I::Ident(i)=>ident(*i)?,
+            I::SetVar(s)|I::GetVar(s)|I::GetVarCall(s, _)|I::NumberSetVar(_, s)=>slot(*s)?,
+            I::SetPath(s, path)=>{
+                slot(*s)?;
+                path.iter().try_for_each(|i|ident(*i))?;
+            },
+            I::Unpack(names)=>vector(names)?,
+            I::Jump(id)|I::JumpIfTrue(id)|I::JumpIfFalse(id)=>ins_id(*id)?,
+            I::Func(id)=>{
+                let Some(f) = state.fns.get(*id) else {
+                    bail!("Bytecode file makes function {}, but it doesn't have it", id.id());
+                };
+                f.name.map_or(Ok(()), ident)?;
+                f.captures.iter().try_for_each(|i|ident(*i))?;
+                for (params, body_ptr) in f.sig.bodies() {
+                    vector(params)?;
+                    ins_id(body_ptr)?;
+                }
+            },
+            I::Module(id)=>if state.modules.try_get(*id).is_none() {
+                bail!("Bytecode file runs module {}, but it doesn't have it", id.id());
+            },
+            _=>{},
+        }
+    }
+
+    if state.modules.try_get(ModuleId::root()).is_none() {
+        bail!("Bytecode file has no root module");
+    }
+    for id in modules {
+        let module = state.modules.get(*id);
+        ident(module.name)?;
+        ins_id(module.start_ins)?;
+        for relative in module.children.iter().chain(module.parent.iter()) {
+            if state.modules.try_get(*relative).is_none() {
+                bail!("Bytecode file refers to module {}, but it doesn't have it", relative.id());
+            }
+        }
+    }
+
+    return verify(state).map_err(|e|anyhow!("Bytecode file has invalid instructions: {e}"));
+}
+
+
+struct Writer(Vec<u8>);
+impl Writer {
+    fn bytes(&mut self, b: &[u8]) {
+        self.0.extend_from_slice(b);
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+
+    fn u16(&mut self, n: u16) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.u64(n as u64);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    fn ident(&mut self, i: Ident) {
+        self.usize(i.0);
+    }
+
+    fn opt_ident(&mut self, i: Option<Ident>) {
+        match i {
+            Some(i)=>{
+                self.u8(1);
+                self.ident(i);
+            },
+            None=>self.u8(0),
+        }
+    }
+
+    fn ins_id(&mut self, id: InstructionId) {
+        self.usize(id.inner());
+    }
+
+    fn var_slot(&mut self, slot: VarSlot) {
+        self.usize(slot.id);
+        self.u8(slot.global as u8);
+    }
+
+    fn vector(&mut self, v: &Vector) {
+        self.usize(v.items.len());
+        for item in v.items.iter() {
+            self.ident(*item);
+        }
+        self.opt_ident(v.remainder);
+    }
+
+    fn arity_map(&mut self, map: &FxIndexMap<usize, (Vector, InstructionId)>) {
+        self.usize(map.len());
+        for (count, (params, body_ptr)) in map.iter() {
+            self.usize(*count);
+            self.vector(params);
+            self.ins_id(*body_ptr);
+        }
+    }
+
+    fn signature(&mut self, sig: &FnSignature) {
+        match sig {
+            FnSignature::Single{params, body_ptr}=>{
+                self.u8(0);
+                self.vector(params);
+                self.ins_id(*body_ptr);
+            },
+            FnSignature::Multi{exact, max_exact, at_least, any}=>{
+                self.u8(1);
+                self.arity_map(exact);
+                self.usize(*max_exact);
+                self.arity_map(at_least);
+                match any {
+                    Some((params, body_ptr))=>{
+                        self.u8(1);
+                        self.vector(params);
+                        self.ins_id(*body_ptr);
+                    },
+                    None=>self.u8(0),
+                }
+            },
+        }
+    }
+
+    fn instruction(&mut self, ins: &Instruction, constants: &ConstPool) {
+        use Instruction as I;
+        match ins {
+            I::Nop=>self.u8(0),
+            I::Exit=>self.u8(1),
+            I::ReturnModule=>self.u8(2),
+            I::Module(id)=>{
+                self.u8(3);
+                self.usize(id.id());
+            },
+            I::Func(id)=>{
+                self.u8(4);
+                self.usize(id.id());
+            },
+            I::SetVar(slot)=>{
+                self.u8(5);
+                self.var_slot(*slot);
+            },
+            I::SetPath(slot, path)=>{
+                self.u8(6);
+                self.var_slot(*slot);
+                self.usize(path.len());
+                for i in path.iter() {
+                    self.ident(*i);
+                }
+            },
+            I::GetVar(slot)=>{
+                self.u8(7);
+                self.var_slot(*slot);
+            },
+            I::Field(i)=>{
+                self.u8(8);
+                self.ident(*i);
+            },
+            I::Number(n)=>{
+                self.u8(9);
+                self.u64(*n as u64);
+            },
+            I::Float(f)=>{
+                self.u8(10);
+                self.u64(f.to_bits());
+            },
+            I::String(s)=>{
+                self.u8(11);
+                let index = constants.string_index(s)
+                    .expect("String literal is missing from the constant pool");
+                self.usize(index);
+            },
+            I::Char(c)=>{
+                self.u8(12);
+                self.u32(*c as u32);
+            },
+            I::Bool(b)=>{
+                self.u8(13);
+                self.u8(*b as u8);
+            },
+            I::Byte(b)=>{
+                self.u8(14);
+                self.u8(*b);
+            },
+            I::Ident(i)=>{
+                self.u8(15);
+                self.ident(*i);
+            },
+            I::None=>self.u8(16),
+            I::Splat=>self.u8(17),
+            I::Call(count)=>{
+                self.u8(18);
+                self.usize(*count);
+            },
+            I::TailCall(count)=>{
+                self.u8(19);
+                self.usize(*count);
+            },
+            I::Return=>self.u8(20),
+            I::Scope(count)=>{
+                self.u8(21);
+                self.usize(*count);
+            },
+            I::EndScope(count)=>{
+                self.u8(22);
+                self.usize(*count);
+            },
+            I::JumpIfTrue(id)=>{
+                self.u8(23);
+                self.ins_id(*id);
+            },
+            I::JumpIfFalse(id)=>{
+                self.u8(24);
+                self.ins_id(*id);
+            },
+            I::Jump(id)=>{
+                self.u8(25);
+                self.ins_id(*id);
+            },
+            I::GetVarCall(slot, count)=>{
+                self.u8(26);
+                self.var_slot(*slot);
+                self.usize(*count);
+            },
+            I::NumberSetVar(n, slot)=>{
+                self.u8(27);
+                self.u64(*n as u64);
+                self.var_slot(*slot);
+            },
+            I::CallBuiltin(id, count)=>{
+                self.u8(28);
+                self.usize(id.inner());
+                self.usize(*count);
+            },
+            I::Unpack(names)=>{
+                self.u8(29);
+                self.vector(names);
+            },
+        }
+    }
+}
+
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize)->Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < count {
+            bail!("Unexpected end of bytecode file");
+        }
+
+        let out = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+
+        return Ok(out);
+    }
+
+    fn u8(&mut self)->Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self)->Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self)->Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self)->Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self)->Result<usize> {
+        let n = self.u64()?;
+        match usize::try_from(n) {
+            Ok(n)=>Ok(n),
+            Err(_)=>bail!("Bytecode file has a value that is too large for this platform"),
+        }
+    }
+
+    fn bool(&mut self)->Result<bool> {
+        match self.u8()? {
+            0=>Ok(false),
+            1=>Ok(true),
+            b=>bail!("Bytecode file has an invalid bool: {b}"),
+        }
+    }
+
+    fn string(&mut self)->Result<String> {
+        let len = self.usize()?;
+        match String::from_utf8(self.take(len)?.to_vec()) {
+            Ok(s)=>Ok(s),
+            Err(_)=>bail!("Bytecode file has an invalid UTF-8 string"),
+        }
+    }
+
+    fn ident(&mut self)->Result<Ident> {
+        Ok(Ident(self.usize()?))
+    }
+
+    fn opt_ident(&mut self)->Result<Option<Ident>> {
+        if self.bool()? {
+            Ok(Some(self.ident()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ins_id(&mut self)->Result<InstructionId> {
+        Ok(InstructionId::from_inner(self.usize()?))
+    }
+
+    fn var_slot(&mut self)->Result<VarSlot> {
+        let id = self.usize()?;
+        let global = self.bool()?;
+
+        return Ok(VarSlot {id, global});
+    }
+
+    fn vector(&mut self)->Result<Vector> {
+        let count = self.usize()?;
+        let mut items = Vec::new();
+        for _ in 0..count {
+            items.push(self.ident()?);
+        }
+        let remainder = self.opt_ident()?;
+
+        return Ok(Vector {items, remainder});
+    }
+
+    fn arity_map(&mut self)->Result<FxIndexMap<usize, (Vector, InstructionId)>> {
+        let count = self.usize()?;
+        let mut map = FxIndexMap::default();
+        for _ in 0..count {
+            let arg_count = self.usize()?;
+            let params = self.vector()?;
+            let body_ptr = self.ins_id()?;
+            map.insert(arg_count, (params, body_ptr));
+        }
+
+        return Ok(map);
+    }
+
+    fn signature(&mut self)->Result<FnSignature> {
+        match self.u8()? {
+            0=>{
+                let params = self.vector()?;
+                let body_ptr = self.ins_id()?;
+
+                Ok(FnSignature::Single {params, body_ptr})
+            },
+            1=>{
+                let exact = self.arity_map()?;
+                let max_exact = self.usize()?;
+                let at_least = self.arity_map()?;
+                let any = if self.bool()? {
+                    let params = self.vector()?;
+                    let body_ptr = self.ins_id()?;
+                    Some((params, body_ptr))
+                } else {
+                    None
+                };
+
+                Ok(FnSignature::Multi {exact, max_exact, at_least, any})
+            },
+            tag=>bail!("Bytecode file has an invalid function signature tag: {tag}"),
+        }
+    }
+
+    fn instruction(&mut self, constants: &[Rc<String>])->Result<Instruction> {
+        use Instruction as I;
+        let ins = match self.u8()? {
+            0=>I::Nop,
+            1=>I::Exit,
+            2=>I::ReturnModule,
+            3=>I::Module(ModuleId::from_id(self.usize()?)),
+            4=>I::Func(FnId::from_id(self.usize()?)),
+            5=>I::SetVar(self.var_slot()?),
+            6=>{
+                let slot = self.var_slot()?;
+                let count = self.usize()?;
+                let mut path = Vec::new();
+                for _ in 0..count {
+                    path.push(self.ident()?);
+                }
+
+                I::SetPath(slot, Rc::new(path))
+            },
+            7=>I::GetVar(self.var_slot()?),
+            8=>I::Field(self.ident()?),
+            9=>I::Number(self.u64()? as i64),
+            10=>I::Float(f64::from_bits(self.u64()?)),
+            11=>match constants.get(self.usize()?) {
+                Some(s)=>I::String(s.clone()),
+                None=>bail!("Bytecode file has an out of bounds string constant"),
+            },
+            12=>match char::from_u32(self.u32()?) {
+                Some(c)=>I::Char(c),
+                None=>bail!("Bytecode file has an invalid char"),
+            },
+            13=>I::Bool(self.bool()?),
+            14=>I::Byte(self.u8()?),
+            15=>I::Ident(self.ident()?),
+            16=>I::None,
+            17=>I::Splat,
+            18=>I::Call(self.usize()?),
+            19=>I::TailCall(self.usize()?),
+            20=>I::Return,
+            21=>I::Scope(self.usize()?),
+            22=>I::EndScope(self.usize()?),
+            23=>I::JumpIfTrue(self.ins_id()?),
+            24=>I::JumpIfFalse(self.ins_id()?),
+            25=>I::Jump(self.ins_id()?),
+            26=>I::GetVarCall(self.var_slot()?, self.usize()?),
+            27=>I::NumberSetVar(self.u64()? as i64, self.var_slot()?),
+            28=>match BuiltinId::from_inner(self.usize()?) {
+                Some(id)=>I::CallBuiltin(id, self.usize()?),
+                None=>bail!("Bytecode file calls a builtin that doesn't exist"),
+            },
+            29=>I::Unpack(Rc::new(self.vector()?)),
+            op=>bail!("Bytecode file has an invalid opcode: {op}"),
+        };
+
+        return Ok(ins);
+    }
+}
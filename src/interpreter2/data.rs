@@ -51,6 +51,7 @@ use std::{
     any::Any,
     ptr::NonNull,
     alloc::Layout,
+    time::Instant,
     rc::Rc,
     ptr,
     mem,
@@ -62,6 +63,10 @@ use super::{
     ConvertState,
     ArgCount,
 };
+use crate::{
+    gc_config::GcStats,
+    truthy::Truth,
+};
 
 
 // TODO: debug asserts
@@ -227,6 +232,56 @@ impl Primitive {
         }
     }
 
+    pub fn type_name(&self)->&'static str {
+        let data = match self {
+            Self::Int(_)=>return "number",
+            Self::Float(_)=>return "float",
+            Self::Char(_)=>return "char",
+            Self::Byte(_)=>return "byte",
+            Self::Bool(_)=>return "bool",
+            Self::Ident(_)=>return "ident",
+            Self::None=>return "none",
+            Self::String(_)=>return "string",
+            Self::Func(_)=>return "fn",
+            Self::NativeFunc(..)=>return "nativeFn",
+            Self::Ref(r)|Self::Root(RootDataRef(r))=>&**r,
+        };
+
+        data.type_name()
+    }
+
+    /// `eq?`: the same data, not just equal data. Strings and refs are the same if they point at
+    /// the same place. Everything else is a value, so equal ones are the same, and floats compare
+    /// by their bits so a NaN is the same as itself.
+    pub fn is_same(&self, other: &Self)->bool {
+        match (self, other) {
+            (Self::Ref(l)|Self::Root(RootDataRef(l)), Self::Ref(r)|Self::Root(RootDataRef(r)))=>l.0 == r.0,
+            (Self::String(l), Self::String(r))=>Rc::ptr_eq(l, r),
+            (Self::Float(l), Self::Float(r))=>l.to_bits() == r.to_bits(),
+            (l, r)=>l == r,
+        }
+    }
+
+    /// The items if this is a list
+    pub fn list_items(&self)->Option<&[Primitive]> {
+        match self {
+            Self::Ref(r)|Self::Root(RootDataRef(r))=>match &**r {
+                Data::List(items)=>Some(items),
+                _=>None,
+            },
+            _=>None,
+        }
+    }
+
+    /// Whether this counts as true when branching. See [`crate::truthy`].
+    pub fn truth(&self)->Truth {
+        match self {
+            Self::Bool(b)=>Truth::Bool(*b),
+            Self::None=>Truth::None,
+            value=>Truth::Other(value.type_name()),
+        }
+    }
+
     pub fn bool_or(self, err: Error)->Result<bool> {
         match self {
             Self::Bool(b)=>Ok(b),
@@ -257,6 +312,16 @@ pub enum Data {
     /// bug to expose this outside this module.
     None,
 }
+impl Data {
+    pub fn type_name(&self)->&'static str {
+        match self {
+            Self::Closure{..}=>"closure",
+            Self::Object(_)=>"object",
+            Self::List(_)=>"list",
+            Self::None=>"none",
+        }
+    }
+}
 impl PartialEq for Data {
     fn eq(&self, other: &Self)->bool {
         match (self, other) {
@@ -843,6 +908,10 @@ pub struct GcContext {
     pub params: Rc<Cell<GcParams>>,
     pub params_dr: DataRef,
     slices: Vec<AllocSlice>,
+    /// Stops the incremental collection. We still allocate more items when we run low.
+    pub disabled: bool,
+    /// What we did since the last `take_stats`
+    stats: GcStats,
 }
 impl GcContext {
     pub fn new(params: GcParams)->Self {
@@ -941,6 +1010,8 @@ impl GcContext {
             params: params_rc.clone(),
             params_dr,
             slices,
+            disabled: false,
+            stats: GcStats::default(),
         };
 
         // allocate the initial slice
@@ -970,14 +1041,34 @@ impl GcContext {
 
         self.move_to_grey(dr);
 
+        self.stats.allocations += 1;
+        let live = self.item_count - self.dead.len;
+        self.stats.peak_live = self.stats.peak_live.max(live);
+
         self.inc_collect();
 
         return dr;
     }
 
+    /// Get what we did since the last call, so the interpreter can add it to its stats
+    pub fn take_stats(&mut self)->GcStats {
+        let live = self.item_count - self.dead.len;
+        return mem::replace(&mut self.stats, GcStats {
+            peak_live: live,
+            ..GcStats::default()
+        });
+    }
+
     pub fn inc_collect(&mut self) {
         let params = self.params.get();
 
+        if self.disabled {
+            if self.dead.len < params.min_free_count {
+                self.alloc_slice(params.alloc_count);
+            }
+            return;
+        }
+
         self.incremental_collection(&params);
     }
 
@@ -1012,7 +1103,7 @@ impl GcContext {
     /// starts.
     ///
     /// Dead: 4; White: 12; Grey: 0; Black: 0
-    /// ```
+    /// ```text
     /// | ------------ | ----------------------------------- |
     /// | 00 01 02 03  | 04 05 06 07 08 09 10 11 12 13 14 15 |
     /// | ------------ | ----------------------------------- |
@@ -1096,7 +1187,7 @@ impl GcContext {
     /// starts.
     ///
     /// Dead: 4; White: 10; Grey: 2; Black: 0
-    /// ```
+    /// ```text
     /// | ------------ | ----------------------------- | --------------- |
     /// | 00 01 02 03  | 04 05 06 07 08 09 10 11 12 13 | 14           15 |
     /// | ------------ | ----------------------------- | --------------- |
@@ -1139,10 +1230,10 @@ impl GcContext {
 
         // if there are no more grey items, then go to the next state
         if self.grey.len == 0 {
-            eprintln!("Finish TraceGrey with {} traced", count);
+            log::debug!("Finish TraceGrey with {} traced", count);
             self.incr_state = IncrementalState::MarkDead;
         } else {
-            eprintln!("Pause TraceGrey with {} traced", count);
+            log::debug!("Pause TraceGrey with {} traced", count);
         }
     }
 
@@ -1153,7 +1244,7 @@ impl GcContext {
     /// starts.
     ///
     /// Dead: 4; White: 5; Grey: 0; Black: 7
-    /// ```
+    /// ```text
     /// | ------------ | ----------------------- | ----------- |
     /// | 00 01 02 03  | 04 05 06 07 08 09 10 11 | 12 13 14 15 |
     /// | ------------ | ----------------------- | ----------- |
@@ -1205,14 +1296,18 @@ impl GcContext {
         }
         self.last_white_worked = Some(next);
 
+        // every box is the same size, and what the data owned isn't counted
+        self.stats.freed_objects += todo_count as u64;
+        self.stats.freed_bytes += (todo_count * mem::size_of::<DataBox>()) as u64;
+
         // no need to change state here. We handle it in `Self::incremental_collect`. We do still
         // have to reset the state though.
         if self.white_count == 0 {
             assert!((self.dead.len + self.black.len) == self.item_count);
-            eprintln!("Finish MarkDead with {} marked", todo_count);
+            log::debug!("Finish MarkDead with {} marked", todo_count);
             self.last_white_worked = None;
         } else {
-            eprintln!("Pause MarkDead with {} marked", todo_count);
+            log::debug!("Pause MarkDead with {} marked", todo_count);
         }
     }
 
@@ -1221,14 +1316,14 @@ impl GcContext {
         use std::alloc::alloc;
 
         if count == 0 {
-            eprintln!("Not allocating");
+            log::debug!("Not allocating");
             return;
         }
 
         // get the layout and allocate
         let layout = Layout::array::<DataBox>(count).unwrap();
         let ptr = unsafe {alloc(layout)};
-        eprintln!("Allocating {} items at {:?}", count, ptr);
+        log::debug!("Allocating {} items at {:?}", count, ptr);
 
         // convert to the correct type
         let first_ptr = ptr as *mut DataBox;
@@ -1300,11 +1395,18 @@ impl GcContext {
     /// all black objects white for the Grey Roots phase. We also reset the black pointer to a
     /// known location.
     pub fn incremental_collection(&mut self, params: &GcParams)->bool {
+        let start = Instant::now();
+        let done = self.incremental_step(params);
+        self.stats.record_pause(true, start.elapsed());
+
+        return done;
+    }
+
+    /// `incremental_collection` without the timing
+    fn incremental_step(&mut self, params: &GcParams)->bool {
         use IncrementalState as State;
 
-        eprintln!("------------ Before inc collect");
-        self.debug_all_data();
-        eprintln!("------------\n");
+        self.debug_all_data("Before inc collect");
 
         // if we are below the free threshold, then allocate more items
         if self.dead.len < params.min_free_count {
@@ -1330,9 +1432,7 @@ impl GcContext {
             },
         }
 
-        eprintln!("------------ After inc collect");
-        self.debug_all_data();
-        eprintln!("------------\n");
+        self.debug_all_data("After inc collect");
 
         if self.cycle_done() {
             // change the meaning of white and black
@@ -1347,6 +1447,8 @@ impl GcContext {
             self.black.len = 0;
             self.grey.ptr = self.black.ptr;
 
+            self.stats.major_collections += 1;
+
             return true;
         }
 
@@ -1482,19 +1584,23 @@ impl GcContext {
         }
     }
 
-    fn debug_all_data(&self) {
+    /// Log every object with its flags. Only when tracing, since it walks the whole heap.
+    fn debug_all_data(&self, label: &str) {
+        if !log::log_enabled!(log::Level::Trace) {return}
+
         let items = self.item_count;
         let dead = self.dead.len;
         let white = self.white_count;
         let grey = self.grey.len;
         let black = self.black.len;
 
-        eprintln!("Units: {items}; Dead: {dead}; White: {white}; Grey: {grey}; Black: {black}");
+        log::trace!("------------ {label}");
+        log::trace!("Units: {items}; Dead: {dead}; White: {white}; Grey: {grey}; Black: {black}");
 
         let mut ptr = self.dead.ptr;
         for _ in 0..items {
             if ptr.0 == self.dead.ptr.0 {
-                eprintln!("Dead head v ");
+                log::trace!("Dead head v ");
             }
             let db = ptr.get_box();
             let flags = db.flags.get();
@@ -1502,37 +1608,38 @@ impl GcContext {
             db.default_asserts();
 
             // debug the permanent, root, and color flags
-            if flags.contains(DataFlags::PERMANENT) {
-                eprint!("P");
-            } else {eprint!(" ")}
-
-            if flags.contains(DataFlags::ROOT) {
-                eprint!("R");
-            } else {eprint!(" ")}
-
-            if flags.contains(DataFlags::GREY) {
-                eprint!("G");
+            let permanent = match flags.contains(DataFlags::PERMANENT) {
+                true=>'P',
+                false=>' ',
+            };
+            let root = match flags.contains(DataFlags::ROOT) {
+                true=>'R',
+                false=>' ',
+            };
+            let color = if flags.contains(DataFlags::GREY) {
+                'G'
             } else if flags.contains(self.black_flag) {
-                eprint!("B");
+                'B'
             } else if flags.contains(self.white_flag) {
                 match self.incr_state {
-                    IncrementalState::MarkDead=>eprint!("O"),
-                    _=>eprint!("W"),
+                    IncrementalState::MarkDead=>'O',
+                    _=>'W',
                 }
             } else {
-                eprint!("D");
-            }
+                'D'
+            };
 
-            eprintln!(" - {:?}", db.get());
+            log::trace!("{permanent}{root}{color} - {:?}", db.get());
 
             if ptr.0 == self.black.ptr.0 {
-                eprintln!("Black head ^ ");
+                log::trace!("Black head ^ ");
             }
             if ptr.0 == self.grey.ptr.0 {
-                eprintln!("Grey head ^ ");
+                log::trace!("Grey head ^ ");
             }
             ptr = db.next();
         }
+        log::trace!("------------");
     }
 }
 impl GcTracer for GcContext {
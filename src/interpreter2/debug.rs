@@ -0,0 +1,110 @@
+//! Watching the V2 interpreter run one instruction at a time. Set a `DebugHook` with
+//! `Interpreter::set_debug_hook` and it is called before every instruction. Without one, the
+//! dispatch loop only checks an `Option`.
+
+
+use anyhow::Result;
+use misc_utils::Key;
+use std::fmt::Write;
+use super::{
+    ast::{
+        ConvertState,
+        FnId,
+        Instruction,
+        InstructionId,
+    },
+    data::{
+        Data,
+        Primitive,
+    },
+    Interpreter,
+};
+use crate::numeric::FloatDisplay;
+
+
+/// Lists nested deeper than this are printed as `...` by `value_line`
+const MAX_DEPTH: usize = 16;
+
+
+pub trait DebugHook {
+    /// Called before the instruction `id` runs. The interpreter can be looked at but not changed.
+    /// Returning an error stops the program with it.
+    fn before_instruction(&mut self, id: InstructionId, ins: &Instruction, interpreter: &Interpreter, state: &ConvertState)->Result<()>;
+}
+
+
+/// The name of function `id`, or `<anonymous>`
+pub fn fn_name(id: FnId, state: &ConvertState)->&str {
+    match state.fns.get(id).and_then(|f|f.name) {
+        Some(name)=>state.interner.get(name),
+        None=>"<anonymous>",
+    }
+}
+
+/// `value` on one line, the way V1's pretty printer writes it with `pretty::ONE_LINE`
+pub fn value_line(value: &Primitive, state: &ConvertState)->String {
+    let mut out = String::new();
+    write_value(&mut out, value, state, 0);
+
+    return out;
+}
+
+fn write_value(out: &mut String, value: &Primitive, state: &ConvertState, depth: usize) {
+    match value {
+        Primitive::Int(i)=>write!(out, "{i}").unwrap(),
+        Primitive::Float(f)=>write!(out, "{}", FloatDisplay(*f)).unwrap(),
+        Primitive::Char(c)=>match c {
+            ' '=>out.push_str("\\space"),
+            '\n'=>out.push_str("\\newline"),
+            '\t'=>out.push_str("\\tab"),
+            c=>write!(out, "\\{c}").unwrap(),
+        },
+        Primitive::Byte(b)=>write!(out, "{b}").unwrap(),
+        Primitive::Bool(b)=>write!(out, "{b}").unwrap(),
+        Primitive::Ident(i)=>write!(out, "'{}", state.interner.get(*i)).unwrap(),
+        Primitive::None=>out.push_str("None"),
+        Primitive::String(s)=>write!(out, "{s:?}").unwrap(),
+        Primitive::Func(_)=>out.push_str("<fn>"),
+        Primitive::NativeFunc(..)=>out.push_str("<nativeFn>"),
+        Primitive::Root(_)=>write_value(out, &value.clone(), state, depth),
+        Primitive::Ref(r)=>match &**r {
+            Data::List(_) if depth >= MAX_DEPTH=>out.push_str("..."),
+            Data::List(items)=>{
+                out.push('(');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {out.push(' ')}
+                    write_value(out, item, state, depth + 1);
+                }
+                out.push(')');
+            },
+            Data::Closure{..}=>out.push_str("<fn>"),
+            Data::Object(_)=>out.push_str("<object>"),
+            Data::None=>out.push_str("None"),
+        },
+    }
+}
+
+/// A short description of `value` for printing, cut off after `width` chars
+pub fn value_text(value: &Primitive, state: &ConvertState, width: usize)->String {
+    let out = match value {
+        Primitive::Int(i)=>i.to_string(),
+        Primitive::Float(f)=>FloatDisplay(*f).to_string(),
+        Primitive::Char(c)=>format!("{c:?}"),
+        Primitive::Byte(b)=>format!("{b}u8"),
+        Primitive::Bool(b)=>b.to_string(),
+        Primitive::Ident(i)=>format!(".{}", state.interner.get(*i)),
+        Primitive::None=>"None".into(),
+        Primitive::String(s)=>format!("{s:?}"),
+        Primitive::Func(id)=>format!("<fn {}/{}>", fn_name(*id, state), id.id()),
+        Primitive::NativeFunc(..)=>"<native fn>".into(),
+        Primitive::Ref(_)|Primitive::Root(_)=>format!("{value:?}"),
+    };
+
+    if out.chars().count() <= width {
+        return out;
+    }
+
+    let mut cut = out.chars().take(width.saturating_sub(3)).collect::<String>();
+    cut.push_str("...");
+    return cut;
+}
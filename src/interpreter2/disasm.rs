@@ -0,0 +1,233 @@
+//! A readable listing of the V2 instructions in the `asm` form. Jump targets get a label line, and
+//! function bodies are grouped under a header with their parameters.
+
+
+use misc_utils::Key;
+use serde::Serialize;
+use std::fmt::{
+    Display,
+    Formatter,
+    Result as FmtResult,
+};
+use super::{
+    ast::*,
+    asm,
+    FxIndexMap,
+    FxIndexSet,
+};
+
+
+#[derive(Debug, Serialize)]
+pub struct Listing {
+    pub sections: Vec<Section>,
+}
+impl Display for Listing {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        for (i, section) in self.sections.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{section}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionKind {
+    Module,
+    Fn,
+}
+
+/// A module or function body. Everything after its start up to the next section belongs to it.
+#[derive(Debug, Serialize)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub name: String,
+    /// The function's id. Always `None` for modules.
+    pub fn_id: Option<usize>,
+    /// The parameters of this function body, e.g. `[a b & rest]`. Always `None` for modules.
+    pub params: Option<String>,
+    pub lines: Vec<Line>,
+}
+impl Display for Section {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        match self.kind {
+            SectionKind::Module=>writeln!(f, "module {}:", self.name)?,
+            SectionKind::Fn=>writeln!(
+                f,
+                "fn {} {} (FnId {}):",
+                self.name,
+                self.params.as_deref().unwrap_or("[]"),
+                self.fn_id.unwrap_or_default(),
+            )?,
+        }
+
+        for line in self.lines.iter() {
+            write!(f, "{line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Line {
+    /// Position in the execution order. `None` if the optimizer removed this instruction.
+    pub index: Option<usize>,
+    /// The `InstructionId`
+    pub id: usize,
+    /// Set if anything jumps here. The same as the jump's operand, like `@12`.
+    pub label: Option<String>,
+    pub mnemonic: &'static str,
+    pub operands: Vec<String>,
+    pub comment: Option<String>,
+}
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        if let Some(label) = &self.label {
+            writeln!(f, "{label}:")?;
+        }
+
+        let mut text = format!("{:<9}{}", self.mnemonic, self.operands.join(" "));
+        if let Some(comment) = &self.comment {
+            text = format!("{text:<32}; {comment}");
+        }
+
+        match self.index {
+            Some(index)=>writeln!(f, "  {:>5}  Id({:>4})  {}", index, self.id, text.trim_end()),
+            None=>writeln!(f, "  {:>5}  Id({:>4})  {}", "-", self.id, text.trim_end()),
+        }
+    }
+}
+
+
+struct Disassembler<'a> {
+    state: &'a ConvertState,
+}
+impl<'a> Disassembler<'a> {
+    fn ident(&self, i: Ident)->String {
+        self.state.interner.get(i).to_string()
+    }
+
+    fn fn_name(&self, id: FnId)->String {
+        match self.state.fns.get(id).and_then(|f|f.name) {
+            Some(name)=>self.ident(name),
+            None=>"<anonymous>".to_string(),
+        }
+    }
+
+    fn vector(&self, v: &Vector)->String {
+        let mut out = v.items.iter()
+            .map(|i|self.ident(*i))
+            .collect::<Vec<_>>();
+        if let Some(rem) = v.remainder {
+            out.push("&".into());
+            out.push(self.ident(rem));
+        }
+
+        return format!("[{}]", out.join(" "));
+    }
+}
+
+
+/// Build the listing for everything in `state`. If `show_eliminated` is set, instructions the
+/// optimizer removed are listed after the instruction that was created before them, without an
+/// index.
+pub fn disassemble(state: &ConvertState, show_eliminated: bool)->Listing {
+    let order = state.instructions.ins_order();
+    let instructions = state.instructions.raw_instructions();
+
+    let targets = instructions.iter()
+        .enumerate()
+        .filter(|(id, _)|show_eliminated || order.contains(&InstructionId::from_inner(*id)))
+        .filter_map(|(_, ins)|match ins {
+            Instruction::JumpIfTrue(id)|
+                Instruction::JumpIfFalse(id)|
+                Instruction::Jump(id)=>Some(id.inner()),
+            _=>None,
+        })
+        .collect::<FxIndexSet<_>>();
+
+    let dis = Disassembler {state};
+
+    // find where each section starts
+    let mut starts = FxIndexMap::default();
+    for id in state.module_ids() {
+        let module = state.modules.get(id);
+        starts.insert(module.start_ins.inner(), Section {
+            kind: SectionKind::Module,
+            name: dis.ident(module.name),
+            fn_id: None,
+            params: None,
+            lines: Vec::new(),
+        });
+    }
+    for id in state.fn_ids() {
+        let Some(f) = state.fns.get(id) else {continue};
+        for (params, body_ptr) in f.sig.bodies() {
+            starts.insert(body_ptr.inner(), Section {
+                kind: SectionKind::Fn,
+                name: dis.fn_name(id),
+                fn_id: Some(id.id()),
+                params: Some(dis.vector(params)),
+                lines: Vec::new(),
+            });
+        }
+    }
+
+    // Instructions are created one after another, so each eliminated one goes after the one with
+    // the id before it. That one may be eliminated too.
+    let mut eliminated_after: FxIndexMap<usize, Vec<usize>> = FxIndexMap::default();
+    if show_eliminated {
+        for id in (0..instructions.len()).filter(|id|!order.contains(&InstructionId::from_inner(*id))) {
+            eliminated_after.entry(id.wrapping_sub(1)).or_default().push(id);
+        }
+    }
+
+    let mut sections = Vec::new();
+    let mut todo = eliminated_after.get(&usize::MAX)
+        .map(|ids|ids.iter().rev().map(|id|(None, *id)).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut order_iter = order.iter().enumerate();
+    loop {
+        let (index, id) = match todo.pop() {
+            Some(item)=>item,
+            None=>match order_iter.next() {
+                Some((index, id))=>(Some(index), id.inner()),
+                None=>break,
+            },
+        };
+
+        if let Some(section) = starts.swap_remove(&id) {
+            sections.push(section);
+        }
+        if sections.is_empty() {
+            sections.push(Section {
+                kind: SectionKind::Module,
+                name: "<unknown>".into(),
+                fn_id: None,
+                params: None,
+                lines: Vec::new(),
+            });
+        }
+
+        let (mnemonic, operands) = asm::parts(&instructions[id], state, false);
+        sections.last_mut().unwrap().lines.push(Line {
+            index,
+            id,
+            label: targets.contains(&id).then(||format!("@{id}")),
+            mnemonic,
+            operands,
+            comment: index.is_none().then(||"eliminated".into()),
+        });
+
+        if let Some(ids) = eliminated_after.get(&id) {
+            todo.extend(ids.iter().rev().map(|id|(None, *id)));
+        }
+    }
+
+    return Listing {sections};
+}
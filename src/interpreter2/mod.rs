@@ -12,10 +12,7 @@ use indexmap::{
 };
 use misc_utils::Stack;
 use std::{
-    // time::{
-    //     Duration,
-    //     Instant,
-    // },
+    time::Duration,
     // collections::{
     //     HashMap,
     //     HashSet,
@@ -24,17 +21,56 @@ use std::{
     //     BufReader,
     //     stdin,
     // },
-    // rc::Rc,
     // cell::RefCell,
+    rc::Rc,
+    cmp::Ordering,
     mem,
 };
 use ast::*;
 use data::*;
+use debug::{
+    DebugHook,
+    value_text,
+};
+use trace::{
+    Trace,
+    TRACE_VALUE_WIDTH,
+    pushes_value,
+};
+use crate::{
+    budget::Budget,
+    opcode_stats::OpcodeCounts,
+    output::Output,
+    gc_config::{
+        GcConfig,
+        GcStats,
+    },
+    error::{
+        LispError,
+        Located,
+        Signature,
+        StackTrace,
+        TraceFrame,
+    },
+    data_path,
+    numeric::{
+        self,
+        Num,
+    },
+};
 
 
 pub mod ast;
+pub mod asm;
 pub mod builtins;
+pub mod bytecode;
 pub mod data;
+pub mod debug;
+pub mod disasm;
+pub mod optimize;
+pub mod source_map;
+pub mod trace;
+pub mod verify;
 
 
 // pub type IdentSet = FxHashSet<Ident>;
@@ -52,6 +88,34 @@ pub const DEFAULT_GLOBALS: &'static [&'static str] = &[
 
     "core",
     "std",
+
+    "*args*",
+    "*script*",
+
+    "args",
+    "nan?",
+    "infinite?",
+    "finite?",
+    "eq?",
+    "not=",
+    "<",
+    "<=",
+    ">",
+    ">=",
+];
+
+/// The natives that aren't builtins. Their globals come right after `*script*`, in this order.
+const NATIVES: &[(&str, NativeFn, ArgCount)] = &[
+    ("args", script_args, ArgCount::Exact(0)),
+    ("nan?", is_nan, ArgCount::Exact(1)),
+    ("infinite?", is_infinite, ArgCount::Exact(1)),
+    ("finite?", is_finite, ArgCount::Exact(1)),
+    ("eq?", identical, ArgCount::Any),
+    ("not=", not_equal, ArgCount::Any),
+    ("<", less, ArgCount::Any),
+    ("<=", less_equal, ArgCount::Any),
+    (">", greater, ArgCount::Any),
+    (">=", greater_equal, ArgCount::Any),
 ];
 
 
@@ -79,10 +143,158 @@ pub enum TailCallState {
 //     stack: ArrayVec<InlineData, 128>,
 // }
 
+/// The global slot of a name in `DEFAULT_GLOBALS`. Panics if the name isn't in the list.
+fn default_global_id(name: &str)->usize {
+    DEFAULT_GLOBALS.iter()
+        .position(|g|*g == name)
+        .expect("Name is not a default global")
+}
+
+/// `(args)`: the arguments passed to the script, as a new list of strings. The script path is
+/// `*script*`.
+fn script_args(params: ObjectParams, _args: Vec<Primitive>)->Result<Primitive> {
+    let interpreter = params.interpreter;
+    let args = interpreter.script_args.iter()
+        .map(|a|Primitive::String(a.clone()))
+        .collect();
+
+    return Ok(Primitive::Ref(interpreter.gc.alloc(Data::List(args))));
+}
+
+/// `(nan? x)`. The only way to tell, since `(= x x)` is false for a NaN.
+fn is_nan(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    float_predicate(&args[0], "nan?", f64::is_nan, false)
+}
+
+/// `(infinite? x)`: `x` is `#inf` or `#-inf`
+fn is_infinite(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    float_predicate(&args[0], "infinite?", f64::is_infinite, false)
+}
+
+/// `(finite? x)`: `x` is neither infinite nor NaN
+fn is_finite(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    float_predicate(&args[0], "finite?", f64::is_finite, true)
+}
+
+/// Check a property of a float. Numbers are always finite, so they get `int_answer`.
+fn float_predicate(arg: &Primitive, op: &str, check: fn(f64)->bool, int_answer: bool)->Result<Primitive> {
+    let holds = match arg {
+        Primitive::Int(_)=>int_answer,
+        Primitive::Float(f)=>check(*f),
+        p=>bail!(LispError::Type{op: op.into(), expected: "number or float", actual: p.type_name()}),
+    };
+
+    return Ok(Primitive::Bool(holds));
+}
+
+/// `(eq? a b ...)`: every arg is the same data as the first. See `Primitive::is_same`.
+fn identical(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    let same = match args.split_first() {
+        Some((first, rest))=>rest.iter().all(|arg|arg.is_same(first)),
+        None=>true,
+    };
+
+    return Ok(Primitive::Bool(same));
+}
+
+/// `(not= a b ...)`: none of the args are equal to the last one, the same way V1 checks it
+fn not_equal(_: ObjectParams, mut args: Vec<Primitive>)->Result<Primitive> {
+    let Some(last) = args.pop() else {
+        return Ok(Primitive::Bool(true));
+    };
+    let last = last.unroot();
+
+    return Ok(Primitive::Bool(args.into_iter().all(|arg|arg.unroot() != last)));
+}
+
+fn less(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    compare_chain(&args, "<", Ordering::is_lt)
+}
+
+fn less_equal(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    compare_chain(&args, "<=", Ordering::is_le)
+}
+
+fn greater(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    compare_chain(&args, ">", Ordering::is_gt)
+}
+
+fn greater_equal(_: ObjectParams, args: Vec<Primitive>)->Result<Primitive> {
+    compare_chain(&args, ">=", Ordering::is_ge)
+}
+
+/// True if `check` holds for every pair of neighboring args, so `(< a b c)` is `a < b < c`
+fn compare_chain(args: &[Primitive], op: &str, check: fn(Ordering)->bool)->Result<Primitive> {
+    let mut holds = true;
+    for pair in args.windows(2) {
+        // keep going so a later pair of the wrong types is still an error
+        holds &= compare(op, &pair[0], &pair[1])?.is_some_and(check);
+    }
+
+    return Ok(Primitive::Bool(holds));
+}
+
+/// How `op` orders `l` and `r`, with the same rules as V1. `None` if either is NaN.
+fn compare(op: &str, l: &Primitive, r: &Primitive)->Result<Option<Ordering>> {
+    let ord = match (l, r) {
+        (Primitive::Int(_)|Primitive::Float(_), Primitive::Int(_)|Primitive::Float(_))=>{
+            numeric::compare(as_num(l).unwrap(), as_num(r).unwrap())
+        },
+        // UTF-8 byte order is the same as code point order
+        (Primitive::String(l), Primitive::String(r))=>Some(l.cmp(r)),
+        (Primitive::Char(l), Primitive::Char(r))=>Some(l.cmp(r)),
+        _=>bail!(LispError::Compare{op: op.into(), left: l.type_name(), right: r.type_name()}),
+    };
+
+    return Ok(ord);
+}
+
+fn as_num(p: &Primitive)->Option<Num> {
+    match p {
+        Primitive::Int(i)=>Some(Num::Int(*i)),
+        Primitive::Float(f)=>Some(Num::Float(*f)),
+        _=>None,
+    }
+}
+
+/// The item segment `i` of `path` picks out of a list of `len` items
+fn path_index(len: usize, slot: VarSlot, path: &[Ident], i: usize, setting: bool, state: &ConvertState)->Result<usize> {
+    match data_path::list_index(state.interner.get(path[i]), len) {
+        Some(Ok(index))=>Ok(index),
+        Some(Err(index))=>Err(data_path::out_of_bounds(path_text(slot, path, state), setting, index, len).into()),
+        None=>Err(dead_end_path(slot, path, i, "list", setting, state).into()),
+    }
+}
+
+fn dead_end_path(slot: VarSlot, path: &[Ident], i: usize, actual: &'static str, setting: bool, state: &ConvertState)->LispError {
+    data_path::dead_end(path_text(slot, path, state), state.interner.get(path[i]), actual, setting)
+}
+
+/// `path` only has the segments after the var, so a global's name is looked up. Locals don't keep
+/// their names, so their paths start at the first segment.
+fn path_text(slot: VarSlot, path: &[Ident], state: &ConvertState)->String {
+    let var = match slot.global {
+        true=>state.vars.globals().nth(slot.id),
+        false=>None,
+    };
+
+    data_path::text(var.into_iter().chain(path.iter().copied()).map(|i|state.interner.get(i)))
+}
+
+/// An instruction `--trace` is printing once it is done
+struct TracedInstruction {
+    line: String,
+    pushes_value: bool,
+    /// The call depth it started at
+    depth: usize,
+}
+
 pub struct CallFrame {
     stack: Stack<Primitive>,
     vars: Vec<Primitive>,
     ret_id: InstructionId,
+    /// What was called, for stack traces. `None` if it wasn't a function.
+    func: Option<FnId>,
 }
 
 // TODO: make single-file programs work
@@ -95,12 +307,34 @@ pub struct Interpreter {
     stack: Stack<Primitive>,
     call_stack: Stack<CallFrame>,
     gc: GcContext,
+    /// Calling a function when the call stack is this deep is an error
+    max_stack_depth: usize,
+    budget: Budget,
+    debug_hook: Option<Box<dyn DebugHook>>,
+    trace: Option<Box<Trace>>,
+    /// Only counted if `set_opcode_stats` turned it on
+    opcodes: Option<Box<OpcodeCounts>>,
+    /// Branching on anything but a bool or `none` is an error
+    strict_bool: bool,
+    /// Where natives write what the program prints
+    output: Box<dyn Output>,
+    pub instructions_executed: u64,
+    /// Updated when each run ends
+    pub gc_stats: GcStats,
+    /// One past where the last instruction to run is in the execution order, so an error can be
+    /// traced back to it
+    ins_position: usize,
+    /// What `(args)` returns
+    script_args: Vec<Rc<String>>,
 }
 impl Interpreter {
     // TODO: Add things to the core and std objects
-    pub fn new(_state: &mut ConvertState, gc_params: Option<GcParams>)->Self {
+    /// The allocation and byte triggers in `gc_config` are ignored since the collector does a bit
+    /// of work on every allocation. Tune it with `GcParams` instead.
+    pub fn new(_state: &mut ConvertState, gc_config: GcConfig, max_stack_depth: usize, max_instructions: Option<u64>, output: Box<dyn Output>)->Self {
         let mut globals = Vec::new();
-        let mut gc = GcContext::new(gc_params.unwrap_or_default());
+        let mut gc = GcContext::new(GcParams::default());
+        gc.disabled = gc_config.disabled;
 
         let mut globals_iter = DEFAULT_GLOBALS.into_iter();
 
@@ -114,15 +348,120 @@ impl Interpreter {
         core_dr.set_permanent();
         globals.push(Primitive::Ref(core_dr).rooted());
 
+        // `std`, `*args*`, and `*script*` are set later
+        globals.resize(default_global_id("args"), Primitive::None);
+        for (name, func, count) in NATIVES {
+            assert!(globals.len() == default_global_id(name));
+            globals.push(Primitive::NativeFunc(*func, *count));
+        }
+
         Interpreter {
             globals,
             vars: Vec::new(),
             stack: Stack::new(),
             call_stack: Stack::new(),
             gc,
+            max_stack_depth,
+            budget: Budget::new(max_instructions, None),
+            debug_hook: None,
+            trace: None,
+            opcodes: None,
+            strict_bool: false,
+            output,
+            instructions_executed: 0,
+            gc_stats: GcStats::default(),
+            ins_position: 0,
+            script_args: Vec::new(),
+        }
+    }
+
+    /// Limit how long each call to `run` may take
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.budget.timeout = timeout;
+    }
+
+    /// Call `hook` before every instruction, or stop calling the old one if `None`
+    pub fn set_debug_hook(&mut self, hook: Option<Box<dyn DebugHook>>) {
+        self.debug_hook = hook;
+    }
+
+    /// Print each instruction to stderr as it runs, or stop if `None`
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace.map(Box::new);
+    }
+
+    /// Start or stop counting how many times each kind of instruction runs
+    pub fn set_opcode_stats(&mut self, enabled: bool) {
+        self.opcodes = enabled.then(||Box::new(OpcodeCounts::new(Instruction::OPCODE_NAMES)));
+    }
+
+    /// `None` unless `set_opcode_stats` turned them on
+    pub fn opcode_counts(&self)->Option<&OpcodeCounts> {
+        self.opcodes.as_deref()
+    }
+
+    /// Make branching on anything but a bool or `none` an error
+    pub fn set_strict_bool(&mut self, strict: bool) {
+        self.strict_bool = strict;
+    }
+
+    /// Send what the program prints to `output` instead
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = output;
+    }
+
+    #[inline]
+    pub fn output(&mut self)->&mut dyn Output {
+        &mut *self.output
+    }
+
+    /// How many calls deep we are. 0 is the top level.
+    #[inline]
+    pub fn call_depth(&self)->usize {
+        self.call_stack.len()
+    }
+
+    /// The function each call frame is running, innermost first. `None` if it wasn't a function.
+    pub fn call_frames(&self)->impl Iterator<Item = Option<FnId>> + '_ {
+        self.call_stack.iter().map(|frame|frame.func)
+    }
+
+    /// The local var slots of the current call
+    #[inline]
+    pub fn locals(&self)->&[Primitive] {
+        &self.vars
+    }
+
+    /// The local var slots of the call `depth` frames out from the current one, so 0 is the same
+    /// as `locals`. A failed run leaves its calls on the stack, so this works after an error too.
+    pub fn frame_locals(&self, depth: usize)->Option<&[Primitive]> {
+        match depth {
+            0=>Some(&self.vars),
+            _=>self.call_stack.get(depth - 1).map(|frame|frame.vars.as_slice()),
         }
     }
 
+    /// The value in global slot `id`, if it was ever set
+    pub fn global(&self, id: usize)->Option<&Primitive> {
+        self.globals.get(id)
+    }
+
+    /// Set `*script*` and `*args*` to the script path and the arguments passed after `--`. `(args)`
+    /// returns the arguments too.
+    pub fn set_script_args(&mut self, script: &str, args: &[String]) {
+        self.script_args = args.iter()
+            .map(|a|Rc::new(a.clone()))
+            .collect();
+        let args = self.script_args.iter()
+            .map(|a|Primitive::String(a.clone()))
+            .collect();
+        let args_dr = self.gc.alloc(Data::List(args));
+        self.set_global(default_global_id("*args*"), Primitive::Ref(args_dr).rooted());
+
+        let script = Primitive::String(Rc::new(script.to_string()));
+        self.set_global(default_global_id("*script*"), script);
+    }
+
     fn get_global(&mut self, id: usize)->Primitive {
         while self.globals.len() <= id {
             self.globals.push(Primitive::None);
@@ -164,6 +503,28 @@ impl Interpreter {
     }
 
     pub fn run(&mut self, state: &mut ConvertState, start_id: Option<InstructionId>)->Result<Primitive> {
+        let call_depth = self.call_stack.len();
+        let res = self.run_inner(state, start_id)
+            .map_err(|e|{
+                // only now is the source map decoded, if there is one
+                let location = self.last_instruction(state)
+                    .and_then(|id|state.source_map.get()?.locate(id));
+                StackTrace::wrap(Located::wrap(e, location), self.stack_trace(call_depth, state))
+            });
+
+        let gc_stats = self.gc.take_stats();
+        self.gc_stats.merge(&gc_stats);
+
+        return res;
+    }
+
+    /// The instruction that ran last. After an error, it is the one that failed.
+    pub fn last_instruction(&self, state: &ConvertState)->Option<InstructionId> {
+        let index = self.ins_position.checked_sub(1)?;
+        state.instructions.ins_order().get_index(index).copied()
+    }
+
+    fn run_inner(&mut self, state: &mut ConvertState, start_id: Option<InstructionId>)->Result<Primitive> {
         use Instruction as I;
         use Primitive as P;
         // use Data as D;
@@ -177,9 +538,26 @@ impl Interpreter {
         const MAX_ITERS: usize = 1_000;
         let mut i = 0;
 
+        self.budget.start();
+
         while let Some(ins) = iter.next() {
             if i >= MAX_ITERS {panic!("Max iters reached!")}
             i += 1;
+            self.ins_position = iter.position();
+            self.instructions_executed += 1;
+            if let Some(opcodes) = &mut self.opcodes {
+                opcodes.record(ins.opcode());
+            }
+            self.budget.check(self.instructions_executed)?;
+
+            if self.debug_hook.is_some() {
+                let id = iter.cur_ins_id().unwrap();
+                self.run_debug_hook(id, ins, state)?;
+            }
+            let traced = match self.trace.is_some() {
+                true=>self.trace_before(iter.cur_ins_id().unwrap(), ins, state),
+                false=>None,
+            };
 
             match ins {
                 I::Nop=>{},
@@ -189,7 +567,8 @@ impl Interpreter {
                 I::ReturnModule=>{
                     todo!();
                 },
-                I::Module(_id)=>{
+                I::Module(id)=>{
+                    let _module = state.get_module(*id)?;
                     todo!();
                 },
 
@@ -221,9 +600,13 @@ impl Interpreter {
                                             set_data,
                                         )?;
                                     },
-                                    _=>bail!("Data is not an object"),
+                                    Data::List(items)=>{
+                                        let index = path_index(items.len(), *slot, &path, i, true, state)?;
+                                        items[index] = set_data;
+                                    },
+                                    data=>bail!(dead_end_path(*slot, &path, i, data.type_name(), true, state)),
                                 },
-                                _=>bail!("Primitive is not a Data Ref"),
+                                p=>bail!(dead_end_path(*slot, &path, i, p.type_name(), true, state)),
                             }
 
                             break;
@@ -236,9 +619,12 @@ impl Interpreter {
                                             ObjectParams {state, interpreter: self},
                                         )?;
                                     },
-                                    _=>bail!("Data is not an object"),
+                                    Data::List(items)=>{
+                                        data = items[path_index(items.len(), *slot, &path, i, true, state)?].clone();
+                                    },
+                                    data=>bail!(dead_end_path(*slot, &path, i, data.type_name(), true, state)),
                                 },
-                                _=>bail!("Primitive is not a Data Ref"),
+                                p=>bail!(dead_end_path(*slot, &path, i, p.type_name(), true, state)),
                             }
                         }
                     }
@@ -268,10 +654,18 @@ impl Interpreter {
                                 iter = state.instructions.iter();
                                 iter.jump(id);
                             },
-                            _=>bail!("Data is not an object"),
+                            Data::List(items)=>{
+                                let segment = state.interner.get(*ident);
+                                match data_path::list_index(segment, items.len()) {
+                                    Some(Ok(index))=>self.push_stack(items[index].clone()),
+                                    Some(Err(index))=>bail!(LispError::IndexOutOfBounds{op: "field".into(), index, len: items.len()}),
+                                    None=>bail!(LispError::Type{op: "field".into(), expected: "object", actual: "list"}),
+                                }
+                            },
+                            data=>bail!(LispError::Type{op: "field".into(), expected: "object", actual: data.type_name()}),
                         },
                         P::Root(_)=>unreachable!("We have a bug that leaks a rooted primitive to the stack!"),
-                        p=>bail!("Primitive `{:?}` is not an Object Ref", p),
+                        p=>bail!(LispError::Type{op: "field".into(), expected: "object", actual: p.type_name()}),
                     }
                 },
                 I::Number(int)=>self.push_stack(P::Int(*int)),
@@ -286,23 +680,49 @@ impl Interpreter {
                 I::Splat=>{
                     todo!();
                 },
+                I::Unpack(names)=>{
+                    // the list stays rooted until the rest is allocated, since the items are only
+                    // reachable through it
+                    let list = self.stack.pop().unwrap();
+                    let Some(items) = list.list_items() else {
+                        bail!(LispError::Type{op: "def".into(), expected: "list", actual: list.type_name()});
+                    };
+
+                    let needed = names.items.len();
+                    let fits = match names.remainder {
+                        Some(_)=>items.len() >= needed,
+                        None=>items.len() == needed,
+                    };
+                    if !fits {
+                        bail!(LispError::DestructureLength {
+                            pattern: names.describe(&state.interner),
+                            needed,
+                            variadic: names.remainder.is_some(),
+                            actual: items.len(),
+                        });
+                    }
+
+                    let items = items.to_vec();
+                    if names.remainder.is_some() {
+                        let rest = self.gc.alloc(Data::List(items[needed..].to_vec()));
+                        self.push_stack(P::Ref(rest));
+                    }
+                    drop(list);
+                    for item in items.into_iter().take(needed).rev() {
+                        self.push_stack(item);
+                    }
+                },
                 I::Call(arg_count)=>{
+                    let arg_count = *arg_count;
                     let to_call = self.pop_stack();
-                    let mut args = Vec::new();
-                    for _ in 0..*arg_count {
-                        args.push(self.pop_stack());
-                    }
 
-                    // save the current state
-                    self.push_call_frame(iter.next_ins_id().unwrap());
+                    let ret_id = iter.next_ins_id().unwrap();
                     drop(iter);
 
-                    let ret = self.call(to_call, None, args, state)?;
+                    self.call_with_stack_args(to_call, arg_count, ret_id, state)?;
 
-                    // restore the current state
                     iter = state.instructions.iter();
-                    iter.jump(self.pop_call_frame());
-                    self.push_stack(ret);
+                    iter.jump(ret_id);
                 },
                 I::TailCall(_arg_count)=>{
                     todo!();
@@ -319,7 +739,7 @@ impl Interpreter {
                 },
                 I::JumpIfTrue(id)=>{
                     let data = self.pop_stack();
-                    if data == P::Bool(true) {
+                    if data.truth().is_truthy("cond", self.strict_bool)? {
                         iter.jump(*id);
                     }
                 },
@@ -329,20 +749,177 @@ impl Interpreter {
                 I::Jump(_id)=>{
                     todo!();
                 },
+
+                I::GetVarCall(slot, arg_count)=>{
+                    let arg_count = *arg_count;
+                    let to_call = self.get_var(*slot);
+
+                    let ret_id = iter.next_ins_id().unwrap();
+                    drop(iter);
+
+                    self.call_with_stack_args(to_call, arg_count, ret_id, state)?;
+
+                    iter = state.instructions.iter();
+                    iter.jump(ret_id);
+                },
+                I::NumberSetVar(int, slot)=>self.set_var(*slot, P::Int(*int)),
+                I::CallBuiltin(id, arg_count)=>{
+                    let (id, arg_count) = (*id, *arg_count);
+
+                    let ret_id = iter.next_ins_id().unwrap();
+                    drop(iter);
+
+                    self.call_builtin(id, arg_count, state)?;
+
+                    iter = state.instructions.iter();
+                    iter.jump(ret_id);
+                },
             }
+
+            if let Some(traced) = traced {
+                self.trace_after(traced, state);
+            }
+        }
+
+        // code that ends with a `def` leaves nothing behind
+        return Ok(self.stack.pop().map_or(P::None, |p|p.unroot()));
+    }
+
+    /// Decide whether to trace the instruction about to run, and describe it if so. The
+    /// instruction can't be looked at afterwards since some of them let go of `state`.
+    #[cold]
+    fn trace_before(&mut self, id: InstructionId, ins: &Instruction, state: &ConvertState)->Option<TracedInstruction> {
+        let depth = self.call_depth();
+        let func = self.call_frames().next().flatten();
+        if !self.trace.as_mut().unwrap().wants(depth, func, state) {
+            return None;
+        }
+
+        return Some(TracedInstruction {
+            line: format!("{:>6} Id({:>4}) {}", self.instructions_executed, id.inner(), ins.asm(state)),
+            pushes_value: pushes_value(ins),
+            depth,
+        });
+    }
+
+    #[cold]
+    fn trace_after(&mut self, traced: TracedInstruction, state: &ConvertState) {
+        // whatever the instruction printed goes first
+        let _ = self.output.flush();
+
+        // a call that went into a new frame hasn't made its value yet
+        let value = match traced.pushes_value && self.call_depth() <= traced.depth {
+            true=>self.stack.get(0),
+            false=>None,
+        };
+
+        match value {
+            Some(value)=>eprintln!("{} => {}", traced.line, value_text(value, state, TRACE_VALUE_WIDTH)),
+            None=>eprintln!("{}", traced.line),
+        }
+    }
+
+    #[cold]
+    fn run_debug_hook(&mut self, id: InstructionId, ins: &Instruction, state: &ConvertState)->Result<()> {
+        // the hook gets to look at us, so it can't be borrowed from us while it does
+        let mut hook = self.debug_hook.take().unwrap();
+        let res = hook.before_instruction(id, ins, self, state);
+        self.debug_hook = Some(hook);
+
+        return res;
+    }
+
+    /// Pop `arg_count` arguments off of the stack, call `to_call` with them, and push the result.
+    /// Execution should continue at `ret_id` afterwards.
+    fn call_with_stack_args(&mut self, to_call: Primitive, arg_count: usize, ret_id: InstructionId, state: &mut ConvertState)->Result<()> {
+        let mut args = Vec::new();
+        for _ in 0..arg_count {
+            args.push(self.pop_stack());
         }
 
-        return Ok(self.pop_stack());
+        if self.call_stack.len() >= self.max_stack_depth {
+            let name = match &to_call {
+                Primitive::Func(id)=>state.fns.get(*id)
+                    .and_then(|f|f.name)
+                    .map(|n|state.interner.get(n).to_string()),
+                _=>None,
+            };
+            bail!(LispError::RecursionLimit{max: self.max_stack_depth, name});
+        }
+
+        // save the current state
+        let func = match &to_call {
+            Primitive::Func(id)=>Some(*id),
+            _=>None,
+        };
+        self.push_call_frame(ret_id, func);
+
+        let ret = self.call(to_call, None, args, state)?;
+
+        // restore the current state
+        let resume_id = self.pop_call_frame();
+        debug_assert!(resume_id == ret_id);
+        self.push_stack(ret);
+
+        return Ok(());
     }
 
-    fn push_call_frame(&mut self, ret_id: InstructionId) {
+    /// Pop `arg_count` arguments off of the stack and call the builtin with them. This skips the
+    /// call frame since builtins never call back into the interpreter.
+    fn call_builtin(&mut self, id: BuiltinId, arg_count: usize, state: &mut ConvertState)->Result<()> {
+        let (_, func, count) = &builtins::ROOT[id.inner()];
+
+        let mut args = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            args.push(self.pop_stack());
+        }
+
+        if let ArgCount::Exact(count) = count {
+            if args.len() != *count {
+                bail!(LispError::Arity{name: Some(id.name().to_string()), expected: vec![Signature::exact(*count)], got: args.len()});
+            }
+        }
+
+        let ret = func(
+            ObjectParams {
+                state,
+                interpreter: self,
+            },
+            args,
+        )?;
+        self.push_stack(ret);
+
+        return Ok(());
+    }
+
+    fn push_call_frame(&mut self, ret_id: InstructionId, func: Option<FnId>) {
         self.call_stack.push(CallFrame {
             vars: mem::replace(&mut self.vars, Vec::new()),
             stack: mem::replace(&mut self.stack, Stack::new()),
             ret_id,
+            func,
         });
     }
 
+    /// The calls made after the call stack was `call_depth` deep, innermost first
+    fn stack_trace(&self, call_depth: usize, state: &ConvertState)->Vec<TraceFrame> {
+        self.call_stack.iter()
+            .take(self.call_stack.len().saturating_sub(call_depth))
+            .map(|frame|{
+                let name = match frame.func.and_then(|id|state.fns.get(id)).and_then(|f|f.name) {
+                    Some(name)=>format!("`{}`", state.interner.get(name)),
+                    None=>"<anonymous>".into(),
+                };
+
+                TraceFrame {
+                    name,
+                    call_site: None,
+                    tail_calls: 0,
+                }
+            })
+            .collect()
+    }
+
     fn pop_call_frame(&mut self)->InstructionId {
         let frame = self.call_stack.pop().unwrap();
         self.vars = frame.vars;
@@ -369,7 +946,7 @@ impl Interpreter {
                     },
                     ArgCount::Exact(count)=>{
                         if args.len() != count {
-                            bail!("Expected {} args for <nativeFn>, but got {}", count, args.len());
+                            bail!(LispError::Arity{name: None, expected: vec![Signature::exact(count)], got: args.len()});
                         }
 
                         return func(
@@ -407,7 +984,7 @@ impl Interpreter {
                     },
                     ArgCount::Exact(count)=>{
                         if args.len() != count {
-                            bail!("Expected {} args for <nativeFn>, but got {}", count, args.len());
+                            bail!(LispError::Arity{name: None, expected: vec![Signature::exact(count)], got: args.len()});
                         }
 
                         return func(
@@ -420,7 +997,10 @@ impl Interpreter {
                     },
                 }
             },
-            _=>todo!(),
+            value=>bail!(LispError::NotCallable {
+                value: Some(value_text(&value, state, 40)),
+                actual: value.type_name(),
+            }),
         }
     }
 
@@ -428,7 +1008,8 @@ impl Interpreter {
         todo!();
     }
 
-    fn call_fn(&mut self, _id: FnId, _arg0: Option<Primitive>, _args: Vec<Primitive>, _state: &mut ConvertState)->Result<Primitive> {
+    fn call_fn(&mut self, id: FnId, _arg0: Option<Primitive>, _args: Vec<Primitive>, state: &mut ConvertState)->Result<Primitive> {
+        let _func = state.get_fn(id)?;
         todo!();
     }
 }
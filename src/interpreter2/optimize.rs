@@ -0,0 +1,331 @@
+//! Passes over the converted instructions that run before they are executed or written to a
+//! bytecode file. These only ever change `ins_order` and jump targets, so every `InstructionId`
+//! stays valid and the instruction list itself is untouched.
+
+
+use super::{
+    ast::*,
+    FxIndexSet,
+};
+
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct OptimizeStats {
+    /// Conditional jumps on a `true` or `false` literal that were resolved at compile time
+    pub folded_branches: usize,
+    /// Instructions removed from the execution order because nothing can reach them
+    pub dead_instructions: usize,
+    /// Jumps pointed straight at the end of a `Jump` chain
+    pub threaded_jumps: usize,
+    /// Jumps to the instruction right after them that were removed
+    pub removed_jumps: usize,
+    /// Pairs of instructions replaced with a single superinstruction
+    pub fused_instructions: usize,
+}
+
+
+/// Run all of the passes on `state`
+pub fn optimize(state: &mut ConvertState)->OptimizeStats {
+    let mut stats = OptimizeStats::default();
+
+    check_placeholders(state);
+
+    stats.folded_branches = fold_constant_branches(state);
+    stats.dead_instructions = eliminate_dead_code(state);
+    stats.threaded_jumps = thread_jumps(state);
+    stats.removed_jumps = remove_jumps_to_next(state);
+    stats.fused_instructions = fuse_instructions(state);
+
+    return stats;
+}
+
+fn jump_target(ins: &Instruction)->Option<InstructionId> {
+    match ins {
+        Instruction::Jump(id)|
+            Instruction::JumpIfTrue(id)|
+            Instruction::JumpIfFalse(id)=>Some(*id),
+        _=>None,
+    }
+}
+
+/// The order indices execution can continue at after the instruction at `index`, not counting
+/// function bodies
+fn successors(order: &FxIndexSet<InstructionId>, ins: &Instruction, index: usize)->Vec<usize> {
+    match ins {
+        Instruction::Jump(target)=>order.get_index_of(target).into_iter().collect(),
+        Instruction::JumpIfTrue(target)|
+            Instruction::JumpIfFalse(target)=>{
+                let mut out = vec![index + 1];
+                out.extend(order.get_index_of(target));
+                out
+            },
+        Instruction::Return|
+            Instruction::ReturnModule|
+            Instruction::Exit=>Vec::new(),
+        _=>vec![index + 1],
+    }
+}
+
+/// Where execution can start: module starts and function bodies
+fn entry_points(state: &ConvertState)->Vec<InstructionId> {
+    let mut out = Vec::new();
+    for id in state.module_ids() {
+        out.push(state.modules.get(id).start_ins);
+    }
+    for id in state.fn_ids() {
+        let Some(f) = state.fns.get(id) else {continue};
+        out.extend(f.sig.bodies().into_iter().map(|(_, body_ptr)|body_ptr));
+    }
+
+    return out;
+}
+
+/// Point everything that jumps to `from` at `to` instead
+fn retarget_jumps(state: &mut ConvertState, from: InstructionId, to: InstructionId) {
+    let ids = state.instructions.ins_order().iter().copied().collect::<Vec<_>>();
+    for id in ids {
+        match state.instructions.get_mut(id) {
+            Instruction::Jump(target)|
+                Instruction::JumpIfTrue(target)|
+                Instruction::JumpIfFalse(target)=>if *target == from {
+                    *target = to;
+                },
+                _=>{},
+        }
+    }
+}
+
+/// `cond` pushes `Exit` as a placeholder for its jumps and fills them in later. Functions never
+/// exit, so one left in a function body means we miscompiled something.
+fn check_placeholders(state: &ConvertState) {
+    let order = state.instructions.ins_order();
+    let raw = state.instructions.raw_instructions();
+
+    for fn_id in state.fn_ids() {
+        let Some(f) = state.fns.get(fn_id) else {continue};
+
+        let mut seen = vec![false; order.len()];
+        let mut todo = f.sig.bodies()
+            .into_iter()
+            .filter_map(|(_, body_ptr)|order.get_index_of(&body_ptr))
+            .collect::<Vec<_>>();
+        while let Some(index) = todo.pop() {
+            if index >= order.len() || seen[index] {continue}
+            seen[index] = true;
+
+            let ins = &raw[order[index].inner()];
+            assert!(
+                !matches!(ins, Instruction::Exit),
+                "Placeholder `Exit` at {:?} survived in the body of {:?}",
+                order[index],
+                fn_id,
+            );
+
+            todo.extend(successors(order, ins, index));
+        }
+    }
+}
+
+/// Resolve `Bool` followed by a conditional jump, like a `cond` arm with `true` as its condition.
+/// A jump that is always taken becomes a `Jump`, and one that never is gets removed along with the
+/// `Bool`. Returns how many were folded.
+fn fold_constant_branches(state: &mut ConvertState)->usize {
+    let entries = entry_points(state).into_iter().collect::<FxIndexSet<_>>();
+    let mut targets = state.instructions.ins_order()
+        .iter()
+        .filter_map(|id|jump_target(&state.instructions.raw_instructions()[id.inner()]))
+        .collect::<FxIndexSet<_>>();
+
+    let ids = state.instructions.ins_order().iter().copied().collect::<Vec<_>>();
+    let mut removed = FxIndexSet::default();
+    let mut count = 0;
+
+    let mut i = 0;
+    while i + 1 < ids.len() {
+        let (bool_id, jump_id) = (ids[i], ids[i + 1]);
+        i += 1;
+
+        let raw = state.instructions.raw_instructions();
+        let (value, target, jump_if) = match (&raw[bool_id.inner()], &raw[jump_id.inner()]) {
+            (Instruction::Bool(value), Instruction::JumpIfTrue(target))=>(*value, *target, true),
+            (Instruction::Bool(value), Instruction::JumpIfFalse(target))=>(*value, *target, false),
+            _=>continue,
+        };
+
+        // something else can land between the two, so the jump doesn't always see our `Bool`
+        if targets.contains(&jump_id) || entries.contains(&jump_id) {continue}
+        // we can't move where a function or module starts
+        if entries.contains(&bool_id) {continue}
+
+        if value == jump_if {
+            state.instructions.set(jump_id, Instruction::Jump(target));
+            retarget_jumps(state, bool_id, jump_id);
+            removed.insert(bool_id);
+            targets.insert(jump_id);
+        } else {
+            let Some(next) = ids.get(i + 1).copied() else {continue};
+
+            retarget_jumps(state, bool_id, next);
+            removed.insert(bool_id);
+            removed.insert(jump_id);
+            targets.insert(next);
+        }
+        targets.swap_remove(&bool_id);
+
+        count += 1;
+        // the jump was handled with the `Bool`
+        i += 1;
+    }
+
+    state.instructions.retain_order(|id|!removed.contains(&id));
+
+    return count;
+}
+
+/// Remove everything that can't be reached from a module start or the body of a function that is
+/// created somewhere reachable. Returns how many instructions were removed.
+fn eliminate_dead_code(state: &mut ConvertState)->usize {
+    let order = state.instructions.ins_order();
+    let raw = state.instructions.raw_instructions();
+
+    let mut reachable = vec![false; order.len()];
+    let mut todo = Vec::new();
+    for id in state.module_ids() {
+        todo.extend(order.get_index_of(&state.modules.get(id).start_ins));
+    }
+
+    while let Some(index) = todo.pop() {
+        if index >= order.len() || reachable[index] {continue}
+        reachable[index] = true;
+
+        let ins = &raw[order[index].inner()];
+        if let Instruction::Func(id) = ins {
+            if let Some(f) = state.fns.get(*id) {
+                for (_, body_ptr) in f.sig.bodies() {
+                    todo.extend(order.get_index_of(&body_ptr));
+                }
+            }
+        }
+        todo.extend(successors(order, ins, index));
+    }
+
+    let dead = reachable.iter().filter(|r|!**r).count();
+    if dead > 0 {
+        let mut index = 0;
+        state.instructions.retain_order(|_|{
+            index += 1;
+            reachable[index - 1]
+        });
+    }
+
+    return dead;
+}
+
+/// Point jumps that land on an unconditional `Jump` at wherever that chain ends. Returns how many
+/// were changed.
+fn thread_jumps(state: &mut ConvertState)->usize {
+    let ids = state.instructions.ins_order().iter().copied().collect::<Vec<_>>();
+    let mut count = 0;
+
+    for id in ids {
+        let Some(target) = jump_target(&state.instructions.raw_instructions()[id.inner()]) else {continue};
+
+        // follow the chain, stopping if it loops
+        let mut seen = FxIndexSet::default();
+        let mut end = target;
+        while let Instruction::Jump(next) = &state.instructions.raw_instructions()[end.inner()] {
+            if !seen.insert(end) {break}
+            end = *next;
+        }
+        if end == target {continue}
+
+        match state.instructions.get_mut(id) {
+            Instruction::Jump(t)|
+                Instruction::JumpIfTrue(t)|
+                Instruction::JumpIfFalse(t)=>*t = end,
+            _=>unreachable!(),
+        }
+        count += 1;
+    }
+
+    return count;
+}
+
+/// Remove `Jump`s to the instruction right after them. Returns how many were removed.
+fn remove_jumps_to_next(state: &mut ConvertState)->usize {
+    let entries = entry_points(state).into_iter().collect::<FxIndexSet<_>>();
+    let mut found = Vec::new();
+
+    let mut windows = state.instructions.cursor().windows(2);
+    while let Some(window) = windows.next_window() {
+        let &Instruction::Jump(target) = window[0] else {continue};
+        let (id, next) = (windows.ids()[0], windows.ids()[1]);
+        if next != target || entries.contains(&id) {continue}
+
+        found.push((id, target));
+    }
+
+    let mut removed = FxIndexSet::default();
+    for (id, target) in found {
+        retarget_jumps(state, id, target);
+        removed.insert(id);
+    }
+
+    state.instructions.retain_order(|id|!removed.contains(&id));
+
+    return removed.len();
+}
+
+/// Replace common instruction pairs with a superinstruction so they only take one dispatch. The
+/// first instruction of the pair is overwritten and the second is removed from the execution
+/// order. Returns how many pairs were fused.
+fn fuse_instructions(state: &mut ConvertState)->usize {
+    let entries = entry_points(state).into_iter().collect::<FxIndexSet<_>>();
+    let targets = state.instructions.ins_order()
+        .iter()
+        .filter_map(|id|jump_target(&state.instructions.raw_instructions()[id.inner()]))
+        .collect::<FxIndexSet<_>>();
+
+    let mut fused = Vec::new();
+    let mut removed = FxIndexSet::default();
+
+    let can_remove = |id: &InstructionId|!(targets.contains(id) || entries.contains(id));
+
+    let mut cursor = state.instructions.cursor();
+    while let Some(first_ins) = cursor.next() {
+        let first = cursor.cur_ins_id().unwrap();
+        let (Some(second_ins), Some(second)) = (cursor.peek(), cursor.next_ins_id()) else {break};
+
+        // calls get their callee after ending the argument scope. A global doesn't care about the
+        // scope, so we can get it after `EndScope` and fuse it with the call.
+        if let (Instruction::GetVar(slot), Instruction::EndScope(slots), Some(Instruction::Call(count)), Some(third)) = (first_ins, second_ins, cursor.peek_n(1), cursor.peek_id_n(1)) {
+            if slot.global && can_remove(&second) && can_remove(&third) {
+                fused.push((first, Instruction::EndScope(*slots)));
+                fused.push((second, Instruction::GetVarCall(*slot, *count)));
+                removed.insert(third);
+                cursor.nth(1);
+                continue;
+            }
+        }
+
+        // execution could start at the second one, so it has to stay
+        if !can_remove(&second) {continue}
+
+        let ins = match (first_ins, second_ins) {
+            (Instruction::GetVar(slot), Instruction::Call(count))=>Instruction::GetVarCall(*slot, *count),
+            (Instruction::Number(n), Instruction::SetVar(slot))=>Instruction::NumberSetVar(*n, *slot),
+            _=>continue,
+        };
+
+        fused.push((first, ins));
+        removed.insert(second);
+        cursor.next();
+    }
+
+    for (id, ins) in fused {
+        state.instructions.set(id, ins);
+    }
+    state.instructions.retain_order(|id|!removed.contains(&id));
+
+    return removed.len();
+}
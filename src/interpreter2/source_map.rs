@@ -0,0 +1,121 @@
+//! Where each instruction came from, so a runtime error can point at the source. Only the top
+//! level forms of each file are tracked, so an instruction points at the start of the form it was
+//! converted from. A function's instructions point at the form that defined it.
+//!
+//! States converted with `convert_mapped` have one, and bytecode files keep it as an optional
+//! section. A loaded file only decodes it the first time something asks for it, so a program that
+//! runs without errors never pays for it.
+
+
+use std::{
+    cell::OnceCell,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use crate::{
+    error::SourceLocation,
+    source::read_source,
+};
+use super::{
+    ast::InstructionId,
+    bytecode::read_source_map,
+    FxIndexMap,
+};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// `None` if it wasn't embedded with `--embed-sources`
+    pub contents: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// The index of the file in `SourceMap::files`
+    pub file: usize,
+    /// 1-based
+    pub line: usize,
+    /// 1-based and counted in chars
+    pub column: usize,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub files: Vec<SourceFile>,
+    pub locations: FxIndexMap<InstructionId, Location>,
+}
+impl SourceMap {
+    /// Add a file and return its index for `Location::file`
+    pub fn add_file(&mut self, path: &Path, contents: &str)->usize {
+        self.files.push(SourceFile {
+            path: path.to_path_buf(),
+            contents: Some(contents.to_string()),
+        });
+
+        return self.files.len() - 1;
+    }
+
+    /// Forget the contents of every file, so only their paths are saved
+    pub fn strip_contents(&mut self) {
+        for file in self.files.iter_mut() {
+            file.contents = None;
+        }
+    }
+
+    /// Where `id` came from, with the file's contents if they were embedded or the file can still
+    /// be read. A file that changed since it was compiled shows the wrong line.
+    pub fn locate(&self, id: InstructionId)->Option<SourceLocation> {
+        let location = self.locations.get(&id)?;
+        let file = self.files.get(location.file)?;
+        let source = file.contents.clone()
+            .or_else(||read_source(&file.path).ok());
+
+        return Some(SourceLocation {
+            path: file.path.clone(),
+            line: location.line,
+            column: location.column,
+            source,
+        });
+    }
+}
+
+
+/// A `SourceMap` that might still be in its serialized form
+#[derive(Debug, Default)]
+pub struct LazySourceMap {
+    /// The section from a bytecode file, decoded by `get`
+    packed: Option<Vec<u8>>,
+    map: OnceCell<Option<SourceMap>>,
+}
+impl LazySourceMap {
+    pub fn new(map: SourceMap)->Self {
+        LazySourceMap {
+            packed: None,
+            map: OnceCell::from(Some(map)),
+        }
+    }
+
+    pub fn packed(bytes: Vec<u8>)->Self {
+        LazySourceMap {
+            packed: Some(bytes),
+            map: OnceCell::new(),
+        }
+    }
+
+    /// The map, decoding it first if it is still packed. `None` if there isn't one or it doesn't
+    /// decode. The checksum covers it, so that only happens if the writer had a bug.
+    pub fn get(&self)->Option<&SourceMap> {
+        self.map
+            .get_or_init(||self.packed.as_deref().and_then(|bytes|read_source_map(bytes).ok()))
+            .as_ref()
+    }
+
+    pub fn get_mut(&mut self)->Option<&mut SourceMap> {
+        self.get();
+        self.map.get_mut()?.as_mut()
+    }
+}
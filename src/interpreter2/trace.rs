@@ -0,0 +1,65 @@
+//! `--trace` prints every instruction to stderr as it runs, along with the value it pushed. The
+//! dispatch loop only checks an `Option` when tracing is off.
+
+
+use super::{
+    ast::{
+        ConvertState,
+        FnId,
+        Instruction,
+    },
+    debug::fn_name,
+};
+
+
+/// Traced values are cut off after this many chars
+pub const TRACE_VALUE_WIDTH: usize = 40;
+
+
+pub struct Trace {
+    /// Only trace while this function is running, including whatever it calls
+    filter: Option<String>,
+    /// The call depth the filtered function was entered at
+    entered_at: Option<usize>,
+}
+impl Trace {
+    pub fn new(filter: Option<String>)->Self {
+        Trace {
+            filter,
+            entered_at: None,
+        }
+    }
+
+    /// Whether to trace the instruction about to run. `func` is the function running at `depth`.
+    pub fn wants(&mut self, depth: usize, func: Option<FnId>, state: &ConvertState)->bool {
+        let Some(filter) = &self.filter else {return true};
+        let is_filtered = func.is_some_and(|id|fn_name(id, state) == filter);
+
+        match self.entered_at {
+            // it returned, or tail called something else
+            Some(at) if depth < at || (depth == at && !is_filtered)=>self.entered_at = None,
+            Some(_)=>return true,
+            None=>{},
+        }
+
+        if is_filtered {
+            self.entered_at = Some(depth);
+        }
+
+        return self.entered_at.is_some();
+    }
+}
+
+
+/// Whether `ins` leaves a value on the stack when it is done. Calls only do if they didn't go into
+/// a new frame.
+pub fn pushes_value(ins: &Instruction)->bool {
+    use Instruction as I;
+
+    match ins {
+        I::Func(_)|I::GetVar(_)|I::Field(_)=>true,
+        I::Number(_)|I::Float(_)|I::String(_)|I::Char(_)|I::Bool(_)|I::Byte(_)|I::Ident(_)|I::None=>true,
+        I::Call(_)|I::TailCall(_)|I::CallBuiltin(..)|I::GetVarCall(..)|I::Return=>true,
+        _=>false,
+    }
+}
@@ -0,0 +1,143 @@
+//! Sanity checks on the converted instructions before we run them. A bad jump would otherwise
+//! panic deep inside `InstructionIter::jump` with no hint of where it came from, and a scope that
+//! is never ended shifts the var slots of everything after it without any error at all.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use rustc_hash::FxHashMap;
+use super::ast::*;
+
+
+/// Check that every jump in the execution order points at an instruction that is also in the
+/// execution order, then that scopes are balanced. See `verify_scopes`.
+pub fn verify(state: &ConvertState)->Result<()> {
+    let order = state.instructions.ins_order();
+    let raw = state.instructions.raw_instructions();
+
+    for (index, id) in order.iter().enumerate() {
+        let Some(ins) = raw.get(id.inner()) else {
+            bail!("Instruction {index} in the execution order has the invalid id {id:?}");
+        };
+
+        let target = match ins {
+            Instruction::Jump(target)|
+                Instruction::JumpIfTrue(target)|
+                Instruction::JumpIfFalse(target)=>*target,
+            _=>continue,
+        };
+
+        if !target.is_valid() || target.inner() >= raw.len() {
+            bail!("{ins:?} at {id:?} jumps to an instruction that doesn't exist");
+        }
+        if !order.contains(&target) {
+            bail!("{ins:?} at {id:?} jumps to an instruction that was removed from the execution order");
+        }
+    }
+
+    return verify_scopes(state);
+}
+
+
+/// The scopes open at an instruction, innermost last. The first holds the function's captures
+/// and params, which have no `Scope` of their own.
+#[derive(Debug, Clone, PartialEq)]
+struct Scopes(Vec<usize>);
+impl Scopes {
+    /// How many local slots can be used
+    fn live(&self)->usize {
+        self.0.iter().sum()
+    }
+}
+
+/// Walk every path from every module start and function body, tracking which scopes are open.
+///
+/// - Each `EndScope(n)` has to end a `Scope(n)`.
+/// - Paths that meet have to have the same scopes open.
+/// - A module has to end every scope it starts. A function doesn't, since returning drops them.
+/// - Local slots have to be in a scope that is open.
+pub fn verify_scopes(state: &ConvertState)->Result<()> {
+    let mut entries = Vec::new();
+    for id in state.module_ids() {
+        entries.push((state.modules.get(id).start_ins, 0));
+    }
+    for id in state.fn_ids() {
+        let Some(f) = state.fns.get(id) else {continue};
+        for (params, body_ptr) in f.sig.bodies() {
+            let locals = f.captures.len() + params.items.len() + params.remainder.iter().count();
+            entries.push((body_ptr, locals));
+        }
+    }
+
+    let order = state.instructions.ins_order();
+    let raw = state.instructions.raw_instructions();
+    let mut seen: FxHashMap<usize, Scopes> = FxHashMap::default();
+    let mut todo = Vec::new();
+    for (id, locals) in entries {
+        let Some(index) = order.get_index_of(&id) else {
+            bail!("Code starting at {id:?} isn't in the execution order");
+        };
+        todo.push((index, Scopes(vec![locals])));
+    }
+
+    while let Some((index, mut scopes)) = todo.pop() {
+        if let Some(prev) = seen.get(&index) {
+            if *prev != scopes {
+                let id = order[index];
+                bail!("Paths meet at {id:?} with different scopes open: {:?} and {:?}", prev.0, scopes.0);
+            }
+            continue;
+        }
+        seen.insert(index, scopes.clone());
+
+        let id = order[index];
+        let ins = &raw[id.inner()];
+        match ins {
+            Instruction::Scope(count)=>scopes.0.push(*count),
+            Instruction::EndScope(count)=>match scopes.0.len() {
+                0|1=>bail!("{ins:?} at {id:?} ends a scope that was never started"),
+                _=>{
+                    let started = scopes.0.pop().unwrap();
+                    if started != *count {
+                        bail!("{ins:?} at {id:?} ends a `Scope({started})`");
+                    }
+                },
+            },
+            Instruction::GetVar(slot)|
+                Instruction::SetVar(slot)|
+                Instruction::SetPath(slot, _)|
+                Instruction::GetVarCall(slot, _)|
+                Instruction::NumberSetVar(_, slot)=>if !slot.global && slot.id >= scopes.live() {
+                    bail!("{ins:?} at {id:?} uses local slot {}, but only {} are in scope", slot.id, scopes.live());
+                },
+            _=>{},
+        }
+
+        let next = match ins {
+            Instruction::Exit|Instruction::ReturnModule=>{
+                if scopes.0.len() > 1 {
+                    bail!("{ins:?} at {id:?} is reached with {} scopes still open", scopes.0.len() - 1);
+                }
+                continue;
+            },
+            Instruction::Return|Instruction::TailCall(_)=>continue,
+            Instruction::Jump(target)=>{
+                todo.push((order.get_index_of(target).unwrap(), scopes));
+                continue;
+            },
+            Instruction::JumpIfTrue(target)|Instruction::JumpIfFalse(target)=>{
+                todo.push((order.get_index_of(target).unwrap(), scopes.clone()));
+                index + 1
+            },
+            _=>index + 1,
+        };
+        if next >= order.len() {
+            bail!("{ins:?} at {id:?} runs off the end of the instructions");
+        }
+        todo.push((next, scopes));
+    }
+
+    return Ok(());
+}
@@ -8,21 +8,34 @@ pub use StartOrEnd::*;
 
 #[derive(Debug, Logos, PartialEq)]
 #[logos(skip "[ \t\r\n]")]
+#[logos(subpattern ident_start = r"[\p{XID_Start}_\-+*<>=!?%&|^~$@]")]
+#[logos(subpattern ident_continue = r"[\p{XID_Continue}\-+*<>=!?%&|^~$@'#:]")]
 pub enum Token<'a> {
-    /// all but whitespace, (), [], `#`, `'`, and `"`
-    #[regex("[^/:;\\\\ .\t\r\n()\\[\\]{}\"'#0-9][^/ .\t\r\n()\\[\\]{}\"]*")]
+    /// Starts with a unicode `XID_Start` char, `_`, or one of `-+*<>=!?%&|^~$@`, and goes on with
+    /// `XID_Continue` chars, the same punctuation, and `'#:`. So `naïve-π`, `x'` and `key:val` are
+    /// idents, but emoji aren't. `/` separates paths, so it can only be in the division ops.
+    #[regex("(?&ident_start)(?&ident_continue)*")]
+    #[token("/", |l|l.slice())]
+    #[token("/=", |l|l.slice())]
     Ident(&'a str),
 
-    #[regex("[^/:;\\\\ .\t\r\n()\\[\\]{}\"'#0-9][^/ .\t\r\n()\\[\\]{}\"]*/", parse_path)]
+    /// Only the first segment has to look like an ident. The rest can be list indices like `-1`.
+    #[regex("(?&ident_start)(?&ident_continue)*(/(?&ident_continue)*)+", parse_path)]
     Path(Vec<&'a str>),
 
-    #[regex("\\.[^ .\t\r\n()\\[\\]{}\"]+", strip_first)]
+    #[regex("\\.(?&ident_continue)+", strip_first)]
     DotIdent(&'a str),
 
-    #[regex("[0-9][0-9_]*", number)]
-    Number(i64),
+    /// A leading `-` is part of the number, but `-` and `-x` are still idents. `None` if it
+    /// doesn't fit in an `i64`, which the parser reports.
+    #[regex("-?[0-9][0-9_]*", number, priority = 3)]
+    Number(Option<i64>),
 
-    #[regex("[0-9][0-9_]*\\.[0-9][0-9_]*", float)]
+    #[regex("-?[0-9][0-9_]*\\.[0-9][0-9_]*([eE][+-]?[0-9]+)?", float, priority = 3)]
+    #[regex("-?[0-9][0-9_]*[eE][+-]?[0-9]+", float, priority = 3)]
+    #[token("#inf", |_|f64::INFINITY)]
+    #[token("#-inf", |_|f64::NEG_INFINITY)]
+    #[token("#nan", |_|f64::NAN)]
     Float(f64),
 
     #[token("\"", string)]
@@ -35,7 +48,7 @@ pub enum Token<'a> {
     #[token("\\space", |_|' ')]
     #[token("\\newline", |_|'\n')]
     #[token("\\tab", |_|'\n')]
-    #[token("\\", parse_char)]
+    #[regex("\\\\(.|\n)", parse_char)]
     Char(char),
 
     #[token("'")]
@@ -65,6 +78,12 @@ pub enum Token<'a> {
     #[regex(";[^\n]*", strip_first, priority=10)]
     Comment(&'a str),
 
+    /// Anything nothing else matches, like a lone `#` or a `\` at the end. Every callback above
+    /// accepts what its regex matches, so the lexer never errors and the parser reports these
+    /// instead.
+    #[regex(".", |l|l.slice(), priority = 0)]
+    Invalid(&'a str),
+
     EOF,
 }
 impl<'a> TokenTrait for Token<'a> {
@@ -78,23 +97,27 @@ pub enum StartOrEnd {
 }
 
 
-fn number<'a>(l: &mut Lexer<'a, Token<'a>>)->Option<i64> {
-    l.slice()
+/// Always `Some`. The inner `Option` is the token's, so a huge literal is a parse error instead
+/// of a lexer error.
+fn number<'a>(l: &mut Lexer<'a, Token<'a>>)->Option<Option<i64>> {
+    let n = l.slice()
         .chars()
         .filter(|c|match c {
-            '0'..='9'=>true,
+            '0'..='9'|'-'=>true,
             _=>false,
         })
         .collect::<String>()
         .parse::<i64>()
-        .ok()
+        .ok();
+
+    return Some(n);
 }
 
 fn float<'a>(l: &mut Lexer<'a, Token<'a>>)->Option<f64> {
     l.slice()
         .chars()
         .filter(|c|match c {
-            '0'..='9'|'.'=>true,
+            '0'..='9'|'.'|'-'|'+'|'e'|'E'=>true,
             _=>false,
         })
         .collect::<String>()
@@ -140,74 +163,12 @@ fn strip_first<'a>(l: &mut Lexer<'a, Token<'a>>)->&'a str {
 }
 
 fn parse_char<'a>(l: &mut Lexer<'a, Token<'a>>)->Option<char> {
-    let c = l.remainder().chars().next()?;
-    l.bump(c.len_utf8());
-    return Some(c);
+    l.slice()[1..].chars().next()
 }
 
 fn parse_path<'a>(l: &mut Lexer<'a, Token<'a>>)->Vec<&'a str> {
-    let mut out = Vec::new();
-    let len = l.slice().len();
-    out.push(&l.slice()[..(len - 1)]);
-
-    let mut slice_start = 0;
-    let mut count = 0;
-    for c in l.remainder().chars() {
-        if slice_start == count {
-            match c {
-                '/'=>panic!("Cannot have a path section of zero length!"),
-                ':'|
-                    ';'|
-                    '\\'|
-                    ' '|
-                    '.'|
-                    '\t'|
-                    '\r'|
-                    '\n'|
-                    '('|
-                    ')'|
-                    '['|
-                    ']'|
-                    '{'|
-                    '}'|
-                    '"'|
-                    '\''|
-                    '#'|
-                    '0'..='9'=>break,
-                _=>{},
-            }
-        } else {
-            match c {
-                '/'=>{
-                    out.push(&l.remainder()[slice_start..count]);
-                    slice_start = count + 1;
-                },
-                ':'|
-                    ';'|
-                    '\\'|
-                    ' '|
-                    '.'|
-                    '\t'|
-                    '\r'|
-                    '\n'|
-                    '('|
-                    ')'|
-                    '['|
-                    ']'|
-                    '{'|
-                    '}'|
-                    '"'|
-                    '\''=>break,
-                _=>{},
-            }
-        }
-        count += c.len_utf8()
-    }
-    out.push(&l.remainder()[slice_start..count]);
-
-    l.bump(count);
-
-    return out;
+    // an empty segment like in `a//b` is kept, and the parser reports it
+    l.slice().split('/').collect()
 }
 
 fn parse_byte_hex<'a>(lex: &mut Lexer<'a, Token<'a>>)->Option<u8> {
@@ -0,0 +1,301 @@
+#![deny(unsafe_code)]
+
+
+//! Bytecode interpreter for Clinery's SimpleLisp language. Embed it with an [`Engine`]:
+//!
+//! ```
+//! use simple_lisp::Engine;
+//!
+//! let mut engine = Engine::new();
+//! let value = engine.eval_str("(+ 1 (* 2 3))").unwrap();
+//! assert_eq!(value.as_i64(), Some(7));
+//! ```
+//!
+//! Everything here is single threaded. An `Engine` and its `Value`s have to stay on the thread
+//! that created them, so neither is `Send` or `Sync`.
+//!
+//! We deny all unsafe code EXCEPT in the garbage collection logic and behind-the-scenes data
+//! handling logic which needs to work with raw pointers. While we could do a deny-unsafe GC, it is
+//! more performant and MUCH easier to just have `DataRef` be a pointer to an object so we can
+//! access it any time we want instead of going through the collector's list of objects.
+
+
+use std::{
+    fmt::Display,
+    time::Duration,
+};
+use diagnostic::{
+    Diagnostic,
+    Severity,
+};
+use gc_config::GcConfig;
+use output::Stdout;
+use error::{
+    LispError,
+    Located,
+    StackTrace,
+};
+
+
+pub use engine::{
+    Ctx,
+    Engine,
+    Value,
+};
+pub use convert::{
+    FromLisp,
+    IntoLisp,
+};
+pub use host::HostObject;
+pub use capabilities::Capabilities;
+pub use interpreter::ArgCount;
+pub use output::{
+    Captured,
+    Output,
+};
+
+
+pub mod error;
+pub mod gc_config;
+pub mod capabilities;
+pub mod output;
+mod engine;
+mod convert;
+mod host;
+mod lexer;
+mod suggest;
+mod prelude;
+
+// the binary needs these, but they aren't meant for embedders
+#[doc(hidden)]
+pub mod parser;
+#[doc(hidden)]
+pub mod ast;
+#[doc(hidden)]
+pub mod interpreter;
+#[doc(hidden)]
+pub mod interpreter2;
+#[doc(hidden)]
+pub mod repl;
+#[doc(hidden)]
+pub mod diagnostic;
+#[doc(hidden)]
+pub mod color;
+#[doc(hidden)]
+pub mod format;
+#[doc(hidden)]
+pub mod bench;
+#[doc(hidden)]
+pub mod difftest;
+#[doc(hidden)]
+pub mod testing;
+#[doc(hidden)]
+pub mod fuzz;
+#[doc(hidden)]
+pub mod budget;
+#[doc(hidden)]
+pub mod truthy;
+#[doc(hidden)]
+pub mod data_path;
+#[doc(hidden)]
+pub mod numeric;
+#[doc(hidden)]
+pub mod opcode_stats;
+#[doc(hidden)]
+pub mod stats_json;
+#[doc(hidden)]
+pub mod source;
+#[doc(hidden)]
+pub mod terminal;
+
+
+/// Deep enough for any reasonable recursion, but shallow enough that we don't overflow the Rust
+/// stack first
+pub const DEFAULT_MAX_STACK_DEPTH: usize = 4000;
+
+
+/// Everything that goes into creating an interpreter. The CLI fills this in from its flags.
+#[derive(Debug, Copy, Clone)]
+pub struct InterpreterOptions {
+    pub gc_config: GcConfig,
+    pub max_stack_depth: usize,
+    pub max_instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+    /// Load the prelude before the program
+    pub prelude: bool,
+    /// What natives may do. Only V1 has natives that need any.
+    pub capabilities: Capabilities,
+    /// Whether `(breakpoint)` pauses the V1 interpreter
+    pub breakpoints: bool,
+    /// Count how many times each kind of instruction runs
+    pub opcode_stats: bool,
+    /// Branching on anything but a bool or `none` is an error
+    pub strict_bool: bool,
+}
+impl Default for InterpreterOptions {
+    fn default()->Self {
+        InterpreterOptions {
+            gc_config: GcConfig::default(),
+            max_stack_depth: DEFAULT_MAX_STACK_DEPTH,
+            max_instructions: None,
+            timeout: None,
+            prelude: true,
+            capabilities: Capabilities::default(),
+            breakpoints: true,
+            opcode_stats: false,
+            strict_bool: false,
+        }
+    }
+}
+impl InterpreterOptions {
+    pub fn new_interpreter(&self, state: &mut interpreter::ast::ConvertState)->interpreter::Interpreter {
+        self.new_interpreter_with_output(state, Box::new(Stdout))
+    }
+
+    pub fn new_interpreter_with_output(&self, state: &mut interpreter::ast::ConvertState, output: Box<dyn Output>)->interpreter::Interpreter {
+        let mut interpreter = interpreter::Interpreter::new(state, self.gc_config, self.max_stack_depth, self.max_instructions, output);
+        interpreter.set_timeout(self.timeout);
+        interpreter.set_capabilities(self.capabilities);
+        interpreter.set_breakpoints(self.breakpoints);
+        interpreter.set_opcode_stats(self.opcode_stats);
+        interpreter.set_strict_bool(self.strict_bool);
+
+        return interpreter;
+    }
+
+    pub fn new_interpreter2(&self, state: &mut interpreter2::ast::ConvertState)->interpreter2::Interpreter {
+        self.new_interpreter2_with_output(state, Box::new(Stdout))
+    }
+
+    pub fn new_interpreter2_with_output(&self, state: &mut interpreter2::ast::ConvertState, output: Box<dyn Output>)->interpreter2::Interpreter {
+        let mut interpreter = interpreter2::Interpreter::new(state, self.gc_config, self.max_stack_depth, self.max_instructions, output);
+        interpreter.set_timeout(self.timeout);
+        interpreter.set_opcode_stats(self.opcode_stats);
+        interpreter.set_strict_bool(self.strict_bool);
+
+        return interpreter;
+    }
+}
+
+
+/// Print a conversion warning, or record it if we are collecting diagnostics
+pub fn warning_trace(warning: anyhow::Error, source: &str, file_path: impl Display) {
+    if diagnostic::is_collecting() {
+        diagnostic::record(Diagnostic::new(&warning, source, file_path, Severity::Warning));
+    } else {
+        let color = color::enabled();
+        println!(
+            "{} ({}): {warning:#}",
+            color::paint(color, color::WARNING, "Warning"),
+            color::paint(color, color::LOCATION, file_path),
+        );
+    }
+}
+
+/// Print `err` with the source around where it happened, or record it if we are collecting
+/// diagnostics
+pub fn error_trace(err: anyhow::Error, source: &str, file_path: impl Display) {
+    // a program that failed in raw mode would print this on one long staircase line
+    terminal::restore();
+
+    let err = match err.downcast::<StackTrace>() {
+        Ok(trace)=>{
+            let collecting = diagnostic::is_collecting();
+            error_trace(trace.error, source, file_path);
+            if !collecting {
+                print_stack(&trace.frames, color::enabled());
+            }
+            return;
+        },
+        Err(err)=>err,
+    };
+
+    // a source map found where it happened, which beats not knowing
+    let (err, location) = match err.downcast::<Located>() {
+        Ok(located)=>(located.error, Some(located.location)),
+        Err(err)=>(err, None),
+    };
+
+    if let Some(LispError::Module{..}) = err.downcast_ref::<LispError>() {
+        let Ok(LispError::Module{imports, path, source, error}) = err.downcast::<LispError>() else {unreachable!()};
+        if !diagnostic::is_collecting() {
+            for import in imports.iter() {
+                println!("While loading {import}");
+            }
+        }
+
+        // show the error with the module's source instead of ours
+        match path {
+            Some(path)=>error_trace(error, &source, path.display()),
+            None=>error_trace(error, &source, file_path),
+        }
+        return;
+    }
+
+    let mut chain = err.chain().rev().peekable();
+    let Some(root_cause) = chain.next() else {unreachable!("Error has no root cause!")};
+
+    let color = color::enabled();
+    match root_cause.downcast_ref::<LispError>() {
+        _ if diagnostic::is_collecting()=>{
+            let mut diag = Diagnostic::new(&err, source, file_path, Severity::Error);
+            if let Some(location) = location {
+                diag.file = location.path.display().to_string();
+                diag.line = Some(location.line);
+                diag.column = Some(location.column);
+            }
+            diagnostic::record(diag);
+            return;
+        },
+        Some(LispError::Parse(serr)|LispError::Incomplete(serr)) if color=>{
+            println!("{}", color::snippet(color, serr, source, file_path, serr.span.start));
+        },
+        Some(LispError::Parse(serr)|LispError::Incomplete(serr))=>{
+            serr.eprint_with_source(source, file_path);
+            println!();
+        },
+        _=>match location {
+            Some(l)=>println!("{}", color::snippet_at(color, root_cause, l.source.as_deref(), l.path.display(), l.line, l.column)),
+            None=>println!("{}: {root_cause}", color::paint(color, color::ERROR, "Error")),
+        },
+    }
+
+    if chain.peek().is_some() {
+        println!("{}", color::paint(color, color::TRACE_HEADER, "Trace:"));
+        print_tree(chain.map(|e|e.to_string()).collect(), color);
+    }
+}
+
+/// Stacks deeper than this only show the calls at each end
+const MAX_STACK_FRAMES: usize = 20;
+
+/// Print the calls that were running when an error happened, innermost first
+fn print_stack(frames: &[error::TraceFrame], color: bool) {
+    let mut lines = frames.iter()
+        .map(|f|f.to_string())
+        .collect::<Vec<_>>();
+    if lines.len() > MAX_STACK_FRAMES {
+        let keep = MAX_STACK_FRAMES / 2;
+        let omitted = lines.len() - MAX_STACK_FRAMES;
+        lines.splice(keep..lines.len() - keep, [format!("... {omitted} frames omitted")]);
+    }
+
+    println!("{}", color::paint(color, color::TRACE_HEADER, "Stack:"));
+    print_tree(lines, color);
+}
+
+/// Print `lines` as a tree where each one is nested under the last. With `color` the lines are
+/// dimmed so they stand apart from the error.
+fn print_tree(lines: Vec<String>, color: bool) {
+    let last = lines.len() - 1;
+    for (i, line) in lines.into_iter().enumerate() {
+        let branch = if i == last {
+            "└─ "
+        } else if i == 0 {
+            " ┌ "
+        } else {
+            "└┬ "
+        };
+        println!("{}{}", " ".repeat(i), color::paint(color, color::TRACE, format!("{branch}{line}")));
+    }
+}
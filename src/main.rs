@@ -1,33 +1,82 @@
 #![deny(unsafe_code)]
 
 
-//! We deny all unsafe code EXCEPT in the garbage collection logic and behind-the-scenes data
-//! handling logic which needs to work with raw pointers. While we could do a deny-unsafe GC, it is
-//! more performant and MUCH easier to just have `DataRef` be a pointer to an object so we can
-//! access it any time we want instead of going through the collector's list of objects.
-//! TODO: `Error` type for proper error handling
+//! The `simple_lisp` command. Everything interesting is in the library; this parses the arguments
+//! and prints the results.
 
 
-use parser_helper::SimpleError;
 use clap::{
     Parser as ArgParser,
     Subcommand,
+    ValueEnum,
 };
 use std::{
     fmt::Display,
-    time::Instant,
-    fs::read_to_string,
+    time::{
+        Duration,
+        Instant,
+    },
+    fs::write,
+    path::{
+        Path,
+        PathBuf,
+    },
+    io::{
+        Read,
+        stdin,
+    },
+    process::exit,
+};
+use simple_lisp::{
+    repl::{
+        Debugger,
+        InitFile,
+        Repl,
+        post_mortem_repl,
+        post_mortem_repl2,
+    },
+    diagnostic::{
+        self,
+        Severity,
+    },
+    gc_config::{
+        GcConfig,
+        GcStats,
+    },
+    capabilities::Capabilities,
+    budget::{
+        BudgetExceeded,
+        BUDGET_EXIT_CODE,
+    },
+    source::{
+        SearchPath,
+        read_file,
+        read_source,
+    },
+    InterpreterOptions,
+    DEFAULT_MAX_STACK_DEPTH,
+    ast::Expr,
+    parser,
+    interpreter,
+    interpreter2::{
+        self,
+        trace::Trace,
+    },
+    stats_json::{
+        GcReport,
+        StatsReport,
+        as_nanos,
+    },
+    format,
+    bench,
+    color,
+    error_trace,
+    warning_trace,
 };
-use parser::ReplContinue;
-use repl::Repl;
 
 
-mod lexer;
-mod parser;
-mod ast;
-mod interpreter;
-mod interpreter2;
-mod repl;
+/// The exit code when the script given on the command line can't be read
+const READ_ERROR_EXIT_CODE: i32 = 2;
 
 
 #[derive(Clone, Subcommand)]
@@ -36,16 +85,159 @@ enum Action {
     Run {
         /// The file to execute
         filename: String,
+
+        /// Arguments passed to the program. Available as `*args*` or `(args)`.
+        #[arg(last = true)]
+        args: Vec<String>,
     },
     /// Run with the V2 interpreter
     Run2 {
         /// The file to execute
         filename: String,
+
+        /// Arguments passed to the program. Available as `*args*` or `(args)`.
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Compile a file to V2 bytecode. The output can be run with `run2`.
+    Compile {
+        /// The file to compile
+        filename: String,
+
+        /// Where to write the bytecode. Defaults to the input with a `.slpc` extension.
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Print the header of the bytecode file `filename` instead of compiling anything
+        #[arg(long, conflicts_with = "output")]
+        print_header: bool,
+
+        /// Put the source files in the bytecode, so errors can show their code even if the files
+        /// moved. Without this only their paths are saved.
+        #[arg(long, conflicts_with = "print_header")]
+        embed_sources: bool,
+    },
+    /// Print a listing of the V2 instructions for a source or bytecode file
+    Disasm {
+        /// The file to disassemble
+        filename: String,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Also list the instructions removed by the optimizer
+        #[arg(long)]
+        show_eliminated: bool,
+    },
+    /// Parse and convert a file without running it. Exits with 1 if there were any errors.
+    Check {
+        /// The file to check
+        filename: String,
+
+        /// Which interpreter's converter to check with
+        #[arg(long, value_enum, default_value_t = InterpreterVersion::V1)]
+        interpreter: InterpreterVersion,
+
+        /// Print the errors and warnings as JSON records
+        #[arg(long)]
+        json: bool,
+    },
+    /// Format a source file in place
+    Fmt {
+        /// The file to format
+        filename: String,
+
+        /// Don't write anything. Exits with 1 if the file isn't formatted.
+        #[arg(long, conflicts_with = "stdout")]
+        check: bool,
+
+        /// Print the formatted source instead of writing it to the file
+        #[arg(long)]
+        stdout: bool,
+
+        /// The line width to format for
+        #[arg(long, default_value_t = format::DEFAULT_WIDTH)]
+        width: usize,
+    },
+    /// Benchmark a file with the V1 interpreter. It is converted once and run with a fresh
+    /// interpreter each iteration.
+    Bench {
+        /// The file to benchmark
+        filename: String,
+
+        /// How many runs to measure
+        #[arg(long, default_value_t = 10)]
+        iterations: usize,
+
+        /// How many runs to do before measuring
+        #[arg(long, default_value_t = 2)]
+        warmup: usize,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Benchmark even if the program uses stdin
+        #[arg(long)]
+        allow_stdin: bool,
+    },
+    /// Run every `.slp` file in a directory with both interpreters and report where they disagree.
+    /// Exits with 1 if any do without a `; difftest-allow: REASON` line.
+    Difftest {
+        /// The directory to search
+        dir: String,
+    },
+    /// Run the `deftest`s in a file, or in every `.slp` file under a directory, with the V1
+    /// interpreter. Exits with 1 if any fail.
+    Test {
+        /// The file or directory to test
+        path: String,
+
+        /// Only run the tests with this in their name
+        #[arg(long)]
+        filter: Option<String>,
     },
     /// Run a REPL with the V1 interpreter
+    Repl {
+        /// Load this file before the first prompt instead of
+        /// `$XDG_CONFIG_HOME/simple_lisp/init.slp`
+        #[arg(long, value_name = "PATH")]
+        init_file: Option<PathBuf>,
+
+        /// Don't load an init file
+        #[arg(long, conflicts_with = "init_file")]
+        no_init: bool,
+    },
+    /// Evaluate expressions and print the results. Uses the V1 interpreter unless `--v2` is given.
+    Eval {
+        /// The expressions to evaluate in order. Use `-` to read from stdin.
+        #[arg(required = true)]
+        exprs: Vec<String>,
+    },
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum InterpreterVersion {
+    V1,
+    V2,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OnError {
+    /// Print the error and stop
+    Exit,
+    /// Print the error, then open a read-only REPL to look at the variables the program left. The
+    /// program can't be resumed.
     Repl,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 
 /// Bytecode interpreter for Clinery's SimpleLisp language
 #[derive(ArgParser)]
@@ -53,40 +245,312 @@ struct Cli {
     #[command(subcommand)]
     action: Option<Action>,
 
-    /// Displays the stats for nerds: parse time, execution time, instructions/second, etc.
-    #[arg(long, short)]
-    stats_for_nerds: bool,
+    /// Displays the stats for nerds: parse time, execution time, instructions/second, etc. Given
+    /// twice, this also counts the opcodes like `--opcode-stats`.
+    #[arg(long, short, action = clap::ArgAction::Count)]
+    stats_for_nerds: u8,
+
+    /// Print how many times each kind of instruction ran, most common first
+    #[arg(long)]
+    opcode_stats: bool,
+
+    /// Write the stats as one JSON object to stderr after `run`, `run2` or `bench`. The fields are
+    /// listed in `stats_json::STATS_FIELDS`.
+    #[arg(long)]
+    stats_json: bool,
 
-    /// Shows debug information about the AST nodes, instructions, etc.
+    /// Write the `--stats-json` object to this file instead of stderr. Implies `--stats-json`.
+    #[arg(long, value_name = "PATH")]
+    stats_file: Option<PathBuf>,
+
+    /// Shows debug information about the AST nodes, instructions, etc. Each `-d` also logs more to
+    /// stderr: info, then debug, then trace (only in debug builds). `RUST_LOG` overrides the log
+    /// level when it's set. `-dddd` also traces V2 programs like `--trace`.
     #[arg(long, short, action = clap::ArgAction::Count)]
     debug: u8,
+
+    /// Print each V2 instruction to stderr as it runs, with the value it made
+    #[arg(long)]
+    trace: bool,
+
+    /// Only trace while this function is running. Implies `--trace`.
+    #[arg(long, value_name = "FN")]
+    trace_filter: Option<String>,
+
+    /// Evaluate an expression and print the result. Can be given multiple times to evaluate them
+    /// in order. Use `-` to read from stdin.
+    #[arg(long, short, value_name = "EXPR")]
+    eval: Vec<String>,
+
+    /// Use the V2 interpreter for `--eval` and `eval`, like `run2` does for files
+    #[arg(long)]
+    v2: bool,
+
+    /// After `run` or `run2`, print the value the program ended with on one line like `eval` does
+    #[arg(long)]
+    print_result: bool,
+
+    /// Collect garbage after this many allocations. 0 only collects when the allocated bytes get
+    /// too high. Only affects the V1 interpreter.
+    #[arg(long, value_name = "ALLOCATIONS")]
+    gc_threshold: Option<usize>,
+
+    /// Mark at most this many objects between instructions while collecting garbage. 0 does the
+    /// whole collection at once. Only affects the V1 interpreter.
+    #[arg(long, value_name = "ITEMS")]
+    gc_slice: Option<usize>,
+
+    /// Collect just the new objects after this many allocations. 0 turns off minor collections.
+    /// Only affects the V1 interpreter.
+    #[arg(long, value_name = "ALLOCATIONS")]
+    gc_nursery: Option<usize>,
+
+    /// Never collect garbage while running. Everything is still freed on exit.
+    #[arg(long)]
+    gc_disable: bool,
+
+    /// Collect garbage before every allocation, so data that isn't rooted properly is freed
+    /// immediately. Very slow; used to find GC bugs. Only affects the V1 interpreter.
+    #[arg(long)]
+    gc_stress: bool,
+
+    /// Calling a function this many calls deep is an error instead of a stack overflow
+    #[arg(long, value_name = "DEPTH", default_value_t = DEFAULT_MAX_STACK_DEPTH)]
+    max_stack_depth: usize,
+
+    /// Stop after executing this many instructions. Exits with code 124.
+    #[arg(long, value_name = "COUNT")]
+    max_instructions: Option<u64>,
+
+    /// Stop if running takes longer than this many milliseconds. Exits with code 124.
+    #[arg(long, value_name = "MS")]
+    timeout_ms: Option<u64>,
+
+    /// Check the V2 instructions for bad jumps before running them. Always on in debug builds.
+    #[arg(long)]
+    verify: bool,
+
+    /// Run V2 programs under the interactive debugger. It pauses before the first instruction;
+    /// type `help` at its prompt for the commands.
+    #[arg(long)]
+    debugger: bool,
+
+    /// Skip `(breakpoint)`s instead of pausing at them
+    #[arg(long)]
+    no_breakpoints: bool,
+
+    /// What `run` and `run2` do when the program fails with a runtime error
+    #[arg(long, value_enum, default_value_t = OnError::Exit)]
+    on_error: OnError,
+
+    /// Make branching on anything but `#t`, `#f` or `None` an error. Normally only `#f` and `None`
+    /// are false.
+    #[arg(long)]
+    strict_bool: bool,
+
+    /// Also look for modules in this directory. Can be given multiple times. They are searched in
+    /// order after the declaring file's directory and before the ones in `SIMPLE_LISP_PATH`.
+    #[arg(long, short = 'I', value_name = "DIR")]
+    include: Vec<PathBuf>,
+
+    /// Don't load the prelude, the functions written in lisp that are normally defined before the
+    /// program runs
+    #[arg(long)]
+    no_prelude: bool,
+
+    /// Deny natives everything outside of the interpreter: files, stdio, the environment,
+    /// processes and the network
+    #[arg(long)]
+    sandbox: bool,
+
+    /// Only allow natives these capabilities, separated by commas. Implies `--sandbox` for the
+    /// rest. One of fs-read, fs-write, stdio, env, process or network.
+    #[arg(long, value_name = "CAPS", value_delimiter = ',', value_parser = Capabilities::parse)]
+    allow: Vec<Capabilities>,
+
+    /// Don't color errors and warnings. They are only colored on a terminal, and not when
+    /// `NO_COLOR` is set.
+    #[arg(long)]
+    no_color: bool,
 }
+impl Cli {
+    fn trace(&self)->Option<Trace> {
+        if self.trace || self.trace_filter.is_some() || self.debug >= 4 {
+            return Some(Trace::new(self.trace_filter.clone()));
+        }
 
+        return None;
+    }
 
-fn main() {
-    env_logger::init();
-    log::set_max_level(log::LevelFilter::Warn);
+    fn stats_dest(&self)->Option<StatsDest> {
+        match &self.stats_file {
+            Some(path)=>Some(StatsDest::File(path.clone())),
+            None if self.stats_json=>Some(StatsDest::Stderr),
+            None=>None,
+        }
+    }
+
+    fn interpreter_options(&self)->InterpreterOptions {
+        let mut gc_config = GcConfig::default();
+        if let Some(threshold) = self.gc_threshold {
+            gc_config.trigger_allocations = threshold;
+        }
+        if let Some(slice_size) = self.gc_slice {
+            gc_config.slice_size = slice_size;
+        }
+        if let Some(nursery_size) = self.gc_nursery {
+            gc_config.nursery_size = nursery_size;
+        }
+        gc_config.disabled = self.gc_disable;
+        gc_config.stress = self.gc_stress;
+
+        let mut capabilities = Capabilities::default();
+        if self.sandbox || !self.allow.is_empty() {
+            capabilities = self.allow.iter()
+                .fold(Capabilities::empty(), |caps, cap|caps | *cap);
+        }
+
+        InterpreterOptions {
+            gc_config,
+            max_stack_depth: self.max_stack_depth,
+            max_instructions: self.max_instructions,
+            timeout: self.timeout_ms.map(Duration::from_millis),
+            prelude: !self.no_prelude,
+            capabilities,
+            breakpoints: !self.no_breakpoints,
+            opcode_stats: self.opcode_stats || self.stats_for_nerds >= 2,
+            strict_bool: self.strict_bool,
+        }
+    }
+}
+
+/// Where `--stats-json` goes
+#[derive(Clone)]
+enum StatsDest {
+    Stderr,
+    File(PathBuf),
+}
+impl StatsDest {
+    fn emit(&self, report: &StatsReport) {
+        let json = report.to_json();
+        match self {
+            Self::Stderr=>eprintln!("{json}"),
+            Self::File(path)=>if let Err(e) = write(path, json + "\n") {
+                eprintln!("Error: Cannot write the stats to `{}`: {e}", path.display());
+            },
+        }
+    }
+}
 
+
+
+fn main() {
     let args = Cli::parse();
+    init_logging(args.debug);
+    if args.no_color {
+        color::disable();
+    }
+
+    let options = args.interpreter_options();
+    let trace = args.trace();
+    let stats_dest = args.stats_dest();
+    let search_path = SearchPath::new(args.include.clone());
+    let prelude = !args.no_prelude;
+
+    if args.eval.len() > 0 {
+        if args.action.is_some() {
+            eprintln!("`--eval` can't be used with a subcommand");
+            exit(2);
+        }
+
+        if !eval(args.eval, args.v2, args.stats_for_nerds > 0, args.debug, options) {
+            exit(1);
+        }
+        return;
+    }
 
     match args.action {
-        Some(Action::Repl)|None=>{
-            let mut repl = Repl::new();
-            repl.run(args.debug, args.stats_for_nerds)
+        Some(Action::Eval{exprs})=>if !eval(exprs, args.v2, args.stats_for_nerds > 0, args.debug, options) {
+            exit(1);
+        },
+        Some(Action::Repl{init_file, no_init})=>{
+            let init = match (init_file, no_init) {
+                (_, true)=>InitFile::None,
+                (Some(path), false)=>InitFile::Path(path),
+                (None, false)=>InitFile::Default,
+            };
+            let mut repl = Repl::new(options, init);
+            repl.run(args.debug, args.stats_for_nerds > 0)
+        },
+        None=>{
+            let mut repl = Repl::new(options, InitFile::Default);
+            repl.run(args.debug, args.stats_for_nerds > 0)
+        },
+        Some(Action::Run2{filename, args: script_args})=>run2(filename, script_args, args.stats_for_nerds > 0, stats_dest, args.debug, args.verify, args.debugger, trace, options, search_path, args.on_error, args.print_result),
+        Some(Action::Run{filename, args: script_args})=>run(filename, script_args, args.stats_for_nerds > 0, stats_dest, args.debug, options, search_path, args.on_error, args.print_result),
+        Some(Action::Bench{filename, iterations, warmup, json, allow_stdin})=>if !bench(filename, iterations, warmup, json, allow_stdin, stats_dest, options, search_path) {
+            exit(1);
+        },
+        Some(Action::Difftest{dir})=>if !difftest(dir, options, search_path) {
+            exit(1);
+        },
+        Some(Action::Test{path, filter})=>if !test(path, filter, options, search_path) {
+            exit(1);
+        },
+        Some(Action::Fmt{filename, check, stdout, width})=>if !fmt(filename, check, stdout, width) {
+            exit(1);
+        },
+        Some(Action::Check{filename, interpreter, json})=>if !check(filename, interpreter, json, search_path, prelude) {
+            exit(1);
+        },
+        Some(Action::Disasm{filename, format, show_eliminated})=>if !disasm(filename, format, show_eliminated, search_path, prelude) {
+            exit(1);
+        },
+        Some(Action::Compile{filename, print_header: true, ..})=>if !print_header(filename) {
+            exit(1);
+        },
+        Some(Action::Compile{filename, output, embed_sources, ..})=>if !compile(filename, output, embed_sources, args.debug, search_path, prelude) {
+            exit(1);
         },
-        Some(Action::Run2{filename})=>run2(filename, args.stats_for_nerds, args.debug),
-        Some(Action::Run{filename})=>run(filename, args.stats_for_nerds, args.debug),
     }
 }
 
-fn run(filename: String, stats_for_nerds: bool, debug: u8) {
+/// Log warnings, or more with each `-d`. `RUST_LOG` is applied after, so it wins.
+fn init_logging(debug: u8) {
+    use log::LevelFilter;
+
+
+    let level = match debug {
+        0=>LevelFilter::Warn,
+        1=>LevelFilter::Info,
+        2=>LevelFilter::Debug,
+        _=>LevelFilter::Trace,
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+}
+
+fn run(filename: String, script_args: Vec<String>, stats_for_nerds: bool, stats_dest: Option<StatsDest>, debug: u8, options: InterpreterOptions, search_path: SearchPath, on_error: OnError, print_result: bool) {
     use interpreter::{
         ast::convert,
-        Interpreter,
+        data::Data,
+        pretty::{
+            ONE_LINE,
+            pretty_format,
+        },
     };
 
 
-    let source = read_to_string(&filename).unwrap();
+    let source = match read_source(Path::new(&filename)) {
+        Ok(s)=>s,
+        Err(e)=>{
+            println!("Error: {e}");
+            exit(READ_ERROR_EXIT_CODE);
+        },
+    };
 
     let mut parser = parser::new_parser(source.as_str());
 
@@ -112,8 +576,23 @@ fn run(filename: String, stats_for_nerds: bool, debug: u8) {
                 }
             }
 
-            let mut state = convert(exprs).unwrap();
-            let mut interpreter = Interpreter::new(&mut state);
+            if debug >= 1 {
+                print_search_path(&search_path);
+            }
+
+            let mut state = match convert(exprs, Path::new(&filename), search_path, options.prelude) {
+                Ok(s)=>s,
+                Err(e)=>{
+                    error_trace(e, &source, &filename);
+                    exit(1);
+                },
+            };
+            for warning in state.warnings.drain(..) {
+                warning_trace(warning, &source, &filename);
+            }
+            let mut interpreter = options.new_interpreter(&mut state);
+            interpreter.set_script_args(Some(filename.clone()), script_args, &mut state);
+            interpreter.set_keep_post_mortem(on_error == OnError::Repl);
 
             if debug >= 3 {
                 use interpreter::ast::Instruction;
@@ -132,37 +611,176 @@ fn run(filename: String, stats_for_nerds: bool, debug: u8) {
             }
 
             let res = interpreter.run(&mut state, None);
+            // what the program printed comes before the stats and errors
+            let _ = interpreter.output().flush();
             match res {
                 Ok(res)=>{
+                    if print_result {
+                        match &res {
+                            Some(dr) if !matches!(&*dr.get_data(), Data::None)=>{
+                                println!("{}", pretty_format(dr, &state.interner, &ONE_LINE));
+                            },
+                            _=>println!("None"),
+                        }
+                    }
                     if stats_for_nerds {
                         println!("> {res:?}");
                         println!("Allocations: {}", interpreter.metrics.allocations);
                         println!("Max call stack depth: {}", interpreter.metrics.max_call_stack_depth);
                         println!("Instruction count: {}", interpreter.metrics.instructions_executed);
                         println!("Max bytes allocated at once: {}", interpreter.metrics.max_allocation_bytes);
+                        print_gc_stats(&interpreter.metrics.gc);
                         println!("Runtime: {:?}", interpreter.metrics.total_run_time);
                         let rt = interpreter.metrics.total_run_time.as_secs_f32();
                         let ins_per_sec = interpreter.metrics.instructions_executed as f32 / rt;
                         println!("{} ins/s", human_readable_fmt(ins_per_sec));
                     }
+                    if let Some(counts) = interpreter.opcode_counts() {
+                        println!("{counts}");
+                    }
+                    if let Some(dest) = stats_dest {
+                        let metrics = &interpreter.metrics;
+                        let mut report = StatsReport::new("v1", metrics.total_run_time, metrics.instructions_executed, metrics.allocations);
+                        report.parse_ns = Some(as_nanos(end));
+                        report.max_stack_depth = Some(metrics.max_call_stack_depth);
+                        report.gc = Some(GcReport::from(&metrics.gc));
+                        dest.emit(&report);
+                    }
                 },
-                Err(e)=>error_trace(e, &source, &filename),
+                Err(e) if on_error == OnError::Repl=>{
+                    let code = runtime_error_code(&e);
+                    error_trace(e, &source, &filename);
+                    let frames = interpreter.take_post_mortem().unwrap_or_default();
+                    post_mortem_repl(&interpreter, &state, frames);
+                    exit(code);
+                },
+                Err(e)=>runtime_error_trace(e, &source, &filename),
             }
         },
-        Err(e)=>error_trace(e, &source, &filename),
+        Err(e)=>{
+            error_trace(e, &source, &filename);
+            exit(1);
+        },
     }
 }
 
-fn run2(filename: String, stats_for_nerds: bool, debug: u8) {
-    use interpreter2::{
-        ast::convert,
-        Interpreter,
+fn run2(filename: String, script_args: Vec<String>, stats_for_nerds: bool, stats_dest: Option<StatsDest>, debug: u8, verify: bool, debugger: bool, trace: Option<Trace>, options: InterpreterOptions, search_path: SearchPath, on_error: OnError, print_result: bool) {
+    use interpreter2::debug::value_line;
+
+
+    let Some((mut state, source, parse_time)) = load2(&filename, stats_for_nerds, debug, search_path, options.prelude) else {
+        exit(1);
     };
 
+    if verify || cfg!(debug_assertions) {
+        if let Err(e) = interpreter2::verify::verify(&state) {
+            error_trace(e, &source, &filename);
+            exit(1);
+        }
+    }
 
-    let source = read_to_string(&filename).unwrap();
+    let mut interpreter = options.new_interpreter2(&mut state);
+    interpreter.set_script_args(&filename, &script_args);
+    if debugger {
+        interpreter.set_debug_hook(Some(Box::new(Debugger::new(&state))));
+    }
+    interpreter.set_trace(trace);
 
-    let mut parser = parser::new_parser(source.as_str());
+    if debug >= 3 {
+        let mut iter = state.instructions.iter();
+        let mut i = 0;
+        while let Some(ins) = iter.next() {
+            let id = iter.cur_ins_id().unwrap();
+            println!("#{i:<3.} Id({:3.}) > {}", id.inner(), ins.asm(&state));
+
+            i += 1;
+        }
+    }
+
+    let start = Instant::now();
+    let res = interpreter.run(&mut state, None);
+    let runtime = start.elapsed();
+    let _ = interpreter.output().flush();
+    match res {
+        Ok(res)=>{
+            if print_result {
+                println!("{}", value_line(&res, &state));
+            }
+            if stats_for_nerds {
+                // TODO: the rest of the V1 stats once V2 has metrics
+                println!("Allocations: {}", interpreter.gc_stats.allocations);
+                println!("Instruction count: {}", interpreter.instructions_executed);
+                print_gc_stats(&interpreter.gc_stats);
+            }
+            if let Some(counts) = interpreter.opcode_counts() {
+                println!("{counts}");
+            }
+            if let Some(dest) = stats_dest {
+                let mut report = StatsReport::new("v2", runtime, interpreter.instructions_executed, interpreter.gc_stats.allocations);
+                report.parse_ns = parse_time.map(as_nanos);
+                report.gc = Some(GcReport::from(&interpreter.gc_stats));
+                dest.emit(&report);
+            }
+        },
+        Err(e) if on_error == OnError::Repl=>{
+            let code = runtime_error_code(&e);
+            error_trace(e, &source, &filename);
+            post_mortem_repl2(&interpreter, &state);
+            exit(code);
+        },
+        Err(e)=>runtime_error_trace(e, &source, &filename),
+    }
+}
+
+/// Load a V2 source or bytecode file. Compiled files skip straight to the converted state, which
+/// already has the prelude if it was compiled with one. Returns the state, the source and how long
+/// parsing took. The source is empty and there is no parse time for bytecode. Errors are printed and
+/// `None` is returned.
+fn load2(filename: &str, stats_for_nerds: bool, debug: u8, search_path: SearchPath, prelude: bool)->Option<(interpreter2::ast::ConvertState, String, Option<Duration>)> {
+    use interpreter2::bytecode;
+
+
+    let bytes = match read_file(Path::new(filename)) {
+        Ok(b)=>b,
+        Err(e)=>{
+            println!("Error: {e}");
+            exit(READ_ERROR_EXIT_CODE);
+        },
+    };
+
+    if bytecode::is_bytecode(&bytes) {
+        match bytecode::deserialize(&bytes) {
+            Ok(state)=>return Some((state, String::new(), None)),
+            Err(e)=>{
+                error_trace(e, "", filename);
+                return None;
+            },
+        }
+    }
+
+    let source = match String::from_utf8(bytes) {
+        Ok(s)=>s,
+        Err(e)=>{
+            let offset = e.utf8_error().valid_up_to();
+            println!("Error: Cannot read `{filename}`: it is not valid UTF-8 or bytecode (bad byte at offset {offset})");
+            exit(READ_ERROR_EXIT_CODE);
+        },
+    };
+
+    let (state, parse_time) = convert2(&source, filename, stats_for_nerds, debug, search_path, prelude)?;
+    return Some((state, source, Some(parse_time)));
+}
+
+/// Parse and convert `source` for the V2 interpreter. Returns the state and how long parsing took.
+/// Errors are printed and `None` is returned.
+fn convert2(source: &str, filename: &str, stats_for_nerds: bool, debug: u8, search_path: SearchPath, prelude: bool)->Option<(interpreter2::ast::ConvertState, Duration)> {
+    use interpreter2::{
+        ast::convert_mapped,
+        optimize::optimize,
+    };
+
+
+    let mut parser = parser::new_parser(source);
 
     let parse_start = Instant::now();
     match parser.parse_all() {
@@ -186,48 +804,703 @@ fn run2(filename: String, stats_for_nerds: bool, debug: u8) {
                 }
             }
 
-            let mut state = match convert(exprs) {
+            if debug >= 1 {
+                print_search_path(&search_path);
+            }
+
+            let mut state = match convert_mapped(exprs, source, Path::new(filename), search_path, prelude) {
                 Ok(s)=>s,
                 Err(e)=>{
-                    error_trace(e, &source, &filename);
-                    return;
+                    error_trace(e, source, filename);
+                    return None;
                 },
             };
-            let mut interpreter = Interpreter::new(&mut state, None);
+            for warning in state.warnings.drain(..) {
+                warning_trace(warning, source, filename);
+            }
 
-            if debug >= 3 {
-                let mut iter = state.instructions.iter();
-                let mut i = 0;
-                while let Some(ins) = iter.next() {
-                    let id = iter.cur_ins_id().unwrap();
-                    println!("#{i:<3.} Id({:3.}) > {:?}", id.inner(), ins);
+            if stats_for_nerds {
+                let (literals, unique) = state.constants.string_counts();
+                println!(
+                    "String literals: {literals}, {unique} unique, {} bytes saved",
+                    state.constants.saved_bytes(),
+                );
+            }
 
-                    i += 1;
+            let stats = optimize(&mut state);
+            if debug >= 1 {
+                println!("Folded {} constant branches", stats.folded_branches);
+                println!("Eliminated {} dead instructions", stats.dead_instructions);
+                println!("Threaded {} jumps and removed {} useless ones", stats.threaded_jumps, stats.removed_jumps);
+                println!("Fused {} instruction pairs", stats.fused_instructions);
+            }
+
+            return Some((state, end));
+        },
+        Err(e)=>{
+            error_trace(e, source, filename);
+            return None;
+        },
+    }
+}
+
+/// Compare the interpreters on every file in `dir` and print the ones that disagree. Returns
+/// `false` if any did without being allowed to.
+fn difftest(dir: String, options: InterpreterOptions, search_path: SearchPath)->bool {
+    use simple_lisp::difftest::{
+        Outcome,
+        difftest_dir,
+    };
+
+
+    fn print_outcome(name: &str, outcome: &Outcome) {
+        match &outcome.result {
+            Ok(value)=>println!("    {name}: {value}"),
+            Err(e)=>println!("    {name}: Error: {e}"),
+        }
+        if !outcome.stdout.is_empty() {
+            println!("    {name} printed: {:?}", outcome.stdout);
+        }
+    }
+
+
+    let reports = match difftest_dir(Path::new(&dir), options, &search_path) {
+        Ok(r)=>r,
+        Err(e)=>{
+            println!("Error: {e}");
+            return false;
+        },
+    };
+
+    let mut failed = 0;
+    let mut allowed = 0;
+    for report in reports.iter() {
+        let path = report.path.display();
+        match (report.agrees(), &report.allowed) {
+            (true, None)=>println!("ok       {path}"),
+            (true, Some(_))=>println!("ok       {path} (it agrees now, so the allow line can go)"),
+            (false, Some(reason))=>{
+                allowed += 1;
+                println!("allowed  {path} ({reason})");
+            },
+            (false, None)=>{
+                failed += 1;
+                println!("DIFFERS  {path}");
+                print_outcome("v1", &report.v1);
+                print_outcome("v2", &report.v2);
+            },
+        }
+    }
+
+    println!();
+    println!("{} files, {failed} differ, {allowed} allowed to differ", reports.len());
+
+    return failed == 0;
+}
+
+/// Run the tests in `path` and report how each went. What a failed test printed is shown with its
+/// error. Returns `false` if any failed or a file couldn't be run.
+fn test(path: String, filter: Option<String>, options: InterpreterOptions, search_path: SearchPath)->bool {
+    use interpreter::ast::convert;
+    use simple_lisp::{
+        source::find_sources,
+        testing::run_tests,
+    };
+
+
+    let path = PathBuf::from(path);
+    let mut files = Vec::new();
+    if path.is_dir() {
+        if let Err(e) = find_sources(&path, &mut files) {
+            println!("Error: Cannot search `{}`: {e}", path.display());
+            return false;
+        }
+        files.sort();
+    } else {
+        files.push(path);
+    }
+
+    let color = color::enabled();
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut filtered = 0;
+    let mut broken = 0;
+    for file in files {
+        let filename = file.display().to_string();
+        let source = match read_source(&file) {
+            Ok(s)=>s,
+            Err(e)=>{
+                println!("Error: {e}");
+                broken += 1;
+                continue;
+            },
+        };
+        let exprs = match parser::new_parser(source.as_str()).parse_all() {
+            Ok(exprs)=>exprs,
+            Err(e)=>{
+                error_trace(e, &source, &filename);
+                broken += 1;
+                continue;
+            },
+        };
+        let mut state = match convert(exprs, &file, search_path.clone(), options.prelude) {
+            Ok(state)=>state,
+            Err(e)=>{
+                error_trace(e, &source, &filename);
+                broken += 1;
+                continue;
+            },
+        };
+        for warning in state.warnings.drain(..) {
+            warning_trace(warning, &source, &filename);
+        }
+        if state.tests.is_empty() {
+            continue;
+        }
+
+        let run = match run_tests(&mut state, options, filter.as_deref()) {
+            Ok(run)=>run,
+            Err(e)=>{
+                error_trace(e, &source, &filename);
+                broken += 1;
+                continue;
+            },
+        };
+        filtered += run.filtered;
+
+        for outcome in run.outcomes.iter() {
+            match outcome.error {
+                Some(_)=>println!("test {filename}::{} ... {} ({:?})", outcome.name, color::paint(color, color::ERROR, "FAILED"), outcome.time),
+                None=>println!("test {filename}::{} ... ok ({:?})", outcome.name, outcome.time),
+            }
+        }
+        for outcome in run.outcomes {
+            let Some(error) = outcome.error else {
+                passed += 1;
+                continue;
+            };
+
+            failed += 1;
+            println!();
+            println!("---- {filename}::{} ----", outcome.name);
+            if !outcome.stdout.is_empty() {
+                println!("It printed:");
+                print!("{}", outcome.stdout);
+                if !outcome.stdout.ends_with('\n') {
+                    println!();
                 }
             }
+            error_trace(error, &source, &filename);
+        }
+    }
 
-            let res = interpreter.run(&mut state, None);
-            match res {
-                Ok(res)=>{
-                    dbg!(res);
-                    if stats_for_nerds {
-                        todo!();
-                        // println!("> {res:?}");
-                        // println!("Allocations: {}", interpreter.metrics.allocations);
-                        // println!("Max call stack depth: {}", interpreter.metrics.max_call_stack_depth);
-                        // println!("Instruction count: {}", interpreter.metrics.instructions_executed);
-                        // println!("Max bytes allocated at once: {}", interpreter.metrics.max_allocation_bytes);
-                        // println!("Runtime: {:?}", interpreter.metrics.total_run_time);
-                        // let rt = interpreter.metrics.total_run_time.as_secs_f32();
-                        // let ins_per_sec = interpreter.metrics.instructions_executed as f32 / rt;
-                        // println!("{} ins/s", human_readable_fmt(ins_per_sec));
-                    }
-                },
-                Err(e)=>error_trace(e, &source, &filename),
+    println!();
+    println!("{passed} passed, {failed} failed, {filtered} filtered out");
+    if broken > 0 {
+        println!("{broken} files couldn't be run");
+    }
+
+    return failed == 0 && broken == 0;
+}
+
+/// Benchmark `filename` and print the report. Returns `false` if it failed.
+fn bench(filename: String, iterations: usize, warmup: usize, json: bool, allow_stdin: bool, stats_dest: Option<StatsDest>, options: InterpreterOptions, search_path: SearchPath)->bool {
+    use interpreter::ast::convert;
+
+
+    let source = match read_source(Path::new(&filename)) {
+        Ok(s)=>s,
+        Err(e)=>{
+            println!("Error: {e}");
+            return false;
+        },
+    };
+
+    let mut parser = parser::new_parser(source.as_str());
+    let parse_start = Instant::now();
+    let exprs = match parser.parse_all() {
+        Ok(exprs)=>exprs,
+        Err(e)=>{
+            error_trace(e, &source, &filename);
+            return false;
+        },
+    };
+    let parse_time = parse_start.elapsed();
+    let mut state = match convert(exprs, Path::new(&filename), search_path, options.prelude) {
+        Ok(state)=>state,
+        Err(e)=>{
+            error_trace(e, &source, &filename);
+            return false;
+        },
+    };
+
+    if bench::reads_stdin(&state) {
+        if !allow_stdin {
+            println!("Error: `{filename}` uses stdin, so it can't be benchmarked. Pass `--allow-stdin` to run it anyways");
+            return false;
+        }
+        println!("Warning: `{filename}` uses stdin. The timings will include waiting for input");
+    }
+
+    let report = match bench::bench(&mut state, iterations, warmup, options) {
+        Ok(r)=>r,
+        Err(e)=>runtime_error_trace(e, &source, &filename),
+    };
+
+    if let Some(dest) = stats_dest {
+        let mut stats = StatsReport::new("v1", report.wall_time.mean, report.instructions_executed, report.allocations);
+        stats.iterations = report.iterations;
+        stats.parse_ns = Some(as_nanos(parse_time));
+        stats.max_stack_depth = Some(report.max_call_stack_depth);
+        dest.emit(&stats);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return true;
+    }
+
+    let time = &report.wall_time;
+    println!("{} iterations after {} warmup", report.iterations, report.warmup);
+    println!("  min:    {:?}", time.min);
+    println!("  median: {:?}", time.median);
+    println!("  mean:   {:?}", time.mean);
+    println!("  p95:    {:?}", time.p95);
+    println!("  max:    {:?}", time.max);
+    println!("Instructions per iteration: {}", report.instructions_executed);
+    println!("Allocations per iteration: {}", report.allocations);
+    let ins_per_sec = report.instructions_executed as f32 / time.mean.as_secs_f32();
+    println!("{} ins/s", human_readable_fmt(ins_per_sec));
+
+    return true;
+}
+
+/// Format `filename`. With `check` nothing is written and `false` is returned if the file isn't
+/// formatted.
+fn fmt(filename: String, check: bool, stdout: bool, width: usize)->bool {
+    let source = match read_source(Path::new(&filename)) {
+        Ok(s)=>s,
+        Err(e)=>{
+            println!("Error: {e}");
+            return false;
+        },
+    };
+
+    let formatted = match format::format_source(&source, width) {
+        Ok(f)=>f,
+        Err(e)=>{
+            error_trace(e.context(format!("Formatting `{filename}`")), &source, &filename);
+            return false;
+        },
+    };
+
+    if stdout {
+        print!("{formatted}");
+        return true;
+    }
+
+    if check {
+        if formatted != source {
+            println!("`{filename}` is not formatted");
+            return false;
+        }
+
+        return true;
+    }
+
+    if formatted != source {
+        if let Err(e) = write(&filename, formatted) {
+            println!("Error: Could not write `{filename}`: {e}");
+            return false;
+        }
+    }
+
+    return true;
+}
+
+/// Parse and convert `filename` without running it. Modules are loaded and checked too. Returns
+/// `false` if there were any errors.
+fn check(filename: String, interpreter: InterpreterVersion, json: bool, search_path: SearchPath, prelude: bool)->bool {
+    let check_inner = ||->bool {
+        let source = match read_source(Path::new(&filename)) {
+            Ok(s)=>s,
+            Err(e)=>{
+                error_trace(e.into(), "", &filename);
+                return false;
+            },
+        };
+
+        let mut parser = parser::new_parser(source.as_str());
+        let exprs = match parser.parse_all() {
+            Ok(exprs)=>exprs,
+            Err(e)=>{
+                error_trace(e, &source, &filename);
+                return false;
+            },
+        };
+
+        let res = match interpreter {
+            InterpreterVersion::V1=>interpreter::ast::convert(exprs, Path::new(&filename), search_path.clone(), prelude)
+                .map(|state|state.warnings),
+            InterpreterVersion::V2=>interpreter2::ast::convert(exprs, Path::new(&filename), search_path.clone(), prelude)
+                .map(|state|state.warnings),
+        };
+
+        match res {
+            Ok(warnings)=>{
+                for warning in warnings {
+                    warning_trace(warning, &source, &filename);
+                }
+                return true;
+            },
+            Err(e)=>{
+                error_trace(e, &source, &filename);
+                return false;
+            },
+        }
+    };
+
+    if !json {
+        let ok = check_inner();
+        if ok {
+            println!("`{filename}` has no errors");
+        }
+
+        return ok;
+    }
+
+    let (ok, diagnostics) = diagnostic::collect(check_inner);
+    println!("{}", serde_json::to_string_pretty(&diagnostics).unwrap());
+
+    return ok && diagnostics.iter().all(|d|d.severity != Severity::Error);
+}
+
+/// Print the V2 instruction listing for `filename`. Returns `false` if it failed.
+fn disasm(filename: String, format: OutputFormat, show_eliminated: bool, search_path: SearchPath, prelude: bool)->bool {
+    use interpreter2::disasm::disassemble;
+
+
+    let Some((state, ..)) = load2(&filename, false, 0, search_path, prelude) else {
+        return false;
+    };
+
+    let listing = disassemble(&state, show_eliminated);
+    match format {
+        OutputFormat::Text=>print!("{listing}"),
+        OutputFormat::Json=>println!("{}", serde_json::to_string_pretty(&listing).unwrap()),
+    }
+
+    return true;
+}
+
+/// Compile `filename` to V2 bytecode. Returns `false` if it failed.
+fn compile(filename: String, output: Option<String>, embed_sources: bool, debug: u8, search_path: SearchPath, prelude: bool)->bool {
+    use interpreter2::bytecode;
+
+
+    let source = match read_source(Path::new(&filename)) {
+        Ok(s)=>s,
+        Err(e)=>{
+            println!("Error: {e}");
+            return false;
+        },
+    };
+
+    let Some((mut state, _)) = convert2(&source, &filename, false, debug, search_path, prelude) else {
+        return false;
+    };
+    if !embed_sources {
+        if let Some(map) = state.source_map.get_mut() {
+            map.strip_contents();
+        }
+    }
+
+    let output = output.unwrap_or_else(||{
+        Path::new(&filename)
+            .with_extension("slpc")
+            .to_string_lossy()
+            .into_owned()
+    });
+    if output == filename {
+        println!("Error: The output would overwrite the source file");
+        return false;
+    }
+
+    let bytes = bytecode::serialize(&state);
+    if let Err(e) = write(&output, &bytes) {
+        println!("Error: Could not write `{output}`: {e}");
+        return false;
+    }
+
+    if debug >= 1 {
+        println!("Wrote {} bytes to `{output}`", bytes.len());
+    }
+
+    return true;
+}
+
+/// Print what the header of the bytecode file `filename` says, and whether its payload matches.
+/// Returns `false` if the header can't be read.
+fn print_header(filename: String)->bool {
+    use interpreter2::bytecode::{
+        INTERPRETER_VERSION,
+        checksum,
+        read_header,
+    };
+
+
+    let bytes = match read_file(Path::new(&filename)) {
+        Ok(b)=>b,
+        Err(e)=>{
+            println!("Error: {e}");
+            return false;
+        },
+    };
+    let (header, payload) = match read_header(&bytes) {
+        Ok(h)=>h,
+        Err(e)=>{
+            error_trace(e, "", &filename);
+            return false;
+        },
+    };
+
+    let interpreter_note = match header.interpreter_version == INTERPRETER_VERSION {
+        true=>String::new(),
+        false=>format!(" (this is {INTERPRETER_VERSION}, so it has to be recompiled)"),
+    };
+    let actual = checksum(payload);
+    let checksum_note = match actual == header.checksum {
+        true=>"ok".to_string(),
+        false=>format!("the payload hashes to {actual:016x}, so the file is corrupted"),
+    };
+
+    println!("Format version:      {}", header.format_version);
+    println!("Interpreter version: {}{interpreter_note}", header.interpreter_version.escape_debug());
+    println!("Payload:             {} bytes", header.payload_len);
+    println!("Checksum:            {:016x} ({checksum_note})", header.checksum);
+
+    return true;
+}
+
+/// Evaluate each source in order using the same state and print the results. Returns `false` if
+/// any of them failed.
+/// Evaluate each of `sources` in order in the same state, printing the results on one line.
+/// Returns `false` if one couldn't be parsed or converted. Runtime errors exit.
+fn eval(sources: Vec<String>, v2: bool, stats_for_nerds: bool, debug: u8, options: InterpreterOptions)->bool {
+    if v2 {
+        return eval2(sources, stats_for_nerds, debug, options);
+    }
+
+    use interpreter::{
+        ast::{
+            ConvertState,
+            Instruction,
+            repl_convert,
+            convert_prelude,
+        },
+        data::Data,
+        pretty::{
+            ONE_LINE,
+            pretty_format,
+        },
+    };
+
+
+    let mut state = ConvertState::new();
+    state.reserve_module();
+    if options.prelude {
+        convert_prelude(&mut state);
+    }
+    let mut interpreter = options.new_interpreter(&mut state);
+
+    for source in sources {
+        let Some((source, name)) = eval_source(source) else {return false};
+
+        let mut parser = parser::new_parser(source.as_str());
+        let exprs = match eval_parse(&mut parser, &source, name, stats_for_nerds, debug) {
+            Some(exprs)=>exprs,
+            None=>return false,
+        };
+        drop(parser);
+
+        if exprs.len() == 0 {continue}
+
+        let start_id = match repl_convert(&mut state, exprs) {
+            Ok(id)=>id,
+            Err(e)=>{
+                error_trace(e, &source, name);
+                return false;
+            },
+        };
+
+        if debug >= 3 {
+            let mut iter = state.instructions.iter();
+            iter.jump(start_id);
+            let mut i = 0;
+            while let Some(ins) = iter.next() {
+                let id = iter.cur_ins_id().unwrap();
+                println!("#{i:<3.} Id({:3.}) > {:?}", id.inner(), ins);
+                if let Instruction::Exit = ins {break}
+
+                i += 1;
             }
+        }
+
+        let start_ins_count = interpreter.metrics.instructions_executed;
+        let res = interpreter.run(&mut state, Some(start_id));
+        let _ = interpreter.output().flush();
+        match res {
+            Ok(Some(dr))=>{
+                let is_none = matches!(&*dr.get_data(), Data::None);
+                if !is_none {
+                    println!("{}", pretty_format(&dr, &state.interner, &ONE_LINE));
+                }
+            },
+            Ok(None)=>{},
+            Err(e)=>runtime_error_trace(e, &source, name),
+        }
+
+        if stats_for_nerds {
+            println!("Runtime: {:?}", interpreter.metrics.last_run_time);
+            println!("Instruction count: {}", interpreter.metrics.instructions_executed - start_ins_count);
+        }
+    }
+
+    if let Some(counts) = interpreter.opcode_counts() {
+        println!("{counts}");
+    }
+
+    return true;
+}
+
+/// `eval` with the V2 interpreter
+fn eval2(sources: Vec<String>, stats_for_nerds: bool, debug: u8, options: InterpreterOptions)->bool {
+    use interpreter2::{
+        ast::{
+            Instruction,
+            convert,
+            repl_convert,
+        },
+        data::Primitive,
+        debug::value_line,
+    };
+
+
+    // the prelude runs once, before the first expression
+    let mut state = convert(Vec::new(), Path::new("<eval>"), SearchPath::default(), options.prelude).unwrap();
+    let mut interpreter = options.new_interpreter2(&mut state);
+    if let Err(e) = interpreter.run(&mut state, None) {
+        runtime_error_trace(e, "", "<prelude>");
+    }
+
+    for source in sources {
+        let Some((source, name)) = eval_source(source) else {return false};
+
+        let mut parser = parser::new_parser(source.as_str());
+        let exprs = match eval_parse(&mut parser, &source, name, stats_for_nerds, debug) {
+            Some(exprs)=>exprs,
+            None=>return false,
+        };
+        drop(parser);
+
+        if exprs.len() == 0 {continue}
+
+        let start_id = match repl_convert(&mut state, exprs) {
+            Ok(id)=>id,
+            Err(e)=>{
+                error_trace(e, &source, name);
+                return false;
+            },
+        };
+        for warning in state.warnings.drain(..) {
+            warning_trace(warning, &source, name);
+        }
+
+        if debug >= 3 {
+            let mut iter = state.instructions.iter();
+            iter.jump(start_id);
+            let mut i = 0;
+            while let Some(ins) = iter.next() {
+                let id = iter.cur_ins_id().unwrap();
+                println!("#{i:<3.} Id({:3.}) > {}", id.inner(), ins.asm(&state));
+                if let Instruction::Exit = ins {break}
+
+                i += 1;
+            }
+        }
+
+        let start_ins_count = interpreter.instructions_executed;
+        let res = interpreter.run(&mut state, Some(start_id));
+        let _ = interpreter.output().flush();
+        match res {
+            Ok(Primitive::None)=>{},
+            Ok(value)=>println!("{}", value_line(&value, &state)),
+            Err(e)=>runtime_error_trace(e, &source, name),
+        }
+
+        if stats_for_nerds {
+            println!("Instruction count: {}", interpreter.instructions_executed - start_ins_count);
+        }
+    }
+
+    if let Some(counts) = interpreter.opcode_counts() {
+        println!("{counts}");
+    }
+
+    return true;
+}
+
+/// The text to evaluate for one `eval` argument and the name to report errors with. `-` reads
+/// stdin. `None` if that failed, after printing why.
+fn eval_source(source: String)->Option<(String, &'static str)> {
+    if source != "-" {
+        return Some((source, "<eval>"));
+    }
+
+    let mut input = String::new();
+    if let Err(e) = stdin().read_to_string(&mut input) {
+        println!("Error: Could not read stdin: {e}");
+        return None;
+    }
+
+    return Some((input, "<stdin>"));
+}
+
+/// Parse one `eval` source, printing what `--stats-for-nerds` and `--debug` ask for. Errors are
+/// printed and `None` is returned.
+fn eval_parse<'a>(parser: &mut parser::MyParser<'a>, source: &str, name: &str, stats_for_nerds: bool, debug: u8)->Option<Vec<Expr<'a>>> {
+    let parse_start = Instant::now();
+    let exprs = match parser.parse_all() {
+        Ok(exprs)=>exprs,
+        Err(e)=>{
+            error_trace(e, source, name);
+            return None;
         },
-        Err(e)=>error_trace(e, &source, &filename),
+    };
+
+    if stats_for_nerds {
+        println!("Parse time: {:?}", parse_start.elapsed());
+    }
+
+    if debug >= 1 {
+        println!("{} root AST nodes", exprs.len());
+    }
+
+    if debug >= 2 {
+        for expr in exprs.iter() {
+            println!("{expr:#?}");
+        }
     }
+
+    return Some(exprs);
+}
+
+/// The collector part of the stats for nerds
+fn print_gc_stats(stats: &GcStats) {
+    println!("GC collections: {} minor, {} major", stats.minor_collections, stats.major_collections);
+    println!("GC time: {:?} minor, {:?} major, {:?} longest pause", stats.minor_time, stats.major_time, stats.max_pause);
+    println!("GC freed: {} objects, ~{} bytes", stats.freed_objects, stats.freed_bytes);
+    println!("Peak live objects: {}", stats.peak_live);
 }
 
 fn human_readable_fmt(val: f32)->String {
@@ -242,37 +1515,29 @@ fn human_readable_fmt(val: f32)->String {
     }
 }
 
-pub fn error_trace(err: anyhow::Error, source: &str, file_path: impl Display) {
-    let mut chain = err.chain().rev().peekable();
-    let Some(root_cause) = chain.next() else {unreachable!("Error has no root cause!")};
+/// Print where modules are looked for, in order
+fn print_search_path(search_path: &SearchPath) {
+    println!("Module search path:");
+    println!("  1. the declaring file's directory");
+    for (i, dir) in search_path.dirs.iter().enumerate() {
+        println!("  {}. {}", i + 2, dir.display());
+    }
+}
 
-    // TODO: change this when V2 is done
-    if let Some(_) = root_cause.downcast_ref::<interpreter::ast::ModuleError>() {
-        return;
-    } else if let Some(_) = root_cause.downcast_ref::<interpreter2::ast::ModuleError>() {
-        return;
-    } else if let Some(serr) = root_cause.downcast_ref::<SimpleError<String>>() {
-        serr.eprint_with_source(source, file_path);
-        println!();
-    } else if let Some(serr) = root_cause.downcast_ref::<ReplContinue>() {
-        serr.eprint_with_source(source, file_path);
-        println!();
-    } else {
-        println!("Error: {root_cause}");
-    }
-
-    if chain.peek().is_some() {
-        let last = chain.len() - 1;
-        println!("Trace:");
-        for (i, err) in chain.enumerate() {
-            for _ in 0..i {print!(" ")}
-            if i == last {
-                println!("└─ {err}");
-            } else if i == 0 {
-                println!(" ┌ {err}");
-            } else {
-                println!("└┬ {err}");
-            }
-        }
+/// `error_trace`, then exit with `runtime_error_code` so scripts and CI can tell the program
+/// failed, and that running out of budget apart from a script error.
+fn runtime_error_trace(err: anyhow::Error, source: &str, file_path: impl Display)->! {
+    let code = runtime_error_code(&err);
+    error_trace(err, source, file_path);
+
+    exit(code);
+}
+
+/// What to exit with after a runtime error. `BUDGET_EXIT_CODE` if the program ran out of budget,
+/// 1 otherwise.
+fn runtime_error_code(err: &anyhow::Error)->i32 {
+    match err.root_cause().is::<BudgetExceeded>() {
+        true=>BUDGET_EXIT_CODE,
+        false=>1,
     }
 }
@@ -0,0 +1,151 @@
+//! How numbers and floats mix. Both interpreters' arithmetic natives use these so the rules only
+//! live in one place:
+//!
+//! - number op number is a number, and an error if it overflows
+//! - if either side is a float, so is the result
+//! - `/` on numbers that don't divide evenly is a float, so `(/ 7 2)` is `3.5`
+//! - numbers and floats compare by their exact values, even past 2^53 where an `i64` doesn't fit
+//!   in an `f64`
+//! - `int` truncates toward zero, and errors if there is no integer to truncate to
+//! - floats always print with a `.` or an exponent so they don't look like numbers, and the special
+//!   ones print as `#inf`, `#-inf` and `#nan`. The lexer reads all of them back to the same bits.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use std::{
+    cmp::Ordering,
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+};
+use crate::error::LispError;
+
+
+/// 2^63 as a float. Every float at or past this (or below its negative) is out of `i64`'s range.
+const I64_LIMIT: f64 = 9223372036854775808.0;
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Num {
+    Int(i64),
+    Float(f64),
+}
+impl Num {
+    pub fn as_f64(self)->f64 {
+        match self {
+            Self::Int(i)=>i as f64,
+            Self::Float(f)=>f,
+        }
+    }
+}
+
+/// Prints a float the way the lexer reads it: the shortest form that round trips
+#[derive(Debug, Copy, Clone)]
+pub struct FloatDisplay(pub f64);
+impl Display for FloatDisplay {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        match self.0 {
+            n if n.is_nan()=>write!(f, "#nan"),
+            f64::INFINITY=>write!(f, "#inf"),
+            f64::NEG_INFINITY=>write!(f, "#-inf"),
+            // `Debug` is `Display` plus a `.0` on whole floats, and switches to an exponent when
+            // the float is very big or small
+            n=>write!(f, "{n:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+
+/// `l op r`. `name` is what to call the op in errors, like `+` or `+=`.
+pub fn apply(name: &str, op: Op, l: Num, r: Num)->Result<Num> {
+    let (l, r) = match (l, r) {
+        (Num::Int(l), Num::Int(r))=>return apply_int(name, op, l, r),
+        (l, r)=>(l.as_f64(), r.as_f64()),
+    };
+
+    let out = match op {
+        Op::Add=>l + r,
+        Op::Sub=>l - r,
+        Op::Mul=>l * r,
+        Op::Div=>l / r,
+        Op::Rem=>l % r,
+    };
+
+    return Ok(Num::Float(out));
+}
+
+fn apply_int(name: &str, op: Op, l: i64, r: i64)->Result<Num> {
+    if r == 0 && matches!(op, Op::Div|Op::Rem) {
+        bail!(LispError::DivisionByZero);
+    }
+
+    let out = match op {
+        Op::Add=>l.checked_add(r),
+        Op::Sub=>l.checked_sub(r),
+        Op::Mul=>l.checked_mul(r),
+        Op::Div=>match l.checked_rem(r) {
+            Some(0)=>l.checked_div(r),
+            Some(_)=>return Ok(Num::Float(l as f64 / r as f64)),
+            None=>None,
+        },
+        Op::Rem=>l.checked_rem(r),
+    };
+
+    match out {
+        Some(n)=>Ok(Num::Int(n)),
+        None=>bail!(LispError::Overflow{op: name.into()}),
+    }
+}
+
+/// Exact ordering of `l` and `r`. `None` if either is NaN.
+pub fn compare(l: Num, r: Num)->Option<Ordering> {
+    match (l, r) {
+        (Num::Int(l), Num::Int(r))=>Some(l.cmp(&r)),
+        (Num::Float(l), Num::Float(r))=>l.partial_cmp(&r),
+        (Num::Int(l), Num::Float(r))=>compare_int_float(l, r),
+        (Num::Float(l), Num::Int(r))=>compare_int_float(r, l).map(Ordering::reverse),
+    }
+}
+
+/// Casting `i` to a float would round it once it is past 2^53, so compare whole parts as `i64`s
+/// instead, then let the fraction break a tie.
+fn compare_int_float(i: i64, f: f64)->Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    }
+    if f >= I64_LIMIT {
+        return Some(Ordering::Less);
+    }
+    if f < -I64_LIMIT {
+        return Some(Ordering::Greater);
+    }
+
+    let whole = f.trunc();
+    let ord = i.cmp(&(whole as i64))
+        .then_with(||0.0.partial_cmp(&(f - whole)).unwrap());
+
+    return Some(ord);
+}
+
+/// `f` truncated toward zero. `op` is what to blame if it has no integer value.
+pub fn to_int(op: &str, f: f64)->Result<i64> {
+    if f.is_nan() || f >= I64_LIMIT || f < -I64_LIMIT {
+        bail!(LispError::NotAnInteger{op: op.into(), value: f});
+    }
+
+    return Ok(f.trunc() as i64);
+}
@@ -0,0 +1,71 @@
+//! How many times each kind of instruction ran. Shared by both interpreters so `--opcode-stats`
+//! looks the same for `run` and `run2`. It is how we pick what to fuse into superinstructions.
+
+
+use std::fmt::{
+    Display,
+    Formatter,
+    Result as FmtResult,
+};
+
+
+/// A count for each opcode, indexed by `Instruction::opcode`
+#[derive(Debug, Clone)]
+pub struct OpcodeCounts {
+    names: &'static [&'static str],
+    counts: Box<[u64]>,
+}
+impl OpcodeCounts {
+    /// `names` is `Instruction::OPCODE_NAMES` of the interpreter this is for
+    pub fn new(names: &'static [&'static str])->Self {
+        OpcodeCounts {
+            names,
+            counts: vec![0; names.len()].into_boxed_slice(),
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, opcode: usize) {
+        self.counts[opcode] += 1;
+    }
+
+    pub fn reset(&mut self) {
+        self.counts.fill(0);
+    }
+
+    pub fn total(&self)->u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Every opcode that ran at least once with how many times it did, most common first
+    pub fn sorted(&self)->Vec<(&'static str, u64)> {
+        let mut out = self.names.iter()
+            .copied()
+            .zip(self.counts.iter().copied())
+            .filter(|(_, count)|*count > 0)
+            .collect::<Vec<_>>();
+        out.sort_by(|l, r|r.1.cmp(&l.1).then(l.0.cmp(r.0)));
+
+        return out;
+    }
+}
+/// A table of the opcodes that ran, with their counts and percentages
+impl Display for OpcodeCounts {
+    fn fmt(&self, f: &mut Formatter)->FmtResult {
+        let rows = self.sorted();
+        let total = self.total();
+        let name_width = rows.iter()
+            .map(|(name, _)|name.len())
+            .max()
+            .unwrap_or(0)
+            .max("Opcode".len());
+        let count_width = total.to_string().len().max("Count".len());
+
+        writeln!(f, "{:<name_width$}  {:>count_width$}  {:>7}", "Opcode", "Count", "%")?;
+        for (name, count) in rows {
+            let percent = count as f64 * 100.0 / total as f64;
+            writeln!(f, "{name:<name_width$}  {count:>count_width$}  {percent:>6.2}%")?;
+        }
+        write!(f, "{:<name_width$}  {total:>count_width$}", "Total")
+    }
+}
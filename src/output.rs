@@ -0,0 +1,104 @@
+//! Where a program's output goes. Interpreters write through an `Output` instead of straight to
+//! stdout, so embedders and tests can capture it. Errors, traces and the debugger still go
+//! straight to stdout or stderr; the sink is flushed before those are printed so they come out in
+//! order.
+
+
+use std::{
+    io::{
+        Result,
+        Write,
+        IsTerminal,
+        stdout,
+    },
+    rc::Rc,
+    cell::{
+        Cell,
+        RefCell,
+    },
+};
+
+
+pub trait Output {
+    fn write_str(&mut self, s: &str)->Result<()>;
+    fn flush(&mut self)->Result<()>;
+
+    /// Whether this goes to a terminal. Natives only style what they print if it does.
+    fn is_terminal(&self)->bool {
+        false
+    }
+}
+
+
+/// The process's stdout. This is the default.
+pub struct Stdout;
+impl Output for Stdout {
+    fn write_str(&mut self, s: &str)->Result<()> {
+        stdout().lock().write_all(s.as_bytes())
+    }
+
+    fn flush(&mut self)->Result<()> {
+        stdout().flush()
+    }
+
+    fn is_terminal(&self)->bool {
+        stdout().is_terminal()
+    }
+}
+
+/// Stdout, but it remembers whether the output stopped in the middle of a line. The REPL uses
+/// this to keep its results on their own lines.
+#[derive(Debug, Clone, Default)]
+pub struct TrackedStdout(Rc<Cell<bool>>);
+impl TrackedStdout {
+    pub fn new()->Self {
+        Self::default()
+    }
+
+    /// Returns true if something was written since the last call and it didn't end with a newline
+    pub fn take_mid_line(&self)->bool {
+        self.0.replace(false)
+    }
+}
+impl Output for TrackedStdout {
+    fn write_str(&mut self, s: &str)->Result<()> {
+        if !s.is_empty() {
+            self.0.set(!s.ends_with('\n'));
+        }
+
+        return Stdout.write_str(s);
+    }
+
+    fn flush(&mut self)->Result<()> {
+        Stdout.flush()
+    }
+
+    fn is_terminal(&self)->bool {
+        Stdout.is_terminal()
+    }
+}
+
+/// Keeps everything written to it. Clones share the same buffer, so keep one to read from after
+/// handing the other to an interpreter.
+#[derive(Debug, Clone, Default)]
+pub struct Captured(Rc<RefCell<String>>);
+impl Captured {
+    pub fn new()->Self {
+        Self::default()
+    }
+
+    /// Everything written so far. The buffer is left empty.
+    pub fn take(&self)->String {
+        self.0.take()
+    }
+}
+impl Output for Captured {
+    fn write_str(&mut self, s: &str)->Result<()> {
+        self.0.borrow_mut().push_str(s);
+        return Ok(());
+    }
+
+    fn flush(&mut self)->Result<()> {
+        Ok(())
+    }
+}
@@ -4,7 +4,6 @@
 use parser_helper::{
     LogosTokenStream,
     LookaheadLexer,
-    SimpleError,
     new_parser,
 };
 use anyhow::{
@@ -12,21 +11,11 @@ use anyhow::{
     Result,
     bail,
 };
-use std::{
-    fmt::{
-        Display,
-        Formatter,
-        Result as FmtResult,
-    },
-    ops::{
-        Fn as FnTrait,
-        Deref,
-    },
-    error::Error,
-};
+use std::ops::Fn as FnTrait;
 use crate::{
     lexer::*,
     ast::*,
+    error::LispError,
 };
 
 
@@ -42,23 +31,15 @@ macro_rules! todo {
 }
 
 
-#[derive(Debug)]
-pub struct ReplContinue(SimpleError<String>);
-impl Error for ReplContinue {}
-impl Display for ReplContinue {
-    fn fmt(&self, f: &mut Formatter)->FmtResult {
-        self.0.fmt(f)
-    }
-}
-impl Deref for ReplContinue {
-    type Target = SimpleError<String>;
-    fn deref(&self)->&Self::Target {
-        &self.0
-    }
-}
+/// Exprs nested deeper than this are an error. Parsing and converting recurse once per level, so
+/// this keeps absurd input from overflowing the stack.
+pub const MAX_NESTING: usize = 256;
+
 
 pub struct ParserData {
     repl: bool,
+    /// How many exprs deep we are. See `MAX_NESTING`.
+    depth: usize,
 }
 
 new_parser!(pub struct MyParser<'a, 1, Token<'a>, LogosTokenStream<'a, Token<'a>>, ParserData>);
@@ -81,7 +62,7 @@ impl<'a> MyParser<'a> {
     fn start_list(&mut self)->Result<()> {
         match self.next() {
             Token::List(Start)=>Ok(()),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Expected `(`")),
         }
     }
@@ -89,7 +70,7 @@ impl<'a> MyParser<'a> {
     fn end_list(&mut self)->Result<()> {
         match self.next() {
             Token::List(End)=>Ok(()),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Expected `)`")),
         }
     }
@@ -105,7 +86,7 @@ impl<'a> MyParser<'a> {
     fn start_vector(&mut self)->Result<()> {
         match self.next() {
             Token::Vector(Start)=>Ok(()),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Expected `[`")),
         }
     }
@@ -113,7 +94,7 @@ impl<'a> MyParser<'a> {
     fn end_vector(&mut self)->Result<()> {
         match self.next() {
             Token::Vector(End)=>Ok(()),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Expected `]`")),
         }
     }
@@ -121,7 +102,7 @@ impl<'a> MyParser<'a> {
     fn start_squiggle(&mut self)->Result<()> {
         match self.next() {
             Token::Squiggle(Start)=>Ok(()),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Expected `{`")),
         }
     }
@@ -129,7 +110,7 @@ impl<'a> MyParser<'a> {
     fn end_squiggle(&mut self)->Result<()> {
         match self.next() {
             Token::Squiggle(End)=>Ok(()),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Expected `}`")),
         }
     }
@@ -141,36 +122,74 @@ impl<'a> MyParser<'a> {
             } else {
                 bail!(self.error(format!("Expected keyword `{i}`")));
             },
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Expected identifier")),
         }
     }
 
     #[inline]
-    fn error<M: Into<String>>(&self, msg: M)->SimpleError<String> {
-        self.0.error(msg)
+    fn error<M: Into<String>>(&self, msg: M)->LispError {
+        LispError::Parse(self.0.error(msg))
+    }
+
+    /// An error for running out of input. The REPL asks for another line instead of printing it.
+    #[inline]
+    fn incomplete<M: Into<String>>(&self, msg: M)->LispError {
+        LispError::Incomplete(self.0.error(msg))
     }
 
     fn ident(&mut self)->Result<&'a str> {
         match self.next() {
             Token::Ident(i)=>Ok(i),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Unexpected token. Expected identifier")),
         }
     }
 
     fn path(&mut self)->Result<Vec<&'a str>> {
         match self.next() {
-            Token::Path(i)=>Ok(i),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::Path(i)=>self.check_path(i),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Unexpected token. Expected path")),
         }
     }
 
+    /// Paths like `a//b` and `a/` lex fine, but an empty segment can't lead anywhere
+    fn check_path(&self, path: Vec<&'a str>)->Result<Vec<&'a str>> {
+        if path.iter().any(|segment|segment.is_empty()) {
+            bail!(self.error("Paths can't have empty segments"));
+        }
+
+        return Ok(path);
+    }
+
+    /// Run `f` one level deeper, or error if that is deeper than `MAX_NESTING`
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self)->Result<T>)->Result<T> {
+        if self.user_data.depth >= MAX_NESTING {
+            bail!(self.error(format!("Exprs can't be nested more than {MAX_NESTING} deep")));
+        }
+
+        self.user_data.depth += 1;
+        let res = f(self);
+        self.user_data.depth -= 1;
+
+        return res;
+    }
+
+    /// An error for a token that can't start an expr, whatever the expr is
+    fn bad_token(&self, token: Token<'a>)->LispError {
+        match token {
+            Token::Number(None)=>self.error("Number doesn't fit in 64 bits"),
+            Token::Byte(_)=>self.error("Byte literals aren't supported yet"),
+            Token::Invalid(s)=>self.error(format!("Invalid token `{s}`")),
+            _=>self.error("Unexpected token"),
+        }
+    }
+
     fn dot_ident(&mut self)->Result<&'a str> {
         match self.next() {
             Token::DotIdent(i)=>Ok(i),
-            Token::EOF if self.user_data.repl=>bail!(ReplContinue(self.error("Unexpected EOF"))),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
             _=>bail!(self.error("Unexpected token. Expected dot identifier")),
         }
     }
@@ -186,13 +205,17 @@ impl<'a> MyParser<'a> {
     }
 
     fn parse_expr(&mut self)->Result<Expr<'a>> {
+        self.nested(Self::parse_expr_inner)
+    }
+
+    fn parse_expr_inner(&mut self)->Result<Expr<'a>> {
         if self.is_next_token(Token::List(Start)) {
             return self.parse_list();
         }
 
         match self.next() {
             Token::ReplDirective(s)=>Ok(Expr::ReplDirective(s)),
-            Token::Number(n)=>Ok(Expr::Number(n)),
+            Token::Number(Some(n))=>Ok(Expr::Number(n)),
             Token::Float(f)=>Ok(Expr::Float(f)),
             Token::String(s)=>Ok(Expr::String(s)),
             Token::Char(c)=>Ok(Expr::Char(c)),
@@ -201,7 +224,7 @@ impl<'a> MyParser<'a> {
             } else {
                 Ok(Expr::Ident(i))
             },
-            Token::Path(path)=>Ok(Expr::Path(path)),
+            Token::Path(path)=>self.check_path(path).map(Expr::Path),
             Token::DotIdent(s)=>Ok(Expr::DotIdent(s)),
             Token::HashLiteral(lit)=>self.match_hash_literal(lit),
             Token::Comment(c)=>Ok(Expr::Comment(c)),
@@ -218,11 +241,11 @@ impl<'a> MyParser<'a> {
             Token::Vector(_)=>bail!(self.error("Vectors are not allowed here")),
             Token::Squiggle(_)=>bail!(self.error("Squiggles are not allowed here")),
             Token::EOF=>if self.user_data.repl {
-                bail!(ReplContinue(self.error("Unexected EOF")));
+                bail!(self.incomplete("Unexected EOF"));
             } else {
                 bail!(self.error("Unexpected EOF"));
             },
-            _=>todo!(),
+            token=>bail!(self.bad_token(token)),
         }
     }
 
@@ -242,14 +265,22 @@ impl<'a> MyParser<'a> {
             Token::Ident(i)=>match *i {
                 "fn"=>return self.parse_fn(),
                 "cond"=>return self.parse_cond(),
-                "def"=>return self.parse_def(),
+                "def"=>return self.parse_def("def", false, false),
+                "def-"=>return self.parse_def("def-", true, false),
+                "defconst"=>return self.parse_def("defconst", false, true),
                 "set"=>return self.parse_set(),
-                "defn"=>return self.parse_defn(),
+                "defn"=>return self.parse_defn(false),
+                "defn-"=>return self.parse_defn(true),
+                "fn-gen"=>return self.parse_fn_gen(),
+                "yield"=>return self.parse_yield(),
                 "quote"=>return self.parse_quote(),
                 "begin"=>return self.parse_begin(),
                 "object"=>return self.parse_object(),
                 "module"=>return self.parse_module(),
+                "use"=>return self.parse_use(),
                 "chain"=>return self.parse_chain(),
+                "breakpoint"=>return self.parse_breakpoint(),
+                "deftest"=>return self.parse_deftest(),
                 _=>{},
             },
             _=>{},
@@ -277,6 +308,8 @@ impl<'a> MyParser<'a> {
         let mut items = vec![Expr::Def {
             name,
             data: Box::new(self.parse_expr()?),
+            private: false,
+            constant: false,
         }];
         self.end_list()?;
 
@@ -300,6 +333,39 @@ impl<'a> MyParser<'a> {
         return Ok(Expr::Module(name));
     }
 
+    fn parse_use(&mut self)->Result<Expr<'a>> {
+        self.match_ident("use")?;
+        let path = match self.next() {
+            Token::Ident(i)=>vec![i],
+            Token::Path(path)=>self.check_path(path)?,
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
+            _=>bail!(self.error("Expected a module name or path")),
+        };
+
+        let mut only = None;
+        let mut alias = None;
+        while !self.try_end_list() {
+            match self.next() {
+                Token::ReplDirective("only") if only.is_none()=>{
+                    let names = self.parse_vector()
+                        .context("Use :only names")?;
+                    if names.remainder.is_some() {
+                        bail!(self.error("`:only` can't have a remainder"));
+                    }
+                    only = Some(names.items);
+                },
+                Token::ReplDirective("as") if alias.is_none()=>{
+                    alias = Some(self.ident().context("Use :as name")?);
+                },
+                Token::ReplDirective(opt @ ("only"|"as"))=>bail!(self.error(format!("`:{opt}` is given more than once"))),
+                Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
+                _=>bail!(self.error("Expected `:only`, `:as` or `)`")),
+            }
+        }
+
+        return Ok(Expr::Use {path, only, alias});
+    }
+
     fn parse_object(&mut self)->Result<Expr<'a>> {
         self.match_ident("object")?;
 
@@ -321,8 +387,8 @@ impl<'a> MyParser<'a> {
                 return Ok(Field::Full(name, e));
             },
             Token::DotIdent(_)=>Ok(Field::Shorthand(self.dot_ident()?)),
-            Token::EOF=>bail!(ReplContinue(self.error("Unexpected token. Expected `(` or `[`"))),
-            _=>bail!("Unexpected token. Expected `(` or DotIdent"),
+            Token::EOF=>bail!(self.incomplete("Unexpected token. Expected `(` or `[`")),
+            _=>bail!(self.error("Unexpected token. Expected `(` or DotIdent")),
         }
     }
 
@@ -334,8 +400,8 @@ impl<'a> MyParser<'a> {
             .context("Begin items");
     }
 
-    fn parse_defn(&mut self)->Result<Expr<'a>> {
-        self.match_ident("defn")?;
+    fn parse_defn(&mut self, private: bool)->Result<Expr<'a>> {
+        self.match_ident(if private {"defn-"} else {"defn"})?;
 
         let name = self.ident()
             .context("Defn name")?;
@@ -345,6 +411,7 @@ impl<'a> MyParser<'a> {
                 name: Some(name),
                 captures,
                 signature,
+                generator: false,
             }))
             .map(Box::new)
             .context("Defn inner")?;
@@ -352,6 +419,8 @@ impl<'a> MyParser<'a> {
         return Ok(Expr::Def {
             name,
             data,
+            private,
+            constant: false,
         });
     }
 
@@ -363,9 +432,45 @@ impl<'a> MyParser<'a> {
             name: None,
             captures,
             signature,
+            generator: false,
         }));
     }
 
+    /// `(fn-gen name [args] body)`. Defines `name` like `defn` does.
+    fn parse_fn_gen(&mut self)->Result<Expr<'a>> {
+        self.match_ident("fn-gen")?;
+
+        let name = self.ident()
+            .context("Fn-gen name")?;
+
+        let (captures, signature) = self.parse_fn_inner()
+            .context("Fn-gen inner")?;
+
+        return Ok(Expr::Def {
+            name,
+            data: Box::new(Expr::Fn(Fn {
+                name: Some(name),
+                captures,
+                signature,
+                generator: true,
+            })),
+            private: false,
+            constant: false,
+        });
+    }
+
+    fn parse_yield(&mut self)->Result<Expr<'a>> {
+        self.match_ident("yield")?;
+
+        let value = self.parse_expr()
+            .map(Box::new)
+            .context("Yield value")?;
+
+        self.end_list().context("End yield")?;
+
+        return Ok(Expr::Yield(value));
+    }
+
     fn parse_fn_inner(&mut self)->Result<(Option<Squiggle<'a>>, FnSignature<'a>)> {
         let captures = match self.peek() {
             Token::Squiggle(Start)=>Some(self.parse_squiggle()?),
@@ -377,7 +482,7 @@ impl<'a> MyParser<'a> {
             Token::List(Start)=>{},    // we are an overloaded function, so continue.
             Token::Vector(Start)=>return self.parse_fn_param_body()
                 .map(|(param, body)|(captures, FnSignature::Single(param, body))),
-            Token::EOF=>bail!(ReplContinue(self.error("Unexpected token. Expected `(` or `[`"))),
+            Token::EOF=>bail!(self.incomplete("Unexpected token. Expected `(` or `[`")),
             _=>{
                 self.next();
                 bail!(self.error("Unexpected token. Expected `(` or `[`"));
@@ -442,8 +547,25 @@ impl<'a> MyParser<'a> {
         return Ok((condition, body));
     }
 
-    fn parse_def(&mut self)->Result<Expr<'a>> {
-        self.match_ident("def")?;
+    fn parse_def(&mut self, keyword: &'static str, private: bool, constant: bool)->Result<Expr<'a>> {
+        self.match_ident(keyword)?;
+
+        if self.is_next_token(Token::Vector(Start)) {
+            let names = self.parse_vector()
+                .context("Def names")?;
+            let data = self.parse_expr()
+                .map(Box::new)
+                .context("Def data")?;
+            self.end_list()
+                .context("End def")?;
+
+            return Ok(Expr::DefVector {
+                names,
+                data,
+                private,
+                constant,
+            });
+        }
 
         let name = self.ident()
             .context("Def name")?;
@@ -464,6 +586,8 @@ impl<'a> MyParser<'a> {
         return Ok(Expr::Def {
             name,
             data,
+            private,
+            constant,
         });
     }
 
@@ -473,7 +597,8 @@ impl<'a> MyParser<'a> {
         match self.peek() {
             Token::Ident(_)=>self.parse_set_ident(),
             Token::Path(_)=>self.parse_set_path(),
-            _=>bail!("Expected Ident or Path"),
+            Token::EOF if self.user_data.repl=>bail!(self.incomplete("Unexpected EOF")),
+            _=>bail!(self.error("Expected Ident or Path")),
         }
     }
 
@@ -511,6 +636,30 @@ impl<'a> MyParser<'a> {
         });
     }
 
+    fn parse_breakpoint(&mut self)->Result<Expr<'a>> {
+        self.match_ident("breakpoint")?;
+        self.end_list().context("End breakpoint")?;
+
+        return Ok(Expr::Breakpoint);
+    }
+
+    fn parse_deftest(&mut self)->Result<Expr<'a>> {
+        self.match_ident("deftest")?;
+
+        let name = self.ident()
+            .context("Deftest name")?;
+
+        let body = self.parse_end_listed_items(Self::parse_expr)
+            .context("Deftest body")?;
+
+        return Ok(Expr::Test(Fn {
+            name: Some(name),
+            captures: None,
+            signature: FnSignature::Single(Vector {items: Vec::new(), remainder: None}, body),
+            generator: false,
+        }));
+    }
+
     fn parse_quote(&mut self)->Result<Expr<'a>> {
         self.match_ident("quote")?;
 
@@ -532,7 +681,7 @@ impl<'a> MyParser<'a> {
         while !self.is_next_token(Token::Squiggle(End)) {
             match self.next() {
                 Token::Ident(i)=>items.push(i),
-                Token::EOF=>bail!(ReplContinue(self.error("Unexpected token. Expected `(` or `[`"))),
+                Token::EOF=>bail!(self.incomplete("Unexpected token. Expected `(` or `[`")),
                 _=>bail!(self.error("Squiggles can only have identifiers")),
             }
         }
@@ -558,7 +707,7 @@ impl<'a> MyParser<'a> {
                     break;
                 },
                 Token::Ident(i)=>items.push(i),
-                Token::EOF=>bail!(ReplContinue(self.error("Unexpected token. Expected `(` or `[`"))),
+                Token::EOF=>bail!(self.incomplete("Unexpected token. Expected `(` or `[`")),
                 _=>bail!(self.error("Vectors can only have identifiers")),
             }
         }
@@ -572,8 +721,12 @@ impl<'a> MyParser<'a> {
     }
 
     fn parse_expr_quoted(&mut self)->Result<Expr<'a>> {
+        self.nested(Self::parse_expr_quoted_inner)
+    }
+
+    fn parse_expr_quoted_inner(&mut self)->Result<Expr<'a>> {
         match self.next() {
-            Token::Number(n)=>Ok(Expr::Number(n)),
+            Token::Number(Some(n))=>Ok(Expr::Number(n)),
             Token::Float(f)=>Ok(Expr::Float(f)),
             Token::String(s)=>Ok(Expr::String(s)),
             Token::Char(c)=>Ok(Expr::Char(c)),
@@ -583,7 +736,7 @@ impl<'a> MyParser<'a> {
             } else {
                 Ok(Expr::Ident(i))
             },
-            Token::Path(path)=>Ok(Expr::Path(path)),
+            Token::Path(path)=>self.check_path(path).map(Expr::Path),
             Token::HashLiteral(lit)=>self.match_hash_literal(lit),
             Token::Comment(c)=>Ok(Expr::Comment(c)),
             Token::Quote=>self.parse_expr_quoted()
@@ -607,12 +760,12 @@ impl<'a> MyParser<'a> {
             Token::Squiggle(End)=>bail!(self.error("Unexpected `}`")),
             Token::List(End)=>bail!(self.error("Unexpected `)`")),
             Token::EOF=>if self.user_data.repl {
-                bail!(ReplContinue(self.error("Unexpected EOF")));
+                bail!(self.incomplete("Unexpected EOF"));
             } else {
                 bail!(self.error("Unexpected EOF"));
             },
             Token::ReplDirective(_)=>bail!(self.error("Repl directives are only allowed at the root level")),
-            _=>todo!(),
+            token=>bail!(self.bad_token(token)),
         }
     }
 
@@ -646,10 +799,10 @@ impl<'a> MyParser<'a> {
 
 pub fn new_parser<'a>(source: &'a str)->MyParser<'a> {
     use logos::Logos;
-    MyParser::new(Token::lexer(source), ParserData {repl: false})
+    MyParser::new(Token::lexer(source), ParserData {repl: false, depth: 0})
 }
 
 pub fn repl_new_parser<'a>(source: &'a str)->MyParser<'a> {
     use logos::Logos;
-    MyParser::new(Token::lexer(source), ParserData {repl: true})
+    MyParser::new(Token::lexer(source), ParserData {repl: true, depth: 0})
 }
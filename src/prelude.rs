@@ -0,0 +1,18 @@
+//! The part of the standard library written in lisp. Both interpreters convert it into the root
+//! module before the program and run it first, so its functions are globals.
+
+
+use crate::{
+    ast::Expr,
+    parser::new_parser,
+};
+
+
+pub const SOURCE: &str = include_str!("prelude.slp");
+
+
+/// Parse the prelude. It ships with the binary, so failing to parse is our bug.
+pub fn exprs()->Vec<Expr<'static>> {
+    return new_parser(SOURCE).parse_all()
+        .unwrap_or_else(|e|panic!("The prelude failed to parse: {e}"));
+}
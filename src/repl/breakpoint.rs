@@ -0,0 +1,132 @@
+//! The REPL `(breakpoint)` opens. Lines are run in the paused call, so its variables can be read
+//! and `set`. `:continue` resumes the program and `:abort` stops it with an error.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use crate::{
+    interpreter::{
+        ast::{
+            ConvertState,
+            repl_convert,
+        },
+        data::Data,
+        pretty::{
+            PrettyConfig,
+            pretty_format,
+            preview,
+        },
+        Interpreter,
+    },
+    parser::repl_new_parser,
+    error::LispError,
+    error_trace,
+};
+use super::{
+    Editor,
+    PREVIEW_WIDTH,
+};
+
+
+pub fn breakpoint_repl(interpreter: &mut Interpreter, state: &mut ConvertState)->Result<()> {
+    match interpreter.current_fn().and_then(|id|state.fns.get(id)?.name) {
+        Some(name)=>println!("Paused in `{}`", state.interner.get(name)),
+        None=>println!("Paused at the top level"),
+    }
+    print_locals(interpreter, state);
+    println!("Type `:continue` to resume or `:abort` to stop the program");
+
+    let mut editor = Editor::new("(break) ");
+    loop {
+        // Ctrl-D resumes, just like `:continue`
+        let Some(source) = editor.read()? else {return Ok(())};
+
+        match source.trim() {
+            ":continue"|":c"=>return Ok(()),
+            ":abort"=>bail!("Aborted at a breakpoint"),
+            ":locals"=>{
+                print_locals(interpreter, state);
+                editor.commit(source);
+                continue;
+            },
+            ":help"=>{
+                print_help();
+                editor.commit(source);
+                continue;
+            },
+            _=>{},
+        }
+
+        let mut parser = repl_new_parser(source.as_str());
+        let exprs = match parser.parse_all() {
+            Ok(exprs)=>exprs,
+            Err(e)=>{
+                drop(parser);
+
+                // keep reading lines until the expression is finished
+                if let Some(LispError::Incomplete(_)) = e.root_cause().downcast_ref::<LispError>() {
+                    continue;
+                }
+
+                error_trace(e, source.as_str(), "<breakpoint>");
+                editor.commit(source);
+                continue;
+            },
+        };
+        drop(parser);
+
+        if exprs.len() == 0 {
+            editor.commit(source);
+            continue;
+        }
+
+        let start_id = match repl_convert(state, exprs) {
+            Ok(start_id)=>start_id,
+            Err(e)=>{
+                error_trace(e, source.as_str(), "<breakpoint>");
+                editor.commit(source);
+                continue;
+            },
+        };
+
+        match interpreter.run_in_frame(state, start_id) {
+            Ok(Some(dr))=>if !matches!(&*dr.get_data(), Data::None) {
+                let pretty = pretty_format(&dr, &state.interner, &PrettyConfig::for_terminal());
+                println!(">> {}", pretty.replace('\n', "\n   "));
+            },
+            Ok(None)=>{},
+            Err(e)=>error_trace(e, source.as_str(), "<breakpoint>"),
+        }
+
+        editor.commit(source);
+    }
+}
+
+fn print_locals(interpreter: &Interpreter, state: &ConvertState) {
+    let mut locals = interpreter.locals()
+        .map(|(name, dr)|(state.interner.get(name), preview(dr, &state.interner, PREVIEW_WIDTH)))
+        .collect::<Vec<_>>();
+    if locals.is_empty() {
+        println!("No locals");
+        return;
+    }
+
+    locals.sort_by(|l, r|l.0.cmp(r.0));
+    let name_width = locals.iter().map(|l|l.0.chars().count()).max().unwrap_or(0);
+    for (name, preview) in locals {
+        println!("    {name:<name_width$}  {preview}");
+    }
+}
+
+fn print_help() {
+    println!(r#"Breakpoint help:"#);
+    println!(r#"    :continue, :c       Resumes the program. So does <Ctrl+d>."#);
+    println!(r#"    :abort              Stops the program with an error"#);
+    println!(r#"    :locals             Lists the variables of the paused call"#);
+    println!(r#"    :help               Display this message"#);
+    println!();
+    println!(r#"Anything else is run in the paused call, so `(set NAME VALUE)` changes its"#);
+    println!(r#"variables."#);
+}
@@ -0,0 +1,340 @@
+//! An interactive stepper for the V2 interpreter. It pauses before the first instruction, and
+//! then whenever a step finishes or a breakpoint is hit. Commands are read with the REPL's editor,
+//! or a line at a time if stdin isn't a terminal so they can be piped in.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use std::io::{
+    IsTerminal,
+    Lines,
+    StdinLock,
+    stdin,
+};
+use crate::{
+    interpreter2::{
+        ast::{
+            ConvertState,
+            FnId,
+            Ident,
+            Instruction,
+            InstructionId,
+        },
+        debug::{
+            DebugHook,
+            fn_name,
+            value_text,
+        },
+        FxIndexMap,
+        FxIndexSet,
+        Interpreter,
+    },
+    suggest::{
+        did_you_mean,
+        similar_names,
+    },
+};
+use super::Editor;
+
+
+/// Values are cut off after this many chars
+const VALUE_WIDTH: usize = 60;
+
+/// Every command name, for suggesting one when the user mistypes it
+const COMMANDS: &[&str] = &["step", "next", "continue", "break", "stack", "locals", "print", "where", "help", "quit"];
+
+
+pub(super) enum Input {
+    Editor(Editor),
+    /// stdin isn't a terminal, so read plain lines from it
+    Lines(Lines<StdinLock<'static>>),
+}
+impl Input {
+    /// The editor with `prompt`, or plain lines if stdin isn't a terminal
+    pub(super) fn new(prompt: &'static str)->Self {
+        match stdin().is_terminal() {
+            true=>Self::Editor(Editor::new(prompt)),
+            false=>Self::Lines(stdin().lines()),
+        }
+    }
+
+    /// The next command, or `None` once there are no more
+    pub(super) fn read(&mut self)->Option<String> {
+        match self {
+            Self::Editor(editor)=>{
+                let line = editor.read().ok()??;
+                editor.commit(line.clone());
+
+                return Some(line);
+            },
+            Self::Lines(lines)=>lines.next()?.ok(),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Mode {
+    /// Pause before the next instruction
+    Step,
+    /// Pause before the next instruction at most this many calls deep, so calls are stepped over
+    Next(usize),
+    /// Only pause at breakpoints
+    Continue,
+}
+
+pub struct Debugger {
+    input: Input,
+    mode: Mode,
+    breakpoints: FxIndexSet<InstructionId>,
+    /// The function each body belongs to and the names of its first local slots
+    bodies: FxIndexMap<InstructionId, (FnId, Vec<Ident>)>,
+    /// The names of the local slots in each call frame, outermost first
+    frames: Vec<Vec<Ident>>,
+}
+impl Debugger {
+    pub fn new(state: &ConvertState)->Self {
+        let mut bodies = FxIndexMap::default();
+        for id in state.fn_ids() {
+            let Some(f) = state.fns.get(id) else {continue};
+            for (params, body_ptr) in f.sig.bodies() {
+                let names = f.captures.iter()
+                    .chain(params.items.iter())
+                    .chain(params.remainder.iter())
+                    .copied()
+                    .collect();
+                bodies.insert(body_ptr, (id, names));
+            }
+        }
+
+        Debugger {
+            input: Input::new("(debug) "),
+            mode: Mode::Step,
+            breakpoints: FxIndexSet::default(),
+            bodies,
+            frames: vec![Vec::new()],
+        }
+    }
+
+    /// Read and run commands until one resumes the program
+    fn prompt(&mut self, interpreter: &Interpreter, state: &ConvertState)->Result<()> {
+        loop {
+            let Some(line) = self.input.read() else {
+                // nobody is left to type commands, so just finish the program
+                self.mode = Mode::Continue;
+                return Ok(());
+            };
+            let (command, arg) = match line.trim().split_once(char::is_whitespace) {
+                Some((command, arg))=>(command, arg.trim()),
+                None=>(line.trim(), ""),
+            };
+
+            match command {
+                ""=>{},
+                "step"|"s"=>{
+                    self.mode = Mode::Step;
+                    return Ok(());
+                },
+                "next"|"n"=>{
+                    self.mode = Mode::Next(interpreter.call_depth());
+                    return Ok(());
+                },
+                "continue"|"c"=>{
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                },
+                "break"|"b"=>self.add_breakpoint(arg, state),
+                "stack"|"bt"=>self.print_stack(interpreter, state),
+                "locals"=>self.print_locals(interpreter, state),
+                "print"|"p"=>self.print_var(arg, interpreter, state),
+                "where"|"w"=>self.print_where(interpreter, state),
+                "help"|"h"=>print_help(),
+                "quit"|"q"=>bail!("Stopped by the debugger"),
+                _=>{
+                    let similar = similar_names(command, COMMANDS.iter().copied());
+                    match did_you_mean(&similar) {
+                        Some(hint)=>println!("Unknown command `{command}`, {hint}"),
+                        None=>println!("Unknown command `{command}`. Type `help` for a list."),
+                    }
+                },
+            }
+        }
+    }
+
+    /// Break on the instruction id `arg`, or at the start of every body of the function named
+    /// `arg`
+    fn add_breakpoint(&mut self, arg: &str, state: &ConvertState) {
+        if arg.is_empty() {
+            println!("Usage: break <fn-name | instruction-id>");
+            return;
+        }
+
+        if let Ok(id) = arg.parse::<usize>() {
+            if id >= state.instructions.raw_instructions().len() {
+                println!("There is no instruction with the id {id}");
+                return;
+            }
+
+            self.breakpoints.insert(InstructionId::from_inner(id));
+            println!("Breakpoint set at Id({id})");
+            return;
+        }
+
+        let found = self.bodies.iter()
+            .filter(|(_, (id, _))|fn_name(*id, state) == arg)
+            .map(|(body_ptr, _)|*body_ptr)
+            .collect::<Vec<_>>();
+        if found.is_empty() {
+            let names = self.bodies.values()
+                .map(|(id, _)|fn_name(*id, state))
+                .collect::<Vec<_>>();
+            let similar = similar_names(arg, names.iter().copied());
+            match did_you_mean(&similar) {
+                Some(hint)=>println!("There is no function named `{arg}`, {hint}"),
+                None=>println!("There is no function named `{arg}`"),
+            }
+            return;
+        }
+
+        for body_ptr in found {
+            self.breakpoints.insert(body_ptr);
+            println!("Breakpoint set at `{arg}` (Id({}))", body_ptr.inner());
+        }
+    }
+
+    fn print_stack(&self, interpreter: &Interpreter, state: &ConvertState) {
+        for (i, func) in interpreter.call_frames().enumerate() {
+            match func {
+                Some(id)=>println!("#{i} {}", fn_name(id, state)),
+                None=>println!("#{i} <not a function>"),
+            }
+        }
+        println!("#{} <top level>", interpreter.call_depth());
+    }
+
+    fn print_locals(&self, interpreter: &Interpreter, state: &ConvertState) {
+        let locals = interpreter.locals();
+        if locals.is_empty() {
+            println!("No locals");
+            return;
+        }
+
+        let names = self.frames.last().map(Vec::as_slice).unwrap_or_default();
+        for (slot, value) in locals.iter().enumerate() {
+            match names.get(slot) {
+                Some(name)=>println!("{} = {}", state.interner.get(*name), value_text(value, state, VALUE_WIDTH)),
+                None=>println!("%{slot} = {}", value_text(value, state, VALUE_WIDTH)),
+            }
+        }
+    }
+
+    /// Locals shadow globals, and later locals shadow earlier ones
+    fn print_var(&self, name: &str, interpreter: &Interpreter, state: &ConvertState) {
+        if name.is_empty() {
+            println!("Usage: print <var>");
+            return;
+        }
+
+        let names = self.frames.last().map(Vec::as_slice).unwrap_or_default();
+        let local = state.interner.lookup(name)
+            .and_then(|ident|names.iter().rposition(|n|*n == ident))
+            .and_then(|slot|interpreter.locals().get(slot));
+        if let Some(value) = local {
+            println!("{name} = {}", value_text(value, state, VALUE_WIDTH));
+            return;
+        }
+
+        match state.lookup_var(name).filter(|slot|slot.global) {
+            Some(slot)=>match interpreter.global(slot.id) {
+                Some(value)=>println!("{name} = {}", value_text(value, state, VALUE_WIDTH)),
+                None=>println!("`{name}` is not set yet"),
+            },
+            None=>println!("{}", state.undefined_var(name)),
+        }
+    }
+
+    /// The line of source the paused instruction came from
+    fn print_where(&self, interpreter: &Interpreter, state: &ConvertState) {
+        let location = interpreter.last_instruction(state)
+            .and_then(|id|state.source_map.get()?.locate(id));
+        let Some(location) = location else {
+            println!("There is no source for this instruction");
+            return;
+        };
+
+        println!("At {}:{}:{}", location.path.display(), location.line, location.column);
+        let text = location.source.as_deref()
+            .and_then(|source|source.lines().nth(location.line - 1));
+        if let Some(text) = text {
+            println!("{} | {text}", location.line);
+        }
+    }
+
+    /// Track which names the local slots have. This runs before `ins` does, which is close enough
+    /// since nothing can read a slot before its scope starts.
+    fn track_scopes(&mut self, id: InstructionId, ins: &Instruction, interpreter: &Interpreter, state: &ConvertState) {
+        self.frames.resize_with(interpreter.call_depth() + 1, Vec::new);
+        let names = self.frames.last_mut().unwrap();
+
+        if let Some((_, params)) = self.bodies.get(&id) {
+            *names = params.clone();
+        }
+
+        match ins {
+            Instruction::Scope(count)=>match state.scope_names.get(&id) {
+                Some(scope)=>names.extend(scope.iter().copied()),
+                // compiled files don't have the names
+                None=>names.truncate(interpreter.locals().len() + count),
+            },
+            Instruction::EndScope(count)=>{
+                let len = names.len().saturating_sub(*count);
+                names.truncate(len);
+            },
+            _=>{},
+        }
+    }
+}
+impl DebugHook for Debugger {
+    fn before_instruction(&mut self, id: InstructionId, ins: &Instruction, interpreter: &Interpreter, state: &ConvertState)->Result<()> {
+        let depth = interpreter.call_depth();
+        let hit_breakpoint = self.breakpoints.contains(&id);
+        let pause = hit_breakpoint || match self.mode {
+            Mode::Step=>true,
+            Mode::Next(max_depth)=>depth <= max_depth,
+            Mode::Continue=>false,
+        };
+
+        if pause {
+            if hit_breakpoint {
+                println!("Breakpoint hit");
+            }
+            let func = interpreter.call_frames()
+                .next()
+                .flatten()
+                .map(|id|fn_name(id, state))
+                .unwrap_or("<top level>");
+            println!("{func} Id({}): {}", id.inner(), ins.asm(state));
+
+            self.prompt(interpreter, state)?;
+        }
+
+        self.track_scopes(id, ins, interpreter, state);
+
+        return Ok(());
+    }
+}
+
+
+fn print_help() {
+    println!(r#"Commands:"#);
+    println!(r#"    step, s             Run one instruction"#);
+    println!(r#"    next, n             Run one instruction, stepping over calls"#);
+    println!(r#"    continue, c         Run until the next breakpoint"#);
+    println!(r#"    break, b NAME|ID    Pause at the start of function `NAME` or instruction `ID`"#);
+    println!(r#"    stack, bt           Print the call stack"#);
+    println!(r#"    locals              Print the locals of the current call"#);
+    println!(r#"    print, p VAR        Print a local or global variable"#);
+    println!(r#"    where, w            Print the source the next instruction came from"#);
+    println!(r#"    quit, q             Stop the program"#);
+}
@@ -0,0 +1,605 @@
+//! The line editor the REPL and the debugger read input with. It highlights with tree-sitter, keeps
+//! a history, and only finishes a line once its brackets are balanced.
+
+
+use anyhow::Result;
+use unicode_width::{
+    UnicodeWidthChar,
+    UnicodeWidthStr,
+};
+use ropey::{
+    Rope,
+    RopeSlice,
+};
+use crossterm::{
+    terminal::{
+        BeginSynchronizedUpdate,
+        EndSynchronizedUpdate,
+        Clear,
+        ClearType,
+        ScrollUp,
+        enable_raw_mode,
+        disable_raw_mode,
+        size as terminal_size,
+    },
+    event::{
+        Event,
+        KeyCode,
+        KeyModifiers,
+        read as read_event,
+    },
+    style::{
+        Color,
+        Stylize,
+    },
+    cursor::{
+        MoveDown,
+        MoveToColumn,
+        MoveToRow,
+        Show as ShowCursor,
+        position as cursor_position,
+    },
+    queue,
+};
+use tree_sitter::{
+    QueryCursor,
+    Node,
+    Parser as TsParser,
+    Query as TsQuery,
+};
+use std::{
+    io::{
+        Stdout,
+        Write,
+    },
+    collections::HashMap,
+    sync::OnceLock,
+    mem,
+};
+use super::colors;
+
+
+const HIGHLIGHT_QUERY: &str = include_str!("highlights.scm");
+
+
+static COLOR_MAP: OnceLock<Vec<Color>> = OnceLock::new();
+
+
+#[derive(Copy, Clone)]
+pub struct Cursor {
+    pub line: usize,
+    pub col: usize,
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    stdout: Stdout,
+    ts_parser: TsParser,
+    ts_query: TsQuery,
+    rope: Rope,
+    cursor_idx: usize,
+    indent_level: usize,
+    cursor: Cursor,
+    /// Printed before the first line
+    prompt: String,
+    /// Printed before the rest. See `continuation_prompt`.
+    continuation: String,
+}
+impl Editor {
+    pub fn new(prompt: &str)->Self {
+        let mut ts_parser = TsParser::new();
+        let lang = tree_sitter_simplelisp::language();
+        ts_parser.set_language(&lang).expect("Error loading simplelisp grammar");
+        let ts_query = TsQuery::new(&lang, HIGHLIGHT_QUERY)
+            .expect("Could not load builtin simplelisp highlight query");
+
+        // Generate the color map at runtime. This is easier since I don't have to worry about the
+        // thing desyncing if I change the `highlights.scm` file or `colors::COLORS` array.
+        COLOR_MAP.get_or_init(||{
+            let raw_color_map: HashMap<&str, Color> = colors::COLORS.into_iter().copied().collect();
+            ts_query.capture_names()
+                .iter()
+                .map(|cap|raw_color_map.get(cap).copied().unwrap())
+                .collect()
+        });
+
+        Editor {
+            history: Vec::new(),
+            stdout: std::io::stdout(),
+            ts_parser,
+            ts_query,
+            rope: Rope::new(),
+            cursor_idx: 0,
+            indent_level: 0,
+            cursor: Cursor {
+                line: 0,
+                col: 0,
+            },
+            prompt: prompt.to_string(),
+            continuation: continuation_prompt(prompt),
+        }
+    }
+
+    /// Use `prompt` from the next `read` on
+    pub fn set_prompt(&mut self, prompt: &str) {
+        if prompt != self.prompt {
+            self.prompt = prompt.to_string();
+            self.continuation = continuation_prompt(prompt);
+        }
+    }
+
+    /// Let the user edit until they press enter with balanced brackets. Returns what they wrote
+    /// without the trailing whitespace, or `None` if they pressed <Ctrl+d> on an empty buffer.
+    /// The buffer is kept until `commit`, so an unfinished expression can be read again and
+    /// continued.
+    pub fn read(&mut self)->Result<Option<String>> {
+        self.reset_cursor();
+        self.prompt_line()?;
+        if self.rope.len_chars() == 0 {
+            return Ok(None);
+        }
+
+        let mut source = self.rope.to_string();
+        while source.ends_with(['\n', ' ']) {
+            source.pop();
+        }
+        println!();
+
+        return Ok(Some(source));
+    }
+
+    /// Clear the buffer and add `source` to the history
+    pub fn commit(&mut self, source: String) {
+        self.history.push(source);
+        self.rope = Rope::new();
+    }
+
+    fn reset_cursor(&mut self) {
+        self.cursor.line = 0;
+        self.cursor.col = 0;
+        self.cursor_idx = 0;
+        self.indent_level = 0;
+    }
+
+    // ----- Adding chars or strings things
+
+    fn add_char(&mut self, c: char) {
+        if c == '\n' {return self.newline()}
+        if self.cursor_idx == self.line().len_chars() && self.cursor.line > 0 {
+            self.rope.insert_char(self.cursor_idx.saturating_sub(1), c);
+        } else {
+            self.rope.insert_char(self.cursor_idx, c);
+        }
+        self.cursor_right();
+    }
+
+    fn newline(&mut self) {
+        self.rope.insert_char(self.cursor_idx, '\n');
+        self.cursor_down();
+        self.cursor_home();
+        for _ in 0..(self.indent_level * 4) {
+            self.add_char(' ');
+        }
+    }
+
+    #[inline]
+    fn add_str(&mut self, s: &str) {
+        s.chars()
+            .for_each(|c|self.add_char(c));
+    }
+
+    // ----- Cursor things
+
+    fn compute_cursor_idx(&mut self) {
+        let line = self.rope.line(self.cursor.line.min(self.rope.len_lines().saturating_sub(1)));
+        let line_char_idx = self.rope.line_to_char(self.cursor.line);
+
+        let char_idx = self.cursor.col.min(line.len_chars());
+
+        self.cursor_idx = line_char_idx + char_idx;
+    }
+
+    fn cursor_up(&mut self) {
+        if self.cursor.line != 0 {
+            self.cursor.line -= 1;
+            self.compute_cursor_idx();
+        }
+    }
+
+    fn cursor_down(&mut self) {
+        if self.cursor.line + 1 < self.rope.len_lines() {
+            self.cursor.line += 1;
+            self.compute_cursor_idx();
+        } else if self.cursor.line >= self.rope.len_lines() {
+            self.cursor.line = self.rope.len_lines() - 1;
+            self.compute_cursor_idx();
+        }
+    }
+
+    fn cursor_left(&mut self) {
+        if self.cursor.col > 0 {
+            let line = self.line();
+            let mut line_end = line.len_chars().saturating_sub(1);
+            if self.line_ends_with_nl() {
+                line_end = line_end.saturating_sub(1);
+            }
+            if self.cursor.col > line_end {
+                self.cursor.col = line_end;
+                self.compute_cursor_idx();
+            } else {
+                self.cursor.col -= 1;
+            }
+            self.compute_cursor_idx();
+        }
+    }
+
+    fn cursor_right(&mut self) {
+        if self.rope.len_chars() > 0 {
+            if self.char() != '\n' {
+                let line = self.rope.line(self.cursor.line);
+                if self.cursor.col < line.len_chars() {
+                    self.cursor.col += 1;
+                }
+                self.compute_cursor_idx();
+            }
+        }
+    }
+
+    fn cursor_home(&mut self) {
+        self.cursor.col = 0;
+        self.compute_cursor_idx();
+    }
+
+    fn cursor_end(&mut self) {
+        // let line = self.line();
+        self.cursor.col = usize::MAX;
+
+        self.compute_cursor_idx();
+    }
+
+    // ----- Removal things
+
+    fn backspace(&mut self) {
+        if self.cursor_idx == 0 {
+            return;
+        }
+        self.cursor_idx -= 1;
+
+        self.delete();
+        if self.cursor.col == 0 {
+            self.cursor.line -= 1;
+            self.cursor_end();
+        } else {
+            self.cursor.col -= 1;
+        }
+    }
+
+    #[inline]
+    fn delete(&mut self) {
+        if self.cursor_idx >= self.rope.len_chars() {return}
+        self.rope.remove(self.cursor_idx..=self.cursor_idx);
+    }
+
+    // ----- Indexing helpers
+
+    fn line_ends_with_nl(&self)->bool {
+        let line = self.line();
+        if line.len_chars() == 0 {return false}
+        line.char(line.len_chars() - 1) == '\n'
+    }
+    
+    // #[inline]
+    // fn prev_char(&self)->char {
+    //     self.rope.char(self.cursor_idx.saturating_sub(1))
+    // }
+
+    #[inline]
+    fn char(&self)->char {
+        self.rope.char(self.cursor_idx.min(self.rope.len_chars() - 1))
+    }
+
+    fn line(&self)->RopeSlice {
+        let line = self.cursor.line.min(self.rope.len_lines().saturating_sub(1));
+        self.rope.line(line)
+    }
+
+    // ----- Display/Input handling things
+
+    fn prompt_line(&mut self)->Result<()> {
+        let mut prev_row = cursor_position()?.1;
+        let mut prev_lines = self.rope.len_lines();
+
+        let mut prev_rope: Option<(Cursor, Rope)> = None;
+        let mut history_item = self.history.len();
+
+        prev_row = self.render_buffer(prev_row, 0)?;
+
+        enable_raw_mode().unwrap();
+
+        loop {
+            match read_event()? {
+                Event::Key(key_event)=>{
+                    let shift = key_event.modifiers == KeyModifiers::SHIFT;
+                    if key_event.modifiers.is_empty() || shift {
+                        match key_event.code {
+                            KeyCode::Backspace=>self.backspace(),
+                            KeyCode::Delete=>self.delete(),
+                            KeyCode::Enter=>{
+                                self.newline();
+                                if self.check_code() {
+                                    break;
+                                }
+                            },
+
+                            // Ensure Shift works as expected, regardless of what char we get
+                            KeyCode::Char(c)=>if shift {
+                                self.add_char(c.to_ascii_uppercase());
+                            } else {
+                                self.add_char(c);
+                            },
+
+                            // Indent things
+                            KeyCode::BackTab=>{ // Shift + Tab
+                                if self.rope.len_chars() != 0 {
+                                let mut prev_cursor = self.cursor;
+                                    self.cursor_home();
+                                    for _ in 0..4 {
+                                        if self.rope.len_chars() == 0 {break}
+                                        if self.char() != ' ' {break}
+                                        self.delete();
+                                        prev_cursor.col = prev_cursor.col.saturating_sub(1);
+                                    }
+                                    self.indent_level = self.indent_level.saturating_sub(1);
+                                    self.cursor = prev_cursor;
+                                    self.compute_cursor_idx();
+                                }
+                            },
+                            KeyCode::Tab=>{
+                                let mut prev_cursor = self.cursor;
+                                prev_cursor.col += 4;
+                                self.indent_level += 1;
+                                self.cursor_home();
+                                self.add_str("    ");
+                                self.cursor = prev_cursor;
+                                self.compute_cursor_idx();
+                            },
+
+                            KeyCode::Left=>self.cursor_left(),
+                            KeyCode::Right=>self.cursor_right(),
+                            KeyCode::Up=>self.cursor_up(),
+                            KeyCode::Down=>self.cursor_down(),
+                            KeyCode::Home=>self.cursor_home(),
+                            KeyCode::End=>self.cursor_end(),
+                            KeyCode::PageUp=>{
+                                if history_item > 0 {
+                                    history_item = (history_item - 1).min(self.history.len().saturating_sub(1));
+                                    if history_item < self.history.len() {
+                                        let new_rope = Rope::from(self.history[history_item].as_str());
+                                        prev_rope = Some((self.cursor, mem::replace(&mut self.rope, new_rope)));
+                                        self.reset_cursor();
+                                    }
+                                }
+                            },
+                            KeyCode::PageDown=>{
+                                history_item = (history_item + 1).min(self.history.len().max(1));
+                                if history_item < self.history.len() {
+                                    self.rope = Rope::from(self.history[history_item].as_str());
+                                    self.reset_cursor();
+                                } else if prev_rope.is_some() {
+                                    let (cursor, rope) = prev_rope.take().unwrap();
+                                    self.rope = rope;
+                                    self.cursor = cursor;
+                                }
+                            },
+
+                            _=>{},
+                        }
+                    } else if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                        match key_event.code {
+                            KeyCode::Char('d'|'D')=>break,
+                            KeyCode::Char('w'|'W')=>{
+                                if self.rope.len_chars() > 0 {
+                                    match self.char() {
+                                        '/'|':'|';'|'\\'|'.'|'\t'|'\r'|'\n'|'('|')'|'['|']'|'{'|'}'|'"'|'\''|'#'=>{
+                                            self.backspace();
+                                        },
+                                        _=>{
+                                            while self.rope.len_chars() > 0 {
+                                                match self.char() {
+                                                    '/'|':'|';'|'\t'|'\r'|'\n'|'('|')'|'['|']'|'{'|'}'|'"'|'\''|'#'=>{
+                                                        break;
+                                                    },
+                                                    _=>self.backspace(),
+                                                }
+                                            }
+                                        },
+                                    }
+                                }
+                            },
+                            _=>{},
+                        }
+                    }
+                },
+                _=>{},
+            }
+
+            prev_row = self.render_buffer(prev_row, prev_lines)?;
+            prev_lines = self.rope.len_lines();
+        }
+
+        disable_raw_mode().unwrap();
+
+        return Ok(());
+    }
+
+    fn render_buffer(&mut self, mut prev_row: u16, prev_lines: usize)->Result<u16> {
+        queue!(&mut self.stdout, BeginSynchronizedUpdate)?;
+        // TODO: treesitter highlighting!
+        let last_line = self.rope.len_lines().saturating_sub(1);
+        let size = terminal_size()?.1;
+        let mut position = prev_row;
+
+        if prev_lines > 0 {
+            queue!(&mut self.stdout, MoveToRow(prev_row), ShowCursor)?;
+            for _ in 0..prev_lines {
+                queue!(&mut self.stdout, Clear(ClearType::CurrentLine), MoveDown(1))?;
+            }
+            queue!(&mut self.stdout, MoveToRow(prev_row))?;
+        }
+
+        let tree = self.ts_parser.parse_with(&mut |offset, _|{
+            let rope = &self.rope;
+            if offset >= rope.len_bytes() {
+                ""
+            } else {
+                let (s, chunk_start_idx, ..) = rope.chunk_at_byte(offset);
+                let offset_into_chunk = offset - chunk_start_idx;
+                &s[offset_into_chunk..]
+            }
+        }, None).expect("Could not generate TS tree");
+
+        let mut query_cursor = QueryCursor::new();
+
+        let text_provider = |node: Node|{
+            let rope = &self.rope;
+            rope.get_byte_slice(node.byte_range())
+                .map(|s|s.to_string())
+                .into_iter()
+        };
+
+        let matches = query_cursor.captures(&self.ts_query, tree.root_node(), text_provider);
+
+        let mut captures_iter = matches
+            .map(|(each_match, _)|each_match.captures.iter())
+            .flatten()
+            .map(|cap|{
+                let color = COLOR_MAP.get().unwrap()[cap.index as usize];
+                let range = cap.node.byte_range();
+                (range.end, color)
+            });
+
+        let mut byte_idx = 0;
+
+        // (capture_end_byte_idx, color)
+        let mut current_capture: Option<(usize, Color)> = captures_iter.next();
+        let default_cap = (usize::MAX, Color::White);
+        for (i, line) in self.rope.lines().enumerate() {
+            queue!(&mut self.stdout, MoveToColumn(0))?;
+            match i {
+                0=>write!(&mut self.stdout, "{}", self.prompt)?,
+                _=>write!(&mut self.stdout, "{}", self.continuation)?,
+            }
+            let (mut cap_end, mut color) = current_capture.unwrap_or(default_cap);
+
+            for c in line.chars() {
+                byte_idx += c.len_utf8();
+                while cap_end < byte_idx {
+                    current_capture = captures_iter.next();
+                    (cap_end, color) = current_capture.unwrap_or(default_cap);
+                }
+
+                if c == '\n' {break}
+                write!(&mut self.stdout, "{}", c.with(color))?;
+            }
+
+            if i != last_line {
+                if position == (size - 1) {
+                    prev_row -= 1;
+                    queue!(&mut self.stdout, ScrollUp(1))?;
+                } else {
+                    position += 1;
+                }
+                queue!(&mut self.stdout, MoveDown(1))?;
+            }
+        }
+
+        let move_up = prev_row + self.cursor.line as u16;
+        if move_up > 0 {
+            queue!(&mut self.stdout, MoveToRow(move_up))?;
+        }
+
+        // wide chars like CJK take two cells, so the cursor goes by their width instead of their
+        // count. The newline at the end doesn't take any.
+        let line = self.line();
+        let char_offset = self.cursor.col.min(line.len_chars());
+        let offset_width = line.chars()
+            .take(char_offset)
+            .filter(|c|*c != '\n')
+            .map(|c|c.width().unwrap_or(0))
+            .sum::<usize>();
+        let prefix_width = match self.cursor.line {
+            0=>self.prompt.width(),
+            _=>self.continuation.width(),
+        };
+        let move_right = (prefix_width + offset_width) as u16;
+        if move_right > 0 {
+            queue!(&mut self.stdout, MoveToColumn(move_right))?;
+        }
+
+        queue!(&mut self.stdout, EndSynchronizedUpdate)?;
+        self.stdout.flush()?;
+
+        return Ok(prev_row);
+    }
+
+    /// A simple check to see if something *may* be successful
+    fn check_code(&self)->bool {
+        let mut depth = 0;
+
+        let mut iter = self.rope.chars();
+
+        while let Some(c) = iter.next() {
+            match c {
+                '{'=>loop {
+                    match iter.next() {
+                        Some('}')=>break,
+                        Some(_)=>{},
+                        None=>return false,
+                    }
+                },
+                '['=>loop {
+                    match iter.next() {
+                        Some(']')=>break,
+                        Some(_)=>{},
+                        None=>return false,
+                    }
+                },
+                '('=>depth += 1,
+                ')'=>if depth == 0 {
+                    return false;
+                } else {
+                    depth -= 1;
+                },
+                '"'=>{
+                    let mut escape = false;
+                    loop {
+                        match iter.next() {
+                            Some('\\')=>escape = true,
+                            Some('"')=>if !escape {break},
+                            Some(_)=>{},
+                            None=>return false,
+                        }
+                        if escape {escape = false}
+                    }
+                },
+                '\\'=>{
+                    iter.next();
+                },
+                _=>{},
+            }
+        }
+
+        return depth == 0;
+    }
+}
+
+
+/// The prompt for the lines after the first, as wide as `prompt` so the code lines up. Its text
+/// becomes dots and any whitespace at the end is kept, so `slp> ` continues with `.... `.
+pub fn continuation_prompt(prompt: &str)->String {
+    let text = prompt.trim_end();
+    let mut out = ".".repeat(text.width());
+    out.push_str(&prompt[text.len()..]);
+
+    return out;
+}
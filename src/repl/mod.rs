@@ -1,588 +1,561 @@
 use anyhow::Result;
-use ropey::{
-    Rope,
-    RopeSlice,
-};
 use crossterm::{
     terminal::{
-        BeginSynchronizedUpdate,
-        EndSynchronizedUpdate,
         SetTitle,
         Clear,
         ClearType,
-        ScrollUp,
-        enable_raw_mode,
-        disable_raw_mode,
-        size as terminal_size,
-    },
-    event::{
-        Event,
-        KeyCode,
-        KeyModifiers,
-        read as read_event,
-    },
-    style::{
-        Color,
-        Stylize,
-    },
-    cursor::{
-        MoveDown,
-        MoveToColumn,
-        MoveToRow,
-        Show as ShowCursor,
-        position as cursor_position,
     },
+    cursor::MoveTo,
     execute,
-    queue,
-};
-use tree_sitter::{
-    QueryCursor,
-    Node,
-    Parser as TsParser,
-    Query as TsQuery,
 };
 use std::{
     io::{
+        IsTerminal,
+        Lines,
+        StdinLock,
         Stdout,
         Write,
+        stdin,
     },
     time::Instant,
-    fs::read_to_string,
-    collections::HashMap,
-    sync::OnceLock,
-    mem,
+    path::{
+        Path,
+        PathBuf,
+    },
 };
 use crate::{
     interpreter::{
         ast::{
             ConvertState,
             InstructionId,
+            Ident,
+            Instruction,
+            FnSignature,
             repl_convert,
+            repl_convert_with_path,
+            convert_prelude,
+        },
+        data::{
+            Data,
+            DataRef,
+        },
+        pretty::{
+            PrettyConfig,
+            pretty_format,
+            preview,
         },
-        data::Data,
         Interpreter,
+        ArgCount,
     },
     parser::{
-        ReplContinue,
         repl_new_parser,
         new_parser,
     },
     ast::Expr,
+    error::LispError,
+    suggest::{
+        similar_names,
+        did_you_mean,
+    },
+    source::{
+        read_source,
+        SearchPath,
+    },
+    output::TrackedStdout,
+    interpreter2,
+    InterpreterOptions,
+    color,
     error_trace,
 };
 
 
 mod colors;
+mod editor;
+mod debugger;
+mod breakpoint;
+mod post_mortem;
+
+
+pub use editor::{
+    Cursor,
+    Editor,
+    continuation_prompt,
+};
+pub use debugger::Debugger;
+pub use breakpoint::breakpoint_repl;
+pub use post_mortem::{
+    post_mortem_repl,
+    post_mortem_repl2,
+};
+
 
+/// Values printed by `:vars` and `:globals` are cut off after this many chars.
+const PREVIEW_WIDTH: usize = 60;
 
-const HIGHLIGHT_QUERY: &str = include_str!("highlights.scm");
+/// Used when `*prompt*` isn't defined or doesn't work
+const DEFAULT_PROMPT: &str = "> ";
 
+/// The global the prompt comes from. A string, or a function with no arguments that returns one.
+const PROMPT_GLOBAL: &str = "*prompt*";
 
-static COLOR_MAP: OnceLock<Vec<Color>> = OnceLock::new();
+/// Every directive name, for suggesting one when the user mistypes it
+const DIRECTIVES: &[&str] = &["exit", "help", "vars", "globals", "reset", "clear", "include", "load", "disasm", "debug-run"];
 
 
 enum ReplDirective<'a> {
     Exit,
     Help,
+    Vars,
+    Globals,
+    Reset,
+    Clear,
     Include(&'a str),
+    Load(&'a str),
+    Disasm(&'a str),
+    DebugRun(&'a str),
 }
 
 
-#[derive(Copy, Clone)]
-pub struct Cursor {
-    pub line: usize,
-    pub col: usize,
+/// The file the REPL loads before its first prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitFile {
+    /// `default_init_file`, if it exists
+    Default,
+    /// From `--init-file`. It not existing is an error.
+    Path(PathBuf),
+    /// `--no-init`
+    None,
 }
 
-pub struct Repl {
-    state: ConvertState,
-    interpreter: Interpreter,
-    history: Vec<String>,
-    stdout: Stdout,
-    ts_parser: TsParser,
-    ts_query: TsQuery,
-    rope: Rope,
-    cursor_idx: usize,
-    indent_level: usize,
-    cursor: Cursor,
+/// `$XDG_CONFIG_HOME/simple_lisp/init.slp`, or `~/.config/simple_lisp/init.slp` if that isn't
+/// set. `None` if neither variable is set.
+pub fn default_init_file()->Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir|dir.is_absolute())
+        .or_else(||std::env::var_os("HOME").map(|home|PathBuf::from(home).join(".config")))?;
+
+    return Some(config.join("simple_lisp").join("init.slp"));
 }
-impl Repl {
-    pub fn new()->Self {
-        let mut ts_parser = TsParser::new();
-        let lang = tree_sitter_simplelisp::language();
-        ts_parser.set_language(&lang).expect("Error loading simplelisp grammar");
-        let ts_query = TsQuery::new(&lang, HIGHLIGHT_QUERY)
-            .expect("Could not load builtin simplelisp highlight query");
-        let mut state = ConvertState::new();
-        state.reserve_module();
 
-        // Generate the color map at runtime. This is easier since I don't have to worry about the
-        // thing desyncing if I change the `highlights.scm` file or `colors::COLORS` array.
-        let raw_color_map: HashMap<&str, Color> = colors::COLORS.into_iter().copied().collect();
-        let mut color_map = Vec::new();
-        for cap in ts_query.capture_names() {
-            let color = raw_color_map.get(cap).copied().unwrap();
-            color_map.push(color);
-        }
-        COLOR_MAP.set(color_map).unwrap();
 
-        Repl {
-            interpreter: Interpreter::new(&mut state),
-            state,
-            history: Vec::new(),
-            stdout: std::io::stdout(),
-            ts_parser,
-            ts_query,
-            rope: Rope::new(),
-            cursor_idx: 0,
-            indent_level: 0,
-            cursor: Cursor {
-                line: 0,
-                col: 0,
+/// Where the REPL reads from: the editor, or plain lines if stdin isn't a terminal. Unlike the
+/// debugger's `Input`, the prompt is printed either way and an unfinished expression is read again
+/// with the lines after it.
+enum ReplInput {
+    Editor(Editor),
+    Lines {
+        lines: Lines<StdinLock<'static>>,
+        prompt: String,
+        /// The lines of the expression being read, kept until `commit`
+        pending: String,
+    },
+}
+impl ReplInput {
+    fn new()->Self {
+        match stdin().is_terminal() {
+            true=>Self::Editor(Editor::new(DEFAULT_PROMPT)),
+            false=>Self::Lines {
+                lines: stdin().lines(),
+                prompt: DEFAULT_PROMPT.to_string(),
+                pending: String::new(),
             },
         }
     }
 
-    fn reset_cursor(&mut self) {
-        self.cursor.line = 0;
-        self.cursor.col = 0;
-        self.cursor_idx = 0;
-        self.indent_level = 0;
+    fn set_prompt(&mut self, new: &str) {
+        match self {
+            Self::Editor(editor)=>editor.set_prompt(new),
+            Self::Lines{prompt, ..}=>*prompt = new.to_string(),
+        }
     }
 
-    // ----- Adding chars or strings things
+    /// Everything read since the last `commit`, or `None` once there is no more input
+    fn read(&mut self)->Option<String> {
+        match self {
+            Self::Editor(editor)=>editor.read().unwrap(),
+            Self::Lines{lines, prompt, pending}=>{
+                match pending.is_empty() {
+                    true=>print!("{prompt}"),
+                    false=>print!("{}", continuation_prompt(prompt)),
+                }
+                let _ = std::io::stdout().flush();
+
+                let line = lines.next()?.ok()?;
+                if !pending.is_empty() {
+                    pending.push('\n');
+                }
+                pending.push_str(&line);
 
-    fn add_char(&mut self, c: char) {
-        if c == '\n' {return self.newline()}
-        if self.cursor_idx == self.line().len_chars() && self.cursor.line > 0 {
-            self.rope.insert_char(self.cursor_idx.saturating_sub(1), c);
-        } else {
-            self.rope.insert_char(self.cursor_idx, c);
+                return Some(pending.clone());
+            },
         }
-        self.cursor_right();
     }
 
-    fn newline(&mut self) {
-        self.rope.insert_char(self.cursor_idx, '\n');
-        self.cursor_down();
-        self.cursor_home();
-        for _ in 0..(self.indent_level * 4) {
-            self.add_char(' ');
+    /// Forget what was read and add `source` to the history
+    fn commit(&mut self, source: String) {
+        match self {
+            Self::Editor(editor)=>editor.commit(source),
+            Self::Lines{pending, ..}=>pending.clear(),
         }
     }
+}
 
-    #[inline]
-    fn add_str(&mut self, s: &str) {
-        s.chars()
-            .for_each(|c|self.add_char(c));
-    }
 
-    // ----- Cursor things
+pub struct Repl {
+    state: ConvertState,
+    interpreter: Interpreter,
+    input: ReplInput,
+    stdout: Stdout,
+    /// What the interpreter prints goes here, so we know when to end its last line
+    output: TrackedStdout,
+    /// `_`, `_2`, and `_3` in that order
+    last_result_idents: [Ident; 3],
+    /// Used for the V2 interpreters `:debug-run` creates. `prelude` also decides whether `:reset`
+    /// loads the prelude again.
+    options: InterpreterOptions,
+    /// Loaded before the first prompt and again after `:reset`
+    init_file: Option<PathBuf>,
+}
+impl Repl {
+    /// Start a session and load `init` into it. Errors in it are reported, and everything it
+    /// defined before the error is kept.
+    pub fn new(options: InterpreterOptions, init: InitFile)->Self {
+        let mut state = ConvertState::new();
+        state.reserve_module();
+        if options.prelude {
+            convert_prelude(&mut state);
+        }
 
-    fn compute_cursor_idx(&mut self) {
-        let line = self.rope.line(self.cursor.line.min(self.rope.len_lines().saturating_sub(1)));
-        let line_char_idx = self.rope.line_to_char(self.cursor.line);
+        let output = TrackedStdout::new();
+        let mut interpreter = options.new_interpreter_with_output(&mut state, Box::new(output.clone()));
+        interpreter.set_allow_global_redefinition(true);
 
-        let char_idx = self.cursor.col.min(line.len_chars());
+        let last_result_idents = [
+            state.intern("_"),
+            state.intern("_2"),
+            state.intern("_3"),
+        ];
 
-        self.cursor_idx = line_char_idx + char_idx;
-    }
+        let init_file = match init {
+            InitFile::Default=>default_init_file().filter(|path|path.is_file()),
+            InitFile::Path(path)=>Some(path),
+            InitFile::None=>None,
+        };
 
-    fn cursor_up(&mut self) {
-        if self.cursor.line != 0 {
-            self.cursor.line -= 1;
-            self.compute_cursor_idx();
-        }
-    }
+        let mut repl = Repl {
+            interpreter,
+            state,
+            input: ReplInput::new(),
+            stdout: std::io::stdout(),
+            output,
+            last_result_idents,
+            options,
+            init_file,
+        };
+        repl.load_init_file();
 
-    fn cursor_down(&mut self) {
-        if self.cursor.line + 1 < self.rope.len_lines() {
-            self.cursor.line += 1;
-            self.compute_cursor_idx();
-        } else if self.cursor.line >= self.rope.len_lines() {
-            self.cursor.line = self.rope.len_lines() - 1;
-            self.compute_cursor_idx();
-        }
+        return repl;
     }
 
-    fn cursor_left(&mut self) {
-        if self.cursor.col > 0 {
-            let line = self.line();
-            let mut line_end = line.len_chars().saturating_sub(1);
-            if self.line_ends_with_nl() {
-                line_end = line_end.saturating_sub(1);
-            }
-            if self.cursor.col > line_end {
-                self.cursor.col = line_end;
-                self.compute_cursor_idx();
-            } else {
-                self.cursor.col -= 1;
-            }
-            self.compute_cursor_idx();
+    fn load_init_file(&mut self) {
+        if let Some(path) = self.init_file.clone() {
+            self.load(&path.to_string_lossy(), false);
         }
     }
 
-    fn cursor_right(&mut self) {
-        if self.rope.len_chars() > 0 {
-            if self.char() != '\n' {
-                let line = self.rope.line(self.cursor.line);
-                if self.cursor.col < line.len_chars() {
-                    self.cursor.col += 1;
+    /// The prompt from `*prompt*`. If it is a function it is called now, so it can show what the
+    /// session is like. Anything wrong with it is reported, and the default is used instead.
+    fn prompt(&mut self)->String {
+        let dr = self.state.interner.lookup(PROMPT_GLOBAL)
+            .and_then(|name|self.interpreter.get_global(name, &self.state.interner));
+        let Some(dr) = dr else {
+            return DEFAULT_PROMPT.to_string();
+        };
+
+        let is_fn = matches!(&*dr.get_data(), Data::Fn(_)|Data::Closure{..}|Data::NativeFn(..));
+        let dr = match is_fn {
+            true=>{
+                let res = self.interpreter.call(&mut self.state, dr, Vec::new());
+                self.end_output_line();
+                match res {
+                    Ok(dr)=>dr,
+                    Err(e)=>{
+                        error_trace(e, "", PROMPT_GLOBAL);
+                        return DEFAULT_PROMPT.to_string();
+                    },
                 }
-                self.compute_cursor_idx();
-            }
+            },
+            false=>dr,
+        };
+
+        let data = dr.get_data();
+        match &*data {
+            Data::String(s)=>return s.clone(),
+            d=>{
+                let e = LispError::Type {
+                    op: PROMPT_GLOBAL.into(),
+                    expected: "string or fn",
+                    actual: d.type_name(),
+                };
+                println!("{}: {e}", color::paint(color::enabled(), color::ERROR, "Error"));
+                return DEFAULT_PROMPT.to_string();
+            },
         }
     }
 
-    fn cursor_home(&mut self) {
-        self.cursor.col = 0;
-        self.compute_cursor_idx();
+    /// `load`, then say so if it worked
+    fn load_file(&mut self, path: &str, stats_for_nerds: bool) {
+        if self.load(path, stats_for_nerds) {
+            println!("Loaded `{path}`");
+        }
     }
 
-    fn cursor_end(&mut self) {
-        // let line = self.line();
-        self.cursor.col = usize::MAX;
-
-        self.compute_cursor_idx();
-    }
+    /// Parse, convert, and run a file in the current session so everything it defines is available
+    /// afterwards. Errors are reported against the file's source, and whatever was defined before
+    /// the error sticks around. False if there was one.
+    fn load(&mut self, path: &str, stats_for_nerds: bool)->bool {
+        let source = match read_source(Path::new(path)) {
+            Ok(s)=>s,
+            Err(e)=>{
+                println!("{}: {e}", color::paint(color::enabled(), color::ERROR, "Error"));
+                return false;
+            },
+        };
 
-    // ----- Removal things
+        let mut parser = new_parser(source.as_str());
+        let exprs = match parser.parse_all() {
+            Ok(exprs)=>exprs,
+            Err(e)=>{
+                error_trace(e, &source, path);
+                return false;
+            },
+        };
+        drop(parser);
 
-    fn backspace(&mut self) {
-        if self.cursor_idx == 0 {
-            return;
+        if exprs.len() == 0 {
+            return true;
         }
-        self.cursor_idx -= 1;
-
-        self.delete();
-        if self.cursor.col == 0 {
-            self.cursor.line -= 1;
-            self.cursor_end();
-        } else {
-            self.cursor.col -= 1;
-        }
-    }
 
-    #[inline]
-    fn delete(&mut self) {
-        if self.cursor_idx >= self.rope.len_chars() {return}
-        self.rope.remove(self.cursor_idx..=self.cursor_idx);
-    }
+        // modules are resolved next to the file, just like `convert_module` does
+        let module_path = Path::new(path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let start_id = match repl_convert_with_path(&mut self.state, exprs, module_path) {
+            Ok(start_id)=>start_id,
+            Err(e)=>{
+                error_trace(e, &source, path);
+                return false;
+            },
+        };
+
+        let start_ins_count = self.interpreter.metrics.instructions_executed;
+        let res = self.interpreter.run(&mut self.state, Some(start_id));
+        self.end_output_line();
+        let ok = match res {
+            Ok(_)=>true,
+            Err(e)=>{
+                error_trace(e, &source, path);
+                false
+            },
+        };
 
-    // ----- Indexing helpers
+        if stats_for_nerds {
+            println!("Run time: {:?}", self.interpreter.metrics.last_run_time);
+            println!("{} instructions executed", self.interpreter.metrics.instructions_executed - start_ins_count);
+        }
 
-    fn line_ends_with_nl(&self)->bool {
-        let line = self.line();
-        if line.len_chars() == 0 {return false}
-        line.char(line.len_chars() - 1) == '\n'
-    }
-    
-    // #[inline]
-    // fn prev_char(&self)->char {
-    //     self.rope.char(self.cursor_idx.saturating_sub(1))
-    // }
-
-    #[inline]
-    fn char(&self)->char {
-        self.rope.char(self.cursor_idx.min(self.rope.len_chars() - 1))
-    }
+        self.interpreter.gc_collect();
 
-    fn line(&self)->RopeSlice {
-        let line = self.cursor.line.min(self.rope.len_lines().saturating_sub(1));
-        self.rope.line(line)
+        return ok;
     }
 
-    // ----- Display/Input handling things
-
-    fn prompt_line(&mut self)->Result<()> {
-        let mut prev_row = cursor_position()?.1;
-        let mut prev_lines = self.rope.len_lines();
+    /// If the program's output stopped mid-line, finish the line so what we print next starts on
+    /// its own
+    fn end_output_line(&mut self) {
+        let _ = self.interpreter.output().flush();
+        if self.output.take_mid_line() {
+            println!(" ⏎");
+        }
+    }
 
-        let mut prev_rope: Option<(Cursor, Rope)> = None;
-        let mut history_item = self.history.len();
+    /// Run a file with a fresh V2 interpreter under the debugger. The REPL session isn't touched.
+    fn debug_run(&mut self, path: &str) {
+        let source = match read_source(Path::new(path)) {
+            Ok(s)=>s,
+            Err(e)=>{
+                println!("{}: {e}", color::paint(color::enabled(), color::ERROR, "Error"));
+                return;
+            },
+        };
 
-        prev_row = self.render_buffer(prev_row, 0)?;
+        let mut parser = new_parser(source.as_str());
+        let exprs = match parser.parse_all() {
+            Ok(exprs)=>exprs,
+            Err(e)=>{
+                error_trace(e, &source, path);
+                return;
+            },
+        };
+        drop(parser);
 
-        enable_raw_mode().unwrap();
+        let mut state = match interpreter2::ast::convert(exprs, Path::new(path), SearchPath::new(Vec::new()), self.options.prelude) {
+            Ok(state)=>state,
+            Err(e)=>{
+                error_trace(e, &source, path);
+                return;
+            },
+        };
 
-        loop {
-            match read_event()? {
-                Event::Key(key_event)=>{
-                    let shift = key_event.modifiers == KeyModifiers::SHIFT;
-                    if key_event.modifiers.is_empty() || shift {
-                        match key_event.code {
-                            KeyCode::Backspace=>self.backspace(),
-                            KeyCode::Delete=>self.delete(),
-                            KeyCode::Enter=>{
-                                self.newline();
-                                if self.check_code() {
-                                    break;
-                                }
-                            },
+        let mut interpreter = self.options.new_interpreter2(&mut state);
+        interpreter.set_debug_hook(Some(Box::new(Debugger::new(&state))));
 
-                            // Ensure Shift works as expected, regardless of what char we get
-                            KeyCode::Char(c)=>if shift {
-                                self.add_char(c.to_ascii_uppercase());
-                            } else {
-                                self.add_char(c);
-                            },
+        match interpreter.run(&mut state, None) {
+            Ok(res)=>println!(">> {res:?}"),
+            Err(e)=>error_trace(e, &source, path),
+        }
+    }
 
-                            // Indent things
-                            KeyCode::BackTab=>{ // Shift + Tab
-                                if self.rope.len_chars() != 0 {
-                                let mut prev_cursor = self.cursor;
-                                    self.cursor_home();
-                                    for _ in 0..4 {
-                                        if self.rope.len_chars() == 0 {break}
-                                        if self.char() != ' ' {break}
-                                        self.delete();
-                                        prev_cursor.col = prev_cursor.col.saturating_sub(1);
-                                    }
-                                    self.indent_level = self.indent_level.saturating_sub(1);
-                                    self.cursor = prev_cursor;
-                                    self.compute_cursor_idx();
-                                }
-                            },
-                            KeyCode::Tab=>{
-                                let mut prev_cursor = self.cursor;
-                                prev_cursor.col += 4;
-                                self.indent_level += 1;
-                                self.cursor_home();
-                                self.add_str("    ");
-                                self.cursor = prev_cursor;
-                                self.compute_cursor_idx();
-                            },
+    /// Print the instructions for each body of the function stored in the global `name`.
+    fn disasm(&mut self, name: &str) {
+        let dr = self.state.interner.lookup(name)
+            .and_then(|ident|self.interpreter.get_global(ident, &self.state.interner));
+        let Some(dr) = dr else {
+            println!("`{name}` is not defined");
+            return;
+        };
 
-                            KeyCode::Left=>self.cursor_left(),
-                            KeyCode::Right=>self.cursor_right(),
-                            KeyCode::Up=>self.cursor_up(),
-                            KeyCode::Down=>self.cursor_down(),
-                            KeyCode::Home=>self.cursor_home(),
-                            KeyCode::End=>self.cursor_end(),
-                            KeyCode::PageUp=>{
-                                if history_item > 0 {
-                                    history_item = (history_item - 1).min(self.history.len().saturating_sub(1));
-                                    if history_item < self.history.len() {
-                                        let new_rope = Rope::from(self.history[history_item].as_str());
-                                        prev_rope = Some((self.cursor, mem::replace(&mut self.rope, new_rope)));
-                                        self.reset_cursor();
-                                    }
-                                }
-                            },
-                            KeyCode::PageDown=>{
-                                history_item = (history_item + 1).min(self.history.len().max(1));
-                                if history_item < self.history.len() {
-                                    self.rope = Rope::from(self.history[history_item].as_str());
-                                    self.reset_cursor();
-                                } else if prev_rope.is_some() {
-                                    let (cursor, rope) = prev_rope.take().unwrap();
-                                    self.rope = rope;
-                                    self.cursor = cursor;
-                                }
-                            },
+        let id = match &*dr.get_data() {
+            Data::Fn(id)|Data::Closure{id,..}=>*id,
+            Data::NativeFn(native_name, ..)=>{
+                println!("`{name}` is the builtin native function `{native_name}`, so there is nothing to disassemble");
+                return;
+            },
+            d=>{
+                println!("`{name}` is a {}, not a function", d.type_name());
+                return;
+            },
+        };
 
-                            _=>{},
-                        }
-                    } else if key_event.modifiers.contains(KeyModifiers::CONTROL) {
-                        match key_event.code {
-                            KeyCode::Char('d'|'D')=>break,
-                            KeyCode::Char('w'|'W')=>{
-                                if self.rope.len_chars() > 0 {
-                                    match self.char() {
-                                        '/'|':'|';'|'\\'|'.'|'\t'|'\r'|'\n'|'('|')'|'['|']'|'{'|'}'|'"'|'\''|'#'=>{
-                                            self.backspace();
-                                        },
-                                        _=>{
-                                            while self.rope.len_chars() > 0 {
-                                                match self.char() {
-                                                    '/'|':'|';'|'\t'|'\r'|'\n'|'('|')'|'['|']'|'{'|'}'|'"'|'\''|'#'=>{
-                                                        break;
-                                                    },
-                                                    _=>self.backspace(),
-                                                }
-                                            }
-                                        },
-                                    }
-                                }
-                            },
-                            _=>{},
-                        }
-                    }
-                },
-                _=>{},
-            }
+        let func = match self.state.get_fn(id) {
+            Ok(func)=>func.clone(),
+            Err(e)=>{
+                println!("Can't disassemble `{name}`: {e}");
+                return;
+            },
+        };
+        let interner = &self.state.interner;
 
-            prev_row = self.render_buffer(prev_row, prev_lines)?;
-            prev_lines = self.rope.len_lines();
+        match &func.sig {
+            FnSignature::Single{params, body_ptr}=>{
+                println!("{name} {}", params.describe(interner));
+                self.print_body(*body_ptr);
+            },
+            FnSignature::Multi{exact, at_least, any, ..}=>{
+                let mut bodies = exact.iter()
+                    .chain(at_least.iter())
+                    .map(|(_, body)|body)
+                    .chain(any.iter())
+                    .collect::<Vec<_>>();
+                bodies.sort_by_key(|(params, _)|(params.items.len(), params.remainder.is_some()));
+
+                for (i, (params, body_ptr)) in bodies.into_iter().enumerate() {
+                    if i > 0 {println!()}
+                    println!("{name} {}", params.describe(interner));
+                    self.print_body(*body_ptr);
+                }
+            },
         }
-
-        disable_raw_mode().unwrap();
-
-        return Ok(());
     }
 
-    fn render_buffer(&mut self, mut prev_row: u16, prev_lines: usize)->Result<u16> {
-        queue!(&mut self.stdout, BeginSynchronizedUpdate)?;
-        // TODO: treesitter highlighting!
-        let last_line = self.rope.len_lines().saturating_sub(1);
-        let lines = self.rope.len_lines();
-        let line_num_cols = match lines {
-            0..=9=>1,
-            10..=99=>2,
-            100..=999=>4,
-            _=>panic!("Too many lines!"),
-        };
+    /// Prints the instructions from `body_ptr` up to and including the matching `Return`
+    fn print_body(&self, body_ptr: InstructionId) {
+        let mut iter = self.state.instructions.iter();
+        iter.jump(body_ptr);
 
-        let size = terminal_size()?.1;
-        let mut position = prev_row;
+        let mut i = 0;
+        while let Some(ins) = iter.next() {
+            let id = iter.cur_ins_id().unwrap();
+            println!("    #{i:<3.} Id({:3.}) > {}", id.inner(), ins.describe(&self.state.interner));
 
-        if prev_lines > 0 {
-            queue!(&mut self.stdout, MoveToRow(prev_row), ShowCursor)?;
-            for _ in 0..prev_lines {
-                queue!(&mut self.stdout, Clear(ClearType::CurrentLine), MoveDown(1))?;
-            }
-            queue!(&mut self.stdout, MoveToRow(prev_row))?;
+            if let Instruction::Return = ins {break}
+            i += 1;
         }
+    }
 
-        let tree = self.ts_parser.parse_with(&mut |offset, _|{
-            let rope = &self.rope;
-            if offset >= rope.len_bytes() {
-                ""
-            } else {
-                let (s, chunk_start_idx, ..) = rope.chunk_at_byte(offset);
-                let offset_into_chunk = offset - chunk_start_idx;
-                &s[offset_into_chunk..]
-            }
-        }, None).expect("Could not generate TS tree");
+    /// Throw away every definition and start with a fresh state, then load the init file again.
+    /// History is kept.
+    fn reset(&mut self) {
+        let mut state = ConvertState::new();
+        state.reserve_module();
+        if self.options.prelude {
+            convert_prelude(&mut state);
+        }
 
-        let mut query_cursor = QueryCursor::new();
+        self.interpreter.reset(&mut state);
+        self.state = state;
 
-        let text_provider = |node: Node|{
-            let rope = &self.rope;
-            rope.get_byte_slice(node.byte_range())
-                .map(|s|s.to_string())
-                .into_iter()
-        };
+        self.last_result_idents = [
+            self.state.intern("_"),
+            self.state.intern("_2"),
+            self.state.intern("_3"),
+        ];
 
-        let matches = query_cursor.captures(&self.ts_query, tree.root_node(), text_provider);
-
-        let mut captures_iter = matches
-            .map(|(each_match, _)|each_match.captures.iter())
-            .flatten()
-            .map(|cap|{
-                let color = COLOR_MAP.get().unwrap()[cap.index as usize];
-                let range = cap.node.byte_range();
-                (range.end, color)
-            });
-
-        let mut byte_idx = 0;
-
-        // (capture_end_byte_idx, color)
-        let mut current_capture: Option<(usize, Color)> = captures_iter.next();
-        let default_cap = (usize::MAX, Color::White);
-        for (i, line) in self.rope.lines().enumerate() {
-            queue!(&mut self.stdout, MoveToColumn(0))?;
-            if lines == 1 {
-                write!(&mut self.stdout, "> ")?;
-            } else {
-                write!(&mut self.stdout, "{:<line_num_cols$} ", i + 1)?;
-            }
-            let (mut cap_end, mut color) = current_capture.unwrap_or(default_cap);
+        self.load_init_file();
+    }
 
-            for c in line.chars() {
-                byte_idx += c.len_utf8();
-                while cap_end < byte_idx {
-                    current_capture = captures_iter.next();
-                    (cap_end, color) = current_capture.unwrap_or(default_cap);
-                }
+    /// Shift `_` and `_2` down and bind `_` to the new result. These are normal globals, so they
+    /// overwrite anything the user defined with the same names.
+    fn push_last_result(&mut self, dr: DataRef) {
+        let [first, second, third] = self.last_result_idents;
+        let interner = &self.state.interner;
 
-                if c == '\n' {break}
-                write!(&mut self.stdout, "{}", c.with(color))?;
-            }
-
-            if i != last_line {
-                if position == (size - 1) {
-                    prev_row -= 1;
-                    queue!(&mut self.stdout, ScrollUp(1))?;
-                } else {
-                    position += 1;
-                }
-                queue!(&mut self.stdout, MoveDown(1))?;
-            }
+        if let Some(prev) = self.interpreter.get_global(second, interner) {
+            self.interpreter.define_global(third, prev);
         }
-
-        let move_up = prev_row + self.cursor.line as u16;
-        if move_up > 0 {
-            queue!(&mut self.stdout, MoveToRow(move_up))?;
+        if let Some(prev) = self.interpreter.get_global(first, interner) {
+            self.interpreter.define_global(second, prev);
         }
+        self.interpreter.define_global(first, dr);
+    }
 
-        let line = self.line();
-        let char_offset = self.cursor.col.min(line.len_chars());
-        let move_right = 1 + (line_num_cols as u16) + char_offset as u16;
-        if move_right > 0 {
-            let mut offset = 0;
-            if line.len_chars() != 0 {
-                if self.line_ends_with_nl() && char_offset == line.len_chars() {
-                    offset = 1;
-                }
-            }
-            queue!(&mut self.stdout, MoveToColumn(move_right.saturating_sub(offset)))?;
+    /// Print a sorted table of the global variables. Builtins are only included if asked for.
+    fn print_globals(&self, include_builtins: bool) {
+        let interner = &self.state.interner;
+
+        let mut rows = self.interpreter.globals()
+            .filter(|(name, _)|include_builtins || !self.interpreter.is_builtin_global(*name))
+            .map(|(name, dr)|{
+                let type_name = match &*dr.get_data() {
+                    Data::NativeFn(_, _, ArgCount::Exact(count))=>format!("nativeFn/{count}"),
+                    Data::NativeFn(_, _, ArgCount::Any)=>"nativeFn/any".to_string(),
+                    d=>d.type_name().to_string(),
+                };
+                let type_name = match self.interpreter.is_constant(name) {
+                    true=>format!("const {type_name}"),
+                    false=>type_name,
+                };
+
+                let preview = preview(dr, interner, PREVIEW_WIDTH);
+
+                (interner.get(name), type_name, preview)
+            })
+            .collect::<Vec<_>>();
+
+        if rows.len() == 0 {
+            println!("No variables defined");
+            return;
         }
 
-        queue!(&mut self.stdout, EndSynchronizedUpdate)?;
-        self.stdout.flush()?;
-
-        return Ok(prev_row);
-    }
-
-    /// A simple check to see if something *may* be successful
-    fn check_code(&self)->bool {
-        let mut depth = 0;
+        rows.sort_by(|l, r|l.0.cmp(r.0));
 
-        let mut iter = self.rope.chars();
+        let name_width = rows.iter().map(|r|r.0.chars().count()).max().unwrap_or(0);
+        let type_width = rows.iter().map(|r|r.1.len()).max().unwrap_or(0);
 
-        while let Some(c) = iter.next() {
-            match c {
-                '{'=>loop {
-                    match iter.next() {
-                        Some('}')=>break,
-                        Some(_)=>{},
-                        None=>return false,
-                    }
-                },
-                '['=>loop {
-                    match iter.next() {
-                        Some(']')=>break,
-                        Some(_)=>{},
-                        None=>return false,
-                    }
-                },
-                '('=>depth += 1,
-                ')'=>if depth == 0 {
-                    return false;
-                } else {
-                    depth -= 1;
-                },
-                '"'=>{
-                    let mut escape = false;
-                    loop {
-                        match iter.next() {
-                            Some('\\')=>escape = true,
-                            Some('"')=>if !escape {break},
-                            Some(_)=>{},
-                            None=>return false,
-                        }
-                        if escape {escape = false}
-                    }
-                },
-                '\\'=>{
-                    iter.next();
-                },
-                _=>{},
-            }
+        for (name, type_name, preview) in rows {
+            println!("{name:<name_width$}  {type_name:<type_width$}  {preview}");
         }
-
-        return depth == 0;
     }
 
     pub fn run(&mut self, debug: u8, stats_for_nerds: bool) {     // TODO: Debug and stats for nerds
@@ -592,18 +565,30 @@ impl Repl {
 
         execute!(&mut self.stdout, SetTitle("Simplelisp REPL")).unwrap();
 
+        // true while reading the rest of an unfinished expression, which keeps the prompt it had
+        let mut continuing = false;
+
         // Read
         'repl:loop {
-            self.reset_cursor();
-
-            self.prompt_line().unwrap();
-            if self.rope.len_chars() == 0 {break 'repl}
-
-            let mut source = self.rope.to_string();
-            while source.ends_with(['\n', ' ']) {
-                source.pop();
+            if !continuing {
+                let prompt = self.prompt();
+                self.input.set_prompt(&prompt);
+            }
+            continuing = false;
+            let Some(source) = self.input.read() else {break 'repl};
+
+            // Some directives take a raw argument, so handle them before the parser mangles it
+            // into paths and dot-idents.
+            if let Some(dir) = match_arg_directive(&source) {
+                match dir {
+                    ReplDirective::Load(path)=>self.load_file(path, stats_for_nerds),
+                    ReplDirective::Disasm(name)=>self.disasm(name),
+                    ReplDirective::DebugRun(path)=>self.debug_run(path),
+                    _=>unreachable!(),
+                }
+                self.input.commit(source);
+                continue 'repl;
             }
-            println!();
 
             // Eval(parse)
             let mut parser = repl_new_parser(source.as_str());
@@ -632,17 +617,37 @@ impl Repl {
                             Ok(Some(dir))=>match dir {
                                 ReplDirective::Help=>{
                                     print_repl_help();
-                                    self.history.push(source);
-                                    self.rope = Rope::new();
+                                    self.input.commit(source);
                                     continue 'repl;
                                 },
                                 ReplDirective::Exit=>break 'repl,
+                                ReplDirective::Reset=>{
+                                    self.reset();
+                                    println!("Cleared all definitions");
+                                    self.input.commit(source);
+                                    continue 'repl;
+                                },
+                                ReplDirective::Clear=>{
+                                    execute!(&mut self.stdout, Clear(ClearType::All), MoveTo(0, 0)).unwrap();
+                                    self.input.commit(source);
+                                    continue 'repl;
+                                },
+                                ReplDirective::Vars|ReplDirective::Globals=>{
+                                    self.print_globals(matches!(dir, ReplDirective::Globals));
+                                    self.input.commit(source);
+                                    continue 'repl;
+                                },
                                 ReplDirective::Include(name)=>Some(include_file(&mut self.state, name).unwrap()),
+                                ReplDirective::Disasm(_)|ReplDirective::DebugRun(_)=>unreachable!(),
+                                ReplDirective::Load(name)=>{
+                                    self.load_file(name, stats_for_nerds);
+                                    self.input.commit(source);
+                                    continue 'repl;
+                                },
                             },
                             Ok(None)=>None,
                             Err(_)=>{
-                                self.history.push(source);
-                                self.rope = Rope::new();
+                                self.input.commit(source);
                                 continue 'repl;
                             },
                         }
@@ -655,8 +660,7 @@ impl Repl {
                             Ok(start_id)=>start_id,
                             Err(e)=>{
                                 error_trace(e, &source, "<REPL>");
-                                self.history.push(source);
-                                self.rope = Rope::new();
+                                self.input.commit(source);
                                 continue 'repl;
                             },
                         }
@@ -667,22 +671,26 @@ impl Repl {
 
                     // if the line looks unfinished, then dont clear it, and don't throw an
                     // error
-                    if e.root_cause().downcast_ref::<ReplContinue>().is_some() {
+                    if let Some(LispError::Incomplete(_)) = e.root_cause().downcast_ref::<LispError>() {
+                        continuing = true;
                         continue 'repl;
                     }
 
                     error_trace(e, source.as_str(), "<REPL>");
-                    self.history.push(source);
-                    self.rope = Rope::new();
+                    self.input.commit(source);
                     continue 'repl;
                 },
             };
 
             // Eval(execute)
             let start_ins_count = self.interpreter.metrics.instructions_executed;
-            match self.interpreter.run(&mut self.state, Some(start_id)) {
+            let res = self.interpreter.run(&mut self.state, Some(start_id));
+            self.end_output_line();
+            match res {
                 // Print
                 Ok(Some(dr))=>{
+                    self.push_last_result(dr.clone());
+
                     if stats_for_nerds {
                         println!("Run time: {:?}", self.interpreter.metrics.last_run_time);
                         println!("{} instructions executed", self.interpreter.metrics.instructions_executed - start_ins_count);
@@ -691,10 +699,14 @@ impl Repl {
                             self.interpreter.get_data_store().get_alloc_rem(),
                         );
                     }
-                    let data_ref = dr.get_data();
-                    match &*data_ref {
-                        Data::None=>{},
-                        d=>println!(">> {d:?}"),
+                    let is_none = matches!(&*dr.get_data(), Data::None);
+                    if !is_none {
+                        // leave room for the `>> ` prefix
+                        let mut config = PrettyConfig::for_terminal();
+                        config.width = config.width.saturating_sub(3);
+
+                        let pretty = pretty_format(&dr, &self.state.interner, &config);
+                        println!(">> {}", pretty.replace('\n', "\n   "));
                     }
                 },
                 Ok(None)=>{},
@@ -708,13 +720,7 @@ impl Repl {
                 }
             }
 
-            let position = cursor_position().unwrap();
-            if position.0 != 0 {
-                println!(" ⏎");
-            }
-
-            self.rope = Rope::new();
-            self.history.push(source);
+            self.input.commit(source);
 
             // Loop
         }
@@ -727,7 +733,22 @@ fn print_repl_help() {
     println!(r#"Help:"#);
     println!(r#"    :help               Display this message"#);
     println!(r#"    :exit               Exits the REPL"#);
+    println!(r#"    :vars               Lists the variables you have defined. Constants say `const`"#);
+    println!(r#"    :globals            Lists all global variables, including the builtins"#);
+    println!(r#"    :reset              Removes everything you have defined, then loads the init"#);
+    println!(r#"                        file again. History is kept."#);
+    println!(r#"    :clear              Clears the screen"#);
     println!(r#"    (:include "NAME")   Reads and executes the file, then keeps the functions"#);
+    println!(r#"    :disasm NAME        Prints the instructions of the function `NAME`"#);
+    println!(r#"    :load PATH          Loads the file into the session, keeping its definitions."#);
+    println!(r#"                        Loading it again redefines everything in place."#);
+    println!(r#"    :debug-run PATH     Runs the file with the V2 interpreter under the debugger."#);
+    println!(r#"                        Nothing it defines is kept."#);
+    println!();
+    println!(r#"The last three results are stored in `_`, `_2`, and `_3`. These are overwritten"#);
+    println!(r#"after every evaluation, so avoid defining variables with those names."#);
+    println!(r#"Define `*prompt*` as a string, or a function that returns one, to change the"#);
+    println!(r#"prompt. The init file is a good place for that."#);
 }
 
 fn match_repl_directive<'a>(exprs: &'a [Expr<'a>])->Result<Option<ReplDirective<'a>>, ()> {
@@ -737,6 +758,19 @@ fn match_repl_directive<'a>(exprs: &'a [Expr<'a>])->Result<Option<ReplDirective<
         Expr::List(items)=>{
             match items.first() {
                 Some(Expr::ReplDirective(s))=>match *s {
+                    "load"=>{
+                        if items.len() != 2 {
+                            println!(":load takes 1 argument");
+                            return Err(());
+                        }
+                        match &items[1] {
+                            Expr::String(s)=>return Ok(Some(ReplDirective::Load(s))),
+                            _=>{
+                                println!(":load only accepts strings in this form. Try `:load PATH`");
+                                return Err(());
+                            },
+                        }
+                    },
                     "include"=>{
                         if items.len() != 2 {
                             println!(":include takes 1 argument");
@@ -751,7 +785,7 @@ fn match_repl_directive<'a>(exprs: &'a [Expr<'a>])->Result<Option<ReplDirective<
                         }
                     }
                     _=>{
-                        println!("Unknown directive: `{s}`");
+                        print_unknown_directive(s);
                         return Err(());
                     },
                 },
@@ -760,11 +794,15 @@ fn match_repl_directive<'a>(exprs: &'a [Expr<'a>])->Result<Option<ReplDirective<
         },
         Expr::ReplDirective(s)=>match *s {
             "exit"=>return Ok(Some(ReplDirective::Exit)),
+            "vars"=>return Ok(Some(ReplDirective::Vars)),
+            "reset"=>return Ok(Some(ReplDirective::Reset)),
+            "clear"=>return Ok(Some(ReplDirective::Clear)),
+            "globals"=>return Ok(Some(ReplDirective::Globals)),
             "help"=>{
                 return Ok(Some(ReplDirective::Help));
             },
             _=>{
-                println!("Unknown directive: `{s}`");
+                print_unknown_directive(s);
                 return Err(());
             },
         },
@@ -772,8 +810,48 @@ fn match_repl_directive<'a>(exprs: &'a [Expr<'a>])->Result<Option<ReplDirective<
     }
 }
 
+fn print_unknown_directive(name: &str) {
+    let similar = similar_names(name, DIRECTIVES.iter().copied())
+        .into_iter()
+        .map(|n|format!(":{n}"))
+        .collect::<Vec<_>>();
+
+    match did_you_mean(&similar) {
+        Some(hint)=>println!("Unknown directive: `{name}`, {hint}"),
+        None=>println!("Unknown directive: `{name}`"),
+    }
+}
+
+/// Matches the directives that take a raw argument: `:load PATH` where `PATH` may optionally be
+/// quoted, `:disasm NAME`, and `:debug-run PATH`.
+fn match_arg_directive(source: &str)->Option<ReplDirective> {
+    let (name, arg) = source.trim()
+        .strip_prefix(':')?
+        .split_once(char::is_whitespace)?;
+    let arg = arg.trim();
+
+    match name {
+        "load"=>{
+            let path = arg.strip_prefix('"')
+                .and_then(|p|p.strip_suffix('"'))
+                .unwrap_or(arg);
+
+            return Some(ReplDirective::Load(path));
+        },
+        "disasm"=>return Some(ReplDirective::Disasm(arg)),
+        "debug-run"=>{
+            let path = arg.strip_prefix('"')
+                .and_then(|p|p.strip_suffix('"'))
+                .unwrap_or(arg);
+
+            return Some(ReplDirective::DebugRun(path));
+        },
+        _=>return None,
+    }
+}
+
 fn include_file(state: &mut ConvertState, name: &str)->Result<InstructionId> {
-    let source = read_to_string(name)?;
+    let source = read_source(Path::new(name))?;
     let mut parser = new_parser(source.as_str());
     let exprs = parser.parse_all()?;
 
@@ -0,0 +1,326 @@
+//! The REPL `--on-error repl` opens after a script fails with a runtime error. The calls that were
+//! running are frozen as they were, so their variables and the globals can be looked at, but
+//! nothing runs again. Lines are read with the REPL's editor, or a line at a time if stdin isn't a
+//! terminal.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use crate::{
+    interpreter::{
+        ast::ConvertState,
+        data::{
+            Data,
+            DataRef,
+        },
+        pretty::{
+            PrettyConfig,
+            pretty_format,
+            preview,
+        },
+        Interpreter,
+        PostMortemFrame,
+    },
+    interpreter2::{
+        self,
+        debug::{
+            fn_name,
+            value_text,
+        },
+    },
+    data_path,
+    error::LispError,
+    suggest::{
+        did_you_mean,
+        similar_names,
+    },
+    color,
+};
+use super::{
+    debugger::Input,
+    PREVIEW_WIDTH,
+};
+
+
+/// Every directive name, for suggesting one when the user mistypes it
+const DIRECTIVES: &[&str] = &["frame", "stack", "locals", "globals", "help", "quit"];
+
+
+/// What a failed run left behind. Frame 0 is the innermost call, and the one after the last call
+/// is the top level.
+trait Inspect {
+    /// Each call, innermost first, like `` in `name` ``
+    fn frames(&self)->Vec<String>;
+
+    /// The names and values of the variables in `frame`
+    fn locals(&self, frame: usize)->Vec<(String, String)>;
+
+    /// The names and values of the globals the program defined
+    fn globals(&self)->Vec<(String, String)>;
+
+    /// The whole value `path` leads to. Its first segment is a var, looked up in `frame` and then
+    /// in the globals.
+    fn lookup(&self, frame: usize, path: &[&str])->Result<String>;
+}
+
+
+/// Look around the V1 interpreter after a run failed. `frames` are from `take_post_mortem`.
+pub fn post_mortem_repl(interpreter: &Interpreter, state: &ConvertState, frames: Vec<PostMortemFrame>) {
+    inspect(&V1 {interpreter, state, frames});
+}
+
+/// Look around the V2 interpreter after a run failed. It doesn't unwind, so the failed calls are
+/// still on its stack.
+pub fn post_mortem_repl2(interpreter: &interpreter2::Interpreter, state: &interpreter2::ast::ConvertState) {
+    inspect(&V2 {interpreter, state});
+}
+
+fn inspect(target: &impl Inspect) {
+    let frames = target.frames();
+    let mut frame = 0;
+    println!("The program stopped. Look at what it left with `:help`, and `:quit` when you are done.");
+    print_frame(&frames, frame);
+
+    let mut input = Input::new("(post-mortem) ");
+    // Ctrl-D quits, just like `:quit`
+    while let Some(line) = input.read() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let Some(directive) = line.strip_prefix(':') else {
+            lookup(target, frame, line);
+            continue;
+        };
+        let (directive, arg) = match directive.split_once(char::is_whitespace) {
+            Some((directive, arg))=>(directive, arg.trim()),
+            None=>(directive, ""),
+        };
+
+        match directive {
+            "quit"|"q"=>return,
+            "frame"|"f"=>{
+                if !arg.is_empty() {
+                    match arg.parse::<usize>() {
+                        Ok(n) if n <= frames.len()=>frame = n,
+                        _=>{
+                            println!("There is no frame `{arg}`. `:stack` lists them.");
+                            continue;
+                        },
+                    }
+                }
+                print_frame(&frames, frame);
+            },
+            "stack"|"bt"=>{
+                for i in 0..=frames.len() {
+                    let marker = if i == frame {"*"} else {" "};
+                    println!("{marker} {}", frame_text(&frames, i));
+                }
+            },
+            "locals"=>print_vars(target.locals(frame), "No locals"),
+            "globals"=>print_vars(target.globals(), "No globals defined"),
+            "help"=>print_help(),
+            _=>{
+                let similar = similar_names(directive, DIRECTIVES.iter().copied());
+                match did_you_mean(&similar) {
+                    Some(hint)=>println!("Unknown directive `:{directive}`, {hint}"),
+                    None=>println!("Unknown directive `:{directive}`. Type `:help` for a list."),
+                }
+            },
+        }
+    }
+}
+
+fn lookup(target: &impl Inspect, frame: usize, line: &str) {
+    if line.contains(|c: char|c.is_whitespace() || "()[]{}\"".contains(c)) {
+        println!("Only names and paths like `foo/items/0` can be looked at. Nothing runs after the program failed.");
+        return;
+    }
+
+    let path = line.split('/').collect::<Vec<_>>();
+    match target.lookup(frame, &path) {
+        Ok(value)=>println!(">> {}", value.replace('\n', "\n   ")),
+        Err(e)=>println!("{}: {e}", color::paint(color::enabled(), color::ERROR, "Error")),
+    }
+}
+
+fn frame_text(frames: &[String], frame: usize)->String {
+    match frames.get(frame) {
+        Some(text)=>format!("#{frame} {text}"),
+        None=>format!("#{frame} <top level>"),
+    }
+}
+
+fn print_frame(frames: &[String], frame: usize) {
+    println!("Looking at {}", frame_text(frames, frame));
+}
+
+fn print_vars(mut vars: Vec<(String, String)>, empty: &str) {
+    if vars.is_empty() {
+        println!("{empty}");
+        return;
+    }
+
+    vars.sort_by(|l, r|l.0.cmp(&r.0));
+    let name_width = vars.iter().map(|v|v.0.chars().count()).max().unwrap_or(0);
+    for (name, value) in vars {
+        println!("    {name:<name_width$}  {value}");
+    }
+}
+
+fn print_help() {
+    println!(r#"Post-mortem help:"#);
+    println!(r#"    NAME, NAME/FIELD/0  Print a variable, or what a path leads to from it"#);
+    println!(r#"    :frame, :f [N]      Look up names in call `N` of the stack, or show which one is used"#);
+    println!(r#"    :stack, :bt         List the calls that were running, innermost first"#);
+    println!(r#"    :locals             List the variables of the current call"#);
+    println!(r#"    :globals            List the globals the program defined"#);
+    println!(r#"    :help               Display this message"#);
+    println!(r#"    :quit, :q           Exit with the program's error. So does <Ctrl+d>."#);
+}
+
+
+struct V1<'a> {
+    interpreter: &'a Interpreter,
+    state: &'a ConvertState,
+    frames: Vec<PostMortemFrame>,
+}
+impl Inspect for V1<'_> {
+    fn frames(&self)->Vec<String> {
+        self.frames.iter()
+            .map(|frame|frame.trace.to_string())
+            .collect()
+    }
+
+    fn locals(&self, frame: usize)->Vec<(String, String)> {
+        let interner = &self.state.interner;
+        self.frames.get(frame)
+            .into_iter()
+            .flat_map(|frame|frame.locals.iter())
+            .map(|(name, dr)|(interner.get(*name).to_string(), preview(dr, interner, PREVIEW_WIDTH)))
+            .collect()
+    }
+
+    fn globals(&self)->Vec<(String, String)> {
+        let interner = &self.state.interner;
+        self.interpreter.globals()
+            .filter(|(name, _)|!self.interpreter.is_builtin_global(*name))
+            .map(|(name, dr)|(interner.get(name).to_string(), preview(dr, interner, PREVIEW_WIDTH)))
+            .collect()
+    }
+
+    fn lookup(&self, frame: usize, path: &[&str])->Result<String> {
+        let interner = &self.state.interner;
+        let name = interner.lookup(path[0]);
+        let local = self.frames.get(frame)
+            .and_then(|frame|frame.locals.iter().find(|(local, _)|Some(*local) == name))
+            .map(|(_, dr)|(**dr).clone());
+        let Some(mut dr) = local.or_else(||self.interpreter.get_global(name?, interner)) else {
+            let visible = self.locals(frame)
+                .into_iter()
+                .chain(self.globals())
+                .map(|(name, _)|name)
+                .collect::<Vec<_>>();
+            bail!(LispError::UndefinedVar {
+                name: path[0].to_string(),
+                span: None,
+                suggestions: similar_names(path[0], visible.iter().map(String::as_str)),
+            });
+        };
+
+        for segment in &path[1..] {
+            dr = v1_segment(&dr, segment, path, self.state)?;
+        }
+
+        return Ok(pretty_format(&dr, interner, &PrettyConfig::for_terminal()));
+    }
+}
+
+/// The value `segment` of `path` picks out of `dr`
+fn v1_segment(dr: &DataRef, segment: &str, path: &[&str], state: &ConvertState)->Result<DataRef> {
+    let data = dr.get_data();
+    match &*data {
+        // a var closures share reads through its cell
+        Data::Cell(inner)=>v1_segment(inner, segment, path, state),
+        Data::Object(fields)=>state.interner.lookup(segment)
+            .and_then(|field|fields.get(&field))
+            .cloned()
+            .ok_or_else(||LispError::UndefinedField{name: segment.to_string()}.into()),
+        Data::List(items)=>match data_path::list_index(segment, items.len()) {
+            Some(Ok(i))=>Ok(items[i].clone()),
+            Some(Err(index))=>bail!(data_path::out_of_bounds(path.join("/"), false, index, items.len())),
+            None=>bail!(data_path::dead_end(path.join("/"), segment, "list", false)),
+        },
+        data=>bail!(data_path::dead_end(path.join("/"), segment, data.type_name(), false)),
+    }
+}
+
+
+struct V2<'a> {
+    interpreter: &'a interpreter2::Interpreter,
+    state: &'a interpreter2::ast::ConvertState,
+}
+impl Inspect for V2<'_> {
+    fn frames(&self)->Vec<String> {
+        self.interpreter.call_frames()
+            .map(|func|match func {
+                Some(id)=>format!("in `{}`", fn_name(id, self.state)),
+                None=>"in <not a function>".into(),
+            })
+            .collect()
+    }
+
+    /// Locals don't keep their names, so they are `%SLOT`
+    fn locals(&self, frame: usize)->Vec<(String, String)> {
+        self.interpreter.frame_locals(frame)
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(slot, value)|(format!("%{slot}"), value_text(value, self.state, PREVIEW_WIDTH)))
+            .collect()
+    }
+
+    fn globals(&self)->Vec<(String, String)> {
+        self.state.vars.module_globals()
+            .filter_map(|name|{
+                let name = self.state.interner.get(name);
+                let slot = self.state.lookup_var(name)?;
+                let value = self.interpreter.global(slot.id)?;
+
+                Some((name.to_string(), value_text(value, self.state, PREVIEW_WIDTH)))
+            })
+            .collect()
+    }
+
+    fn lookup(&self, frame: usize, path: &[&str])->Result<String> {
+        let local = path[0].strip_prefix('%')
+            .and_then(|slot|slot.parse::<usize>().ok())
+            .and_then(|slot|self.interpreter.frame_locals(frame)?.get(slot));
+        let mut value = match local {
+            Some(value)=>value,
+            None=>match self.state.lookup_var(path[0]).filter(|slot|slot.global) {
+                Some(slot)=>match self.interpreter.global(slot.id) {
+                    Some(value)=>value,
+                    None=>bail!("`{}` was never set", path[0]),
+                },
+                None=>bail!(self.state.undefined_var(path[0])),
+            },
+        };
+
+        for segment in &path[1..] {
+            let Some(items) = value.list_items() else {
+                bail!(data_path::dead_end(path.join("/"), segment, value.type_name(), false));
+            };
+            value = match data_path::list_index(segment, items.len()) {
+                Some(Ok(i))=>&items[i],
+                Some(Err(index))=>bail!(data_path::out_of_bounds(path.join("/"), false, index, items.len())),
+                None=>bail!(data_path::dead_end(path.join("/"), segment, "list", false)),
+            };
+        }
+
+        return Ok(value_text(value, self.state, usize::MAX));
+    }
+}
@@ -0,0 +1,160 @@
+//! Reading source files and finding modules, shared by both interpreters and the CLI.
+
+
+use std::{
+    env::{
+        var_os,
+        split_paths,
+    },
+    fs::{
+        read,
+        read_dir,
+    },
+    io,
+    ops::Range,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use logos::Logos;
+use crate::{
+    error::{
+        LispError,
+        ReadFailure,
+    },
+    lexer::*,
+};
+
+
+/// Read `path` for something that might be source or bytecode
+pub fn read_file(path: &Path)->Result<Vec<u8>, LispError> {
+    if path.is_dir() {
+        return Err(LispError::Read {
+            path: path.to_path_buf(),
+            reason: ReadFailure::Directory,
+        });
+    }
+
+    return read(path).map_err(|e|LispError::Read {
+        path: path.to_path_buf(),
+        reason: ReadFailure::Io(e),
+    });
+}
+
+/// Add every `.slp` file under `dir` to `out`, searching subdirectories too
+pub fn find_sources(dir: &Path, out: &mut Vec<PathBuf>)->io::Result<()> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_sources(&path, out)?;
+        } else if path.extension().is_some_and(|e|e == "slp") {
+            out.push(path);
+        }
+    }
+
+    return Ok(());
+}
+
+/// Read the source file at `path`
+pub fn read_source(path: &Path)->Result<String, LispError> {
+    let bytes = read_file(path)?;
+
+    return String::from_utf8(bytes).map_err(|e|LispError::Read {
+        path: path.to_path_buf(),
+        reason: ReadFailure::NotUtf8 {
+            offset: e.utf8_error().valid_up_to(),
+        },
+    });
+}
+
+/// Where each top level form of `source` is, in order. A quote or splat is part of the form it
+/// applies to. The parser makes one expr for each of these, comments included.
+pub fn top_level_spans(source: &str)->Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut depth = 0usize;
+    let mut start = None;
+    for (token, span) in Token::lexer(source).spanned() {
+        let form_start = *start.get_or_insert(span.start);
+        match token {
+            Ok(Token::Quote|Token::Splat) if depth == 0=>continue,
+            Ok(Token::List(Start)|Token::Vector(Start)|Token::Squiggle(Start))=>depth += 1,
+            Ok(Token::List(End)|Token::Vector(End)|Token::Squiggle(End))=>depth = depth.saturating_sub(1),
+            _=>{},
+        }
+
+        if depth == 0 {
+            spans.push(form_start..span.end);
+            start = None;
+        }
+    }
+
+    return spans;
+}
+
+/// The environment variable with more directories to look for modules in, separated like `PATH`
+pub const SEARCH_PATH_VAR: &str = "SIMPLE_LISP_PATH";
+
+
+/// Where modules are looked for when they aren't next to the module that declared them
+#[derive(Debug, Clone, Default)]
+pub struct SearchPath {
+    /// Searched in order
+    pub dirs: Vec<PathBuf>,
+}
+impl SearchPath {
+    /// `includes` first, then the directories in `SIMPLE_LISP_PATH`
+    pub fn new(includes: Vec<PathBuf>)->Self {
+        let mut dirs = includes;
+        if let Some(var) = var_os(SEARCH_PATH_VAR) {
+            dirs.extend(split_paths(&var).filter(|p|!p.as_os_str().is_empty()));
+        }
+
+        return SearchPath {dirs};
+    }
+}
+
+/// Where a module was found
+pub struct FoundModule {
+    pub path: PathBuf,
+    /// Where its own modules are
+    pub dir: PathBuf,
+    /// Other files for the same module further down the search path. These are ignored.
+    pub shadowed: Vec<PathBuf>,
+}
+
+/// Find the file for module `name`. It can be either `name.slp` or `name/mod.slp`, tried in that
+/// order in `dir` and then in each directory of `search`. One next to the module that declared it
+/// always wins.
+pub fn find_module(dir: &Path, name: &str, search: &SearchPath)->Result<FoundModule, LispError> {
+    let mut tried = Vec::new();
+    let mut found = Vec::new();
+    for (i, root) in [dir].into_iter().chain(search.dirs.iter().map(PathBuf::as_path)).enumerate() {
+        let candidates = [
+            root.join(format!("{name}.slp")),
+            root.join(name).join("mod.slp"),
+        ];
+
+        if let Some(path) = candidates.iter().find(|p|p.is_file()) {
+            found.push((path.clone(), root.join(name)));
+            if i == 0 {
+                break;
+            }
+        }
+        tried.extend(candidates);
+    }
+
+    let mut found = found.into_iter();
+    let Some((path, dir)) = found.next() else {
+        return Err(LispError::ModuleNotFound {
+            name: name.to_string(),
+            tried,
+        });
+    };
+
+    return Ok(FoundModule {
+        path,
+        dir,
+        shadowed: found.map(|(p, _)|p).collect(),
+    });
+}
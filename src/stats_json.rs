@@ -0,0 +1,110 @@
+//! `--stats-json`: the stats for nerds as one JSON object, so scripts and CI can compare runs. It
+//! goes to stderr or a file so it never mixes with what the program prints.
+//!
+//! The field names are an interface. Adding fields is fine, but don't rename or remove any;
+//! `STATS_FIELDS` lists them and `tests/stats.rs` checks the output against it.
+
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::time::Duration;
+use crate::gc_config::GcStats;
+
+
+/// Every field in a report with what it means. Fields of `gc` are written as `gc.FIELD`.
+pub const STATS_FIELDS: &[(&str, &str)] = &[
+    ("version", "The version of simple_lisp that wrote the report"),
+    ("interpreter", "`v1` or `v2`"),
+    ("iterations", "How many runs the numbers are for. Always 1 except for `bench`, where they are means."),
+    ("parse_ns", "How long parsing took, or null for bytecode"),
+    ("instructions", "Instructions executed"),
+    ("allocations", "Objects put on the heap"),
+    ("max_stack_depth", "The deepest the call stack got, or null if the interpreter doesn't track it"),
+    ("runtime_ns", "How long running took, not counting parsing and converting"),
+    ("instructions_per_second", "`instructions` divided by the runtime"),
+    ("gc", "What the collector did, or null if it wasn't tracked"),
+    ("gc.minor_collections", "Nursery only collections"),
+    ("gc.major_collections", "Full collections"),
+    ("gc.minor_ns", "Total time in minor collections"),
+    ("gc.major_ns", "Total time in major collections"),
+    ("gc.max_pause_ns", "The longest the program was stopped for a collection"),
+    ("gc.freed_objects", "Objects freed"),
+    ("gc.freed_bytes", "Roughly how many bytes the freed objects took up"),
+    ("gc.peak_live", "The most objects the heap held at once"),
+];
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub version: String,
+    pub interpreter: String,
+    pub iterations: usize,
+    pub parse_ns: Option<u64>,
+    pub instructions: u64,
+    pub allocations: u64,
+    pub max_stack_depth: Option<usize>,
+    pub runtime_ns: u64,
+    pub instructions_per_second: f64,
+    pub gc: Option<GcReport>,
+}
+impl StatsReport {
+    /// A report for one run. Fill in the optional fields after.
+    pub fn new(interpreter: &str, runtime: Duration, instructions: u64, allocations: u64)->Self {
+        // JSON has no infinity, so a run too fast to time gets 0
+        let instructions_per_second = match runtime.is_zero() {
+            true=>0.0,
+            false=>instructions as f64 / runtime.as_secs_f64(),
+        };
+
+        StatsReport {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            interpreter: interpreter.to_string(),
+            iterations: 1,
+            parse_ns: None,
+            instructions,
+            allocations,
+            max_stack_depth: None,
+            runtime_ns: as_nanos(runtime),
+            instructions_per_second,
+            gc: None,
+        }
+    }
+
+    pub fn to_json(&self)->String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GcReport {
+    pub minor_collections: u64,
+    pub major_collections: u64,
+    pub minor_ns: u64,
+    pub major_ns: u64,
+    pub max_pause_ns: u64,
+    pub freed_objects: u64,
+    pub freed_bytes: u64,
+    pub peak_live: usize,
+}
+impl From<&GcStats> for GcReport {
+    fn from(stats: &GcStats)->Self {
+        GcReport {
+            minor_collections: stats.minor_collections,
+            major_collections: stats.major_collections,
+            minor_ns: as_nanos(stats.minor_time),
+            major_ns: as_nanos(stats.major_time),
+            max_pause_ns: as_nanos(stats.max_pause),
+            freed_objects: stats.freed_objects,
+            freed_bytes: stats.freed_bytes,
+            peak_live: stats.peak_live,
+        }
+    }
+}
+
+
+#[inline]
+pub fn as_nanos(d: Duration)->u64 {
+    d.as_nanos() as u64
+}
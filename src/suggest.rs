@@ -0,0 +1,60 @@
+//! "Did you mean" suggestions for misspelled names.
+
+
+/// Names further away than this aren't suggested
+const MAX_DISTANCE: usize = 2;
+
+/// How many names a suggestion lists at most
+const MAX_SUGGESTIONS: usize = 3;
+
+
+/// Levenshtein distance between `a` and `b`, counted in chars
+pub fn edit_distance(a: &str, b: &str)->usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut cur = vec![0; b.len() + 1];
+
+    for (i, ac) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, bc) in b.iter().enumerate() {
+            let substitute = prev[j] + (ac != *bc) as usize;
+            cur[j + 1] = substitute
+                .min(prev[j + 1] + 1)
+                .min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    return prev[b.len()];
+}
+
+/// The names in `candidates` that are close to `name`, closest first
+pub fn similar_names<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>)->Vec<String> {
+    let mut found = candidates.into_iter()
+        .filter(|c|*c != name)
+        .map(|c|(edit_distance(name, c), c))
+        .filter(|(dist, _)|*dist <= MAX_DISTANCE)
+        .collect::<Vec<_>>();
+    found.sort();
+    found.dedup();
+
+    return found.into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)|c.to_string())
+        .collect();
+}
+
+/// Formats `names` like "did you mean `a`, `b` or `c`?". `None` if there aren't any.
+pub fn did_you_mean(names: &[String])->Option<String> {
+    let (last, rest) = names.split_last()?;
+    if rest.is_empty() {
+        return Some(format!("did you mean `{last}`?"));
+    }
+
+    let rest = rest.iter()
+        .map(|n|format!("`{n}`"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    return Some(format!("did you mean {rest} or `{last}`?"));
+}
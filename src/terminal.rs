@@ -0,0 +1,276 @@
+//! Raw terminal input for interactive programs. `parse_key` turns the bytes a terminal sends into
+//! keys, and is kept apart from the reading so it can be tested without a terminal.
+//!
+//! Raw mode is process wide, so a `RawMode` guard turns it back off when it is dropped, and a panic
+//! hook turns it off before the panic message is printed. Anything that exits the process without
+//! dropping the interpreter has to call `restore` first, or the user's shell is left in raw mode.
+
+
+use crossterm::terminal::{
+    enable_raw_mode,
+    disable_raw_mode,
+};
+use std::{
+    io::{
+        Read,
+        Result as IoResult,
+        stdin,
+    },
+    sync::{
+        Mutex,
+        Once,
+        OnceLock,
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc::{
+            Receiver,
+            RecvTimeoutError,
+            channel,
+        },
+    },
+    panic,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+
+/// How long to wait for the rest of an escape sequence before deciding it was the escape key
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(25);
+
+/// Whether a `RawMode` is alive
+static RAW: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: Once = Once::new();
+static INPUT: OnceLock<Mutex<Input>> = OnceLock::new();
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// Like `up`, `enter` or `pageDown`
+    Named(&'static str),
+    /// Ctrl and a letter, which terminals send as 1 through 26
+    Ctrl(char),
+}
+impl Key {
+    /// The name natives return for keys that aren't chars
+    pub fn name(&self)->Option<String> {
+        match self {
+            Self::Char(_)=>None,
+            Self::Named(name)=>Some(name.to_string()),
+            Self::Ctrl(c)=>Some(format!("ctrl-{c}")),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parsed {
+    /// The key and how many bytes it took
+    Key(Key, usize),
+    /// Nothing yet, or the start of a sequence that needs more bytes
+    Incomplete,
+}
+
+
+/// Parse the first key in `bytes`. If more bytes aren't coming, an incomplete sequence should be
+/// handled by `parse_partial`.
+pub fn parse_key(bytes: &[u8])->Parsed {
+    let Some(&first) = bytes.first() else {
+        return Parsed::Incomplete;
+    };
+
+    let key = match first {
+        0x1b=>return parse_escape(bytes),
+        b'\r'|b'\n'=>Key::Named("enter"),
+        b'\t'=>Key::Named("tab"),
+        0x7f|0x08=>Key::Named("backspace"),
+        0x00=>Key::Named("ctrl-space"),
+        0x01..=0x1a=>Key::Ctrl((b'a' + first - 1) as char),
+        0x1c..=0x1f=>Key::Named("unknown"),
+        _=>return parse_utf8(bytes),
+    };
+
+    return Parsed::Key(key, 1);
+}
+
+/// What the start of `bytes` is when nothing else is coming: a lone escape or a broken char
+pub fn parse_partial(bytes: &[u8])->Option<(Key, usize)> {
+    match bytes.first()? {
+        0x1b=>Some((Key::Named("escape"), 1)),
+        _=>Some((Key::Char(char::REPLACEMENT_CHARACTER), 1)),
+    }
+}
+
+fn parse_utf8(bytes: &[u8])->Parsed {
+    let len = match bytes[0] {
+        0x00..=0x7f=>1,
+        0xc0..=0xdf=>2,
+        0xe0..=0xef=>3,
+        0xf0..=0xf7=>4,
+        _=>return Parsed::Key(Key::Char(char::REPLACEMENT_CHARACTER), 1),
+    };
+    if bytes.len() < len {
+        return Parsed::Incomplete;
+    }
+
+    match std::str::from_utf8(&bytes[..len]) {
+        Ok(s)=>Parsed::Key(Key::Char(s.chars().next().unwrap()), len),
+        Err(_)=>Parsed::Key(Key::Char(char::REPLACEMENT_CHARACTER), 1),
+    }
+}
+
+/// `ESC [ ...` and `ESC O ...` are keys like the arrows. An escape followed by anything else is
+/// just the escape key, and the next byte is a key of its own.
+fn parse_escape(bytes: &[u8])->Parsed {
+    match bytes.get(1) {
+        None=>Parsed::Incomplete,
+        Some(b'[')=>parse_csi(bytes),
+        Some(b'O')=>match bytes.get(2) {
+            None=>Parsed::Incomplete,
+            Some(&last)=>Parsed::Key(final_key(last, None), 3),
+        },
+        Some(_)=>Parsed::Key(Key::Named("escape"), 1),
+    }
+}
+
+/// `ESC [`, then numbers separated by `;`, then a final byte. The numbers after the first are
+/// modifiers, which are ignored.
+fn parse_csi(bytes: &[u8])->Parsed {
+    for (index, &b) in bytes.iter().enumerate().skip(2) {
+        match b {
+            b'0'..=b'9'|b';'=>{},
+            0x40..=0x7e=>{
+                let params = std::str::from_utf8(&bytes[2..index]).unwrap();
+                let first = params.split(';')
+                    .next()
+                    .and_then(|n|n.parse::<u32>().ok());
+                return Parsed::Key(final_key(b, first), index + 1);
+            },
+            // not something we know how to read, so only the escape is taken
+            _=>return Parsed::Key(Key::Named("escape"), 1),
+        }
+    }
+
+    return Parsed::Incomplete;
+}
+
+fn final_key(last: u8, number: Option<u32>)->Key {
+    let name = match (last, number) {
+        (b'A', _)=>"up",
+        (b'B', _)=>"down",
+        (b'C', _)=>"right",
+        (b'D', _)=>"left",
+        (b'H', _)=>"home",
+        (b'F', _)=>"end",
+        (b'Z', _)=>"backTab",
+        (b'~', Some(1|7))=>"home",
+        (b'~', Some(4|8))=>"end",
+        (b'~', Some(2))=>"insert",
+        (b'~', Some(3))=>"delete",
+        (b'~', Some(5))=>"pageUp",
+        (b'~', Some(6))=>"pageDown",
+        _=>"unknown",
+    };
+
+    return Key::Named(name);
+}
+
+
+/// Raw mode is on while this is alive
+pub struct RawMode(());
+impl RawMode {
+    pub fn enable()->IoResult<Self> {
+        PANIC_HOOK.call_once(||{
+            let old_hook = panic::take_hook();
+            panic::set_hook(Box::new(move|info|{
+                restore();
+                old_hook(info);
+            }));
+        });
+
+        enable_raw_mode()?;
+        RAW.store(true, Ordering::SeqCst);
+
+        return Ok(RawMode(()));
+    }
+}
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Turn raw mode off if a `RawMode` turned it on
+pub fn restore() {
+    if RAW.swap(false, Ordering::SeqCst) {
+        let _ = disable_raw_mode();
+    }
+}
+
+
+/// The bytes read from stdin and not used yet. A thread reads stdin so waiting for a key can time
+/// out.
+struct Input {
+    bytes: Receiver<u8>,
+    pending: Vec<u8>,
+}
+impl Input {
+    fn new()->Self {
+        let (send, bytes) = channel();
+        thread::spawn(move||{
+            for byte in stdin().lock().bytes() {
+                let Ok(byte) = byte else {break};
+                if send.send(byte).is_err() {break}
+            }
+        });
+
+        return Input {bytes, pending: Vec::new()};
+    }
+
+    /// Wait for one more byte, up to `timeout`. False if none came.
+    fn wait(&mut self, timeout: Option<Duration>)->bool {
+        let byte = match timeout {
+            Some(timeout)=>self.bytes.recv_timeout(timeout),
+            None=>self.bytes.recv().map_err(|_|RecvTimeoutError::Disconnected),
+        };
+        match byte {
+            Ok(byte)=>{
+                self.pending.push(byte);
+                true
+            },
+            Err(_)=>false,
+        }
+    }
+
+    fn take(&mut self, key: Key, len: usize)->Key {
+        self.pending.drain(..len);
+        return key;
+    }
+}
+
+/// Read one key from stdin. `None` if `timeout` passed first or stdin ended. Once this is used,
+/// stdin should only be read through it, since the bytes it read ahead aren't given back.
+pub fn read_key(timeout: Option<Duration>)->Option<Key> {
+    let deadline = timeout.map(|t|Instant::now() + t);
+    let mut input = INPUT.get_or_init(||Mutex::new(Input::new())).lock().unwrap();
+
+    loop {
+        if let Parsed::Key(key, len) = parse_key(&input.pending) {
+            return Some(input.take(key, len));
+        }
+
+        if input.pending.is_empty() {
+            let left = deadline.map(|d|d.saturating_duration_since(Instant::now()));
+            if !input.wait(left) {
+                return None;
+            }
+        } else if !input.wait(Some(ESCAPE_TIMEOUT)) {
+            let (key, len) = parse_partial(&input.pending).unwrap();
+            return Some(input.take(key, len));
+        }
+    }
+}
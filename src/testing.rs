@@ -0,0 +1,85 @@
+//! `simple_lisp test`: runs the `deftest`s in a script with the V1 interpreter. The script runs
+//! first so every test sees its globals, then each test is called on its own with fresh scopes.
+//! What a test prints is captured so it is only shown if the test fails.
+
+
+use anyhow::{
+    Error,
+    Result,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+use crate::{
+    interpreter::{
+        ast::ConvertState,
+        data::Data,
+    },
+    output::Captured,
+    InterpreterOptions,
+};
+
+
+/// What running one test did
+#[derive(Debug)]
+pub struct TestOutcome {
+    pub name: String,
+    pub time: Duration,
+    /// What the test printed
+    pub stdout: String,
+    /// Why it failed, with the calls it failed in. `None` if it passed.
+    pub error: Option<Error>,
+}
+
+/// How running a script's tests went
+#[derive(Debug, Default)]
+pub struct TestRun {
+    /// In the order the tests were defined
+    pub outcomes: Vec<TestOutcome>,
+    /// How many tests `filter` left out
+    pub filtered: usize,
+}
+impl TestRun {
+    pub fn failed(&self)->usize {
+        self.outcomes.iter()
+            .filter(|o|o.error.is_some())
+            .count()
+    }
+}
+
+
+/// Run the script in `state`, then every test with `filter` in its name. Errors if the script
+/// itself fails, since its tests can't run without it.
+pub fn run_tests(state: &mut ConvertState, options: InterpreterOptions, filter: Option<&str>)->Result<TestRun> {
+    let output = Captured::new();
+    let mut interpreter = options.new_interpreter_with_output(state, Box::new(output.clone()));
+    interpreter.run(state, None)?;
+    // only what the tests print is shown
+    output.take();
+
+    let mut run = TestRun::default();
+    for test in state.tests.clone() {
+        let name = state.interner.get(test.name).to_string();
+        if filter.is_some_and(|filter|!name.contains(filter)) {
+            run.filtered += 1;
+            continue;
+        }
+
+        // the test can allocate, so keep it alive until it returns
+        let func = interpreter.alloc(Data::Fn(test.id)).external();
+        let start = Instant::now();
+        let res = interpreter.call(state, (*func).clone(), Vec::new());
+        let time = start.elapsed();
+        let _ = interpreter.output().flush();
+
+        run.outcomes.push(TestOutcome {
+            name,
+            time,
+            stdout: output.take(),
+            error: res.err(),
+        });
+    }
+
+    return Ok(run);
+}
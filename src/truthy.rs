@@ -0,0 +1,35 @@
+//! What counts as true when a program branches. Both interpreters and the `and`, `or` and `not`
+//! natives go through here so they can't disagree.
+//!
+//! Only `false` and `none` are falsy. `0`, `""`, empty lists and everything else are truthy. With
+//! `--strict-bool`, branching on anything but a bool or `none` is a type error instead.
+
+
+use anyhow::{
+    Result,
+    bail,
+};
+use crate::error::LispError;
+
+
+/// The part of a value that decides if it is truthy
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Truth {
+    Bool(bool),
+    None,
+    /// Anything else, with its type name for the error
+    Other(&'static str),
+}
+impl Truth {
+    pub fn is_truthy(self, op: &str, strict: bool)->Result<bool> {
+        match self {
+            Self::Bool(b)=>Ok(b),
+            Self::None=>Ok(false),
+            Self::Other(actual)=>if strict {
+                bail!(LispError::Type{op: op.into(), expected: "bool", actual});
+            } else {
+                Ok(true)
+            },
+        }
+    }
+}
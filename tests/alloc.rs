@@ -0,0 +1,57 @@
+//! V2 keeps numbers, bools, chars and `None` out of the heap, so arithmetic shouldn't allocate at
+//! all. V1 boxes every value, so it is the baseline.
+
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::Command,
+};
+
+
+/// A script that defines `count` globals, each one more than the last, and ends with the last one
+fn numeric_script(count: usize)->PathBuf {
+    let mut source = String::from("(def x0 0)\n");
+    for i in 1..=count {
+        source.push_str(&format!("(def x{i} (+ x{} 1))\n", i - 1));
+    }
+    source.push_str(&format!("x{count}\n"));
+
+    let path = std::env::temp_dir().join(format!("simple_lisp_alloc_{}_{count}.slp", std::process::id()));
+    fs::write(&path, source).unwrap();
+
+    return path;
+}
+
+/// Run `script` with `-s` and return the allocation count
+fn allocations(run: &str, script: &PathBuf)->u64 {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .args(["-s", run])
+        .arg(script)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let line = stdout.lines()
+        .find(|l|l.starts_with("Allocations: "))
+        .unwrap_or_else(||panic!("No allocation count from `{run}`: {stdout}"));
+
+    return line["Allocations: ".len()..].parse().unwrap();
+}
+
+#[test]
+fn numbers_are_not_boxed() {
+    // V2 can only run a few hundred instructions for now, so keep these short
+    let small = numeric_script(10);
+    let big = numeric_script(100);
+
+    let v1 = allocations("run", &big) - allocations("run", &small);
+    let v2 = allocations("run2", &big) - allocations("run2", &small);
+
+    fs::remove_file(small).unwrap();
+    fs::remove_file(big).unwrap();
+
+    // a literal and a result for every extra `def`
+    assert!(v1 >= 180, "V1 only allocated {v1} more objects");
+    assert_eq!(v2, 0, "V2 allocated for numbers");
+}
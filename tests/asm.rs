@@ -0,0 +1,68 @@
+//! The assembly form of V2 instructions. Everything the converter makes has to read back as the
+//! same instruction.
+
+
+mod common;
+
+use common::convert_source;
+use simple_lisp::interpreter2::asm::parse;
+
+
+const SOURCE: &str = r#"
+(def fib (fn [n] (cond (n (+ (fib (- n 1)) (fib (- n 2)))) (#t 0))))
+(def many (fn ([a] a) ([a b & rest] b)))
+(def s "a string with \"quotes\", a ; and a newline\n that is long enough to be cut off")
+(def c \a)
+(def anon (fn [x] x))
+(fib 10)
+"#;
+
+
+#[test]
+fn round_trip() {
+    let mut state = convert_source(SOURCE);
+    let instructions = state.instructions.raw_instructions().to_vec();
+    assert!(instructions.len() > 10);
+
+    for ins in instructions.iter() {
+        let text = format!("{:#}", ins.asm(&state));
+        let parsed = parse(&text, &mut state).unwrap_or_else(|e|panic!("`{text}`: {e}"));
+        assert_eq!(format!("{parsed:?}"), format!("{ins:?}"), "`{text}`");
+    }
+}
+
+#[test]
+fn short_form() {
+    let state = convert_source(SOURCE);
+    let listing = state.instructions.raw_instructions()
+        .iter()
+        .map(|ins|ins.asm(&state).to_string())
+        .collect::<Vec<_>>();
+
+    for expected in ["func fib/1", "func many/1,2+", "getg fib", "callb + 2", "get %0", "char 'a'"] {
+        assert!(listing.iter().any(|line|line == expected), "no `{expected}` in {listing:#?}");
+    }
+    assert!(listing.iter().any(|line|line.starts_with("jf @")), "{listing:#?}");
+    assert!(listing.iter().any(|line|line.starts_with("str \"a string with") && line.ends_with("...")), "{listing:#?}");
+}
+
+#[test]
+fn parse_errors() {
+    let mut state = convert_source(SOURCE);
+    for (text, error) in [
+        ("", "Expected an instruction"),
+        ("bogus 1", "Unknown instruction"),
+        ("call", "takes 1 operands"),
+        ("get x", "local slot"),
+        ("getg nope", "no global named `nope`"),
+        ("func nope/1", "no function `nope/1`"),
+        ("str \"unclosed", "Unclosed"),
+    ] {
+        let e = parse(text, &mut state).unwrap_err().to_string();
+        assert!(e.contains(error), "`{text}` failed with {e:?}");
+    }
+
+    // comments are ignored, even after quotes
+    let ins = parse("str \"a ; b\" ; the comment", &mut state).unwrap();
+    assert_eq!(format!("{:#}", ins.asm(&state)), "str \"a ; b\"");
+}
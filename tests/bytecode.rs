@@ -0,0 +1,284 @@
+//! Compiled `.slpc` files have to be from this format and interpreter version, and whole. Anything
+//! else is a clean error before any of the file is used, and so is a file that refers to strings,
+//! globals or instructions it doesn't have. Compiling a script doesn't change what it does. Their
+//! source maps have to point runtime errors at the source, even once it is gone if it was embedded.
+
+
+use simple_lisp::{
+    interpreter2::{
+        ast::{
+            Ident,
+            Instruction,
+            InstructionStore,
+            VarSlot,
+            convert,
+            convert_mapped,
+        },
+        bytecode::{
+            INTERPRETER_VERSION,
+            deserialize,
+            read_header,
+            serialize,
+        },
+    },
+    parser,
+    source::SearchPath,
+};
+use std::{
+    fs,
+    path::Path,
+    process::Command,
+};
+
+
+const SOURCE: &str = r#"
+(def pick (fn [a b] (cond (a "first") (b "second"))))
+(def s "a string constant")
+(pick #f #t)
+"#;
+
+
+fn compiled()->Vec<u8> {
+    let exprs = parser::new_parser(SOURCE).parse_all().unwrap();
+    let state = convert(exprs, Path::new("bytecode.slp"), SearchPath::default(), false).unwrap();
+    return serialize(&state);
+}
+
+/// Where the payload starts
+fn payload_start(bytes: &[u8])->usize {
+    let (_, payload) = read_header(bytes).unwrap();
+    return bytes.len() - payload.len();
+}
+
+fn error(bytes: &[u8])->String {
+    match deserialize(bytes) {
+        Ok(_)=>panic!("A broken file loaded"),
+        Err(e)=>format!("{e:#}"),
+    }
+}
+
+/// Run the binary with `args`. Returns the exit code and stdout.
+fn simple_lisp(args: &[&str])->(Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .args(args)
+        .output()
+        .unwrap();
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+
+#[test]
+fn round_trip() {
+    let bytes = compiled();
+    let (header, payload) = read_header(&bytes).unwrap();
+    assert_eq!(header.interpreter_version, INTERPRETER_VERSION);
+    assert_eq!(header.payload_len, payload.len() as u64);
+
+    let state = deserialize(&bytes).unwrap();
+    assert!(state.lookup_var("pick").is_some());
+}
+
+#[test]
+fn every_bit_flip_is_caught() {
+    let bytes = compiled();
+    for i in 0..bytes.len() {
+        let mut broken = bytes.clone();
+        broken[i] ^= 1 << (i % 8);
+        error(&broken);
+    }
+
+    let mut broken = bytes.clone();
+    let last = broken.len() - 1;
+    broken[last] ^= 0x10;
+    assert!(error(&broken).contains("Bytecode file is corrupted"), "{}", error(&broken));
+}
+
+#[test]
+fn every_truncation_is_caught() {
+    let bytes = compiled();
+    for len in 0..bytes.len() {
+        error(&bytes[..len]);
+    }
+
+    // in the middle of the instructions
+    let len = payload_start(&bytes) + (bytes.len() - payload_start(&bytes)) / 2;
+    assert!(error(&bytes[..len]).contains("Bytecode file is truncated"), "{}", error(&bytes[..len]));
+
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert!(error(&longer).contains("trailing data"), "{}", error(&longer));
+}
+
+#[test]
+fn versions_have_to_match() {
+    let bytes = compiled();
+
+    let mut old = bytes.clone();
+    old[4..6].copy_from_slice(&6u16.to_le_bytes());
+    let e = error(&old);
+    assert!(e.contains("format version 6 is not supported"), "{e}");
+    assert!(e.contains("recompile the script with `simple_lisp compile`"), "{e}");
+
+    // the version is right after the magic, format version, byte order mark and its length
+    let at = 4 + 2 + 4 + 8;
+    let mut other = bytes.clone();
+    other[at..at + INTERPRETER_VERSION.len()].fill(b'9');
+    let e = error(&other);
+    let nines = "9".repeat(INTERPRETER_VERSION.len());
+    assert!(e.contains(&format!("compiled by simple_lisp {nines}, but this is {INTERPRETER_VERSION}")), "{e}");
+    assert!(e.contains("recompile the script with `simple_lisp compile`"), "{e}");
+}
+
+/// A file with a valid checksum can still be crafted to refer to things that don't exist
+#[test]
+fn out_of_bounds_references() {
+    let cases = [
+        (Instruction::SetVar(VarSlot {id: 9999, global: true}), "refers to global 9999"),
+        (Instruction::Ident(Ident(9999)), "refers to interned string 9999"),
+        (Instruction::Field(Ident(9999)), "refers to interned string 9999"),
+    ];
+    for (bad, message) in cases {
+        let exprs = parser::new_parser(SOURCE).parse_all().unwrap();
+        let mut state = convert(exprs, Path::new("bytecode.slp"), SearchPath::default(), false).unwrap();
+
+        let mut instructions = state.instructions.raw_instructions().to_vec();
+        let first_set = instructions.iter()
+            .position(|ins|matches!(ins, Instruction::SetVar(_)))
+            .unwrap();
+        instructions[first_set] = bad;
+        let order = state.instructions.ins_order().clone();
+        state.instructions = InstructionStore::from_parts(instructions, order);
+
+        let e = error(&serialize(&state));
+        assert!(e.contains(message), "{e}");
+    }
+}
+
+#[test]
+fn source_map_round_trip() {
+    let exprs = parser::new_parser(SOURCE).parse_all().unwrap();
+    let state = convert_mapped(exprs, SOURCE, Path::new("bytecode.slp"), SearchPath::default(), false).unwrap();
+    let loaded = deserialize(&serialize(&state)).unwrap();
+
+    let map = loaded.source_map.get().unwrap();
+    assert_eq!(map.files.len(), 1);
+    assert_eq!(map.files[0].path, Path::new("bytecode.slp"));
+    assert_eq!(map.files[0].contents.as_deref(), Some(SOURCE));
+
+    // every instruction that was kept is from one of the three forms, and each form made some
+    let mut lines = map.locations.values()
+        .map(|l|l.line)
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.dedup();
+    assert_eq!(lines, [2, 3, 4]);
+    assert_eq!(map.locations.len(), loaded.instructions.ins_order().len() - 1, "only the exit has no form");
+
+    // files without one still load
+    assert!(deserialize(&compiled()).unwrap().source_map.get().is_none());
+}
+
+#[test]
+fn embedded_sources() {
+    let dir = std::env::temp_dir().join(format!("simple_lisp_source_map_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("prog.slp");
+    let embedded = dir.join("embedded.slpc");
+    let plain = dir.join("plain.slpc");
+    fs::write(&source, "(def x 5)\n\n(% x 0)\n").unwrap();
+    let source_str = source.to_str().unwrap();
+
+    let (code, stdout) = simple_lisp(&["--no-prelude", "compile", "--embed-sources", source_str, "-o", embedded.to_str().unwrap()]);
+    assert_eq!(code, Some(0), "{stdout}");
+    let (code, stdout) = simple_lisp(&["--no-prelude", "compile", source_str, "-o", plain.to_str().unwrap()]);
+    assert_eq!(code, Some(0), "{stdout}");
+    fs::remove_file(&source).unwrap();
+
+    let (embedded_code, embedded_run) = simple_lisp(&["--no-prelude", "--no-color", "run2", embedded.to_str().unwrap()]);
+    let (plain_code, plain_run) = simple_lisp(&["--no-prelude", "--no-color", "run2", plain.to_str().unwrap()]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let location = format!(" --> {source_str}:3:1\n");
+    assert_eq!(embedded_code, Some(1), "{embedded_run}");
+    assert!(embedded_run.starts_with("Error: "), "{embedded_run}");
+    assert!(embedded_run.contains(&location), "{embedded_run}");
+    assert!(embedded_run.contains("3 | (% x 0)\n  | ^\n"), "{embedded_run}");
+
+    // the source is gone, so all it knows is where it was
+    assert_eq!(plain_code, Some(1), "{plain_run}");
+    assert!(plain_run.contains(&location), "{plain_run}");
+    assert!(!plain_run.contains("3 | "), "{plain_run}");
+}
+
+/// Every script in `tests/difftest` prints the same, ends with the same value and exits the same
+/// whether it is run directly or compiled first. The ones V2 can't convert yet can't be compiled either.
+#[test]
+fn corpus_round_trip() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/difftest");
+    let dir = std::env::temp_dir().join(format!("simple_lisp_corpus_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut scripts = fs::read_dir(&corpus).unwrap()
+        .map(|entry|entry.unwrap().path())
+        .filter(|path|path.extension().is_some_and(|ext|ext == "slp"))
+        .collect::<Vec<_>>();
+    scripts.sort();
+    assert!(scripts.len() > 0);
+
+    let mut compiled_count = 0;
+    for script in scripts {
+        let script_str = script.to_str().unwrap();
+        let compiled = dir.join(script.file_stem().unwrap()).with_extension("slpc");
+        let compiled_str = compiled.to_str().unwrap();
+
+        let direct = simple_lisp(&["--no-prelude", "--no-color", "--print-result", "run2", script_str]);
+        let (code, stdout) = simple_lisp(&["--no-prelude", "compile", "--embed-sources", script_str, "-o", compiled_str]);
+        if code != Some(0) {
+            assert_ne!(direct.0, Some(0), "{script_str} compiled with {stdout}, but ran fine");
+            continue;
+        }
+        compiled_count += 1;
+
+        let loaded = simple_lisp(&["--no-prelude", "--no-color", "--print-result", "run2", compiled_str]);
+        assert_eq!(loaded, direct, "{script_str} runs differently once compiled");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(compiled_count > 0, "none of the corpus compiled");
+}
+
+#[test]
+fn cli() {
+    let dir = std::env::temp_dir().join(format!("simple_lisp_bytecode_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("prog.slp");
+    let compiled = dir.join("prog.slpc");
+    fs::write(&source, "(def x 40) (+ x 2)").unwrap();
+    let compiled_str = compiled.to_str().unwrap();
+
+    let (code, stdout) = simple_lisp(&["--no-prelude", "compile", source.to_str().unwrap()]);
+    assert_eq!(code, Some(0), "{stdout}");
+
+    let (code, stdout) = simple_lisp(&["compile", "--print-header", compiled_str]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains(&format!("Interpreter version: {INTERPRETER_VERSION}\n")), "{stdout}");
+    assert!(stdout.contains("Checksum:") && stdout.contains("(ok)"), "{stdout}");
+
+    let (code, stdout) = simple_lisp(&["--no-prelude", "run2", compiled_str]);
+    assert_eq!(code, Some(0), "{stdout}");
+
+    let mut bytes = fs::read(&compiled).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0x01;
+    fs::write(&compiled, &bytes).unwrap();
+
+    let (code, header) = simple_lisp(&["compile", "--print-header", compiled_str]);
+    let (run_code, run) = simple_lisp(&["--no-prelude", "run2", compiled_str]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(code, Some(0), "{header}");
+    assert!(header.contains("so the file is corrupted"), "{header}");
+    assert_eq!(run_code, Some(1), "{run}");
+    assert!(run.contains("Error: Bytecode file is corrupted"), "{run}");
+}
@@ -0,0 +1,83 @@
+//! Errors and warnings are colored when `CLICOLOR_FORCE` is set, and never with `--no-color` or
+//! `NO_COLOR`. The escape sequences are pinned here so a change to them is on purpose.
+
+
+use std::{
+    path::Path,
+    process::Command,
+};
+use simple_lisp::color;
+
+
+/// Run the binary with `args` from `tests/files`, with color forced on and `envs` set. Returns
+/// stdout.
+fn run(args: &[&str], envs: &[(&str, &str)])->String {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files");
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .current_dir(&dir)
+        .env_remove("NO_COLOR")
+        .env("CLICOLOR_FORCE", "1")
+        .envs(envs.iter().copied())
+        .args(args)
+        .output()
+        .unwrap();
+
+    return String::from_utf8(output.stdout).unwrap();
+}
+
+#[test]
+fn snippet() {
+    let source = "(def x 1)\n(+ 1 x))\n";
+    let expected = concat!(
+        "\x1b[1;31mError\x1b[0m: Unexpected `)`\n",
+        " \x1b[36m-->\x1b[0m \x1b[36mtest.slp:2:8\x1b[0m\n",
+        "  \x1b[36m|\x1b[0m\n",
+        "\x1b[36m2\x1b[0m \x1b[36m|\x1b[0m (+ 1 x))\n",
+        "  \x1b[36m|\x1b[0m        \x1b[1;31m^\x1b[0m\n",
+    );
+    assert_eq!(color::snippet(true, "Unexpected `)`", source, "test.slp", 17), expected);
+
+    let expected = concat!(
+        "Error: Unexpected `)`\n",
+        " --> test.slp:2:8\n",
+        "  |\n",
+        "2 | (+ 1 x))\n",
+        "  |        ^\n",
+    );
+    assert_eq!(color::snippet(false, "Unexpected `)`", source, "test.slp", 17), expected);
+}
+
+#[test]
+fn forced_color() {
+    let stdout = run(&["eval", "nope"], &[]);
+    assert!(stdout.starts_with("\x1b[1;31mError\x1b[0m: Var `nope` is not defined\n"), "{stdout:?}");
+
+    let stdout = run(&["run", "color_warning.slp"], &[]);
+    assert!(
+        stdout.starts_with("\x1b[1;33mWarning\x1b[0m (\x1b[36mcolor_warning.slp\x1b[0m): Var `unused` is never used"),
+        "{stdout:?}",
+    );
+
+    let stdout = run(&["run", "color_parse.slp"], &[]);
+    assert!(stdout.starts_with("\x1b[1;31mError\x1b[0m: "), "{stdout:?}");
+    assert!(stdout.contains("\x1b[36m1\x1b[0m \x1b[36m|\x1b[0m (+ 1 2))\n"), "{stdout:?}");
+    assert!(stdout.contains("\x1b[1;31m^\x1b[0m\n"), "{stdout:?}");
+    assert!(stdout.contains("\n\x1b[1;35mTrace:\x1b[0m\n"), "{stdout:?}");
+    assert!(stdout.contains("\x1b[2m└─ "), "{stdout:?}");
+}
+
+#[test]
+fn disabled() {
+    let cases: [(&[&str], &[(&str, &str)]); 4] = [
+        (&["--no-color", "eval", "nope"], &[]),
+        (&["eval", "nope"], &[("NO_COLOR", "1")]),
+        (&["--no-color", "run", "color_warning.slp"], &[]),
+        (&["run", "color_parse.slp"], &[("NO_COLOR", "1")]),
+    ];
+
+    for (args, envs) in cases {
+        let stdout = run(args, envs);
+        assert!(!stdout.is_empty(), "{args:?}");
+        assert!(!stdout.contains('\x1b'), "{args:?} was colored: {stdout:?}");
+    }
+}
@@ -0,0 +1,67 @@
+//! Helpers shared by the integration tests. Each test file that uses them declares `mod common;`,
+//! and not every file uses every helper.
+
+#![allow(dead_code)]
+
+
+use simple_lisp::{
+    interpreter2::ast::{
+        ConvertState,
+        convert,
+    },
+    parser,
+    source::SearchPath,
+};
+use std::{
+    path::Path,
+    process::Command,
+};
+
+
+/// Run the binary with `args` from `tests/files`. Returns the exit code and stdout.
+pub fn run_in_files(args: &[&str])->(Option<i32>, String) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files");
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .current_dir(&dir)
+        .args(args)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{args:?} panicked: {stderr}");
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+/// What the V1 interpreter prints last after evaluating `exprs` in order
+pub fn eval_v1(exprs: &[&str])->String {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .arg("eval")
+        .args(exprs)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    return stdout.lines().last().unwrap_or_default().to_string();
+}
+
+/// Evaluate `exprs` in order with the V1 interpreter. Returns the exit code and stdout.
+pub fn eval_v1_with_code(exprs: &[&str])->(Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .arg("eval")
+        .args(exprs)
+        .output()
+        .unwrap();
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+/// Convert `source` for V2 without the prelude. Panics if it doesn't parse.
+pub fn try_convert_source(source: &str)->anyhow::Result<ConvertState> {
+    let exprs = parser::new_parser(source).parse_all().unwrap();
+    return convert(exprs, Path::new("test.slp"), SearchPath::default(), false);
+}
+
+/// Like `try_convert_source`, but panics if it doesn't convert either
+pub fn convert_source(source: &str)->ConvertState {
+    try_convert_source(source).unwrap()
+}
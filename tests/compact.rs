@@ -0,0 +1,163 @@
+//! Removing instructions from the V2 store and compacting it. Every kind of jump has to land on
+//! the same instruction it did before the ids changed.
+
+
+mod common;
+
+use common::convert_source;
+use simple_lisp::{
+    interpreter2::{
+        ast::{
+            Instruction,
+            InstructionId,
+            InstructionStore,
+        },
+        bytecode::{
+            deserialize,
+            serialize,
+        },
+        optimize::optimize,
+    },
+};
+
+
+const SOURCE: &str = r#"
+(def pick (fn [a b]
+    (cond
+        (#f "never")
+        (a 1)
+        (b 2)
+        (#t 3))))
+(def count (fn [n] (cond (n (count (- n 1))) (#t 0))))
+(cond (#t (pick #f #t)) (#t "dead"))
+"#;
+
+
+fn target(ins: &Instruction)->Option<InstructionId> {
+    match ins {
+        Instruction::Jump(id)|
+            Instruction::JumpIfTrue(id)|
+            Instruction::JumpIfFalse(id)=>Some(*id),
+        _=>None,
+    }
+}
+
+/// The instructions in execution order, with jump targets written as positions in that order
+fn listing(store: &InstructionStore)->Vec<String> {
+    let order = store.ins_order();
+    let raw = store.raw_instructions();
+
+    return order.iter()
+        .map(|id|{
+            let ins = &raw[id.inner()];
+            match target(ins) {
+                Some(t)=>format!("{} -> {:?}", ins_kind(ins), order.get_index_of(&t)),
+                None=>format!("{ins:?}"),
+            }
+        })
+        .collect();
+}
+
+fn ins_kind(ins: &Instruction)->&'static str {
+    match ins {
+        Instruction::Jump(_)=>"Jump",
+        Instruction::JumpIfTrue(_)=>"JumpIfTrue",
+        Instruction::JumpIfFalse(_)=>"JumpIfFalse",
+        _=>"other",
+    }
+}
+
+#[test]
+fn compact_keeps_jump_targets() {
+    let mut store = InstructionStore::new();
+    let mut ids = Vec::new();
+    for n in 0..11 {
+        ids.push(store.push(Instruction::Number(n)));
+    }
+    store.set(ids[1], Instruction::Jump(ids[5]));
+    store.set(ids[3], Instruction::JumpIfTrue(ids[7]));
+    store.set(ids[6], Instruction::JumpIfFalse(ids[9]));
+    store.set(ids[10], Instruction::Jump(ids[0]));
+
+    assert!(store.remove(ids[2]));
+    assert!(!store.remove(ids[2]));
+    store.retain(|_, ins|!matches!(ins, Instruction::Number(4|8)));
+    let before = listing(&store);
+
+    let remap = store.compact();
+
+    assert_eq!(listing(&store), before);
+    assert_eq!(store.raw_instructions().len(), 8);
+    assert_eq!(store.ins_order().len(), 8);
+    for (index, id) in store.ins_order().iter().enumerate() {
+        assert_eq!(id.inner(), index);
+    }
+
+    assert_eq!(remap.get(ids[2]), None);
+    assert_eq!(remap.get(ids[4]), None);
+    assert_eq!(remap.get(ids[8]), None);
+    assert_eq!(remap.get(ids[9]).map(|id|id.inner()), Some(6));
+    let raw = store.raw_instructions();
+    assert!(matches!(raw[1], Instruction::Jump(t) if t == remap.expect(ids[5])));
+    assert!(matches!(raw[2], Instruction::JumpIfTrue(t) if t == remap.expect(ids[7])));
+    assert!(matches!(raw[4], Instruction::JumpIfFalse(t) if t == remap.expect(ids[9])));
+    assert!(matches!(raw[7], Instruction::Jump(t) if t == remap.expect(ids[0])));
+}
+
+#[test]
+#[should_panic(expected = "was dropped")]
+fn compact_panics_on_dangling_jump() {
+    let mut store = InstructionStore::new();
+    let first = store.push(Instruction::Nop);
+    let jump = store.push(Instruction::Jump(first));
+    store.remove(first);
+    assert!(target(&store.raw_instructions()[jump.inner()]).is_some());
+
+    store.compact();
+}
+
+#[test]
+fn serialize_compacts() {
+    let mut state = convert_source(SOURCE);
+    let stats = optimize(&mut state);
+    assert!(stats.dead_instructions > 0, "nothing was removed, so this doesn't test compaction");
+
+    // `cond` only makes `jf` and `jmp`, and the optimizer takes most of those out, so put one of
+    // each before the top level `exit`
+    let store = &mut state.instructions;
+    let exit = *store.ins_order().iter()
+        .find(|id|matches!(store.raw_instructions()[id.inner()], Instruction::Exit))
+        .unwrap();
+    store.insert_before(exit, Instruction::Bool(true));
+    let jt = store.insert_before(exit, Instruction::Nop);
+    let jf = store.insert_before(exit, Instruction::Nop);
+    let jmp = store.insert_before(exit, Instruction::Nop);
+    let dropped = store.insert_before(exit, Instruction::Number(99));
+    let end = store.insert_before(exit, Instruction::Nop);
+    store.set(jt, Instruction::JumpIfTrue(jmp));
+    store.set(jf, Instruction::JumpIfFalse(end));
+    store.set(jmp, Instruction::Jump(end));
+    store.remove(dropped);
+    assert!(store.raw_instructions().len() > store.ins_order().len());
+
+    let loaded = deserialize(&serialize(&state)).unwrap();
+
+    assert_eq!(listing(&loaded.instructions), listing(&state.instructions));
+    assert_eq!(loaded.instructions.raw_instructions().len(), loaded.instructions.ins_order().len());
+    for kind in ["Jump", "JumpIfTrue", "JumpIfFalse"] {
+        assert!(
+            listing(&loaded.instructions).iter().any(|line|line.starts_with(&format!("{kind} ->"))),
+            "no {kind} left to check",
+        );
+    }
+
+    for id in loaded.fn_ids() {
+        let f = loaded.fns.get(id).unwrap();
+        for (_, body_ptr) in f.sig.bodies() {
+            assert!(loaded.instructions.ins_order().contains(&body_ptr), "{id:?} starts at a dropped instruction");
+        }
+    }
+    for id in loaded.module_ids() {
+        assert!(loaded.instructions.ins_order().contains(&loaded.modules.get(id).start_ins));
+    }
+}
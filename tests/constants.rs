@@ -0,0 +1,78 @@
+//! `defconst` globals can't be set or defined again. V2 catches it while converting, V1 when it
+//! runs. What a constant holds can still change.
+
+
+mod common;
+
+use common::{
+    eval_v1_with_code,
+    try_convert_source,
+};
+use simple_lisp::{
+    error::LispError,
+    interpreter2::bytecode::{
+        deserialize,
+        serialize,
+    },
+};
+
+
+#[test]
+fn v2_rejects_changes() {
+    let cases = [
+        "(defconst limit 10) (set limit 11)",
+        "(defconst limit 10) (def limit 11)",
+        "(defconst limit 10) (defconst limit 11)",
+        "(defconst config 10) (set config/size 11)",
+        "(defconst limit 10) (def f (fn [] (set limit 11)))",
+    ];
+
+    for source in cases {
+        let Err(e) = try_convert_source(source) else {
+            panic!("`{source}` converted");
+        };
+        assert!(matches!(e.root_cause().downcast_ref(), Some(LispError::Constant{..})), "`{source}`: {e}");
+        assert!(e.to_string().contains("is a constant, so it can't be set or defined again"), "`{source}`: {e}");
+    }
+}
+
+#[test]
+fn v2_locals_can_use_the_name() {
+    try_convert_source("(defconst n 10) (def f (fn [n] (set n 11) n)) (f n)").unwrap();
+
+    let Err(e) = try_convert_source("(def f (fn [] (defconst n 1) n))") else {
+        panic!("`defconst` in a function converted");
+    };
+    assert!(e.to_string().contains("top level"), "{e}");
+}
+
+#[test]
+fn flag_survives_bytecode() {
+    let state = try_convert_source("(defconst limit 10) (def other 5) (+ limit other)").unwrap();
+    let loaded = deserialize(&serialize(&state)).unwrap();
+
+    assert!(loaded.vars.is_constant(loaded.lookup_var("limit").unwrap()));
+    assert!(!loaded.vars.is_constant(loaded.lookup_var("other").unwrap()));
+    assert!(!loaded.vars.is_constant(loaded.lookup_var("+").unwrap()));
+}
+
+#[test]
+fn v1_runtime_errors() {
+    for change in ["(set limit 11)", "(def limit 11)", "(defconst limit 11)"] {
+        let (code, stdout) = eval_v1_with_code(&["(defconst limit 10)", change]);
+        assert_eq!(code, Some(1), "{change}: {stdout}");
+        assert!(stdout.contains("Error: `limit` is a constant, so it can't be set or defined again"), "{change}: {stdout}");
+    }
+
+    // params are in the function's own env
+    let (code, stdout) = eval_v1_with_code(&["(defconst n 10)", "(defn f [n] (set n 11) n)", "(f n)"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.ends_with("11\n"), "{stdout}");
+}
+
+#[test]
+fn contents_can_change() {
+    let (code, stdout) = eval_v1_with_code(&["(defconst items (core/list 1))", "(+= items 2)", "(core/length items)"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.ends_with("2\n"), "{stdout}");
+}
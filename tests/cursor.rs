@@ -0,0 +1,128 @@
+//! `InstructionCursor`, the optimizer's way of walking the V2 execution order
+
+
+use simple_lisp::{
+    interpreter2::{
+        ast::{
+            convert,
+            Instruction,
+            InstructionId,
+            InstructionStore,
+        },
+        optimize::optimize,
+    },
+    parser,
+    source::SearchPath,
+};
+use std::path::Path;
+
+
+/// `Number(0)` to `Number(7)`, with 3 taken out of the execution order
+fn store()->(InstructionStore, Vec<InstructionId>) {
+    let mut store = InstructionStore::new();
+    let ids = (0..8)
+        .map(|n|store.push(Instruction::Number(n)))
+        .collect::<Vec<_>>();
+    store.remove(ids[3]);
+
+    return (store, ids);
+}
+
+fn num(ins: Option<&Instruction>)->Option<i64> {
+    match ins? {
+        Instruction::Number(n)=>Some(*n),
+        other=>panic!("Expected a number, but got {other:?}"),
+    }
+}
+
+#[test]
+fn peek_and_prev() {
+    let (store, ids) = store();
+    let mut cursor = store.cursor();
+
+    assert_eq!(num(cursor.prev()), None);
+    assert_eq!(num(cursor.peek()), Some(0));
+    assert_eq!(num(cursor.peek_n(3)), Some(4));
+    assert_eq!(cursor.peek_id_n(3), Some(ids[4]));
+    assert_eq!(num(cursor.peek_n(7)), None);
+
+    assert_eq!(num(cursor.next()), Some(0));
+    assert_eq!(num(cursor.next()), Some(1));
+    assert_eq!(cursor.cur_ins_id(), Some(ids[1]));
+    assert_eq!(cursor.next_ins_id(), Some(ids[2]));
+    assert_eq!(num(cursor.prev()), Some(1));
+    assert_eq!(num(cursor.next()), Some(1));
+}
+
+#[test]
+fn both_ends() {
+    let (store, _) = store();
+
+    let backwards = store.cursor().rev().map(|ins|num(Some(ins)).unwrap()).collect::<Vec<_>>();
+    assert_eq!(backwards, [7, 6, 5, 4, 2, 1, 0]);
+
+    let mut cursor = store.cursor();
+    assert_eq!(cursor.len(), 7);
+    assert_eq!(num(cursor.next_back()), Some(7));
+    assert_eq!(num(cursor.next()), Some(0));
+    assert_eq!(num(cursor.peek_n(4)), Some(6));
+    assert_eq!(num(cursor.peek_n(5)), None);
+    assert_eq!(cursor.by_ref().count(), 5);
+    assert_eq!(num(cursor.next_back()), None);
+}
+
+#[test]
+fn starting_in_the_middle() {
+    let (store, ids) = store();
+
+    let rest = store.iter_from(ids[4]).map(|ins|num(Some(ins)).unwrap()).collect::<Vec<_>>();
+    assert_eq!(rest, [4, 5, 6, 7]);
+
+    let mut cursor = store.iter_from(ids[4]);
+    assert_eq!(num(cursor.prev()), Some(2));
+}
+
+#[test]
+#[should_panic(expected = "Invalid ID")]
+fn starting_at_a_removed_instruction() {
+    let (store, ids) = store();
+    store.iter_from(ids[3]);
+}
+
+#[test]
+fn windows() {
+    let (store, ids) = store();
+    let mut windows = store.cursor().windows(3);
+
+    let mut seen = Vec::new();
+    while let Some(window) = windows.next_window() {
+        let nums = window.iter().map(|ins|num(Some(ins)).unwrap()).collect::<Vec<_>>();
+        if nums[0] == 1 {
+            assert_eq!(windows.ids(), &[ids[1], ids[2], ids[4]]);
+            // pretend the window was used up, so the next one starts after it
+            windows.skip(2);
+        }
+        seen.push(nums);
+    }
+
+    assert_eq!(seen, [vec![0, 1, 2], vec![1, 2, 4], vec![5, 6, 7]]);
+
+    let mut windows = store.cursor().windows(8);
+    assert!(windows.next_window().is_none());
+}
+
+#[test]
+fn passes_on_the_cursor() {
+    let source = "(def x 5) (def f (fn [a] a)) (f x)";
+    let exprs = parser::new_parser(source).parse_all().unwrap();
+    let mut state = convert(exprs, Path::new("cursor.slp"), SearchPath::default(), false).unwrap();
+    let stats = optimize(&mut state);
+    assert!(stats.fused_instructions >= 2, "{stats:?}");
+
+    let listing = state.instructions.cursor()
+        .map(|ins|format!("{ins:?}"))
+        .collect::<Vec<_>>();
+    assert!(listing.iter().any(|ins|ins.starts_with("NumberSetVar(5,")), "{listing:#?}");
+    assert!(listing.iter().any(|ins|ins.starts_with("GetVarCall(")), "{listing:#?}");
+    assert!(!listing.iter().any(|ins|ins.starts_with("Jump(")), "{listing:#?}");
+}
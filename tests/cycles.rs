@@ -0,0 +1,64 @@
+//! Data that holds itself has to print and compare without recursing forever
+
+
+mod common;
+
+use common::eval_v1_with_code;
+
+
+const PRINT: &str = "(defn print [x] (std/io/write std/io/stdout (std/string/format x \"\\n\")) None)";
+
+#[test]
+fn direct_cycle() {
+    let (code, stdout) = eval_v1_with_code(&[PRINT, "(def l (core/list 1))", "(+= l l)", "(print l)", "(= l l)"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("(1 #cycle->list)\n"), "{stdout}");
+}
+
+#[test]
+fn mutual_cycle() {
+    let (code, stdout) = eval_v1_with_code(&[
+        PRINT,
+        "(def a (core/list 1))",
+        "(def b (core/list 2 a))",
+        "(+= a b)",
+        "(print a)",
+        "(print b)",
+    ]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("(1 (2 #cycle->list))\n"), "{stdout}");
+    assert!(stdout.contains("(2 (1 #cycle->list))\n"), "{stdout}");
+}
+
+#[test]
+fn equal_cycles() {
+    // two different lists with the same shape, each holding itself
+    let (code, stdout) = eval_v1_with_code(&[
+        PRINT,
+        "(def l (core/list 1))",
+        "(+= l l)",
+        "(def m (core/list 1))",
+        "(+= m m)",
+        "(def n (core/list 2))",
+        "(+= n n)",
+        "(print (= l m))",
+        "(print (= l n))",
+    ]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("true\nfalse\n"), "{stdout}");
+}
+
+#[test]
+fn shared_data_is_not_a_cycle() {
+    // the same list showing up more than once, or deeply nested, isn't a cycle
+    let (code, stdout) = eval_v1_with_code(&[
+        PRINT,
+        "(def s (core/list 2))",
+        "(def d (core/list s s (core/list s (core/list (core/list s)))))",
+        "(print d)",
+        "(print (= d (core/list s s (core/list s (core/list (core/list s))))))",
+    ]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(!stdout.contains("cycle"), "{stdout}");
+    assert!(stdout.contains("((2) (2) ((2) (((2)))))\ntrue\n"), "{stdout}");
+}
@@ -0,0 +1,68 @@
+//! Path segments that look like integers index lists, and negative ones count from the end. Both
+//! interpreters pick the item with `data_path`, so its answers are what either of them does.
+//!
+//! V2 can't make lists from scripts yet, so only V1 runs the paths here.
+
+
+use simple_lisp::{
+    data_path::{
+        self,
+        list_index,
+    },
+    Engine,
+};
+
+
+#[test]
+fn indices() {
+    let cases = [
+        ("0", Some(Ok(0))),
+        ("2", Some(Ok(2))),
+        ("3", Some(Err(3))),
+        ("-1", Some(Ok(2))),
+        ("-3", Some(Ok(0))),
+        ("-4", Some(Err(-4))),
+        ("name", None),
+        ("1.5", None),
+    ];
+
+    for (segment, expected) in cases {
+        assert_eq!(list_index(segment, 3), expected, "{segment:?}");
+    }
+    assert_eq!(list_index("0", 0), Some(Err(0)));
+    assert_eq!(list_index("-1", 0), Some(Err(-1)));
+}
+
+#[test]
+fn messages() {
+    let err = data_path::out_of_bounds("foo/items/5".into(), true, 5, 2);
+    assert_eq!(err.to_string(), "`set foo/items/5`: index 5 is out of bounds for length 2");
+
+    let err = data_path::dead_end("foo/items/3/name".into(), "name", "none", true);
+    assert_eq!(err.to_string(), "Cannot access field `name` of none while setting `foo/items/3/name`");
+
+    let err = data_path::dead_end("foo/n/0".into(), "0", "number", false);
+    assert_eq!(err.to_string(), "Cannot access item `0` of number while reading `foo/n/0`");
+}
+
+#[test]
+fn v1_paths() {
+    let mut engine = Engine::new();
+    engine.eval_str("(def foo (object (.items (core/list (object (.name 1)) None))))").unwrap();
+
+    engine.eval_str("(set foo/items/0/name 2)").unwrap();
+    engine.eval_str("(set foo/items/-1 3)").unwrap();
+    let value = engine.eval_str("(core/list foo/items/-2/name foo/items/1)").unwrap();
+    let items = value.as_list().unwrap().iter().map(|v|v.as_i64()).collect::<Vec<_>>();
+    assert_eq!(items, [Some(2), Some(3)]);
+
+    let err = engine.eval_str("(set foo/items/2/name 4)").unwrap_err();
+    assert!(err.to_string().contains("index 2 is out of bounds for length 2"), "{err}");
+
+    engine.eval_str("(set foo/items/1 None)").unwrap();
+    let err = engine.eval_str("(set foo/items/1/name 4)").unwrap_err();
+    assert!(err.to_string().contains("Cannot access field `name` of none while setting `foo/items/1/name`"), "{err}");
+
+    let err = engine.eval_str("foo/items/name").unwrap_err();
+    assert!(err.to_string().contains("Cannot access field `name` of list while reading `foo/items/name`"), "{err}");
+}
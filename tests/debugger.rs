@@ -0,0 +1,110 @@
+//! `--debugger` reads its commands from stdin when it isn't a terminal, so a whole session can be
+//! piped in. `(breakpoint)` needs a terminal, so only skipping it can be tested here. `--trace`
+//! is here too since it is the non-interactive version of the debugger.
+
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{
+        Command,
+        Stdio,
+    },
+};
+
+
+/// Run the binary with `args` from `tests/files` and `input` on stdin. Returns the exit code,
+/// stdout, and stderr.
+fn run(args: &[&str], input: &str)->(Option<i32>, String, String) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .current_dir(&dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("panicked"), "{args:?} with {input:?} panicked: {stderr}");
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap(), stderr);
+}
+
+/// Run `debug.slp` under the debugger with `commands` on stdin. Returns the exit code and stdout.
+fn debug(commands: &str)->(Option<i32>, String) {
+    let (code, stdout, _) = run(&["--no-prelude", "--debugger", "run2", "debug.slp"], commands);
+    return (code, stdout);
+}
+
+#[test]
+fn pauses_at_start() {
+    let (_, stdout) = debug("stack\ncontinue\n");
+    assert!(stdout.contains("<top level> Id(0):"), "{stdout}");
+    assert!(stdout.contains("#0 <top level>"), "{stdout}");
+}
+
+#[test]
+fn breakpoints_and_print() {
+    let (_, stdout) = debug("print x\nbreak 3\ncontinue\nprint x\nprint y\ncontinue\n");
+    assert!(stdout.contains("`x` is not set yet"), "{stdout}");
+    assert!(stdout.contains("Breakpoint set at Id(3)"), "{stdout}");
+    assert!(stdout.contains("Breakpoint hit"), "{stdout}");
+    assert!(stdout.contains("x = 5"), "{stdout}");
+    assert!(stdout.contains("`y` is not set yet"), "{stdout}");
+}
+
+#[test]
+fn where_() {
+    let (_, stdout) = debug("where\nbreak 3\ncontinue\nw\ncontinue\n");
+    assert!(stdout.contains("At debug.slp:1:1\n1 | (def x 5)\n"), "{stdout}");
+    assert!(stdout.contains("At debug.slp:2:1\n2 | (def y (+ x 1))\n"), "{stdout}");
+}
+
+#[test]
+fn bad_commands() {
+    let (_, stdout) = debug("brek 3\nbreak nope\nbreak 100000\nprint nope\ncontinue\n");
+    assert!(stdout.contains("did you mean `break`?"), "{stdout}");
+    assert!(stdout.contains("There is no function named `nope`"), "{stdout}");
+    assert!(stdout.contains("There is no instruction with the id 100000"), "{stdout}");
+    assert!(stdout.contains("`nope` is not defined"), "{stdout}");
+}
+
+#[test]
+fn end_of_input_finishes() {
+    let (code, stdout) = debug("");
+    assert_eq!(code, Some(0), "{stdout}");
+}
+
+#[test]
+fn quit() {
+    let (_, stdout) = debug("quit\n");
+    assert!(stdout.contains("Stopped by the debugger"), "{stdout}");
+}
+
+#[test]
+fn breakpoint_without_terminal() {
+    let (code, stdout, stderr) = run(&["run", "breakpoint.slp"], ":abort\n");
+    assert_eq!(code, Some(0));
+    assert!(stderr.contains("skipping `(breakpoint)` because stdin is not a terminal"), "{stderr}");
+    assert_eq!(stdout.trim(), "3");
+
+    let (_, stdout, stderr) = run(&["--no-breakpoints", "run", "breakpoint.slp"], "");
+    assert!(stderr.contains("skipping `(breakpoint)` because breakpoints are disabled"), "{stderr}");
+    assert_eq!(stdout.trim(), "3");
+}
+
+#[test]
+fn trace() {
+    let (code, stdout, stderr) = run(&["--no-prelude", "--trace", "run2", "debug.slp"], "");
+    assert_eq!(code, Some(0));
+    assert!(!stdout.contains("Id("), "the trace should only be on stderr: {stdout}");
+    assert!(stderr.contains("int 1 => 1"), "{stderr}");
+    assert!(stderr.contains("callb + 2 => 6"), "{stderr}");
+
+    let (_, _, stderr) = run(&["--no-prelude", "--trace-filter", "nope", "run2", "debug.slp"], "");
+    assert!(!stderr.contains("Id("), "nothing runs in `nope`: {stderr}");
+}
@@ -0,0 +1,73 @@
+//! `(def [a b & rest] data)` binds each name to an item of a list, and checks the length first.
+//!
+//! V2 can't make lists from scripts yet, so its side unpacks `*args*`.
+
+
+use simple_lisp::{
+    interpreter2::{
+        self,
+        data::Primitive,
+        verify::verify,
+    },
+    output::Captured,
+    parser,
+    Engine,
+    InterpreterOptions,
+};
+
+
+#[test]
+fn v1_lengths() {
+    let mut engine = Engine::new();
+    engine.eval_str("(def [a b] (core/list 1 2))").unwrap();
+    assert_eq!(engine.get_global("b").unwrap().as_i64(), Some(2));
+
+    engine.eval_str("(def [c & rest] (core/list 3 4 5))").unwrap();
+    assert_eq!(engine.get_global("c").unwrap().as_i64(), Some(3));
+    let rest = engine.get_global("rest").unwrap().as_list().unwrap();
+    assert_eq!(rest.iter().map(|v|v.as_i64()).collect::<Vec<_>>(), [Some(4), Some(5)]);
+
+    let err = engine.eval_str("(def [d e f] (core/list 1 2))").unwrap_err();
+    assert!(err.to_string().contains("`[d e f]` needs exactly 3 items, but the list has 2"), "{err}");
+    assert!(engine.get_global("d").is_none());
+
+    let err = engine.eval_str("(def [g] (core/list 1 2))").unwrap_err();
+    assert!(err.to_string().contains("`[g]` needs exactly 1 item, but the list has 2"), "{err}");
+
+    let err = engine.eval_str("(def [h & more] \"abc\")").unwrap_err();
+    assert!(err.to_string().contains("`def` expected list, but got string"), "{err}");
+}
+
+#[test]
+fn v2_unpacks_args() {
+    use interpreter2::ast::{
+        ConvertState,
+        repl_convert,
+    };
+
+    let mut state = ConvertState::new();
+    let mut interpreter = InterpreterOptions::default().new_interpreter2_with_output(&mut state, Box::new(Captured::new()));
+    let args = ["a", "b", "c"].map(String::from);
+    interpreter.set_script_args("destructure.slp", &args);
+
+    let run = |interpreter: &mut interpreter2::Interpreter, state: &mut ConvertState, source: &str|{
+        let exprs = parser::new_parser(source).parse_all().unwrap();
+        let start = repl_convert(state, exprs)?;
+        verify(state).unwrap();
+        interpreter.run(state, Some(start))
+    };
+
+    let value = run(&mut interpreter, &mut state, "(def [first & rest] *args*) first").unwrap();
+    assert!(matches!(&value, Primitive::String(s) if s.as_str() == "a"), "{value:?}");
+    let value = run(&mut interpreter, &mut state, "rest").unwrap();
+    assert_eq!(value.list_items().map(<[_]>::len), Some(2));
+
+    let value = run(&mut interpreter, &mut state, "(def [x y z] *args*) z").unwrap();
+    assert!(matches!(&value, Primitive::String(s) if s.as_str() == "c"), "{value:?}");
+
+    let err = run(&mut interpreter, &mut state, "(def [p q] *args*)").unwrap_err();
+    assert!(err.to_string().contains("`[p q]` needs exactly 2 items, but the list has 3"), "{err}");
+
+    let err = run(&mut interpreter, &mut state, "(def [p q] *script*)").unwrap_err();
+    assert!(err.to_string().contains("`def` expected list, but got string"), "{err}");
+}
@@ -0,0 +1,47 @@
+//! Runs `tests/difftest` through `simple_lisp difftest`, so the two interpreters can't drift apart
+//! without a test failing.
+
+
+use std::{
+    fs,
+    path::Path,
+    process::Command,
+};
+
+
+/// Run `difftest` on `dir`. Returns the exit code and stdout.
+fn difftest(dir: &Path)->(Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .arg("--no-prelude")
+        .arg("difftest")
+        .arg(dir)
+        .output()
+        .unwrap();
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn interpreters_agree() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/difftest");
+    let (code, stdout) = difftest(&dir);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains(" 0 differ"), "{stdout}");
+}
+
+#[test]
+fn disagreements_are_reported() {
+    let dir = std::env::temp_dir().join(format!("simple_lisp_difftest_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("agrees.slp"), "(+ 1 2)").unwrap();
+    fs::write(dir.join("differs.slp"), "(core/pprint 1)").unwrap();
+    fs::write(dir.join("allowed.slp"), "; difftest-allow: testing\n(core/pprint 1)").unwrap();
+    let (code, stdout) = difftest(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(stdout.contains("DIFFERS") && stdout.contains("differs.slp"), "{stdout}");
+    assert!(stdout.contains("v1 printed: \"1\\n\""), "{stdout}");
+    assert!(stdout.contains("allowed.slp (testing)"), "{stdout}");
+    assert!(stdout.contains("3 files, 1 differ, 1 allowed to differ"), "{stdout}");
+}
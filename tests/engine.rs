@@ -0,0 +1,391 @@
+//! Embedding the interpreter through `Engine`.
+
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    path::Path,
+    rc::Rc,
+};
+use simple_lisp::{
+    ArgCount,
+    Captured,
+    Capabilities,
+    Ctx,
+    Engine,
+    HostObject,
+    InterpreterOptions,
+    Value,
+};
+
+
+#[test]
+fn eval_values() {
+    let mut engine = Engine::new();
+
+    assert_eq!(engine.eval_str("(+ 40 2)").unwrap().as_i64(), Some(42));
+    assert_eq!(engine.eval_str("1.5").unwrap().as_f64(), Some(1.5));
+    assert_eq!(engine.eval_str("(= 1 1)").unwrap().as_bool(), Some(true));
+    assert_eq!(engine.eval_str("\"hi\"").unwrap().as_string().as_deref(), Some("hi"));
+    assert!(engine.eval_str("").unwrap().is_none());
+
+    let list = engine.eval_str("(core/list 1 2)").unwrap();
+    assert_eq!(list.type_name(), "list");
+    assert_eq!(list.as_i64(), None);
+}
+
+#[test]
+fn globals() {
+    let mut engine = Engine::new();
+
+    engine.eval_str("(def x 5)").unwrap();
+    assert_eq!(engine.get_global("x").unwrap().as_i64(), Some(5));
+    assert!(engine.get_global("y").is_none());
+
+    // the prelude is there too
+    assert!(engine.get_global("inc").is_some());
+
+    let value = engine.eval_str("(+ x 1)").unwrap();
+    engine.set_global("y", value);
+    assert_eq!(engine.eval_str("(* y 2)").unwrap().as_i64(), Some(12));
+}
+
+#[test]
+fn values_survive_collections() {
+    let mut engine = Engine::new();
+
+    let held = engine.eval_str("(core/list 1 2 3)").unwrap();
+    engine.eval_str("(core/gcCollect)").unwrap();
+    engine.eval_str("(core/list 4 5 6)").unwrap();
+    engine.eval_str("(core/gcCollect)").unwrap();
+
+    assert_eq!(format!("{held:?}"), format!("{:?}", held.clone()));
+    assert_eq!(held.type_name(), "list");
+}
+
+#[test]
+fn eval_files_and_errors() {
+    let mut engine = Engine::new();
+
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files/engine_lib.slp");
+    assert_eq!(engine.eval_file(&file).unwrap().as_i64(), Some(42));
+    assert_eq!(engine.eval_str("(double 4)").unwrap().as_i64(), Some(8));
+
+    let err = engine.eval_str("(undefined_thing 1)").unwrap_err();
+    assert!(err.to_string().contains("undefined_thing"), "{err}");
+    assert!(engine.eval_str("(+ 1").is_err());
+    assert!(engine.eval_file("no_such_file.slp").is_err());
+
+    // errors don't break the engine
+    assert_eq!(engine.eval_str("(double 5)").unwrap().as_i64(), Some(10));
+}
+
+#[test]
+fn host_functions() {
+    let mut engine = Engine::new();
+
+    engine.register_fn("apply-twice", ArgCount::Exact(2), |ctx, args|{
+        let once = ctx.call(&args[0], &args[1..])?;
+        ctx.call(&args[0], &[once])
+    });
+    engine.register_fn("host-range", ArgCount::Exact(1), |ctx, args|{
+        let Some(n) = args[0].as_i64() else {
+            return Err(ctx.type_error("number", &args[0]));
+        };
+        let items = (0..n).map(|i|ctx.number(i)).collect::<Vec<_>>();
+
+        Ok(ctx.list(&items))
+    });
+    engine.register_fn("host-sum", ArgCount::Any, |ctx, args|{
+        let sum = args.iter().filter_map(|a|a.as_i64()).sum();
+        Ok(ctx.number(sum))
+    });
+
+    assert_eq!(engine.eval_str("(apply-twice inc 1)").unwrap().as_i64(), Some(3));
+    assert_eq!(engine.eval_str("(host-sum 1 2 3)").unwrap().as_i64(), Some(6));
+    assert_eq!(engine.eval_str("(core/length (host-range 4))").unwrap().as_i64(), Some(4));
+
+    // collections while lisp runs under a host function don't free the host's values
+    engine.eval_str("(defn noisy [n] (core/gcCollect) (+ n 10))").unwrap();
+    assert_eq!(engine.eval_str("(apply-twice noisy 1)").unwrap().as_i64(), Some(21));
+    assert_eq!(engine.eval_str("(apply-twice (fn [n] (* n n)) 3)").unwrap().as_i64(), Some(81));
+
+    let err = engine.eval_str("(host-range \"4\")").unwrap_err();
+    assert!(err.to_string().contains("`host-range` expected number, but got string"), "{err}");
+    let err = engine.eval_str("(host-range)").unwrap_err();
+    assert!(err.to_string().contains("takes 1 argument"), "{err}");
+    let err = engine.eval_str("(apply-twice undefined_fn 1)").unwrap_err();
+    assert!(err.to_string().contains("undefined_fn"), "{err}");
+    let err = engine.eval_str("(apply-twice (fn [n] (core/index n 5)) (core/list))").unwrap_err();
+    assert!(!err.to_string().is_empty());
+
+    // errors from inside a callback don't break the engine
+    assert_eq!(engine.eval_str("(apply-twice inc 5)").unwrap().as_i64(), Some(7));
+}
+
+#[test]
+fn conversions() {
+    let mut engine = Engine::new();
+
+    engine.set_global("n", 5i64);
+    engine.set_global("f", 1.5);
+    engine.set_global("s", "text");
+    engine.set_global("c", 'x');
+    engine.set_global("nothing", None::<i64>);
+    engine.set_global("items", vec![1i64, 2, 3]);
+    engine.set_global("pair", (true, "two".to_string()));
+    engine.set_global("config", HashMap::from([
+        ("name".to_string(), "lisp".to_string()),
+        ("mode".to_string(), "fast".to_string()),
+    ]));
+
+    assert_eq!(engine.eval_str("(+ n 1)").unwrap().convert::<i64>().unwrap(), 6);
+    assert_eq!(engine.eval_str("f").unwrap().convert::<f64>().unwrap(), 1.5);
+    assert_eq!(engine.eval_str("s").unwrap().convert::<String>().unwrap(), "text");
+    assert_eq!(engine.eval_str("c").unwrap().convert::<char>().unwrap(), 'x');
+    assert_eq!(engine.eval_str("nothing").unwrap().convert::<Option<i64>>().unwrap(), None);
+    assert_eq!(engine.eval_str("n").unwrap().convert::<Option<i64>>().unwrap(), Some(5));
+    assert_eq!(engine.eval_str("(core/index items 2)").unwrap().convert::<i64>().unwrap(), 3);
+    assert_eq!(engine.eval_str("items").unwrap().convert::<Vec<i64>>().unwrap(), [1, 2, 3]);
+    assert_eq!(
+        engine.eval_str("pair").unwrap().convert::<(bool, String)>().unwrap(),
+        (true, "two".to_string()),
+    );
+    assert_eq!(engine.eval_str("(config .mode)").unwrap().convert::<String>().unwrap(), "fast");
+
+    engine.eval_str("(config .mode \"slow\")").unwrap();
+    let config = engine.get_global("config").unwrap().convert::<HashMap<String, String>>().unwrap();
+    assert_eq!(config.len(), 2);
+    assert_eq!(config["mode"], "slow");
+
+    // values go back in untouched
+    let items = engine.get_global("items").unwrap();
+    engine.set_global("same", items);
+    assert_eq!(engine.eval_str("(= same items)").unwrap().convert::<bool>().unwrap(), true);
+
+    let err = engine.eval_str("(core/list 1 \"2\")").unwrap().convert::<Vec<i64>>().unwrap_err();
+    assert_eq!(err.to_string(), "Cannot convert string to `i64`");
+    let err = engine.eval_str("items").unwrap().convert::<(i64, i64)>().unwrap_err();
+    assert_eq!(err.to_string(), "Cannot convert list of 3 items to `(i64, i64)`");
+    let err = engine.eval_str("n").unwrap().convert::<HashMap<String, i64>>().unwrap_err();
+    assert_eq!(err.to_string(), "Cannot convert number to `HashMap<String, i64>`");
+}
+
+#[test]
+fn call_functions() {
+    let mut engine = Engine::new();
+
+    engine.register_fn("host-double", ArgCount::Exact(2), |ctx, args|{
+        let once = ctx.call(&args[0], &args[1..])?;
+        let n = once.convert::<i64>()?;
+        Ok(ctx.number(n * 2))
+    });
+    engine.eval_str("(defn on-event [total n] (core/list total n) (+ total n))").unwrap();
+    engine.eval_str("(defn nested [n] (host-double inc n))").unwrap();
+
+    let one = engine.value_of(1);
+    let mut total = engine.value_of(0);
+    for _ in 0..100 {
+        total = engine.call("on-event", &[total, one.clone()]).unwrap();
+    }
+    engine.collect_garbage();
+    let live = engine.live_data();
+
+    for i in 0..10_000 {
+        let n = engine.value_of(i);
+        let before = total.convert::<i64>().unwrap();
+        total = engine.call("on-event", &[total, n]).unwrap();
+        assert_eq!(total.convert::<i64>().unwrap(), before + i);
+    }
+    assert_eq!(total.convert::<i64>().unwrap(), 100 + (0..10_000).sum::<i64>());
+
+    // everything the calls made is garbage now
+    engine.collect_garbage();
+    assert_eq!(engine.live_data(), live);
+
+    // lisp calling the host calling lisp
+    let five = engine.value_of(5);
+    assert_eq!(engine.call("nested", &[five.clone()]).unwrap().as_i64(), Some(12));
+    let dec = engine.get_global("dec").unwrap();
+    assert_eq!(engine.call("host-double", &[dec, five.clone()]).unwrap().as_i64(), Some(8));
+
+    let err = engine.call("on-evnt", &[]).unwrap_err();
+    assert!(err.to_string().contains("did you mean `on-event`"), "{err}");
+    let err = engine.call("on-event", &[five.clone()]).unwrap_err();
+    assert!(err.to_string().contains("takes 2 arguments"), "{err}");
+    engine.eval_str("(def not-a-fn 5)").unwrap();
+    let err = engine.call("not-a-fn", &[]).unwrap_err();
+    assert!(err.to_string().contains("expected fn, but got number"), "{err}");
+    let x = engine.value_of("x");
+    let err = engine.call("on-event", &[five.clone(), x]).unwrap_err();
+    assert!(err.to_string().contains("string"), "{err}");
+
+    // the failed calls didn't leave anything behind
+    assert_eq!(engine.call("on-event", &[five.clone(), five]).unwrap().as_i64(), Some(10));
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    count: Cell<i64>,
+}
+impl HostObject for Counter {
+    fn type_name(&self)->&'static str {
+        "counter"
+    }
+
+    fn field(&self, name: &str, ctx: &mut Ctx)->Option<Value> {
+        match name {
+            "count"=>Some(ctx.number(self.count.get())),
+            _=>None,
+        }
+    }
+}
+
+#[test]
+fn host_objects() {
+    let mut engine = Engine::new();
+
+    engine.register_fn("counter-new", ArgCount::Exact(0), |ctx, _|{
+        Ok(Value::from_host(ctx, Rc::new(Counter::default())))
+    });
+    engine.register_fn("counter-add", ArgCount::Exact(2), |ctx, args|{
+        let Some(counter) = args[0].downcast_host::<Counter>() else {
+            return Err(ctx.type_error("counter", &args[0]));
+        };
+        let n = args[1].convert::<i64>()?;
+        counter.count.set(counter.count.get() + n);
+
+        Ok(args[0].clone())
+    });
+
+    let counter = Rc::new(Counter::default());
+    engine.set_global("clicks", counter.clone());
+    engine.eval_str("(counter-add clicks 5) (counter-add clicks 2)").unwrap();
+    assert_eq!(counter.count.get(), 7);
+
+    // scripts can read its fields, but it is opaque otherwise
+    assert_eq!(engine.eval_str("clicks/count").unwrap().as_i64(), Some(7));
+    assert!(engine.eval_str("(= (core/typeOf clicks) .native)").unwrap().as_bool().unwrap());
+    let printed = engine.eval_str("(std/string/format clicks)").unwrap();
+    assert_eq!(printed.as_string().as_deref(), Some("<native: counter>"));
+    let err = engine.eval_str("clicks/missing").unwrap_err();
+    assert!(err.to_string().contains("`missing`"), "{err}");
+    let err = engine.eval_str("(counter-add 5 1)").unwrap_err();
+    assert!(err.to_string().contains("`counter-add` expected counter, but got number"), "{err}");
+
+    // they are only equal to themselves
+    engine.eval_str("(def other (counter-new))").unwrap();
+    assert!(engine.eval_str("(= clicks clicks)").unwrap().as_bool().unwrap());
+    assert!(!engine.eval_str("(= clicks other)").unwrap().as_bool().unwrap());
+    assert_eq!(engine.eval_str("(counter-add other 1) other/count").unwrap().as_i64(), Some(1));
+    assert_eq!(counter.count.get(), 7);
+
+    let back = engine.get_global("clicks").unwrap().downcast_host::<Counter>().unwrap();
+    assert!(Rc::ptr_eq(&back, &counter));
+    assert!(engine.get_global("other").unwrap().convert::<Rc<Counter>>().is_ok());
+    assert!(engine.eval_str("5").unwrap().downcast_host::<Counter>().is_none());
+}
+
+#[test]
+fn capabilities() {
+    let mut engine = Engine::with_options(InterpreterOptions {
+        capabilities: Capabilities::STDIO,
+        ..Default::default()
+    });
+    let fetches = Rc::new(Cell::new(0));
+    let host_fetches = fetches.clone();
+    engine.register_fn_needing("fetch", ArgCount::Exact(0), Capabilities::NETWORK, move|ctx, _|{
+        host_fetches.set(host_fetches.get() + 1);
+        Ok(ctx.none())
+    });
+    engine.register_fn("pure", ArgCount::Exact(0), |ctx, _|Ok(ctx.number(1)));
+
+    let err = engine.eval_str("(fetch)").unwrap_err();
+    assert!(err.to_string().contains("capability denied: network"), "{err}");
+    assert_eq!(fetches.get(), 0);
+    let err = engine.eval_str("(std/io/open \"engine_lib.slp\")").unwrap_err();
+    assert!(err.to_string().contains("capability denied: filesystem-read"), "{err}");
+
+    // the error doesn't break the engine, and everything else still works
+    assert_eq!(engine.eval_str("(pure)").unwrap().as_i64(), Some(1));
+    assert!(engine.eval_str("(std/io/write std/io/stdout \"\")").is_ok());
+}
+
+#[test]
+fn isolated_engines() {
+    let mut a = Engine::new();
+    let mut b = Engine::new();
+
+    // interleave the allocations and collections, and mutate old data so both engines' barriers
+    // have something to remember
+    for i in 0..200 {
+        a.eval_str(&format!("(def a{i} (core/list {i} \"a\"))")).unwrap();
+        b.eval_str(&format!("(def b{i} (core/list {i} \"b\"))")).unwrap();
+        if i % 50 == 0 {
+            a.collect_garbage();
+            b.collect_garbage();
+        }
+    }
+    for i in 0..200 {
+        a.eval_str(&format!("(+= a{i} \"more\")")).unwrap();
+        b.eval_str(&format!("(+= b{i} \"more\")")).unwrap();
+    }
+    b.collect_garbage();
+    let live = b.live_data();
+
+    // dropping `a` runs its leak check, which only counts its own data
+    drop(a);
+    assert_eq!(b.live_data(), live);
+    b.collect_garbage();
+    assert_eq!(b.live_data(), live);
+    for i in 0..200 {
+        let list = b.get_global(&format!("b{i}")).unwrap().convert::<(i64, String, String)>().unwrap();
+        assert_eq!(list, (i, "b".to_string(), "more".to_string()));
+    }
+}
+
+#[test]
+fn reset() {
+    let mut engine = Engine::new();
+    engine.register_fn("host-answer", ArgCount::Exact(0), |ctx, _|Ok(ctx.number(42)));
+    engine.collect_garbage();
+    let live = engine.live_data();
+
+    engine.eval_str("(def items (core/list 1 2 3)) (defn inc [n] (+ n 10))").unwrap();
+    engine.set_global("config", vec![1, 2, 3]);
+    let kept = engine.eval_str("(core/list \"kept\")").unwrap();
+    assert_eq!(engine.eval_str("(inc 1)").unwrap().as_i64(), Some(11));
+
+    engine.reset();
+    for name in ["items", "config"] {
+        assert!(engine.get_global(name).is_none(), "{name} survived the reset");
+    }
+    // the prelude's `inc` is back and can be redefined again, and host functions stay
+    assert_eq!(engine.eval_str("(inc 1)").unwrap().as_i64(), Some(2));
+    assert_eq!(engine.eval_str("(host-answer)").unwrap().as_i64(), Some(42));
+    engine.eval_str("(defn inc [n] n) (def items 5)").unwrap();
+    assert_eq!(kept.convert::<Vec<String>>().unwrap(), ["kept"]);
+
+    // nothing is left besides what the host still holds
+    engine.reset();
+    drop(kept);
+    engine.collect_garbage();
+    assert_eq!(engine.live_data(), live);
+}
+
+#[test]
+fn captured_output() {
+    let output = Captured::new();
+    let mut engine = Engine::with_output(InterpreterOptions::default(), output.clone());
+
+    engine.eval_str("(core/pprint (core/list 1 2))").unwrap();
+    engine.eval_str("(std/io/write std/io/stdout \"no newline\")").unwrap();
+    assert_eq!(output.take(), "(1 2)\nno newline");
+
+    // a new sink only gets what is printed after it is set
+    let later = Captured::new();
+    engine.set_output(later.clone());
+    engine.eval_str("(core/pprint 3)").unwrap();
+    assert_eq!(output.take(), "");
+    assert_eq!(later.take(), "3\n");
+}
@@ -0,0 +1,157 @@
+//! `=` compares structure, `eq?` compares identity, and `<` and friends order numbers, strings and
+//! chars. Each case is one expression and what it should print, and `CASES` runs with both
+//! interpreters.
+//!
+//! V2's `core` module lives in its builtins module, which isn't part of this tree, so the cases
+//! that build lists or pairs only run with V1. So do the ones that need V2's arithmetic to mix
+//! numbers and floats.
+
+
+mod common;
+
+use common::eval_v1;
+use simple_lisp::{
+    interpreter2::{
+        ast::{
+            ConvertState,
+            repl_convert,
+        },
+        data::Primitive,
+        debug::value_line,
+    },
+    output::Captured,
+    parser,
+    InterpreterOptions,
+};
+
+
+const SETUP: &[&str] = &[
+    "(def nan #nan)",
+    "(def text \"abc\")",
+];
+
+const CASES: &[(&str, &str)] = &[
+    // structural
+    ("(= 1 1)", "true"),
+    ("(= 1 2)", "false"),
+    ("(= \"abc\" text)", "true"),
+    ("(= 1 1.0)", "false"),
+    ("(= nan nan)", "false"),
+    ("(not= 1 2)", "true"),
+    ("(not= 1 2 1)", "false"),
+    ("(not= \"abc\" text)", "false"),
+    ("(not= nan nan)", "true"),
+
+    // identity
+    ("(eq? text text)", "true"),
+    ("(eq? 5 5)", "true"),
+    ("(eq? 5 5.0)", "false"),
+    ("(eq? \\a \\a)", "true"),
+    ("(eq? #t #t)", "true"),
+    ("(eq? nan nan)", "true"),
+    ("(eq? nan #nan)", "true"),
+    ("(eq? 0.0 -0.0)", "false"),
+    ("(eq? #inf #inf)", "true"),
+
+    // ordering
+    ("(< 1 2)", "true"),
+    ("(< 1 2 3)", "true"),
+    ("(< 1 3 2)", "false"),
+    ("(<= 2 2 3)", "true"),
+    ("(> 3 2.5)", "true"),
+    ("(>= 1.5 2)", "false"),
+    ("(< \"abc\" \"abd\")", "true"),
+    ("(< \"ab\" \"abc\")", "true"),
+    ("(> \"Z\" \"a\")", "false"),
+    ("(< \"z\" \"é\")", "true"),
+    ("(<= \\a \\b)", "true"),
+    ("(> \\a \\b)", "false"),
+    ("(< nan 1)", "false"),
+    ("(> nan 1)", "false"),
+    ("(>= nan nan)", "false"),
+    ("(< 9223372036854775807 #inf)", "true"),
+    ("(< #-inf -9223372036854775808 1e308 #inf)", "true"),
+
+    // incompatible types
+    ("(< 1 \"a\")", "Error: Type error: `<` can't compare number with string"),
+    ("(>= \\a \"a\")", "Error: Type error: `>=` can't compare char with string"),
+    ("(< 1 2 \"a\")", "Error: Type error: `<` can't compare number with string"),
+];
+
+/// Evaluated after `SETUP`, with V1 only
+const V1_SETUP: &[&str] = &[
+    "(def inf #inf)",
+    "(def items (core/list 1 2))",
+    "(def pair (core/cons 1 (core/cons 2 3)))",
+];
+
+const V1_CASES: &[(&str, &str)] = &[
+    ("(= items (core/list 1 2))", "true"),
+    ("(= items (core/list 2 1))", "false"),
+    ("(= pair (core/cons 1 (core/cons 2 3)))", "true"),
+    ("(= pair (core/cons 1 (core/cons 2 None)))", "false"),
+    ("(= (core/cons 1 None) (core/list 1))", "false"),
+    ("(not= items (core/list 1 2))", "false"),
+    ("(eq? items items)", "true"),
+    ("(eq? items (core/list 1 2))", "false"),
+    ("(eq? pair (core/cons 1 (core/cons 2 3)))", "false"),
+    // V2 keeps one copy of each string literal, so there this literal is the same string as `text`
+    ("(eq? text \"abc\")", "false"),
+    ("(eq? inf #inf)", "true"),
+    ("(< (- 0 inf) -9223372036854775808 1e308 inf)", "true"),
+    ("(= inf (* 2 inf))", "true"),
+    ("(> items items)", "Error: Type error: `>` can't compare list with list"),
+];
+
+
+/// What the V1 interpreter prints last for `expr`
+fn run_v1(expr: &str)->String {
+    let exprs = SETUP.iter().chain(V1_SETUP).copied().chain([expr]).collect::<Vec<_>>();
+    eval_v1(&exprs)
+}
+
+/// What V2 ends with for `expr`, written the way `eval` prints it
+fn run_v2(expr: &str)->String {
+    let mut state = ConvertState::new();
+    let mut interpreter = InterpreterOptions::default().new_interpreter2_with_output(&mut state, Box::new(Captured::new()));
+
+    let mut result = Ok(Primitive::None);
+    for source in SETUP.iter().chain([&expr]) {
+        let exprs = parser::new_parser(source).parse_all().unwrap();
+        let start = repl_convert(&mut state, exprs).unwrap();
+        result = interpreter.run(&mut state, Some(start));
+    }
+
+    return match result {
+        Ok(value)=>value_line(&value, &state),
+        Err(e)=>format!("Error: {e}"),
+    };
+}
+
+/// The cases `run` doesn't print what they should
+fn failures(cases: &[(&str, &str)], run: fn(&str)->String)->Vec<String> {
+    cases.iter()
+        .filter_map(|(expr, expected)|{
+            let got = run(expr);
+            (got != *expected).then(||format!("{expr}: expected `{expected}`, got `{got}`"))
+        })
+        .collect()
+}
+
+#[test]
+fn v1_comparison_table() {
+    let failures = failures(CASES, run_v1);
+    assert!(failures.is_empty(), "{failures:#?}");
+}
+
+#[test]
+fn v1_only_table() {
+    let failures = failures(V1_CASES, run_v1);
+    assert!(failures.is_empty(), "{failures:#?}");
+}
+
+#[test]
+fn v2_comparison_table() {
+    let failures = failures(CASES, run_v2);
+    assert!(failures.is_empty(), "{failures:#?}");
+}
@@ -0,0 +1,116 @@
+//! Scripts that should stop with a runtime error instead of panicking and taking the whole process
+//! down with them.
+
+
+mod common;
+
+use common::eval_v1_with_code;
+use std::process::Command;
+
+
+#[test]
+fn mutating_data_with_itself() {
+    let cases = [
+        ("(def n 1)", "(+= n n)"),
+        ("(def s \"a\")", "(+= s s)"),
+        ("(def o (object (.a 1)))", "(+= o o)"),
+        ("(def o (object (.a 1)))", "(-= o o)"),
+    ];
+
+    for (def, mutate) in cases {
+        let (code, stdout) = eval_v1_with_code(&[def, mutate]);
+        assert_eq!(code, Some(1), "{mutate} didn't fail cleanly: {stdout}");
+        assert!(stdout.contains("can't read data while it is being modified"), "{mutate}: {stdout}");
+    }
+}
+
+#[test]
+fn self_referential_list() {
+    // a list can hold itself, and using it afterwards is fine as long as nothing recurses into it
+    let (code, stdout) = eval_v1_with_code(&["(def l (core/list 1))", "(+= l l)", "(core/length l)"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.ends_with("2\n"), "{stdout}");
+}
+
+#[test]
+fn error_kinds() {
+    let cases: &[(&[&str], &str)] = &[
+        (&["(% 1 0)"], "Error: Division by zero"),
+        (&["(+ 1 \"a\")"], "Error: Type error: `+` expected number or float, but got string"),
+        (&["(core/listPop 1)"], "Error: Type error: `listPop` expected list, but got number"),
+        (&["nope"], "Error: Var `nope` is not defined"),
+        (&["(def x 1)", "(def x 2)"], "Error: Var `x` is already defined"),
+        (&["(defn f [a b] a)", "(f 1)"], "Error: Function `f` takes 2 arguments ([a b]), but got 1"),
+        (
+            &["(defn f ([a] a) ([a b c] a) ([a b c d e & rest] a))", "(f 1 2)"],
+            "Error: Function `f` takes 1, 3 or 5+ arguments ([a], [a b c], [a b c d e & rest]), but got 2",
+        ),
+        (&["(core/length 1 2)"], "Error: Function `length` takes 1 argument, but got 2"),
+        (&["(def o (object (.a 1)))", "(o .b)"], "Error: Object does not have a field named `b`"),
+    ];
+
+    for (exprs, message) in cases {
+        let (code, stdout) = eval(exprs);
+        assert_eq!(code, Some(1), "{exprs:?} didn't fail: {stdout}");
+        assert!(stdout.contains(message), "{exprs:?}: {stdout}");
+    }
+}
+
+#[test]
+fn stack_trace() {
+    let (code, stdout) = eval_v1_with_code(&[
+        "(defn c [x] (+ x \"a\"))",
+        "(defn b [x] (+ (c x) 1))",
+        "(defn a [x] (+ (b x) 1))",
+        "(a 1)",
+    ]);
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(stdout.contains("Stack:"), "{stdout}");
+
+    let c = stdout.find("in `c`").expect(&stdout);
+    let b = stdout.find("in `b`").expect(&stdout);
+    let a = stdout.find("in `a`").expect(&stdout);
+    assert!(c < b && b < a, "frames are out of order: {stdout}");
+}
+
+#[test]
+fn did_you_mean() {
+    let cases: &[(&[&str], &str)] = &[
+        (&["(def count 1)", "coutn"], "Var `coutn` is not defined, did you mean `count`?"),
+        (&["(def count 1)", "(def cont 2)", "coutn"], "did you mean `cont` or `count`?"),
+        (&["(defn f [value] (+ valeu 1))", "(f 1)"], "did you mean `value`?"),
+        (&["(def count 1)", "(defn f [x] (set coutn x))", "(f 1)"], "did you mean `count`?"),
+    ];
+
+    for (exprs, message) in cases {
+        let (code, stdout) = eval(exprs);
+        assert_eq!(code, Some(1), "{exprs:?} didn't fail: {stdout}");
+        assert!(stdout.contains(message), "{exprs:?}: {stdout}");
+    }
+
+    let (_, stdout) = eval_v1_with_code(&["zzzz"]);
+    assert!(!stdout.contains("did you mean"), "{stdout}");
+}
+
+#[test]
+fn scripts_exit_1() {
+    let dir = std::env::temp_dir().join(format!("simple_lisp_exit_code_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("fails.slp");
+    std::fs::write(&script, "(def x 5)\n(% x 0)\n").unwrap();
+
+    let mut results = Vec::new();
+    for action in ["run", "run2"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+            .args(["--no-prelude", action, script.to_str().unwrap()])
+            .output()
+            .unwrap();
+        results.push((action, output.status.code(), String::from_utf8(output.stdout).unwrap()));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for (action, code, stdout) in results {
+        assert_eq!(code, Some(1), "{action}: {stdout}");
+        assert!(stdout.contains("Error: "), "{action}: {stdout}");
+    }
+}
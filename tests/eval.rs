@@ -0,0 +1,38 @@
+//! `eval` and `--eval` print each result on one line in the form the reader reads back, with
+//! whichever interpreter was picked.
+
+
+use std::process::Command;
+
+
+/// Run `simple_lisp` with `args`. Returns the exit code and stdout.
+fn run(args: &[&str])->(Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .args(args)
+        .output()
+        .unwrap();
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn v1_results() {
+    assert_eq!(run(&["eval", "(+ 1 2)"]), (Some(0), "3\n".into()));
+    assert_eq!(run(&["eval", "(core/list \"a b\" \\space 1.5)"]), (Some(0), "(\"a b\" \\space 1.5)\n".into()));
+    assert_eq!(run(&["-e", "(def x 4)", "-e", "(* x x)"]), (Some(0), "16\n".into()));
+
+    // nothing is left out, however long it is
+    let (_, stdout) = run(&["eval", "(std/seq/realize (std/seq/range 0 200))"]);
+    assert!(stdout.ends_with(" 198 199)\n") && !stdout.contains("..."), "{stdout}");
+}
+
+#[test]
+fn v2_results() {
+    assert_eq!(run(&["--v2", "eval", "(+ 1 2)"]), (Some(0), "3\n".into()));
+    assert_eq!(run(&["--v2", "eval", "\"a b\""]), (Some(0), "\"a b\"\n".into()));
+    assert_eq!(run(&["--v2", "-e", "(def x 4)", "-e", "(* x x)"]), (Some(0), "16\n".into()));
+
+    let (code, stdout) = run(&["--v2", "eval", "(% 1 0)"]);
+    assert_eq!(code, Some(1));
+    assert!(stdout.contains("Error: "), "{stdout}");
+}
@@ -0,0 +1,179 @@
+//! Scripts and modules that can't be read should say why instead of panicking.
+
+
+use std::{
+    path::Path,
+    process::Command,
+};
+
+
+/// Run `script` from `tests/files` with `run` or `run2`. Returns the exit code and stdout.
+fn run(action: &str, script: &str)->(Option<i32>, String) {
+    run_with(&[action, script], None)
+}
+
+/// Run the binary with `args` from `tests/files` and `SIMPLE_LISP_PATH` set to `search_path`
+fn run_with(args: &[&str], search_path: Option<&str>)->(Option<i32>, String) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files");
+    let mut command = Command::new(env!("CARGO_BIN_EXE_simple_lisp"));
+    command.current_dir(&dir)
+        .env_remove("SIMPLE_LISP_PATH")
+        .args(args);
+    if let Some(search_path) = search_path {
+        command.env("SIMPLE_LISP_PATH", search_path);
+    }
+    let output = command.output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{args:?} panicked: {stderr}");
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn unreadable_scripts() {
+    let cases = [
+        ("scrpt.slp", "Error: Cannot read `scrpt.slp`: No such file or directory"),
+        (".", "Error: Cannot read `.`: it is a directory"),
+        ("bad_utf8.slp", "Error: Cannot read `bad_utf8.slp`: it is not valid UTF-8"),
+    ];
+
+    for action in ["run", "run2"] {
+        for (script, message) in cases {
+            let (code, stdout) = run(action, script);
+            assert_eq!(code, Some(2), "{action} {script}: {stdout}");
+            assert!(stdout.contains(message), "{action} {script}: {stdout}");
+        }
+    }
+}
+
+#[test]
+fn missing_module() {
+    for action in ["run", "run2"] {
+        let (code, stdout) = run(action, "missing_module.slp");
+        assert_eq!(code, Some(1), "{action}: {stdout}");
+        assert!(
+            stdout.contains("While loading module `nope` from `missing_module.slp`\nError: Module `nope` was not found, tried `nope.slp` and `nope/mod.slp`"),
+            "{action}: {stdout}",
+        );
+    }
+}
+
+#[test]
+fn broken_nested_module() {
+    for action in ["run", "run2"] {
+        let (code, stdout) = run(action, "broken_module.slp");
+        assert_eq!(code, Some(1), "{action}: {stdout}");
+        assert!(
+            stdout.starts_with("While loading module `broken` from `broken_module.slp`\nWhile loading module `inner` from `broken/mod.slp`\n"),
+            "{action}: {stdout}",
+        );
+        // the parse error itself goes to stderr
+        assert!(stdout.contains("Parsing an Expr"), "{action}: {stdout}");
+    }
+}
+
+#[test]
+fn search_path() {
+    let (code, stdout) = run_with(&["-I", "lib_a", "run", "uses_lib.slp"], None);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "util from lib_a\nhelper from lib_a\n");
+
+    let (code, stdout) = run_with(&["run", "uses_lib.slp"], Some("lib_b"));
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "util from lib_b\n");
+
+    // `-I` comes before `SIMPLE_LISP_PATH`, and finding it twice is worth a warning
+    let (code, stdout) = run_with(&["-I", "lib_a", "run", "uses_lib.slp"], Some("lib_b"));
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(
+        stdout.starts_with("Warning (uses_lib.slp): Module `util` is in more than one search directory. Using `lib_a/util.slp` and ignoring `lib_b/util.slp`\nutil from lib_a\n"),
+        "{stdout}",
+    );
+
+    // V2 can't run modules yet, but it should find them the same way
+    let (code, stdout) = run_with(&["-I", "lib_a", "check", "--interpreter", "v2", "uses_lib.slp"], None);
+    assert_eq!(code, Some(0), "{stdout}");
+
+    let (code, stdout) = run("run", "uses_lib.slp");
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(stdout.contains("Module `util` was not found"), "{stdout}");
+}
+
+#[test]
+fn diamond_modules() {
+    // `a` and `b` both use `util`, which should only run once
+    let (code, stdout) = run_with(&["-I", "diamond_lib", "run", "diamond/main.slp"], None);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "util loaded\na loaded\nb loaded\n");
+
+    let (code, stdout) = run_with(&["-I", "diamond_lib", "check", "--interpreter", "v2", "diamond/main.slp"], None);
+    assert_eq!(code, Some(0), "{stdout}");
+}
+
+#[test]
+fn module_cycle() {
+    let (_, stdout) = run_with(&["-I", ".", "run", "cycle.slp"], None);
+    assert!(stdout.contains("Error: Module `loop_a` was used while it was still loading"), "{stdout}");
+}
+
+#[test]
+fn use_forms() {
+    let (code, stdout) = run("run", "use_forms.slp");
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "hello world!\nhello alias!\nhello module!\n!\n");
+
+    let (code, stdout) = run_with(&["check", "--interpreter", "v2", "use_forms.slp"], None);
+    assert_eq!(code, Some(0), "{stdout}");
+
+    // importing something the module doesn't define is caught before anything runs
+    let cases: &[&[&str]] = &[
+        &["run", "use_missing.slp"],
+        &["check", "--interpreter", "v2", "use_missing.slp"],
+    ];
+    for args in cases {
+        let (code, stdout) = run_with(args, None);
+        assert_eq!(code, Some(1), "{args:?}: {stdout}");
+        assert!(
+            stdout.contains("Error: Module `greet_lib/greet` does not define `goodbye`, it only defines `punctuation` and `hello`"),
+            "{args:?}: {stdout}",
+        );
+    }
+}
+
+#[test]
+fn private_defs() {
+    let message = "Error: No such field, `key` is private to module `private_lib/secret`";
+
+    // paths are only checked when they run in V1
+    let (_, stdout) = run("run", "private_path.slp");
+    assert!(stdout.starts_with(&format!("visible\n{message}")), "{stdout}");
+
+    let cases: &[&[&str]] = &[
+        &["check", "--interpreter", "v2", "private_path.slp"],
+        &["run", "private_only.slp"],
+        &["check", "--interpreter", "v2", "private_only.slp"],
+    ];
+    for args in cases {
+        let (code, stdout) = run_with(args, None);
+        assert_eq!(code, Some(1), "{args:?}: {stdout}");
+        assert!(stdout.contains(message), "{args:?}: {stdout}");
+    }
+}
+
+#[test]
+fn module_values() {
+    // each module runs once, however many times `module` is reached
+    let (code, stdout) = run("run", "members/main.slp");
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "net loaded\nhttp loaded\nhelper root\nget root\nget nested\nhelper function\n");
+
+    let (code, stdout) = run_with(&["check", "--interpreter", "v2", "members/main.slp"], None);
+    assert_eq!(code, Some(0), "{stdout}");
+
+    let (code, stdout) = run("run", "members/missing.slp");
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(
+        stdout.contains("Error: Module `net` has no member `helperr`, it exports `http`, `helper` and `fetch`"),
+        "{stdout}",
+    );
+}
@@ -0,0 +1,144 @@
+//! Runs every `tests/fixtures/**/NAME.slp` and compares what it prints to `NAME.expected`. If
+//! there is a `NAME.error`, the program has to fail with an error containing its text, otherwise
+//! it has to succeed. Each fixture runs with both interpreters unless it has a
+//! `; v1-only: REASON` line.
+//!
+//! If there is a `NAME.warnings`, converting the fixture for V2 has to give exactly those
+//! warnings, one per line. An empty file checks that there are none.
+//!
+//! `UPDATE_EXPECT=1 cargo test --test fixtures` rewrites the expectations from what V1 does now.
+
+
+use simple_lisp::{
+    interpreter2::ast::convert,
+    difftest::{
+        Outcome,
+        run_v1,
+        run_v2,
+    },
+    source::SearchPath,
+    parser,
+    InterpreterOptions,
+};
+use std::{
+    env::var_os,
+    fs::{
+        read_dir,
+        read_to_string,
+        remove_file,
+        write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+
+const V1_ONLY_DIRECTIVE: &str = "v1-only:";
+
+
+fn find_fixtures(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_fixtures(&path, out);
+        } else if path.extension().is_some_and(|e|e == "slp") {
+            out.push(path);
+        }
+    }
+}
+
+fn is_v1_only(source: &str)->bool {
+    source.lines()
+        .filter_map(|line|line.trim_start().strip_prefix(';'))
+        .any(|comment|comment.trim_start().starts_with(V1_ONLY_DIRECTIVE))
+}
+
+/// Describe how `outcome` doesn't match the expectations, if it doesn't
+fn mismatch(outcome: &Outcome, expected: &str, error: Option<&str>)->Option<String> {
+    if outcome.stdout != expected {
+        return Some(format!("printed {:?}, expected {expected:?}", outcome.stdout));
+    }
+
+    match (&outcome.result, error) {
+        (Ok(_), None)=>None,
+        (Ok(value), Some(error))=>Some(format!("ended with {value} instead of failing with {error:?}")),
+        (Err(e), None)=>Some(format!("failed with {e:?}")),
+        (Err(e), Some(error))=>match e.contains(error) {
+            true=>None,
+            false=>Some(format!("failed with {e:?}, expected {error:?}")),
+        },
+    }
+}
+
+/// The warnings from converting `source` for V2, one per line
+fn v2_warnings(source: &str, path: &Path)->Result<String, String> {
+    let exprs = parser::new_parser(source).parse_all().map_err(|e|e.to_string())?;
+    let state = convert(exprs, path, SearchPath::default(), true).map_err(|e|e.to_string())?;
+
+    return Ok(state.warnings.iter().map(|w|format!("{w}\n")).collect());
+}
+
+fn update(path: &Path, outcome: &Outcome) {
+    write(path.with_extension("expected"), &outcome.stdout).unwrap();
+
+    let error_path = path.with_extension("error");
+    match &outcome.result {
+        Ok(_)=>if error_path.exists() {
+            remove_file(&error_path).unwrap();
+        },
+        Err(e)=>write(&error_path, format!("{e}\n")).unwrap(),
+    }
+}
+
+#[test]
+fn fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let updating = var_os("UPDATE_EXPECT").is_some_and(|v|v == "1");
+
+    let mut paths = Vec::new();
+    find_fixtures(&dir, &mut paths);
+    paths.sort();
+    assert!(paths.len() > 0, "No fixtures found");
+
+    let mut failures = Vec::new();
+    for path in paths.iter() {
+        let name = path.strip_prefix(&dir).unwrap().display().to_string();
+        let source = read_to_string(path).unwrap();
+        let options = InterpreterOptions::default();
+
+        let v1 = run_v1(&source, path, options, SearchPath::default());
+        if updating {
+            update(path, &v1);
+        }
+
+        let expected = read_to_string(path.with_extension("expected"))
+            .unwrap_or_else(|_|panic!("{name} has no .expected file. Run with UPDATE_EXPECT=1 to make one."));
+        let error = read_to_string(path.with_extension("error")).ok();
+        let error = error.as_deref().map(str::trim);
+
+        if let Some(why) = mismatch(&v1, &expected, error) {
+            failures.push(format!("{name} (v1): {why}"));
+        }
+        let warnings_path = path.with_extension("warnings");
+        if let Ok(expected) = read_to_string(&warnings_path) {
+            match v2_warnings(&source, path) {
+                Ok(warnings) if updating=>write(&warnings_path, warnings).unwrap(),
+                Ok(warnings) if warnings != expected=>{
+                    failures.push(format!("{name} (warnings): got {warnings:?}, expected {expected:?}"));
+                },
+                Ok(_)=>{},
+                Err(e)=>failures.push(format!("{name} (warnings): failed to convert: {e}")),
+            }
+        }
+        if !is_v1_only(&source) {
+            let v2 = run_v2(&source, path, options, SearchPath::default());
+            if let Some(why) = mismatch(&v2, &expected, error) {
+                failures.push(format!("{name} (v2): {why}"));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{} fixtures failed:\n{}", failures.len(), failures.join("\n"));
+}
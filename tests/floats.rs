@@ -0,0 +1,195 @@
+//! Floats print in a form the lexer reads back to the same bits, and never look like numbers. The
+//! special ones are written `#inf`, `#-inf` and `#nan`, so they can't collide with names.
+
+
+mod common;
+
+use common::eval_v1;
+use simple_lisp::{
+    ast::Expr,
+    interpreter2::{
+        ast::{
+            ConvertState,
+            repl_convert,
+        },
+        data::Primitive,
+    },
+    numeric::FloatDisplay,
+    output::Captured,
+    parser,
+    InterpreterOptions,
+};
+
+
+/// Subnormals, both zeros, both ends of the range, and floats that need all 17 digits
+const CORPUS: &[f64] = &[
+    0.0,
+    -0.0,
+    1.0,
+    -1.0,
+    0.1,
+    1.0 / 3.0,
+    -2.5e-8,
+    123456789.125,
+    9007199254740993.0,
+    1e15,
+    1e16,
+    1e300,
+    -1.7976931348623157e308,
+    f64::MAX,
+    f64::MIN_POSITIVE,
+    f64::EPSILON,
+    5e-324,
+    2.225073858507201e-308,
+    -1e-310,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+];
+
+
+fn parse_float(text: &str)->f64 {
+    let exprs = parser::new_parser(text).parse_all().unwrap_or_else(|e|panic!("`{text}`: {e}"));
+    match exprs.as_slice() {
+        [Expr::Float(f)]=>*f,
+        other=>panic!("`{text}` parsed to {other:?}"),
+    }
+}
+
+#[test]
+fn round_trip() {
+    for f in CORPUS.iter().copied().chain([f64::MIN_POSITIVE / 3.0, f64::from_bits(1), f64::from_bits(0x000f_ffff_ffff_ffff)]) {
+        let text = FloatDisplay(f).to_string();
+        assert!(text.contains(['.', 'e', '#']), "{f:e} printed as `{text}`");
+        assert_eq!(parse_float(&text).to_bits(), f.to_bits(), "{f:e} printed as `{text}`");
+    }
+
+    let text = FloatDisplay(f64::NAN).to_string();
+    assert_eq!(text, "#nan");
+    assert!(parse_float(&text).is_nan());
+}
+
+#[test]
+fn printing() {
+    let cases = [
+        ("1.0", "1.0"),
+        ("(* 0.5 4.0)", "2.0"),
+        ("(float 3)", "3.0"),
+        ("1e16", "1e16"),
+        ("(core/list 1.0 2.5)", "(1.0 2.5)"),
+        ("(std/string/format 1.0 \" \" 0.25)", "\"1.0 0.25\""),
+        ("(* 1e200 1e200)", "#inf"),
+        ("(- 0 (* 1e200 1e200))", "#-inf"),
+        ("(- #inf #inf)", "#nan"),
+        ("(std/string/formatFloat 3.14159 2)", "\"3.14\""),
+        ("(std/string/formatFloat 2 3)", "\"2.000\""),
+        ("(std/string/formatFloat 0.5 0)", "\"0\""),
+        ("(std/string/formatFloat #-inf 2)", "\"#-inf\""),
+        ("(std/string/formatFloat 1.0 65)", "Error: `formatFloat` takes 0 to 64 digits, but got 65"),
+    ];
+
+    for (expr, expected) in cases {
+        assert_eq!(eval_v1(&[expr]), expected, "{expr}");
+    }
+}
+
+#[test]
+fn negative_literals() {
+    assert_eq!(parse_float("-2.5"), -2.5);
+    assert_eq!(eval_v1(&["(- 5 -3)"]), "8");
+    assert_eq!(eval_v1(&["(+ -1.5 -9223372036854775808)"]), "-9.223372036854776e18");
+    // `-` on its own, or in front of a name, is still an ident
+    let exprs = parser::new_parser("- -x -= -1x").parse_all().unwrap();
+    assert_eq!(exprs, [Expr::Ident("-"), Expr::Ident("-x"), Expr::Ident("-="), Expr::Ident("-1x")]);
+}
+
+#[test]
+fn special_values() {
+    let cases = [
+        ("#inf", "#inf"),
+        ("#-inf", "#-inf"),
+        ("#nan", "#nan"),
+        ("(/ 1.0 0.0)", "#inf"),
+        ("(/ -1 0.0)", "#-inf"),
+        ("(* 0.0 #inf)", "#nan"),
+        ("(core/list #inf #nan)", "(#inf #nan)"),
+        ("(= #nan #nan)", "false"),
+        ("(= #inf (/ 1.0 0.0))", "true"),
+        ("(nan? #nan)", "true"),
+        ("(nan? (- #inf #inf))", "true"),
+        ("(nan? #inf)", "false"),
+        ("(nan? 0)", "false"),
+        ("(infinite? #inf)", "true"),
+        ("(infinite? #-inf)", "true"),
+        ("(infinite? #nan)", "false"),
+        ("(infinite? 1e308)", "false"),
+        ("(infinite? 5)", "false"),
+        ("(finite? 1.5)", "true"),
+        ("(finite? 9223372036854775807)", "true"),
+        ("(finite? #nan)", "false"),
+        ("(finite? #-inf)", "false"),
+        ("(nan? \"nan\")", "Error: Type error: `nan?` expected number or float, but got string"),
+    ];
+
+    for (expr, expected) in cases {
+        assert_eq!(eval_v1(&[expr]), expected, "{expr}");
+    }
+}
+
+#[test]
+fn v2_special_values() {
+    let mut state = ConvertState::new();
+    let mut interpreter = InterpreterOptions::default().new_interpreter2_with_output(&mut state, Box::new(Captured::new()));
+    let mut run = |source: &str|{
+        let exprs = parser::new_parser(source).parse_all().unwrap();
+        let start = repl_convert(&mut state, exprs).unwrap();
+        interpreter.run(&mut state, Some(start))
+    };
+
+    let cases = [
+        ("(nan? #nan)", true),
+        ("(nan? #inf)", false),
+        ("(nan? 0)", false),
+        ("(infinite? #-inf)", true),
+        ("(infinite? 1e308)", false),
+        ("(finite? 1.5)", true),
+        ("(finite? 9223372036854775807)", true),
+        ("(finite? #nan)", false),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(run(source).unwrap(), Primitive::Bool(expected), "{source}");
+    }
+
+    let err = run("(finite? \\a)").unwrap_err();
+    assert!(err.to_string().contains("`finite?` expected number or float, but got char"), "{err}");
+}
+
+/// JSON has no NaN or infinity, so they are an error or `null`, never invalid JSON
+#[test]
+fn json_write() {
+    let cases = [
+        ("(std/string/json-write (core/list 1 2.5 \"a\" #t None))", r#""[1,2.5,\"a\",true,null]""#),
+        ("(std/string/json-write #nan)", "Error: JSON has no `#nan`. Pass .null to `json-write` to write it as null"),
+        ("(std/string/json-write (core/list 1.0 #-inf))", "Error: JSON has no `#-inf`. Pass .null to `json-write` to write it as null"),
+        ("(std/string/json-write (core/list #inf #nan 0.5) .null)", r#""[null,null,0.5]""#),
+        ("(std/string/json-write #inf .error)", "Error: JSON has no `#inf`. Pass .null to `json-write` to write it as null"),
+        ("(std/string/json-write 1 .nope)", "Error: `json-write` takes .null or .error, but got .nope"),
+    ];
+
+    for (expr, expected) in cases {
+        assert_eq!(eval_v1(&[expr]), expected, "{expr}");
+    }
+}
+
+/// The literals need a `#`, so `inf` and `nan` are still free to be names
+#[test]
+fn special_names() {
+    let exprs = parser::new_parser("inf -inf nan #inf").parse_all().unwrap();
+    assert_eq!(exprs[..3], [Expr::Ident("inf"), Expr::Ident("-inf"), Expr::Ident("nan")]);
+    assert!(matches!(exprs[3], Expr::Float(f) if f == f64::INFINITY));
+
+    let out = eval_v1(&["(def inf 5)", "(def nan \"not a number\")", "(core/list inf nan #inf)"]);
+    assert_eq!(out, "(5 \"not a number\" #inf)");
+
+    let err = parser::new_parser("#NaN").parse_all().unwrap_err();
+    assert!(format!("{err:#}").contains("Invalid #literal `NaN`"), "{err:#}");
+}
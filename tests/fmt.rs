@@ -0,0 +1,47 @@
+//! Runs `simple_lisp fmt` over the fixtures in `tests/fmt`. Each `NAME.in.slp` has to format to
+//! `NAME.out.slp`, and formatting `NAME.out.slp` again has to leave it unchanged.
+
+
+use std::{
+    fs::{
+        read_dir,
+        read_to_string,
+    },
+    path::Path,
+    process::Command,
+};
+
+
+fn format_file(path: &Path)->String {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .arg("fmt")
+        .arg("--stdout")
+        .arg(path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "Formatting {} failed: {}", path.display(), String::from_utf8_lossy(&output.stdout));
+
+    return String::from_utf8(output.stdout).unwrap();
+}
+
+#[test]
+fn fmt_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fmt");
+    let mut count = 0;
+
+    for entry in read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let Some(stem) = name.strip_suffix(".in.slp") else {continue};
+
+        let out_path = dir.join(format!("{stem}.out.slp"));
+        let expected = read_to_string(&out_path).unwrap();
+
+        assert_eq!(format_file(&path), expected, "{name} did not format to {stem}.out.slp");
+        assert_eq!(format_file(&out_path), expected, "Formatting {stem}.out.slp is not idempotent");
+
+        count += 1;
+    }
+
+    assert!(count > 0, "No fixtures found");
+}
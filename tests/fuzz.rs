@@ -0,0 +1,168 @@
+//! Random input for the lexer, parser and both converters, from the generators in
+//! `simple_lisp::fuzz`. None of them may panic: the parser has to give back exprs or a parse error,
+//! and the converters exprs or an error. Whatever V2 converts has to pass the verifier.
+//!
+//! Set `FUZZ_ITERATIONS` to run longer and `FUZZ_SEED` to start somewhere else. A failure prints
+//! the seed that replays it.
+
+
+use simple_lisp::{
+    ast::Expr,
+    error::LispError,
+    fuzz::{
+        Rng,
+        random_bytes,
+        random_exprs,
+        random_source,
+    },
+    interpreter,
+    interpreter2::{
+        self,
+        verify::verify,
+    },
+    parser::{
+        self,
+        MAX_NESTING,
+    },
+    source::SearchPath,
+};
+use std::{
+    panic::{
+        AssertUnwindSafe,
+        catch_unwind,
+    },
+    path::Path,
+};
+
+
+/// How many inputs each test tries
+fn iterations()->u64 {
+    std::env::var("FUZZ_ITERATIONS").ok()
+        .and_then(|n|n.parse().ok())
+        .unwrap_or(300)
+}
+
+fn first_seed()->u64 {
+    std::env::var("FUZZ_SEED").ok()
+        .and_then(|n|n.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Run `f` with a fresh `Rng` for each seed, and name the seed if it panics
+fn fuzz(what: &str, f: impl Fn(&mut Rng)) {
+    let first = first_seed();
+    for seed in first..first + iterations() {
+        let mut rng = Rng::new(seed);
+        if catch_unwind(AssertUnwindSafe(||f(&mut rng))).is_err() {
+            panic!("{what} panicked. Replay it with FUZZ_SEED={seed} FUZZ_ITERATIONS=1");
+        }
+    }
+}
+
+/// Parse `source` both ways, and check that any error is a parse error
+fn check_parse(source: &str) {
+    let results = [
+        parser::new_parser(source).parse_all(),
+        parser::repl_new_parser(source).parse_all(),
+    ];
+    for res in results {
+        if let Err(e) = res {
+            assert!(
+                matches!(e.root_cause().downcast_ref(), Some(LispError::Parse(_)|LispError::Incomplete(_))),
+                "{source:?} failed without a parse error: {e:#}",
+            );
+        }
+    }
+}
+
+/// Convert `exprs` with V1 and V2, and verify what V2 makes. `make` is called once for each since
+/// converting takes the exprs.
+fn check_convert<'a>(make: impl Fn()->Vec<Expr<'a>>) {
+    let _ = interpreter::ast::convert(make(), Path::new("fuzz.slp"), SearchPath::default(), false);
+
+    if let Ok(state) = interpreter2::ast::convert(make(), Path::new("fuzz.slp"), SearchPath::default(), false) {
+        if let Err(e) = verify(&state) {
+            panic!("V2 converted {:#?} to code that doesn't verify: {e}", make());
+        }
+    }
+}
+
+
+#[test]
+fn parse_bytes() {
+    fuzz("Parsing random bytes", |rng|{
+        let len = rng.below(64);
+        check_parse(&random_bytes(rng, len));
+    });
+}
+
+#[test]
+fn parse_fragments() {
+    fuzz("Parsing random fragments", |rng|{
+        let count = rng.below(48);
+        check_parse(&random_source(rng, count));
+    });
+}
+
+#[test]
+fn convert_exprs() {
+    fuzz("Converting random exprs", |rng|{
+        let count = rng.below(6) + 1;
+        let depth = rng.below(6);
+        let start = rng.clone();
+        check_convert(||random_exprs(&mut start.clone(), count, depth));
+    });
+}
+
+/// Inputs that used to panic
+#[test]
+fn known_crashes() {
+    let sources = [
+        "()",
+        "(())",
+        "9223372036854775808",
+        "-9223372036854775809",
+        "(+ 1 99999999999999999999999)",
+        "a//b",
+        "a/",
+        "(set a//b 1)",
+        "#",
+        "\\",
+        "..",
+        "#x4f",
+        "'x",
+        "(quote (a [b] {c}))",
+        "(object (.a 1))",
+        "(fn {x} [] x)",
+        "(set 1 2)",
+        "(object 1)",
+    ];
+    for source in sources {
+        check_parse(source);
+        let Ok(_) = parser::new_parser(source).parse_all() else {continue};
+        check_convert(||parser::new_parser(source).parse_all().unwrap());
+    }
+}
+
+#[test]
+fn deep_nesting() {
+    for open in ["(", "'", "'(", "(begin ", "[", "...("] {
+        let source = open.repeat(100_000);
+        check_parse(&source);
+    }
+
+    // right at the limit still parses and converts. The binary does that on the main thread, which
+    // has more stack than test threads do.
+    let source = format!("{}1{}", "(begin ".repeat(MAX_NESTING - 1), ")".repeat(MAX_NESTING - 1));
+    assert!(parser::new_parser(&source).parse_all().is_ok());
+    std::thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(move||check_convert(||parser::new_parser(&source).parse_all().unwrap()))
+        .unwrap()
+        .join()
+        .unwrap();
+
+    let source = format!("{}1{}", "(begin ".repeat(MAX_NESTING), ")".repeat(MAX_NESTING));
+    let err = parser::new_parser(&source).parse_all().unwrap_err();
+    assert!(format!("{err:#}").contains(&format!("nested more than {MAX_NESTING} deep")), "{err:#}");
+}
@@ -0,0 +1,114 @@
+//! Stress tests for the V1 garbage collector. The scripts collect every few allocations with tiny
+//! slices and nurseries, so lots of mutation happens between and during collections. Debug builds
+//! check that nothing reachable is left unmarked before sweeping, so a hole in a write barrier
+//! panics instead of quietly freeing live data. `--gc-stress` collects before every allocation
+//! and poisons freed data, so it catches natives that don't root what they allocate.
+
+
+use std::{
+    path::Path,
+    process::Command,
+};
+
+
+fn run_stressed(script: &str, gc_args: &[&str])->String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/gc").join(script);
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .args(gc_args)
+        .arg("run")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{script} failed with {gc_args:?}: {}",
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    return String::from_utf8(output.stdout).unwrap();
+}
+
+#[test]
+fn mutate_during_incremental_collection() {
+    for slice_size in ["1", "3"] {
+        let args = ["--gc-threshold", "20", "--gc-slice", slice_size, "--gc-nursery", "0"];
+        assert_eq!(run_stressed("mutate.slp", &args), "(45250 300) (79500 200)\n");
+    }
+}
+
+#[test]
+fn mutate_between_minor_collections() {
+    for nursery_size in ["15", "40"] {
+        let args = ["--gc-threshold", "200", "--gc-slice", "3", "--gc-nursery", nursery_size];
+        assert_eq!(run_stressed("mutate.slp", &args), "(45250 300) (79500 200)\n");
+    }
+}
+
+#[test]
+fn natives_under_stress() {
+    assert_eq!(
+        run_stressed("natives.slp", &["--gc-stress"]),
+        "((<ident> gc) (<ident> (1 2 3)) (<ident> 3))\n((1 2) (3 4 5))\n(a bb ccc) (x y)\n(\\s \\t \\r \\e \\s \\s) 9 None\n4 ()\n",
+    );
+}
+
+#[test]
+fn copies_under_stress() {
+    let expected = "(1 2) (1 2)\n(1 2 9) (1 2 9) (1 2 9)\n3 2 (1 2 5)\n3 4\n(1 #cycle->list) (2 #cycle->list) false\n";
+    assert_eq!(run_stressed("copy.slp", &[]), expected);
+    assert_eq!(run_stressed("copy.slp", &["--gc-stress"]), expected);
+}
+
+#[test]
+fn pairs_under_stress() {
+    let expected = "200 0 1\n((1 2) . (3)) ((1 2 9) . (3)) true\n";
+    assert_eq!(run_stressed("pairs.slp", &["--gc-stress"]), expected);
+    assert_eq!(run_stressed("pairs.slp", &["--gc-threshold", "20", "--gc-slice", "1", "--gc-nursery", "0"]), expected);
+}
+
+#[test]
+fn lazy_seqs_under_stress() {
+    let expected = "((3 103) (4 104) (5 105))\n50\n(39900)\n";
+    assert_eq!(run_stressed("lazy.slp", &["--gc-stress"]), expected);
+    assert_eq!(run_stressed("lazy.slp", &["--gc-threshold", "20", "--gc-slice", "1", "--gc-nursery", "0"]), expected);
+    assert_eq!(run_stressed("lazy.slp", &["--gc-threshold", "200", "--gc-slice", "3", "--gc-nursery", "15"]), expected);
+}
+
+#[test]
+fn suspended_generators_under_stress() {
+    let expected = "0\n((0) None (0 0))\n(1 ((1) None (1 1)) 2 ((2) None (2 2)))\n((5 ((5) None (5 5))) (6 ((6) None (6 6))))\n";
+    assert_eq!(run_stressed("generators.slp", &["--gc-stress"]), expected);
+    assert_eq!(run_stressed("generators.slp", &["--gc-threshold", "20", "--gc-slice", "1", "--gc-nursery", "0"]), expected);
+    assert_eq!(run_stressed("generators.slp", &["--gc-threshold", "200", "--gc-slice", "3", "--gc-nursery", "15"]), expected);
+}
+
+#[test]
+fn stats_report_collections() {
+    let output = run_stressed("natives.slp", &["-s", "--gc-threshold", "20", "--gc-slice", "0"]);
+    let line = output.lines()
+        .find(|l|l.starts_with("GC freed: "))
+        .expect("No GC stats in the output");
+    let freed = line["GC freed: ".len()..].split(' ').next().unwrap();
+
+    assert!(output.contains("longest pause"));
+    assert!(freed.parse::<u64>().unwrap() > 0, "{line}");
+}
+
+#[test]
+fn debug_flag_logs_collections() {
+    let gc_log = |args: &[&str]|{
+        let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+            .env_remove("RUST_LOG")
+            .args(args)
+            .args(["--gc-threshold", "20", "--gc-slice", "0", "--gc-nursery", "0", "run"])
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/gc/natives.slp"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    assert!(!gc_log(&[]).contains("data entries"));
+    let stderr = gc_log(&["-dd"]);
+    assert!(stderr.contains("Freed") && stderr.contains("data entries"), "{stderr}");
+}
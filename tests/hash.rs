@@ -0,0 +1,193 @@
+//! `core/hash` has to agree with `=`: values that are `=` hash the same, however they were made.
+//! Random values are generated in pairs of two different ways to write the same thing, like fields
+//! in another order or `-0.0` for `0.0`.
+
+
+use simple_lisp::Engine;
+
+
+/// Small and seeded, so a failure happens again on the next run
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self)->u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64)->u64 {
+        self.next() % n
+    }
+}
+
+const FIELDS: &[&str] = &["x", "y", "z", "w"];
+
+/// Two expressions for values that are `=`, written differently where the value allows it
+fn gen_pair(rng: &mut Rng, depth: u32)->(String, String) {
+    let kinds = if depth == 0 {7} else {10};
+    match rng.below(kinds) {
+        0=>{
+            let n = rng.below(2001) as i64 - 1000;
+            (n.to_string(), n.to_string())
+        },
+        1=>match rng.below(4) {
+            0=>("0.0".into(), "-0.0".into()),
+            1=>("-0.0".into(), "0.0".into()),
+            _=>{
+                let f = format!("{}.{}", rng.below(100) as i64 - 50, rng.below(100));
+                (f.clone(), f)
+            },
+        },
+        2=>{
+            let s = format!("\"s{}\"", rng.below(20));
+            (s.clone(), s)
+        },
+        3=>{
+            let c = format!("\\{}", (b'a' + rng.below(26) as u8) as char);
+            (c.clone(), c)
+        },
+        4=>{
+            let b = if rng.below(2) == 0 {"#t"} else {"#f"};
+            (b.into(), b.into())
+        },
+        5=>("None".into(), "None".into()),
+        6=>{
+            let name = format!("(core/intern \"k{}\")", rng.below(5));
+            (name.clone(), name)
+        },
+        7=>{
+            let (mut l, mut r) = ("(core/list".to_string(), "(core/list".to_string());
+            for _ in 0..rng.below(4) {
+                let (item_l, item_r) = gen_pair(rng, depth - 1);
+                l.push_str(&format!(" {item_l}"));
+                r.push_str(&format!(" {item_r}"));
+            }
+            (l + ")", r + ")")
+        },
+        // the same fields, but the right one has them backwards
+        8=>{
+            let mut fields = Vec::new();
+            for name in FIELDS {
+                if rng.below(2) == 0 {
+                    let (value_l, value_r) = gen_pair(rng, depth - 1);
+                    fields.push((format!("(.{name} {value_l})"), format!("(.{name} {value_r})")));
+                }
+            }
+            let l = fields.iter().map(|(l, _)|l.as_str()).collect::<Vec<_>>().join(" ");
+            let r = fields.iter().rev().map(|(_, r)|r.as_str()).collect::<Vec<_>>().join(" ");
+            (format!("(object {l})"), format!("(object {r})"))
+        },
+        _=>{
+            let (mut l, mut r) = gen_pair(rng, depth - 1);
+            for _ in 0..rng.below(3) + 1 {
+                let (car_l, car_r) = gen_pair(rng, depth - 1);
+                l = format!("(core/cons {car_l} {l})");
+                r = format!("(core/cons {car_r} {r})");
+            }
+            (l, r)
+        },
+    }
+}
+
+fn eval_hash(engine: &mut Engine, expr: &str)->i64 {
+    engine.eval_str(&format!("(core/hash {expr})"))
+        .unwrap_or_else(|e|panic!("{expr}: {e}"))
+        .as_i64()
+        .unwrap()
+}
+
+fn eval_equal(engine: &mut Engine, l: &str, r: &str)->bool {
+    engine.eval_str(&format!("(= {l} {r})"))
+        .unwrap_or_else(|e|panic!("(= {l} {r}): {e}"))
+        .as_bool()
+        .unwrap()
+}
+
+#[test]
+fn equal_values_hash_equal() {
+    let mut engine = Engine::new();
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+
+    let mut seen = Vec::new();
+    for _ in 0..300 {
+        let (l, r) = gen_pair(&mut rng, 3);
+        assert!(eval_equal(&mut engine, &l, &r), "{l} and {r} should be `=`");
+
+        let hash = eval_hash(&mut engine, &l);
+        assert_eq!(hash, eval_hash(&mut engine, &r), "{l} and {r}");
+        assert_eq!(hash, eval_hash(&mut engine, &l), "{l} hashed differently the second time");
+        seen.push((l, hash));
+    }
+
+    // values that happen to be `=` across cases have to agree too
+    for _ in 0..300 {
+        let (l, l_hash) = &seen[rng.below(seen.len() as u64) as usize];
+        let (r, r_hash) = &seen[rng.below(seen.len() as u64) as usize];
+        if eval_equal(&mut engine, l, r) {
+            assert_eq!(l_hash, r_hash, "{l} and {r}");
+        }
+    }
+}
+
+#[test]
+fn types_hash_apart() {
+    let mut engine = Engine::new();
+    let exprs = [
+        "1",
+        "1.0",
+        "\"1\"",
+        "\\1",
+        "(core/list 1)",
+        "(core/cons 1 None)",
+        "(object (.x 1))",
+        "(object (.y 1))",
+        "(core/list 1 2)",
+        "(core/list 2 1)",
+    ];
+    let hashes = exprs.iter()
+        .map(|expr|eval_hash(&mut engine, expr))
+        .collect::<Vec<_>>();
+
+    // not guaranteed, but a collision here would mean the hash ignores something it shouldn't
+    for (i, l) in hashes.iter().enumerate() {
+        for (j, r) in hashes.iter().enumerate().skip(i + 1) {
+            assert_ne!(l, r, "{} and {}", exprs[i], exprs[j]);
+        }
+    }
+}
+
+#[test]
+fn unhashable() {
+    let mut engine = Engine::new();
+    engine.eval_str("(def l (core/list 1))").unwrap();
+    engine.eval_str("(+= l l)").unwrap();
+    // fields can't be set yet, so this loops back through a list
+    engine.eval_str("(def items (core/list))").unwrap();
+    engine.eval_str("(def o (object (.items items)))").unwrap();
+    engine.eval_str("(+= items o)").unwrap();
+
+    for (expr, error) in [
+        ("(fn [] 1)", "can't hash a"),
+        ("core/hash", "can't hash a nativeFn"),
+        ("(core/list 1 (core/list (fn [] 1)))", "can't hash a"),
+        ("std/io/stdout", "can't hash a nativeData"),
+        ("l", "can't hash a value that contains itself"),
+        ("o", "can't hash a value that contains itself"),
+    ] {
+        let err = engine.eval_str(&format!("(core/hash {expr})")).unwrap_err();
+        assert!(err.to_string().contains(error), "{expr}: {err}");
+    }
+}
+
+#[test]
+fn memoize_with_unhashable_args() {
+    let mut engine = Engine::new();
+    engine.eval_str("(def calls 0)").unwrap();
+    engine.eval_str("(defn five [] 5)").unwrap();
+    engine.eval_str("(def m (std/func/memoize (fn [f] (+= calls 1) (f))))").unwrap();
+
+    assert_eq!(engine.eval_str("(m five)").unwrap().as_i64(), Some(5));
+    assert_eq!(engine.eval_str("(m five)").unwrap().as_i64(), Some(5));
+    assert_eq!(engine.eval_str("calls").unwrap().as_i64(), Some(1));
+}
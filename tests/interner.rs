@@ -0,0 +1,49 @@
+//! Looking names up in the interners doesn't intern them, so typos don't grow the tables.
+
+
+use simple_lisp::{
+    interpreter::ast::Interner,
+    interpreter2,
+    parser,
+    source::SearchPath,
+};
+use std::path::Path;
+
+
+#[test]
+fn lookup_does_not_intern() {
+    let mut interner = Interner::new();
+    let a = interner.intern("a");
+    let b = interner.intern("b");
+    assert_eq!(interner.len(), 2);
+
+    assert_eq!(interner.lookup("a"), Some(a));
+    assert_eq!(interner.lookup("typo"), None);
+    assert_eq!(interner.len(), 2);
+
+    assert_eq!(interner.get_opt(b), Some("b"));
+    assert_eq!(interner.iter().collect::<Vec<_>>(), [(a, "a"), (b, "b")]);
+}
+
+#[test]
+fn get_opt_does_not_panic() {
+    let v1 = Interner::new();
+    let mut v2 = interpreter2::ast::Interner::new();
+    let ident = v2.intern("x");
+    v2.intern("y");
+
+    assert_eq!(v1.get_opt(simple_lisp::interpreter::ast::Ident(5)), None);
+    assert_eq!(v2.get_opt(ident), Some("x"));
+    assert_eq!(v2.get_opt(interpreter2::ast::Ident(2)), None);
+}
+
+#[test]
+fn looking_up_undefined_vars() {
+    let exprs = parser::new_parser("(def x 1)").parse_all().unwrap();
+    let state = interpreter2::ast::convert(exprs, Path::new("interner.slp"), SearchPath::default(), false).unwrap();
+    let len = state.interner.len();
+
+    assert!(state.lookup_var("x").is_some());
+    assert!(state.lookup_var("not-defined").is_none());
+    assert_eq!(state.interner.len(), len);
+}
@@ -0,0 +1,125 @@
+//! Turning what a terminal sends into keys. The terminal itself can't be tested here, but the
+//! parser can, and so can reading keys from a pipe.
+
+
+use simple_lisp::terminal::{
+    Key,
+    Parsed,
+    parse_key,
+    parse_partial,
+};
+use std::{
+    io::Write,
+    process::{
+        Command,
+        Stdio,
+    },
+};
+
+
+#[test]
+fn single_keys() {
+    let cases: &[(&[u8], Key, usize)] = &[
+        (b"a", Key::Char('a'), 1),
+        (b"ab", Key::Char('a'), 1),
+        (b" ", Key::Char(' '), 1),
+        (b"\r", Key::Named("enter"), 1),
+        (b"\n", Key::Named("enter"), 1),
+        (b"\t", Key::Named("tab"), 1),
+        (b"\x7f", Key::Named("backspace"), 1),
+        (b"\x03", Key::Ctrl('c'), 1),
+        (b"\x01", Key::Ctrl('a'), 1),
+        (b"\x1a", Key::Ctrl('z'), 1),
+        ("é".as_bytes(), Key::Char('é'), 2),
+        ("日本".as_bytes(), Key::Char('日'), 3),
+        ("🦀".as_bytes(), Key::Char('🦀'), 4),
+        (b"\xff", Key::Char(char::REPLACEMENT_CHARACTER), 1),
+        (b"\xc3x", Key::Char(char::REPLACEMENT_CHARACTER), 1),
+    ];
+
+    for (bytes, key, len) in cases.iter().copied() {
+        assert_eq!(parse_key(bytes), Parsed::Key(key, len), "{bytes:?}");
+    }
+}
+
+#[test]
+fn escape_sequences() {
+    let cases: &[(&[u8], Key, usize)] = &[
+        (b"\x1b[A", Key::Named("up"), 3),
+        (b"\x1b[B", Key::Named("down"), 3),
+        (b"\x1b[C", Key::Named("right"), 3),
+        (b"\x1b[D", Key::Named("left"), 3),
+        (b"\x1bOA", Key::Named("up"), 3),
+        (b"\x1b[H", Key::Named("home"), 3),
+        (b"\x1b[F", Key::Named("end"), 3),
+        (b"\x1b[1~", Key::Named("home"), 4),
+        (b"\x1b[3~", Key::Named("delete"), 4),
+        (b"\x1b[5~", Key::Named("pageUp"), 4),
+        (b"\x1b[6~", Key::Named("pageDown"), 4),
+        (b"\x1b[Z", Key::Named("backTab"), 3),
+        // modifiers are ignored
+        (b"\x1b[1;5A", Key::Named("up"), 6),
+        (b"\x1b[3;2~", Key::Named("delete"), 6),
+        // one sequence at a time, even if more are waiting
+        (b"\x1b[A\x1b[B", Key::Named("up"), 3),
+        (b"\x1b[99~", Key::Named("unknown"), 5),
+        // an escape before something that isn't a sequence is the escape key alone
+        (b"\x1bx", Key::Named("escape"), 1),
+        (b"\x1b\x1b[A", Key::Named("escape"), 1),
+        (b"\x1b[\x01", Key::Named("escape"), 1),
+    ];
+
+    for (bytes, key, len) in cases.iter().copied() {
+        assert_eq!(parse_key(bytes), Parsed::Key(key, len), "{bytes:?}");
+    }
+}
+
+#[test]
+fn incomplete() {
+    let cases: &[&[u8]] = &[b"", b"\x1b", b"\x1b[", b"\x1b[1", b"\x1b[1;5", b"\x1bO", b"\xc3", b"\xe6\x97"];
+    for bytes in cases.iter().copied() {
+        assert_eq!(parse_key(bytes), Parsed::Incomplete, "{bytes:?}");
+    }
+
+    // what they are if nothing else comes
+    assert_eq!(parse_partial(b"\x1b"), Some((Key::Named("escape"), 1)));
+    assert_eq!(parse_partial(b"\x1b[1"), Some((Key::Named("escape"), 1)));
+    assert_eq!(parse_partial(b"\xc3"), Some((Key::Char(char::REPLACEMENT_CHARACTER), 1)));
+    assert_eq!(parse_partial(b""), None);
+}
+
+#[test]
+fn read_keys_from_a_pipe() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .arg("eval")
+        .arg("(def k std/term/readKey) (std/string/format (k) \" \" (k) \" \" (k) \" \" (k) \" \" (k) \" \" (k))")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // a lone escape at the end of input is the escape key
+    child.stdin.take().unwrap().write_all("a\x1b[A\x03é\x1b".as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\\a up ctrl-c \\é escape None"), "{stdout}");
+}
+
+#[test]
+fn read_key_timeout() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .arg("eval")
+        .arg("(std/string/format (std/term/readKeyTimeout 20))")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // stdin stays open, so this only returns because of the timeout
+    let stdin = child.stdin.take().unwrap();
+    let output = child.wait_with_output().unwrap();
+    drop(stdin);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("None"), "{stdout}");
+}
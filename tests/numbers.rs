@@ -0,0 +1,141 @@
+//! How numbers and floats mix. The table runs with V1, the rest checks the shared rules in
+//! `numeric` directly, mostly around 2^53 where an `i64` stops fitting in an `f64`. V2's arithmetic
+//! natives are in its builtins module, which isn't part of this tree, so the table can't run with
+//! V2 yet.
+
+
+mod common;
+
+use common::eval_v1;
+use simple_lisp::numeric::{
+    Num,
+    Op,
+    apply,
+    compare,
+    to_int,
+};
+use std::cmp::Ordering;
+
+
+const CASES: &[(&str, &str)] = &[
+    ("(+ 1 2)", "3"),
+    ("(+ 1 2.5)", "3.5"),
+    ("(+ 2.5 1)", "3.5"),
+    ("(- 1 0.5)", "0.5"),
+    ("(* 2 1.25)", "2.5"),
+    ("(/ 6 3)", "2"),
+    ("(/ 7 2)", "3.5"),
+    ("(/ 1 4.0)", "0.25"),
+    ("(% 7 3)", "1"),
+    ("(% 7.5 2)", "1.5"),
+    ("(* 9223372036854775807 2)", "Error: Integer overflow in `*`"),
+    ("(+ 9223372036854775807 1)", "Error: Integer overflow in `+`"),
+    ("(- 0 9223372036854775807 2)", "Error: Integer overflow in `-`"),
+    ("(/ 1 0)", "Error: Division by zero"),
+    ("(% 1 0)", "Error: Division by zero"),
+    ("(+ 1 \"a\")", "Error: Type error: `+` expected number or float, but got string"),
+    ("(begin (def x 1) (+= x 0.5) x)", "1.5"),
+    ("(begin (def x 9) (/= x 2) x)", "4.5"),
+
+    ("(int 2.9)", "2"),
+    ("(int (- 0 2.9))", "-2"),
+    ("(int 7)", "7"),
+    ("(float 1)", "1.0"),
+    ("(+ (float 1) 0.5)", "1.5"),
+    ("(int (float 9223372036854775807))", "Error: `int` can't convert 9.223372036854776e18 to an integer"),
+    ("(int \"1\")", "Error: Type error: `int` expected number or float, but got string"),
+
+    ("(< 9007199254740993 9007199254740992.0)", "false"),
+    ("(> 9007199254740993 9007199254740992.0)", "true"),
+    ("(<= 1 1.0)", "true"),
+    ("(< 1 1.5 2)", "true"),
+];
+
+/// 2^53, the first integer where neighboring `i64`s can round to the same `f64`
+const TWO_53: i64 = 1 << 53;
+
+
+/// The exact ordering, done in `i128`. Only for integral floats, which all of them are past 2^52.
+fn exact(i: i64, f: f64)->Ordering {
+    assert_eq!(f.fract(), 0.0);
+    (i as i128).cmp(&(f as i128))
+}
+
+/// Floats near `f`, `f` included
+fn floats_around(f: f64)->Vec<f64> {
+    let mut out = vec![f];
+    let (mut down, mut up) = (f, f);
+    for _ in 0..8 {
+        down = down.next_down();
+        up = up.next_up();
+        out.push(down);
+        out.push(up);
+    }
+
+    return out;
+}
+
+#[test]
+fn mixed_table() {
+    let failures = CASES.iter()
+        .filter_map(|(expr, expected)|{
+            let got = eval_v1(&[expr]);
+            (got != *expected).then(||format!("{expr}: expected `{expected}`, got `{got}`"))
+        })
+        .collect::<Vec<_>>();
+
+    assert!(failures.is_empty(), "{failures:#?}");
+}
+
+#[test]
+fn compare_around_2_53() {
+    for base in [TWO_53, -TWO_53, TWO_53 * 4, i64::MAX / 2] {
+        for i in (base - 20)..=(base + 20) {
+            for f in floats_around(i as f64) {
+                assert_eq!(compare(Num::Int(i), Num::Float(f)), Some(exact(i, f)), "{i} vs {f}");
+                assert_eq!(compare(Num::Float(f), Num::Int(i)), Some(exact(i, f).reverse()), "{f} vs {i}");
+            }
+        }
+    }
+}
+
+#[test]
+fn compare_at_the_ends() {
+    let limit = 9223372036854775808.0;
+    assert_eq!(compare(Num::Int(i64::MAX), Num::Float(limit)), Some(Ordering::Less));
+    assert_eq!(compare(Num::Int(i64::MIN), Num::Float(-limit)), Some(Ordering::Equal));
+    assert_eq!(compare(Num::Int(i64::MIN), Num::Float((-limit).next_up())), Some(Ordering::Less));
+    assert_eq!(compare(Num::Int(i64::MAX), Num::Float(f64::INFINITY)), Some(Ordering::Less));
+    assert_eq!(compare(Num::Int(i64::MIN), Num::Float(f64::NEG_INFINITY)), Some(Ordering::Greater));
+    assert_eq!(compare(Num::Int(0), Num::Float(f64::NAN)), None);
+
+    // the fraction breaks ties
+    assert_eq!(compare(Num::Int(2), Num::Float(2.5)), Some(Ordering::Less));
+    assert_eq!(compare(Num::Int(-2), Num::Float(-2.5)), Some(Ordering::Greater));
+    assert_eq!(compare(Num::Int(0), Num::Float(-0.0)), Some(Ordering::Equal));
+}
+
+#[test]
+fn int_truncates() {
+    assert_eq!(to_int("int", 2.9).unwrap(), 2);
+    assert_eq!(to_int("int", -2.9).unwrap(), -2);
+    assert_eq!(to_int("int", -9223372036854775808.0).unwrap(), i64::MIN);
+    for f in floats_around(TWO_53 as f64) {
+        assert_eq!(to_int("int", f).unwrap() as f64, f);
+    }
+    for f in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 9223372036854775808.0] {
+        assert!(to_int("int", f).is_err(), "{f}");
+    }
+}
+
+#[test]
+fn apply_rules() {
+    assert_eq!(apply("+", Op::Add, Num::Int(TWO_53), Num::Int(1)).unwrap(), Num::Int(TWO_53 + 1));
+    assert_eq!(apply("/", Op::Div, Num::Int(-9), Num::Int(3)).unwrap(), Num::Int(-3));
+    assert_eq!(apply("/", Op::Div, Num::Int(1), Num::Int(8)).unwrap(), Num::Float(0.125));
+    assert_eq!(apply("/", Op::Div, Num::Float(1.0), Num::Int(0)).unwrap(), Num::Float(f64::INFINITY));
+    assert_eq!(apply("%", Op::Rem, Num::Int(-7), Num::Int(2)).unwrap(), Num::Int(-1));
+    assert!(apply("/", Op::Div, Num::Int(i64::MIN), Num::Int(-1)).is_err());
+    assert!(apply("%", Op::Rem, Num::Int(i64::MIN), Num::Int(-1)).is_err());
+    assert!(apply("-", Op::Sub, Num::Int(i64::MIN), Num::Int(1)).is_err());
+}
@@ -0,0 +1,135 @@
+//! The `std/path` natives agree with `std::path` on the tricky cases, and give back what they were
+//! given when their results are put back together.
+
+
+use simple_lisp::{
+    Capabilities,
+    Engine,
+    InterpreterOptions,
+};
+
+
+/// Call `std/path/NAME` with string arguments. `None` if it returned `none`.
+fn call(engine: &mut Engine, name: &str, args: &[&str])->Option<String> {
+    let args = args.iter()
+        .map(|arg|format!("{arg:?}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let value = engine.eval_str(&format!("(std/path/{name} {args})"))
+        .unwrap_or_else(|e|panic!("`{name}` {args}: {e}"));
+    if value.is_none() {
+        return None;
+    }
+
+    return Some(value.as_string().unwrap_or_else(||panic!("`{name}` {args} returned a {}", value.type_name())));
+}
+
+#[cfg(unix)]
+#[test]
+fn components() {
+    let mut engine = Engine::new();
+    // path, parent, filename, stem, extension
+    let cases: &[(&str, Option<&str>, Option<&str>, Option<&str>, Option<&str>)] = &[
+        ("a/b.txt", Some("a"), Some("b.txt"), Some("b"), Some("txt")),
+        ("a/b/", Some("a"), Some("b"), Some("b"), None),
+        ("/", None, None, None, None),
+        ("", None, None, None, None),
+        ("file", Some(""), Some("file"), Some("file"), None),
+        (".bashrc", Some(""), Some(".bashrc"), Some(".bashrc"), None),
+        ("archive.tar.gz", Some(""), Some("archive.tar.gz"), Some("archive.tar"), Some("gz")),
+        ("a/..", Some("a"), None, None, None),
+        ("/dönër/ファイル.md", Some("/dönër"), Some("ファイル.md"), Some("ファイル"), Some("md")),
+    ];
+
+    for (path, parent, filename, stem, extension) in cases.iter().copied() {
+        assert_eq!(call(&mut engine, "parent", &[path]).as_deref(), parent, "parent of {path:?}");
+        assert_eq!(call(&mut engine, "filename", &[path]).as_deref(), filename, "filename of {path:?}");
+        assert_eq!(call(&mut engine, "stem", &[path]).as_deref(), stem, "stem of {path:?}");
+        assert_eq!(call(&mut engine, "extension", &[path]).as_deref(), extension, "extension of {path:?}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn normalize() {
+    let mut engine = Engine::new();
+    let cases = [
+        ("a/./b/../c", "a/c"),
+        ("a/b/", "a/b"),
+        ("a/..", "."),
+        ("", "."),
+        ("./", "."),
+        ("../../a", "../../a"),
+        ("a/../../b", "../b"),
+        ("/..", "/"),
+        ("/../../a/./b", "/a/b"),
+        ("/dönër/../ファイル", "/ファイル"),
+    ];
+
+    for (path, expected) in cases {
+        assert_eq!(call(&mut engine, "normalize", &[path]).as_deref(), Some(expected), "{path:?}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn join_and_relative() {
+    let mut engine = Engine::new();
+    assert_eq!(call(&mut engine, "join", &["a", "b", "c.txt"]).as_deref(), Some("a/b/c.txt"));
+    assert_eq!(call(&mut engine, "join", &["a/", "b"]).as_deref(), Some("a/b"));
+    assert_eq!(call(&mut engine, "join", &["a", "/b"]).as_deref(), Some("/b"));
+
+    let cases = [
+        ("/a/b", "/a/c/d", Some("../c/d")),
+        ("/a/b", "/a/b", Some(".")),
+        ("/a/b/", "/a/b/c", Some("c")),
+        ("a", "b", Some("../b")),
+        ("/", "/x/y", Some("x/y")),
+        ("a", "/b", None),
+        ("/a", "b", None),
+        ("..", "a", None),
+        ("../x", "../y", Some("../y")),
+    ];
+    for (base, path, expected) in cases {
+        assert_eq!(call(&mut engine, "relative", &[base, path]).as_deref(), expected, "from {base:?} to {path:?}");
+    }
+}
+
+/// Splitting a path and joining it back gives the normalized path, and so does going to it from
+/// somewhere and back
+#[test]
+fn round_trip() {
+    let mut engine = Engine::new();
+    let paths = ["a/b.txt", "a/b/", "/x/./y/../z.tar.gz", "dönër/ファイル.md", "../up/one"];
+
+    for path in paths {
+        let normalized = call(&mut engine, "normalize", &[path]).unwrap();
+        let parent = call(&mut engine, "parent", &[&normalized]).unwrap();
+        let filename = call(&mut engine, "filename", &[&normalized]).unwrap();
+        assert_eq!(call(&mut engine, "join", &[&parent, &filename]), Some(normalized.clone()), "{path:?}");
+
+        let relative = call(&mut engine, "relative", &["base/dir", &normalized]);
+        if let Some(relative) = relative {
+            let back = call(&mut engine, "join", &["base/dir", &relative]).unwrap();
+            assert_eq!(call(&mut engine, "normalize", &[&back]), Some(normalized.clone()), "{path:?}");
+        }
+    }
+}
+
+#[test]
+fn absolute_needs_read_access() {
+    let mut engine = Engine::new();
+    let cwd = std::env::current_dir().unwrap();
+    let expected = cwd.join("some/file").to_string_lossy().into_owned();
+    assert_eq!(call(&mut engine, "absolute", &["some/file"]), Some(expected));
+
+    let mut engine = Engine::with_options(InterpreterOptions {
+        capabilities: Capabilities::empty(),
+        ..Default::default()
+    });
+    let err = engine.eval_str("(std/path/absolute \"x\")").unwrap_err();
+    assert!(err.to_string().contains("capability denied: filesystem-read"), "{err}");
+
+    // the rest never touch the filesystem
+    assert!(engine.eval_str("(std/path/normalize \"x/..\")").is_ok());
+}
@@ -0,0 +1,90 @@
+//! `--on-error repl` opens a read-only REPL over what a failed run left. It reads from stdin when
+//! it isn't a terminal, so a whole session can be piped in.
+
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{
+        Command,
+        Stdio,
+    },
+};
+
+
+/// Run `script` from `tests/files` with `--on-error repl` and `input` on stdin. Returns the exit
+/// code and stdout.
+fn run(action: &str, script: &str, input: &str)->(Option<i32>, String) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .current_dir(&dir)
+        .env_remove("CLICOLOR_FORCE")
+        .args(["--on-error", "repl", action, script])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("panicked"), "{script} with {input:?} panicked: {stderr}");
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn v1_frames() {
+    let input = "b\nscaled\n:frame 1\nscaled\ncount\n:stack\n:frame 2\nconfig/items/-1\nconfig/items/3\n:quit\n";
+    let (code, stdout) = run("run", "post_mortem.slp", input);
+    assert_eq!(code, Some(1), "{stdout}");
+
+    // the error and trace come first, like without the REPL
+    let start = stdout.find("Error: Division by zero").unwrap();
+    let opened = stdout.find("The program stopped.").unwrap();
+    assert!(start < opened, "{stdout}");
+    assert!(stdout.contains("Looking at #0 in `ratio`"), "{stdout}");
+
+    let answers = stdout[opened..].lines()
+        .filter(|l|l.starts_with(">> ") || l.starts_with("Error: "))
+        .collect::<Vec<_>>();
+    assert_eq!(answers, [
+        ">> 0",
+        "Error: Var `scaled` is not defined",
+        ">> 500",
+        ">> 0",
+        ">> 3",
+        "Error: `config/items/3`: index 3 is out of bounds for length 3",
+    ]);
+
+    assert!(stdout.contains("  #0 in `ratio`"), "{stdout}");
+    assert!(stdout.contains("* #1 in `average`"), "{stdout}");
+    assert!(stdout.contains("  #2 <top level>"), "{stdout}");
+}
+
+#[test]
+fn v1_read_only() {
+    let (code, stdout) = run("run", "post_mortem.slp", ":locals\n(set config 1)\n:fram\n");
+    // running out of input quits too
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(stdout.contains("    a  500\n    b  0\n"), "{stdout}");
+    assert!(stdout.contains("Only names and paths like `foo/items/0` can be looked at"), "{stdout}");
+    assert!(stdout.contains("Unknown directive `:fram`, did you mean `frame`?"), "{stdout}");
+}
+
+#[test]
+fn v2_globals() {
+    let (code, stdout) = run("run2", "post_mortem2.slp", "x\n:globals\n:quit\n");
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(stdout.contains("Looking at #0 <top level>"), "{stdout}");
+    assert!(stdout.contains(">> 5\n"), "{stdout}");
+    assert!(stdout.contains("    x  5\n"), "{stdout}");
+}
+
+#[test]
+fn only_on_errors() {
+    let (code, stdout) = run("run", "debug.slp", "");
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(!stdout.contains("The program stopped."), "{stdout}");
+}
@@ -0,0 +1,54 @@
+//! The prelude is loaded before every program unless `--no-prelude` is given, and the program can
+//! redefine what it defines.
+
+
+mod common;
+
+use common::run_in_files;
+
+
+#[test]
+fn prelude_converts() {
+    for interpreter in ["v1", "v2"] {
+        let (code, stdout) = run_in_files(&["check", "--interpreter", interpreter, "prelude_use.slp"]);
+        assert_eq!(code, Some(0), "{interpreter}: {stdout}");
+    }
+
+    // V1 only finds undefined vars at runtime
+    let (code, stdout) = run_in_files(&["--no-prelude", "check", "--interpreter", "v2", "prelude_use.slp"]);
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(stdout.contains("is not defined"), "{stdout}");
+}
+
+#[test]
+fn prelude_functions() {
+    let (code, stdout) = run_in_files(&["run", "prelude_use.slp"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "123\n207false\n");
+
+    let (_, stdout) = run_in_files(&["--no-prelude", "run", "prelude_use.slp"]);
+    assert!(stdout.contains("Var `first` is not defined"), "{stdout}");
+
+    let (code, stdout) = run_in_files(&["eval", "(inc 41)"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "42\n");
+}
+
+#[test]
+fn redefine_prelude() {
+    let (code, stdout) = run_in_files(&["run", "prelude_redefine.slp"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert_eq!(stdout, "11\n");
+
+    let (code, stdout) = run_in_files(&["check", "--interpreter", "v2", "prelude_redefine.slp"]);
+    assert_eq!(code, Some(0), "{stdout}");
+
+    // only the prelude's definition can be replaced silently. V2 allows it again with a warning.
+    let (_, stdout) = run_in_files(&["run", "prelude_redefine_twice.slp"]);
+    assert!(stdout.contains("Var `last` is already defined"), "{stdout}");
+
+    let (code, stdout) = run_in_files(&["check", "--interpreter", "v2", "prelude_redefine_twice.slp"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("Global `last` is already defined, this replaces it"), "{stdout}");
+    assert_eq!(stdout.matches("Warning").count(), 1, "{stdout}");
+}
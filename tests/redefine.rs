@@ -0,0 +1,91 @@
+//! Defining a global again at the top level replaces it with a warning instead of failing, both
+//! in files and when converting a line at a time like a REPL.
+
+
+use simple_lisp::{
+    interpreter2::{
+        ast::{
+            ConvertState,
+            repl_convert,
+        },
+        data::Primitive,
+    },
+    output::Captured,
+    parser,
+    InterpreterOptions,
+};
+use std::{
+    path::Path,
+    process::Command,
+};
+
+
+/// Convert and run each line in order, returning what the last one ended with
+fn run_lines(state: &mut ConvertState, lines: &[&str])->Primitive {
+    let mut interpreter = InterpreterOptions::default().new_interpreter2_with_output(state, Box::new(Captured::new()));
+
+    let mut last = Primitive::None;
+    for line in lines {
+        let exprs = parser::new_parser(line).parse_all().unwrap();
+        let start = repl_convert(state, exprs).unwrap_or_else(|e|panic!("`{line}`: {e}"));
+        last = interpreter.run(state, Some(start)).unwrap_or_else(|e|panic!("`{line}`: {e}"));
+    }
+
+    return last;
+}
+
+fn warnings(state: &ConvertState)->Vec<String> {
+    state.warnings.iter().map(|w|w.to_string()).collect()
+}
+
+#[test]
+fn incremental_redefinition() {
+    let mut state = ConvertState::new();
+
+    run_lines(&mut state, &["(def f (fn [] 1))"]);
+    let slot = state.lookup_var("f").unwrap();
+    assert!(state.warnings.is_empty(), "{:?}", warnings(&state));
+
+    // V2 can't call functions yet, so check that `f` is the newest one instead
+    let value = run_lines(&mut state, &["(def f (fn [] 2))", "(def f (fn [] 3))", "f"]);
+    let newest = *state.fn_ids().last().unwrap();
+    assert!(matches!(value, Primitive::Func(id) if id == newest), "{value:?}");
+    assert_eq!(state.lookup_var("f"), Some(slot));
+
+    assert_eq!(warnings(&state), [
+        "Global `f` is already defined, this replaces it",
+        "Global `f` is already defined, this replaces it",
+    ]);
+}
+
+#[test]
+fn incremental_values() {
+    let mut state = ConvertState::new();
+    let value = run_lines(&mut state, &["(def x 1)", "(def x (+ x 1))", "(def x (* x 10))", "x"]);
+    assert!(matches!(value, Primitive::Int(20)), "{value:?}");
+    assert_eq!(state.warnings.len(), 2);
+}
+
+#[test]
+fn redefined_builtin() {
+    let mut state = ConvertState::new();
+    let value = run_lines(&mut state, &["(+ 5 3)", "(def + -)", "(+ 5 3)"]);
+    assert!(matches!(value, Primitive::Int(2)), "{value:?}");
+    assert_eq!(warnings(&state), ["This replaces the builtin `+` for everything after it"]);
+}
+
+#[test]
+fn redefinition_in_a_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files"))
+        .args(["--print-result", "run2", "redefine.slp"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(stdout.contains("Warning (redefine.slp): Global `x` is already defined, this replaces it"), "{stdout}");
+    assert!(stdout.contains("Warning (redefine.slp): This replaces the builtin `+` for everything after it"), "{stdout}");
+    // 2 - 5, printed last
+    assert_eq!(stdout.lines().last(), Some("-3"), "{stdout}");
+}
@@ -0,0 +1,120 @@
+//! The REPL loads an init file before its first prompt, and takes its prompt from `*prompt*`. It
+//! reads plain lines when stdin isn't a terminal, so a session can be piped in.
+
+
+use std::{
+    fs,
+    io::Write,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Command,
+        Stdio,
+    },
+};
+
+
+/// A config dir for one test, with `init` as its `simple_lisp/init.slp`
+fn config_dir(name: &str, init: &str)->PathBuf {
+    let dir = std::env::temp_dir().join(format!("simple_lisp_repl_{name}_{}", std::process::id()));
+    fs::create_dir_all(dir.join("simple_lisp")).unwrap();
+    fs::write(dir.join("simple_lisp/init.slp"), init).unwrap();
+
+    return dir;
+}
+
+/// Run the REPL with `config` as `XDG_CONFIG_HOME`, `args` after `repl`, and `input` on stdin.
+/// Returns stdout.
+fn repl(config: &Path, args: &[&str], input: &str)->String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .env("XDG_CONFIG_HOME", config)
+        .env_remove("CLICOLOR_FORCE")
+        .arg("repl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("panicked"), "{input:?} panicked: {stderr}");
+    assert_eq!(output.status.code(), Some(0), "{stderr}");
+
+    return String::from_utf8(output.stdout).unwrap();
+}
+
+
+#[test]
+fn init_file() {
+    let dir = config_dir("init", "(defn double [x] (* x 2))\n(def *prompt* \"slp> \")\n");
+    let other = dir.join("other.slp");
+    fs::write(&other, "(defn double [x] (* x 3))\n").unwrap();
+
+    let loaded = repl(&dir, &[], "(double 21)\n");
+    let custom = repl(&dir, &["--init-file", other.to_str().unwrap()], "(double 21)\n");
+    let skipped = repl(&dir, &["--no-init"], "(double 21)\n");
+    let missing = repl(&dir, &["--init-file", "/does/not/exist.slp"], "(+ 1 2)\n");
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(loaded.contains("slp> >> 42\n"), "{loaded}");
+    assert!(!loaded.contains("Loaded"), "{loaded}");
+
+    assert!(custom.contains("> >> 63\n"), "{custom}");
+    assert!(!custom.contains("slp> "), "{custom}");
+
+    assert!(skipped.contains("Error: "), "{skipped}");
+    assert!(!skipped.contains(">> 42"), "{skipped}");
+
+    // it still starts
+    assert!(missing.contains("Error: "), "{missing}");
+    assert!(missing.contains(">> 3\n"), "{missing}");
+}
+
+#[test]
+fn broken_init_file() {
+    let dir = config_dir("broken", "(def before 1)\n(/ 1 0)\n(def after 2)\n");
+    let runtime = repl(&dir, &[], "before\nafter\n");
+    fs::write(dir.join("simple_lisp/init.slp"), "(def before 1)\n(def x (+ 1 2)\n").unwrap();
+    let parse = repl(&dir, &[], "(+ 1 2)\n");
+    fs::remove_dir_all(&dir).unwrap();
+
+    // the error comes first, then the session starts with what was defined before it
+    let error = runtime.find("Error: Division by zero").unwrap();
+    let welcome = runtime.find("Welcome to the slp REPL!").unwrap();
+    assert!(error < welcome, "{runtime}");
+    assert!(runtime.contains("> >> 1\n"), "{runtime}");
+    assert!(runtime.contains("Var `after` is not defined"), "{runtime}");
+
+    assert!(parse.contains("Error: "), "{parse}");
+    assert!(parse.contains(">> 3\n"), "{parse}");
+}
+
+#[test]
+fn function_prompt() {
+    let init = r#"
+(def count 0)
+(def *prompt* (fn []
+    (set count (+ count 1))
+    (std/string/format "[" count "]> ")))
+"#;
+    let dir = config_dir("fn_prompt", init);
+    let stdout = repl(&dir, &[], "(+ 1 2)\n(+ 1\n2\n3)\ncount\n");
+
+    // a prompt that doesn't return a string falls back to the default
+    fs::write(dir.join("simple_lisp/init.slp"), "(def *prompt* (fn [] 5))\n").unwrap();
+    let wrong = repl(&dir, &[], "(+ 1 2)\n");
+    fs::remove_dir_all(&dir).unwrap();
+
+    // it is called once for each expression, and the unfinished one continues with dots
+    assert!(stdout.contains("[1]> >> 3\n"), "{stdout}");
+    assert!(stdout.contains("[2]> .... .... >> 6\n"), "{stdout}");
+    assert!(stdout.contains("[3]> >> 3\n"), "{stdout}");
+
+    assert!(wrong.contains("`*prompt*` expected string or fn, but got number"), "{wrong}");
+    assert!(wrong.contains("> >> 3\n"), "{wrong}");
+}
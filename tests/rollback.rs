@@ -0,0 +1,152 @@
+//! A REPL entry that fails to convert is undone, so the next entry can't run into its
+//! half-converted code or see the globals it defined.
+
+
+use simple_lisp::{
+    interpreter::{
+        self,
+        data::Data,
+    },
+    interpreter2::{
+        self,
+        data::Primitive,
+        verify::verify,
+    },
+    output::Captured,
+    parser,
+    InterpreterOptions,
+};
+
+
+#[test]
+fn v1_failed_entry_is_undone() {
+    use interpreter::ast::{
+        ConvertState,
+        repl_convert,
+    };
+
+    let mut state = ConvertState::new();
+    let mut interpreter = InterpreterOptions::default().new_interpreter_with_output(&mut state, Box::new(Captured::new()));
+
+    let exprs = parser::new_parser("(def a 1)").parse_all().unwrap();
+    let start = repl_convert(&mut state, exprs).unwrap();
+    interpreter.run(&mut state, Some(start)).unwrap();
+
+    let next_id = state.next_ins_id();
+    let interned = state.interner.len();
+
+    // the fn body is converted after the rest, so this fails with plenty already emitted
+    let exprs = parser::new_parser("(def b (+ a 1)) (def newName 2) (def f (fn [] (yield b)))").parse_all().unwrap();
+    let err = repl_convert(&mut state, exprs).unwrap_err();
+    assert!(err.to_string().contains("`yield` can only be used"), "{err}");
+    assert_eq!(state.next_ins_id(), next_id);
+    assert_eq!(state.interner.len(), interned);
+    assert_eq!(state.interner.lookup("newName"), None);
+
+    let exprs = parser::new_parser("(def c (+ a 4)) c").parse_all().unwrap();
+    let start = repl_convert(&mut state, exprs).unwrap();
+    assert_eq!(start, next_id);
+    let value = interpreter.run(&mut state, Some(start)).unwrap().unwrap();
+    assert!(matches!(*value.get_data(), Data::Number(5)));
+}
+
+#[test]
+fn v2_failed_entry_is_undone() {
+    use interpreter2::ast::{
+        ConvertState,
+        repl_convert,
+    };
+
+    let mut state = ConvertState::new();
+    let mut interpreter = InterpreterOptions::default().new_interpreter2_with_output(&mut state, Box::new(Captured::new()));
+
+    let exprs = parser::new_parser("(def a 1)").parse_all().unwrap();
+    let start = repl_convert(&mut state, exprs).unwrap();
+    interpreter.run(&mut state, Some(start)).unwrap();
+
+    let instructions = state.instructions.raw_instructions().len();
+    let order = state.instructions.ins_order().len();
+    let interned = state.interner.len();
+
+    let exprs = parser::new_parser("(def b (+ a 1)) (def f (fn [x] x)) (def c missing)").parse_all().unwrap();
+    repl_convert(&mut state, exprs).unwrap_err();
+    assert_eq!(state.instructions.raw_instructions().len(), instructions);
+    assert_eq!(state.instructions.ins_order().len(), order);
+    assert_eq!(state.interner.len(), interned);
+    assert_eq!(state.lookup_var("b"), None);
+    assert_eq!(state.lookup_var("f"), None);
+    verify(&state).unwrap();
+
+    // `b` was never defined, so this isn't a redefinition, and its slot comes right after `a`
+    let exprs = parser::new_parser("(def b (+ a 6)) b").parse_all().unwrap();
+    let start = repl_convert(&mut state, exprs).unwrap();
+    let value = interpreter.run(&mut state, Some(start)).unwrap();
+    assert!(matches!(value, Primitive::Int(7)), "{value:?}");
+    assert!(state.warnings.is_empty(), "{:?}", state.warnings);
+    assert_eq!(state.lookup_var("b").unwrap().id, state.lookup_var("a").unwrap().id + 1);
+    verify(&state).unwrap();
+}
+
+/// What a conversion that stopped between `reserve_func` and `insert_reserved` leaves behind. The
+/// check catches it, running it is an error instead of a panic, and a rollback recovers.
+#[test]
+fn v1_unfilled_fn() {
+    use interpreter::ast::{
+        ConvertState,
+        repl_convert,
+    };
+
+    let mut state = ConvertState::new();
+    let mut interpreter = InterpreterOptions::default().new_interpreter_with_output(&mut state, Box::new(Captured::new()));
+
+    let checkpoint = state.checkpoint();
+    let start = state.next_ins_id();
+    let id = state.reserve_func();
+    state.function(id);
+    state.push_exit();
+
+    let err = state.check_reserved().unwrap_err();
+    assert!(err.to_string().contains("Internal error: unresolved function"), "{err}");
+    let err = interpreter.run(&mut state, Some(start)).unwrap_err();
+    assert!(err.to_string().contains("Internal error: unresolved function"), "{err}");
+
+    state.rollback(checkpoint);
+    let exprs = parser::new_parser("(def g (fn [] 3)) (g)").parse_all().unwrap();
+    let start = repl_convert(&mut state, exprs).unwrap();
+    let value = interpreter.run(&mut state, Some(start)).unwrap().unwrap();
+    assert!(matches!(*value.get_data(), Data::Number(3)));
+}
+
+#[test]
+fn v2_unfilled_fn() {
+    use interpreter2::ast::{
+        ConvertState,
+        repl_convert,
+    };
+
+    let mut state = ConvertState::new();
+    let mut interpreter = InterpreterOptions::default().new_interpreter2_with_output(&mut state, Box::new(Captured::new()));
+
+    let checkpoint = state.checkpoint();
+    let start = state.next_ins_id();
+    let id = state.reserve_func();
+    state.function(id);
+    state.call(0);
+    state.push_exit();
+
+    let err = state.check_reserved().unwrap_err();
+    assert!(err.to_string().contains("Internal error: unresolved function"), "{err}");
+    let err = interpreter.run(&mut state, Some(start)).unwrap_err();
+    assert!(err.to_string().contains("Internal error: unresolved function"), "{err}");
+
+    state.rollback(checkpoint);
+    verify(&state).unwrap();
+
+    // the fn body fails after the fn was reserved, so the entry is rolled back and nothing is left
+    let exprs = parser::new_parser("(def f (fn [] missing))").parse_all().unwrap();
+    repl_convert(&mut state, exprs).unwrap_err();
+    let exprs = parser::new_parser("(def a 2) a").parse_all().unwrap();
+    let start = repl_convert(&mut state, exprs).unwrap();
+    let value = interpreter.run(&mut state, Some(start)).unwrap();
+    assert!(matches!(value, Primitive::Int(2)), "{value:?}");
+}
@@ -0,0 +1,36 @@
+//! `--sandbox` and `--allow` take capabilities away from the natives. Using one that was taken
+//! away is a runtime error like any other.
+
+
+mod common;
+
+use common::run_in_files;
+
+
+#[test]
+fn sandbox_denies_files() {
+    let (_, stdout) = run_in_files(&["--sandbox", "run", "read_file.slp"]);
+    assert!(stdout.contains("capability denied: filesystem-read"), "{stdout}");
+
+    let (_, stdout) = run_in_files(&["--allow", "stdio", "run", "read_file.slp"]);
+    assert!(stdout.contains("capability denied: filesystem-read"), "{stdout}");
+
+    let (_, stdout) = run_in_files(&["--allow", "stdio,fs-read", "run", "read_file.slp"]);
+    assert!(!stdout.contains("capability denied"), "{stdout}");
+    assert!(stdout.contains("defn double"), "{stdout}");
+}
+
+#[test]
+fn sandbox_denies_stdio() {
+    let (_, stdout) = run_in_files(&["--sandbox", "eval", "(std/io/write std/io/stdout \"hi\")"]);
+    assert!(stdout.contains("capability denied: stdio"), "{stdout}");
+
+    let (_, stdout) = run_in_files(&["--allow", "stdio", "eval", "(std/io/write std/io/stdout \"hi\")"]);
+    assert!(!stdout.contains("capability denied"), "{stdout}");
+}
+
+#[test]
+fn unknown_capability() {
+    let (code, _) = run_in_files(&["--allow", "teleport", "eval", "1"]);
+    assert_eq!(code, Some(2));
+}
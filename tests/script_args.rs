@@ -0,0 +1,52 @@
+//! Arguments after `--` reach the script as `*args*` and `(args)`, and its path as `*script*`.
+
+
+use std::{
+    path::Path,
+    process::Command,
+};
+
+
+/// Run `script` with `a b` after `--`, printing its result. Returns stdout.
+fn run(action: &str, script: &Path)->String {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .args(["--no-prelude", "--print-result", action, script.to_str().unwrap(), "--", "a", "b"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{action} {}: {stdout}", script.display());
+
+    return stdout;
+}
+
+#[test]
+fn args_and_script() {
+    let dir = std::env::temp_dir().join(format!("simple_lisp_script_args_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, source: &str|{
+        let path = dir.join(name);
+        std::fs::write(&path, source).unwrap();
+        path
+    };
+
+    let script = write("script.slp", "*script*\n");
+    let args = write("args.slp", "(def [x y] *args*)\ny\n");
+    let call = write("call.slp", "(def [x y] (args))\ny\n");
+    let cases = [
+        (&script, format!("{:?}\n", script.to_str().unwrap())),
+        (&args, "\"b\"\n".to_string()),
+        (&call, "\"b\"\n".to_string()),
+    ];
+
+    let mut results = Vec::new();
+    for (path, expected) in &cases {
+        for action in ["run", "run2"] {
+            results.push((action, path.display().to_string(), run(action, path), expected.clone()));
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for (action, path, stdout, expected) in results {
+        assert_eq!(stdout, expected, "{action} {path}");
+    }
+}
@@ -0,0 +1,185 @@
+//! The stats printed after a program runs. Timings change from run to run, so only the opcode
+//! counts and the shape of `--stats-json` are checked.
+
+
+use serde::Deserialize;
+use serde_json::Value;
+use simple_lisp::stats_json::STATS_FIELDS;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::Path,
+    process::{
+        Command,
+        Output,
+    },
+};
+
+
+/// A copy of the `--stats-json` schema. If this stops deserializing, the schema changed and
+/// everything reading it will break. Only add fields, and add them here and to `STATS_FIELDS`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct StatsJson {
+    version: String,
+    interpreter: String,
+    iterations: usize,
+    parse_ns: Option<u64>,
+    instructions: u64,
+    allocations: u64,
+    max_stack_depth: Option<usize>,
+    runtime_ns: u64,
+    instructions_per_second: f64,
+    gc: Option<GcJson>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct GcJson {
+    minor_collections: u64,
+    major_collections: u64,
+    minor_ns: u64,
+    major_ns: u64,
+    max_pause_ns: u64,
+    freed_objects: u64,
+    freed_bytes: u64,
+    peak_live: usize,
+}
+
+
+fn command(args: &[&str])->Output {
+    Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run the binary with `args` from `tests/files`. Returns the exit code and stdout.
+fn run(args: &[&str])->(Option<i32>, String) {
+    let output = command(args);
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+/// Run the binary with `args` and return the stats object it wrote to stderr
+fn stats_from_stderr(args: &[&str])->String {
+    let output = command(args);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{stderr}");
+
+    let mut objects = stderr.lines().filter(|line|line.starts_with('{'));
+    let json = objects.next().unwrap_or_else(||panic!("No stats in {stderr:?}"));
+    assert!(objects.next().is_none(), "More than one stats object in {stderr:?}");
+
+    return json.to_string();
+}
+
+/// Check `json` against the schema and `STATS_FIELDS`
+fn check_schema(json: &str)->StatsJson {
+    fn keys(prefix: &str, value: &Value, out: &mut BTreeSet<String>) {
+        if let Value::Object(map) = value {
+            for (key, value) in map.iter() {
+                let name = format!("{prefix}{key}");
+                keys(&format!("{name}."), value, out);
+                out.insert(name);
+            }
+        }
+    }
+
+
+    let stats: StatsJson = serde_json::from_str(json).unwrap_or_else(|e|panic!("{e}: {json}"));
+    assert_eq!(stats.version, env!("CARGO_PKG_VERSION"));
+
+    // `gc` can be null, so only check its fields when it isn't
+    let mut found = BTreeSet::new();
+    keys("", &serde_json::from_str(json).unwrap(), &mut found);
+    let documented = STATS_FIELDS.iter()
+        .map(|(name, _)|name.to_string())
+        .filter(|name|stats.gc.is_some() || !name.starts_with("gc."))
+        .collect::<BTreeSet<_>>();
+    assert_eq!(found, documented);
+
+    return stats;
+}
+
+/// The count in the `opcode` row of the table, if there is one
+fn opcode_count(stdout: &str, opcode: &str)->Option<u64> {
+    stdout.lines()
+        .map(|line|line.split_whitespace().collect::<Vec<_>>())
+        .find(|cols|cols.first() == Some(&opcode))?
+        .get(1)?
+        .parse()
+        .ok()
+}
+
+#[test]
+fn opcode_stats() {
+    let (code, stdout) = run(&["--no-prelude", "--opcode-stats", "eval", "(def x 1)", "(+ x 2)"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("Opcode"), "{stdout}");
+    assert_eq!(opcode_count(&stdout, "Define"), Some(1), "{stdout}");
+    assert_eq!(opcode_count(&stdout, "Call"), Some(1), "{stdout}");
+
+    // the rows add up to the total, and the most common opcodes come first
+    let total = opcode_count(&stdout, "Total").unwrap();
+    let counts = stdout.lines()
+        .skip_while(|line|!line.starts_with("Opcode"))
+        .skip(1)
+        .take_while(|line|!line.starts_with("Total"))
+        .map(|line|line.split_whitespace().nth(1).unwrap().parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(counts.iter().sum::<u64>(), total, "{stdout}");
+    assert!(counts.windows(2).all(|w|w[0] >= w[1]), "{stdout}");
+}
+
+#[test]
+fn opcode_stats_v2() {
+    let (code, stdout) = run(&["--no-prelude", "--opcode-stats", "run2", "debug.slp"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("Opcode"), "{stdout}");
+    assert!(opcode_count(&stdout, "Total").unwrap() > 0, "{stdout}");
+}
+
+#[test]
+fn stats_for_nerds_twice() {
+    let (_, once) = run(&["--no-prelude", "-s", "eval", "(+ 1 2)"]);
+    assert!(!once.contains("Opcode"), "{once}");
+
+    let (code, twice) = run(&["--no-prelude", "-ss", "eval", "(+ 1 2)"]);
+    assert_eq!(code, Some(0), "{twice}");
+    assert!(twice.contains("Opcode"), "{twice}");
+}
+
+#[test]
+fn stats_json_run() {
+    let json = stats_from_stderr(&["--no-prelude", "--stats-json", "run", "debug.slp"]);
+    let stats = check_schema(&json);
+    assert_eq!(stats.interpreter, "v1");
+    assert_eq!(stats.iterations, 1);
+    assert!(stats.parse_ns.is_some() && stats.max_stack_depth.is_some() && stats.gc.is_some(), "{json}");
+    assert!(stats.instructions > 0, "{json}");
+}
+
+#[test]
+fn stats_json_run2_file() {
+    let path = std::env::temp_dir().join(format!("simple_lisp_stats_{}.json", std::process::id()));
+    let (code, stdout) = run(&["--no-prelude", "--stats-file", path.to_str().unwrap(), "run2", "debug.slp"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    let json = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let stats = check_schema(json.trim());
+    assert_eq!(stats.interpreter, "v2");
+    assert!(stats.parse_ns.is_some(), "{json}");
+    assert!(stats.instructions > 0, "{json}");
+}
+
+#[test]
+fn stats_json_bench() {
+    let json = stats_from_stderr(&["--no-prelude", "--stats-json", "bench", "--iterations", "3", "--warmup", "0", "debug.slp"]);
+    let stats = check_schema(&json);
+    assert_eq!(stats.interpreter, "v1");
+    assert_eq!(stats.iterations, 3);
+}
@@ -0,0 +1,68 @@
+//! `std/temp` makes unique entries in the temp directory, and `withDir` cleans up after itself
+//! whether its body succeeds or fails.
+
+
+use std::{
+    env::temp_dir,
+    fs::{
+        remove_dir,
+        remove_file,
+    },
+    path::PathBuf,
+};
+use simple_lisp::Engine;
+
+
+fn eval_path(engine: &mut Engine, source: &str)->PathBuf {
+    let value = engine.eval_str(source).unwrap_or_else(|e|panic!("{source}: {e}"));
+    PathBuf::from(value.as_string().unwrap_or_else(||panic!("{source} returned a {}", value.type_name())))
+}
+
+#[test]
+fn make_entries() {
+    let mut engine = Engine::new();
+    assert_eq!(eval_path(&mut engine, "(std/temp/dir)"), temp_dir());
+
+    let first = eval_path(&mut engine, "(std/temp/makeFile \"scratch-\")");
+    let second = eval_path(&mut engine, "(std/temp/makeFile \"scratch-\")");
+    let dir = eval_path(&mut engine, "(std/temp/makeDir \"scratch-\")");
+
+    assert_ne!(first, second);
+    for path in [&first, &second, &dir] {
+        assert_eq!(path.parent(), Some(temp_dir().as_path()));
+        assert!(path.file_name().unwrap().to_str().unwrap().starts_with("scratch-"), "{path:?}");
+    }
+    assert!(first.is_file() && second.is_file(), "{first:?} {second:?}");
+    assert!(dir.is_dir(), "{dir:?}");
+
+    remove_file(first).unwrap();
+    remove_file(second).unwrap();
+    remove_dir(dir).unwrap();
+
+    let err = engine.eval_str("(std/temp/makeDir \"../escape\")").unwrap_err();
+    assert!(err.to_string().contains("`makeDir` needs a prefix without path separators"), "{err}");
+}
+
+#[test]
+fn with_dir_cleans_up() {
+    let mut engine = Engine::new();
+    let dir = eval_path(&mut engine, "(std/temp/withDir (fn [dir] dir))");
+    assert_eq!(dir.parent(), Some(temp_dir().as_path()));
+    assert!(!dir.exists(), "{dir:?} was not removed");
+
+    // what the body returns is what `withDir` returns
+    let value = engine.eval_str("(std/temp/withDir (fn [_] 42))").unwrap();
+    assert_eq!(value.as_i64(), Some(42));
+}
+
+#[test]
+fn with_dir_cleans_up_after_errors() {
+    let mut engine = Engine::new();
+    engine.eval_str("(def seen None)").unwrap();
+
+    let err = engine.eval_str("(std/temp/withDir (fn [dir] (set seen dir) (/ 1 0)))").unwrap_err();
+    assert!(err.to_string().contains("Division by zero"), "{err}");
+
+    let dir = eval_path(&mut engine, "seen");
+    assert!(!dir.exists(), "{dir:?} was not removed");
+}
@@ -0,0 +1,60 @@
+//! `std/term/style` makes the exact escape codes we expect, and nothing when output isn't a
+//! terminal.
+
+
+use simple_lisp::{
+    Captured,
+    Engine,
+    InterpreterOptions,
+};
+
+
+fn eval_string(engine: &mut Engine, source: &str)->String {
+    let value = engine.eval_str(source).unwrap_or_else(|e|panic!("{source}: {e}"));
+    value.as_string().unwrap_or_else(||panic!("{source} returned a {}", value.type_name()))
+}
+
+#[test]
+fn styled_bytes() {
+    let mut engine = Engine::new();
+    engine.eval_str("(std/term/forceColor #t)").unwrap();
+
+    let cases = [
+        ("(std/term/style \"hi\" \"red\")", "\x1b[31mhi\x1b[0m"),
+        ("(std/term/style \"hi\" \"bold\" \"onBlue\")", "\x1b[1m\x1b[44mhi\x1b[0m"),
+        ("(std/term/style \"hi\")", "hi"),
+        ("(std/term/style \"\" \"green\")", "\x1b[32m\x1b[0m"),
+        // the inner reset puts the outer style back instead of ending it
+        (
+            "(std/term/style (std/string/format \"a \" (std/term/style \"b\" \"red\") \" c\") \"bold\")",
+            "\x1b[1ma \x1b[31mb\x1b[0m\x1b[1m c\x1b[0m",
+        ),
+        (
+            "(std/term/style (std/term/style (std/term/style \"x\" \"red\") \"underline\") \"bold\")",
+            "\x1b[1m\x1b[4m\x1b[31mx\x1b[0m\x1b[1m\x1b[4m\x1b[0m\x1b[1m\x1b[0m",
+        ),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(eval_string(&mut engine, source), expected, "{source}");
+    }
+
+    let err = engine.eval_str("(std/term/style \"hi\" \"purple\")").unwrap_err();
+    assert!(err.to_string().contains("`style` has no style named `purple`"), "{err}");
+    let err = engine.eval_str("(std/term/style \"hi\" 1)").unwrap_err();
+    assert!(err.to_string().contains("`style` expected string or ident, but got number"), "{err}");
+}
+
+#[test]
+fn plain_when_not_a_terminal() {
+    let mut engine = Engine::with_output(InterpreterOptions::default(), Captured::new());
+    assert_eq!(eval_string(&mut engine, "(std/term/style \"hi\" \"red\" \"bold\")"), "hi");
+    assert_eq!(engine.eval_str("(std/term/isTty)").unwrap().as_bool(), Some(false));
+    assert!(engine.eval_str("(std/term/width)").unwrap().is_none());
+
+    engine.eval_str("(std/term/forceColor #t)").unwrap();
+    assert_eq!(eval_string(&mut engine, "(std/term/style \"hi\" \"red\")"), "\x1b[31mhi\x1b[0m");
+    engine.eval_str("(std/term/forceColor #f)").unwrap();
+    assert_eq!(eval_string(&mut engine, "(std/term/style \"hi\" \"red\")"), "hi");
+    engine.eval_str("(std/term/forceColor None)").unwrap();
+    assert_eq!(eval_string(&mut engine, "(std/term/style \"hi\" \"red\")"), "hi");
+}
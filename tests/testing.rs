@@ -0,0 +1,65 @@
+//! `simple_lisp test` runs the `deftest`s of every script in a directory. What a test prints is
+//! only shown if it fails.
+
+
+use std::{
+    path::Path,
+    process::Command,
+};
+
+
+/// Run the binary with `args` from `tests/files`. Returns the exit code and stdout.
+fn run(args: &[&str])->(Option<i32>, String) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/files");
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_lisp"))
+        .current_dir(&dir)
+        .env_remove("CLICOLOR_FORCE")
+        .args(args)
+        .output()
+        .unwrap();
+
+    return (output.status.code(), String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn directory() {
+    let (code, stdout) = run(&["test", "deftest"]);
+    assert_eq!(code, Some(1), "{stdout}");
+
+    assert!(stdout.contains("test deftest/lists.slp::length ... ok ("), "{stdout}");
+    assert!(stdout.contains("test deftest/math.slp::adds ... ok ("), "{stdout}");
+    assert!(stdout.contains("test deftest/math.slp::shares_globals ... ok ("), "{stdout}");
+    assert!(stdout.contains("test deftest/math.slp::prints_then_fails ... FAILED ("), "{stdout}");
+    assert!(stdout.ends_with("\n3 passed, 1 failed, 0 filtered out\n"), "{stdout}");
+
+    // only the failed test's output is shown, with its error and where it happened
+    let failure = stdout.find("---- deftest/math.slp::prints_then_fails ----\nIt printed:\n\"checking add\"\n").unwrap();
+    assert!(stdout[failure..].contains("Error: Assertion failed: add 2 2 should be 5"), "{stdout}");
+    assert!(stdout[failure..].contains("in `prints_then_fails`"), "{stdout}");
+    assert!(!stdout.contains("counting"), "{stdout}");
+
+    // scripts without tests aren't run
+    assert!(!stdout.contains("no tests here"), "{stdout}");
+}
+
+#[test]
+fn filter() {
+    let (code, stdout) = run(&["test", "--filter", "globals", "deftest/math.slp"]);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("::shares_globals ... ok"), "{stdout}");
+    assert!(stdout.ends_with("\n1 passed, 0 failed, 2 filtered out\n"), "{stdout}");
+}
+
+#[test]
+fn misplaced_tests() {
+    let cases = [
+        ("(defn f [] (deftest inner 1))", "`deftest` is only allowed at the top level of a script"),
+        ("(deftest twice 1) (deftest twice 2)", "Test `twice` is already defined"),
+    ];
+
+    for (expr, message) in cases {
+        let (code, stdout) = run(&["eval", expr]);
+        assert_eq!(code, Some(1), "{expr}: {stdout}");
+        assert!(stdout.contains(message), "{expr}: {stdout}");
+    }
+}
@@ -0,0 +1,98 @@
+//! Only `#f` and `None` are false. With `--strict-bool`, branching on anything but a bool or
+//! `None` is a type error.
+//!
+//! V2 can't run a `cond` yet, so its side only checks the values it branches on.
+
+
+use simple_lisp::interpreter2::data::Primitive;
+use std::{
+    process::Command,
+    rc::Rc,
+};
+
+
+/// Each value with its type name and whether it is truthy. The type name is `None` for values
+/// that strict mode allows.
+const CASES: &[(&str, Option<&str>, bool)] = &[
+    ("0", Some("number"), true),
+    ("0.0", Some("float"), true),
+    ("\"\"", Some("string"), true),
+    ("(core/list)", Some("list"), true),
+    ("(object)", Some("object"), true),
+    ("\\a", Some("char"), true),
+    ("#t", None, true),
+    ("#f", None, false),
+    ("None", None, false),
+];
+
+
+/// What the V1 interpreter prints last for `expr`
+fn run_v1(strict: bool, expr: &str)->String {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_simple_lisp"));
+    if strict {
+        command.arg("--strict-bool");
+    }
+    let output = command
+        .arg("eval")
+        .arg(expr)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    return stdout.lines().last().unwrap_or_default().to_string();
+}
+
+#[test]
+fn v1_default() {
+    for (value, _, truthy) in CASES {
+        let expected = if *truthy {"1"} else {"2"};
+        assert_eq!(run_v1(false, &format!("(cond ({value} 1) (#t 2))")), expected, "{value}");
+        assert_eq!(run_v1(false, &format!("(core/not {value})")), (!truthy).to_string(), "{value}");
+        assert_eq!(run_v1(false, &format!("(core/and #t {value})")), truthy.to_string(), "{value}");
+        assert_eq!(run_v1(false, &format!("(core/or #f {value})")), truthy.to_string(), "{value}");
+    }
+}
+
+#[test]
+fn v1_strict() {
+    for (value, type_name, truthy) in CASES {
+        let cond = run_v1(true, &format!("(cond ({value} 1) (#t 2))"));
+        let not = run_v1(true, &format!("(core/not {value})"));
+        match type_name {
+            Some(name)=>{
+                assert_eq!(cond, format!("Error: Type error: `cond` expected bool, but got {name}"), "{value}");
+                assert_eq!(not, format!("Error: Type error: `not` expected bool, but got {name}"), "{value}");
+            },
+            None=>{
+                assert_eq!(cond, if *truthy {"1"} else {"2"}, "{value}");
+                assert_eq!(not, (!truthy).to_string(), "{value}");
+            },
+        }
+    }
+}
+
+#[test]
+fn v2_values() {
+    let cases = [
+        (Primitive::Int(0), Some("number"), true),
+        (Primitive::Float(0.0), Some("float"), true),
+        (Primitive::String(Rc::new(String::new())), Some("string"), true),
+        (Primitive::Char('a'), Some("char"), true),
+        (Primitive::Bool(true), None, true),
+        (Primitive::Bool(false), None, false),
+        (Primitive::None, None, false),
+    ];
+
+    for (value, type_name, truthy) in cases {
+        assert_eq!(value.truth().is_truthy("cond", false).unwrap(), truthy, "{value:?}");
+
+        let strict = value.truth().is_truthy("cond", true);
+        match type_name {
+            Some(name)=>{
+                let e = strict.unwrap_err();
+                assert_eq!(e.to_string(), format!("Type error: `cond` expected bool, but got {name}"));
+            },
+            None=>assert_eq!(strict.unwrap(), truthy, "{value:?}"),
+        }
+    }
+}
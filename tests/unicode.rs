@@ -0,0 +1,85 @@
+//! Identifiers are unicode `XID_Start` and `XID_Continue` chars plus the lisp punctuation, so other
+//! scripts work but emoji don't. Carets still line up under errors on lines with wide chars.
+
+
+mod common;
+
+use common::eval_v1;
+use simple_lisp::{
+    ast::Expr,
+    color,
+    interpreter::ast::Interner,
+    parser,
+};
+
+
+fn parse(source: &str)->Vec<Expr> {
+    parser::new_parser(source).parse_all().unwrap_or_else(|e|panic!("`{source}`: {e:#}"))
+}
+
+#[test]
+fn greek_names() {
+    match parse("(def naïve-π 3.14159)").as_slice() {
+        [Expr::Def {name, ..}]=>assert_eq!(*name, "naïve-π"),
+        other=>panic!("{other:?}"),
+    }
+    assert!(matches!(parse("λ?").as_slice(), [Expr::Ident("λ?")]));
+    assert!(matches!(parse(".ύψος").as_slice(), [Expr::DotIdent("ύψος")]));
+    match parse("σ/α/-1").as_slice() {
+        [Expr::Path(path)]=>assert_eq!(path, &["σ", "α", "-1"]),
+        other=>panic!("{other:?}"),
+    }
+
+    assert_eq!(eval_v1(&["(def λ 40)", "(def Δ 2)", "(+ λ Δ)"]), "42");
+
+    let mut interner = Interner::new();
+    let pi = interner.intern("π");
+    assert_eq!(interner.lookup("π"), Some(pi));
+    assert_eq!(interner.lookup("p"), None);
+    assert_eq!(interner.get_opt(pi), Some("π"));
+}
+
+#[test]
+fn quote_hash_and_colon_after_the_start() {
+    for name in ["x'", "a#b", "key:val", "f''"] {
+        assert!(matches!(parse(name).as_slice(), [Expr::Ident(n)] if *n == name), "{name}");
+    }
+    match parse("a/b:c").as_slice() {
+        [Expr::Path(path)]=>assert_eq!(path, &["a", "b:c"]),
+        other=>panic!("{other:?}"),
+    }
+
+    // they still can't start one
+    assert!(matches!(parse("'x").as_slice(), [Expr::Quote(..)]));
+    assert!(matches!(parse("#t").as_slice(), [Expr::True]));
+}
+
+#[test]
+fn emoji_are_not_names() {
+    for source in ["😀", "(def 😀 1)", "(def x😀 1)"] {
+        let e = parser::new_parser(source).parse_all().unwrap_err();
+        assert!(format!("{e:#}").contains("Invalid token `😀`"), "{source}: {e:#}");
+    }
+
+    // they are still fine in strings
+    assert!(matches!(parse("\"😀\"").as_slice(), [Expr::String(s)] if s == "😀"));
+}
+
+#[test]
+fn caret_after_wide_chars() {
+    let source = "(def 名前 \"東京\") (+ 名前 x)\n";
+    let offset = source.find('x').unwrap();
+    let snippet = color::snippet(false, "Undefined `x`", source, "cjk.slp", offset);
+
+    // the column counts chars, but the caret has to skip two cells for each CJK char
+    let expected = format!(
+        "Error: Undefined `x`\n --> cjk.slp:1:21\n  |\n1 | {}  | {}^\n",
+        source,
+        " ".repeat(26),
+    );
+    assert_eq!(snippet, expected);
+
+    // tabs are copied so they line up however wide the terminal makes them
+    let snippet = color::snippet(false, "Undefined `x`", "\t名 x", "tab.slp", 5);
+    assert!(snippet.ends_with("  | \t   ^\n"), "{snippet:?}");
+}
@@ -0,0 +1,116 @@
+//! The V2 verifier walks every path through the instructions. What the converter makes has to pass,
+//! and each way of breaking the scopes has to be caught.
+
+
+mod common;
+
+use common::convert_source;
+use simple_lisp::{
+    interpreter2::{
+        ast::{
+            ConvertState,
+            Instruction,
+            InstructionId,
+            VarSlot,
+        },
+        optimize::optimize,
+        verify::verify,
+    },
+};
+
+
+const SOURCE: &str = r#"
+(def pick (fn [a b]
+    (cond
+        (#f "never")
+        (a (begin (def x 1) x))
+        (b 2))))
+(def after (cond (#f 1) (#t 2)))
+(def nothing (cond (#f 1)))
+(def also (+ after 1))
+(begin
+    (def inner (cond (#t also)))
+    inner)
+"#;
+
+
+fn find(state: &ConvertState, f: impl Fn(&Instruction)->bool)->InstructionId {
+    *state.instructions.ins_order()
+        .iter()
+        .find(|id|f(&state.instructions.raw_instructions()[id.inner()]))
+        .unwrap()
+}
+
+fn exit(state: &ConvertState)->InstructionId {
+    find(state, |ins|matches!(ins, Instruction::Exit))
+}
+
+#[test]
+fn converted_code_passes() {
+    let mut state = convert_source(SOURCE);
+    verify(&state).unwrap();
+
+    optimize(&mut state);
+    verify(&state).unwrap();
+}
+
+#[test]
+fn scope_left_open() {
+    let mut state = convert_source(SOURCE);
+    let exit = exit(&state);
+    state.instructions.insert_before(exit, Instruction::Scope(0));
+
+    let err = verify(&state).unwrap_err().to_string();
+    assert!(err.contains("1 scopes still open"), "{err}");
+}
+
+#[test]
+fn end_without_scope() {
+    let mut state = convert_source(SOURCE);
+    let exit = exit(&state);
+    state.instructions.insert_before(exit, Instruction::EndScope(0));
+
+    let err = verify(&state).unwrap_err().to_string();
+    assert!(err.contains("ends a scope that was never started"), "{err}");
+}
+
+#[test]
+fn end_scope_count_mismatch() {
+    let mut state = convert_source(SOURCE);
+    let exit = exit(&state);
+    state.instructions.insert_before(exit, Instruction::Scope(2));
+    state.instructions.insert_before(exit, Instruction::EndScope(1));
+
+    let err = verify(&state).unwrap_err().to_string();
+    assert!(err.contains("ends a `Scope(2)`"), "{err}");
+}
+
+#[test]
+fn local_out_of_scope() {
+    let mut state = convert_source(SOURCE);
+    let exit = exit(&state);
+    state.instructions.insert_before(exit, Instruction::GetVar(VarSlot {id: 0, global: false}));
+
+    let err = verify(&state).unwrap_err().to_string();
+    assert!(err.contains("uses local slot 0, but only 0 are in scope"), "{err}");
+
+    // a function's params are in scope without a `Scope`
+    let state = convert_source("(def f (fn [a b] b))");
+    verify(&state).unwrap();
+}
+
+#[test]
+fn paths_meet_with_different_scopes() {
+    let mut state = convert_source(SOURCE);
+    let exit = exit(&state);
+
+    // one path starts a scope and the other doesn't, then both end up at `end`
+    state.instructions.insert_before(exit, Instruction::Bool(true));
+    let jump = state.instructions.insert_before(exit, Instruction::Nop);
+    state.instructions.insert_before(exit, Instruction::Scope(0));
+    let end = state.instructions.insert_before(exit, Instruction::Nop);
+    state.instructions.set(jump, Instruction::JumpIfTrue(end));
+
+    let err = verify(&state).unwrap_err().to_string();
+    assert!(err.contains("with different scopes open"), "{err}");
+}